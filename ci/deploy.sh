@@ -36,7 +36,9 @@ case $OSTYPE in
     case `lsb_release -ds` in
       Ubuntu*|Debian*)
         rm -rf pkg
-        mkdir -p pkg/debian/usr/bin pkg/debian/DEBIAN
+        mkdir -p pkg/debian/usr/bin pkg/debian/DEBIAN \
+          pkg/debian/usr/share/applications \
+          pkg/debian/usr/share/icons/hicolor/128x128/apps
         cat > pkg/debian/DEBIAN/control <<EOF
 Package: wezterm
 Version: ${TAG_NAME}
@@ -52,6 +54,8 @@ Description: Wez's Terminal Emulator.
 Depends: libc6, libegl-mesa0, libxcb-icccm4, libxcb-ewmh2, libxcb-keysyms1, libxcb-xkb1, libxkbcommon0, libxkbcommon-x11-0, libfontconfig1, xdg-utils, libxcb-render0, libxcb-shape0, libx11-6, libegl1
 EOF
         cp target/release/wezterm pkg/debian/usr/bin
+        cp assets/wezterm.desktop pkg/debian/usr/share/applications/org.wezfurlong.wezterm.desktop
+        cp assets/icon/terminal.png pkg/debian/usr/share/icons/hicolor/128x128/apps/org.wezfurlong.wezterm.png
         fakeroot dpkg-deb --build pkg/debian wezterm-$TAG_NAME.deb
         rm -rf pkg
       ;;
@@ -1,7 +1,5 @@
 #[cfg(windows)]
 use failure::{ensure, Error};
-#[cfg(windows)]
-use log::error;
 use serde_derive::*;
 use std::ffi::{OsStr, OsString};
 #[cfg(windows)]
@@ -12,7 +10,13 @@ use std::os::windows::ffi::OsStrExt;
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct CommandBuilder {
     args: Vec<OsString>,
-    envs: Vec<(OsString, OsString)>,
+    /// The changes to the environment, in the order that they were
+    /// made.  None removes the variable.
+    envs: Vec<(OsString, Option<OsString>)>,
+    /// Whether the command starts with an empty environment rather
+    /// than inheriting the environment of wezterm
+    env_clear: bool,
+    cwd: Option<OsString>,
 }
 
 impl CommandBuilder {
@@ -22,6 +26,8 @@ impl CommandBuilder {
         Self {
             args: vec![program.as_ref().to_owned()],
             envs: vec![],
+            env_clear: false,
+            cwd: None,
         }
     }
 
@@ -48,13 +54,31 @@ impl CommandBuilder {
         V: AsRef<OsStr>,
     {
         self.envs
-            .push((key.as_ref().to_owned(), val.as_ref().to_owned()));
-        #[cfg(windows)]
-        error!(
-            "ignoring env {:?}={:?} for child; FIXME: implement this!",
-            key.as_ref(),
-            val.as_ref()
-        );
+            .push((key.as_ref().to_owned(), Some(val.as_ref().to_owned())));
+    }
+
+    /// Remove an environmental variable, so that the command doesn't
+    /// inherit it
+    pub fn env_remove<K: AsRef<OsStr>>(&mut self, key: K) {
+        self.envs.push((key.as_ref().to_owned(), None));
+    }
+
+    /// Start the command with an empty environment, rather than the
+    /// environment of this process.  Variables set after this call
+    /// are still passed to the command.
+    pub fn env_clear(&mut self) {
+        self.envs.clear();
+        self.env_clear = true;
+    }
+
+    /// Set the directory that the command starts in
+    pub fn cwd<D: AsRef<OsStr>>(&mut self, dir: D) {
+        self.cwd = Some(dir.as_ref().to_owned());
+    }
+
+    /// Returns the directory that the command starts in, if one was set
+    pub fn get_cwd(&self) -> Option<&OsString> {
+        self.cwd.as_ref()
     }
 }
 
@@ -64,8 +88,17 @@ impl CommandBuilder {
     pub(crate) fn as_command(&self) -> std::process::Command {
         let mut cmd = std::process::Command::new(&self.args[0]);
         cmd.args(&self.args[1..]);
+        if self.env_clear {
+            cmd.env_clear();
+        }
         for (key, val) in &self.envs {
-            cmd.env(key, val);
+            match val {
+                Some(val) => cmd.env(key, val),
+                None => cmd.env_remove(key),
+            };
+        }
+        if let Some(cwd) = self.cwd.as_ref() {
+            cmd.current_dir(cwd);
         }
 
         cmd
@@ -127,6 +160,51 @@ impl CommandBuilder {
         Ok((exe, cmdline))
     }
 
+    /// Builds the environment block for CreateProcessW: nul terminated
+    /// `KEY=VALUE` entries, sorted case insensitively by key and
+    /// followed by an extra nul
+    pub(crate) fn environment_block(&self) -> Vec<u16> {
+        // Names are case insensitive on Windows
+        let fold = |key: &OsStr| key.to_string_lossy().to_uppercase();
+        let mut vars: Vec<(OsString, OsString)> = if self.env_clear {
+            vec![]
+        } else {
+            std::env::vars_os().collect()
+        };
+        for (key, val) in &self.envs {
+            let folded = fold(key);
+            vars.retain(|(existing, _)| fold(existing) != folded);
+            if let Some(val) = val {
+                vars.push((key.clone(), val.clone()));
+            }
+        }
+        vars.sort_by_key(|(key, _)| fold(key));
+
+        let mut block = vec![];
+        for (key, val) in vars {
+            block.extend(key.encode_wide());
+            block.push('=' as u16);
+            block.extend(val.encode_wide());
+            block.push(0);
+        }
+        // An empty block still needs two nuls
+        if block.is_empty() {
+            block.push(0);
+        }
+        block.push(0);
+        block
+    }
+
+    /// Returns the nul terminated directory that the command starts
+    /// in, or None to start in the current directory of wezterm
+    pub(crate) fn current_directory(&self) -> Option<Vec<u16>> {
+        self.cwd.as_ref().map(|cwd| {
+            let mut wide: Vec<u16> = cwd.encode_wide().collect();
+            wide.push(0);
+            wide
+        })
+    }
+
     // Borrowed from https://github.com/hniksic/rust-subprocess/blob/873dfed165173e52907beb87118b2c0c05d8b8a1/src/popen.rs#L1117
     // which in turn was translated from ArgvQuote at http://tinyurl.com/zmgtnls
     fn append_quoted(arg: &OsStr, cmdline: &mut Vec<u16>) {
@@ -174,3 +252,47 @@ impl CommandBuilder {
         cmdline.push('"' as u16);
     }
 }
+
+#[cfg(all(test, windows))]
+mod test {
+    use super::*;
+
+    /// Splits an environment block back into its `KEY=VALUE` entries
+    fn entries(block: &[u16]) -> Vec<String> {
+        assert_eq!(&block[block.len() - 2..], &[0, 0]);
+        block[..block.len() - 2]
+            .split(|&c| c == 0)
+            .filter(|entry| !entry.is_empty())
+            .map(String::from_utf16_lossy)
+            .collect()
+    }
+
+    #[test]
+    fn environment_block() {
+        let mut cmd = CommandBuilder::new("cmd.exe");
+        cmd.env_clear();
+        assert_eq!(cmd.environment_block(), vec![0, 0]);
+
+        // Names are compared and sorted case insensitively, and the
+        // last change to a variable wins
+        cmd.env("b", "2");
+        cmd.env("A", "1");
+        cmd.env("B", "3");
+        cmd.env("c", "4");
+        cmd.env_remove("C");
+        assert_eq!(entries(&cmd.environment_block()), vec!["A=1", "B=3"]);
+    }
+
+    #[test]
+    fn inherited_environment_block() {
+        std::env::set_var("WEZTERM_ENV_BLOCK_KEEP", "kept");
+        std::env::set_var("WEZTERM_ENV_BLOCK_DROP", "dropped");
+        let mut cmd = CommandBuilder::new("cmd.exe");
+        cmd.env_remove("wezterm_env_block_drop");
+        let entries = entries(&cmd.environment_block());
+        assert!(entries.contains(&"WEZTERM_ENV_BLOCK_KEEP=kept".to_owned()));
+        assert!(!entries
+            .iter()
+            .any(|entry| entry.starts_with("WEZTERM_ENV_BLOCK_DROP=")));
+    }
+}
@@ -36,6 +36,11 @@
 //! writeln!(pair.master, "ls -l\r\n")?;
 //! # Ok::<(), Error>(())
 //! ```
+//!
+//! Reading the output of each pty on its own thread, as above, is the
+//! simplest approach.  Programs that manage many ptys can instead use
+//! `MasterPty::set_nonblocking` and `MasterPty::as_raw_fd` to wait for
+//! output from all of them with a single poller, such as `mio`.
 use failure::{bail, format_err, Error, Fallible};
 #[cfg(feature = "serde_support")]
 use serde_derive::*;
@@ -86,6 +91,34 @@ pub trait MasterPty: std::io::Write {
     /// Obtain a readable handle; output from the slave(s) is readable
     /// via this stream.
     fn try_clone_reader(&self) -> Result<Box<dyn std::io::Read + Send>, Error>;
+    /// Obtain a writable handle; input written to it is sent to the
+    /// slave(s) just as input written to the master is.
+    fn try_clone_writer(&self) -> Result<Box<dyn std::io::Write + Send>, Error>;
+
+    /// Switches reads from and writes to the master, including those
+    /// made via readers obtained from `try_clone_reader`, between
+    /// blocking and non-blocking mode.  In non-blocking mode they
+    /// fail with `ErrorKind::WouldBlock` rather than waiting.
+    fn set_nonblocking(&self, _nonblocking: bool) -> Fallible<()> {
+        bail!("this pty implementation doesn't support non-blocking mode")
+    }
+
+    /// Returns the descriptor that output from the slave is read from
+    /// and input is written to, so that it can be registered with a
+    /// poller, or None if the implementation doesn't have one.  The
+    /// descriptor remains owned by the master.
+    #[cfg(unix)]
+    fn as_raw_fd(&self) -> Option<std::os::unix::io::RawFd> {
+        None
+    }
+
+    /// Returns the handle that output from the slave is read from, or
+    /// None if the implementation doesn't expose one.  The handle
+    /// remains owned by the master.
+    #[cfg(windows)]
+    fn as_raw_handle(&self) -> Option<std::os::windows::io::RawHandle> {
+        None
+    }
 }
 
 /// Represents a child process spawned into the pty.
@@ -110,31 +143,71 @@ pub trait SlavePty {
     fn spawn_command(&self, cmd: CommandBuilder) -> Result<Box<dyn Child>, Error>;
 }
 
-/// Represents the exit status of a child process.
-/// This is rather anemic in the current version of this crate,
-/// holding only an indicator of success or failure.
-#[derive(Debug)]
+/// Represents the exit status of a child process: either the code
+/// that it exited with, or the signal that terminated it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ExitStatus {
-    successful: bool,
+    code: Option<u32>,
+    signal: Option<i32>,
 }
 
 impl ExitStatus {
     /// Construct an ExitStatus from a process return code
     pub fn with_exit_code(code: u32) -> Self {
         Self {
-            successful: code == 0,
+            code: Some(code),
+            signal: None,
+        }
+    }
+
+    /// Construct an ExitStatus for a process that was terminated by
+    /// a signal
+    pub fn with_signal(signal: i32) -> Self {
+        Self {
+            code: None,
+            signal: Some(signal),
         }
     }
 
     pub fn success(&self) -> bool {
-        self.successful
+        self.code == Some(0)
+    }
+
+    /// Returns the code that the process exited with, or None if it
+    /// was terminated by a signal
+    pub fn exit_code(&self) -> Option<u32> {
+        self.code
+    }
+
+    /// Returns the signal that terminated the process, if any
+    pub fn signal(&self) -> Option<i32> {
+        self.signal
+    }
+}
+
+impl std::fmt::Display for ExitStatus {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match (self.code, self.signal) {
+            (Some(0), _) => write!(fmt, "exited successfully"),
+            (Some(code), _) => write!(fmt, "exited with code {}", code),
+            (None, Some(signal)) => write!(fmt, "was terminated by signal {}", signal),
+            (None, None) => write!(fmt, "exited with an unknown status"),
+        }
     }
 }
 
 impl From<std::process::ExitStatus> for ExitStatus {
     fn from(status: std::process::ExitStatus) -> ExitStatus {
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+            if let Some(signal) = status.signal() {
+                return ExitStatus::with_signal(signal);
+            }
+        }
         ExitStatus {
-            successful: status.success(),
+            code: status.code().map(|code| code as u32),
+            signal: None,
         }
     }
 }
@@ -174,7 +247,7 @@ impl Child for std::process::Child {
 /// `PtySystemSelection` allows selecting and constructing one of the
 /// pty implementations provided by this crate.
 #[derive(Debug, Clone, Copy)]
-#[cfg_attr(feature = "serde_support", derive(Deserialize))]
+#[cfg_attr(feature = "serde_support", derive(Deserialize, Serialize))]
 pub enum PtySystemSelection {
     /// The Unix style pty interface
     Unix,
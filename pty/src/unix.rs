@@ -92,7 +92,6 @@ fn cloexec(fd: RawFd) -> Result<(), Error> {
     Ok(())
 }
 
-#[allow(dead_code)]
 fn clear_nonblocking(fd: RawFd) -> Result<(), Error> {
     let flags = unsafe { libc::fcntl(fd, libc::F_GETFL, 0) };
     if flags == -1 {
@@ -111,7 +110,6 @@ fn clear_nonblocking(fd: RawFd) -> Result<(), Error> {
     Ok(())
 }
 
-#[allow(dead_code)]
 fn set_nonblocking(fd: RawFd) -> Result<(), Error> {
     let flags = unsafe { libc::fcntl(fd, libc::F_GETFL, 0) };
     if flags == -1 {
@@ -239,6 +237,25 @@ impl MasterPty for UnixMasterPty {
         let fd = self.fd.try_clone()?;
         Ok(Box::new(fd))
     }
+
+    fn try_clone_writer(&self) -> Result<Box<dyn std::io::Write + Send>, Error> {
+        let fd = self.fd.try_clone()?;
+        Ok(Box::new(fd))
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> Fallible<()> {
+        // The flag belongs to the open file description, so it also
+        // applies to the descriptors held by cloned readers
+        if nonblocking {
+            set_nonblocking(self.fd.as_raw_fd())
+        } else {
+            clear_nonblocking(self.fd.as_raw_fd())
+        }
+    }
+
+    fn as_raw_fd(&self) -> Option<RawFd> {
+        Some(self.fd.as_raw_fd())
+    }
 }
 
 impl io::Write for UnixMasterPty {
@@ -18,8 +18,8 @@ use winapi::shared::minwindef::DWORD;
 use winapi::shared::winerror::{HRESULT, S_OK};
 use winapi::um::handleapi::*;
 use winapi::um::processthreadsapi::*;
-use winapi::um::winbase::EXTENDED_STARTUPINFO_PRESENT;
 use winapi::um::winbase::STARTUPINFOEXW;
+use winapi::um::winbase::{CREATE_UNICODE_ENVIRONMENT, EXTENDED_STARTUPINFO_PRESENT};
 use winapi::um::wincon::COORD;
 
 const PROC_THREAD_ATTRIBUTE_PSEUDOCONSOLE: usize = 0x00020016;
@@ -200,10 +200,14 @@ impl Inner {
         pixel_width: u16,
         pixel_height: u16,
     ) -> Result<(), Error> {
-        self.con.resize(COORD {
-            X: num_cols as i16,
-            Y: num_rows as i16,
-        })?;
+        // ConPTY redraws the whole screen when it is resized, so only
+        // tell it when the number of cells actually changes
+        if num_rows != self.size.rows || num_cols != self.size.cols {
+            self.con.resize(COORD {
+                X: num_cols as i16,
+                Y: num_rows as i16,
+            })?;
+        }
         self.size = PtySize {
             rows: num_rows,
             cols: num_cols,
@@ -237,6 +241,14 @@ impl MasterPty for ConPtyMasterPty {
     fn try_clone_reader(&self) -> Result<Box<std::io::Read + Send>, Error> {
         Ok(Box::new(self.inner.lock().unwrap().readable.try_clone()?))
     }
+
+    fn try_clone_writer(&self) -> Result<Box<std::io::Write + Send>, Error> {
+        Ok(Box::new(self.inner.lock().unwrap().writable.try_clone()?))
+    }
+
+    fn as_raw_handle(&self) -> Option<HANDLE> {
+        Some(self.inner.lock().unwrap().readable.as_raw_handle())
+    }
 }
 
 impl io::Write for ConPtyMasterPty {
@@ -263,6 +275,8 @@ impl SlavePty for ConPtySlavePty {
 
         let (mut exe, mut cmdline) = cmd.cmdline()?;
         let cmd_os = OsString::from_wide(&cmdline);
+        let cwd = cmd.current_directory();
+        let mut env = cmd.environment_block();
         let res = unsafe {
             CreateProcessW(
                 exe.as_mut_slice().as_mut_ptr(),
@@ -270,9 +284,9 @@ impl SlavePty for ConPtySlavePty {
                 ptr::null_mut(),
                 ptr::null_mut(),
                 0,
-                EXTENDED_STARTUPINFO_PRESENT,
-                ptr::null_mut(), // FIXME: env
-                ptr::null_mut(),
+                EXTENDED_STARTUPINFO_PRESENT | CREATE_UNICODE_ENVIRONMENT,
+                env.as_mut_ptr() as *mut _,
+                cwd.as_ref().map(|c| c.as_ptr()).unwrap_or(ptr::null()),
                 &mut si.StartupInfo,
                 &mut pi,
             )
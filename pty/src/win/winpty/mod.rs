@@ -49,6 +49,10 @@ impl MasterPty for WinPtyMasterPty {
     fn try_clone_reader(&self) -> Result<Box<std::io::Read + Send>, Error> {
         Ok(Box::new(self.inner.lock().unwrap().reader.try_clone()?))
     }
+
+    fn try_clone_writer(&self) -> Result<Box<std::io::Write + Send>, Error> {
+        Ok(Box::new(self.inner.lock().unwrap().writer.try_clone()?))
+    }
 }
 
 impl std::io::Write for WinPtyMasterPty {
@@ -74,8 +78,8 @@ impl SlavePty for WinPtySlavePty {
             SpawnFlags::AUTO_SHUTDOWN | SpawnFlags::EXIT_AFTER_SHUTDOWN,
             Some(exe),
             Some(cmdline),
-            None, // cwd
-            None, // env
+            cmd.current_directory(),
+            Some(cmd.environment_block()),
         )?;
 
         let mut inner = self.inner.lock().unwrap();
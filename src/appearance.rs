@@ -0,0 +1,302 @@
+//! Detects whether the desktop prefers a light or a dark appearance,
+//! so that the color scheme can follow it.
+use failure::Fallible;
+use log::error;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Appearance {
+    Light,
+    Dark,
+}
+
+impl Default for Appearance {
+    fn default() -> Self {
+        Appearance::Light
+    }
+}
+
+/// How often to check the appearance on systems that cannot tell
+/// us when it changes
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Returns the appearance that the desktop currently prefers
+pub fn get_appearance() -> Fallible<Appearance> {
+    imp::get_appearance()
+}
+
+/// Spawns a thread that calls `on_change` each time that the
+/// appearance preferred by the desktop changes.
+pub fn watch_for_changes<F>(on_change: F) -> Fallible<()>
+where
+    F: Fn(Appearance) + Send + 'static,
+{
+    let current = get_appearance()?;
+    std::thread::spawn(move || imp::watch(current, on_change));
+    Ok(())
+}
+
+fn poll_for_changes<F: Fn(Appearance)>(mut current: Appearance, on_change: F) {
+    loop {
+        std::thread::sleep(POLL_INTERVAL);
+        match get_appearance() {
+            Ok(appearance) if appearance != current => {
+                current = appearance;
+                on_change(appearance);
+            }
+            Ok(_) => {}
+            Err(err) => {
+                error!("Unable to check the desktop appearance: {}", err);
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod imp {
+    use super::*;
+    use failure::{bail, format_err};
+    use std::io::{BufRead, BufReader};
+    use std::process::{Command, Stdio};
+
+    const PORTAL_DEST: &str = "org.freedesktop.portal.Desktop";
+    const PORTAL_PATH: &str = "/org/freedesktop/portal/desktop";
+
+    fn command_output(cmd: &mut Command) -> Fallible<String> {
+        let output = cmd.output()?;
+        if !output.status.success() {
+            bail!(
+                "{:?} failed: {}",
+                cmd,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// Interprets the `color-scheme` value from the output of gdbus,
+    /// which looks like `(<<uint32 1>>,)` for a method call and ends
+    /// in `<uint32 1>)` for a SettingChanged signal.
+    /// The portal uses 1 for dark, 2 for light and 0 for no preference.
+    fn parse_portal_color_scheme(output: &str) -> Option<Appearance> {
+        let value = output.split("uint32").nth(1)?;
+        let digits: String = value
+            .trim_start()
+            .chars()
+            .take_while(char::is_ascii_digit)
+            .collect();
+        match digits.parse::<u32>().ok()? {
+            1 => Some(Appearance::Dark),
+            _ => Some(Appearance::Light),
+        }
+    }
+
+    pub fn get_appearance() -> Fallible<Appearance> {
+        match command_output(Command::new("gdbus").args(&[
+            "call",
+            "--session",
+            "--dest",
+            PORTAL_DEST,
+            "--object-path",
+            PORTAL_PATH,
+            "--method",
+            "org.freedesktop.portal.Settings.Read",
+            "org.freedesktop.appearance",
+            "color-scheme",
+        ])) {
+            Ok(output) => parse_portal_color_scheme(&output)
+                .ok_or_else(|| format_err!("unexpected response from the portal: {}", output)),
+            Err(_) => {
+                // Desktops without the settings portal may still
+                // have the GNOME setting
+                let output = command_output(Command::new("gsettings").args(&[
+                    "get",
+                    "org.gnome.desktop.interface",
+                    "color-scheme",
+                ]))?;
+                if output.contains("dark") {
+                    Ok(Appearance::Dark)
+                } else {
+                    Ok(Appearance::Light)
+                }
+            }
+        }
+    }
+
+    /// Follows the SettingChanged signals of the settings portal,
+    /// falling back to polling if we cannot monitor the session bus.
+    pub fn watch<F: Fn(Appearance)>(mut current: Appearance, on_change: F) {
+        let mut child = match Command::new("gdbus")
+            .args(&[
+                "monitor",
+                "--session",
+                "--dest",
+                PORTAL_DEST,
+                "--object-path",
+                PORTAL_PATH,
+            ])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(err) => {
+                error!("Unable to monitor the settings portal: {}", err);
+                return poll_for_changes(current, on_change);
+            }
+        };
+
+        if let Some(stdout) = child.stdout.take() {
+            for line in BufReader::new(stdout).lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+                if !line.contains("SettingChanged") || !line.contains("'color-scheme'") {
+                    continue;
+                }
+                if let Some(appearance) = parse_portal_color_scheme(&line) {
+                    if appearance != current {
+                        current = appearance;
+                        on_change(appearance);
+                    }
+                }
+            }
+        }
+        let _ = child.wait();
+        poll_for_changes(current, on_change);
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        #[test]
+        fn portal_color_scheme() {
+            assert_eq!(
+                parse_portal_color_scheme("(<<uint32 1>>,)\n"),
+                Some(Appearance::Dark)
+            );
+            assert_eq!(
+                parse_portal_color_scheme("(<<uint32 2>>,)\n"),
+                Some(Appearance::Light)
+            );
+            // No preference
+            assert_eq!(
+                parse_portal_color_scheme("(<<uint32 0>>,)\n"),
+                Some(Appearance::Light)
+            );
+            assert_eq!(
+                parse_portal_color_scheme(
+                    "/org/freedesktop/portal/desktop: org.freedesktop.portal.Settings.SettingChanged \
+                     ('org.freedesktop.appearance', 'color-scheme', <uint32 1>)"
+                ),
+                Some(Appearance::Dark)
+            );
+            assert_eq!(parse_portal_color_scheme("(<'prefer-dark'>,)"), None);
+            assert_eq!(parse_portal_color_scheme("(<<uint32 >>,)"), None);
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod imp {
+    use super::*;
+    use std::process::Command;
+
+    pub fn get_appearance() -> Fallible<Appearance> {
+        // The key is absent, and `defaults` fails, in light mode
+        let output = Command::new("defaults")
+            .args(&["read", "-g", "AppleInterfaceStyle"])
+            .output()?;
+        if String::from_utf8_lossy(&output.stdout).trim() == "Dark" {
+            Ok(Appearance::Dark)
+        } else {
+            Ok(Appearance::Light)
+        }
+    }
+
+    pub fn watch<F: Fn(Appearance)>(current: Appearance, on_change: F) {
+        poll_for_changes(current, on_change);
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use super::*;
+    use winapi::shared::minwindef::{DWORD, HKEY};
+    use winapi::shared::winerror::ERROR_SUCCESS;
+    use winapi::um::winnt::{KEY_NOTIFY, REG_NOTIFY_CHANGE_LAST_SET};
+    use winapi::um::winreg::{
+        RegCloseKey, RegGetValueW, RegNotifyChangeKeyValue, RegOpenKeyExW, HKEY_CURRENT_USER,
+        RRF_RT_REG_DWORD,
+    };
+
+    const PERSONALIZE: &str = r"Software\Microsoft\Windows\CurrentVersion\Themes\Personalize";
+
+    fn wide(s: &str) -> Vec<u16> {
+        s.encode_utf16().chain(std::iter::once(0)).collect()
+    }
+
+    pub fn get_appearance() -> Fallible<Appearance> {
+        let key = wide(PERSONALIZE);
+        let value = wide("AppsUseLightTheme");
+        let mut data: DWORD = 1;
+        let mut size = std::mem::size_of::<DWORD>() as DWORD;
+        let res = unsafe {
+            RegGetValueW(
+                HKEY_CURRENT_USER,
+                key.as_ptr(),
+                value.as_ptr(),
+                RRF_RT_REG_DWORD,
+                std::ptr::null_mut(),
+                &mut data as *mut DWORD as *mut _,
+                &mut size,
+            )
+        };
+        // Versions of Windows without a dark mode lack the value
+        if res as DWORD == ERROR_SUCCESS && data == 0 {
+            Ok(Appearance::Dark)
+        } else {
+            Ok(Appearance::Light)
+        }
+    }
+
+    /// Waits for the values under the Personalize key to change,
+    /// falling back to polling if we cannot open it.
+    pub fn watch<F: Fn(Appearance)>(mut current: Appearance, on_change: F) {
+        let key_name = wide(PERSONALIZE);
+        let mut key: HKEY = std::ptr::null_mut();
+        let res = unsafe {
+            RegOpenKeyExW(
+                HKEY_CURRENT_USER,
+                key_name.as_ptr(),
+                0,
+                KEY_NOTIFY,
+                &mut key,
+            )
+        };
+        if res as DWORD != ERROR_SUCCESS {
+            error!("Unable to watch the Personalize registry key: {}", res);
+            return poll_for_changes(current, on_change);
+        }
+        loop {
+            // Without an event this blocks until something changes
+            let res = unsafe {
+                RegNotifyChangeKeyValue(key, 0, REG_NOTIFY_CHANGE_LAST_SET, std::ptr::null_mut(), 0)
+            };
+            if res as DWORD != ERROR_SUCCESS {
+                break;
+            }
+            match get_appearance() {
+                Ok(appearance) if appearance != current => {
+                    current = appearance;
+                    on_change(appearance);
+                }
+                _ => {}
+            }
+        }
+        unsafe { RegCloseKey(key) };
+    }
+}
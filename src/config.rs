@@ -1,13 +1,15 @@
 //! Configuration for the gui portion of the terminal
 
+use crate::appearance::Appearance;
 use crate::font::FontSystemSelection;
 use crate::frontend::guicommon::host::KeyAssignment;
 use crate::frontend::FrontEndSelection;
 use crate::get_shell;
+use crate::server::codec::{Compression, CompressionPolicy};
 use failure::{bail, err_msg, format_err, Error, Fallible};
 use lazy_static::lazy_static;
 use portable_pty::{CommandBuilder, PtySystemSelection};
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_derive::*;
 use std;
 use std::collections::HashMap;
@@ -15,14 +17,17 @@ use std::convert::TryInto;
 use std::ffi::OsStr;
 use std::fs;
 use std::io::prelude::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
 use term;
 use term::color::RgbColor;
+use termwiz::escape::csi::CursorStyle;
 use termwiz::hyperlink;
 use termwiz::input::{KeyCode, Modifiers};
 use toml;
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Config {
     /// The font size, measured in points
     #[serde(default = "default_font_size")]
@@ -32,6 +37,18 @@ pub struct Config {
     #[serde(default = "default_dpi")]
     pub dpi: f64,
 
+    /// Scales the height of a cell relative to the height computed
+    /// from the font metrics.  Values greater than 1.0 add space
+    /// between lines, split evenly above and below the text.
+    #[serde(default = "default_cell_scale")]
+    pub line_height: f64,
+
+    /// Scales the width of a cell relative to the width computed
+    /// from the font metrics.  Values less than 1.0 tighten the
+    /// spacing between characters.
+    #[serde(default = "default_cell_scale")]
+    pub cell_width: f64,
+
     /// The baseline font to use
     #[serde(default)]
     pub font: TextStyle,
@@ -41,12 +58,178 @@ pub struct Config {
     #[serde(default)]
     pub font_rules: Vec<StyleRule>,
 
-    /// The color palette
+    /// Fonts to search for glyphs that are missing from the fonts
+    /// selected by `font` or `font_rules`, in order of preference.
+    /// These are consulted ahead of the built-in emoji and symbol
+    /// fallbacks.
+    ///
+    /// ```
+    /// font_fallback = [{family = "Noto Sans CJK JP"}, {family = "Noto Color Emoji"}]
+    /// ```
+    #[serde(default)]
+    pub font_fallback: Vec<FontAttributes>,
+
+    /// Whether runs of text are shaped together, allowing the font
+    /// to substitute ligatures for sequences of characters such as
+    /// `->` or `!=`.  When false, each character is drawn with its
+    /// own glyph.  The default is true.
+    pub font_shaping: Option<bool>,
+
+    /// The color palette.  Additional named palettes may be defined
+    /// in `[colors.<name>]` sections and selected via `color_scheme`.
     pub colors: Option<Palette>,
 
+    /// The name of the color scheme to use at startup.  When not set,
+    /// the palette from the `[colors]` section is used.
+    ///
+    /// ```
+    /// color_scheme = "light"
+    ///
+    /// [colors.light]
+    /// foreground = "#000000"
+    /// background = "#ffffff"
+    /// ```
+    pub color_scheme: Option<String>,
+
+    /// The names of the color schemes to use while the desktop prefers
+    /// a light or a dark appearance.  When either is set at startup, the
+    /// color scheme switches as the desktop preference changes; an
+    /// appearance without a scheme of its own uses `color_scheme`.
+    ///
+    /// ```
+    /// light_color_scheme = "light"
+    /// dark_color_scheme = "dark"
+    /// ```
+    pub light_color_scheme: Option<String>,
+    pub dark_color_scheme: Option<String>,
+
+    /// An image, such as a png or jpeg file, to draw beneath the
+    /// terminal.  Cells that have the default background color let
+    /// the image show through.
+    ///
+    /// ```
+    /// window_background_image = "/home/me/Pictures/sunset.jpg"
+    /// window_background_image_mode = "Cover"
+    /// ```
+    pub window_background_image: Option<PathBuf>,
+
+    /// How the background image is fitted to the window.  One of
+    /// "Stretch", "Fit", "Cover" or "Tile".  The default is "Stretch".
+    pub window_background_image_mode: Option<BackgroundImageMode>,
+
+    /// The opacity of the window background, including the background
+    /// image, from 0.0 (fully transparent) to 1.0 (the default).
+    /// Transparency requires a compositing window manager, and
+    /// changing whether a window is transparent applies to the
+    /// windows that are opened afterwards.
+    pub window_background_opacity: Option<f32>,
+
+    /// The background opacities that the `CycleWindowOpacity` action
+    /// steps each window through, eg: `[1.0, 0.8, 0.6]`.  Windows are
+    /// opened with transparency enabled when any of them is below 1.0.
+    pub window_opacity_presets: Option<Vec<f32>>,
+
+    /// Which GPU to prefer on systems with both an integrated and a
+    /// discrete GPU: "LowPower" or "HighPerformance".  The default is
+    /// whichever the system chooses.  This is only honored where the
+    /// system lets a program choose, which currently means Mesa
+    /// drivers on Linux and the BSDs, and only applies at startup.
+    pub prefer_gpu: Option<GpuPreference>,
+
+    /// The opacity of the background colors that programs set on
+    /// cells, from 0.0 to 1.0 (the default).  Lowering it lets the
+    /// background image show through colored cells too.
+    pub text_background_opacity: Option<f32>,
+
+    /// How bright the text of a window that doesn't have the keyboard
+    /// focus is, from 0.0, which fades it into its background, to
+    /// 1.0 (the default), which leaves it as it is.
+    pub unfocused_window_text_brightness: Option<f32>,
+
+    /// The shape of the cursor, used unless a program chooses one
+    /// with DECSCUSR.  One of "SteadyBlock" (the default),
+    /// "BlinkingBlock", "SteadyUnderline", "BlinkingUnderline",
+    /// "SteadyBar" or "BlinkingBar".
+    pub default_cursor_style: Option<DefaultCursorStyle>,
+
+    /// How long a blinking cursor is shown and then hidden for, in
+    /// milliseconds.  Defaults to 800; 0 stops the cursor blinking.
+    pub cursor_blink_rate: Option<u64>,
+
+    /// The width of the bar cursor, in pixels at a dpi scale of 1.0.
+    /// Defaults to a sixteenth of the cell height.
+    pub cursor_bar_thickness: Option<f32>,
+
+    /// The height of the underline cursor, in pixels at a dpi scale
+    /// of 1.0.  Defaults to a sixteenth of the cell height.
+    pub cursor_underline_height: Option<f32>,
+
+    /// When set, the block cursor is drawn as an outline of this
+    /// width, in pixels at a dpi scale of 1.0, around a cell that
+    /// keeps its own colors, rather than as a filled cell.
+    pub cursor_block_outline_width: Option<f32>,
+
+    /// When true, keys pressed with modifiers are always reported to
+    /// programs in the CSI u form, such as `ESC [ 67 ; 6 u` for
+    /// Ctrl+Shift+C, so that chords that are usually indistinguishable
+    /// can be bound separately.  Programs that understand xterm's
+    /// modifyOtherKeys can instead turn on reporting themselves.
+    /// Defaults to false.
+    pub enable_csi_u_key_encoding: Option<bool>,
+
+    /// When true, new tabs start in the mode for displaying ANSI art,
+    /// in which the output is decoded as CP437 rather than UTF-8 and
+    /// blinking text has a bright background instead (iCE colors).
+    /// Programs can switch the mode with `CSI ? 8437 h` and
+    /// `CSI ? 8437 l`.  Defaults to false.
+    pub ansi_art_mode: Option<bool>,
+
+    /// The names of the domains, such as "local", whose new tabs are
+    /// run as dumb terminals: TERM is set to "dumb", the text is
+    /// rendered without colors or attributes and only the basic
+    /// cursor and editing sequences are acted upon.  Tabs on a mux
+    /// server follow the server's setting for its "local" domain.
+    #[serde(default)]
+    pub dumb_terminal_domains: Vec<String>,
+
+    /// Settings for the new tabs of particular domains, keyed by the
+    /// name of the domain, such as "local" or "mux:hostname".  These
+    /// override `default_prog` and `term`, and add to the environment:
+    ///
+    /// ```
+    /// [domains.local]
+    /// default_prog = ["zsh", "-l"]
+    /// term = "xterm-256color"
+    /// [domains.local.set_environment_variables]
+    /// EDITOR = "vim"
+    /// ```
+    #[serde(default)]
+    pub domains: HashMap<String, DomainSettings>,
+
+    /// What to do with a tab when the program running in it exits.
+    /// One of "Close" (the default), "Hold" or "CloseOnCleanExit".
+    pub exit_behavior: Option<ExitBehavior>,
+
     /// How many lines of scrollback you want to retain
     pub scrollback_lines: Option<usize>,
 
+    /// How many of the most recent lines of scrollback are kept as
+    /// they are.  Older lines are compressed in chunks to save memory
+    /// and are decompressed when they are scrolled back to.  Defaults
+    /// to 10000; set it to at least `scrollback_lines` to never
+    /// compress the scrollback.
+    pub uncompressed_scrollback_lines: Option<usize>,
+
+    /// How many recently copied pieces of text to remember for the
+    /// ShowPasteHistory action.  Defaults to 16.
+    pub paste_history_size: Option<usize>,
+
+    /// Whether dismissing the lock screen shown by the LockScreen
+    /// action requires the password of the user, rather than just
+    /// pressing Enter.  This needs a build with the `pam` feature.
+    /// Defaults to false.
+    pub lock_screen_requires_password: Option<bool>,
+
     /// If no `prog` is specified on the command line, use this
     /// instead of running the user's shell.
     /// For example, to have `wezterm` always run `top` by default,
@@ -64,6 +247,51 @@ pub struct Config {
     #[serde(default = "default_hyperlink_rules")]
     pub hyperlink_rules: Vec<hyperlink::Rule>,
 
+    /// The regular expressions that the `QuickSelect` action labels
+    /// on the screen.  The default finds URLs, paths, git hashes and
+    /// IP addresses.
+    pub quick_select_patterns: Option<Vec<String>>,
+
+    /// The modifiers that must be held down when clicking on a link
+    /// found by the `hyperlink_rules` to open it, such as `"CTRL"`
+    /// (the default) or `"NONE"`.  Links that the program emitted
+    /// with OSC 8 open on a plain click.
+    #[serde(
+        default = "default_implicit_link_click_modifiers",
+        deserialize_with = "de_modifiers",
+        serialize_with = "ser_modifiers"
+    )]
+    pub implicit_link_click_modifiers: Modifiers,
+
+    /// The command used to open the `file:path#line` links produced
+    /// for `src/main.rs:42` by the default hyperlink rules.  `{path}`
+    /// and `{line}` in each argument are replaced by the path and line
+    /// number from the link.  When not set, the file is opened with
+    /// the default application for it and the line number is ignored.
+    /// Other `file:` links, such as `file://host/path`, are always
+    /// opened with the default application.
+    ///
+    /// ```
+    /// editor_command = ["code", "--goto", "{path}:{line}"]
+    /// ```
+    pub editor_command: Option<Vec<String>>,
+
+    /// The set of characters that end a word when double-click
+    /// selecting.  Defaults to whitespace, brackets and quotes.
+    /// Non-ASCII characters other than letters and digits always end
+    /// a word.
+    pub selection_word_boundary: Option<String>,
+
+    /// Controls whether programs running in the terminal may use the
+    /// OSC 52 escape sequence to access the clipboard.  One of "Deny",
+    /// "WriteOnly" or "ReadWrite".  The default is "WriteOnly", which
+    /// lets programs such as vim or tmux copy to the clipboard without
+    /// being able to read what other programs copied there.
+    /// When connected to a mux server, the server's setting governs
+    /// its tabs and the client's setting governs whether their
+    /// requests reach the local clipboard.
+    pub osc52_clipboard_access: Option<ClipboardAccess>,
+
     /// What to set the TERM variable to
     #[serde(default = "default_term")]
     pub term: String,
@@ -122,15 +350,292 @@ pub struct Config {
     /// weakens the security of the TLS channel.
     pub mux_client_accept_invalid_hostnames: Option<bool>,
 
+    /// The certificate presented by a mux server is recorded in the
+    /// `known_hosts` file in the wezterm data directory the first
+    /// time that we connect to it, after asking in the window if one
+    /// is open.  A server whose certificate later changes is refused,
+    /// unless the user agrees in the window to replace the recorded
+    /// certificate.  If this is set to false, servers whose certificate
+    /// hasn't been recorded are refused without asking, so that the
+    /// file has to be filled in by hand.  The default is true.
+    pub mux_client_trust_new_hosts: Option<bool>,
+
+    /// The maximum size, in bytes, of a single frame sent between
+    /// the mux client and server.  Frames that claim to be larger
+    /// than this are rejected and the connection is dropped.
+    /// The default is 64MB.
+    pub mux_max_frame_size: Option<usize>,
+
+    /// The maximum number of clients that may be connected to the
+    /// mux server at the same time.  Additional connections are
+    /// closed immediately.  The default is 32.
+    pub mux_server_max_clients: Option<usize>,
+
+    /// The maximum number of requests per second that the mux server
+    /// will process for a single client connection.  Requests beyond
+    /// this rate are delayed.  The default is 1000.
+    pub mux_server_max_pdus_per_second: Option<u32>,
+
+    /// The maximum number of spawn requests per minute that the mux
+    /// server will accept across all clients.  Requests beyond this
+    /// rate fail with an error.  The default is 60.
+    pub mux_server_max_spawns_per_minute: Option<u32>,
+
+    /// The number of lines of output that the mux server pushes to a
+    /// client for a tab before waiting for the client to acknowledge
+    /// them.  While it waits it stops reading the output of the program
+    /// in the tab, so that a program producing output at full speed
+    /// can't flood a slow connection.  0 disables the limit.  The
+    /// default is 2000.
+    pub mux_flow_control_window: Option<usize>,
+
+    /// A shared secret that mux clients must present when they
+    /// connect to the mux server.  When set in the server config,
+    /// connections that don't present a matching token are dropped.
+    /// The mux client sends the value from its own config.
+    pub mux_auth_token: Option<String>,
+
+    /// PDUs whose serialized size exceeds this many bytes are
+    /// candidates for compression.  The default is 32.
+    pub mux_compression_threshold: Option<usize>,
+
+    /// The zstd compression level used for mux PDUs.  Higher levels
+    /// save bandwidth on slow links at the cost of more CPU time.
+    /// The default is zstd's default level.
+    pub mux_compression_level: Option<i32>,
+
+    /// Overrides the compression policy for specific types of PDU,
+    /// keyed by PDU name; unknown names are rejected.  Each value is
+    /// one of "Never", "Auto" or "Always".  By default, keyboard and
+    /// mouse input is never compressed, renderable data is always
+    /// compressed and other PDUs are compressed when they exceed
+    /// mux_compression_threshold.
+    pub mux_compression_policy: Option<HashMap<String, CompressionPolicy>>,
+
     #[serde(default)]
     pub keys: Vec<Key>,
+
+    /// Mouse buttons that perform an action instead of being passed
+    /// to the terminal.  For example:
+    /// `{button = "Right", mods = "", action = "Paste"}`
+    #[serde(default)]
+    pub mouse_bindings: Vec<MouseBinding>,
+
+    /// If set, controls the text of the window title.  The following
+    /// placeholders are expanded:
+    ///
+    /// * `{title}` - the title of the active tab, as set by the
+    ///   program in it with OSC 0 or 2, or by `RenameTab`
+    /// * `{cwd}` - the working directory of the active tab, as
+    ///   reported by the shell with OSC 7
+    /// * `{tab_index}` - the 1-based index of the active tab
+    /// * `{tab_count}` - the number of tabs in the window
+    /// * `{domain}` - the name of the domain hosting the active tab,
+    ///   for example `local` or `mux:hostname`
+    /// * `{domain_state}` - the connection state of that domain,
+    ///   for example `connected` or `reconnecting`
+    ///
+    /// ```
+    /// window_title_template = "{title} ({domain}: {domain_state})"
+    /// ```
+    pub window_title_template: Option<String>,
+
+    /// If set, controls the label of each tab in the tab bar, using
+    /// the same placeholders as `window_title_template` for that tab.
+    /// By default tabs show `{title}`, preceded by `{domain}` for tabs
+    /// that don't run on this machine, and by `{domain_state}` too
+    /// while their domain isn't connected.
+    pub tab_title_template: Option<String>,
+
+    /// Whether to show the tab bar.  The default is true.
+    pub enable_tab_bar: Option<bool>,
+
+    /// If true, the tab bar is placed at the bottom of the window
+    /// rather than at the top.  The default is false.
+    pub tab_bar_at_bottom: Option<bool>,
+
+    /// Whether to show a scrollbar on the right edge of the window.
+    /// It shows the position of the viewport within the scrollback
+    /// and can be dragged to scroll.  The default is false.
+    pub enable_scroll_bar: Option<bool>,
+
+    /// Whether to watch the configuration file and apply any changes
+    /// made to it to the running windows.  The default is true.
+    pub automatically_reload_config: Option<bool>,
+
+    /// How long toasts, the short notices shown in a corner of the
+    /// window, stay up, in milliseconds.  0 turns them off.  The
+    /// default is 3000.
+    pub toast_duration_ms: Option<u64>,
+
+    /// Which corner of the window toasts are shown in.  The default
+    /// is `TopRight`.
+    pub toast_position: Option<ToastPosition>,
+
+    /// Whether to briefly flash the window when the tab being shown
+    /// in it rings the bell.  The default is false.
+    pub visual_bell: Option<bool>,
+
+    /// Whether to raise a desktop notification when a tab that isn't
+    /// being shown rings the bell or, if `tab_activity_silence_secs`
+    /// is set, produces output after being silent.  The default is
+    /// false.
+    pub bell_desktop_notification: Option<bool>,
+
+    /// How long a tab that isn't being shown must have been silent,
+    /// in seconds, before new output marks it in the tab bar.  0, the
+    /// default, turns off activity monitoring.
+    pub tab_activity_silence_secs: Option<u64>,
+
+    /// Counts the number of times that the configuration has been
+    /// reloaded, so that holders of an older copy can tell that
+    /// it has been replaced.  This is not read from the file.
+    #[serde(skip)]
+    pub generation: usize,
+}
+
+/// When a changed setting takes effect after the configuration
+/// is reloaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReloadScope {
+    /// Applied to the existing tabs and windows right away
+    Immediate,
+    /// Used by new windows; the existing windows only switch to
+    /// them when the reload is forced
+    Fonts,
+    /// Only used when spawning tabs or starting wezterm
+    NewTabsOrRestart,
+}
+
+/// Returns when a change to the named top level setting takes effect
+pub fn reload_scope(name: &str) -> ReloadScope {
+    match name {
+        "font_size" | "dpi" | "line_height" | "cell_width" | "font" | "font_rules"
+        | "font_fallback" | "font_shaping" | "font_system" => ReloadScope::Fonts,
+        "default_prog" | "term" | "dumb_terminal_domains" | "domains" => {
+            ReloadScope::NewTabsOrRestart
+        }
+        "front_end" | "pty" | "automatically_reload_config" => ReloadScope::NewTabsOrRestart,
+        name if name.starts_with("mux_") => ReloadScope::NewTabsOrRestart,
+        _ => ReloadScope::Immediate,
+    }
+}
+
+/// Which clipboard operations programs may perform via OSC 52
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum ClipboardAccess {
+    /// OSC 52 requests are ignored
+    Deny,
+    /// Programs may set or clear the clipboard, but not read it
+    WriteOnly,
+    /// Programs may set, clear and read the clipboard
+    ReadWrite,
+}
+
+impl ClipboardAccess {
+    pub fn allows_write(self) -> bool {
+        self != ClipboardAccess::Deny
+    }
+
+    pub fn allows_read(self) -> bool {
+        self == ClipboardAccess::ReadWrite
+    }
+}
+
+/// The corner of the window in which toasts are shown
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum ToastPosition {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl Default for ToastPosition {
+    fn default() -> Self {
+        ToastPosition::TopRight
+    }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+/// Which GPU to render with on systems that have more than one
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum GpuPreference {
+    /// The integrated GPU, which uses less power
+    LowPower,
+    /// The discrete GPU, which is faster
+    HighPerformance,
+}
+
+/// How the window background image is fitted to the window
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum BackgroundImageMode {
+    /// Scale the image to the size of the window, ignoring its
+    /// aspect ratio
+    Stretch,
+    /// Scale the image to fit inside the window, keeping its
+    /// aspect ratio and centering it
+    Fit,
+    /// Scale the image to cover the window, keeping its aspect
+    /// ratio and cropping the edges that don't fit
+    Cover,
+    /// Repeat the image at its natural size
+    Tile,
+}
+
+/// What happens to a tab when the program running in it exits
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum ExitBehavior {
+    /// Close the tab
+    Close,
+    /// Keep showing the tab and how the program exited until a key
+    /// is pressed
+    Hold,
+    /// Close the tab if the program exited successfully, otherwise
+    /// hold it like `Hold`
+    CloseOnCleanExit,
+}
+
+impl Default for ExitBehavior {
+    fn default() -> Self {
+        ExitBehavior::Close
+    }
+}
+
+/// The cursor shapes that can be configured as the default
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum DefaultCursorStyle {
+    SteadyBlock,
+    BlinkingBlock,
+    SteadyUnderline,
+    BlinkingUnderline,
+    SteadyBar,
+    BlinkingBar,
+}
+
+impl Default for DefaultCursorStyle {
+    fn default() -> Self {
+        DefaultCursorStyle::SteadyBlock
+    }
+}
+
+impl From<DefaultCursorStyle> for CursorStyle {
+    fn from(style: DefaultCursorStyle) -> CursorStyle {
+        match style {
+            DefaultCursorStyle::SteadyBlock => CursorStyle::SteadyBlock,
+            DefaultCursorStyle::BlinkingBlock => CursorStyle::BlinkingBlock,
+            DefaultCursorStyle::SteadyUnderline => CursorStyle::SteadyUnderline,
+            DefaultCursorStyle::BlinkingUnderline => CursorStyle::BlinkingUnderline,
+            DefaultCursorStyle::SteadyBar => CursorStyle::SteadyBar,
+            DefaultCursorStyle::BlinkingBar => CursorStyle::BlinkingBar,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Key {
-    #[serde(deserialize_with = "de_keycode")]
+    #[serde(deserialize_with = "de_keycode", serialize_with = "ser_keycode")]
     pub key: KeyCode,
-    #[serde(deserialize_with = "de_modifiers")]
+    #[serde(deserialize_with = "de_modifiers", serialize_with = "ser_modifiers")]
     pub mods: Modifiers,
     pub action: KeyAction,
     pub arg: Option<String>,
@@ -139,43 +644,122 @@ pub struct Key {
 impl std::convert::TryInto<KeyAssignment> for &Key {
     type Error = Error;
     fn try_into(self) -> Result<KeyAssignment, Error> {
-        Ok(match self.action {
-            KeyAction::SpawnTab => KeyAssignment::SpawnTab,
-            KeyAction::SpawnTabInCurrentTabDomain => KeyAssignment::SpawnTabInCurrentTabDomain,
-            KeyAction::SpawnWindow => KeyAssignment::SpawnWindow,
-            KeyAction::ToggleFullScreen => KeyAssignment::ToggleFullScreen,
-            KeyAction::Copy => KeyAssignment::Copy,
-            KeyAction::Paste => KeyAssignment::Paste,
-            KeyAction::Hide => KeyAssignment::Hide,
-            KeyAction::Show => KeyAssignment::Show,
-            KeyAction::IncreaseFontSize => KeyAssignment::IncreaseFontSize,
-            KeyAction::DecreaseFontSize => KeyAssignment::DecreaseFontSize,
-            KeyAction::ResetFontSize => KeyAssignment::ResetFontSize,
-            KeyAction::Nop => KeyAssignment::Nop,
-            KeyAction::CloseCurrentTab => KeyAssignment::CloseCurrentTab,
-            KeyAction::ActivateTab => KeyAssignment::ActivateTab(
-                self.arg
-                    .as_ref()
-                    .ok_or_else(|| format_err!("missing arg for {:?}", self))?
-                    .parse()?,
-            ),
-            KeyAction::ActivateTabRelative => KeyAssignment::ActivateTabRelative(
-                self.arg
-                    .as_ref()
-                    .ok_or_else(|| format_err!("missing arg for {:?}", self))?
-                    .parse()?,
-            ),
-            KeyAction::SendString => KeyAssignment::SendString(
-                self.arg
-                    .as_ref()
-                    .ok_or_else(|| format_err!("missing arg for {:?}", self))?
-                    .to_owned(),
-            ),
-        })
-    }
-}
-
-#[derive(Debug, Deserialize, Clone)]
+        resolve_assignment(&self.action, &self.arg, self)
+    }
+}
+
+/// A mouse button, pressed with `mods` held down, that performs
+/// `action` instead of being passed to the terminal
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MouseBinding {
+    pub button: term::MouseButton,
+    #[serde(deserialize_with = "de_modifiers", serialize_with = "ser_modifiers")]
+    pub mods: Modifiers,
+    pub action: KeyAction,
+    pub arg: Option<String>,
+}
+
+impl std::convert::TryInto<KeyAssignment> for &MouseBinding {
+    type Error = Error;
+    fn try_into(self) -> Result<KeyAssignment, Error> {
+        resolve_assignment(&self.action, &self.arg, self)
+    }
+}
+
+/// Resolves `action` and its `arg` into the assignment that they
+/// describe.  `binding` is used to report problems with the `arg`.
+fn resolve_assignment(
+    action: &KeyAction,
+    arg: &Option<String>,
+    binding: &dyn std::fmt::Debug,
+) -> Fallible<KeyAssignment> {
+    Ok(match action {
+        KeyAction::SpawnTab => KeyAssignment::SpawnTab,
+        KeyAction::SpawnTabInCurrentTabDomain => KeyAssignment::SpawnTabInCurrentTabDomain,
+        KeyAction::SpawnWindow => KeyAssignment::SpawnWindow,
+        KeyAction::ToggleFullScreen => KeyAssignment::ToggleFullScreen,
+        KeyAction::Copy => KeyAssignment::Copy,
+        KeyAction::Paste => KeyAssignment::Paste,
+        KeyAction::Hide => KeyAssignment::Hide,
+        KeyAction::Show => KeyAssignment::Show,
+        KeyAction::IncreaseFontSize => KeyAssignment::IncreaseFontSize,
+        KeyAction::DecreaseFontSize => KeyAssignment::DecreaseFontSize,
+        KeyAction::ResetFontSize => KeyAssignment::ResetFontSize,
+        KeyAction::Nop => KeyAssignment::Nop,
+        KeyAction::CloseCurrentTab => KeyAssignment::CloseCurrentTab,
+        KeyAction::ActivateCopyMode => KeyAssignment::ActivateCopyMode,
+        KeyAction::CopyNearestUrl => KeyAssignment::CopyNearestUrl,
+        KeyAction::OpenNearestUrl => KeyAssignment::OpenNearestUrl,
+        KeyAction::CycleColorScheme => KeyAssignment::CycleColorScheme,
+        KeyAction::ShowPasteHistory => KeyAssignment::ShowPasteHistory,
+        KeyAction::ShowLauncher => KeyAssignment::ShowLauncher,
+        KeyAction::OpenLinkAtMouseCursor => KeyAssignment::OpenLinkAtMouseCursor,
+        KeyAction::LockScreen => KeyAssignment::LockScreen,
+        KeyAction::RenameTab => KeyAssignment::RenameTab,
+        KeyAction::ReloadConfiguration => KeyAssignment::ReloadConfiguration,
+        KeyAction::DetachDomain => KeyAssignment::DetachDomain,
+        KeyAction::SaveLayout => KeyAssignment::SaveLayout(arg.as_ref().map(PathBuf::from)),
+        KeyAction::RestoreLayout => KeyAssignment::RestoreLayout(arg.as_ref().map(PathBuf::from)),
+        KeyAction::ScrollByPage => KeyAssignment::ScrollByPage(
+            arg.as_ref()
+                .ok_or_else(|| format_err!("missing arg for {:?}", binding))?
+                .parse()?,
+        ),
+        KeyAction::ScrollByLine => KeyAssignment::ScrollByLine(
+            arg.as_ref()
+                .ok_or_else(|| format_err!("missing arg for {:?}", binding))?
+                .parse()?,
+        ),
+        KeyAction::ScrollToPrompt => KeyAssignment::ScrollToPrompt(
+            arg.as_ref()
+                .ok_or_else(|| format_err!("missing arg for {:?}", binding))?
+                .parse()?,
+        ),
+        KeyAction::SplitWindow => KeyAssignment::SplitWindow(
+            arg.as_ref()
+                .ok_or_else(|| format_err!("missing arg for {:?}", binding))?
+                .parse()?,
+        ),
+        KeyAction::PasteFrom => KeyAssignment::PasteFrom(
+            arg.as_ref()
+                .ok_or_else(|| format_err!("missing arg for {:?}", binding))?
+                .parse()?,
+        ),
+        KeyAction::ActivateTab => KeyAssignment::ActivateTab(
+            arg.as_ref()
+                .ok_or_else(|| format_err!("missing arg for {:?}", binding))?
+                .parse()?,
+        ),
+        KeyAction::ActivateTabRelative => KeyAssignment::ActivateTabRelative(
+            arg.as_ref()
+                .ok_or_else(|| format_err!("missing arg for {:?}", binding))?
+                .parse()?,
+        ),
+        KeyAction::MoveTabRelative => KeyAssignment::MoveTabRelative(
+            arg.as_ref()
+                .ok_or_else(|| format_err!("missing arg for {:?}", binding))?
+                .parse()?,
+        ),
+        KeyAction::ActivateLastTab => KeyAssignment::ActivateLastTab,
+        KeyAction::ToggleAlwaysOnTop => KeyAssignment::ToggleAlwaysOnTop,
+        KeyAction::CycleWindowOpacity => KeyAssignment::CycleWindowOpacity,
+        KeyAction::ShowGpuInfo => KeyAssignment::ShowGpuInfo,
+        KeyAction::QuickSelect => KeyAssignment::QuickSelect,
+        KeyAction::Search => KeyAssignment::Search,
+        KeyAction::SendString => KeyAssignment::SendString(
+            arg.as_ref()
+                .ok_or_else(|| format_err!("missing arg for {:?}", binding))?
+                .to_owned(),
+        ),
+        KeyAction::SetColorScheme => KeyAssignment::SetColorScheme(
+            arg.as_ref()
+                .ok_or_else(|| format_err!("missing arg for {:?}", binding))?
+                .to_owned(),
+        ),
+    })
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub enum KeyAction {
     SpawnTab,
     SpawnTabInCurrentTabDomain,
@@ -188,11 +772,37 @@ pub enum KeyAction {
     DecreaseFontSize,
     ResetFontSize,
     ActivateTab,
+    MoveTabRelative,
+    ActivateLastTab,
+    ToggleAlwaysOnTop,
+    CycleWindowOpacity,
+    ShowGpuInfo,
+    QuickSelect,
+    Search,
     SendString,
     Nop,
     Hide,
     Show,
     CloseCurrentTab,
+    ActivateCopyMode,
+    CopyNearestUrl,
+    OpenNearestUrl,
+    CycleColorScheme,
+    SetColorScheme,
+    PasteFrom,
+    ShowPasteHistory,
+    ShowLauncher,
+    SplitWindow,
+    OpenLinkAtMouseCursor,
+    LockScreen,
+    RenameTab,
+    ScrollByPage,
+    ScrollByLine,
+    ScrollToPrompt,
+    ReloadConfiguration,
+    DetachDomain,
+    SaveLayout,
+    RestoreLayout,
 }
 
 fn de_keycode<'de, D>(deserializer: D) -> Result<KeyCode, D::Error>
@@ -320,7 +930,10 @@ where
     let s = String::deserialize(deserializer)?;
     let mut mods = Modifiers::NONE;
     for ele in s.split('|') {
-        if ele == "SHIFT" {
+        if ele.is_empty() || ele == "NONE" {
+            // Allows bindings that don't require any modifiers
+            continue;
+        } else if ele == "SHIFT" {
             mods |= Modifiers::SHIFT;
         } else if ele == "ALT" || ele == "OPT" || ele == "META" {
             mods |= Modifiers::ALT;
@@ -338,12 +951,48 @@ where
     Ok(mods)
 }
 
+fn ser_keycode<S>(key: &KeyCode, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let s = match key {
+        KeyCode::Char(c) => c.to_string(),
+        KeyCode::Function(n) => format!("F{}", n),
+        key => format!("{:?}", key),
+    };
+    s.serialize(serializer)
+}
+
+fn ser_modifiers<S>(mods: &Modifiers, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let mut names = vec![];
+    for (flag, name) in &[
+        (Modifiers::SHIFT, "SHIFT"),
+        (Modifiers::ALT, "ALT"),
+        (Modifiers::CTRL, "CTRL"),
+        (Modifiers::SUPER, "SUPER"),
+    ] {
+        if mods.contains(*flag) {
+            names.push(*name);
+        }
+    }
+    names.join("|").serialize(serializer)
+}
+
+fn default_implicit_link_click_modifiers() -> Modifiers {
+    Modifiers::CTRL
+}
+
 fn default_hyperlink_rules() -> Vec<hyperlink::Rule> {
     vec![
         // URL with a protocol
         hyperlink::Rule::new(r"\b\w+://(?:[\w.-]+)\.[a-z]{2,15}\S*\b", "$0").unwrap(),
         // implicit mailto link
         hyperlink::Rule::new(r"\b\w+@[\w-]+(\.[\w-]+)+\b", "mailto:$0").unwrap(),
+        // file path with a line number, as emitted by compilers
+        hyperlink::Rule::new(r"((?:/|\b)(?:[\w.-]+/)+[\w.-]+\.\w+):(\d+)\b", "file:$1#$2").unwrap(),
     ]
 }
 
@@ -359,19 +1008,55 @@ fn default_dpi() -> f64 {
     96.0
 }
 
+fn default_cell_scale() -> f64 {
+    1.0
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
             font_size: default_font_size(),
             dpi: default_dpi(),
+            line_height: default_cell_scale(),
+            cell_width: default_cell_scale(),
             font: TextStyle::default(),
             font_rules: Vec::new(),
+            font_fallback: Vec::new(),
+            font_shaping: None,
             font_system: FontSystemSelection::default(),
             front_end: FrontEndSelection::default(),
             pty: PtySystemSelection::default(),
             colors: None,
+            color_scheme: None,
+            light_color_scheme: None,
+            dark_color_scheme: None,
+            window_background_image: None,
+            window_background_image_mode: None,
+            window_background_opacity: None,
+            window_opacity_presets: None,
+            prefer_gpu: None,
+            text_background_opacity: None,
+            unfocused_window_text_brightness: None,
+            default_cursor_style: None,
+            cursor_blink_rate: None,
+            cursor_bar_thickness: None,
+            cursor_underline_height: None,
+            cursor_block_outline_width: None,
+            enable_csi_u_key_encoding: None,
+            ansi_art_mode: None,
+            dumb_terminal_domains: vec![],
+            domains: HashMap::new(),
+            exit_behavior: None,
             scrollback_lines: None,
+            uncompressed_scrollback_lines: None,
+            paste_history_size: None,
+            lock_screen_requires_password: None,
             hyperlink_rules: default_hyperlink_rules(),
+            quick_select_patterns: None,
+            implicit_link_click_modifiers: default_implicit_link_click_modifiers(),
+            editor_command: None,
+            selection_word_boundary: None,
+            osc52_clipboard_access: None,
             term: default_term(),
             default_prog: None,
             mux_server_unix_domain_socket_path: None,
@@ -384,7 +1069,30 @@ impl Default for Config {
             mux_client_pem_cert: None,
             mux_client_pem_ca: None,
             mux_client_accept_invalid_hostnames: None,
+            mux_client_trust_new_hosts: None,
+            mux_max_frame_size: None,
+            mux_server_max_clients: None,
+            mux_server_max_pdus_per_second: None,
+            mux_server_max_spawns_per_minute: None,
+            mux_flow_control_window: None,
+            mux_auth_token: None,
+            mux_compression_threshold: None,
+            mux_compression_level: None,
+            mux_compression_policy: None,
             keys: vec![],
+            mouse_bindings: vec![],
+            window_title_template: None,
+            tab_title_template: None,
+            enable_tab_bar: None,
+            tab_bar_at_bottom: None,
+            enable_scroll_bar: None,
+            automatically_reload_config: None,
+            toast_duration_ms: None,
+            toast_position: None,
+            visual_bell: None,
+            bell_desktop_notification: None,
+            tab_activity_silence_secs: None,
+            generation: 0,
         }
     }
 }
@@ -396,7 +1104,7 @@ const FONT_FAMILY: &str = "Consolas";
 #[cfg(all(not(target_os = "macos"), not(windows)))]
 const FONT_FAMILY: &str = "monospace";
 
-#[derive(Debug, Deserialize, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Hash)]
 pub struct FontAttributes {
     /// The font family name
     pub family: String,
@@ -421,7 +1129,7 @@ fn empty_font_attributes() -> Vec<FontAttributes> {
 }
 
 /// Represents textual styling.
-#[derive(Debug, Deserialize, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Hash)]
 pub struct TextStyle {
     #[serde(default = "empty_font_attributes")]
     pub font: Vec<FontAttributes>,
@@ -475,8 +1183,11 @@ impl TextStyle {
         }
     }
 
+    /// Returns the fonts for this style followed by the `fallback`
+    /// fonts from the config and then the built-in fallbacks for
+    /// the platform.
     #[cfg_attr(feature = "cargo-clippy", allow(clippy::let_and_return))]
-    pub fn font_with_fallback(&self) -> Vec<FontAttributes> {
+    pub fn font_with_fallback(&self, fallback: &[FontAttributes]) -> Vec<FontAttributes> {
         #[allow(unused_mut)]
         let mut font = self.font.clone();
 
@@ -487,6 +1198,8 @@ impl TextStyle {
             font.push(FontAttributes::default());
         }
 
+        font.extend(fallback.iter().cloned());
+
         #[cfg(target_os = "macos")]
         font.push(FontAttributes {
             family: "Apple Color Emoji".into(),
@@ -517,6 +1230,12 @@ impl TextStyle {
             bold: None,
             italic: None,
         });
+        #[cfg(all(unix, not(target_os = "macos")))]
+        font.push(FontAttributes {
+            family: "Noto Color Emoji".into(),
+            bold: None,
+            italic: None,
+        });
 
         font
     }
@@ -536,7 +1255,7 @@ impl TextStyle {
 /// The above is translated as: "if the `CellAttributes` have the italic bit
 /// set, then use the italic style of font rather than the default", and
 /// stop processing further font rules.
-#[derive(Debug, Default, Deserialize, Clone)]
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
 pub struct StyleRule {
     /// If present, this rule matches when CellAttributes::intensity holds
     /// a value that matches this rule.  Valid values are "Bold", "Normal",
@@ -566,6 +1285,7 @@ pub struct StyleRule {
     pub font: TextStyle,
 }
 
+#[cfg(unix)]
 fn compute_runtime_dir() -> Result<PathBuf, Error> {
     if let Some(runtime) = dirs::runtime_dir() {
         return Ok(runtime.join("wezterm"));
@@ -575,30 +1295,202 @@ fn compute_runtime_dir() -> Result<PathBuf, Error> {
     Ok(home.join(".local/share/wezterm"))
 }
 
+#[cfg(windows)]
+fn compute_runtime_dir() -> Result<PathBuf, Error> {
+    let local = dirs::data_local_dir().ok_or_else(|| err_msg("can't find LOCALAPPDATA"))?;
+    Ok(local.join("wezterm"))
+}
+
+#[cfg(unix)]
+fn compute_data_dir() -> Result<PathBuf, Error> {
+    if let Some(data) = dirs::data_dir() {
+        return Ok(data.join("wezterm"));
+    }
+
+    let home = dirs::home_dir().ok_or_else(|| err_msg("can't find home dir"))?;
+    Ok(home.join(".local/share/wezterm"))
+}
+
+#[cfg(windows)]
+fn compute_data_dir() -> Result<PathBuf, Error> {
+    let data = dirs::data_dir().ok_or_else(|| err_msg("can't find APPDATA"))?;
+    Ok(data.join("wezterm"))
+}
+
+/// Returns the per-user directory that holds data that should outlive
+/// the mux server, creating it if it doesn't exist yet
+pub fn data_dir() -> Fallible<PathBuf> {
+    let dir = compute_data_dir()?;
+    create_user_owned_dirs(&dir)?;
+    Ok(dir)
+}
+
+/// Returns the file that records the certificates of the mux servers
+/// that we have connected to
+pub fn known_hosts_path() -> Fallible<PathBuf> {
+    Ok(data_dir()?.join("known_hosts"))
+}
+
+/// Returns the per-user directory that holds the mux server sockets
+/// and pid file, creating it if it doesn't exist yet
+pub fn runtime_dir() -> Fallible<PathBuf> {
+    create_user_owned_dirs(&RUNTIME_DIR)?;
+    Ok(RUNTIME_DIR.clone())
+}
+
+/// Returns the file in which a mux server records its process id
+pub fn pid_file_path() -> PathBuf {
+    RUNTIME_DIR.join("pid")
+}
+
+/// Creates `path` and any missing parents, accessible only to the
+/// current user, and checks that other users can't manipulate its
+/// contents if it already existed.
+/// Setting `WEZTERM_SKIP_MUX_SOCK_PERMISSIONS_CHECK` skips the check.
+pub fn create_user_owned_dirs(path: &Path) -> Fallible<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+
+    builder
+        .create(path)
+        .map_err(|e| format_err!("failed to create {}: {}", path.display(), e))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+        if std::env::var_os("WEZTERM_SKIP_MUX_SOCK_PERMISSIONS_CHECK").is_none() {
+            let meta = path.symlink_metadata()?;
+            if meta.uid() != unsafe { libc::getuid() } {
+                bail!(
+                    "{} is owned by another user (uid={})",
+                    path.display(),
+                    meta.uid()
+                );
+            }
+
+            let permissions = meta.permissions();
+            if (permissions.mode() & 0o22) != 0 {
+                bail!(
+                    "The permissions for {} are insecure and currently
+                allow other users to write to it (permissions={:?})",
+                    path.display(),
+                    permissions
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
 lazy_static! {
     static ref HOME_DIR: PathBuf = dirs::home_dir().expect("can't find HOME dir");
     static ref RUNTIME_DIR: PathBuf = compute_runtime_dir().unwrap();
+    static ref CONFIG_FILE_OVERRIDE: Mutex<Option<PathBuf>> = Mutex::new(None);
+}
+
+/// Arranges for the configuration to be loaded only from `path`,
+/// as though it had been named by `WEZTERM_CONFIG_FILE`.
+/// This is used to implement the `--config-file` flag.
+pub fn set_config_file_override(path: &Path) {
+    *CONFIG_FILE_OVERRIDE.lock().unwrap() = Some(path.to_path_buf());
+}
+
+/// Returns the configuration file that was explicitly named, either
+/// by `--config-file` or the `WEZTERM_CONFIG_FILE` environment variable
+fn config_file_override() -> Option<PathBuf> {
+    if let Some(path) = CONFIG_FILE_OVERRIDE.lock().unwrap().as_ref() {
+        return Some(path.clone());
+    }
+    std::env::var_os("WEZTERM_CONFIG_FILE")
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Returns the locations that are searched for the configuration file,
+/// in order of preference
+fn config_file_paths() -> Vec<PathBuf> {
+    if let Some(path) = config_file_override() {
+        return vec![path];
+    }
+
+    let mut paths = vec![];
+    // On Linux this honors $XDG_CONFIG_HOME; on macOS and Windows it
+    // is the platform equivalent
+    if let Some(dir) = dirs::config_dir() {
+        paths.push(dir.join("wezterm").join("wezterm.toml"));
+    }
+    paths.push(
+        HOME_DIR
+            .join(".config")
+            .join("wezterm")
+            .join("wezterm.toml"),
+    );
+    paths.push(HOME_DIR.join(".wezterm.toml"));
+    paths.dedup();
+    paths
+}
+
+/// Spawns a thread that watches the configuration file for changes.
+/// Each time that it changes the configuration is loaded again and
+/// passed to `on_change`.  If the new configuration cannot be loaded
+/// the error is logged and the change is otherwise ignored.
+pub fn watch_for_changes<F>(on_change: F) -> Fallible<()>
+where
+    F: Fn(Fallible<Config>) + Send + 'static,
+{
+    use notify::{DebouncedEvent, RecursiveMode, Watcher};
+    use std::sync::mpsc::channel;
+
+    let paths = config_file_paths();
+    let (tx, rx) = channel();
+    let mut watcher = notify::watcher(tx, std::time::Duration::from_millis(200))
+        .map_err(|e| format_err!("failed to create config watcher: {}", e))?;
+
+    // Editors commonly replace the file rather than writing to it,
+    // and the file may not exist yet, so watch the containing
+    // directories rather than the files themselves
+    for dir in paths.iter().filter_map(|p| p.parent()) {
+        if dir.is_dir() {
+            watcher
+                .watch(dir, RecursiveMode::NonRecursive)
+                .map_err(|e| format_err!("failed to watch {}: {}", dir.display(), e))?;
+        }
+    }
+
+    std::thread::spawn(move || {
+        // Keep the watcher alive for as long as we are receiving from it
+        let _watcher = watcher;
+        for event in rx {
+            let changed = match event {
+                DebouncedEvent::Create(path)
+                | DebouncedEvent::Write(path)
+                | DebouncedEvent::Remove(path) => paths.contains(&path),
+                DebouncedEvent::Rename(from, to) => paths.contains(&from) || paths.contains(&to),
+                _ => false,
+            };
+            if !changed {
+                continue;
+            }
+            on_change(Config::load());
+        }
+    });
+    Ok(())
 }
 
 impl Config {
     pub fn load() -> Result<Self, Error> {
-        // Note that the directories crate has methods for locating project
-        // specific config directories, but only returns one of them, not
-        // multiple.  In addition, it spawns a lot of subprocesses,
-        // so we do this bit "by-hand"
-        let paths = [
-            HOME_DIR
-                .join(".config")
-                .join("wezterm")
-                .join("wezterm.toml"),
-            HOME_DIR.join(".wezterm.toml"),
-        ];
-
-        for p in &paths {
+        for p in &config_file_paths() {
             let mut file = match fs::File::open(p) {
                 Ok(file) => file,
                 Err(err) => match err.kind() {
-                    std::io::ErrorKind::NotFound => continue,
+                    std::io::ErrorKind::NotFound if config_file_override().is_none() => continue,
                     _ => bail!("Error opening {}: {:?}", p.display(), err),
                 },
             };
@@ -612,16 +1504,252 @@ impl Config {
             // Compute but discard the key bindings here so that we raise any
             // problems earlier than we use them.
             let _ = cfg.key_bindings()?;
+            let _ = cfg.mouse_bindings()?;
+            if let Some(policy) = cfg.mux_compression_policy.as_ref() {
+                Compression::validate_policy(policy)
+                    .map_err(|e| format_err!("{} in {}", e, p.display()))?;
+            }
+            if cfg.line_height <= 0.0 || cfg.cell_width <= 0.0 {
+                bail!(
+                    "line_height and cell_width must be greater than zero in {}",
+                    p.display()
+                );
+            }
+            for (key, name) in &[
+                ("color_scheme", &cfg.color_scheme),
+                ("light_color_scheme", &cfg.light_color_scheme),
+                ("dark_color_scheme", &cfg.dark_color_scheme),
+            ] {
+                if let Some(name) = name.as_ref() {
+                    if cfg.color_scheme(name).is_none() {
+                        bail!(
+                            "{} `{}` is not defined in {}; the available schemes are {:?}",
+                            key,
+                            name,
+                            p.display(),
+                            cfg.color_scheme_names()
+                        );
+                    }
+                }
+            }
             return Ok(cfg.compute_extra_defaults());
         }
 
         Ok(Self::default().compute_extra_defaults())
     }
 
+    /// Returns the configured tab bar colors, or the defaults
+    /// if none were specified
+    pub fn tab_bar_colors(&self) -> TabBarColors {
+        self.colors
+            .as_ref()
+            .and_then(|colors| colors.tab_bar.clone())
+            .unwrap_or_default()
+    }
+
+    /// Returns the configured toast colors, or the defaults if none
+    /// were specified
+    pub fn toast_colors(&self) -> ToastColors {
+        self.colors
+            .as_ref()
+            .and_then(|colors| colors.toast.clone())
+            .unwrap_or_default()
+    }
+
+    /// Returns the configured colors of scrollback search matches, or
+    /// the defaults if none were specified
+    pub fn search_colors(&self) -> SearchColors {
+        self.colors
+            .as_ref()
+            .and_then(|colors| colors.search.clone())
+            .unwrap_or_default()
+    }
+
+    /// Returns how long toasts are shown for, or None if they are
+    /// turned off
+    pub fn toast_duration(&self) -> Option<Duration> {
+        match self.toast_duration_ms.unwrap_or(3000) {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        }
+    }
+
+    /// Returns how long a tab must be silent before new output marks
+    /// it as active, or None if activity isn't monitored
+    pub fn tab_activity_silence(&self) -> Option<Duration> {
+        match self.tab_activity_silence_secs.unwrap_or(0) {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+
+    /// Returns the names of the schemes defined in `[colors.<name>]`
+    /// sections, in sorted order
+    pub fn color_scheme_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .colors
+            .as_ref()
+            .map(|colors| colors.schemes.keys().cloned().collect())
+            .unwrap_or_else(Vec::new);
+        names.sort();
+        names
+    }
+
+    pub fn color_scheme(&self, name: &str) -> Option<&Palette> {
+        self.colors
+            .as_ref()
+            .and_then(|colors| colors.schemes.get(name))
+    }
+
+    /// Returns true if the color scheme should follow the appearance
+    /// preferred by the desktop
+    pub fn follows_appearance(&self) -> bool {
+        self.light_color_scheme.is_some() || self.dark_color_scheme.is_some()
+    }
+
+    /// Returns the name of the color scheme to use while the desktop
+    /// prefers `appearance`
+    pub fn color_scheme_for_appearance(&self, appearance: Appearance) -> Option<String> {
+        let scheme = match appearance {
+            Appearance::Light => self.light_color_scheme.as_ref(),
+            Appearance::Dark => self.dark_color_scheme.as_ref(),
+        };
+        scheme.or_else(|| self.color_scheme.as_ref()).cloned()
+    }
+
+    /// Computes the palette for the named scheme.  When `scheme` is
+    /// None or is not defined, the palette from the `[colors]` section
+    /// is used.
+    pub fn palette(&self, scheme: Option<&str>) -> term::color::ColorPalette {
+        match scheme.and_then(|name| self.color_scheme(name)) {
+            Some(palette) => palette.clone().into(),
+            None => self
+                .colors
+                .as_ref()
+                .map(|colors| colors.clone().into())
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn scrollback_lines(&self) -> usize {
+        self.scrollback_lines.unwrap_or(3500)
+    }
+
+    pub fn uncompressed_scrollback_lines(&self) -> usize {
+        self.uncompressed_scrollback_lines.unwrap_or(10000)
+    }
+
+    pub fn osc52_clipboard_access(&self) -> ClipboardAccess {
+        self.osc52_clipboard_access
+            .unwrap_or(ClipboardAccess::WriteOnly)
+    }
+
+    pub fn window_background_image_mode(&self) -> BackgroundImageMode {
+        self.window_background_image_mode
+            .unwrap_or(BackgroundImageMode::Stretch)
+    }
+
+    pub fn window_background_opacity(&self) -> f32 {
+        self.window_background_opacity
+            .unwrap_or(1.0)
+            .max(0.0)
+            .min(1.0)
+    }
+
+    /// Returns the configured window opacity presets, clamped to the
+    /// range 0.0 to 1.0
+    pub fn window_opacity_presets(&self) -> Vec<f32> {
+        self.window_opacity_presets
+            .iter()
+            .flatten()
+            .map(|opacity| opacity.max(0.0).min(1.0))
+            .collect()
+    }
+
+    /// Returns true if new windows need an alpha channel, because they
+    /// are, or may later be made, transparent
+    pub fn window_is_translucent(&self) -> bool {
+        self.window_background_opacity() < 1.0
+            || self
+                .window_opacity_presets()
+                .iter()
+                .any(|opacity| *opacity < 1.0)
+    }
+
+    pub fn quick_select_patterns(&self) -> Vec<String> {
+        match self.quick_select_patterns.as_ref() {
+            Some(patterns) => patterns.clone(),
+            None => vec![
+                // URL with a protocol
+                r#"\b\w+://\S+[^\s.,;:!?'")\]]"#.to_owned(),
+                // path with at least one slash
+                r"(?:~|\.{1,2}|\b[\w.-]+)?(?:/[\w.@+-]+)+/?".to_owned(),
+                // git hash
+                r"\b[0-9a-f]{7,40}\b".to_owned(),
+                // IPv4 address
+                r"\b\d{1,3}(?:\.\d{1,3}){3}\b".to_owned(),
+            ],
+        }
+    }
+
+    pub fn text_background_opacity(&self) -> f32 {
+        self.text_background_opacity
+            .unwrap_or(1.0)
+            .max(0.0)
+            .min(1.0)
+    }
+
+    pub fn unfocused_window_text_brightness(&self) -> f32 {
+        self.unfocused_window_text_brightness
+            .unwrap_or(1.0)
+            .max(0.0)
+            .min(1.0)
+    }
+
+    /// Resolves the cursor style requested by a program, replacing
+    /// CursorStyle::Default with the configured default style
+    pub fn cursor_style(&self, requested: CursorStyle) -> CursorStyle {
+        match requested {
+            CursorStyle::Default => self.default_cursor_style.unwrap_or_default().into(),
+            style => style,
+        }
+    }
+
+    /// Returns how long a blinking cursor stays in each phase, or
+    /// None if the cursor shouldn't blink
+    pub fn cursor_blink_rate(&self) -> Option<Duration> {
+        match self.cursor_blink_rate.unwrap_or(800) {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        }
+    }
+
     pub fn default_config() -> Self {
         Self::default().compute_extra_defaults()
     }
 
+    /// Returns the sorted names of the top level settings whose
+    /// values differ between `self` and `other`
+    pub fn changed_settings(&self, other: &Config) -> Fallible<Vec<String>> {
+        let table = |config: &Config| -> Fallible<toml::value::Table> {
+            match toml::Value::try_from(config)? {
+                toml::Value::Table(table) => Ok(table),
+                _ => bail!("config did not serialize as a table"),
+            }
+        };
+        let ours = table(self)?;
+        let theirs = table(other)?;
+        let mut names: Vec<String> = ours
+            .keys()
+            .chain(theirs.keys())
+            .filter(|name| ours.get(*name) != theirs.get(*name))
+            .cloned()
+            .collect();
+        names.sort();
+        names.dedup();
+        Ok(names)
+    }
+
     pub fn key_bindings(&self) -> Fallible<HashMap<(KeyCode, Modifiers), KeyAssignment>> {
         let mut map = HashMap::new();
 
@@ -633,6 +1761,19 @@ impl Config {
         Ok(map)
     }
 
+    pub fn mouse_bindings(
+        &self,
+    ) -> Fallible<HashMap<(term::MouseButton, Modifiers), KeyAssignment>> {
+        let mut map = HashMap::new();
+
+        for m in &self.mouse_bindings {
+            let value = m.try_into()?;
+            map.insert((m.button, m.mods), value);
+        }
+
+        Ok(map)
+    }
+
     /// In some cases we need to compute expanded values based
     /// on those provided by the user.  This is where we do that.
     fn compute_extra_defaults(&self) -> Self {
@@ -729,9 +1870,61 @@ impl Config {
 
         Ok(cmd)
     }
+
+    /// Builds the command that a new tab in the named domain runs when
+    /// it isn't given one: the `default_prog` of the domain, if it has
+    /// one, or else the global `default_prog`
+    pub fn build_prog_for_domain(&self, domain_name: &str) -> Result<CommandBuilder, Error> {
+        let prog = match self
+            .domains
+            .get(domain_name)
+            .and_then(|settings| settings.default_prog.clone())
+        {
+            Some(prog) => prog,
+            None => self.default_prog()?,
+        };
+        if prog.is_empty() {
+            bail!("the default_prog of domain {} is empty", domain_name);
+        }
+        let mut cmd = self.build_prog(Some(prog.iter().map(OsStr::new).collect()))?;
+        self.apply_domain_environment(domain_name, &mut cmd);
+        Ok(cmd)
+    }
+
+    /// Sets TERM and the environment variables configured for the
+    /// named domain on a command that is to be spawned in it, along
+    /// with the directory of the domain if the command has none
+    pub fn apply_domain_environment(&self, domain_name: &str, cmd: &mut CommandBuilder) {
+        if let Some(settings) = self.domains.get(domain_name) {
+            if let Some(term) = settings.term.as_ref() {
+                cmd.env("TERM", term);
+            }
+            if let (Some(cwd), None) = (settings.cwd.as_ref(), cmd.get_cwd()) {
+                cmd.cwd(cwd);
+            }
+            for (name, value) in &settings.set_environment_variables {
+                cmd.env(name, value);
+            }
+        }
+    }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+/// The settings that apply to the new tabs of a domain
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct DomainSettings {
+    /// The program to run in place of the global `default_prog`
+    pub default_prog: Option<Vec<String>>,
+    /// What to set the TERM variable to in place of the global `term`
+    pub term: Option<String>,
+    /// Environment variables to set for the programs in the tabs
+    #[serde(default)]
+    pub set_environment_variables: HashMap<String, String>,
+    /// The directory to start the programs in, rather than the home
+    /// directory.  This is a path on the machine that runs them.
+    pub cwd: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Palette {
     /// The text color to use when the attributes are reset to default
     pub foreground: Option<RgbColor>,
@@ -748,6 +1941,108 @@ pub struct Palette {
     /// A list of 8 colors corresponding to bright versions of the
     /// ANSI palette
     pub brights: Option<[RgbColor; 8]>,
+    /// A list of 8 colors corresponding to half-bright versions of
+    /// the ANSI palette, used for text with the dim (SGR 2) attribute.
+    /// When not set, dim text is blended toward the background.
+    pub dims: Option<[RgbColor; 8]>,
+    /// The colors used to render the tab bar
+    pub tab_bar: Option<TabBarColors>,
+    /// The colors used to render toasts
+    pub toast: Option<ToastColors>,
+    /// The colors used to highlight scrollback search matches
+    pub search: Option<SearchColors>,
+    /// Named schemes defined in `[colors.<name>]` sections.  These
+    /// are only meaningful at the top level of the `[colors]` section.
+    #[serde(flatten)]
+    pub schemes: HashMap<String, Palette>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct TabBarColors {
+    /// The color of the strip that goes along the top of the window
+    pub background: Option<RgbColor>,
+    /// The colors of the tab that is currently active
+    pub active_tab_fg: Option<RgbColor>,
+    pub active_tab_bg: Option<RgbColor>,
+    /// The colors of the tabs that are not active
+    pub inactive_tab_fg: Option<RgbColor>,
+    pub inactive_tab_bg: Option<RgbColor>,
+}
+
+impl TabBarColors {
+    pub fn background(&self) -> RgbColor {
+        self.background
+            .unwrap_or_else(|| RgbColor::new(0x0b, 0x00, 0x22))
+    }
+
+    pub fn active_tab_fg(&self) -> RgbColor {
+        self.active_tab_fg
+            .unwrap_or_else(|| RgbColor::new(0xc0, 0xc0, 0xc0))
+    }
+
+    pub fn active_tab_bg(&self) -> RgbColor {
+        self.active_tab_bg
+            .unwrap_or_else(|| RgbColor::new(0x2b, 0x20, 0x42))
+    }
+
+    pub fn inactive_tab_fg(&self) -> RgbColor {
+        self.inactive_tab_fg
+            .unwrap_or_else(|| RgbColor::new(0x80, 0x80, 0x80))
+    }
+
+    pub fn inactive_tab_bg(&self) -> RgbColor {
+        self.inactive_tab_bg
+            .unwrap_or_else(|| RgbColor::new(0x1b, 0x10, 0x32))
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct ToastColors {
+    pub foreground: Option<RgbColor>,
+    pub background: Option<RgbColor>,
+}
+
+impl ToastColors {
+    pub fn foreground(&self) -> RgbColor {
+        self.foreground
+            .unwrap_or_else(|| RgbColor::new(0xc0, 0xc0, 0xc0))
+    }
+
+    pub fn background(&self) -> RgbColor {
+        self.background
+            .unwrap_or_else(|| RgbColor::new(0x2b, 0x20, 0x42))
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct SearchColors {
+    /// The colors of the matches in view
+    pub match_fg: Option<RgbColor>,
+    pub match_bg: Option<RgbColor>,
+    /// The colors of the match that was stepped to
+    pub current_match_fg: Option<RgbColor>,
+    pub current_match_bg: Option<RgbColor>,
+}
+
+impl SearchColors {
+    pub fn match_fg(&self) -> RgbColor {
+        self.match_fg.unwrap_or_else(|| RgbColor::new(0, 0, 0))
+    }
+
+    pub fn match_bg(&self) -> RgbColor {
+        self.match_bg
+            .unwrap_or_else(|| RgbColor::new(0xff, 0xd7, 0x00))
+    }
+
+    pub fn current_match_fg(&self) -> RgbColor {
+        self.current_match_fg
+            .unwrap_or_else(|| RgbColor::new(0, 0, 0))
+    }
+
+    pub fn current_match_bg(&self) -> RgbColor {
+        self.current_match_bg
+            .unwrap_or_else(|| RgbColor::new(0xff, 0x87, 0x00))
+    }
 }
 
 impl From<Palette> for term::color::ColorPalette {
@@ -777,6 +2072,159 @@ impl From<Palette> for term::color::ColorPalette {
                 p.colors.0[idx + 8] = *col;
             }
         }
+        if cfg.dims.is_some() {
+            p.dims = cfg.dims;
+        }
         p
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn window_opacity_presets() {
+        let config: Config = toml::from_str("window_opacity_presets = [1.5, 0.8, -0.2]").unwrap();
+        assert_eq!(config.window_opacity_presets(), vec![1.0, 0.8, 0.0]);
+        assert!(config.window_is_translucent());
+
+        let config: Config = toml::from_str("").unwrap();
+        assert!(config.window_opacity_presets().is_empty());
+        assert!(!config.window_is_translucent());
+    }
+
+    #[test]
+    fn mouse_bindings() {
+        let config: Config = toml::from_str(
+            r#"
+[[mouse_bindings]]
+button = "Right"
+mods = ""
+action = "Paste"
+
+[[mouse_bindings]]
+button = "Left"
+mods = "CTRL|SHIFT"
+action = "OpenLinkAtMouseCursor"
+
+[[mouse_bindings]]
+button = "Middle"
+mods = "ALT"
+action = "PasteFrom"
+arg = "PrimarySelection"
+"#,
+        )
+        .unwrap();
+        let map = config.mouse_bindings().unwrap();
+        assert_eq!(map.len(), 3);
+        match map.get(&(term::MouseButton::Right, Modifiers::NONE)) {
+            Some(KeyAssignment::Paste) => {}
+            other => panic!("unexpected binding {:?}", other),
+        }
+        match map.get(&(term::MouseButton::Left, Modifiers::CTRL | Modifiers::SHIFT)) {
+            Some(KeyAssignment::OpenLinkAtMouseCursor) => {}
+            other => panic!("unexpected binding {:?}", other),
+        }
+        match map.get(&(term::MouseButton::Middle, Modifiers::ALT)) {
+            Some(KeyAssignment::PasteFrom(_)) => {}
+            other => panic!("unexpected binding {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reload_scopes() {
+        assert_eq!(reload_scope("font_size"), ReloadScope::Fonts);
+        assert_eq!(reload_scope("font_rules"), ReloadScope::Fonts);
+        assert_eq!(reload_scope("default_prog"), ReloadScope::NewTabsOrRestart);
+        assert_eq!(reload_scope("front_end"), ReloadScope::NewTabsOrRestart);
+        assert_eq!(
+            reload_scope("mux_compression_level"),
+            ReloadScope::NewTabsOrRestart
+        );
+        assert_eq!(reload_scope("colors"), ReloadScope::Immediate);
+        assert_eq!(reload_scope("scrollback_lines"), ReloadScope::Immediate);
+
+        let before: Config = toml::from_str("font_size = 10.0\nterm = \"xterm\"").unwrap();
+        let after: Config =
+            toml::from_str("font_size = 12.0\nterm = \"xterm\"\nscrollback_lines = 100").unwrap();
+        let changed = before.changed_settings(&after).unwrap();
+        assert_eq!(changed, vec!["font_size", "scrollback_lines"]);
+        let scopes: Vec<ReloadScope> = changed.iter().map(|name| reload_scope(name)).collect();
+        assert_eq!(scopes, vec![ReloadScope::Fonts, ReloadScope::Immediate]);
+    }
+
+    #[test]
+    fn color_schemes() {
+        let config: Config = toml::from_str(
+            r##"
+color_scheme = "light"
+
+[colors]
+foreground = "#cccccc"
+
+[colors.light]
+foreground = "#000000"
+background = "#ffffff"
+
+[colors.dark]
+background = "#000000"
+"##,
+        )
+        .unwrap();
+        assert_eq!(config.color_scheme_names(), vec!["dark", "light"]);
+        assert_eq!(
+            config.color_scheme("light").unwrap().foreground,
+            Some(RgbColor::new(0, 0, 0))
+        );
+        assert!(config.color_scheme("solarized").is_none());
+
+        // The schemes stand alone, rather than being layered over the
+        // top level of the section
+        let light = config.palette(Some("light"));
+        assert_eq!(light.foreground, RgbColor::new(0, 0, 0));
+        assert_eq!(light.background, RgbColor::new(0xff, 0xff, 0xff));
+        let dark = config.palette(Some("dark"));
+        assert_eq!(
+            dark.foreground,
+            term::color::ColorPalette::default().foreground
+        );
+
+        // Unknown schemes fall back to the top level of the section
+        let fallback = config.palette(Some("solarized"));
+        assert_eq!(fallback.foreground, RgbColor::new(0xcc, 0xcc, 0xcc));
+        assert_eq!(
+            config.palette(None).foreground,
+            RgbColor::new(0xcc, 0xcc, 0xcc)
+        );
+    }
+
+    #[test]
+    fn invalid_mouse_bindings() {
+        // PasteFrom needs to know where to paste from
+        let config: Config = toml::from_str(
+            r#"
+[[mouse_bindings]]
+button = "Middle"
+mods = ""
+action = "PasteFrom"
+"#,
+        )
+        .unwrap();
+        assert!(config.mouse_bindings().is_err());
+
+        for binding in &[
+            r#"button = "Fourth"
+mods = """#,
+            r#"button = "Left"
+mods = "HYPER""#,
+        ] {
+            let text = format!("[[mouse_bindings]]\n{}\naction = \"Paste\"\n", binding);
+            assert!(
+                toml::from_str::<Config>(&text).is_err(),
+                "{} was accepted",
+                binding
+            );
+        }
+    }
+}
@@ -50,7 +50,7 @@ impl FontSystem for CoreTextSystem {
         font_scale: f64,
     ) -> Result<Box<dyn NamedFont>, Error> {
         let mut fonts = Vec::new();
-        for font_attr in style.font_with_fallback() {
+        for font_attr in style.font_with_fallback(&config.font_fallback) {
             let col = match create_for_family(&font_attr.family) {
                 Some(col) => col,
                 None => continue,
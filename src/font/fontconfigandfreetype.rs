@@ -6,7 +6,8 @@ use crate::font::ftfont::FreeTypeFontImpl;
 use crate::font::{fcwrap, ftwrap};
 use crate::font::{shape_with_harfbuzz, FallbackIdx, Font, FontSystem, GlyphInfo, NamedFont};
 use failure::{bail, ensure, err_msg, Error};
-use log::{debug, error, warn};
+use log::{debug, warn};
+use std::collections::HashSet;
 
 pub type FontSystemImpl = FontConfigAndFreeType;
 
@@ -25,17 +26,14 @@ impl FontSystem for FontConfigAndFreeType {
         style: &TextStyle,
         font_scale: f64,
     ) -> Result<Box<dyn NamedFont>, Error> {
-        let fonts = style.font_with_fallback();
-        let mut pattern = if !fonts.is_empty() {
+        let fonts = style.font_with_fallback(&config.font_fallback);
+        if fonts.is_empty() {
+            bail!("no fonts specified!? {:?}", fonts);
+        }
+
+        let mut patterns = vec![];
+        for attr in &fonts {
             let mut pattern = FontPattern::new()?;
-            if fonts.len() > 1 {
-                error!(
-                    "FIXME: fontconfig loader currently only processes
-                      the first in your set of fonts for {:?}",
-                    style
-                );
-            }
-            let attr = &fonts[0];
             pattern.family(&attr.family)?;
             if *attr.bold.as_ref().unwrap_or(&false) {
                 pattern.add_integer("weight", 200)?;
@@ -43,14 +41,12 @@ impl FontSystem for FontConfigAndFreeType {
             if *attr.italic.as_ref().unwrap_or(&false) {
                 pattern.add_integer("slant", 100)?;
             }
-            pattern
-        } else {
-            bail!("no fonts specified!? {:?}", fonts);
-        };
-        pattern.add_double("size", config.font_size * font_scale)?;
-        pattern.add_double("dpi", config.dpi)?;
+            pattern.add_double("size", config.font_size * font_scale)?;
+            pattern.add_double("dpi", config.dpi)?;
+            patterns.push(pattern);
+        }
 
-        Ok(Box::new(NamedFontImpl::new(pattern)?))
+        Ok(Box::new(NamedFontImpl::new(patterns)?))
     }
 }
 
@@ -58,8 +54,16 @@ impl FontSystem for FontConfigAndFreeType {
 /// holds the set of fallback fonts that match their criteria
 pub struct NamedFontImpl {
     lib: ftwrap::Library,
-    pattern: fcwrap::Pattern,
-    font_list: fcwrap::FontSet,
+    /// A pattern for each of the configured fonts, in order of
+    /// preference, along with the fonts that fontconfig considers
+    /// to be the best matches for it
+    font_lists: Vec<(fcwrap::Pattern, fcwrap::FontSet)>,
+    /// The position in font_lists of the next candidate to load
+    next_candidate: (usize, usize),
+    /// The files that have been loaded so far.  The lists for the
+    /// different patterns tend to overlap, and there is no point
+    /// in trying the same file twice.
+    loaded_files: HashSet<String>,
     fonts: Vec<FreeTypeFontImpl>,
 }
 
@@ -81,8 +85,8 @@ impl NamedFont for NamedFontImpl {
 }
 
 impl NamedFontImpl {
-    /// Construct a new Font from the user supplied pattern
-    pub fn new(mut pattern: FontPattern) -> Result<Self, Error> {
+    /// Construct a new Font from the user supplied patterns
+    pub fn new(patterns: Vec<FontPattern>) -> Result<Self, Error> {
         let mut lib = ftwrap::Library::new()?;
 
         // Some systems don't support this mode, so if it fails, we don't
@@ -92,43 +96,64 @@ impl NamedFontImpl {
             Err(err) => warn!("Ignoring: FT_LcdFilter failed: {:?}", err),
         };
 
-        // Enable some filtering options and pull in the standard
-        // fallback font selection from the user configuration
-        pattern.monospace()?;
-        pattern.config_substitute(fcwrap::MatchKind::Pattern)?;
-        pattern.default_substitute();
+        let mut font_lists = vec![];
+        for (idx, mut pattern) in patterns.into_iter().enumerate() {
+            // Enable some filtering options and pull in the standard
+            // fallback font selection from the user configuration.
+            // Only the primary font needs to be monospace; fallbacks
+            // for emoji and symbols rarely are.
+            if idx == 0 {
+                pattern.monospace()?;
+            }
+            pattern.config_substitute(fcwrap::MatchKind::Pattern)?;
+            pattern.default_substitute();
 
-        // and obtain the selection with the best preference
-        // at index 0.
-        let font_list = pattern.sort(true)?;
+            // and obtain the selection with the best preference
+            // at index 0.
+            let font_list = pattern.sort(true)?;
+            font_lists.push((pattern, font_list));
+        }
 
         Ok(Self {
             lib,
-            font_list,
-            pattern,
+            font_lists,
+            next_candidate: (0, 0),
+            loaded_files: HashSet::new(),
             fonts: Vec::new(),
         })
     }
 
     fn load_next_fallback(&mut self) -> Result<(), Error> {
-        let idx = self.fonts.len();
-        let pat = self
-            .font_list
-            .iter()
-            .nth(idx)
-            .ok_or_else(|| err_msg("no more fallbacks"))?;
-        let pat = self.pattern.render_prepare(&pat)?;
-        let file = pat.get_file()?;
-
-        debug!("load_next_fallback: file={}", file);
-        debug!("{}", pat.format("%{=unparse}")?);
-
-        let size = pat.get_double("size")?;
-        let dpi = pat.get_double("dpi")? as u32;
-        let face = self.lib.new_face(file, 0)?;
-        self.fonts
-            .push(FreeTypeFontImpl::with_face_size_and_dpi(face, size, dpi)?);
-        Ok(())
+        loop {
+            let (list_idx, font_idx) = self.next_candidate;
+            let (pattern, font_list) = self
+                .font_lists
+                .get(list_idx)
+                .ok_or_else(|| err_msg("no more fallbacks"))?;
+            let pat = match font_list.iter().nth(font_idx) {
+                Some(pat) => pattern.render_prepare(&pat)?,
+                None => {
+                    self.next_candidate = (list_idx + 1, 0);
+                    continue;
+                }
+            };
+            self.next_candidate = (list_idx, font_idx + 1);
+
+            let file = pat.get_file()?;
+            if !self.loaded_files.insert(file.clone()) {
+                continue;
+            }
+
+            debug!("load_next_fallback: file={}", file);
+            debug!("{}", pat.format("%{=unparse}")?);
+
+            let size = pat.get_double("size")?;
+            let dpi = pat.get_double("dpi")? as u32;
+            let face = self.lib.new_face(file, 0)?;
+            self.fonts
+                .push(FreeTypeFontImpl::with_face_size_and_dpi(face, size, dpi)?);
+            return Ok(());
+        }
     }
 
     fn get_font(&mut self, idx: usize) -> Result<&mut FreeTypeFontImpl, Error> {
@@ -1,16 +1,22 @@
 use crate::config::{Config, TextStyle};
-use failure::{format_err, Error};
+use failure::{bail, Error};
 use font_loader::system_fonts;
+use log::debug;
 
-pub fn load_system_fonts(
-    _config: &Config,
-    style: &TextStyle,
-) -> Result<Vec<(Vec<u8>, i32)>, Error> {
+pub fn load_system_fonts(config: &Config, style: &TextStyle) -> Result<Vec<(Vec<u8>, i32)>, Error> {
     let mut fonts = Vec::new();
-    for font_attr in style.font_with_fallback() {
-        let mut font_props = system_fonts::FontPropertyBuilder::new()
-            .family(&font_attr.family)
-            .monospace();
+    for (idx, font_attr) in style
+        .font_with_fallback(&config.font_fallback)
+        .into_iter()
+        .enumerate()
+    {
+        let mut font_props = system_fonts::FontPropertyBuilder::new().family(&font_attr.family);
+        // Fallback fonts, such as those for emoji, are rarely monospace
+        font_props = if idx == 0 {
+            font_props.monospace()
+        } else {
+            font_props
+        };
         font_props = if *font_attr.bold.as_ref().unwrap_or(&false) {
             font_props.bold()
         } else {
@@ -23,10 +29,13 @@ pub fn load_system_fonts(
         };
         let font_props = font_props.build();
 
-        fonts.push(
-            system_fonts::get(&font_props)
-                .ok_or_else(|| format_err!("no font matching {:?}", font_attr))?,
-        );
+        match system_fonts::get(&font_props) {
+            Some(font) => fonts.push(font),
+            // Only the primary font is required; the fallbacks
+            // may not be installed on this system
+            None if idx == 0 => bail!("no font matching {:?}", font_attr),
+            None => debug!("no fallback font matching {:?}", font_attr),
+        }
     }
     Ok(fonts)
 }
@@ -43,7 +43,7 @@ pub struct FontConfiguration {
     font_scale: RefCell<f64>,
 }
 
-#[derive(Debug, Deserialize, Clone, Copy)]
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
 pub enum FontSystemSelection {
     FontConfigAndFreeType,
     FontLoaderAndFreeType,
@@ -140,6 +140,10 @@ impl FontConfiguration {
         }
     }
 
+    pub fn config(&self) -> &Arc<Config> {
+        &self.config
+    }
+
     /// Given a text style, load (with caching) the font that best
     /// matches according to the fontconfig pattern.
     pub fn cached_font(&self, style: &TextStyle) -> Result<Rc<RefCell<Box<dyn NamedFont>>>, Error> {
@@ -188,13 +192,52 @@ impl FontConfiguration {
         }
 
         let font = self.default_font()?;
-        let metrics = font.borrow_mut().get_fallback(0)?.metrics();
+        let font_metrics = font.borrow_mut().get_fallback(0)?.metrics();
+
+        // Apply the line_height and cell_width overrides.  The extra
+        // height is split above and below the text, so the baseline
+        // moves down by half of it.
+        let cell_height = font_metrics.cell_height * self.config.line_height;
+        let metrics = FontMetrics {
+            cell_width: font_metrics.cell_width * self.config.cell_width,
+            cell_height,
+            descender: font_metrics.descender - (cell_height - font_metrics.cell_height) / 2.0,
+        };
 
         *self.metrics.borrow_mut() = Some(metrics);
 
         Ok(metrics)
     }
 
+    /// Shape `text` using the font that matches `style`.
+    /// When `font_shaping` is disabled in the config, each character
+    /// is shaped along with any zero-width characters that follow it,
+    /// so that adjacent characters don't combine into ligatures.
+    pub fn shape(&self, style: &TextStyle, text: &str) -> Result<Vec<GlyphInfo>, Error> {
+        let font = self.cached_font(style)?;
+        let mut font = font.borrow_mut();
+        if self.config.font_shaping.unwrap_or(true) {
+            return font.shape(text);
+        }
+
+        use unicode_width::UnicodeWidthChar;
+        let mut starts: Vec<usize> = text
+            .char_indices()
+            .filter(|(idx, c)| *idx == 0 || c.width().unwrap_or(0) > 0)
+            .map(|(idx, _)| idx)
+            .collect();
+        starts.push(text.len());
+
+        let mut glyphs = vec![];
+        for range in starts.windows(2) {
+            for mut info in font.shape(&text[range[0]..range[1]])? {
+                info.cluster += range[0] as u32;
+                glyphs.push(info);
+            }
+        }
+        Ok(glyphs)
+    }
+
     /// Apply the defined font_rules from the user configuration to
     /// produce the text style that best matches the supplied input
     /// cell attributes.
@@ -245,6 +288,9 @@ pub fn shape_with_harfbuzz(
         harfbuzz::feature_from_string("liga")?,
         // contextual ligatures
         harfbuzz::feature_from_string("clig")?,
+        // contextual alternates; this is how fonts such as Fira Code
+        // implement their programming ligatures
+        harfbuzz::feature_from_string("calt")?,
     ];
 
     let mut buf = harfbuzz::Buffer::new()?;
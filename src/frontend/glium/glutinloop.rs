@@ -1,25 +1,27 @@
 use crate::config::Config;
 use crate::font::FontConfiguration;
 use crate::frontend::glium::window::GliumTerminalWindow;
+use crate::frontend::guicommon::attention;
+use crate::frontend::guicommon::toast;
 use crate::frontend::guicommon::window::TerminalWindow;
 use crate::frontend::{front_end, FrontEnd};
-use crate::mux::tab::Tab;
+use crate::mux::tab::{Tab, TabId};
 use crate::mux::window::WindowId as MuxWindowId;
-use crate::mux::{Mux, SessionTerminated};
+use crate::mux::{Mux, MuxNotification, SessionTerminated};
 use failure::{bail, Error, Fallible};
 use glium;
 use glium::glutin::EventsLoopProxy;
 use glium::glutin::WindowId;
 use log::{debug, error};
 use promise::{Executor, Future, SpawnFunc};
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::collections::VecDeque;
 use std::rc::Rc;
-use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender, TryRecvError};
 use std::sync::Arc;
 use std::thread;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 
 /// The GuiSender is used as a handle that allows sending SpawnFunc
 /// instances to be executed on the gui thread.
@@ -89,6 +91,24 @@ struct Windows {
     by_id: HashMap<WindowId, GliumTerminalWindow>,
 }
 
+impl Windows {
+    /// Files the windows whose glutin window was replaced, which
+    /// happens when their OpenGL context is lost, under their new ids
+    fn update_ids(&mut self) {
+        let moved: Vec<WindowId> = self
+            .by_id
+            .iter()
+            .filter(|(window_id, window)| **window_id != window.window_id())
+            .map(|(window_id, _)| *window_id)
+            .collect();
+        for window_id in moved {
+            if let Some(window) = self.by_id.remove(&window_id) {
+                self.by_id.insert(window.window_id(), window);
+            }
+        }
+    }
+}
+
 /// The `GuiEventLoop` represents the combined gui event processor,
 /// and a core for spawning tasks from futures.  It acts as the manager
 /// for various events and is responsible for driving things forward.
@@ -98,10 +118,13 @@ pub struct GuiEventLoop {
     gui_tx: Arc<GuiSender>,
     gui_rx: Receiver<SpawnFunc>,
     gui_thread_sends: RefCell<VecDeque<SpawnFunc>>,
-    tick_rx: Receiver<()>,
+    /// Asks the timer thread to wake the loop at a particular time
+    wakeup_tx: Sender<Instant>,
+    /// The earliest time that the timer thread was asked to wake
+    /// the loop, so that it isn't asked again for a later time
+    scheduled_wakeup: Cell<Option<Instant>>,
 }
 
-const TICK_INTERVAL: Duration = Duration::from_millis(50);
 const MAX_POLL_LOOP_DURATION: Duration = Duration::from_millis(500);
 
 pub struct GlutinFrontEnd {
@@ -111,6 +134,78 @@ pub struct GlutinFrontEnd {
 impl GlutinFrontEnd {
     pub fn try_new(mux: &Rc<Mux>) -> Result<Rc<dyn FrontEnd>, Error> {
         let event_loop = Rc::new(GuiEventLoop::new(mux)?);
+        let executor = event_loop.gui_executor();
+        mux.subscribe(move |notification| {
+            // Apply the change from the gui loop rather than while
+            // the mux is in the middle of notifying its subscribers
+            match notification {
+                MuxNotification::ConfigReloaded => {
+                    Future::with_executor(executor.clone_executor(), || {
+                        let front_end = front_end().expect("to be called on gui thread");
+                        let front_end = front_end
+                            .downcast_ref::<GlutinFrontEnd>()
+                            .expect("front_end to be GlutinFrontEnd");
+                        front_end.event_loop.config_was_reloaded();
+                        Ok(())
+                    });
+                }
+                MuxNotification::Resumed => {
+                    Future::with_executor(executor.clone_executor(), || {
+                        let front_end = front_end().expect("to be called on gui thread");
+                        let front_end = front_end
+                            .downcast_ref::<GlutinFrontEnd>()
+                            .expect("front_end to be GlutinFrontEnd");
+                        front_end.event_loop.system_resumed();
+                        Ok(())
+                    });
+                }
+                MuxNotification::SetClipboard(tab_id, clip) => {
+                    Future::with_executor(executor.clone_executor(), move || {
+                        let front_end = front_end().expect("to be called on gui thread");
+                        let front_end = front_end
+                            .downcast_ref::<GlutinFrontEnd>()
+                            .expect("front_end to be GlutinFrontEnd");
+                        front_end.event_loop.set_clipboard(tab_id, clip)
+                    });
+                }
+                MuxNotification::Bell(tab_id) => {
+                    Future::with_executor(executor.clone_executor(), move || {
+                        attention::bell(tab_id);
+                        Ok(())
+                    });
+                }
+                MuxNotification::CurrentDirChanged(tab_id) => {
+                    Future::with_executor(executor.clone_executor(), move || {
+                        let front_end = front_end().expect("to be called on gui thread");
+                        let front_end = front_end
+                            .downcast_ref::<GlutinFrontEnd>()
+                            .expect("front_end to be GlutinFrontEnd");
+                        front_end.event_loop.current_dir_changed(tab_id);
+                        Ok(())
+                    });
+                }
+                MuxNotification::TabOutput(tab_id) => {
+                    Future::with_executor(executor.clone_executor(), move || {
+                        attention::output(tab_id);
+                        Ok(())
+                    });
+                }
+                MuxNotification::TabRemoved(tab_id) => {
+                    Future::with_executor(executor.clone_executor(), move || {
+                        attention::forget(tab_id);
+                        Ok(())
+                    });
+                }
+                MuxNotification::Notice(text) => {
+                    Future::with_executor(executor.clone_executor(), move || {
+                        toast::show(None, &text);
+                        Ok(())
+                    });
+                }
+                _ => {}
+            }
+            true
+        });
         Ok(Rc::new(Self { event_loop }))
     }
 }
@@ -120,6 +215,14 @@ impl FrontEnd for GlutinFrontEnd {
         self.event_loop.gui_executor()
     }
 
+    fn get_clipboard(&self) -> Fallible<String> {
+        let mut windows = self.event_loop.windows.borrow_mut();
+        match windows.by_id.values_mut().next() {
+            Some(window) => window.get_clipboard(),
+            None => bail!("no window from which to read the clipboard"),
+        }
+    }
+
     fn run_forever(&self) -> Result<(), Error> {
         // This convoluted run() signature is present because of this issue:
         // https://github.com/tomaka/winit/issues/413
@@ -137,10 +240,20 @@ impl FrontEnd for GlutinFrontEnd {
 
             myself.run_event_loop()?;
             myself.process_gui_exec()?;
-            myself.process_tick()?;
+            myself.test_for_child_exit();
+            myself.do_paint();
         }
     }
 
+    fn window_at(&self, x: i64, y: i64) -> Option<MuxWindowId> {
+        let windows = self.event_loop.windows.borrow();
+        windows
+            .by_id
+            .values()
+            .find(|window| window.contains_screen_point(x, y))
+            .map(|window| window.get_mux_window_id())
+    }
+
     fn spawn_new_window(
         &self,
         config: &Arc<Config>,
@@ -158,19 +271,47 @@ impl GuiEventLoop {
     pub fn new(_mux: &Rc<Mux>) -> Result<Self, Error> {
         let event_loop = glium::glutin::EventsLoop::new();
 
+        #[cfg(windows)]
+        {
+            if let Err(err) = crate::frontend::glium::jumplist::register_tasks() {
+                error!("Failed to set up the jump list: {}", err);
+            }
+        }
+
         let (gui_tx, gui_rx) = GuiSender::new(event_loop.create_proxy());
 
-        // The glutin/glium plumbing has no native tick/timer stuff, so
-        // we implement one using a thread.  Nice.
+        // The glutin/glium plumbing has no native timer stuff, so we
+        // implement one using a thread that wakes the loop at the
+        // earliest of the times that it is sent
         let proxy = event_loop.create_proxy();
-        let (tick_tx, tick_rx) = mpsc::channel();
-        thread::spawn(move || loop {
-            std::thread::sleep(TICK_INTERVAL);
-            if tick_tx.send(()).is_err() {
-                return;
-            }
-            if proxy.wakeup().is_err() {
-                return;
+        let (wakeup_tx, wakeup_rx) = mpsc::channel::<Instant>();
+        thread::spawn(move || {
+            let mut deadline: Option<Instant> = None;
+            loop {
+                let received = match deadline {
+                    Some(deadline) => {
+                        let now = Instant::now();
+                        let timeout = if deadline > now {
+                            deadline - now
+                        } else {
+                            Duration::from_millis(0)
+                        };
+                        wakeup_rx.recv_timeout(timeout)
+                    }
+                    None => wakeup_rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+                match received {
+                    Ok(when) => {
+                        deadline = Some(deadline.map_or(when, |deadline| deadline.min(when)));
+                    }
+                    Err(RecvTimeoutError::Timeout) => {
+                        deadline = None;
+                        if proxy.wakeup().is_err() {
+                            return;
+                        }
+                    }
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
             }
         });
 
@@ -178,7 +319,8 @@ impl GuiEventLoop {
             gui_rx,
             gui_tx: Arc::new(gui_tx),
             gui_thread_sends: RefCell::new(VecDeque::new()),
-            tick_rx,
+            wakeup_tx,
+            scheduled_wakeup: Cell::new(None),
             event_loop: RefCell::new(event_loop),
             windows: Rc::new(RefCell::new(Default::default())),
         })
@@ -235,16 +377,19 @@ impl GuiEventLoop {
         use glium::glutin::Event;
         let result = match *event {
             Event::WindowEvent { window_id, .. } => {
-                let dead = match self.windows.borrow_mut().by_id.get_mut(&window_id) {
+                let mut windows = self.windows.borrow_mut();
+                let dead = match windows.by_id.get_mut(&window_id) {
                     Some(window) => match window.dispatch_event(event) {
                         Ok(_) => None,
                         Err(err) => match err.downcast_ref::<SessionTerminated>() {
-                            Some(_) => Some(window_id),
+                            Some(_) => Some(window.window_id()),
                             _ => return Err(err),
                         },
                     },
                     None => None,
                 };
+                windows.update_ids();
+                drop(windows);
 
                 if let Some(window_id) = dead {
                     self.schedule_window_close(window_id)?;
@@ -280,12 +425,104 @@ impl GuiEventLoop {
         Ok(())
     }
 
-    /// Run through all of the windows and cause them to paint if they need it.
-    /// This happens ~50ms or so.
-    fn do_paint(&self) {
+    /// Apply the reloaded configuration to all of the windows
+    fn config_was_reloaded(&self) {
         for window in &mut self.windows.borrow_mut().by_id.values_mut() {
+            if let Err(err) = window.config_was_reloaded() {
+                error!("Failed to apply the reloaded configuration: {}", err);
+            }
+        }
+    }
+
+    /// Paints every window afresh and looks for programs that exited
+    /// while the system was asleep, rather than waiting for something
+    /// else to prompt it after the system resumes
+    fn system_resumed(&self) {
+        for window in self.windows.borrow_mut().by_id.values_mut() {
+            window.renderer().invalidate();
+        }
+        self.test_for_child_exit();
+        self.do_paint();
+    }
+
+    /// Updates the titles of the windows that hold the tab, since they
+    /// may show its working directory
+    fn current_dir_changed(&self, tab_id: TabId) {
+        let mux = Mux::get().unwrap();
+        for window in self.windows.borrow_mut().by_id.values_mut() {
+            let holds_tab = mux
+                .get_window(window.get_mux_window_id())
+                .map(|w| w.idx_by_id(tab_id).is_some())
+                .unwrap_or(false);
+            if holds_tab {
+                window.update_title();
+            }
+        }
+    }
+
+    /// Sets the clipboard via the window that holds the tab, falling
+    /// back to any window if the tab isn't in one of ours.
+    fn set_clipboard(&self, tab_id: TabId, clip: Option<String>) -> Fallible<()> {
+        let mux = Mux::get().unwrap();
+        let mut windows = self.windows.borrow_mut();
+        let window_id = windows
+            .by_id
+            .iter()
+            .find(|(_, window)| {
+                mux.get_window(window.get_mux_window_id())
+                    .map(|w| w.idx_by_id(tab_id).is_some())
+                    .unwrap_or(false)
+            })
+            .or_else(|| windows.by_id.iter().next())
+            .map(|(window_id, _)| *window_id);
+        match window_id.and_then(|window_id| windows.by_id.get_mut(&window_id)) {
+            Some(window) => window.set_clipboard(clip),
+            None => bail!("no window in which to set the clipboard"),
+        }
+    }
+
+    /// Run through all of the windows and cause them to paint if they
+    /// need it.  This happens each time the loop wakes up, which is
+    /// when there is input, output from a tab or some other work for
+    /// the gui thread, so a tab is painted as soon as it changes.
+    /// The windows use vsync, so painting a changed window waits for
+    /// the display refresh, and output that arrives in the meantime
+    /// is coalesced into the next frame.
+    /// If nothing else will wake the loop when a window next needs
+    /// to be painted, such as to blink the cursor or remove a toast,
+    /// a wakeup is scheduled for that time; otherwise the loop sleeps
+    /// until there is more to do.
+    fn do_paint(&self) {
+        let mut next_repaint = match (toast::next_expiry(), attention::next_expiry()) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        let mut windows = self.windows.borrow_mut();
+        for window in windows.by_id.values_mut() {
             window.paint_if_needed().unwrap();
+            if let Some(when) = window.renderer().next_repaint() {
+                next_repaint = Some(next_repaint.map_or(when, |next| next.min(when)));
+            }
+        }
+        windows.update_ids();
+        drop(windows);
+        if let Some(when) = next_repaint {
+            self.schedule_wakeup(when);
+        }
+    }
+
+    /// Arranges for the loop to wake up at `when`, unless it is
+    /// already due to wake up before then
+    fn schedule_wakeup(&self, when: Instant) {
+        let now = Instant::now();
+        if let Some(scheduled) = self.scheduled_wakeup.get() {
+            if scheduled > now && scheduled <= when {
+                return;
+            }
         }
+        self.scheduled_wakeup.set(Some(when));
+        // This only fails if the timer thread has died
+        self.wakeup_tx.send(when).ok();
     }
 
     fn pop_gui_thread_send(&self) -> Option<SpawnFunc> {
@@ -301,6 +538,9 @@ impl GuiEventLoop {
         loop {
             match start.elapsed() {
                 Ok(elapsed) if elapsed > MAX_POLL_LOOP_DURATION => {
+                    // Come back for the rest once the windows have
+                    // had a chance to paint and process input
+                    self.gui_tx.proxy.wakeup()?;
                     return Ok(());
                 }
                 Err(_) => {
@@ -316,19 +556,6 @@ impl GuiEventLoop {
         }
     }
 
-    fn process_tick(&self) -> Result<(), Error> {
-        loop {
-            match self.tick_rx.try_recv() {
-                Ok(_) => {
-                    self.test_for_child_exit();
-                    self.do_paint();
-                }
-                Err(TryRecvError::Empty) => return Ok(()),
-                Err(err) => bail!("tick_rx disconnected {:?}", err),
-            }
-        }
-    }
-
     fn test_for_child_exit(&self) {
         let window_ids: Vec<WindowId> = self
             .windows
@@ -0,0 +1,133 @@
+//! Populates the jump list of the taskbar button on Windows, which
+//! is the menu shown when the button is right clicked, with a task
+//! that opens a new window.
+use failure::{bail, Fallible};
+use std::ffi::OsStr;
+use std::mem;
+use std::os::windows::ffi::OsStrExt;
+use std::ptr;
+use winapi::ctypes::c_void;
+use winapi::shared::guiddef::GUID;
+use winapi::shared::winerror::{FAILED, HRESULT};
+use winapi::shared::wtypes::VT_LPWSTR;
+use winapi::um::combaseapi::{CoCreateInstance, CoInitializeEx, CLSCTX_INPROC_SERVER};
+use winapi::um::objbase::COINIT_APARTMENTTHREADED;
+use winapi::um::objectarray::{IObjectArray, IObjectCollection};
+use winapi::um::propidl::PROPVARIANT;
+use winapi::um::propkey::PKEY_Title;
+use winapi::um::propsys::IPropertyStore;
+use winapi::um::shobjidl::{
+    CLSID_DestinationList, CLSID_EnumerableObjectCollection, ICustomDestinationList,
+};
+use winapi::um::shobjidl_core::{CLSID_ShellLink, IShellLinkW};
+use winapi::um::unknwnbase::IUnknown;
+use winapi::Interface;
+
+/// Owns a reference to a COM object
+struct ComPtr<T: Interface>(*mut T);
+
+impl<T: Interface> Drop for ComPtr<T> {
+    fn drop(&mut self) {
+        unsafe {
+            (*(self.0 as *mut IUnknown)).Release();
+        }
+    }
+}
+
+impl<T: Interface> ComPtr<T> {
+    fn create(clsid: &GUID) -> Fallible<Self> {
+        let mut object = ptr::null_mut();
+        check("CoCreateInstance", unsafe {
+            CoCreateInstance(
+                clsid,
+                ptr::null_mut(),
+                CLSCTX_INPROC_SERVER,
+                &T::uuidof(),
+                &mut object,
+            )
+        })?;
+        Ok(ComPtr(object as *mut T))
+    }
+
+    fn cast<U: Interface>(&self) -> Fallible<ComPtr<U>> {
+        let mut object = ptr::null_mut();
+        check("QueryInterface", unsafe {
+            (*(self.0 as *mut IUnknown)).QueryInterface(&U::uuidof(), &mut object)
+        })?;
+        Ok(ComPtr(object as *mut U))
+    }
+}
+
+fn check(what: &str, result: HRESULT) -> Fallible<()> {
+    if FAILED(result) {
+        bail!("{} failed: HRESULT {:#x}", what, result);
+    }
+    Ok(())
+}
+
+fn wide<S: AsRef<OsStr>>(s: S) -> Vec<u16> {
+    s.as_ref().encode_wide().chain(Some(0)).collect()
+}
+
+/// Makes a shell link that runs wezterm with `args`, which is shown
+/// in the jump list as `title`
+fn shell_link(args: &str, title: &str) -> Fallible<ComPtr<IShellLinkW>> {
+    let exe = std::env::current_exe()?;
+    let link = ComPtr::<IShellLinkW>::create(&CLSID_ShellLink)?;
+    unsafe {
+        check("SetPath", (*link.0).SetPath(wide(&exe).as_ptr()))?;
+        check("SetArguments", (*link.0).SetArguments(wide(args).as_ptr()))?;
+        check(
+            "SetIconLocation",
+            (*link.0).SetIconLocation(wide(&exe).as_ptr(), 0),
+        )?;
+
+        // The title of a task is a property of the link rather than
+        // its description
+        let store = link.cast::<IPropertyStore>()?;
+        let title = wide(title);
+        let mut value: PROPVARIANT = mem::zeroed();
+        {
+            let value = value.u.s_mut();
+            value.vt = VT_LPWSTR as u16;
+            *value.data.pwszVal_mut() = title.as_ptr() as *mut u16;
+        }
+        check("SetValue", (*store.0).SetValue(&PKEY_Title, &value))?;
+        check("Commit", (*store.0).Commit())?;
+    }
+    Ok(link)
+}
+
+/// Replaces the tasks in the jump list of the taskbar button
+pub fn register_tasks() -> Fallible<()> {
+    unsafe {
+        // This fails harmlessly if COM was already initialized on
+        // this thread, such as by the windowing code
+        CoInitializeEx(ptr::null_mut(), COINIT_APARTMENTTHREADED);
+
+        let list = ComPtr::<ICustomDestinationList>::create(&CLSID_DestinationList)?;
+        let mut max_slots = 0;
+        let mut removed: *mut c_void = ptr::null_mut();
+        check(
+            "BeginList",
+            (*list.0).BeginList(&mut max_slots, &IObjectArray::uuidof(), &mut removed),
+        )?;
+        // We don't add any of the items that the user can remove, so
+        // just release the list of the removed ones
+        if !removed.is_null() {
+            drop(ComPtr(removed as *mut IObjectArray));
+        }
+
+        let tasks = ComPtr::<IObjectCollection>::create(&CLSID_EnumerableObjectCollection)?;
+        let new_window = shell_link("start", "New Window")?;
+        check(
+            "AddObject",
+            (*tasks.0).AddObject(new_window.0 as *mut IUnknown),
+        )?;
+
+        let tasks = tasks.cast::<IObjectArray>()?;
+        check("AddUserTasks", (*list.0).AddUserTasks(tasks.0))?;
+        check("CommitList", (*list.0).CommitList())?;
+    }
+    Ok(())
+}
@@ -1,2 +1,4 @@
 pub mod glutinloop;
+#[cfg(windows)]
+mod jumplist;
 pub mod window;
@@ -4,6 +4,16 @@ use crate::config::Config;
 use crate::font::FontConfiguration;
 use crate::frontend::glium::glutinloop::GuiEventLoop;
 use crate::frontend::guicommon::host::{HostHelper, HostImpl, TabHost};
+use crate::frontend::guicommon::input::{self, ModifierState};
+use crate::frontend::guicommon::launcher::Launcher;
+use crate::frontend::guicommon::lineeditor::LineEditor;
+use crate::frontend::guicommon::mouse::MouseTranslator;
+use crate::frontend::guicommon::pastehistory::PastePicker;
+use crate::frontend::guicommon::quickselect::QuickSelect;
+use crate::frontend::guicommon::scrollbar::{scroll_bar_cols, ScrollbarState};
+use crate::frontend::guicommon::search::Search;
+use crate::frontend::guicommon::tabbar::{tab_bar_rows, TabBarState};
+use crate::frontend::guicommon::window::MouseCursor as CursorShape;
 use crate::frontend::guicommon::window::{Dimensions, TerminalWindow};
 use crate::mux::tab::Tab;
 use crate::mux::window::WindowId;
@@ -13,12 +23,11 @@ use failure::{format_err, Error};
 use glium;
 use glium::glutin::dpi::{LogicalPosition, LogicalSize, PhysicalPosition, PhysicalSize};
 use glium::glutin::{self, ElementState, MouseCursor};
-use log::{debug, error};
+use log::debug;
 use std::rc::Rc;
 use std::sync::Arc;
 use term;
 use term::KeyCode;
-use term::KeyModifiers;
 use term::{MouseButton, MouseEventKind};
 #[cfg(target_os = "macos")]
 use winit::os::macos::WindowExt;
@@ -30,6 +39,7 @@ struct Host {
     /// if is_some, holds position to be restored after exiting
     /// fullscreen mode.
     is_fullscreen: Option<LogicalPosition>,
+    always_on_top: bool,
 }
 
 impl HostHelper for Host {
@@ -67,6 +77,13 @@ impl HostHelper for Host {
             window.set_fullscreen(Some(window.get_current_monitor()));
         }
     }
+
+    fn toggle_always_on_top(&mut self) {
+        self.always_on_top = !self.always_on_top;
+        self.display
+            .gl_window()
+            .set_always_on_top(self.always_on_top);
+    }
 }
 
 pub struct GliumTerminalWindow {
@@ -74,15 +91,20 @@ pub struct GliumTerminalWindow {
     config: Arc<Config>,
     fonts: Rc<FontConfiguration>,
     renderer: Renderer,
+    tab_bar: TabBarState,
+    scrollbar: ScrollbarState,
     width: u16,
     height: u16,
     cell_height: usize,
     cell_width: usize,
-    last_mouse_coords: PhysicalPosition,
-    last_modifiers: KeyModifiers,
+    mouse: MouseTranslator,
+    last_modifiers: ModifierState,
     allow_received_character: bool,
     mux_window_id: WindowId,
     have_pending_resize_check: bool,
+    /// The title last given to the window, which is given again to
+    /// the window that `recreate_context` replaces it with
+    title: String,
 }
 
 impl TerminalWindow for GliumTerminalWindow {
@@ -96,9 +118,16 @@ impl TerminalWindow for GliumTerminalWindow {
     fn fonts(&self) -> &Rc<FontConfiguration> {
         &self.fonts
     }
+    fn set_config(&mut self, config: &Arc<Config>, fonts: &Rc<FontConfiguration>) {
+        self.config = Arc::clone(config);
+        self.fonts = Rc::clone(fonts);
+        self.renderer.fonts = Rc::clone(fonts);
+        self.host.config_was_reloaded();
+    }
 
     fn set_window_title(&mut self, title: &str) -> Result<(), Error> {
         self.host.display.gl_window().set_title(title);
+        self.title = title.to_owned();
         Ok(())
     }
 
@@ -117,9 +146,77 @@ impl TerminalWindow for GliumTerminalWindow {
     fn renderer(&mut self) -> &mut Renderer {
         &mut self.renderer
     }
+    fn tab_bar(&mut self) -> &mut TabBarState {
+        &mut self.tab_bar
+    }
+    fn scrollbar(&mut self) -> &mut ScrollbarState {
+        &mut self.scrollbar
+    }
+    fn paste_picker(&self) -> Option<&PastePicker> {
+        self.host.paste_picker()
+    }
+    fn rename_prompt(&self) -> Option<&LineEditor> {
+        self.host.rename_prompt()
+    }
+    fn launcher(&self) -> Option<&Launcher> {
+        self.host.launcher()
+    }
+    fn quick_select(&self) -> Option<&QuickSelect> {
+        self.host.quick_select()
+    }
+    fn search(&self) -> Option<&Search> {
+        self.host.search()
+    }
+    fn refresh_search(&mut self, tab: &dyn Tab) -> bool {
+        self.host.refresh_search(tab)
+    }
+    fn refresh_quick_select(&mut self, tab: &dyn Tab) -> bool {
+        self.host.refresh_quick_select(tab)
+    }
+    fn show_launcher(&mut self) {
+        self.host.show_launcher()
+    }
+    fn get_clipboard(&mut self) -> Result<String, Error> {
+        self.host.get_clipboard()
+    }
+    fn set_clipboard(&mut self, clip: Option<String>) -> Result<(), Error> {
+        self.host.set_clipboard(clip)
+    }
     fn recreate_texture_atlas(&mut self, size: u32) -> Result<(), Error> {
         self.renderer.recreate_atlas(&self.host.display, size)
     }
+    fn is_context_lost(&self) -> bool {
+        self.host.display.is_context_lost()
+    }
+    fn recreate_context(&mut self) -> Result<(), Error> {
+        // glutin only creates a context along with a window, so the
+        // window is replaced too, in the same place and state
+        let dpi = self.host.display.gl_window().get_hidpi_factor();
+        let size = PhysicalSize::new(f64::from(self.width), f64::from(self.height));
+        let size = size.to_logical(dpi);
+        let display = Self::create_display(
+            &self.host.event_loop,
+            &self.config,
+            size,
+            self.cell_width,
+            self.cell_height,
+        )?;
+        {
+            let window = display.gl_window();
+            if let Some(position) = self.host.window_position {
+                window.set_position(position);
+            }
+            window.set_always_on_top(self.host.always_on_top);
+            window.set_cursor(MouseCursor::Text);
+            window.set_title(&self.title);
+        }
+        self.host.display = display;
+        if let Some(position) = self.host.is_fullscreen.take() {
+            self.host.toggle_full_screen();
+            self.host.is_fullscreen = Some(position);
+        }
+        self.renderer.recreate(&self.host.display)
+    }
 
     fn get_dimensions(&self) -> Dimensions {
         Dimensions {
@@ -165,6 +262,60 @@ impl TerminalWindow for GliumTerminalWindow {
             Ok(false)
         }
     }
+    #[cfg(windows)]
+    fn set_ime_position(&mut self, x: usize, y: usize) {
+        use winapi::shared::windef::{HWND, POINT};
+        use winapi::um::imm::{
+            ImmGetContext, ImmReleaseContext, ImmSetCompositionWindow, CFS_POINT, COMPOSITIONFORM,
+        };
+        use winit::os::windows::WindowExt;
+
+        // glutin doesn't position the input method window on Windows,
+        // so tell the input context where the composition starts; the
+        // candidate window is shown just below it
+        let hwnd = self.host.display.gl_window().get_hwnd() as HWND;
+        unsafe {
+            let context = ImmGetContext(hwnd);
+            if context.is_null() {
+                return;
+            }
+            let mut form = COMPOSITIONFORM {
+                dwStyle: CFS_POINT,
+                ptCurrentPos: POINT {
+                    x: x as i32,
+                    y: y as i32,
+                },
+                rcArea: std::mem::zeroed(),
+            };
+            ImmSetCompositionWindow(context, &mut form);
+            ImmReleaseContext(hwnd, context);
+        }
+    }
+    #[cfg(not(windows))]
+    fn set_ime_position(&mut self, x: usize, y: usize) {
+        // The spot is where the text being composed is drawn, which is
+        // on the baseline of the cursor cell
+        let window = self.host.display.gl_window();
+        let dpi = window.get_hidpi_factor();
+        let y = y + self.cell_height;
+        window.set_ime_spot(PhysicalPosition::new(x as f64, y as f64).to_logical(dpi));
+    }
+    fn pointer_position(&self) -> Option<(i64, i64)> {
+        self.mouse.last_pixel()
+    }
+    fn screen_position(&self) -> Option<(i64, i64)> {
+        let window = self.host.display.gl_window();
+        let dpi = window.get_hidpi_factor();
+        let (x, y): (i32, i32) = window.get_inner_position()?.to_physical(dpi).into();
+        Some((i64::from(x), i64::from(y)))
+    }
+    fn set_mouse_cursor(&mut self, cursor: CursorShape) {
+        self.host.display.gl_window().set_cursor(match cursor {
+            CursorShape::Arrow => MouseCursor::Default,
+            CursorShape::Text => MouseCursor::Text,
+            CursorShape::Hand => MouseCursor::Hand,
+        });
+    }
     fn check_for_resize(&mut self) -> Result<(), Error> {
         self.have_pending_resize_check = false;
         let old_dpi_scale = self.fonts.get_dpi_scale();
@@ -176,6 +327,13 @@ impl TerminalWindow for GliumTerminalWindow {
             .ok_or_else(|| format_err!("failed to get inner window size"))?;
         let dpi_scale = self.host.display.gl_window().get_hidpi_factor();
         let (width, height): (u32, u32) = size.to_physical(dpi_scale).into();
+        if width == 0 || height == 0 {
+            // Minimized windows on Windows have no size; resizing the
+            // ptys to match would make ConPTY reflow their contents
+            // into a single column
+            debug!("ignoring resize to {}x{}", width, height);
+            return Ok(());
+        }
         debug!(
             "resize {}x{}@{} -> {}x{}@{}",
             self.width, self.height, old_dpi_scale, width, height, dpi_scale
@@ -205,29 +363,14 @@ impl GliumTerminalWindow {
             metrics.cell_width.ceil() as usize,
         );
 
-        let width = cell_width * physical_cols;
-        let height = cell_height * physical_rows;
+        let width = cell_width * (physical_cols + scroll_bar_cols(config));
+        let height = cell_height * (physical_rows + tab_bar_rows(config));
 
         let logical_size = LogicalSize::new(width as f64, height as f64);
         debug!("make window with {}x{}", width, height);
 
-        let display = {
-            let pref_context = glutin::ContextBuilder::new()
-                .with_vsync(true)
-                .with_pixel_format(24, 8);
-            let window = glutin::WindowBuilder::new()
-                .with_min_dimensions(LogicalSize::new(cell_width as f64, cell_height as f64))
-                .with_dimensions(logical_size)
-                .with_window_icon(Some(glutin::Icon::from_bytes(include_bytes!(
-                    "../../../assets/icon/terminal.png"
-                ))?))
-                .with_title("wezterm");
-
-            let mut_loop = event_loop.event_loop.borrow_mut();
-
-            glium::Display::new(window, pref_context, &*mut_loop)
-                .map_err(|e| format_err!("{:?}", e))?
-        };
+        let display =
+            Self::create_display(event_loop, config, logical_size, cell_width, cell_height)?;
         let window_position = display.gl_window().get_position();
 
         let host = HostImpl::new(Host {
@@ -235,6 +378,7 @@ impl GliumTerminalWindow {
             display,
             window_position,
             is_fullscreen: None,
+            always_on_top: false,
         });
 
         host.display.gl_window().set_cursor(MouseCursor::Text);
@@ -248,37 +392,65 @@ impl GliumTerminalWindow {
             config: Arc::clone(config),
             fonts: Rc::clone(fonts),
             renderer,
+            tab_bar: TabBarState::default(),
+            scrollbar: ScrollbarState::default(),
             width,
             height,
             cell_height,
             cell_width,
-            last_mouse_coords: PhysicalPosition::new(0.0, 0.0),
+            mouse: MouseTranslator::default(),
             last_modifiers: Default::default(),
             allow_received_character: false,
             mux_window_id,
             have_pending_resize_check: false,
+            title: "wezterm".to_owned(),
         })
     }
 
+    /// Creates a window along with its OpenGL context
+    fn create_display(
+        event_loop: &Rc<GuiEventLoop>,
+        config: &Arc<Config>,
+        logical_size: LogicalSize,
+        cell_width: usize,
+        cell_height: usize,
+    ) -> Result<glium::Display, Error> {
+        let pref_context = glutin::ContextBuilder::new()
+            .with_vsync(true)
+            .with_pixel_format(24, 8)
+            // So that glium can tell when the context is lost
+            .with_gl_robustness(glutin::Robustness::TryRobustLoseContextOnReset);
+        let window = glutin::WindowBuilder::new()
+            .with_min_dimensions(LogicalSize::new(cell_width as f64, cell_height as f64))
+            .with_dimensions(logical_size)
+            .with_window_icon(Some(glutin::Icon::from_bytes(include_bytes!(
+                "../../../assets/icon/terminal.png"
+            ))?))
+            .with_title("wezterm")
+            .with_transparency(config.window_is_translucent());
+
+        let mut_loop = event_loop.event_loop.borrow_mut();
+
+        glium::Display::new(window, pref_context, &*mut_loop).map_err(|e| format_err!("{:?}", e))
+    }
+
     pub fn window_id(&self) -> glutin::WindowId {
         self.host.display.gl_window().id()
     }
 
-    fn decode_modifiers(state: glium::glutin::ModifiersState) -> term::KeyModifiers {
-        let mut mods = Default::default();
-        if state.shift {
-            mods |= term::KeyModifiers::SHIFT;
-        }
-        if state.ctrl {
-            mods |= term::KeyModifiers::CTRL;
-        }
-        if state.alt {
-            mods |= term::KeyModifiers::ALT;
+    fn modifier_state(state: glium::glutin::ModifiersState) -> ModifierState {
+        ModifierState {
+            shift: state.shift,
+            ctrl: state.ctrl,
+            alt: state.alt,
+            logo: state.logo,
+            // Windows reports AltGr as ctrl+alt
+            alt_gr: cfg!(windows) && state.ctrl && state.alt,
         }
-        if state.logo {
-            mods |= term::KeyModifiers::SUPER;
-        }
-        mods
+    }
+
+    fn decode_modifiers(state: glium::glutin::ModifiersState) -> term::KeyModifiers {
+        Self::modifier_state(state).key_modifiers()
     }
 
     fn mouse_move(
@@ -287,11 +459,19 @@ impl GliumTerminalWindow {
         modifiers: glium::glutin::ModifiersState,
     ) -> Result<(), Error> {
         // On Windows, I've observed that we receive a continuous stream of
-        // CursorMoved events with the same coordinates.  Let's avoid
-        // doing any real work in that situation.
-        if position == self.last_mouse_coords {
-            return Ok(());
-        }
+        // CursorMoved events with the same coordinates.  The translator
+        // only reports motion into a new cell, so those are dropped here.
+        let (x, y): (i32, i32) = position.into();
+        let event = match self.mouse.motion(
+            i64::from(x),
+            i64::from(y),
+            self.cell_width,
+            self.cell_height,
+            Self::decode_modifiers(modifiers),
+        ) {
+            Some(event) => event,
+            None => return Ok(()),
+        };
 
         let mux = Mux::get().unwrap();
         let tab = match mux.get_active_tab_for_window(self.get_mux_window_id()) {
@@ -299,31 +479,23 @@ impl GliumTerminalWindow {
             None => return Ok(()),
         };
 
-        self.last_mouse_coords = position;
-        let (x, y): (i32, i32) = position.into();
-        tab.mouse_event(
-            term::MouseEvent {
-                kind: MouseEventKind::Move,
-                button: MouseButton::None,
-                x: (x as usize / self.cell_width) as usize,
-                y: (y as usize / self.cell_height) as i64,
-                modifiers: Self::decode_modifiers(modifiers),
-            },
-            &mut TabHost::new(&mut *tab.writer(), &mut self.host),
-        )?;
+        let event = match self.translate_mouse_event(event)? {
+            Some(event) => event,
+            None => {
+                if let Some(cursor) = self.host.hover(None) {
+                    self.set_mouse_cursor(cursor);
+                }
+                return Ok(());
+            }
+        };
+        tab.mouse_event(event, &mut TabHost::new(&mut *tab.writer(), &mut self.host))?;
         // Deliberately not forcing a paint on mouse move as it
         // makes selection feel sluggish
         // self.paint_if_needed()?;
 
-        // When hovering over a hyperlink, show an appropriate
-        // mouse cursor to give the cue that it is clickable
-
-        let cursor = if tab.renderer().current_highlight().is_some() {
-            MouseCursor::Hand
-        } else {
-            MouseCursor::Text
-        };
-        self.host.display.gl_window().set_cursor(cursor);
+        if let Some(cursor) = self.host.hover(Some(&*tab)) {
+            self.set_mouse_cursor(cursor);
+        }
 
         Ok(())
     }
@@ -340,24 +512,30 @@ impl GliumTerminalWindow {
             None => return Ok(()),
         };
 
-        tab.mouse_event(
-            term::MouseEvent {
-                kind: match state {
-                    ElementState::Pressed => MouseEventKind::Press,
-                    ElementState::Released => MouseEventKind::Release,
-                },
-                button: match button {
-                    glutin::MouseButton::Left => MouseButton::Left,
-                    glutin::MouseButton::Right => MouseButton::Right,
-                    glutin::MouseButton::Middle => MouseButton::Middle,
-                    glutin::MouseButton::Other(_) => return Ok(()),
-                },
-                x: (self.last_mouse_coords.x as usize / self.cell_width) as usize,
-                y: (self.last_mouse_coords.y as usize / self.cell_height) as i64,
-                modifiers: Self::decode_modifiers(modifiers),
+        let event = match self.mouse.button(
+            match state {
+                ElementState::Pressed => MouseEventKind::Press,
+                ElementState::Released => MouseEventKind::Release,
             },
-            &mut TabHost::new(&mut *tab.writer(), &mut self.host),
-        )?;
+            match button {
+                glutin::MouseButton::Left => MouseButton::Left,
+                glutin::MouseButton::Right => MouseButton::Right,
+                glutin::MouseButton::Middle => MouseButton::Middle,
+                glutin::MouseButton::Other(button) => {
+                    debug!("ignoring button {}", button);
+                    return Ok(());
+                }
+            },
+            Self::decode_modifiers(modifiers),
+        ) {
+            Some(event) => event,
+            None => return Ok(()),
+        };
+        if let Some(event) = self.translate_mouse_event(event)? {
+            if !self.host.process_mouse_bindings(&*tab, &event)? {
+                tab.mouse_event(event, &mut TabHost::new(&mut *tab.writer(), &mut self.host))?;
+            }
+        }
         self.paint_if_needed()?;
 
         Ok(())
@@ -375,47 +553,31 @@ impl GliumTerminalWindow {
         delta: glutin::MouseScrollDelta,
         modifiers: glium::glutin::ModifiersState,
     ) -> Result<(), Error> {
-        // Figure out which wheel button and how many times we want
-        // to trigger it based on the magnitude of the wheel event.
-        // We currently only care about vertical scrolling so the code
-        // below will return early if all we have is horizontal scroll
-        // components.
-        let (button, times) = match delta {
-            glutin::MouseScrollDelta::LineDelta(_, lines) if lines > 0.0 => {
-                (MouseButton::WheelUp, lines.abs().ceil() as usize)
-            }
-            glutin::MouseScrollDelta::LineDelta(_, lines) if lines < 0.0 => {
-                (MouseButton::WheelDown, lines.abs().ceil() as usize)
-            }
-            glutin::MouseScrollDelta::PixelDelta(position) => {
-                let lines = position.y / self.cell_height as f64;
-                if lines > 0.0 {
-                    (MouseButton::WheelUp, lines.abs().ceil() as usize)
-                } else if lines < 0.0 {
-                    (MouseButton::WheelDown, lines.abs().ceil() as usize)
-                } else {
-                    return Ok(());
-                }
-            }
-            _ => return Ok(()),
+        // We currently only care about vertical scrolling, so any
+        // horizontal scroll components are ignored.
+        let lines = match delta {
+            glutin::MouseScrollDelta::LineDelta(_, lines) => f64::from(lines),
+            glutin::MouseScrollDelta::PixelDelta(position) => position.y / self.cell_height as f64,
         };
+        let events = self.mouse.wheel(lines, Self::decode_modifiers(modifiers));
+        if events.is_empty() {
+            return Ok(());
+        }
 
         let mux = Mux::get().unwrap();
         let tab = match mux.get_active_tab_for_window(self.get_mux_window_id()) {
             Some(tab) => tab,
             None => return Ok(()),
         };
-        for _ in 0..times {
-            tab.mouse_event(
-                term::MouseEvent {
-                    kind: MouseEventKind::Press,
-                    button,
-                    x: (self.last_mouse_coords.x as usize / self.cell_width) as usize,
-                    y: (self.last_mouse_coords.y as usize / self.cell_height) as i64,
-                    modifiers: Self::decode_modifiers(modifiers),
-                },
-                &mut TabHost::new(&mut *tab.writer(), &mut self.host),
-            )?;
+        for event in events {
+            let event = match self.translate_mouse_event(event)? {
+                Some(event) => event,
+                None => break,
+            };
+            if self.host.process_mouse_bindings(&*tab, &event)? {
+                continue;
+            }
+            tab.mouse_event(event, &mut TabHost::new(&mut *tab.writer(), &mut self.host))?;
         }
         self.paint_if_needed()?;
 
@@ -628,11 +790,20 @@ impl GliumTerminalWindow {
             Some(tab) => tab,
             None => return Ok(()),
         };
-        let mods = Self::decode_modifiers(event.modifiers);
-        self.last_modifiers = mods;
+        let state = Self::modifier_state(event.modifiers);
+        self.last_modifiers = state;
         self.allow_received_character = false;
         if let Some(key) = Self::keycode_from_input(&event) {
             // debug!("event {:?} -> {:?}", event, key);
+            // AltGr only applies to the characters produced by the
+            // keyboard layout, which we don't see for mapped keys
+            let (key, mods) = input::translate_key(
+                key,
+                ModifierState {
+                    alt_gr: false,
+                    ..state
+                },
+            );
             match event.state {
                 ElementState::Pressed => {
                     if self.host.process_gui_shortcuts(&*tab, mods, key)? {
@@ -643,8 +814,11 @@ impl GliumTerminalWindow {
                 }
                 ElementState::Released => {}
             }
-        } else {
-            error!("event {:?} with no mapping", event);
+        } else if event.state == ElementState::Pressed {
+            // We don't know this key; let the character that the
+            // keyboard layout produces for it, if any, through instead
+            debug!("event {:?} with no mapping", event);
+            self.allow_received_character = true;
         }
         self.paint_if_needed()?;
         Ok(())
@@ -692,7 +866,10 @@ impl GliumTerminalWindow {
                         Some(tab) => tab,
                         None => return Ok(()),
                     };
-                    tab.key_down(KeyCode::Char(c), self.last_modifiers)?;
+                    let (key, mods) = input::translate_key(KeyCode::Char(c), self.last_modifiers);
+                    if !self.host.process_overlay_key(&*tab, mods, key)? {
+                        tab.key_down(key, mods)?;
+                    }
                     self.paint_if_needed()?;
                 }
                 return Ok(());
@@ -736,6 +913,12 @@ impl GliumTerminalWindow {
             } => {
                 self.mouse_wheel(delta, modifiers)?;
             }
+            Event::WindowEvent {
+                event: WindowEvent::Focused(focused),
+                ..
+            } => {
+                self.focus_changed(focused)?;
+            }
             Event::WindowEvent {
                 event: WindowEvent::Refresh,
                 ..
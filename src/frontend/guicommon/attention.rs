@@ -0,0 +1,243 @@
+//! Keeps track of the tabs that want the attention of the user,
+//! because their program rang the bell or, after being silent for
+//! `tab_activity_silence_secs`, produced output while they weren't
+//! being shown.  Such tabs are marked in the tab bar until they are
+//! activated, and may also raise a desktop notification.  This also
+//! drives the visual bell.
+use crate::frontend::guicommon::toast::{self, repaint, window_containing_tab};
+use crate::mux::tab::TabId;
+use crate::mux::window::WindowId;
+use crate::mux::Mux;
+use log::error;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// How long the visual bell flashes the window for
+const FLASH_DURATION: Duration = Duration::from_millis(100);
+
+/// Why a tab wants attention.  A bell outranks activity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Attention {
+    Activity,
+    Bell,
+}
+
+impl Attention {
+    /// Returns the marker that precedes the title of the tab in the
+    /// tab bar
+    pub fn marker(self) -> &'static str {
+        match self {
+            Attention::Activity => "•",
+            Attention::Bell => "!",
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    tabs: HashMap<TabId, Attention>,
+    /// When each tab last produced output
+    last_output: HashMap<TabId, Instant>,
+    /// When the flash of each window that is flashing ends
+    flashes: HashMap<WindowId, Instant>,
+}
+
+thread_local! {
+    static STATE: RefCell<State> = RefCell::new(State::default());
+}
+
+/// Returns the index of the tab in its window, unless the tab is the
+/// one being shown in that window
+fn inactive_tab(tab_id: TabId) -> Option<(WindowId, usize)> {
+    let (window_id, tab_idx) = window_containing_tab(tab_id)?;
+    let is_active = Mux::get()
+        .unwrap()
+        .get_window(window_id)
+        .map(|window| window.get_active_idx() == tab_idx)
+        .unwrap_or(false);
+    if is_active {
+        None
+    } else {
+        Some((window_id, tab_idx))
+    }
+}
+
+/// Records that the tab wants attention, unless it already wants it
+/// for a more pressing reason.  Returns true if this is news.
+fn mark(tab_id: TabId, attention: Attention) -> bool {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        match state.tabs.get(&tab_id) {
+            Some(prior) if *prior >= attention => false,
+            _ => {
+                state.tabs.insert(tab_id, attention);
+                true
+            }
+        }
+    })
+}
+
+/// Handles the bell being rung by the program in the tab: the window
+/// flashes if the tab is being shown and the visual bell is enabled,
+/// and otherwise the tab is marked and the user is notified
+pub fn bell(tab_id: TabId) {
+    let mux = Mux::get().unwrap();
+    let config = mux.config();
+    let (window_id, tab_idx) = match inactive_tab(tab_id) {
+        Some(found) => found,
+        None => {
+            if config.visual_bell.unwrap_or(false) {
+                if let Some((window_id, _)) = window_containing_tab(tab_id) {
+                    STATE.with(|state| {
+                        state
+                            .borrow_mut()
+                            .flashes
+                            .insert(window_id, Instant::now() + FLASH_DURATION)
+                    });
+                    repaint(Some(window_id));
+                }
+            }
+            return;
+        }
+    };
+    let is_news = mark(tab_id, Attention::Bell);
+    toast::bell(tab_id);
+    // Only the first bell until the tab is activated raises a desktop
+    // notification, so that a flood of bells doesn't raise a flood of them
+    if is_news && config.bell_desktop_notification.unwrap_or(false) {
+        let title = mux
+            .get_tab(tab_id)
+            .map(|tab| tab.get_title())
+            .unwrap_or_default();
+        desktop_notification(&format!("Bell in tab {}", tab_idx + 1), &title);
+    }
+    repaint(Some(window_id));
+}
+
+/// Handles output from the tab, which marks the tab if it isn't being
+/// shown and had been silent for long enough
+pub fn output(tab_id: TabId) {
+    let mux = Mux::get().unwrap();
+    let config = mux.config();
+    let silence = match config.tab_activity_silence() {
+        Some(silence) => silence,
+        None => return,
+    };
+    let now = Instant::now();
+    let was_silent = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        match state.last_output.insert(tab_id, now) {
+            Some(last) => now.duration_since(last) >= silence,
+            None => false,
+        }
+    });
+    if !was_silent {
+        return;
+    }
+    if let Some((_, tab_idx)) = inactive_tab(tab_id) {
+        if mark(tab_id, Attention::Activity) && config.bell_desktop_notification.unwrap_or(false) {
+            let title = mux
+                .get_tab(tab_id)
+                .map(|tab| tab.get_title())
+                .unwrap_or_default();
+            desktop_notification(&format!("Activity in tab {}", tab_idx + 1), &title);
+        }
+    }
+}
+
+/// Returns why the tab wants attention, if it does
+pub fn get(tab_id: TabId) -> Option<Attention> {
+    STATE.with(|state| state.borrow().tabs.get(&tab_id).cloned())
+}
+
+/// Forgets that the tab wanted attention, as it is now being shown
+pub fn clear(tab_id: TabId) {
+    STATE.with(|state| state.borrow_mut().tabs.remove(&tab_id));
+}
+
+/// Forgets everything about a tab that has been removed
+pub fn forget(tab_id: TabId) {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.tabs.remove(&tab_id);
+        state.last_output.remove(&tab_id);
+    });
+}
+
+/// Returns true if the window is in the middle of a visual bell
+pub fn is_flashing(window: WindowId) -> bool {
+    STATE.with(|state| state.borrow().flashes.contains_key(&window))
+}
+
+/// Ends the flashes that have gone on for long enough
+pub fn expire() {
+    let now = Instant::now();
+    let expired: Vec<WindowId> = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let expired = state
+            .flashes
+            .iter()
+            .filter(|(_, ends)| **ends <= now)
+            .map(|(window, _)| *window)
+            .collect();
+        state.flashes.retain(|_, ends| *ends > now);
+        expired
+    });
+    for window in expired {
+        repaint(Some(window));
+    }
+}
+
+/// Returns when the next flash is due to end
+pub fn next_expiry() -> Option<Instant> {
+    STATE.with(|state| state.borrow().flashes.values().cloned().min())
+}
+
+/// Shows a notification on the desktop, using whatever tool the
+/// system provides for the purpose
+fn desktop_notification(title: &str, body: &str) {
+    let mut command = if cfg!(target_os = "macos") {
+        let mut command = std::process::Command::new("osascript");
+        command.args(&[
+            "-e",
+            "on run argv",
+            "-e",
+            "display notification (item 2 of argv) with title (item 1 of argv)",
+            "-e",
+            "end run",
+            title,
+            body,
+        ]);
+        command
+    } else if cfg!(unix) {
+        let mut command = std::process::Command::new("notify-send");
+        command.args(&["--app-name=wezterm", title, body]);
+        command
+    } else {
+        error!("desktop notifications aren't supported on this system yet");
+        return;
+    };
+    match command.spawn() {
+        Ok(mut child) => {
+            // Reap the notifier when it exits
+            std::thread::spawn(move || child.wait());
+        }
+        Err(err) => error!("failed to raise a desktop notification: {}", err),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bell_outranks_activity() {
+        assert!(mark(1, Attention::Activity));
+        assert!(mark(1, Attention::Bell));
+        assert!(!mark(1, Attention::Activity));
+        assert_eq!(get(1), Some(Attention::Bell));
+        clear(1);
+        assert_eq!(get(1), None);
+    }
+}
@@ -0,0 +1,141 @@
+//! Questions that the user answers with `y` or `n` in the window, such
+//! as whether to trust the certificate of a mux server that we haven't
+//! connected to before.  The question at the front of the queue is
+//! shown at the bottom of every window and takes the keyboard until it
+//! is answered; Escape answers no.
+use crate::frontend::guicommon::pastehistory::text_line;
+use crate::mux::Mux;
+use promise::{Future, Promise};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use term::{CellAttributes, KeyCode, Line};
+
+struct Question {
+    text: String,
+    answer: Promise<bool>,
+}
+
+thread_local! {
+    static QUESTIONS: RefCell<VecDeque<Question>> = RefCell::new(VecDeque::new());
+}
+
+/// Asks the user `text`, returning a future that is fulfilled with true
+/// if they answer yes.  This must be called on the gui thread.
+pub fn ask(text: String) -> Future<bool> {
+    let mut answer = Promise::new();
+    let future = answer.get_future().unwrap();
+    QUESTIONS.with(|questions| questions.borrow_mut().push_back(Question { text, answer }));
+    repaint_all_windows();
+    future
+}
+
+/// Renders the pending question, if any, as lines `width` cells wide
+/// to be shown at the bottom of the window
+pub fn lines(width: usize) -> Option<Vec<Line>> {
+    QUESTIONS.with(|questions| {
+        let questions = questions.borrow();
+        let question = questions.front()?;
+        let mut attrs = CellAttributes::default();
+        attrs.set_reverse(true);
+        let mut lines: Vec<Line> = wrap(&question.text, width.saturating_sub(2))
+            .iter()
+            .map(|text| text_line(&format!(" {}", text), width, &attrs))
+            .collect();
+        lines.push(text_line(" [y/n] ", width, &attrs));
+        Some(lines)
+    })
+}
+
+/// Passes a key press to the pending question: `y` answers yes, and
+/// `n` or Escape answer no, while other keys are ignored.  Returns
+/// false if there is no question.
+pub fn process_key(key: KeyCode) -> bool {
+    let answered = QUESTIONS.with(|questions| {
+        let mut questions = questions.borrow_mut();
+        if questions.is_empty() {
+            return None;
+        }
+        let answer = match key {
+            KeyCode::Char('y') | KeyCode::Char('Y') => true,
+            KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Escape => false,
+            _ => return Some(false),
+        };
+        if let Some(mut question) = questions.pop_front() {
+            question.answer.ok(answer);
+        }
+        Some(true)
+    });
+    match answered {
+        Some(true) => {
+            repaint_all_windows();
+            true
+        }
+        Some(false) => true,
+        None => false,
+    }
+}
+
+/// Breaks `text` into lines of at most `width` characters, between
+/// words where it can
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut lines = vec![];
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let mut word = word;
+        loop {
+            let line_len = line.chars().count();
+            let word_len = word.chars().count();
+            let needed = if line.is_empty() { 0 } else { 1 } + word_len;
+            if line_len + needed <= width {
+                if !line.is_empty() {
+                    line.push(' ');
+                }
+                line.push_str(word);
+                break;
+            }
+            if !line.is_empty() {
+                lines.push(std::mem::replace(&mut line, String::new()));
+                continue;
+            }
+            // The word alone is too long, so split it
+            let split = word
+                .char_indices()
+                .nth(width)
+                .map(|(idx, _)| idx)
+                .unwrap_or_else(|| word.len());
+            lines.push(word[..split].to_owned());
+            word = &word[split..];
+            if word.is_empty() {
+                break;
+            }
+        }
+    }
+    if !line.is_empty() || lines.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// Marks the active tab of each window as needing to be painted, so
+/// that the question is shown or removed promptly
+fn repaint_all_windows() {
+    let mux = Mux::get().unwrap();
+    for window_id in mux.iter_windows() {
+        if let Some(tab) = mux.get_active_tab_for_window(window_id) {
+            tab.renderer().make_all_lines_dirty();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn wrap_words() {
+        assert_eq!(wrap("trust this host?", 10), vec!["trust this", "host?"]);
+        assert_eq!(wrap("abcdefghijkl xy", 5), vec!["abcde", "fghij", "kl xy"]);
+        assert_eq!(wrap("", 5), vec![""]);
+    }
+}
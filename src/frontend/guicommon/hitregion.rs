@@ -0,0 +1,153 @@
+//! Records which part of the window each cell belongs to as a frame
+//! is painted, so that mouse events can be routed to the tab bar,
+//! the scrollbar, an overlay or the terminal without each of them
+//! repeating the layout arithmetic.
+use std::ops::Range;
+use std::sync::Arc;
+use term::Line;
+use termwiz::hyperlink::Hyperlink;
+
+/// The things that a mouse event can land on
+#[derive(Debug, Clone, PartialEq)]
+pub enum HitTarget {
+    /// The cells of the terminal
+    Terminal,
+    /// Cells of the terminal that hold a hyperlink
+    Hyperlink(Arc<Hyperlink>),
+    /// The tab with the given index in the tab bar
+    Tab(usize),
+    /// The close button of the tab with the given index
+    CloseTab(usize),
+    /// The button that opens a new tab
+    NewTab,
+    /// The parts of the tab bar that are not occupied by a tab
+    TabBar,
+    /// The scrollbar column
+    Scrollbar,
+    /// Lines drawn over the terminal, such as the paste picker
+    Overlay,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct HitRegion {
+    rows: Range<usize>,
+    cols: Range<usize>,
+    target: HitTarget,
+}
+
+/// The hit regions of a painted frame, in cells from the top left
+/// of the window
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct HitRegions {
+    regions: Vec<HitRegion>,
+    terminal_row: usize,
+}
+
+impl HitRegions {
+    /// Starts the regions for a frame whose terminal begins in
+    /// `terminal_row` of the window
+    pub fn new(terminal_row: usize) -> Self {
+        Self {
+            regions: vec![],
+            terminal_row,
+        }
+    }
+
+    /// Adds a region.  Regions are added in the order that they are
+    /// drawn, so a region takes precedence over those added before it.
+    pub fn add(&mut self, rows: Range<usize>, cols: Range<usize>, target: HitTarget) {
+        if rows.start < rows.end && cols.start < cols.end {
+            self.regions.push(HitRegion { rows, cols, target });
+        }
+    }
+
+    /// Adds a region for each run of cells in the first `num_cols`
+    /// columns of `line` that hold the same hyperlink.  The line is
+    /// drawn in `row` of the window.
+    pub fn add_hyperlinks(&mut self, row: usize, line: &Line, num_cols: usize) {
+        let mut run: Option<(usize, &Arc<Hyperlink>)> = None;
+        for (col, cell) in line.cells().iter().take(num_cols).enumerate() {
+            let link = cell.attrs().hyperlink.as_ref();
+            if run.map(|(_, current)| Some(current)) == Some(link) {
+                continue;
+            }
+            if let Some((start, current)) = run.take() {
+                self.add(
+                    row..row + 1,
+                    start..col,
+                    HitTarget::Hyperlink(Arc::clone(current)),
+                );
+            }
+            run = link.map(|link| (col, link));
+        }
+        if let Some((start, current)) = run {
+            let end = line.cells().len().min(num_cols);
+            self.add(
+                row..row + 1,
+                start..end,
+                HitTarget::Hyperlink(Arc::clone(current)),
+            );
+        }
+    }
+
+    /// Returns what is drawn in the cell at `x`, `y`
+    pub fn hit_test(&self, x: usize, y: i64) -> Option<&HitTarget> {
+        if y < 0 {
+            return None;
+        }
+        let y = y as usize;
+        self.regions
+            .iter()
+            .rev()
+            .find(|region| region.rows.contains(&y) && region.cols.contains(&x))
+            .map(|region| &region.target)
+    }
+
+    /// Returns the row of the window in which the terminal begins
+    pub fn terminal_row(&self) -> usize {
+        self.terminal_row
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn later_regions_win() {
+        let mut regions = HitRegions::new(1);
+        regions.add(0..1, 0..10, HitTarget::TabBar);
+        regions.add(0..1, 0..4, HitTarget::Tab(0));
+        regions.add(1..5, 0..10, HitTarget::Terminal);
+        regions.add(1..3, 0..10, HitTarget::Overlay);
+
+        assert_eq!(regions.hit_test(2, 0), Some(&HitTarget::Tab(0)));
+        assert_eq!(regions.hit_test(6, 0), Some(&HitTarget::TabBar));
+        assert_eq!(regions.hit_test(6, 2), Some(&HitTarget::Overlay));
+        assert_eq!(regions.hit_test(6, 4), Some(&HitTarget::Terminal));
+        assert_eq!(regions.hit_test(6, 5), None);
+        assert_eq!(regions.hit_test(10, 4), None);
+        assert_eq!(regions.hit_test(0, -1), None);
+    }
+
+    #[test]
+    fn hyperlink_runs() {
+        let link = Arc::new(Hyperlink::new("http://example.com"));
+        let mut attrs = term::CellAttributes::default();
+        attrs.set_hyperlink(Some(Arc::clone(&link)));
+        let mut line = Line::from_text("see link now", &term::CellAttributes::default());
+        for (col, c) in "link".chars().enumerate() {
+            line.set_cell(col + 4, term::Cell::new(c, attrs.clone()));
+        }
+
+        let mut regions = HitRegions::new(0);
+        regions.add(0..1, 0..12, HitTarget::Terminal);
+        regions.add_hyperlinks(0, &line, 12);
+
+        let target = HitTarget::Hyperlink(link);
+        assert_eq!(regions.hit_test(3, 0), Some(&HitTarget::Terminal));
+        assert_eq!(regions.hit_test(4, 0), Some(&target));
+        assert_eq!(regions.hit_test(7, 0), Some(&target));
+        assert_eq!(regions.hit_test(8, 0), Some(&HitTarget::Terminal));
+    }
+}
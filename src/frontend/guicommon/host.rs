@@ -1,8 +1,22 @@
-use super::window::TerminalWindow;
+use super::confirm;
+use super::launcher::{launcher_entries, new_launcher, Launcher, LauncherAction};
+use super::lineeditor::{EditorAction, LineEditor};
+use super::lockscreen;
+use super::pastehistory::{
+    new_paste_picker, PasteHistory, PastePicker, DEFAULT_PASTE_HISTORY_SIZE,
+};
+use super::picker::PickerOutcome;
+use super::quickselect::{self, compile_patterns, Outcome};
+use super::search;
+use super::toast;
+use super::window::{MouseCursor, TerminalWindow};
+use crate::config::Config;
 use crate::font::{FontConfiguration, FontSystemSelection};
-use crate::frontend::guicommon::window::SpawnTabDomain;
+use crate::frontend::guicommon::window::{step_font_scale, SpawnTabDomain, SplitDirection};
 use crate::frontend::{front_end, gui_executor};
+use crate::mux::layout::{default_layout_path, Layout};
 use crate::mux::tab::{Tab, TabId};
+use crate::mux::window::WindowId;
 use crate::mux::Mux;
 use clipboard::{ClipboardContext, ClipboardProvider};
 use failure::Fallible;
@@ -10,11 +24,16 @@ use failure::{format_err, Error};
 use log::error;
 use portable_pty::PtySize;
 use promise::Future;
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::ops::{Deref, DerefMut};
+use std::path::PathBuf;
 use std::rc::Rc;
-use std::sync::{Arc, Mutex};
-use term::{KeyCode, KeyModifiers};
+use std::str::FromStr;
+use std::sync::Arc;
+use term::{
+    CopyModeAction, CopyModeMotion, KeyCode, KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+};
 use termwiz::hyperlink::Hyperlink;
 
 #[derive(Debug, Clone)]
@@ -27,7 +46,34 @@ pub enum KeyAssignment {
     ToggleFullScreen,
     Copy,
     Paste,
+    /// Paste from a specific clipboard
+    PasteFrom(ClipboardPasteSource),
+    /// Show an overlay listing the recently copied text, from which
+    /// an entry can be chosen and pasted
+    ShowPasteHistory,
+    /// Show the launcher menu, from which a new tab can be opened in
+    /// any of the domains
+    ShowLauncher,
     ActivateTabRelative(isize),
+    /// Move the current tab this many places to the right, or to the
+    /// left if negative, wrapping around at either end
+    MoveTabRelative(isize),
+    /// Activate the tab that was active before the current one
+    ActivateLastTab,
+    /// Keep the window above the windows of other programs, or stop
+    /// doing so
+    ToggleAlwaysOnTop,
+    /// Step the background opacity of the window through the
+    /// configured `window_opacity_presets`
+    CycleWindowOpacity,
+    /// Show which GPU and OpenGL implementation the window renders
+    /// with
+    ShowGpuInfo,
+    /// Label the text on the screen that matches the quick select
+    /// patterns, so that it can be copied or pasted by typing a label
+    QuickSelect,
+    /// Search the scrollback for some text, highlighting the matches
+    Search,
     IncreaseFontSize,
     DecreaseFontSize,
     ResetFontSize,
@@ -37,6 +83,78 @@ pub enum KeyAssignment {
     Hide,
     Show,
     CloseCurrentTab,
+    /// Enter a mode where the keyboard moves a cursor through the
+    /// screen and scrollback to select text
+    ActivateCopyMode,
+    /// Copy the URL at or preceding the cursor to the clipboard
+    CopyNearestUrl,
+    /// Open the URL at or preceding the cursor in the browser
+    OpenNearestUrl,
+    /// Switch to the next of the configured color schemes
+    CycleColorScheme,
+    /// Switch the tabs of the window to the named color scheme
+    SetColorScheme(String),
+    /// Shrink the window and spawn a new window alongside it
+    SplitWindow(SplitDirection),
+    /// Open the URL under the mouse pointer in the browser
+    OpenLinkAtMouseCursor,
+    /// Hide the contents of every window behind the lock screen
+    LockScreen,
+    /// Prompt for a new title for the current tab
+    RenameTab,
+    /// Scroll the viewport by this many pages, where a page is the
+    /// height of the terminal; negative values scroll back
+    ScrollByPage(f64),
+    /// Scroll the viewport by this many lines; negative values scroll
+    /// back
+    ScrollByLine(isize),
+    /// Scroll the viewport back (negative) or forward (positive) by
+    /// this many shell prompts
+    ScrollToPrompt(isize),
+    /// Reload the configuration file, applying changes to the fonts
+    /// to the existing windows as well as to new ones
+    ReloadConfiguration,
+    /// Close the tabs of the mux server domain of the current tab,
+    /// leaving them running on the server
+    DetachDomain,
+    /// Save the windows and tabs to the named layout file, or to the
+    /// default one
+    SaveLayout(Option<PathBuf>),
+    /// Open the windows and tabs described by the named layout file,
+    /// or by the default one
+    RestoreLayout(Option<PathBuf>),
+}
+
+/// Which of the system clipboards to paste from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClipboardPasteSource {
+    /// The clipboard that is set by explicit copy operations
+    Clipboard,
+    /// The X11 primary selection.  On systems without one this is
+    /// the same as `Clipboard`.
+    PrimarySelection,
+}
+
+impl FromStr for ClipboardPasteSource {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "Clipboard" => Ok(ClipboardPasteSource::Clipboard),
+            "PrimarySelection" => Ok(ClipboardPasteSource::PrimarySelection),
+            _ => Err(format_err!("invalid ClipboardPasteSource {}", s)),
+        }
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+type PrimarySelectionContext =
+    clipboard::x11_clipboard::X11ClipboardContext<clipboard::x11_clipboard::Primary>;
+
+thread_local! {
+    /// The text recently copied from any window
+    static PASTE_HISTORY: RefCell<PasteHistory> = RefCell::new(PasteHistory::default());
+    /// The titles previously given to tabs, oldest first
+    static TITLE_HISTORY: RefCell<Vec<String>> = RefCell::new(vec![]);
 }
 
 pub trait HostHelper {
@@ -45,6 +163,21 @@ pub trait HostHelper {
         func: F,
     );
     fn toggle_full_screen(&mut self);
+    /// Keeps the window above the windows of other programs, or stops
+    /// doing so
+    fn toggle_always_on_top(&mut self);
+
+    /// Returns the system clipboards when the front end manages them
+    /// itself, rather than leaving that to the clipboard crate
+    fn selections(&self) -> Option<&dyn SystemSelections> {
+        None
+    }
+}
+
+/// The system clipboards of a front end that manages them itself
+pub trait SystemSelections {
+    fn get(&self, source: ClipboardPasteSource) -> Fallible<String>;
+    fn set(&self, source: ClipboardPasteSource, text: String) -> Fallible<()>;
 }
 
 pub struct HostImpl<H: HostHelper> {
@@ -52,49 +185,235 @@ pub struct HostImpl<H: HostHelper> {
     /// macOS gets unhappy if we set up the clipboard too early,
     /// so we use an Option to defer it until we use it
     clipboard: Option<ClipboardContext>,
+    #[cfg(all(unix, not(target_os = "macos")))]
+    primary_selection: Option<PrimarySelectionContext>,
     keys: KeyMap,
+    copy_mode_keys: CopyModeKeyMap,
+    mouse_bindings: MouseMap,
+    /// The button whose press performed a mouse binding; its release
+    /// is swallowed too
+    bound_mouse_button: Option<MouseButton>,
+    /// Set while the paste history overlay is shown
+    paste_picker: Option<PastePicker>,
+    /// Set while prompting for the title of the current tab
+    rename_prompt: Option<LineEditor>,
+    /// Set while the launcher menu is shown
+    launcher: Option<Launcher>,
+    /// Set while quick select mode labels the screen
+    quick_select: Option<quickselect::QuickSelect>,
+    /// Set while searching the scrollback
+    search: Option<search::Search>,
+    /// The mouse cursor that was last shown for the position of the
+    /// mouse, or None if the window hasn't set one yet
+    mouse_cursor: Option<MouseCursor>,
 }
 
-const PASTE_CHUNK_SIZE: usize = 1024;
+fn open_link(link: &Hyperlink) {
+    if let Some((path, line)) = editor_link(link.uri()) {
+        let editor = Mux::get().unwrap().config().editor_command.clone();
+        if let Some(editor) = editor {
+            open_in_editor(path, line, &editor);
+            return;
+        }
+    }
+    match open::that(link.uri()) {
+        Ok(_) => {}
+        Err(err) => error!("failed to open {}: {:?}", link.uri(), err),
+    }
+}
 
-struct Paste {
-    tab_id: TabId,
-    text: String,
-    offset: usize,
+/// Splits a `file:path#line` link, as produced by the default
+/// hyperlink rules, into its path and line number.  Other links,
+/// including `file://host/path` URLs, are left to the default opener.
+fn editor_link(uri: &str) -> Option<(&str, &str)> {
+    if !uri.starts_with("file:") || uri.starts_with("file://") {
+        return None;
+    }
+    let mut iter = uri["file:".len()..].splitn(2, '#');
+    let path = iter.next()?;
+    let line = iter.next()?;
+    if path.is_empty() || line.is_empty() || !line.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    Some((path, line))
 }
 
-fn schedule_next_paste(paste: &Arc<Mutex<Paste>>) {
-    let paste = Arc::clone(paste);
+/// Runs the configured editor_command for a `file:path#line` link
+fn open_in_editor(path: &str, line: &str, editor: &[String]) {
+    let args: Vec<String> = editor
+        .iter()
+        .map(|arg| arg.replace("{path}", path).replace("{line}", line))
+        .collect();
+    if args.is_empty() {
+        error!("editor_command is empty; cannot open {}", path);
+        return;
+    }
+    match std::process::Command::new(&args[0])
+        .args(&args[1..])
+        .spawn()
+    {
+        Ok(mut child) => {
+            // Reap the editor when it exits
+            std::thread::spawn(move || child.wait());
+        }
+        Err(err) => error!("failed to run editor_command {:?}: {}", args, err),
+    }
+}
+
+/// Spawns a new window holding a tab of the given size in the default
+/// domain.  This is deferred to the gui loop so that it is safe to call
+/// while a window is being operated upon.
+pub fn spawn_window(size: PtySize) {
     Future::with_executor(gui_executor().unwrap(), move || {
-        let mut locked = paste.lock().unwrap();
         let mux = Mux::get().unwrap();
-        let tab = mux.get_tab(locked.tab_id).unwrap();
-
-        let remain = locked.text.len() - locked.offset;
-        let chunk = remain.min(PASTE_CHUNK_SIZE);
-        let text_slice = &locked.text[locked.offset..locked.offset + chunk];
-        tab.send_paste(text_slice).unwrap();
+        let fonts = Rc::new(FontConfiguration::new(
+            mux.config(),
+            FontSystemSelection::get_default(),
+        ));
+        let window_id = mux.new_empty_window();
+        let tab = mux.default_domain().spawn(size, None, window_id)?;
+        let front_end = front_end().expect("to be called on gui thread");
+        front_end.spawn_new_window(&mux.config(), &fonts, &tab, window_id)?;
+        Ok(())
+    });
+}
 
-        if chunk < remain {
-            // There is more to send
-            locked.offset += chunk;
-            schedule_next_paste(&paste);
+/// Opens a window for each of the windows described by the layout.
+/// This is deferred to the gui loop, as windows can't be added while
+/// one of them is handling input.
+pub fn restore_layout(layout: Layout) {
+    Future::with_executor(gui_executor().unwrap(), move || {
+        let mux = Mux::get().unwrap();
+        let config = mux.config();
+        let fonts = Rc::new(FontConfiguration::new(
+            Arc::clone(&config),
+            FontSystemSelection::get_default(),
+        ));
+        let domain = Arc::clone(mux.default_domain());
+        let front_end = front_end().expect("to be called on gui thread");
+        for window in &layout.windows {
+            if window.tabs.is_empty() {
+                continue;
+            }
+            let window_id = mux.new_empty_window();
+            let mut first_tab = None;
+            for tab in &window.tabs {
+                let command = tab.command(&config, domain.domain_name())?;
+                let tab = domain.spawn(PtySize::default(), command, window_id)?;
+                first_tab.get_or_insert(tab);
+            }
+            if let Some(tab) = first_tab {
+                mux.get_window_mut(window_id)
+                    .ok_or_else(|| format_err!("no such window {}", window_id))?
+                    .set_active(0);
+                front_end.spawn_new_window(&config, &fonts, &tab, window_id)?;
+            }
         }
+        Ok(())
+    });
+}
 
+/// Moves a tab that was dragged out of the window `from` into the
+/// window found at the point `at` on the screen, or into a new window
+/// if there is none there.  This is deferred to the gui loop, as the
+/// windows can't be looked up while one of them is handling input.
+pub fn tear_off_tab(tab_id: TabId, from: WindowId, at: Option<(i64, i64)>) {
+    Future::with_executor(gui_executor().unwrap(), move || {
+        let mux = Mux::get().unwrap();
+        let front_end = front_end().expect("to be called on gui thread");
+        let tab = mux
+            .get_tab(tab_id)
+            .ok_or_else(|| format_err!("no such tab {}", tab_id))?;
+        let target = at
+            .and_then(|(x, y)| front_end.window_at(x, y))
+            .filter(|window_id| *window_id != from);
+        match target {
+            Some(window_id) => {
+                // Fit the tab to the window that it joins
+                if let Some(active) = mux.get_active_tab_for_window(window_id) {
+                    let (rows, cols) = active.renderer().physical_dimensions();
+                    tab.resize(PtySize {
+                        rows: rows as u16,
+                        cols: cols as u16,
+                        pixel_width: 0,
+                        pixel_height: 0,
+                    })?;
+                }
+                mux.move_tab_to_window(tab_id, window_id)?;
+                let mut window = mux
+                    .get_window_mut(window_id)
+                    .ok_or_else(|| format_err!("no such window {}", window_id))?;
+                if let Some(idx) = window.idx_by_id(tab_id) {
+                    window.set_active(idx);
+                }
+            }
+            None => {
+                // A tab that is alone in its window has nothing to be
+                // torn away from
+                let alone = mux
+                    .get_window(from)
+                    .map(|window| window.len() <= 1)
+                    .unwrap_or(true);
+                if alone {
+                    return Ok(());
+                }
+                let fonts = Rc::new(FontConfiguration::new(
+                    mux.config(),
+                    FontSystemSelection::get_default(),
+                ));
+                let window_id = mux.new_empty_window();
+                mux.move_tab_to_window(tab_id, window_id)?;
+                front_end.spawn_new_window(&mux.config(), &fonts, &tab, window_id)?;
+            }
+        }
         Ok(())
     });
 }
 
-fn trickle_paste(tab_id: TabId, text: String) {
-    let paste = Arc::new(Mutex::new(Paste {
-        tab_id,
-        text,
-        offset: PASTE_CHUNK_SIZE,
-    }));
-    schedule_next_paste(&paste);
+/// Completes a tab title from the titles of the other tabs
+fn complete_tab_title(text: &str) -> Vec<String> {
+    let mux = Mux::get().unwrap();
+    let mut titles = vec![];
+    for window_id in mux.iter_windows() {
+        if let Some(window) = mux.get_window(window_id) {
+            for tab in window.iter() {
+                let title = tab.get_title();
+                if title.starts_with(text) && !titles.contains(&title) {
+                    titles.push(title);
+                }
+            }
+        }
+    }
+    titles
 }
 
 type KeyMap = HashMap<(KeyCode, KeyModifiers), KeyAssignment>;
+type MouseMap = HashMap<(MouseButton, KeyModifiers), KeyAssignment>;
+
+/// Scrolls the viewport of `tab` by `lines`, where negative values
+/// scroll back into the scrollback
+fn scroll_by_lines(tab: &dyn Tab, lines: isize) {
+    let mut renderer = tab.renderer();
+    let offset = renderer.scrollbar_info().viewport_offset as isize - lines;
+    renderer.set_viewport_offset(offset.max(0) as usize);
+}
+
+fn mouse_bindings(previous: Option<&MouseMap>) -> MouseMap {
+    mouse_bindings_from_config(&Mux::get().unwrap().config(), previous)
+}
+
+/// Returns the mouse bindings from `config`.  The bindings are checked
+/// when the config is loaded, but should they be invalid regardless,
+/// the `previous` bindings are kept rather than taking down the gui.
+fn mouse_bindings_from_config(config: &Config, previous: Option<&MouseMap>) -> MouseMap {
+    match config.mouse_bindings() {
+        Ok(map) => map,
+        Err(err) => {
+            error!("ignoring the invalid mouse_bindings of the config: {}", err);
+            previous.cloned().unwrap_or_default()
+        }
+    }
+}
 
 fn key_bindings() -> KeyMap {
     let mux = Mux::get().unwrap();
@@ -123,8 +442,6 @@ fn key_bindings() -> KeyMap {
         // Window management
         [KeyModifiers::SUPER, KeyCode::Char('m'), Hide],
         [KeyModifiers::SUPER, KeyCode::Char('n'), SpawnWindow],
-        [KeyModifiers::ALT, KeyCode::Char('\n'), ToggleFullScreen],
-        [KeyModifiers::ALT, KeyCode::Char('\r'), ToggleFullScreen],
         [KeyModifiers::ALT, KeyCode::Enter, ToggleFullScreen],
         // Font size manipulation
         [KeyModifiers::SUPER, KeyCode::Char('-'), DecreaseFontSize],
@@ -165,6 +482,178 @@ fn key_bindings() -> KeyMap {
             KeyCode::Char('}'),
             ActivateTabRelative(1)
         ],
+        // Copy mode
+        [
+            KeyModifiers::CTRL | KeyModifiers::SHIFT,
+            KeyCode::Char('X'),
+            ActivateCopyMode
+        ],
+        [
+            KeyModifiers::CTRL | KeyModifiers::SHIFT,
+            KeyCode::Char('x'),
+            ActivateCopyMode
+        ],
+        // Keyboard access to URLs
+        [
+            KeyModifiers::CTRL | KeyModifiers::SHIFT,
+            KeyCode::Char('Y'),
+            CopyNearestUrl
+        ],
+        [
+            KeyModifiers::CTRL | KeyModifiers::SHIFT,
+            KeyCode::Char('y'),
+            CopyNearestUrl
+        ],
+        [
+            KeyModifiers::CTRL | KeyModifiers::SHIFT,
+            KeyCode::Char('O'),
+            OpenNearestUrl
+        ],
+        [
+            KeyModifiers::CTRL | KeyModifiers::SHIFT,
+            KeyCode::Char('o'),
+            OpenNearestUrl
+        ],
+        // Paste history
+        [
+            KeyModifiers::CTRL | KeyModifiers::SHIFT,
+            KeyCode::Char('H'),
+            ShowPasteHistory
+        ],
+        [
+            KeyModifiers::CTRL | KeyModifiers::SHIFT,
+            KeyCode::Char('h'),
+            ShowPasteHistory
+        ],
+        // Quick select
+        [
+            KeyModifiers::CTRL | KeyModifiers::SHIFT,
+            KeyCode::Char(' '),
+            QuickSelect
+        ],
+        // Search
+        [
+            KeyModifiers::CTRL | KeyModifiers::SHIFT,
+            KeyCode::Char('F'),
+            Search
+        ],
+        [
+            KeyModifiers::CTRL | KeyModifiers::SHIFT,
+            KeyCode::Char('f'),
+            Search
+        ],
+        // Lock screen
+        [
+            KeyModifiers::CTRL | KeyModifiers::SHIFT,
+            KeyCode::Char('L'),
+            LockScreen
+        ],
+        [
+            KeyModifiers::CTRL | KeyModifiers::SHIFT,
+            KeyCode::Char('l'),
+            LockScreen
+        ],
+        // Colors
+        [
+            KeyModifiers::CTRL | KeyModifiers::SHIFT,
+            KeyCode::Char('P'),
+            CycleColorScheme
+        ],
+        [
+            KeyModifiers::CTRL | KeyModifiers::SHIFT,
+            KeyCode::Char('p'),
+            CycleColorScheme
+        ],
+        // Configuration
+        [
+            KeyModifiers::CTRL | KeyModifiers::SHIFT,
+            KeyCode::Char('R'),
+            ReloadConfiguration
+        ],
+        [
+            KeyModifiers::CTRL | KeyModifiers::SHIFT,
+            KeyCode::Char('r'),
+            ReloadConfiguration
+        ],
+        // Multiplexer
+        [
+            KeyModifiers::CTRL | KeyModifiers::SHIFT,
+            KeyCode::Char('D'),
+            DetachDomain
+        ],
+        [
+            KeyModifiers::CTRL | KeyModifiers::SHIFT,
+            KeyCode::Char('d'),
+            DetachDomain
+        ],
+    );
+
+    map
+}
+
+type CopyModeKeyMap = HashMap<(KeyCode, KeyModifiers), CopyModeAction>;
+
+/// The keys that are active while in copy mode.  These follow the
+/// vi and emacs bindings of the tmux copy mode.  Shift is ignored
+/// for character keys, so `G` and `$` need no special treatment.
+fn copy_mode_key_bindings() -> CopyModeKeyMap {
+    let mut map = HashMap::new();
+
+    macro_rules! m {
+        ($([$mod:expr, $code:expr, $action:expr]),* $(,)?) => {
+            $(
+            map.insert(($code, $mod), $action);
+            )*
+        };
+    };
+
+    use CopyModeAction::*;
+    use CopyModeMotion::*;
+    const NONE: KeyModifiers = KeyModifiers::NONE;
+    const CTRL: KeyModifiers = KeyModifiers::CTRL;
+    const ALT: KeyModifiers = KeyModifiers::ALT;
+
+    m!(
+        [NONE, KeyCode::Char('h'), Move(Left)],
+        [NONE, KeyCode::LeftArrow, Move(Left)],
+        [NONE, KeyCode::Char('l'), Move(Right)],
+        [NONE, KeyCode::RightArrow, Move(Right)],
+        [NONE, KeyCode::Char('k'), Move(Up)],
+        [NONE, KeyCode::UpArrow, Move(Up)],
+        [CTRL, KeyCode::Char('p'), Move(Up)],
+        [NONE, KeyCode::Char('j'), Move(Down)],
+        [NONE, KeyCode::DownArrow, Move(Down)],
+        [CTRL, KeyCode::Char('n'), Move(Down)],
+        [NONE, KeyCode::Char('0'), Move(StartOfLine)],
+        [NONE, KeyCode::Home, Move(StartOfLine)],
+        [CTRL, KeyCode::Char('a'), Move(StartOfLine)],
+        [NONE, KeyCode::Char('$'), Move(EndOfLine)],
+        [NONE, KeyCode::End, Move(EndOfLine)],
+        [CTRL, KeyCode::Char('e'), Move(EndOfLine)],
+        [NONE, KeyCode::Char('w'), Move(WordForward)],
+        [ALT, KeyCode::Char('f'), Move(WordForward)],
+        [NONE, KeyCode::Char('b'), Move(WordBackward)],
+        [ALT, KeyCode::Char('b'), Move(WordBackward)],
+        [NONE, KeyCode::PageUp, Move(PageUp)],
+        [CTRL, KeyCode::Char('b'), Move(PageUp)],
+        [ALT, KeyCode::Char('v'), Move(PageUp)],
+        [NONE, KeyCode::PageDown, Move(PageDown)],
+        [CTRL, KeyCode::Char('f'), Move(PageDown)],
+        [CTRL, KeyCode::Char('v'), Move(PageDown)],
+        [NONE, KeyCode::Char('g'), Move(Top)],
+        [ALT, KeyCode::Char('<'), Move(Top)],
+        [NONE, KeyCode::Char('G'), Move(Bottom)],
+        [ALT, KeyCode::Char('>'), Move(Bottom)],
+        [NONE, KeyCode::Char('v'), ToggleSelection],
+        [NONE, KeyCode::Char(' '), ToggleSelection],
+        [CTRL, KeyCode::Char(' '), ToggleSelection],
+        [NONE, KeyCode::Char('y'), Copy],
+        [NONE, KeyCode::Enter, Copy],
+        [ALT, KeyCode::Char('w'), Copy],
+        [NONE, KeyCode::Escape, Exit],
+        [NONE, KeyCode::Char('q'), Exit],
+        [CTRL, KeyCode::Char('c'), Exit],
+        [CTRL, KeyCode::Char('g'), Exit],
     );
 
     map
@@ -175,10 +664,27 @@ impl<H: HostHelper> HostImpl<H> {
         Self {
             helper,
             clipboard: None,
+            #[cfg(all(unix, not(target_os = "macos")))]
+            primary_selection: None,
             keys: key_bindings(),
+            copy_mode_keys: copy_mode_key_bindings(),
+            mouse_bindings: mouse_bindings(None),
+            bound_mouse_button: None,
+            paste_picker: None,
+            rename_prompt: None,
+            launcher: None,
+            quick_select: None,
+            search: None,
+            mouse_cursor: None,
         }
     }
 
+    /// Picks up the key and mouse bindings from the reloaded configuration
+    pub fn config_was_reloaded(&mut self) {
+        self.keys = key_bindings();
+        self.mouse_bindings = mouse_bindings(Some(&self.mouse_bindings));
+    }
+
     fn clipboard(&mut self) -> Result<&mut ClipboardContext, Error> {
         if self.clipboard.is_none() {
             self.clipboard = Some(ClipboardContext::new().map_err(|e| format_err!("{}", e))?);
@@ -187,12 +693,59 @@ impl<H: HostHelper> HostImpl<H> {
     }
 
     pub fn get_clipboard(&mut self) -> Result<String, Error> {
+        if let Some(selections) = self.helper.selections() {
+            return selections.get(ClipboardPasteSource::Clipboard);
+        }
         self.clipboard()?
             .get_contents()
             .map_err(|e| format_err!("{}", e))
     }
 
+    #[cfg(all(unix, not(target_os = "macos")))]
+    fn get_primary_selection(&mut self) -> Fallible<String> {
+        if let Some(selections) = self.helper.selections() {
+            return selections.get(ClipboardPasteSource::PrimarySelection);
+        }
+        if self.primary_selection.is_none() {
+            self.primary_selection =
+                Some(PrimarySelectionContext::new().map_err(|e| format_err!("{}", e))?);
+        }
+        self.primary_selection
+            .as_mut()
+            .unwrap()
+            .get_contents()
+            .map_err(|e| format_err!("{}", e))
+    }
+
+    #[cfg(not(all(unix, not(target_os = "macos"))))]
+    fn get_primary_selection(&mut self) -> Fallible<String> {
+        self.get_clipboard()
+    }
+
+    pub fn get_clipboard_from(&mut self, source: ClipboardPasteSource) -> Fallible<String> {
+        match source {
+            ClipboardPasteSource::Clipboard => self.get_clipboard(),
+            ClipboardPasteSource::PrimarySelection => self.get_primary_selection(),
+        }
+    }
+
+    fn add_to_paste_history(text: &str) {
+        let depth = Mux::get()
+            .unwrap()
+            .config()
+            .paste_history_size
+            .unwrap_or(DEFAULT_PASTE_HISTORY_SIZE);
+        PASTE_HISTORY.with(|history| history.borrow_mut().push(text, depth));
+    }
+
+    /// Sets the clipboard that is used by explicit copy operations
     pub fn set_clipboard(&mut self, clip: Option<String>) -> Result<(), Error> {
+        if let Some(text) = clip.as_ref() {
+            Self::add_to_paste_history(text);
+        }
+        if let Some(selections) = self.helper.selections() {
+            return selections.set(ClipboardPasteSource::Clipboard, clip.unwrap_or_default());
+        }
         self.clipboard()?
             .set_contents(clip.unwrap_or_else(|| "".into()))
             .map_err(|e| format_err!("{}", e))?;
@@ -202,21 +755,39 @@ impl<H: HostHelper> HostImpl<H> {
         self.get_clipboard().map(|_| ())
     }
 
+    /// Sets the primary selection to the text selected with the mouse.
+    /// When the front end doesn't manage the clipboards itself, the
+    /// selection is copied to the clipboard instead.  Clearing the
+    /// selection in the terminal leaves the primary selection alone,
+    /// as it is conventional for it to outlive the highlighted text.
+    pub fn set_primary_selection(&mut self, clip: Option<String>) -> Fallible<()> {
+        if self.helper.selections().is_none() {
+            return self.set_clipboard(clip);
+        }
+        match clip {
+            Some(text) => {
+                Self::add_to_paste_history(&text);
+                self.helper
+                    .selections()
+                    .unwrap()
+                    .set(ClipboardPasteSource::PrimarySelection, text)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the text that middle clicking pastes, which is the
+    /// counterpart of `set_primary_selection`
+    pub fn get_selection_for_middle_click(&mut self) -> Fallible<String> {
+        if self.helper.selections().is_some() {
+            self.get_primary_selection()
+        } else {
+            self.get_clipboard()
+        }
+    }
+
     pub fn spawn_new_window(&mut self) {
-        Future::with_executor(gui_executor().unwrap(), move || {
-            let mux = Mux::get().unwrap();
-            let fonts = Rc::new(FontConfiguration::new(
-                Arc::clone(mux.config()),
-                FontSystemSelection::get_default(),
-            ));
-            let window_id = mux.new_empty_window();
-            let tab = mux
-                .default_domain()
-                .spawn(PtySize::default(), None, window_id)?;
-            let front_end = front_end().expect("to be called on gui thread");
-            front_end.spawn_new_window(mux.config(), &fonts, &tab, window_id)?;
-            Ok(())
-        });
+        spawn_window(PtySize::default());
     }
 
     pub fn perform_key_assignment(
@@ -235,20 +806,77 @@ impl<H: HostHelper> HostImpl<H> {
             SpawnWindow => self.spawn_new_window(),
             ToggleFullScreen => self.toggle_full_screen(),
             Copy => {
-                // Nominally copy, but that is implicit, so NOP
+                // Selecting text copies it to the primary selection, or
+                // to the clipboard on systems without one; this copies
+                // the selection to the clipboard
+                if let Some(text) = tab.selection_text().filter(|text| !text.is_empty()) {
+                    let count = text.chars().count();
+                    self.set_clipboard(Some(text))?;
+                    toast::show_for_tab(tab.tab_id(), &format!("Copied {} characters", count));
+                }
             }
             Paste => {
                 let text = self.get_clipboard()?;
-                if text.len() <= PASTE_CHUNK_SIZE {
-                    // Send it all now
-                    tab.send_paste(&text)?;
-                } else {
-                    // It's pretty heavy, so we trickle it into the pty
-                    tab.send_paste(&text[0..PASTE_CHUNK_SIZE])?;
-                    trickle_paste(tab.tab_id(), text);
+                tab.send_paste(&text)?;
+            }
+            PasteFrom(source) => {
+                let text = self.get_clipboard_from(*source)?;
+                tab.send_paste(&text)?;
+            }
+            ShowPasteHistory => {
+                let entries = PASTE_HISTORY.with(|history| history.borrow().entries());
+                if !entries.is_empty() {
+                    self.paste_picker = Some(new_paste_picker(entries));
+                    tab.renderer().make_all_lines_dirty();
                 }
             }
+            ShowLauncher => {
+                self.show_launcher();
+                tab.renderer().make_all_lines_dirty();
+            }
             ActivateTabRelative(n) => self.activate_tab_relative(*n),
+            MoveTabRelative(n) => {
+                let n = *n;
+                self.with_window(move |win| win.move_tab_relative(n))
+            }
+            ActivateLastTab => self.with_window(move |win| win.activate_last_tab()),
+            ToggleAlwaysOnTop => self.toggle_always_on_top(),
+            CycleWindowOpacity => {
+                let tab_id = tab.tab_id();
+                self.with_window(move |win| {
+                    if !win.cycle_window_opacity() {
+                        toast::show_for_tab(tab_id, "No window_opacity_presets are configured");
+                    }
+                    Ok(())
+                })
+            }
+            QuickSelect => {
+                let config = Mux::get().unwrap().config();
+                let patterns = compile_patterns(&config.quick_select_patterns())?;
+                let snapshot = tab.snapshot(None);
+                let select = quickselect::QuickSelect::new(
+                    snapshot.lines.iter().map(|(line, _)| &**line),
+                    &patterns,
+                );
+                if select.is_empty() {
+                    toast::show_for_tab(tab.tab_id(), "Nothing to select");
+                } else {
+                    self.quick_select = Some(select);
+                    tab.renderer().make_all_lines_dirty();
+                }
+            }
+            Search => {
+                self.search = Some(search::Search::default());
+                tab.renderer().make_all_lines_dirty();
+            }
+            ShowGpuInfo => {
+                let tab_id = tab.tab_id();
+                self.with_window(move |win| {
+                    let info = win.renderer().gpu_info().to_string();
+                    toast::show_for_tab(tab_id, &info);
+                    Ok(())
+                })
+            }
             DecreaseFontSize => self.decrease_font_size(),
             IncreaseFontSize => self.increase_font_size(),
             ResetFontSize => self.reset_font_size(),
@@ -257,6 +885,84 @@ impl<H: HostHelper> HostImpl<H> {
             Hide => self.hide_window(),
             Show => self.show_window(),
             CloseCurrentTab => self.close_current_tab(),
+            ActivateCopyMode => {
+                tab.copy_mode(CopyModeAction::Enter)?;
+            }
+            CopyNearestUrl => {
+                if let Some(link) = tab.hyperlink_near_cursor()? {
+                    self.set_clipboard(Some(link.uri().to_owned()))?;
+                    toast::show_for_tab(tab.tab_id(), &format!("Copied {}", link.uri()));
+                }
+            }
+            OpenNearestUrl => {
+                if let Some(link) = tab.hyperlink_near_cursor()? {
+                    open_link(&link);
+                }
+            }
+            CycleColorScheme => Mux::get().unwrap().cycle_color_scheme()?,
+            SetColorScheme(name) => {
+                let name = name.clone();
+                self.with_window(move |win| {
+                    Mux::get()
+                        .unwrap()
+                        .set_window_color_scheme(win.get_mux_window_id(), &name)
+                })
+            }
+            SplitWindow(direction) => {
+                let direction = *direction;
+                self.with_window(move |win| win.split_window(direction))
+            }
+            OpenLinkAtMouseCursor => {
+                if let Some(link) = tab.renderer().current_highlight() {
+                    open_link(&link);
+                }
+            }
+            LockScreen => lockscreen::lock(),
+            RenameTab => {
+                let history = TITLE_HISTORY.with(|history| history.borrow().clone());
+                self.rename_prompt = Some(
+                    LineEditor::new(&tab.get_title(), history)
+                        .with_completer(Box::new(complete_tab_title)),
+                );
+                tab.renderer().make_all_lines_dirty();
+            }
+            ScrollByPage(pages) => {
+                let (rows, _cols) = tab.renderer().physical_dimensions();
+                let mut lines = (pages * rows as f64).round() as isize;
+                if lines == 0 && *pages != 0.0 {
+                    // Fractions of a page move by at least one line
+                    lines = pages.signum() as isize;
+                }
+                scroll_by_lines(tab, lines);
+            }
+            ScrollByLine(lines) => scroll_by_lines(tab, *lines),
+            ReloadConfiguration => Mux::get().unwrap().set_config(Config::load()?, true),
+            ScrollToPrompt(delta) => tab.renderer().scroll_to_prompt(*delta),
+            DetachDomain => {
+                let mux = Mux::get().unwrap();
+                let domain = mux
+                    .get_domain(tab.domain_id())
+                    .ok_or_else(|| format_err!("tab {} has no domain", tab.tab_id()))?;
+                domain.detach()?;
+            }
+            SaveLayout(path) => {
+                let path = match path {
+                    Some(path) => path.clone(),
+                    None => default_layout_path()?,
+                };
+                Layout::from_mux(&Mux::get().unwrap()).save(&path)?;
+                toast::show_for_tab(
+                    tab.tab_id(),
+                    &format!("Saved the layout to {}", path.display()),
+                );
+            }
+            RestoreLayout(path) => {
+                let path = match path {
+                    Some(path) => path.clone(),
+                    None => default_layout_path()?,
+                };
+                restore_layout(Layout::load(&path)?);
+            }
             Nop => {}
         }
         Ok(())
@@ -268,6 +974,9 @@ impl<H: HostHelper> HostImpl<H> {
         mods: KeyModifiers,
         key: KeyCode,
     ) -> Result<bool, Error> {
+        if self.process_overlay_key(tab, mods, key)? {
+            return Ok(true);
+        }
         if let Some(assignment) = self.keys.get(&(key, mods)).cloned() {
             self.perform_key_assignment(tab, &assignment)?;
             Ok(true)
@@ -276,6 +985,311 @@ impl<H: HostHelper> HostImpl<H> {
         }
     }
 
+    /// Passes a key press to whichever of the lock screen, the prompts
+    /// and pickers or copy mode is active; each of them consumes all
+    /// keys, including those that have no meaning to it.
+    /// Returns false if none of them is active.
+    pub fn process_overlay_key(
+        &mut self,
+        tab: &dyn Tab,
+        mods: KeyModifiers,
+        key: KeyCode,
+    ) -> Fallible<bool> {
+        if lockscreen::process_key(key, mods) {
+            return Ok(true);
+        }
+        if confirm::process_key(key) {
+            return Ok(true);
+        }
+        if self.rename_prompt.is_some() {
+            self.process_rename_prompt_key(tab, mods, key);
+            return Ok(true);
+        }
+        if self.search.is_some() {
+            self.process_search_key(tab, mods, key);
+            return Ok(true);
+        }
+        if self.paste_picker.is_some() {
+            self.process_paste_picker_key(tab, key)?;
+            return Ok(true);
+        }
+        if self.launcher.is_some() {
+            self.process_launcher_key(tab, key);
+            return Ok(true);
+        }
+        if self.quick_select.is_some() {
+            self.process_quick_select_key(tab, key)?;
+            return Ok(true);
+        }
+        if tab.in_copy_mode() {
+            self.process_copy_mode_key(tab, mods, key)?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Performs the assignment bound to a mouse button press, if any.
+    /// Returns true if the event was consumed and must not be passed
+    /// on to the tab.  While the program in the tab asks for mouse
+    /// events the presses go to it, unless Shift is held, in which
+    /// case a binding with or without Shift applies.
+    pub fn process_mouse_bindings(&mut self, tab: &dyn Tab, event: &MouseEvent) -> Fallible<bool> {
+        match event.kind {
+            MouseEventKind::Press => {
+                let shifted = event.modifiers.contains(KeyModifiers::SHIFT);
+                let mut candidates = vec![event.modifiers];
+                if tab.renderer().is_mouse_grabbed() {
+                    if !shifted {
+                        return Ok(false);
+                    }
+                    candidates.push(event.modifiers - KeyModifiers::SHIFT);
+                }
+                let assignment = candidates
+                    .into_iter()
+                    .filter_map(|mods| self.mouse_bindings.get(&(event.button, mods)))
+                    .next()
+                    .cloned();
+                match assignment {
+                    Some(assignment) => {
+                        self.bound_mouse_button = Some(event.button);
+                        self.perform_key_assignment(tab, &assignment)?;
+                        Ok(true)
+                    }
+                    None => Ok(false),
+                }
+            }
+            MouseEventKind::Release if self.bound_mouse_button == Some(event.button) => {
+                self.bound_mouse_button = None;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn process_copy_mode_key(
+        &mut self,
+        tab: &dyn Tab,
+        mods: KeyModifiers,
+        key: KeyCode,
+    ) -> Fallible<()> {
+        let mods = match key {
+            KeyCode::Char(_) => mods - KeyModifiers::SHIFT,
+            _ => mods,
+        };
+        if let Some(action) = self.copy_mode_keys.get(&(key, mods)).cloned() {
+            if let Some(text) = tab.copy_mode(action)? {
+                self.set_clipboard(Some(text))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Navigate the paste history overlay, pasting the entry that is
+    /// chosen; see `Picker::process_key` for the keys
+    fn process_paste_picker_key(&mut self, tab: &dyn Tab, key: KeyCode) -> Fallible<()> {
+        let picker = match self.paste_picker.as_mut() {
+            Some(picker) => picker,
+            None => return Ok(()),
+        };
+        tab.renderer().make_all_lines_dirty();
+        match picker.process_key(key) {
+            PickerOutcome::Pending => return Ok(()),
+            PickerOutcome::Cancel => {}
+            PickerOutcome::Choose => {
+                if let Some(text) = picker.selected_entry() {
+                    tab.send_paste(text)?;
+                }
+            }
+        }
+        self.paste_picker = None;
+        Ok(())
+    }
+
+    /// Returns the paste history overlay, if it is currently shown
+    pub fn paste_picker(&self) -> Option<&PastePicker> {
+        self.paste_picker.as_ref()
+    }
+
+    /// Handles a key while quick select mode labels the screen: typing
+    /// a label copies its text, typing it in upper case pastes it, and
+    /// Escape, or a key that starts no label, cancels.
+    fn process_quick_select_key(&mut self, tab: &dyn Tab, key: KeyCode) -> Fallible<()> {
+        let select = match self.quick_select.as_mut() {
+            Some(select) => select,
+            None => return Ok(()),
+        };
+        let outcome = match key {
+            KeyCode::Char(c) => select.type_char(c),
+            KeyCode::Backspace => {
+                select.backspace();
+                Outcome::Pending
+            }
+            KeyCode::Escape => Outcome::Cancel,
+            _ => Outcome::Pending,
+        };
+        match outcome {
+            Outcome::Pending => {}
+            Outcome::Cancel => self.quick_select = None,
+            Outcome::Copy(text) => {
+                self.quick_select = None;
+                toast::show_for_tab(tab.tab_id(), &format!("Copied {}", text));
+                self.set_clipboard(Some(text))?;
+            }
+            Outcome::Paste(text) => {
+                self.quick_select = None;
+                tab.send_paste(&text)?;
+            }
+        }
+        tab.renderer().make_all_lines_dirty();
+        Ok(())
+    }
+
+    /// Returns quick select mode, if it currently labels the screen
+    pub fn quick_select(&self) -> Option<&quickselect::QuickSelect> {
+        self.quick_select.as_ref()
+    }
+
+    /// Labels the screen of `tab` again if it changed while quick
+    /// select mode is shown, leaving the mode once nothing on the
+    /// screen matches.  Returns true if the labels changed.
+    pub fn refresh_quick_select(&mut self, tab: &dyn Tab) -> bool {
+        let select = match self.quick_select.as_mut() {
+            Some(select) => select,
+            None => return false,
+        };
+        if !select.refresh(tab) {
+            return false;
+        }
+        if select.is_empty() {
+            self.quick_select = None;
+        }
+        true
+    }
+
+    /// Shows the launcher menu
+    pub fn show_launcher(&mut self) {
+        self.launcher = Some(new_launcher(launcher_entries()));
+    }
+
+    /// Navigate the launcher menu, which takes the same keys as the
+    /// paste history overlay; choosing an entry opens a new tab
+    fn process_launcher_key(&mut self, tab: &dyn Tab, key: KeyCode) {
+        let launcher = match self.launcher.as_mut() {
+            Some(launcher) => launcher,
+            None => return,
+        };
+        tab.renderer().make_all_lines_dirty();
+        match launcher.process_key(key) {
+            PickerOutcome::Pending => return,
+            PickerOutcome::Cancel => {}
+            PickerOutcome::Choose => match launcher.selected_entry().map(|entry| entry.action) {
+                Some(LauncherAction::SpawnTab(domain)) => {
+                    self.with_window(move |win| win.spawn_tab(domain).map(|_| ()));
+                }
+                Some(LauncherAction::Attach(domain_id)) => {
+                    let mux = Mux::get().unwrap();
+                    if let Some(domain) = mux.get_domain(domain_id) {
+                        if let Err(err) = domain.reattach() {
+                            mux.notify_user(&format!(
+                                "Unable to attach to {}: {}",
+                                domain.domain_name(),
+                                err
+                            ));
+                        }
+                    }
+                }
+                None => {}
+            },
+        }
+        self.launcher = None;
+    }
+
+    /// Returns the launcher menu, if it is currently shown
+    pub fn launcher(&self) -> Option<&Launcher> {
+        self.launcher.as_ref()
+    }
+
+    /// Edits the title in the rename prompt.  Entering a title sets it
+    /// as the title of the tab, while entering an empty one reverts
+    /// to the title chosen by the program in the tab.
+    fn process_rename_prompt_key(&mut self, tab: &dyn Tab, mods: KeyModifiers, key: KeyCode) {
+        let action = match self.rename_prompt.as_mut() {
+            Some(editor) => editor.key(key, mods),
+            None => return,
+        };
+        match action {
+            EditorAction::Continue => {}
+            EditorAction::Accept(title) => {
+                self.rename_prompt = None;
+                let title = title.trim().to_owned();
+                if title.is_empty() {
+                    tab.set_title(None);
+                } else {
+                    TITLE_HISTORY.with(|history| {
+                        let mut history = history.borrow_mut();
+                        history.retain(|entry| *entry != title);
+                        history.push(title.clone());
+                    });
+                    tab.set_title(Some(title));
+                }
+            }
+            EditorAction::Cancel => self.rename_prompt = None,
+        }
+        tab.renderer().make_all_lines_dirty();
+    }
+
+    /// Returns the tab rename prompt, if it is currently shown
+    pub fn rename_prompt(&self) -> Option<&LineEditor> {
+        self.rename_prompt.as_ref()
+    }
+
+    /// Edits the text being searched for or steps through the matches,
+    /// until Escape ends the search
+    fn process_search_key(&mut self, tab: &dyn Tab, mods: KeyModifiers, key: KeyCode) {
+        let searching = match self.search.as_mut() {
+            Some(search) => search.key(tab, key, mods),
+            None => return,
+        };
+        if !searching {
+            self.search = None;
+        }
+        tab.renderer().make_all_lines_dirty();
+    }
+
+    /// Returns the scrollback search, if it is active
+    pub fn search(&self) -> Option<&search::Search> {
+        self.search.as_ref()
+    }
+
+    /// Keeps the scrollback search up with the output in `tab`,
+    /// returning true if its matches were found again
+    pub fn refresh_search(&mut self, tab: &dyn Tab) -> bool {
+        match self.search.as_mut() {
+            Some(search) => search.refresh(tab),
+            None => false,
+        }
+    }
+
+    /// Tracks what is under the mouse after a mouse event, where `tab`
+    /// is None if the event didn't reach the terminal.  Hovering over
+    /// a hyperlink shows a hand, the rest of the terminal shows the
+    /// text cursor and the tab bar, scrollbar and overlays show an
+    /// arrow.  Returns the mouse cursor if it differs from the one
+    /// that is currently shown.
+    pub fn hover(&mut self, tab: Option<&dyn Tab>) -> Option<MouseCursor> {
+        let cursor = match tab {
+            Some(tab) if tab.renderer().current_highlight().is_some() => MouseCursor::Hand,
+            Some(_) => MouseCursor::Text,
+            None => MouseCursor::Arrow,
+        };
+        if self.mouse_cursor == Some(cursor) {
+            None
+        } else {
+            self.mouse_cursor = Some(cursor);
+            Some(cursor)
+        }
+    }
+
     pub fn activate_tab(&mut self, tab: usize) {
         self.with_window(move |win| win.activate_tab(tab))
     }
@@ -286,17 +1300,17 @@ impl<H: HostHelper> HostImpl<H> {
 
     pub fn increase_font_size(&mut self) {
         self.with_window(move |win| {
-            let scale = win.fonts().get_font_scale();
+            let scale = step_font_scale(win.fonts().get_font_scale(), 1);
             let dims = win.get_dimensions();
-            win.scaling_changed(Some(scale * 1.1), None, dims.width, dims.height)
+            win.scaling_changed(Some(scale), None, dims.width, dims.height)
         })
     }
 
     pub fn decrease_font_size(&mut self) {
         self.with_window(move |win| {
-            let scale = win.fonts().get_font_scale();
+            let scale = step_font_scale(win.fonts().get_font_scale(), -1);
             let dims = win.get_dimensions();
-            win.scaling_changed(Some(scale * 0.9), None, dims.width, dims.height)
+            win.scaling_changed(Some(scale), None, dims.width, dims.height)
         })
     }
 
@@ -310,15 +1324,11 @@ impl<H: HostHelper> HostImpl<H> {
     pub fn close_current_tab(&mut self) {
         self.with_window(move |win| {
             let mux = Mux::get().unwrap();
-            let tab = match mux.get_active_tab_for_window(win.get_mux_window_id()) {
-                Some(tab) => tab,
+            let tab_idx = match mux.get_window(win.get_mux_window_id()) {
+                Some(window) => window.get_active_idx(),
                 None => return Ok(()),
             };
-            mux.remove_tab(tab.tab_id());
-            if let Some(mut win) = mux.get_window_mut(win.get_mux_window_id()) {
-                win.remove_by_id(tab.tab_id());
-            }
-            win.activate_tab_relative(0)
+            win.close_tab(tab_idx)
         });
     }
 
@@ -369,18 +1379,17 @@ impl<'a, H: HostHelper> term::TerminalHost for TabHost<'a, H> {
     }
 
     fn click_link(&mut self, link: &Arc<Hyperlink>) {
-        match open::that(link.uri()) {
-            Ok(_) => {}
-            Err(err) => error!("failed to open {}: {:?}", link.uri(), err),
-        }
+        open_link(link);
     }
 
+    /// The terminal pastes this when the middle button is clicked
     fn get_clipboard(&mut self) -> Result<String, Error> {
-        self.host.get_clipboard()
+        self.host.get_selection_for_middle_click()
     }
 
+    /// The terminal sets this to the text selected with the mouse
     fn set_clipboard(&mut self, clip: Option<String>) -> Result<(), Error> {
-        self.host.set_clipboard(clip)
+        self.host.set_primary_selection(clip)
     }
 
     fn set_title(&mut self, _title: &str) {
@@ -390,6 +1399,13 @@ impl<'a, H: HostHelper> term::TerminalHost for TabHost<'a, H> {
         })
     }
 
+    fn set_current_dir(&mut self, _dir: &str) {
+        self.host.with_window(move |win| {
+            win.update_title();
+            Ok(())
+        })
+    }
+
     fn activate_tab(&mut self, tab: usize) {
         self.host.activate_tab(tab)
     }
@@ -410,3 +1426,58 @@ impl<'a, H: HostHelper> term::TerminalHost for TabHost<'a, H> {
         self.host.reset_font_size()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn editor_links() {
+        assert_eq!(
+            editor_link("file:src/main.rs#42"),
+            Some(("src/main.rs", "42"))
+        );
+        assert_eq!(
+            editor_link("file:/home/me/a.rs#7"),
+            Some(("/home/me/a.rs", "7"))
+        );
+        assert_eq!(editor_link("file:///home/me/a.rs"), None);
+        assert_eq!(editor_link("file://host/home/me/a.rs#7"), None);
+        assert_eq!(editor_link("file:/home/me/a.rs"), None);
+        assert_eq!(editor_link("file:/home/me/a.html#intro"), None);
+        assert_eq!(editor_link("https://example.com/#1"), None);
+    }
+
+    #[test]
+    fn invalid_mouse_bindings_are_ignored() {
+        let valid: Config = toml::from_str(
+            r#"
+[[mouse_bindings]]
+button = "Right"
+mods = ""
+action = "Paste"
+"#,
+        )
+        .unwrap();
+        let previous = mouse_bindings_from_config(&valid, None);
+        assert_eq!(previous.len(), 1);
+
+        // PasteFrom needs to know where to paste from
+        let invalid: Config = toml::from_str(
+            r#"
+[[mouse_bindings]]
+button = "Middle"
+mods = ""
+action = "PasteFrom"
+"#,
+        )
+        .unwrap();
+        let kept = mouse_bindings_from_config(&invalid, Some(&previous));
+        assert_eq!(kept.len(), 1);
+        match kept.get(&(MouseButton::Right, KeyModifiers::NONE)) {
+            Some(KeyAssignment::Paste) => {}
+            other => panic!("unexpected binding {:?}", other),
+        }
+        assert!(mouse_bindings_from_config(&invalid, None).is_empty());
+    }
+}
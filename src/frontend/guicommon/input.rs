@@ -0,0 +1,139 @@
+//! Translates the keyboard and mouse button input of the gui frontends
+//! into the values understood by the terminal model.
+//! Each frontend extracts the raw state from its windowing system's
+//! events and passes it through here, so that the same keys and
+//! buttons behave the same way regardless of which frontend is in use.
+use term::{KeyCode, KeyModifiers, MouseButton};
+
+/// The modifier keys that were held, as reported by the windowing system
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ModifierState {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub logo: bool,
+    /// AltGr (ISO_Level3_Shift) was used to select the character
+    /// that the key produced
+    pub alt_gr: bool,
+}
+
+impl ModifierState {
+    pub fn key_modifiers(self) -> KeyModifiers {
+        let mut mods = KeyModifiers::default();
+        if self.shift {
+            mods |= KeyModifiers::SHIFT;
+        }
+        if self.ctrl {
+            mods |= KeyModifiers::CTRL;
+        }
+        if self.alt {
+            mods |= KeyModifiers::ALT;
+        }
+        if self.logo {
+            mods |= KeyModifiers::SUPER;
+        }
+        mods
+    }
+}
+
+/// Computes the key and modifiers to pass to the terminal for a key
+/// that the windowing system translated to `code`.
+/// Keys that produce control characters are reported using their
+/// named KeyCode, so that Enter, Tab and Escape are the same in all
+/// frontends.
+/// A character that was selected using AltGr already reflects that
+/// modifier, so any ctrl and alt modifiers reported alongside it
+/// (Windows reports AltGr as ctrl+alt) are dropped rather than being
+/// turned into a control code or an escape prefix by the terminal.
+pub fn translate_key(code: KeyCode, state: ModifierState) -> (KeyCode, KeyModifiers) {
+    let code = match code {
+        KeyCode::Char('\r') => KeyCode::Enter,
+        KeyCode::Char('\t') => KeyCode::Tab,
+        KeyCode::Char('\x1b') => KeyCode::Escape,
+        code => code,
+    };
+    let mut mods = state.key_modifiers();
+    if let KeyCode::Char(_) = code {
+        if state.alt_gr {
+            mods.remove(KeyModifiers::CTRL | KeyModifiers::ALT);
+        }
+    }
+    (code, mods)
+}
+
+/// Returns the MouseButton corresponding to a button numbered the
+/// way that X11 numbers them.  Buttons that have no equivalent in
+/// the terminal model, such as horizontal scrolling (6 and 7) and
+/// the back and forward buttons (8 and 9), yield None.
+pub fn mouse_button_from_number(number: u8) -> Option<MouseButton> {
+    match number {
+        1 => Some(MouseButton::Left),
+        2 => Some(MouseButton::Middle),
+        3 => Some(MouseButton::Right),
+        4 => Some(MouseButton::WheelUp),
+        5 => Some(MouseButton::WheelDown),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn control_keys_are_named() {
+        let state = ModifierState::default();
+        assert_eq!(
+            translate_key(KeyCode::Char('\r'), state),
+            (KeyCode::Enter, KeyModifiers::NONE)
+        );
+        assert_eq!(translate_key(KeyCode::Char('\t'), state).0, KeyCode::Tab);
+        assert_eq!(
+            translate_key(KeyCode::Char('\x1b'), state).0,
+            KeyCode::Escape
+        );
+        assert_eq!(
+            translate_key(KeyCode::Char('a'), state).0,
+            KeyCode::Char('a')
+        );
+    }
+
+    #[test]
+    fn alt_gr() {
+        let state = ModifierState {
+            ctrl: true,
+            alt: true,
+            alt_gr: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            translate_key(KeyCode::Char('@'), state),
+            (KeyCode::Char('@'), KeyModifiers::NONE)
+        );
+        // Only characters have had AltGr applied to them
+        assert_eq!(
+            translate_key(KeyCode::LeftArrow, state),
+            (KeyCode::LeftArrow, KeyModifiers::CTRL | KeyModifiers::ALT)
+        );
+
+        let state = ModifierState {
+            ctrl: true,
+            alt: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            translate_key(KeyCode::Char('q'), state),
+            (KeyCode::Char('q'), KeyModifiers::CTRL | KeyModifiers::ALT)
+        );
+    }
+
+    #[test]
+    fn buttons() {
+        assert_eq!(mouse_button_from_number(1), Some(MouseButton::Left));
+        assert_eq!(mouse_button_from_number(2), Some(MouseButton::Middle));
+        assert_eq!(mouse_button_from_number(3), Some(MouseButton::Right));
+        assert_eq!(mouse_button_from_number(4), Some(MouseButton::WheelUp));
+        assert_eq!(mouse_button_from_number(5), Some(MouseButton::WheelDown));
+        assert_eq!(mouse_button_from_number(8), None);
+    }
+}
@@ -0,0 +1,268 @@
+//! A minimal way for other programs on the same machine, such as the
+//! "Open Terminal Here" action of a file manager or the actions of a
+//! .desktop file, to ask the running gui to open a window or a tab.
+//! This is deliberately much simpler than the mux protocol: requests
+//! are lines of text written to a named pipe in the runtime directory,
+//! usually by `wezterm open`.
+//!
+//! Each line holds fields separated by tabs: `window` or `tab`, then
+//! the directory to start in (empty for the default), then the program
+//! to run and its arguments (none for the default program).
+use crate::config::runtime_dir;
+use crate::font::{FontConfiguration, FontSystemSelection};
+use crate::frontend::front_end;
+use crate::mux::Mux;
+use failure::{bail, format_err, Fallible};
+use log::error;
+use portable_pty::{CommandBuilder, PtySize};
+use promise::{Executor, Future};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// The longest request that can be written to the pipe in one go,
+/// which keeps requests from different writers from being interleaved
+const MAX_REQUEST_LEN: usize = 512;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpenRequest {
+    /// Open a tab in the most recently opened window, rather than
+    /// opening a new window
+    pub new_tab: bool,
+    pub cwd: Option<String>,
+    pub prog: Vec<String>,
+}
+
+impl OpenRequest {
+    /// Encodes the request as a line to be written to the pipe
+    pub fn to_line(&self) -> Fallible<String> {
+        let mut fields = vec![if self.new_tab { "tab" } else { "window" }];
+        fields.push(self.cwd.as_ref().map(String::as_str).unwrap_or(""));
+        fields.extend(self.prog.iter().map(String::as_str));
+        for field in &fields {
+            if field.contains(|c| c == '\t' || c == '\n') {
+                bail!("{:?} contains a tab or a newline", field);
+            }
+        }
+        let line = format!("{}\n", fields.join("\t"));
+        if line.len() > MAX_REQUEST_LEN {
+            bail!("the request is longer than {} bytes", MAX_REQUEST_LEN);
+        }
+        Ok(line)
+    }
+
+    pub fn parse(line: &str) -> Fallible<Self> {
+        let mut fields = line.trim_end_matches('\n').split('\t');
+        let new_tab = match fields.next() {
+            Some("tab") => true,
+            Some("window") => false,
+            other => bail!("expected tab or window, got {:?}", other),
+        };
+        let cwd = fields
+            .next()
+            .filter(|cwd| !cwd.is_empty())
+            .map(str::to_owned);
+        let prog = fields.map(str::to_owned).collect();
+        Ok(Self { new_tab, cwd, prog })
+    }
+
+    /// Opens the window or tab.  This must be called on the gui thread.
+    fn perform(self) -> Fallible<()> {
+        let mux = Mux::get().unwrap();
+        let domain = Arc::clone(mux.default_domain());
+        let command = if self.prog.is_empty() && self.cwd.is_none() {
+            None
+        } else {
+            let mut command = if self.prog.is_empty() {
+                mux.config().build_prog_for_domain(domain.domain_name())?
+            } else {
+                let mut command = CommandBuilder::new(&self.prog[0]);
+                command.args(&self.prog[1..]);
+                command
+            };
+            if let Some(cwd) = &self.cwd {
+                command.cwd(cwd);
+            }
+            Some(command)
+        };
+
+        let window_id = if self.new_tab {
+            mux.iter_windows().into_iter().max()
+        } else {
+            None
+        };
+        match window_id {
+            Some(window_id) => {
+                let size = match mux.get_active_tab_for_window(window_id) {
+                    Some(tab) => {
+                        let (rows, cols) = tab.renderer().physical_dimensions();
+                        PtySize {
+                            rows: rows as u16,
+                            cols: cols as u16,
+                            ..PtySize::default()
+                        }
+                    }
+                    None => PtySize::default(),
+                };
+                let tab = domain.spawn(size, command, window_id)?;
+                let mut window = mux
+                    .get_window_mut(window_id)
+                    .ok_or_else(|| format_err!("window {} went away", window_id))?;
+                if let Some(idx) = window.idx_by_id(tab.tab_id()) {
+                    window.set_active(idx);
+                }
+            }
+            None => {
+                let fonts = Rc::new(FontConfiguration::new(
+                    mux.config(),
+                    FontSystemSelection::get_default(),
+                ));
+                let window_id = mux.new_empty_window();
+                let tab = domain.spawn(PtySize::default(), command, window_id)?;
+                let front_end = front_end().expect("to be called on gui thread");
+                front_end.spawn_new_window(&mux.config(), &fonts, &tab, window_id)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the named pipe that the gui reads requests from
+pub fn pipe_path() -> Fallible<PathBuf> {
+    Ok(runtime_dir()?.join("gui-pipe"))
+}
+
+/// Asks the running gui to perform the request
+#[cfg(unix)]
+pub fn send(request: &OpenRequest) -> Fallible<()> {
+    use std::io::Write;
+    use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+    let line = request.to_line()?;
+    let path = pipe_path()?;
+    let is_pipe = std::fs::metadata(&path)
+        .map(|meta| meta.file_type().is_fifo())
+        .unwrap_or(false);
+    if !is_pipe {
+        bail!("wezterm isn't running; {} doesn't exist", path.display());
+    }
+    // Don't wait for a reader to turn up if the gui has gone away
+    let mut pipe = std::fs::OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(&path)
+        .map_err(|e| format_err!("wezterm isn't running; opening {}: {}", path.display(), e))?;
+    pipe.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn send(_request: &OpenRequest) -> Fallible<()> {
+    bail!("wezterm open isn't supported on this system yet")
+}
+
+/// Creates the named pipe and starts a thread that performs the
+/// requests written to it.  Only one gui at a time reads the pipe;
+/// this does nothing if another one already is.
+#[cfg(unix)]
+pub fn listen(executor: Box<dyn Executor>) -> Fallible<()> {
+    use std::io::BufRead;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::io::AsRawFd;
+
+    let path = pipe_path()?;
+    let lock_path = path.with_extension("lock");
+    let lock = std::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .open(&lock_path)
+        .map_err(|e| format_err!("opening {}: {}", lock_path.display(), e))?;
+    if unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        log::info!("another wezterm is reading {}", path.display());
+        return Ok(());
+    }
+
+    let is_pipe = std::fs::symlink_metadata(&path)
+        .map(|meta| meta.file_type().is_fifo())
+        .unwrap_or(false);
+    if !is_pipe {
+        let _ = std::fs::remove_file(&path);
+        let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+        if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+            bail!(
+                "creating {}: {}",
+                path.display(),
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+
+    // Opening for writing as well keeps the pipe from reporting the
+    // end of the input each time that a writer closes it
+    let pipe = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)
+        .map_err(|e| format_err!("opening {}: {}", path.display(), e))?;
+
+    std::thread::spawn(move || {
+        // Holding the lock for as long as we read the pipe
+        let _lock = lock;
+        for line in std::io::BufReader::new(pipe).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(err) => {
+                    error!("reading the gui pipe: {}", err);
+                    break;
+                }
+            };
+            match OpenRequest::parse(&line) {
+                Ok(request) => {
+                    Future::with_executor(executor.clone_executor(), move || {
+                        if let Err(err) = request.perform() {
+                            error!("unable to open a window or tab: {}", err);
+                        }
+                        Ok(())
+                    });
+                }
+                Err(err) => error!("ignoring {:?} from the gui pipe: {}", line, err),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn listen(_executor: Box<dyn Executor>) -> Fallible<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let request = OpenRequest {
+            new_tab: true,
+            cwd: Some("/home/user/some dir".to_owned()),
+            prog: vec!["vim".to_owned(), "notes.txt".to_owned()],
+        };
+        let line = request.to_line().unwrap();
+        assert_eq!(line, "tab\t/home/user/some dir\tvim\tnotes.txt\n");
+        assert_eq!(OpenRequest::parse(&line).unwrap(), request);
+
+        let request = OpenRequest::parse("window\t").unwrap();
+        assert!(!request.new_tab);
+        assert_eq!(request.cwd, None);
+        assert!(request.prog.is_empty());
+
+        assert!(OpenRequest::parse("split\t").is_err());
+        let bad = OpenRequest {
+            new_tab: false,
+            cwd: Some("a\tb".to_owned()),
+            prog: vec![],
+        };
+        assert!(bad.to_line().is_err());
+    }
+}
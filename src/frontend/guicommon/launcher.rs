@@ -0,0 +1,99 @@
+//! The launcher menu, an overlay listing the ways to open a new tab,
+//! which is shown by right clicking, or pressing and holding, the `+`
+//! button on the tab bar.
+use crate::frontend::guicommon::picker::{Picker, PickerEntry};
+use crate::frontend::guicommon::window::SpawnTabDomain;
+use crate::mux::domain::{DomainId, DomainState};
+use crate::mux::Mux;
+
+/// What choosing an entry in the launcher menu does
+#[derive(Debug, Clone, Copy)]
+pub enum LauncherAction {
+    /// Open a new tab in the domain
+    SpawnTab(SpawnTabDomain),
+    /// Attach again to a domain that was detached
+    Attach(DomainId),
+}
+
+/// An entry in the launcher menu
+#[derive(Debug, Clone)]
+pub struct LauncherEntry {
+    pub label: String,
+    pub action: LauncherAction,
+}
+
+/// Returns an entry for spawning a tab in the default domain, followed
+/// by one for each of the other domains that are attached, and one to
+/// attach to each domain that was detached
+pub fn launcher_entries() -> Vec<LauncherEntry> {
+    let mux = Mux::get().unwrap();
+    let default_id = mux.default_domain().domain_id();
+    let mut entries = vec![LauncherEntry {
+        label: "New tab".to_owned(),
+        action: LauncherAction::SpawnTab(SpawnTabDomain::DefaultDomain),
+    }];
+    for domain in mux.iter_domains() {
+        if domain.domain_id() == default_id {
+            continue;
+        }
+        match domain.state() {
+            DomainState::Attached => entries.push(LauncherEntry {
+                label: format!("New tab in {}", domain.domain_name()),
+                action: LauncherAction::SpawnTab(SpawnTabDomain::Domain(domain.domain_id())),
+            }),
+            DomainState::Detached => entries.push(LauncherEntry {
+                label: format!("Attach to {}", domain.domain_name()),
+                action: LauncherAction::Attach(domain.domain_id()),
+            }),
+            DomainState::Reconnecting => {}
+        }
+    }
+    entries
+}
+
+impl PickerEntry for LauncherEntry {
+    fn label(&self) -> String {
+        self.label.clone()
+    }
+}
+
+/// The launcher menu overlay
+pub type Launcher = Picker<LauncherEntry>;
+
+pub fn new_launcher(entries: Vec<LauncherEntry>) -> Launcher {
+    Picker::new(
+        " Launch: Up/Down to choose, Enter to open, Esc to cancel",
+        entries,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn menu_lists_entries() {
+        let entries = vec![
+            LauncherEntry {
+                label: "New tab".to_owned(),
+                action: LauncherAction::SpawnTab(SpawnTabDomain::DefaultDomain),
+            },
+            LauncherEntry {
+                label: "New tab in remote".to_owned(),
+                action: LauncherAction::SpawnTab(SpawnTabDomain::Domain(1)),
+            },
+        ];
+        let mut launcher = new_launcher(entries);
+        launcher.move_selection(5);
+        assert_eq!(
+            launcher.selected_entry().unwrap().label,
+            "New tab in remote"
+        );
+        assert!(!launcher.select(2));
+
+        let lines = launcher.lines(24, 4);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[1].as_str(), " 1: New tab             ");
+        assert!(lines[2].cells()[0].attrs().reverse());
+    }
+}
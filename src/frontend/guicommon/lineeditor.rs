@@ -0,0 +1,324 @@
+//! A single line text editor with emacs style key bindings, history
+//! and completion, for overlays that prompt for some text
+use crate::frontend::guicommon::pastehistory::text_line;
+use term::{Cell, CellAttributes, KeyCode, KeyModifiers, Line};
+use unicode_width::UnicodeWidthChar;
+
+/// Returns the candidates for completing the text that is passed in
+pub type Completer = Box<dyn Fn(&str) -> Vec<String>>;
+
+/// What the overlay should do after a key was passed to the editor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EditorAction {
+    /// Keep editing
+    Continue,
+    /// Enter was pressed; this holds the text that was entered
+    Accept(String),
+    /// The edit was abandoned with Escape or CTRL-g
+    Cancel,
+}
+
+pub struct LineEditor {
+    text: Vec<char>,
+    /// The position of the cursor, in chars
+    cursor: usize,
+    /// The previously entered lines, oldest first
+    history: Vec<String>,
+    /// The entry of `history` being shown, or `history.len()` for
+    /// the line being composed
+    history_pos: usize,
+    /// The line being composed, kept while browsing the history
+    draft: Vec<char>,
+    /// The text most recently removed by a kill command
+    kill_buffer: Vec<char>,
+    completer: Option<Completer>,
+}
+
+impl LineEditor {
+    /// Creates an editor holding `text`, whose history holds the
+    /// previously entered lines, oldest first
+    pub fn new(text: &str, history: Vec<String>) -> Self {
+        let text: Vec<char> = text.chars().collect();
+        Self {
+            cursor: text.len(),
+            text,
+            history_pos: history.len(),
+            history,
+            draft: vec![],
+            kill_buffer: vec![],
+            completer: None,
+        }
+    }
+
+    /// Sets the function that Tab uses to complete the text
+    pub fn with_completer(mut self, completer: Completer) -> Self {
+        self.completer = Some(completer);
+        self
+    }
+
+    pub fn text(&self) -> String {
+        self.text.iter().collect()
+    }
+
+    fn set_text(&mut self, text: Vec<char>) {
+        self.cursor = text.len();
+        self.text = text;
+    }
+
+    /// Returns the position of the start of the word before the cursor
+    fn word_start(&self) -> usize {
+        let mut pos = self.cursor;
+        while pos > 0 && !self.text[pos - 1].is_alphanumeric() {
+            pos -= 1;
+        }
+        while pos > 0 && self.text[pos - 1].is_alphanumeric() {
+            pos -= 1;
+        }
+        pos
+    }
+
+    /// Returns the position of the end of the word after the cursor
+    fn word_end(&self) -> usize {
+        let mut pos = self.cursor;
+        while pos < self.text.len() && !self.text[pos].is_alphanumeric() {
+            pos += 1;
+        }
+        while pos < self.text.len() && self.text[pos].is_alphanumeric() {
+            pos += 1;
+        }
+        pos
+    }
+
+    fn delete_backward(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.text.remove(self.cursor);
+        }
+    }
+
+    fn delete_forward(&mut self) {
+        if self.cursor < self.text.len() {
+            self.text.remove(self.cursor);
+        }
+    }
+
+    /// Removes the text between the cursor and `pos`, keeping it to
+    /// be yanked later
+    fn kill_to(&mut self, pos: usize) {
+        let range = if pos < self.cursor {
+            pos..self.cursor
+        } else {
+            self.cursor..pos
+        };
+        self.cursor = range.start;
+        self.kill_buffer = self.text.drain(range).collect();
+    }
+
+    /// Moves through the history by `delta` entries, where negative
+    /// values go back to older entries
+    fn browse_history(&mut self, delta: isize) {
+        let pos = self.history_pos as isize + delta;
+        if pos < 0 || pos > self.history.len() as isize {
+            return;
+        }
+        if self.history_pos == self.history.len() {
+            self.draft = self.text.clone();
+        }
+        self.history_pos = pos as usize;
+        let text = match self.history.get(self.history_pos) {
+            Some(entry) => entry.chars().collect(),
+            None => self.draft.clone(),
+        };
+        self.set_text(text);
+    }
+
+    /// Replaces the text with the only completion, or extends it to
+    /// the longest prefix shared by all of the completions
+    fn complete(&mut self) {
+        let candidates: Vec<Vec<char>> = match self.completer.as_ref() {
+            Some(completer) => completer(&self.text())
+                .iter()
+                .map(|candidate| candidate.chars().collect())
+                .collect(),
+            None => return,
+        };
+        let mut prefix = match candidates.first() {
+            Some(first) => first.clone(),
+            None => return,
+        };
+        if candidates.len() == 1 {
+            self.set_text(prefix);
+            return;
+        }
+        for candidate in &candidates[1..] {
+            let shared = prefix
+                .iter()
+                .zip(candidate.iter())
+                .take_while(|(a, b)| a == b)
+                .count();
+            prefix.truncate(shared);
+        }
+        if prefix.len() > self.text.len() && prefix.starts_with(&self.text) {
+            self.set_text(prefix);
+        }
+    }
+
+    /// Applies a key press to the line
+    pub fn key(&mut self, key: KeyCode, mods: KeyModifiers) -> EditorAction {
+        let ctrl = mods.contains(KeyModifiers::CTRL);
+        let alt = mods.contains(KeyModifiers::ALT);
+        match key {
+            KeyCode::Enter => return EditorAction::Accept(self.text()),
+            KeyCode::Escape => return EditorAction::Cancel,
+            KeyCode::Char('g') if ctrl => return EditorAction::Cancel,
+
+            KeyCode::LeftArrow => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Char('b') if ctrl => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::RightArrow => self.cursor = (self.cursor + 1).min(self.text.len()),
+            KeyCode::Char('f') if ctrl => self.cursor = (self.cursor + 1).min(self.text.len()),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::Char('a') if ctrl => self.cursor = 0,
+            KeyCode::End => self.cursor = self.text.len(),
+            KeyCode::Char('e') if ctrl => self.cursor = self.text.len(),
+            KeyCode::Char('b') if alt => self.cursor = self.word_start(),
+            KeyCode::Char('f') if alt => self.cursor = self.word_end(),
+
+            KeyCode::Backspace => self.delete_backward(),
+            KeyCode::Char('h') if ctrl => self.delete_backward(),
+            KeyCode::Delete => self.delete_forward(),
+            KeyCode::Char('d') if ctrl => self.delete_forward(),
+            KeyCode::Char('k') if ctrl => self.kill_to(self.text.len()),
+            KeyCode::Char('u') if ctrl => self.kill_to(0),
+            KeyCode::Char('w') if ctrl => self.kill_to(self.word_start()),
+            KeyCode::Char('d') if alt => self.kill_to(self.word_end()),
+            KeyCode::Char('y') if ctrl => {
+                let yanked = self.kill_buffer.clone();
+                let count = yanked.len();
+                self.text.splice(self.cursor..self.cursor, yanked);
+                self.cursor += count;
+            }
+
+            KeyCode::UpArrow => self.browse_history(-1),
+            KeyCode::Char('p') if ctrl => self.browse_history(-1),
+            KeyCode::DownArrow => self.browse_history(1),
+            KeyCode::Char('n') if ctrl => self.browse_history(1),
+
+            KeyCode::Tab => self.complete(),
+
+            KeyCode::Char(c) if !ctrl && !alt && !c.is_control() => {
+                self.text.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            _ => {}
+        }
+        EditorAction::Continue
+    }
+
+    /// Renders `prompt` followed by the text as a line `width` cells
+    /// wide, with the cursor shown in reverse video.  The text is
+    /// scrolled to keep the cursor visible.
+    pub fn line(&self, prompt: &str, width: usize, attrs: &CellAttributes) -> Line {
+        let char_width = |c: &char| c.width().unwrap_or(1).max(1);
+        let prompt_width: usize = prompt.chars().map(|c| char_width(&c)).sum();
+        let room = width.saturating_sub(prompt_width + 1);
+
+        // Scroll the start of the text along until the text up to the
+        // cursor fits alongside the prompt
+        let mut start = 0;
+        let mut before_cursor: usize = self.text[..self.cursor].iter().map(char_width).sum();
+        while before_cursor > room && start < self.cursor {
+            before_cursor -= char_width(&self.text[start]);
+            start += 1;
+        }
+
+        let visible: String = self.text[start..].iter().collect();
+        let mut line = text_line(&format!("{}{}", prompt, visible), width, attrs);
+
+        let cursor_col = prompt_width + before_cursor;
+        if cursor_col < width {
+            let mut cursor_attrs = attrs.clone();
+            cursor_attrs.set_reverse(!attrs.reverse());
+            let under_cursor = self.text.get(self.cursor).cloned().unwrap_or(' ');
+            line.set_cell(cursor_col, Cell::new(under_cursor, cursor_attrs));
+        }
+        line
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn type_text(editor: &mut LineEditor, text: &str) {
+        for c in text.chars() {
+            editor.key(KeyCode::Char(c), KeyModifiers::NONE);
+        }
+    }
+
+    #[test]
+    fn emacs_editing() {
+        let mut editor = LineEditor::new("", vec![]);
+        type_text(&mut editor, "hello world");
+        editor.key(KeyCode::Char('b'), KeyModifiers::ALT);
+        editor.key(KeyCode::Char('k'), KeyModifiers::CTRL);
+        assert_eq!(editor.text(), "hello ");
+        editor.key(KeyCode::Char('a'), KeyModifiers::CTRL);
+        editor.key(KeyCode::Char('y'), KeyModifiers::CTRL);
+        type_text(&mut editor, " ");
+        assert_eq!(editor.text(), "world hello ");
+        editor.key(KeyCode::Char('e'), KeyModifiers::CTRL);
+        editor.key(KeyCode::Char('w'), KeyModifiers::CTRL);
+        editor.key(KeyCode::Backspace, KeyModifiers::NONE);
+        assert_eq!(editor.text(), "world");
+        assert_eq!(
+            editor.key(KeyCode::Enter, KeyModifiers::NONE),
+            EditorAction::Accept("world".to_owned())
+        );
+        assert_eq!(
+            editor.key(KeyCode::Char('g'), KeyModifiers::CTRL),
+            EditorAction::Cancel
+        );
+    }
+
+    #[test]
+    fn history_keeps_draft() {
+        let mut editor = LineEditor::new("", vec!["one".to_owned(), "two".to_owned()]);
+        type_text(&mut editor, "thr");
+        editor.key(KeyCode::UpArrow, KeyModifiers::NONE);
+        assert_eq!(editor.text(), "two");
+        editor.key(KeyCode::Char('p'), KeyModifiers::CTRL);
+        editor.key(KeyCode::UpArrow, KeyModifiers::NONE);
+        assert_eq!(editor.text(), "one");
+        editor.key(KeyCode::DownArrow, KeyModifiers::NONE);
+        editor.key(KeyCode::DownArrow, KeyModifiers::NONE);
+        assert_eq!(editor.text(), "thr");
+    }
+
+    #[test]
+    fn completion() {
+        let words = vec!["build", "builder", "bundle"];
+        let mut editor = LineEditor::new("bu", vec![]).with_completer(Box::new(move |text| {
+            words
+                .iter()
+                .filter(|word| word.starts_with(text))
+                .map(|word| word.to_string())
+                .collect()
+        }));
+        editor.key(KeyCode::Tab, KeyModifiers::NONE);
+        assert_eq!(editor.text(), "bu");
+        type_text(&mut editor, "i");
+        editor.key(KeyCode::Tab, KeyModifiers::NONE);
+        assert_eq!(editor.text(), "build");
+        type_text(&mut editor, "e");
+        editor.key(KeyCode::Tab, KeyModifiers::NONE);
+        assert_eq!(editor.text(), "builder");
+    }
+
+    #[test]
+    fn line_scrolls_to_cursor() {
+        let editor = LineEditor::new("abcdefghij", vec![]);
+        let line = editor.line("> ", 8, &CellAttributes::default());
+        assert_eq!(line.as_str(), "> fghij ");
+        assert!(line.cells()[7].attrs().reverse());
+    }
+}
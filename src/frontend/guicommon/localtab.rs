@@ -1,11 +1,17 @@
+use crate::config::{Config, ExitBehavior};
 use crate::mux::domain::DomainId;
 use crate::mux::renderable::Renderable;
-use crate::mux::tab::{alloc_tab_id, Tab, TabId};
+use crate::mux::tab::{Tab, TabId};
+use crate::mux::Mux;
 use failure::Error;
-use portable_pty::{Child, MasterPty, PtySize};
-use std::cell::{RefCell, RefMut};
+use log::error;
+use portable_pty::{Child, ExitStatus, MasterPty, PtySize};
+use std::cell::{Cell, RefCell, RefMut};
+use std::sync::Arc;
 use term::color::ColorPalette;
-use term::{KeyCode, KeyModifiers, MouseEvent, Terminal, TerminalHost};
+use term::{write_paste, PASTE_CHUNK_SIZE};
+use term::{CopyModeAction, KeyCode, KeyModifiers, MouseEvent, Terminal, TerminalHost};
+use termwiz::hyperlink::Hyperlink;
 
 pub struct LocalTab {
     tab_id: TabId,
@@ -13,6 +19,13 @@ pub struct LocalTab {
     process: RefCell<Box<dyn Child>>,
     pty: RefCell<Box<dyn MasterPty>>,
     domain_id: DomainId,
+    /// Set when the process has exited and `exit_behavior` holds the
+    /// tab open; records how the process exited
+    held_exit: Cell<Option<ExitStatus>>,
+    /// Set when a key is pressed in a held tab
+    closed: Cell<bool>,
+    /// The title chosen by the user, if any
+    title: RefCell<Option<String>>,
 }
 
 impl Tab for LocalTab {
@@ -26,7 +39,24 @@ impl Tab for LocalTab {
     }
 
     fn is_dead(&self) -> bool {
-        if let Ok(None) = self.process.borrow_mut().try_wait() {
+        if self.held_exit.get().is_some() {
+            return self.closed.get();
+        }
+        let status = match self.process.borrow_mut().try_wait() {
+            Ok(None) => return false,
+            Ok(Some(status)) => Some(status),
+            Err(_) => None,
+        };
+        let exit_behavior = Mux::get().unwrap().config().exit_behavior;
+        let hold = match (exit_behavior.unwrap_or_default(), status) {
+            (ExitBehavior::Close, _) | (_, None) => false,
+            (ExitBehavior::Hold, _) => true,
+            (ExitBehavior::CloseOnCleanExit, Some(status)) => !status.success(),
+        };
+        if hold {
+            self.held_exit.set(status);
+            // Repaint so that the exit status is shown
+            self.terminal.borrow_mut().make_all_lines_dirty();
             false
         } else {
             log::error!("is_dead: {:?}", self.tab_id);
@@ -34,6 +64,10 @@ impl Tab for LocalTab {
         }
     }
 
+    fn held_exit(&self) -> Option<ExitStatus> {
+        self.held_exit.get()
+    }
+
     fn advance_bytes(&self, buf: &[u8], host: &mut dyn TerminalHost) {
         self.terminal.borrow_mut().advance_bytes(buf, host)
     }
@@ -42,7 +76,17 @@ impl Tab for LocalTab {
         self.terminal.borrow_mut().mouse_event(event, host)
     }
 
+    fn focus_changed(&self, focused: bool) -> Result<(), Error> {
+        self.terminal
+            .borrow_mut()
+            .focus_changed(focused, &mut *self.pty.borrow_mut())
+    }
+
     fn key_down(&self, key: KeyCode, mods: KeyModifiers) -> Result<(), Error> {
+        if self.held_exit.get().is_some() {
+            self.closed.set(true);
+            return Ok(());
+        }
         self.terminal
             .borrow_mut()
             .key_down(key, mods, &mut *self.pty.borrow_mut())
@@ -50,9 +94,12 @@ impl Tab for LocalTab {
 
     fn resize(&self, size: PtySize) -> Result<(), Error> {
         self.pty.borrow_mut().resize(size)?;
-        self.terminal
-            .borrow_mut()
-            .resize(size.rows as usize, size.cols as usize);
+        self.terminal.borrow_mut().resize(
+            size.rows as usize,
+            size.cols as usize,
+            size.pixel_width as usize,
+            size.pixel_height as usize,
+        );
         Ok(())
     }
 
@@ -64,39 +111,121 @@ impl Tab for LocalTab {
         self.pty.borrow_mut().try_clone_reader()
     }
 
+    #[cfg(unix)]
+    fn pollable_reader(&self) -> Result<Option<std::fs::File>, Error> {
+        use std::os::unix::io::FromRawFd;
+        let fd = match self.pty.borrow().as_raw_fd() {
+            Some(fd) => fd,
+            None => return Ok(None),
+        };
+        // The duplicate shares the open file of the pty, and remains
+        // valid for as long as the mux is reading it
+        let fd = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) };
+        if fd == -1 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(Some(unsafe { std::fs::File::from_raw_fd(fd) }))
+    }
+
     fn send_paste(&self, text: &str) -> Result<(), Error> {
-        self.terminal
-            .borrow_mut()
-            .send_paste(text, &mut *self.pty.borrow_mut())
+        let mut terminal = self.terminal.borrow_mut();
+        if text.len() <= PASTE_CHUNK_SIZE {
+            return terminal.send_paste(text, &mut *self.pty.borrow_mut());
+        }
+        // Writing a large paste takes as long as the program in the tab
+        // takes to read it, so it is written from its own thread to keep
+        // the gui responsive meanwhile
+        let mut writer = self.pty.borrow().try_clone_writer()?;
+        let bracketed = terminal.bracketed_paste_enabled();
+        let text = text.to_owned();
+        std::thread::spawn(move || {
+            if let Err(err) = write_paste(&text, bracketed, &mut writer) {
+                error!("failed to paste: {}", err);
+            }
+        });
+        Ok(())
     }
 
     fn get_title(&self) -> String {
-        self.terminal.borrow_mut().get_title().to_string()
+        match self.title.borrow().as_ref() {
+            Some(title) => title.clone(),
+            None => self.terminal.borrow_mut().get_title().to_string(),
+        }
+    }
+
+    fn set_title(&self, title: Option<String>) {
+        *self.title.borrow_mut() = title;
+    }
+
+    fn get_current_dir(&self) -> Option<String> {
+        self.terminal.borrow().get_current_dir().map(str::to_owned)
     }
 
     fn palette(&self) -> ColorPalette {
         self.terminal.borrow().palette().clone()
     }
 
+    fn set_palette(&self, palette: ColorPalette) {
+        self.terminal.borrow_mut().set_palette(palette)
+    }
+
     fn domain_id(&self) -> DomainId {
         self.domain_id
     }
+
+    fn copy_mode(&self, action: CopyModeAction) -> Result<Option<String>, Error> {
+        Ok(self.terminal.borrow_mut().copy_mode_action(action))
+    }
+
+    fn in_copy_mode(&self) -> bool {
+        self.terminal.borrow().in_copy_mode()
+    }
+
+    fn hyperlink_near_cursor(&self) -> Result<Option<Arc<Hyperlink>>, Error> {
+        Ok(self.terminal.borrow_mut().hyperlink_near_cursor())
+    }
+
+    fn selection_text(&self) -> Option<String> {
+        Some(self.terminal.borrow().get_selection_text())
+    }
+
+    fn config_was_reloaded(&self, config: &Config) {
+        let mut terminal = self.terminal.borrow_mut();
+        terminal.set_hyperlink_rules(config.hyperlink_rules.clone());
+        terminal.set_implicit_link_click_modifiers(config.implicit_link_click_modifiers);
+        terminal.set_word_boundary(
+            config
+                .selection_word_boundary
+                .as_ref()
+                .map(String::as_str)
+                .unwrap_or(term::DEFAULT_WORD_BOUNDARY),
+        );
+        terminal.set_scrollback_size(config.scrollback_lines());
+        terminal.set_uncompressed_scrollback(Some(config.uncompressed_scrollback_lines()));
+        terminal.set_csi_u_key_encoding(config.enable_csi_u_key_encoding.unwrap_or(false));
+    }
 }
 
 impl LocalTab {
+    /// Wraps a spawned terminal as the tab `tab_id`, which the caller
+    /// allocates with `alloc_tab_id` so that it can be passed to the
+    /// spawned process
     pub fn new(
+        tab_id: TabId,
         terminal: Terminal,
         process: Box<dyn Child>,
         pty: Box<dyn MasterPty>,
         domain_id: DomainId,
     ) -> Self {
-        let tab_id = alloc_tab_id();
         Self {
             tab_id,
             terminal: RefCell::new(terminal),
             process: RefCell::new(process),
             pty: RefCell::new(pty),
             domain_id,
+            held_exit: Cell::new(None),
+            closed: Cell::new(false),
+            title: RefCell::new(None),
         }
     }
 }
@@ -104,7 +233,10 @@ impl LocalTab {
 impl Drop for LocalTab {
     fn drop(&mut self) {
         // Avoid lingering zombies
-        self.process.borrow_mut().kill().ok();
-        self.process.borrow_mut().wait().ok();
+        let mut process = self.process.borrow_mut();
+        if let Ok(None) = process.try_wait() {
+            process.kill().ok();
+        }
+        process.wait().ok();
     }
 }
@@ -0,0 +1,308 @@
+//! The lock screen, which hides the contents of every window and
+//! swallows their input until it is dismissed with Enter or, if
+//! configured, with the password of the user.
+use crate::frontend::gui_executor;
+use crate::frontend::guicommon::pastehistory::text_line;
+use crate::mux::Mux;
+use failure::Fallible;
+use log::error;
+use promise::Future;
+use std::cell::RefCell;
+use std::thread;
+use term::{CellAttributes, KeyCode, KeyModifiers, Line};
+
+thread_local! {
+    /// Set while the windows are locked
+    static LOCK_SCREEN: RefCell<Option<LockScreen>> = RefCell::new(None);
+}
+
+/// What to do after a key was pressed on the lock screen
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LockOutcome {
+    /// Stay locked
+    Pending,
+    /// Dismiss the lock screen
+    Unlock,
+    /// Check the password, which can take a while, and then pass the
+    /// result to `LockScreen::checked`
+    Check(String),
+}
+
+/// The state of the lock screen
+#[derive(Debug, Default)]
+pub struct LockScreen {
+    /// Whether unlocking requires the password, rather than Enter
+    needs_password: bool,
+    /// The password typed so far
+    password: String,
+    /// Set while the password is being checked; keys are ignored
+    /// until it has been
+    checking: bool,
+    /// Shown below the prompt, such as after a wrong password
+    message: Option<String>,
+}
+
+impl LockScreen {
+    pub fn new(needs_password: bool) -> Self {
+        Self {
+            needs_password,
+            ..Default::default()
+        }
+    }
+
+    /// Processes a key press.  Printable characters are collected as
+    /// the password, Backspace erases the last one, Escape discards
+    /// them and Enter attempts to unlock, asking for the password to
+    /// be checked if one is needed.
+    pub fn key(&mut self, key: KeyCode, mods: KeyModifiers) -> LockOutcome {
+        if self.checking {
+            return LockOutcome::Pending;
+        }
+        match key {
+            KeyCode::Enter if !self.needs_password => return LockOutcome::Unlock,
+            KeyCode::Enter => {
+                self.checking = true;
+                self.message = Some("Checking the password...".to_owned());
+                let password = std::mem::replace(&mut self.password, String::new());
+                return LockOutcome::Check(password);
+            }
+            KeyCode::Char(c)
+                if self.needs_password
+                    && !c.is_control()
+                    && !mods.intersects(KeyModifiers::CTRL | KeyModifiers::SUPER) =>
+            {
+                self.password.push(c);
+                self.message = None;
+            }
+            KeyCode::Backspace => {
+                self.password.pop();
+            }
+            KeyCode::Escape => {
+                self.password.clear();
+                self.message = None;
+            }
+            _ => {}
+        }
+        LockOutcome::Pending
+    }
+
+    /// Takes the result of checking the password that was typed.
+    /// Returns true if the screen was unlocked.
+    pub fn checked(&mut self, result: Fallible<bool>) -> bool {
+        self.checking = false;
+        self.message = None;
+        match result {
+            Ok(true) => return true,
+            Ok(false) => self.message = Some("Incorrect password".to_owned()),
+            Err(err) => {
+                error!("unable to check the password: {}", err);
+                self.message = Some(format!("Unable to check the password: {}", err));
+            }
+        }
+        false
+    }
+
+    /// Renders the lock screen as `rows` lines, each `width` cells
+    /// wide, with the prompt in the middle.  The password is shown
+    /// as a row of asterisks.
+    pub fn lines(&self, width: usize, rows: usize) -> Vec<Line> {
+        let attrs = CellAttributes::default();
+        let prompt = if self.needs_password {
+            format!(
+                "Locked; enter your password to unlock: {}",
+                "*".repeat(self.password.chars().count())
+            )
+        } else {
+            "Locked; press Enter to unlock".to_owned()
+        };
+        let centered = |text: &str| {
+            let pad = width.saturating_sub(text.chars().count()) / 2;
+            text_line(&format!("{}{}", " ".repeat(pad), text), width, &attrs)
+        };
+
+        let middle = rows / 2;
+        (0..rows)
+            .map(|row| {
+                if row == middle {
+                    centered(&prompt)
+                } else if row == middle + 1 {
+                    centered(self.message.as_ref().map(String::as_str).unwrap_or(""))
+                } else {
+                    text_line("", width, &attrs)
+                }
+            })
+            .collect()
+    }
+}
+
+/// Locks every window, unless they are already locked
+pub fn lock() {
+    let needs_password = Mux::get()
+        .unwrap()
+        .config()
+        .lock_screen_requires_password
+        .unwrap_or(false);
+    let needs_password = if needs_password && !cfg!(all(unix, feature = "pam")) {
+        error!(
+            "lock_screen_requires_password needs the pam feature; \
+             the lock screen will be dismissed with Enter instead"
+        );
+        false
+    } else {
+        needs_password
+    };
+    LOCK_SCREEN.with(|lock| {
+        let mut lock = lock.borrow_mut();
+        if lock.is_none() {
+            *lock = Some(LockScreen::new(needs_password));
+        }
+    });
+    repaint_all_windows();
+}
+
+pub fn is_locked() -> bool {
+    LOCK_SCREEN.with(|lock| lock.borrow().is_some())
+}
+
+/// Renders the lock screen if the windows are locked
+pub fn lines(width: usize, rows: usize) -> Option<Vec<Line>> {
+    LOCK_SCREEN.with(|lock| {
+        lock.borrow()
+            .as_ref()
+            .map(|screen| screen.lines(width, rows))
+    })
+}
+
+/// Passes a key press to the lock screen, unlocking the windows if
+/// it confirms the unlock.  Returns false if they aren't locked.
+pub fn process_key(key: KeyCode, mods: KeyModifiers) -> bool {
+    let outcome = LOCK_SCREEN.with(|lock| {
+        let mut lock = lock.borrow_mut();
+        let outcome = match lock.as_mut() {
+            Some(screen) => screen.key(key, mods),
+            None => return None,
+        };
+        if outcome == LockOutcome::Unlock {
+            *lock = None;
+        }
+        Some(outcome)
+    });
+    match outcome {
+        Some(outcome) => {
+            if let LockOutcome::Check(password) = outcome {
+                check_password(password);
+            }
+            repaint_all_windows();
+            true
+        }
+        None => false,
+    }
+}
+
+/// Checks the password on a thread of its own, since PAM can take
+/// seconds to reject a password and the windows need to keep painting
+/// in the meantime, and then hands the result to the lock screen on
+/// the gui thread
+fn check_password(password: String) {
+    let executor = gui_executor().expect("gui_executor was not registered yet!?");
+    thread::spawn(move || {
+        let result = authenticate(&password);
+        Future::with_executor(executor, move || {
+            LOCK_SCREEN.with(|lock| {
+                let mut lock = lock.borrow_mut();
+                let unlocked = match lock.as_mut() {
+                    Some(screen) => screen.checked(result),
+                    None => false,
+                };
+                if unlocked {
+                    *lock = None;
+                }
+            });
+            repaint_all_windows();
+            Ok(())
+        });
+    });
+}
+
+/// Marks the active tab of each window as needing to be painted,
+/// so that the lock screen is shown or removed promptly
+fn repaint_all_windows() {
+    let mux = Mux::get().unwrap();
+    for window_id in mux.iter_windows() {
+        if let Some(tab) = mux.get_active_tab_for_window(window_id) {
+            tab.renderer().make_all_lines_dirty();
+        }
+    }
+}
+
+#[cfg(all(unix, feature = "pam"))]
+fn authenticate(password: &str) -> Fallible<bool> {
+    super::pam::authenticate(password)
+}
+
+#[cfg(not(all(unix, feature = "pam")))]
+fn authenticate(_password: &str) -> Fallible<bool> {
+    failure::bail!("wezterm was built without the pam feature")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn enter_unlocks_without_password() {
+        let mut screen = LockScreen::new(false);
+        assert_eq!(
+            screen.key(KeyCode::Char('x'), KeyModifiers::NONE),
+            LockOutcome::Pending
+        );
+        assert_eq!(
+            screen.key(KeyCode::Enter, KeyModifiers::NONE),
+            LockOutcome::Unlock
+        );
+    }
+
+    #[test]
+    fn password_is_checked() {
+        let mut screen = LockScreen::new(true);
+        for c in "hunter3".chars() {
+            screen.key(KeyCode::Char(c), KeyModifiers::NONE);
+        }
+        assert_eq!(
+            screen.key(KeyCode::Enter, KeyModifiers::NONE),
+            LockOutcome::Check("hunter3".to_owned())
+        );
+        assert!(screen.password.is_empty());
+
+        // Keys are ignored until the check is done
+        assert_eq!(
+            screen.key(KeyCode::Char('x'), KeyModifiers::NONE),
+            LockOutcome::Pending
+        );
+        assert!(screen.password.is_empty());
+        assert!(!screen.checked(Ok(false)));
+        assert_eq!(screen.message.as_ref().unwrap(), "Incorrect password");
+
+        for c in "hunter33".chars() {
+            screen.key(KeyCode::Char(c), KeyModifiers::NONE);
+        }
+        screen.key(KeyCode::Backspace, KeyModifiers::NONE);
+        screen.key(KeyCode::Backspace, KeyModifiers::NONE);
+        screen.key(KeyCode::Char('2'), KeyModifiers::NONE);
+        assert!(screen.message.is_none());
+        assert_eq!(
+            screen.key(KeyCode::Enter, KeyModifiers::NONE),
+            LockOutcome::Check("hunter2".to_owned())
+        );
+        assert!(screen.checked(Ok(true)));
+    }
+
+    #[test]
+    fn prompt_is_centered() {
+        let screen = LockScreen::new(false);
+        let lines = screen.lines(40, 5);
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[2].as_str().trim(), "Locked; press Enter to unlock");
+        assert!(lines[0].as_str().trim().is_empty());
+    }
+}
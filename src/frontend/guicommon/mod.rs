@@ -1,3 +1,21 @@
+pub mod attention;
+pub mod confirm;
+pub mod hitregion;
 pub mod host;
+pub mod input;
+pub mod ipc;
+pub mod launcher;
+pub mod lineeditor;
 pub mod localtab;
+pub mod lockscreen;
+pub mod mouse;
+#[cfg(all(unix, feature = "pam"))]
+mod pam;
+pub mod pastehistory;
+pub mod picker;
+pub mod quickselect;
+pub mod scrollbar;
+pub mod search;
+pub mod tabbar;
+pub mod toast;
 pub mod window;
@@ -0,0 +1,142 @@
+//! Translates the pixel based mouse input of the gui frontends into
+//! the cell based MouseEvents understood by the terminal model.
+//! Each frontend feeds its raw input through a MouseTranslator so
+//! that the same input produces the same events regardless of which
+//! windowing system delivered it.
+use term::{KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
+
+#[derive(Debug, Default)]
+pub struct MouseTranslator {
+    /// The cell that the mouse pointer was last seen over
+    last_cell: Option<(usize, i64)>,
+    /// Where the mouse pointer was last seen, in pixels, which may be
+    /// outside of the window while a button is held
+    last_pixel: Option<(i64, i64)>,
+}
+
+impl MouseTranslator {
+    fn cell_for_pixels(x: i64, y: i64, cell_width: usize, cell_height: usize) -> (usize, i64) {
+        // Pointer grabs can report positions to the left of or above
+        // the window; treat those as being in the first row or column
+        (
+            x.max(0) as usize / cell_width.max(1),
+            y.max(0) / cell_height.max(1) as i64,
+        )
+    }
+
+    /// Record the pointer position without generating an event.
+    /// This is used for button events that carry their own position.
+    pub fn set_position(&mut self, x: i64, y: i64, cell_width: usize, cell_height: usize) {
+        self.last_pixel = Some((x, y));
+        self.last_cell = Some(Self::cell_for_pixels(x, y, cell_width, cell_height));
+    }
+
+    /// Returns where the pointer was last seen, in pixels relative to
+    /// the top left of the window
+    pub fn last_pixel(&self) -> Option<(i64, i64)> {
+        self.last_pixel
+    }
+
+    /// Translate pointer motion to the pixel position `x`, `y`.
+    /// Motion is only reported when the pointer enters a different
+    /// cell, as the terminal has no finer resolution than that.
+    pub fn motion(
+        &mut self,
+        x: i64,
+        y: i64,
+        cell_width: usize,
+        cell_height: usize,
+        modifiers: KeyModifiers,
+    ) -> Option<MouseEvent> {
+        self.last_pixel = Some((x, y));
+        let cell = Self::cell_for_pixels(x, y, cell_width, cell_height);
+        if self.last_cell == Some(cell) {
+            return None;
+        }
+        self.last_cell = Some(cell);
+        Some(MouseEvent {
+            kind: MouseEventKind::Move,
+            button: MouseButton::None,
+            x: cell.0,
+            y: cell.1,
+            modifiers,
+        })
+    }
+
+    /// Translate a button press or release at the last known position.
+    /// The wheel "buttons" have no meaningful release; some systems
+    /// (such as X11) report one, but it is dropped here so that all
+    /// frontends report the wheel as a series of presses.
+    pub fn button(
+        &mut self,
+        kind: MouseEventKind,
+        button: MouseButton,
+        modifiers: KeyModifiers,
+    ) -> Option<MouseEvent> {
+        let is_wheel = button == MouseButton::WheelUp || button == MouseButton::WheelDown;
+        if kind == MouseEventKind::Release && is_wheel {
+            return None;
+        }
+        let (x, y) = self.last_cell.unwrap_or((0, 0));
+        Some(MouseEvent {
+            kind,
+            button,
+            x,
+            y,
+            modifiers,
+        })
+    }
+
+    /// Translate a vertical scroll of `lines` (positive is up, and
+    /// fractional amounts are rounded away from zero) into a wheel
+    /// press for each line.
+    pub fn wheel(&mut self, lines: f64, modifiers: KeyModifiers) -> Vec<MouseEvent> {
+        let button = if lines > 0.0 {
+            MouseButton::WheelUp
+        } else if lines < 0.0 {
+            MouseButton::WheelDown
+        } else {
+            return vec![];
+        };
+        (0..lines.abs().ceil() as usize)
+            .filter_map(|_| self.button(MouseEventKind::Press, button, modifiers))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn motion_is_per_cell() {
+        let mut mouse = MouseTranslator::default();
+        let event = mouse.motion(25, 35, 10, 20, KeyModifiers::NONE).unwrap();
+        assert_eq!((event.x, event.y), (2, 1));
+        assert!(mouse.motion(29, 39, 10, 20, KeyModifiers::NONE).is_none());
+        let event = mouse.motion(-5, 40, 10, 20, KeyModifiers::NONE).unwrap();
+        assert_eq!((event.x, event.y), (0, 2));
+        assert_eq!(mouse.last_pixel(), Some((-5, 40)));
+        assert!(mouse.motion(-9, 41, 10, 20, KeyModifiers::NONE).is_none());
+        assert_eq!(mouse.last_pixel(), Some((-9, 41)));
+    }
+
+    #[test]
+    fn wheel_has_no_release() {
+        let mut mouse = MouseTranslator::default();
+        mouse.set_position(15, 5, 10, 20);
+        let events = mouse.wheel(-1.5, KeyModifiers::CTRL);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].button, MouseButton::WheelDown);
+        assert_eq!(events[0].kind, MouseEventKind::Press);
+        assert_eq!(events[0].x, 1);
+        assert_eq!(events[0].modifiers, KeyModifiers::CTRL);
+        assert!(mouse
+            .button(
+                MouseEventKind::Release,
+                MouseButton::WheelUp,
+                KeyModifiers::NONE
+            )
+            .is_none());
+    }
+}
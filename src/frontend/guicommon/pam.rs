@@ -0,0 +1,122 @@
+//! Checks the password of the current user with PAM, for unlocking
+//! the lock screen
+use failure::{bail, Fallible};
+use libc::{c_char, c_int, c_void};
+use std::ffi::{CStr, CString};
+use std::ptr;
+
+/// The PAM service whose configuration is used to check the password
+const SERVICE: &str = "login";
+
+const PAM_SUCCESS: c_int = 0;
+const PAM_BUF_ERR: c_int = 5;
+const PAM_CONV_ERR: c_int = 19;
+const PAM_AUTH_ERR: c_int = 7;
+const PAM_PROMPT_ECHO_OFF: c_int = 1;
+const PAM_PROMPT_ECHO_ON: c_int = 2;
+
+#[repr(C)]
+struct PamMessage {
+    msg_style: c_int,
+    msg: *const c_char,
+}
+
+#[repr(C)]
+struct PamResponse {
+    resp: *mut c_char,
+    resp_retcode: c_int,
+}
+
+#[repr(C)]
+struct PamConv {
+    conv: extern "C" fn(
+        num_msg: c_int,
+        msg: *mut *const PamMessage,
+        resp: *mut *mut PamResponse,
+        appdata_ptr: *mut c_void,
+    ) -> c_int,
+    appdata_ptr: *mut c_void,
+}
+
+#[link(name = "pam")]
+extern "C" {
+    fn pam_start(
+        service_name: *const c_char,
+        user: *const c_char,
+        pam_conversation: *const PamConv,
+        pamh: *mut *mut c_void,
+    ) -> c_int;
+    fn pam_authenticate(pamh: *mut c_void, flags: c_int) -> c_int;
+    fn pam_end(pamh: *mut c_void, pam_status: c_int) -> c_int;
+}
+
+/// Answers the prompts of PAM with the password, which is passed
+/// as the application data
+extern "C" fn conversation(
+    num_msg: c_int,
+    msg: *mut *const PamMessage,
+    resp: *mut *mut PamResponse,
+    appdata_ptr: *mut c_void,
+) -> c_int {
+    if num_msg <= 0 {
+        return PAM_CONV_ERR;
+    }
+    unsafe {
+        // PAM frees the responses, so they are allocated with malloc
+        let responses =
+            libc::calloc(num_msg as usize, std::mem::size_of::<PamResponse>()) as *mut PamResponse;
+        if responses.is_null() {
+            return PAM_BUF_ERR;
+        }
+        let password = appdata_ptr as *const c_char;
+        for idx in 0..num_msg as usize {
+            // Linux-PAM passes an array of pointers to the messages
+            let message = &**msg.add(idx);
+            match message.msg_style {
+                PAM_PROMPT_ECHO_OFF | PAM_PROMPT_ECHO_ON => {
+                    (*responses.add(idx)).resp = libc::strdup(password);
+                }
+                _ => {}
+            }
+        }
+        *resp = responses;
+    }
+    PAM_SUCCESS
+}
+
+/// Returns the name of the user that runs wezterm
+fn user_name() -> Fallible<CString> {
+    unsafe {
+        let passwd = libc::getpwuid(libc::getuid());
+        if passwd.is_null() {
+            bail!("unable to find the name of the current user");
+        }
+        Ok(CStr::from_ptr((*passwd).pw_name).to_owned())
+    }
+}
+
+/// Returns true if `password` is the password of the current user
+pub fn authenticate(password: &str) -> Fallible<bool> {
+    let service = CString::new(SERVICE)?;
+    let user = user_name()?;
+    let password = CString::new(password)?;
+    let conv = PamConv {
+        conv: conversation,
+        appdata_ptr: password.as_ptr() as *mut c_void,
+    };
+
+    unsafe {
+        let mut pamh = ptr::null_mut();
+        let status = pam_start(service.as_ptr(), user.as_ptr(), &conv, &mut pamh);
+        if status != PAM_SUCCESS {
+            bail!("pam_start failed with status {}", status);
+        }
+        let status = pam_authenticate(pamh, 0);
+        pam_end(pamh, status);
+        match status {
+            PAM_SUCCESS => Ok(true),
+            PAM_AUTH_ERR => Ok(false),
+            status => bail!("pam_authenticate failed with status {}", status),
+        }
+    }
+}
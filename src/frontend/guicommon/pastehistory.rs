@@ -0,0 +1,114 @@
+//! Keeps a history of the text recently copied to the clipboard and
+//! computes the overlay that is rendered over the terminal when picking
+//! an older entry to paste.
+use crate::frontend::guicommon::picker::{Picker, PickerEntry};
+use std::collections::VecDeque;
+use term::{Cell, CellAttributes, Line};
+
+/// The number of entries kept when `paste_history_size` is not configured
+pub const DEFAULT_PASTE_HISTORY_SIZE: usize = 16;
+
+/// The most recently copied text, most recent first
+#[derive(Debug, Default)]
+pub struct PasteHistory {
+    entries: VecDeque<String>,
+}
+
+impl PasteHistory {
+    /// Record `text` as the most recent entry, keeping at most `depth`
+    /// entries.  Copying text that is already present moves it to the
+    /// front rather than adding a duplicate.
+    pub fn push(&mut self, text: &str, depth: usize) {
+        if text.is_empty() {
+            return;
+        }
+        self.entries.retain(|entry| entry != text);
+        self.entries.push_front(text.to_owned());
+        self.entries.truncate(depth);
+    }
+
+    pub fn entries(&self) -> Vec<String> {
+        self.entries.iter().cloned().collect()
+    }
+}
+
+/// The overlay used to pick an entry from the history
+pub type PastePicker = Picker<String>;
+
+pub fn new_paste_picker(entries: Vec<String>) -> PastePicker {
+    Picker::new(
+        " Paste history: Up/Down to choose, Enter to paste, Esc to cancel",
+        entries,
+    )
+}
+
+/// Each entry shows its first line, followed by `...` if it has more
+impl PickerEntry for String {
+    fn label(&self) -> String {
+        let mut preview: String = self
+            .lines()
+            .next()
+            .unwrap_or("")
+            .chars()
+            .map(|c| if c.is_control() { ' ' } else { c })
+            .collect();
+        if self.trim_end().contains('\n') {
+            preview.push_str(" ...");
+        }
+        preview
+    }
+}
+
+/// Produce a line `width` cells wide holding `text`, truncated to fit
+pub fn text_line(text: &str, width: usize, attrs: &CellAttributes) -> Line {
+    let mut line = Line::with_width(width);
+    for x in 0..width {
+        line.set_cell(x, Cell::new(' ', attrs.clone()));
+    }
+    let text = Line::from_text(text, attrs);
+    let mut col = 0;
+    for cell in text.cells() {
+        let cell_width = cell.width().max(1);
+        if col + cell_width > width {
+            break;
+        }
+        line.set_cell(col, cell.clone());
+        col += cell_width;
+    }
+    line
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn history_depth_and_dedup() {
+        let mut history = PasteHistory::default();
+        history.push("one", 3);
+        history.push("two", 3);
+        history.push("", 3);
+        history.push("three", 3);
+        history.push("one", 3);
+        history.push("four", 3);
+        assert_eq!(history.entries(), vec!["four", "one", "three"]);
+    }
+
+    #[test]
+    fn picker_scrolls_to_selection() {
+        let entries: Vec<String> = (0..5).map(|n| format!("entry{}", n)).collect();
+        let mut picker = new_paste_picker(entries);
+        picker.move_selection(-1);
+        assert_eq!(picker.selected_entry().unwrap(), "entry0");
+        picker.move_selection(10);
+        assert_eq!(picker.selected_entry().unwrap(), "entry4");
+        assert!(!picker.select(5));
+
+        let lines = picker.lines(12, 3);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[1].as_str(), " 4: entry3  ");
+        assert_eq!(lines[2].as_str(), " 5: entry4  ");
+        assert_eq!("one\ntwo\n".to_owned().label(), "one ...");
+        assert_eq!("one\n".to_owned().label(), "one");
+    }
+}
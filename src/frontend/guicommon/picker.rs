@@ -0,0 +1,151 @@
+//! A list of entries shown over the terminal from which one is picked
+//! with the keyboard, such as the paste history and the launcher menu.
+use crate::frontend::guicommon::pastehistory::text_line;
+use term::{CellAttributes, KeyCode, Line};
+
+/// An entry that can be shown in a `Picker`
+pub trait PickerEntry {
+    /// The text shown for the entry, on a single line
+    fn label(&self) -> String;
+}
+
+/// What to do after a key was pressed in a picker
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PickerOutcome {
+    /// Keep showing the picker
+    Pending,
+    /// Dismiss the picker without choosing anything
+    Cancel,
+    /// Dismiss the picker and use the selected entry
+    Choose,
+}
+
+/// The state of a picker overlay
+#[derive(Debug, Clone)]
+pub struct Picker<T> {
+    /// Shown in reverse video above the entries
+    title: &'static str,
+    entries: Vec<T>,
+    selected: usize,
+}
+
+impl<T: PickerEntry> Picker<T> {
+    pub fn new(title: &'static str, entries: Vec<T>) -> Self {
+        Self {
+            title,
+            entries,
+            selected: 0,
+        }
+    }
+
+    /// Move the selection up (negative) or down (positive),
+    /// stopping at the first and last entries
+    pub fn move_selection(&mut self, delta: isize) {
+        let last = self.entries.len().saturating_sub(1) as isize;
+        self.selected = (self.selected as isize + delta).max(0).min(last) as usize;
+    }
+
+    /// Select the entry at `idx`, returning false if there is no such entry
+    pub fn select(&mut self, idx: usize) -> bool {
+        if idx < self.entries.len() {
+            self.selected = idx;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn selected_entry(&self) -> Option<&T> {
+        self.entries.get(self.selected)
+    }
+
+    /// Handles a key: the arrow keys (or j and k) move the selection,
+    /// Enter chooses the selected entry, 1-9 choose the corresponding
+    /// entry and Escape (or q) cancels
+    pub fn process_key(&mut self, key: KeyCode) -> PickerOutcome {
+        match key {
+            KeyCode::UpArrow | KeyCode::Char('k') => self.move_selection(-1),
+            KeyCode::DownArrow | KeyCode::Char('j') => self.move_selection(1),
+            KeyCode::Enter => return PickerOutcome::Choose,
+            KeyCode::Char(c @ '1'..='9') => {
+                if self.select(c as usize - '1' as usize) {
+                    return PickerOutcome::Choose;
+                }
+            }
+            KeyCode::Escape | KeyCode::Char('q') => return PickerOutcome::Cancel,
+            _ => {}
+        }
+        PickerOutcome::Pending
+    }
+
+    /// Render the picker as at most `max_rows` lines, each `width` cells
+    /// wide.  The selected entry is shown in reverse video and the list
+    /// scrolls to keep it visible.
+    pub fn lines(&self, width: usize, max_rows: usize) -> Vec<Line> {
+        let mut lines = vec![];
+        if max_rows == 0 {
+            return lines;
+        }
+
+        let mut header_attrs = CellAttributes::default();
+        header_attrs.set_reverse(true);
+        lines.push(text_line(self.title, width, &header_attrs));
+
+        let visible = max_rows - 1;
+        let first = if self.selected >= visible {
+            self.selected + 1 - visible
+        } else {
+            0
+        };
+
+        let normal = CellAttributes::default();
+        let mut selected = CellAttributes::default();
+        selected.set_reverse(true);
+
+        for (idx, entry) in self.entries.iter().enumerate().skip(first).take(visible) {
+            let text = if idx < 9 {
+                format!(" {}: {}", idx + 1, entry.label())
+            } else {
+                format!("    {}", entry.label())
+            };
+            let attrs = if idx == self.selected {
+                &selected
+            } else {
+                &normal
+            };
+            lines.push(text_line(&text, width, attrs));
+        }
+
+        lines
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    impl PickerEntry for &'static str {
+        fn label(&self) -> String {
+            self.to_string()
+        }
+    }
+
+    #[test]
+    fn keys() {
+        use PickerOutcome::{Cancel, Choose, Pending};
+        let mut picker = Picker::new(" Pick", vec!["one", "two", "three"]);
+        assert_eq!(picker.process_key(KeyCode::DownArrow), Pending);
+        assert_eq!(picker.process_key(KeyCode::Char('j')), Pending);
+        assert_eq!(picker.process_key(KeyCode::DownArrow), Pending);
+        assert_eq!(picker.selected_entry(), Some(&"three"));
+        assert_eq!(picker.process_key(KeyCode::Char('k')), Pending);
+        assert_eq!(picker.process_key(KeyCode::Enter), Choose);
+        assert_eq!(picker.selected_entry(), Some(&"two"));
+
+        // A number past the last entry does nothing
+        assert_eq!(picker.process_key(KeyCode::Char('4')), Pending);
+        assert_eq!(picker.process_key(KeyCode::Char('1')), Choose);
+        assert_eq!(picker.selected_entry(), Some(&"one"));
+        assert_eq!(picker.process_key(KeyCode::Escape), Cancel);
+    }
+}
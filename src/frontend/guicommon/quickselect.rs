@@ -0,0 +1,341 @@
+//! Quick select mode, which labels the text on the screen that matches
+//! `quick_select_patterns`, such as paths, URLs and git hashes, so that
+//! it can be copied by typing its label, or pasted by typing the label
+//! in upper case.
+use crate::mux::tab::{Tab, TabId};
+use crate::opengl::render::Overlay;
+use failure::{format_err, Fallible};
+use regex::Regex;
+use std::ops::Range;
+use term::{Cell, CellAttributes, Intensity, Line, SequenceNo, Underline};
+use termwiz::color::AnsiColor;
+
+/// The characters that labels are made of, easiest to type first
+const ALPHABET: &str = "asdfqwerzxcvjklmiuopghtybn";
+
+/// Compiles the patterns that quick select mode looks for
+pub fn compile_patterns(patterns: &[String]) -> Fallible<Vec<Regex>> {
+    patterns
+        .iter()
+        .map(|pattern| {
+            Regex::new(pattern)
+                .map_err(|e| format_err!("invalid quick select pattern {:?}: {}", pattern, e))
+        })
+        .collect()
+}
+
+/// Text on the screen that matched one of the patterns
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Match {
+    row: usize,
+    cols: Range<usize>,
+    text: String,
+    label: String,
+}
+
+/// What to do after a key was typed in quick select mode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The label isn't complete yet
+    Pending,
+    /// No label starts with what was typed
+    Cancel,
+    /// Copy the text to the clipboard
+    Copy(String),
+    /// Paste the text into the tab
+    Paste(String),
+}
+
+/// The screen that the labels were last checked against
+#[derive(Debug, Clone, Copy)]
+struct Scan {
+    tab_id: TabId,
+    dimensions: (usize, usize),
+    seqno: SequenceNo,
+}
+
+/// The state of quick select mode
+#[derive(Debug, Clone)]
+pub struct QuickSelect {
+    patterns: Vec<Regex>,
+    matches: Vec<Match>,
+    /// The start of a label that has been typed so far
+    typed: String,
+    /// Whether the text is to be pasted rather than copied, which the
+    /// case of the first character of the label decides
+    paste: bool,
+    scanned: Option<Scan>,
+}
+
+impl QuickSelect {
+    /// Finds the text on `lines`, the visible lines of the terminal,
+    /// that matches the patterns, and labels it
+    pub fn new<'a, I: Iterator<Item = &'a Line>>(lines: I, patterns: &[Regex]) -> Self {
+        Self {
+            patterns: patterns.to_vec(),
+            matches: label_matches(lines, patterns),
+            typed: String::new(),
+            paste: false,
+            scanned: None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.matches.is_empty()
+    }
+
+    /// Labels the screen of `tab` again if its text changed or moved
+    /// since it was labelled, or if a different tab is shown, which
+    /// forgets what was typed so far.  Returns true if the labels
+    /// changed.
+    pub fn refresh(&mut self, tab: &dyn Tab) -> bool {
+        let dimensions = tab.renderer().physical_dimensions();
+        if let Some(scan) = self.scanned {
+            if scan.tab_id == tab.tab_id()
+                && scan.dimensions == dimensions
+                && !tab.renderer().has_changes_since(scan.seqno)
+            {
+                return false;
+            }
+        }
+        let snapshot = tab.snapshot(None);
+        self.scanned = Some(Scan {
+            tab_id: tab.tab_id(),
+            dimensions,
+            seqno: snapshot.seqno,
+        });
+        self.relabel(snapshot.lines.iter().map(|(line, _)| &**line))
+    }
+
+    /// Labels `lines`, returning false if they hold the same matches
+    /// in the same places as before
+    fn relabel<'a, I: Iterator<Item = &'a Line>>(&mut self, lines: I) -> bool {
+        let matches = label_matches(lines, &self.patterns);
+        if matches == self.matches {
+            return false;
+        }
+        self.matches = matches;
+        self.typed.clear();
+        true
+    }
+
+    /// Handles a key typed while the labels are shown
+    pub fn type_char(&mut self, c: char) -> Outcome {
+        if self.typed.is_empty() {
+            self.paste = c.is_uppercase();
+        }
+        self.typed.extend(c.to_lowercase());
+        if let Some(m) = self.matches.iter().find(|m| m.label == self.typed) {
+            let text = m.text.clone();
+            return if self.paste {
+                Outcome::Paste(text)
+            } else {
+                Outcome::Copy(text)
+            };
+        }
+        if self
+            .matches
+            .iter()
+            .any(|m| m.label.starts_with(&self.typed))
+        {
+            Outcome::Pending
+        } else {
+            Outcome::Cancel
+        }
+    }
+
+    /// Forgets the last key that was typed
+    pub fn backspace(&mut self) {
+        self.typed.pop();
+    }
+
+    /// Returns the overlays that highlight the matches whose labels
+    /// start with what has been typed so far, each beginning with its
+    /// label
+    pub fn overlays(&self) -> Vec<Overlay> {
+        let mut text_attrs = CellAttributes::default();
+        text_attrs
+            .set_foreground(AnsiColor::Yellow)
+            .set_underline(Underline::Single);
+        let mut label_attrs = CellAttributes::default();
+        label_attrs
+            .set_foreground(AnsiColor::Yellow)
+            .set_intensity(Intensity::Bold)
+            .set_reverse(true);
+
+        self.matches
+            .iter()
+            .filter(|m| m.label.starts_with(&self.typed))
+            .map(|m| {
+                let mut line = Line::from_text(&m.text, &text_attrs);
+                let width = line.cells().len();
+                for (idx, c) in m.label.chars().enumerate().take(width) {
+                    line.set_cell(idx, Cell::new(c, label_attrs.clone()));
+                }
+                Overlay {
+                    row: m.row,
+                    col: m.cols.start,
+                    lines: vec![line],
+                }
+            })
+            .collect()
+    }
+}
+
+/// Finds the text on `lines` that matches the patterns, and labels it.
+/// The same text gets the same label wherever it appears, and the text
+/// nearest the bottom of the screen gets the shortest labels.
+fn label_matches<'a, I: Iterator<Item = &'a Line>>(lines: I, patterns: &[Regex]) -> Vec<Match> {
+    let mut matches: Vec<Match> = lines
+        .enumerate()
+        .flat_map(|(row, line)| {
+            line_matches(line, patterns)
+                .into_iter()
+                .map(move |(cols, text)| Match {
+                    row,
+                    cols,
+                    text,
+                    label: String::new(),
+                })
+        })
+        .collect();
+    matches.sort_by(|a, b| b.row.cmp(&a.row).then(a.cols.start.cmp(&b.cols.start)));
+
+    let mut unique: Vec<&str> = vec![];
+    for m in &matches {
+        if !unique.contains(&m.text.as_str()) {
+            unique.push(&m.text);
+        }
+    }
+    let labels = labels(unique.len());
+    let unique: Vec<String> = unique.into_iter().map(str::to_owned).collect();
+    for m in &mut matches {
+        let idx = unique.iter().position(|text| *text == m.text).unwrap();
+        if let Some(label) = labels.get(idx) {
+            m.label = label.clone();
+        }
+    }
+    // There may be more matches than labels
+    matches.retain(|m| !m.label.is_empty());
+
+    matches
+}
+
+/// Returns `count` labels, none of which is the start of another
+fn labels(count: usize) -> Vec<String> {
+    let alphabet: Vec<char> = ALPHABET.chars().collect();
+    if count <= alphabet.len() {
+        return alphabet.iter().take(count).map(|c| c.to_string()).collect();
+    }
+    alphabet
+        .iter()
+        .flat_map(|a| alphabet.iter().map(move |b| format!("{}{}", a, b)))
+        .take(count)
+        .collect()
+}
+
+/// Returns the columns and text of the matches in the line.  Where
+/// matches overlap, the longest one wins.
+pub fn line_matches(line: &Line, patterns: &[Regex]) -> Vec<(Range<usize>, String)> {
+    // The text of the line, along with the column and the width of the
+    // cell that each byte of it came from
+    let mut text = String::new();
+    let mut byte_cells = vec![];
+    for (idx, cell) in line.visible_cells() {
+        text.push_str(cell.str());
+        byte_cells.extend(std::iter::repeat((idx, cell.width().max(1))).take(cell.str().len()));
+    }
+
+    let mut found: Vec<Range<usize>> = vec![];
+    for pattern in patterns {
+        for m in pattern.find_iter(&text) {
+            if m.start() < m.end() {
+                found.push(m.start()..m.end());
+            }
+        }
+    }
+    found.sort_by(|a, b| (b.end - b.start).cmp(&(a.end - a.start)));
+    let mut taken: Vec<Range<usize>> = vec![];
+    for range in found {
+        if !taken
+            .iter()
+            .any(|other| range.start < other.end && other.start < range.end)
+        {
+            taken.push(range);
+        }
+    }
+    taken.sort_by_key(|range| range.start);
+
+    taken
+        .into_iter()
+        .map(|range| {
+            let (first_col, _) = byte_cells[range.start];
+            let (last_col, last_width) = byte_cells[range.end - 1];
+            (first_col..last_col + last_width, text[range].to_owned())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn label_and_select() {
+        let patterns =
+            compile_patterns(&[r"\b[0-9a-f]{7,40}\b".to_owned(), r"\b\w+://\S+".to_owned()])
+                .unwrap();
+        let attrs = CellAttributes::default();
+        let lines = vec![
+            Line::from_text("commit 1a2b3c4d see https://example.com/1a2b3c4d", &attrs),
+            Line::from_text("fixup 1a2b3c4d", &attrs),
+        ];
+        let mut select = QuickSelect::new(lines.iter(), &patterns);
+
+        // The URL wins over the hash inside it, and the repeated hash
+        // shares a label, starting from the bottom of the screen
+        let overlays = select.overlays();
+        assert_eq!(overlays.len(), 3);
+        assert_eq!((overlays[0].row, overlays[0].col), (1, 6));
+        assert_eq!(overlays[0].lines[0].as_str(), "aa2b3c4d");
+        assert_eq!((overlays[2].row, overlays[2].col), (0, 20));
+        assert_eq!(
+            overlays[2].lines[0].as_str(),
+            "sttps://example.com/1a2b3c4d"
+        );
+
+        assert_eq!(
+            select.type_char('S'),
+            Outcome::Paste("https://example.com/1a2b3c4d".to_owned())
+        );
+        select.backspace();
+        assert_eq!(select.type_char('a'), Outcome::Copy("1a2b3c4d".to_owned()));
+        select.backspace();
+        assert_eq!(select.type_char('z'), Outcome::Cancel);
+        select.backspace();
+
+        // Unchanged text keeps its labels, while new output moves them
+        // and forgets what was typed
+        select.type_char('s');
+        assert!(!select.relabel(lines.iter()));
+        assert_eq!(select.typed, "s");
+        let scrolled = vec![
+            Line::from_text("fixup 1a2b3c4d", &attrs),
+            Line::from_text("", &attrs),
+        ];
+        assert!(select.relabel(scrolled.iter()));
+        assert!(select.typed.is_empty());
+        let overlays = select.overlays();
+        assert_eq!(overlays.len(), 1);
+        assert_eq!((overlays[0].row, overlays[0].col), (0, 6));
+
+        // The case of the first character of a label decides whether
+        // its text is pasted
+        select.matches[0].label = "sa".to_owned();
+        assert_eq!(select.type_char('S'), Outcome::Pending);
+        assert_eq!(select.type_char('a'), Outcome::Paste("1a2b3c4d".to_owned()));
+
+        assert_eq!(labels(3), vec!["a", "s", "d"]);
+        assert_eq!(labels(27)[26], "sa");
+    }
+}
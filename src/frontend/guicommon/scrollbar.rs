@@ -0,0 +1,88 @@
+//! Computes the geometry of the scrollbar that is optionally rendered
+//! along the right edge of each gui window, and maps mouse positions
+//! on it back to a position within the scrollback.
+use crate::config::Config;
+use crate::mux::renderable::ScrollbarInfo;
+use std::ops::Range;
+
+/// Returns the number of columns that the scrollbar occupies
+pub fn scroll_bar_cols(config: &Config) -> usize {
+    if config.enable_scroll_bar.unwrap_or(false) {
+        1
+    } else {
+        0
+    }
+}
+
+/// Holds the state of a mouse drag of the scrollbar thumb
+#[derive(Debug, Default)]
+pub struct ScrollbarState {
+    pub dragging: bool,
+}
+
+/// Returns the rows of a scrollbar that is `rows` tall that are covered
+/// by the thumb.  The thumb is sized in proportion to the fraction of
+/// the scrollback that is visible, but is always at least one row.
+pub fn thumb_range(info: ScrollbarInfo, rows: usize) -> Range<usize> {
+    if rows == 0 {
+        return 0..0;
+    }
+    let size = thumb_size(info, rows);
+    if info.scrollback_rows == 0 {
+        return 0..size;
+    }
+    let top = info.scrollback_rows - info.viewport_offset.min(info.scrollback_rows);
+    let start = top * (rows - size) / info.scrollback_rows;
+    start..start + size
+}
+
+fn thumb_size(info: ScrollbarInfo, rows: usize) -> usize {
+    (rows * rows / (info.scrollback_rows + rows).max(1)).max(1)
+}
+
+/// Returns the viewport offset that places the top of the thumb of a
+/// scrollbar that is `rows` tall at `row`
+pub fn offset_for_row(info: ScrollbarInfo, rows: usize, row: usize) -> usize {
+    let travel = rows.saturating_sub(thumb_size(info, rows));
+    if travel == 0 {
+        return 0;
+    }
+    let top = (row * info.scrollback_rows / travel).min(info.scrollback_rows);
+    info.scrollback_rows - top
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn thumb() {
+        let info = ScrollbarInfo {
+            scrollback_rows: 30,
+            viewport_offset: 0,
+        };
+        assert_eq!(thumb_range(info, 10), 8..10);
+        assert_eq!(
+            thumb_range(
+                ScrollbarInfo {
+                    viewport_offset: 30,
+                    ..info
+                },
+                10
+            ),
+            0..2
+        );
+        assert_eq!(thumb_range(ScrollbarInfo::default(), 10), 0..10);
+    }
+
+    #[test]
+    fn offset() {
+        let info = ScrollbarInfo {
+            scrollback_rows: 30,
+            viewport_offset: 0,
+        };
+        assert_eq!(offset_for_row(info, 10, 0), 30);
+        assert_eq!(offset_for_row(info, 10, 4), 15);
+        assert_eq!(offset_for_row(info, 10, 9), 0);
+    }
+}
@@ -0,0 +1,419 @@
+//! Searching the scrollback of a tab for some text.  Every match in
+//! view is highlighted, the viewport steps from one match to the next,
+//! and the prompt counts the matches, such as "3/17" for the third of
+//! seventeen.  The lines that change while searching are searched
+//! again, so that the matches keep up with the output that arrives.
+use super::lineeditor::{EditorAction, LineEditor};
+use super::quickselect::line_matches;
+use crate::config::SearchColors;
+use crate::mux::renderable::{LineNumbering, ScrollbarInfo};
+use crate::mux::tab::{Tab, TabId};
+use crate::opengl::render::Overlay;
+use regex::Regex;
+use std::ops::Range;
+use term::{CellAttributes, KeyCode, KeyModifiers, Line, SequenceNo};
+use termwiz::color::{ColorAttribute, RgbColor};
+
+/// Text in the scrollback that matched the search
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Match {
+    /// The number of the line holding the match, which it keeps as
+    /// older lines are discarded; see `LineNumbering`
+    row: usize,
+    cols: Range<usize>,
+    text: String,
+}
+
+impl Match {
+    /// Identifies the match among those that are found again
+    fn key(&self) -> (usize, usize) {
+        (self.row, self.cols.start)
+    }
+}
+
+/// The state of the tab when its matches were last found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Scan {
+    tab_id: TabId,
+    seqno: SequenceNo,
+    numbering: LineNumbering,
+    /// The number that follows that of the bottom line
+    end: usize,
+    /// The physical (rows, cols) of the tab
+    dimensions: (usize, usize),
+}
+
+pub struct Search {
+    editor: LineEditor,
+    /// The matches, oldest first
+    matches: Vec<Match>,
+    /// The row and starting column of the match that was stepped to
+    current: Option<(usize, usize)>,
+    scanned: Option<Scan>,
+}
+
+impl Default for Search {
+    fn default() -> Self {
+        Self {
+            editor: LineEditor::new("", vec![]),
+            matches: vec![],
+            current: None,
+            scanned: None,
+        }
+    }
+}
+
+impl Search {
+    /// Handles a key typed while searching.  Enter and Up step to the
+    /// previous, older, match and Shift+Enter and Down to the next one,
+    /// while the other keys edit the text being searched for.
+    /// Returns false once the search was abandoned with Escape.
+    pub fn key(&mut self, tab: &dyn Tab, key: KeyCode, mods: KeyModifiers) -> bool {
+        match key {
+            KeyCode::Enter if mods.contains(KeyModifiers::SHIFT) => self.step(1),
+            KeyCode::Enter | KeyCode::UpArrow => self.step(-1),
+            KeyCode::DownArrow => self.step(1),
+            _ => {
+                let text = self.editor.text();
+                if self.editor.key(key, mods) == EditorAction::Cancel {
+                    return false;
+                }
+                if self.editor.text() == text {
+                    return true;
+                }
+                self.scanned = None;
+                self.refresh(tab);
+                // Start from the match nearest the bottom of the viewport
+                let offset = tab.renderer().scrollbar_info().viewport_offset;
+                let end = self.end();
+                self.current = self
+                    .matches
+                    .iter()
+                    .rev()
+                    .find(|m| end - 1 - m.row >= offset)
+                    .or_else(|| self.matches.first())
+                    .map(Match::key);
+            }
+        }
+        self.show_current(tab);
+        true
+    }
+
+    /// Finds the matches in the lines of the tab that changed since
+    /// they were last found, keeping the same match current.  Returns
+    /// true if the lines were searched again.
+    pub fn refresh(&mut self, tab: &dyn Tab) -> bool {
+        let mut renderer = tab.renderer();
+        let dimensions = renderer.physical_dimensions();
+        let numbering = renderer.line_numbering();
+        let scrollbar = renderer.scrollbar_info();
+        let end = numbering.first_row + scrollbar.scrollback_rows + dimensions.0;
+
+        // The lines on the screen when the matches were last found are
+        // the only ones that can have changed since, so the search starts
+        // from the top of that screen, unless it has to start over
+        let prior = self.scanned.filter(|scan| {
+            scan.tab_id == tab.tab_id()
+                && scan.dimensions == dimensions
+                && scan.numbering.alt_screen == numbering.alt_screen
+                && numbering.first_row >= scan.numbering.first_row
+                && end >= scan.end
+        });
+        let mut from = match prior {
+            Some(scan) => (scan.end - dimensions.0).max(numbering.first_row),
+            None => numbering.first_row,
+        };
+
+        let top = from - numbering.first_row;
+        let mut lines = renderer.get_viewport_lines(scrollbar.scrollback_rows - top, end - from);
+        let in_view_only = lines.len() < end - from;
+        if in_view_only {
+            // Tabs in mux domains only hold the lines in view
+            let offset = scrollbar.viewport_offset;
+            lines = renderer.get_viewport_lines(offset, dimensions.0);
+            from = end.saturating_sub(offset + lines.len());
+        }
+        if let Some(scan) = prior {
+            if end == scan.end && !lines.iter().any(|line| line.changed_since(scan.seqno)) {
+                return false;
+            }
+        }
+        let seqno = renderer.current_seqno();
+        if in_view_only {
+            self.matches.clear();
+        }
+
+        self.update(numbering.first_row, from, &lines);
+        self.scanned = Some(Scan {
+            tab_id: tab.tab_id(),
+            seqno,
+            numbering,
+            end,
+            dimensions,
+        });
+        true
+    }
+
+    /// Replaces the matches in the lines numbered `from` onwards with
+    /// those in `lines`, and forgets those before `first_row`, which
+    /// were discarded.  The same match is kept current, or the one that
+    /// followed it if it is gone.
+    fn update(&mut self, first_row: usize, from: usize, lines: &[Line]) {
+        self.matches.retain(|m| m.row >= first_row && m.row < from);
+        if let Some(pattern) = compile(&self.editor.text()) {
+            self.matches.extend(find_matches(lines, from, &pattern));
+        }
+        if let Some(current) = self.current {
+            self.current = self
+                .matches
+                .iter()
+                .find(|m| m.key() >= current)
+                .or_else(|| self.matches.last())
+                .map(Match::key);
+        }
+    }
+
+    /// Returns the overlays that highlight the matches that are in the
+    /// viewport, which is `offset` rows back and `rows` rows high
+    pub fn overlays(&self, offset: usize, rows: usize, colors: &SearchColors) -> Vec<Overlay> {
+        let match_attrs = attrs(colors.match_fg(), colors.match_bg());
+        let current_attrs = attrs(colors.current_match_fg(), colors.current_match_bg());
+        let end = self.end();
+        self.matches
+            .iter()
+            .filter_map(|m| {
+                let rows_up = end - 1 - m.row;
+                if rows_up < offset || rows_up >= offset + rows {
+                    return None;
+                }
+                let attrs = if Some(m.key()) == self.current {
+                    &current_attrs
+                } else {
+                    &match_attrs
+                };
+                Some(Overlay {
+                    row: offset + rows - 1 - rows_up,
+                    col: m.cols.start,
+                    lines: vec![Line::from_text(&m.text, attrs)],
+                })
+            })
+            .collect()
+    }
+
+    /// Renders the prompt as a line `width` cells wide, which shows the
+    /// position of the current match among them all
+    pub fn line(&self, width: usize, attrs: &CellAttributes) -> Line {
+        self.editor
+            .line(&format!(" Search ({}): ", self.position()), width, attrs)
+    }
+
+    /// Describes the position of the current match, counting from the
+    /// oldest, such as "3/17"
+    fn position(&self) -> String {
+        let current = self.current_index().map(|idx| idx + 1).unwrap_or(0);
+        format!("{}/{}", current, self.matches.len())
+    }
+
+    /// The number that follows that of the bottom line when the matches
+    /// were last found
+    fn end(&self) -> usize {
+        self.scanned.map(|scan| scan.end).unwrap_or(0)
+    }
+
+    fn current_index(&self) -> Option<usize> {
+        let current = self.current?;
+        self.matches.iter().position(|m| m.key() == current)
+    }
+
+    /// Moves the current match forward (positive) or back (negative),
+    /// wrapping around at either end
+    fn step(&mut self, delta: isize) {
+        let count = self.matches.len() as isize;
+        if count == 0 {
+            return;
+        }
+        let current = self
+            .current_index()
+            .map(|idx| idx as isize)
+            .unwrap_or(count);
+        let idx = (((current + delta) % count + count) % count) as usize;
+        self.current = Some(self.matches[idx].key());
+    }
+
+    /// Scrolls the viewport of the tab to bring the current match into
+    /// view
+    fn show_current(&self, tab: &dyn Tab) {
+        if let Some(idx) = self.current_index() {
+            let rows_up = self.end() - 1 - self.matches[idx].row;
+            let mut renderer = tab.renderer();
+            let (rows, _cols) = renderer.physical_dimensions();
+            // The prompt covers the bottom row
+            let offset = offset_showing(rows_up, renderer.scrollbar_info(), rows.saturating_sub(1));
+            renderer.set_viewport_offset(offset);
+        }
+    }
+}
+
+fn attrs(fg: RgbColor, bg: RgbColor) -> CellAttributes {
+    let mut attrs = CellAttributes::default();
+    attrs
+        .set_foreground(ColorAttribute::TrueColorWithDefaultFallback(fg))
+        .set_background(ColorAttribute::TrueColorWithDefaultFallback(bg));
+    attrs
+}
+
+/// Compiles a pattern that matches `text` literally, ignoring case
+/// unless `text` has upper case letters in it
+fn compile(text: &str) -> Option<Regex> {
+    if text.is_empty() {
+        return None;
+    }
+    let flags = if text.chars().any(char::is_uppercase) {
+        ""
+    } else {
+        "(?i)"
+    };
+    Regex::new(&format!("{}{}", flags, regex::escape(text))).ok()
+}
+
+/// Finds the matches for `pattern` in `lines`, the first of which is
+/// numbered `first_row`
+fn find_matches(lines: &[Line], first_row: usize, pattern: &Regex) -> Vec<Match> {
+    let patterns = [pattern.clone()];
+    lines
+        .iter()
+        .enumerate()
+        .flat_map(|(idx, line)| {
+            line_matches(line, &patterns)
+                .into_iter()
+                .map(move |(cols, text)| Match {
+                    row: first_row + idx,
+                    cols,
+                    text,
+                })
+        })
+        .collect()
+}
+
+/// Returns the viewport offset that brings the line that is `rows_up`
+/// rows above the bottom of the scrollback into a view `rows` high.
+/// The viewport stays put if the line is already in view, otherwise
+/// the line is put in the middle of it.
+fn offset_showing(rows_up: usize, scrollbar: ScrollbarInfo, rows: usize) -> usize {
+    let offset = scrollbar.viewport_offset;
+    if rows_up >= offset && rows_up < offset + rows {
+        offset
+    } else {
+        rows_up
+            .saturating_sub(rows / 2)
+            .min(scrollbar.scrollback_rows)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn text_lines(lines: &[&str]) -> Vec<Line> {
+        let attrs = CellAttributes::default();
+        lines
+            .iter()
+            .map(|text| Line::from_text(text, &attrs))
+            .collect()
+    }
+
+    fn search_for(text: &str, lines: &[&str]) -> Search {
+        let lines = text_lines(lines);
+        let mut search = Search {
+            editor: LineEditor::new(text, vec![]),
+            ..Search::default()
+        };
+        search.update(0, 0, &lines);
+        search.current = search.matches.last().map(Match::key);
+        search.scanned = Some(Scan {
+            tab_id: 0,
+            seqno: term::SEQ_ZERO,
+            numbering: LineNumbering::default(),
+            end: lines.len(),
+            dimensions: (lines.len(), 80),
+        });
+        search
+    }
+
+    #[test]
+    fn find_and_step() {
+        let search = search_for("error", &["Error: one", "ok", "an error and an ERROR"]);
+        let found: Vec<(usize, usize)> = search.matches.iter().map(Match::key).collect();
+        assert_eq!(found, vec![(0, 0), (2, 3), (2, 16)]);
+        assert_eq!(search.position(), "3/3");
+
+        // Upper case letters make the search case sensitive
+        let mut search = search_for("ERROR", &["Error: one", "ok", "an error and an ERROR"]);
+        assert_eq!(search.position(), "1/1");
+        search.matches.clear();
+        search.current = None;
+        search.step(-1);
+        assert_eq!(search.position(), "0/0");
+
+        let mut search = search_for("a.b", &["a.b axb", "a.b"]);
+        assert_eq!(search.matches.len(), 2);
+        search.step(-1);
+        assert_eq!(search.position(), "1/2");
+        search.step(-1);
+        assert_eq!(search.position(), "2/2");
+        search.step(1);
+        assert_eq!(search.position(), "1/2");
+    }
+
+    #[test]
+    fn keep_current_as_lines_are_discarded() {
+        let mut search = search_for("x", &["x", "x", "x", ""]);
+        search.step(-1);
+        assert_eq!(search.current, Some((1, 0)));
+        assert_eq!(search.position(), "2/3");
+
+        // The first line was discarded and the screen, starting from
+        // the third line, scrolled up to show a new match
+        search.update(1, 2, &text_lines(&["x", "", "x"]));
+        assert_eq!(search.current, Some((1, 0)));
+        assert_eq!(search.position(), "1/3");
+
+        // Once the current match is discarded, the next one is current
+        search.update(2, 2, &text_lines(&["x", "", "x"]));
+        assert_eq!(search.current, Some((2, 0)));
+        assert_eq!(search.position(), "1/2");
+    }
+
+    #[test]
+    fn highlight_matches_in_view() {
+        let search = search_for("x", &["x", "", "x", "", "x x"]);
+        let colors = SearchColors::default();
+        let rows: Vec<(usize, usize, bool)> = search
+            .overlays(1, 3, &colors)
+            .iter()
+            .map(|overlay| {
+                let current = overlay.lines[0].cells()[0].attrs().background
+                    == ColorAttribute::TrueColorWithDefaultFallback(colors.current_match_bg());
+                (overlay.row, overlay.col, current)
+            })
+            .collect();
+        // Only the match two rows up is in view, on the middle row
+        assert_eq!(rows, vec![(1, 0, false)]);
+        let rows: Vec<(usize, usize)> = search
+            .overlays(0, 3, &colors)
+            .iter()
+            .map(|overlay| (overlay.row, overlay.col))
+            .collect();
+        assert_eq!(rows, vec![(0, 0), (2, 0), (2, 2)]);
+
+        let scrollbar = |viewport_offset| ScrollbarInfo {
+            scrollback_rows: 100,
+            viewport_offset,
+        };
+        assert_eq!(offset_showing(5, scrollbar(0), 10), 0);
+        assert_eq!(offset_showing(50, scrollbar(0), 10), 45);
+        assert_eq!(offset_showing(50, scrollbar(48), 10), 48);
+        assert_eq!(offset_showing(2, scrollbar(48), 10), 0);
+        assert_eq!(offset_showing(108, scrollbar(0), 10), 100);
+    }
+}
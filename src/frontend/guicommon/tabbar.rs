@@ -0,0 +1,284 @@
+//! Computes the content of the tab bar that is rendered along the
+//! top (or bottom) of each gui window.
+use crate::config::{Config, TabBarColors};
+use crate::frontend::guicommon::attention;
+use crate::frontend::guicommon::hitregion::HitTarget;
+use crate::frontend::guicommon::window::{format_tab_title, tab_domain_info};
+use crate::mux::domain::{DomainState, LocalDomain};
+use crate::mux::tab::Tab;
+use crate::mux::window::Window;
+use crate::mux::Mux;
+use std::ops::Range;
+use std::time::{Duration, Instant};
+use term::color::ColorAttribute;
+use term::{Cell, CellAttributes, Line};
+
+/// The maximum number of cells that a single tab may occupy
+const MAX_TAB_WIDTH: usize = 32;
+
+/// Tabs narrower than this have no close button, leaving the room
+/// for the title
+const MIN_WIDTH_FOR_CLOSE_BUTTON: usize = 8;
+
+/// The number of cells taken by the new tab button
+const NEW_TAB_BUTTON_WIDTH: usize = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+struct TabEntry {
+    tab_idx: usize,
+    x: Range<usize>,
+    /// The column of the close button, if the tab has one
+    close: Option<usize>,
+}
+
+/// Holds the rendered form of the tab bar as a single terminal Line,
+/// along with enough information to map a column back to the tab
+/// that occupies it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TabBarState {
+    line: Line,
+    items: Vec<TabEntry>,
+    /// The columns of the new tab button that follows the tabs
+    new_tab: Option<Range<usize>>,
+    /// The index of the tab that the left button was pressed on,
+    /// which is moved to wherever the button is released
+    dragging: Option<usize>,
+    /// When the left button was pressed on the new tab button
+    new_tab_pressed: Option<Instant>,
+}
+
+/// The label of a tab when `tab_title_template` isn't set: its title,
+/// preceded by the domain of tabs that don't run on this machine
+fn default_tab_label(tab: &dyn Tab) -> String {
+    let mux = Mux::get().unwrap();
+    let is_local = mux
+        .get_domain(tab.domain_id())
+        .map(|domain| domain.downcast_ref::<LocalDomain>().is_some())
+        .unwrap_or(false);
+    if is_local {
+        tab.get_title()
+    } else {
+        let (name, state) = tab_domain_info(tab);
+        remote_tab_label(&tab.get_title(), &name, state)
+    }
+}
+
+/// Labels a tab of a remote domain with the name of the domain, along
+/// with its connection state while it isn't connected
+fn remote_tab_label(title: &str, domain_name: &str, state: DomainState) -> String {
+    match state {
+        DomainState::Attached => format!("{}: {}", domain_name, title),
+        _ => format!("{} ({}): {}", domain_name, state, title),
+    }
+}
+
+/// Returns the number of rows that the tab bar occupies
+pub fn tab_bar_rows(config: &Config) -> usize {
+    if config.enable_tab_bar.unwrap_or(true) {
+        1
+    } else {
+        0
+    }
+}
+
+fn attrs_with_colors(fg: ColorAttribute, bg: ColorAttribute) -> CellAttributes {
+    let mut attrs = CellAttributes::default();
+    attrs.set_foreground(fg).set_background(bg);
+    attrs
+}
+
+impl Default for TabBarState {
+    fn default() -> Self {
+        Self {
+            line: Line::with_width(0),
+            items: vec![],
+            new_tab: None,
+            dragging: None,
+            new_tab_pressed: None,
+        }
+    }
+}
+
+impl TabBarState {
+    /// Lay out the tabs from `window` into a line that is `width`
+    /// cells wide.  Each tab gets an equal share of the available
+    /// space, up to MAX_TAB_WIDTH, and its title is truncated to fit.
+    /// The title is formatted with `template` if one is configured.
+    pub fn new(
+        width: usize,
+        window: &Window,
+        colors: &TabBarColors,
+        template: Option<&str>,
+    ) -> Self {
+        let bar_attrs = attrs_with_colors(
+            ColorAttribute::TrueColorWithDefaultFallback(colors.inactive_tab_fg()),
+            ColorAttribute::TrueColorWithDefaultFallback(colors.background()),
+        );
+        let active_attrs = attrs_with_colors(
+            ColorAttribute::TrueColorWithDefaultFallback(colors.active_tab_fg()),
+            ColorAttribute::TrueColorWithDefaultFallback(colors.active_tab_bg()),
+        );
+        let inactive_attrs = attrs_with_colors(
+            ColorAttribute::TrueColorWithDefaultFallback(colors.inactive_tab_fg()),
+            ColorAttribute::TrueColorWithDefaultFallback(colors.inactive_tab_bg()),
+        );
+
+        let mut line = Line::with_width(width);
+        for x in 0..width {
+            line.set_cell(x, Cell::new(' ', bar_attrs.clone()));
+        }
+
+        let num_tabs = window.len();
+        let mut items = vec![];
+        if num_tabs == 0 || width == 0 {
+            return Self {
+                line,
+                items,
+                new_tab: None,
+                dragging: None,
+                new_tab_pressed: None,
+            };
+        }
+
+        // Make room for the new tab button after the tabs
+        let tab_width = (width.saturating_sub(NEW_TAB_BUTTON_WIDTH) / num_tabs).min(MAX_TAB_WIDTH);
+        let active_idx = window.get_active_idx();
+        let mut x = 0;
+
+        for (tab_idx, tab) in window.iter().enumerate() {
+            if tab_width < 2 || x + tab_width > width {
+                break;
+            }
+            let attrs = if tab_idx == active_idx {
+                &active_attrs
+            } else {
+                &inactive_attrs
+            };
+
+            // Leave a one cell gap at the right of each tab so that
+            // adjacent tabs are visually separated
+            let start = x;
+            let end = x + tab_width - 1;
+            for col in start..end {
+                line.set_cell(col, Cell::new(' ', attrs.clone()));
+            }
+            let close = if tab_width >= MIN_WIDTH_FOR_CLOSE_BUTTON {
+                line.set_cell(end - 2, Cell::new('×', attrs.clone()));
+                Some(end - 2)
+            } else {
+                None
+            };
+            let title_end = close.unwrap_or(end);
+
+            let title = match template {
+                Some(template) => format_tab_title(template, &**tab, tab_idx, num_tabs),
+                None => default_tab_label(&**tab),
+            };
+            let title = match attention::get(tab.tab_id()) {
+                Some(attention) => format!(" {} {} ", attention.marker(), title),
+                None => format!(" {} ", title),
+            };
+            let title = Line::from_text(&title, attrs);
+            let mut col = start;
+            for cell in title.cells() {
+                let cell_width = cell.width().max(1);
+                if col + cell_width > title_end {
+                    break;
+                }
+                line.set_cell(col, cell.clone());
+                col += cell_width;
+            }
+
+            items.push(TabEntry {
+                tab_idx,
+                x: start..end,
+                close,
+            });
+            x += tab_width;
+        }
+
+        let new_tab = if x + NEW_TAB_BUTTON_WIDTH <= width {
+            for (col, c) in " + ".chars().enumerate() {
+                line.set_cell(x + col, Cell::new(c, inactive_attrs.clone()));
+            }
+            Some(x..x + NEW_TAB_BUTTON_WIDTH)
+        } else {
+            None
+        };
+
+        Self {
+            line,
+            items,
+            new_tab,
+            dragging: None,
+            new_tab_pressed: None,
+        }
+    }
+
+    pub fn line(&self) -> &Line {
+        &self.line
+    }
+
+    /// Returns the columns of the tab bar that respond to the mouse:
+    /// the whole bar, followed by each of the tabs on it
+    pub fn hit_targets(&self) -> Vec<(Range<usize>, HitTarget)> {
+        let mut targets = vec![(0..self.line.cells().len(), HitTarget::TabBar)];
+        for item in &self.items {
+            targets.push((item.x.clone(), HitTarget::Tab(item.tab_idx)));
+            if let Some(close) = item.close {
+                targets.push((close..close + 1, HitTarget::CloseTab(item.tab_idx)));
+            }
+        }
+        if let Some(new_tab) = self.new_tab.as_ref() {
+            targets.push((new_tab.clone(), HitTarget::NewTab));
+        }
+        targets
+    }
+
+    /// Records that the left button was pressed on the tab, which
+    /// starts dragging it
+    pub fn start_drag(&mut self, tab_idx: usize) {
+        self.dragging = Some(tab_idx);
+    }
+
+    /// Ends the drag, returning the index of the tab that was being
+    /// dragged, if any
+    pub fn take_drag(&mut self) -> Option<usize> {
+        self.dragging.take()
+    }
+
+    /// Records that the left button was pressed on the new tab button
+    pub fn press_new_tab(&mut self) {
+        self.new_tab_pressed = Some(Instant::now());
+    }
+
+    /// Ends a press of the new tab button, returning how long the
+    /// button was held, if it was pressed there
+    pub fn take_new_tab_press(&mut self) -> Option<Duration> {
+        self.new_tab_pressed.take().map(|pressed| pressed.elapsed())
+    }
+
+    /// Carries the drag or press in progress over to a newly laid out
+    /// tab bar
+    pub fn keep_drag(&mut self, other: &TabBarState) {
+        self.dragging = other.dragging;
+        self.new_tab_pressed = other.new_tab_pressed;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn remote_labels() {
+        assert_eq!(
+            remote_tab_label("vim", "mux:host", DomainState::Attached),
+            "mux:host: vim"
+        );
+        assert_eq!(
+            remote_tab_label("vim", "mux:host", DomainState::Reconnecting),
+            "mux:host (reconnecting): vim"
+        );
+    }
+}
@@ -0,0 +1,211 @@
+//! Toasts: short notices, such as the outcome of reloading the
+//! configuration, that are shown in a corner of the windows and go
+//! away by themselves after `toast_duration_ms`.
+use crate::config::{Config, ToastPosition};
+use crate::frontend::guicommon::pastehistory::text_line;
+use crate::mux::tab::TabId;
+use crate::mux::window::WindowId;
+use crate::mux::Mux;
+use crate::opengl::render::Overlay;
+use std::cell::RefCell;
+use std::time::Instant;
+use term::color::ColorAttribute;
+use term::CellAttributes;
+use unicode_width::UnicodeWidthStr;
+
+/// The most toasts that are shown at once; older ones are dropped
+/// to make room for new ones
+const MAX_TOASTS: usize = 4;
+
+thread_local! {
+    static TOASTS: RefCell<Vec<Toast>> = RefCell::new(vec![]);
+}
+
+struct Toast {
+    /// The window to show it in, or None for every window
+    window: Option<WindowId>,
+    text: String,
+    expires: Instant,
+}
+
+/// Shows `text` in the given window, or in every window if `window`
+/// is None
+pub fn show(window: Option<WindowId>, text: &str) {
+    let mux = Mux::get().unwrap();
+    let duration = match mux.config().toast_duration() {
+        Some(duration) => duration,
+        None => return,
+    };
+    TOASTS.with(|toasts| {
+        let mut toasts = toasts.borrow_mut();
+        toasts.push(Toast {
+            window,
+            text: text.to_owned(),
+            expires: Instant::now() + duration,
+        });
+        let excess = toasts.len().saturating_sub(MAX_TOASTS);
+        toasts.drain(0..excess);
+    });
+    repaint(window);
+}
+
+/// Shows `text` in the window that holds the tab
+pub fn show_for_tab(tab_id: TabId, text: &str) {
+    if let Some((window_id, _)) = window_containing_tab(tab_id) {
+        show(Some(window_id), text);
+    }
+}
+
+/// Tells the user that the program in the tab rang the bell, unless
+/// the tab is the one being shown in its window
+pub fn bell(tab_id: TabId) {
+    let mux = Mux::get().unwrap();
+    let (window_id, tab_idx) = match window_containing_tab(tab_id) {
+        Some(found) => found,
+        None => return,
+    };
+    let is_active = mux
+        .get_window(window_id)
+        .map(|window| window.get_active_idx() == tab_idx)
+        .unwrap_or(false);
+    if is_active {
+        return;
+    }
+    let title = mux
+        .get_tab(tab_id)
+        .map(|tab| tab.get_title())
+        .unwrap_or_default();
+    show(
+        Some(window_id),
+        &format!("Bell in tab {}: {}", tab_idx + 1, title),
+    );
+}
+
+/// Removes the toasts that have been shown for long enough
+pub fn expire() {
+    let now = Instant::now();
+    let expired: Vec<Option<WindowId>> = TOASTS.with(|toasts| {
+        let mut toasts = toasts.borrow_mut();
+        let expired = toasts
+            .iter()
+            .filter(|toast| toast.expires <= now)
+            .map(|toast| toast.window)
+            .collect();
+        toasts.retain(|toast| toast.expires > now);
+        expired
+    });
+    for window in expired {
+        repaint(window);
+    }
+}
+
+/// Returns when the next toast is due to be removed
+pub fn next_expiry() -> Option<Instant> {
+    TOASTS.with(|toasts| toasts.borrow().iter().map(|toast| toast.expires).min())
+}
+
+/// Renders the toasts for the window into an overlay for the corner
+/// named by the config, or returns None if there are none to show
+pub fn overlay(window: WindowId, cols: usize, rows: usize, config: &Config) -> Option<Overlay> {
+    let texts: Vec<String> = TOASTS.with(|toasts| {
+        toasts
+            .borrow()
+            .iter()
+            .filter(|toast| toast.window.map(|id| id == window).unwrap_or(true))
+            .map(|toast| format!(" {} ", toast.text))
+            .collect()
+    });
+    if texts.is_empty() {
+        return None;
+    }
+
+    let colors = config.toast_colors();
+    let mut attrs = CellAttributes::default();
+    attrs
+        .set_foreground(ColorAttribute::TrueColorWithDefaultFallback(
+            colors.foreground(),
+        ))
+        .set_background(ColorAttribute::TrueColorWithDefaultFallback(
+            colors.background(),
+        ));
+
+    let width = texts
+        .iter()
+        .map(|text| text.width())
+        .max()
+        .unwrap_or(0)
+        .min(cols);
+    let lines: Vec<_> = texts
+        .iter()
+        .take(rows)
+        .map(|text| text_line(text, width, &attrs))
+        .collect();
+
+    let position = config.toast_position.unwrap_or_default();
+    let row = match position {
+        ToastPosition::TopLeft | ToastPosition::TopRight => 0,
+        ToastPosition::BottomLeft | ToastPosition::BottomRight => rows - lines.len(),
+    };
+    let col = match position {
+        ToastPosition::TopLeft | ToastPosition::BottomLeft => 0,
+        ToastPosition::TopRight | ToastPosition::BottomRight => cols - width,
+    };
+    Some(Overlay { row, col, lines })
+}
+
+/// Returns the window that holds the tab, along with the index of
+/// the tab in that window
+pub fn window_containing_tab(tab_id: TabId) -> Option<(WindowId, usize)> {
+    let mux = Mux::get().unwrap();
+    for window_id in mux.iter_windows() {
+        if let Some(window) = mux.get_window(window_id) {
+            if let Some(idx) = window.idx_by_id(tab_id) {
+                return Some((window_id, idx));
+            }
+        }
+    }
+    None
+}
+
+/// Marks the active tab of the window, or of every window, as needing
+/// to be painted so that toasts are shown or removed promptly
+pub fn repaint(window: Option<WindowId>) {
+    let mux = Mux::get().unwrap();
+    let windows = match window {
+        Some(window_id) => vec![window_id],
+        None => mux.iter_windows(),
+    };
+    for window_id in windows {
+        if let Some(tab) = mux.get_active_tab_for_window(window_id) {
+            tab.renderer().make_all_lines_dirty();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn placement() {
+        TOASTS.with(|toasts| {
+            toasts.borrow_mut().push(Toast {
+                window: None,
+                text: "hi".to_owned(),
+                expires: Instant::now() + Duration::from_secs(60),
+            })
+        });
+        let mut config = Config::default();
+        let place = |config: &Config| {
+            let overlay = overlay(0, 20, 5, config).unwrap();
+            (overlay.row, overlay.col, overlay.lines[0].as_str())
+        };
+
+        assert_eq!(place(&config), (0, 16, " hi ".to_owned()));
+        config.toast_position = Some(ToastPosition::BottomLeft);
+        assert_eq!(place(&config), (4, 0, " hi ".to_owned()));
+        config.toast_position = Some(ToastPosition::TopLeft);
+        assert_eq!(place(&config), (0, 0, " hi ".to_owned()));
+    }
+}
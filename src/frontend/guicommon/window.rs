@@ -1,17 +1,34 @@
-use crate::config::Config;
-use crate::font::FontConfiguration;
-use crate::mux::domain::DomainId;
+use crate::config::{reload_scope, Config, ReloadScope};
+use crate::font::{FontConfiguration, FontSystemSelection};
+use crate::frontend::guicommon::attention;
+use crate::frontend::guicommon::confirm;
+use crate::frontend::guicommon::hitregion::HitTarget;
+use crate::frontend::guicommon::host::{spawn_window, tear_off_tab};
+use crate::frontend::guicommon::launcher::Launcher;
+use crate::frontend::guicommon::lineeditor::LineEditor;
+use crate::frontend::guicommon::lockscreen;
+use crate::frontend::guicommon::pastehistory::{text_line, PastePicker};
+use crate::frontend::guicommon::quickselect::QuickSelect;
+use crate::frontend::guicommon::scrollbar::{offset_for_row, scroll_bar_cols, ScrollbarState};
+use crate::frontend::guicommon::search::Search;
+use crate::frontend::guicommon::tabbar::{tab_bar_rows, TabBarState};
+use crate::frontend::guicommon::toast;
+use crate::mux::domain::{DomainId, DomainState};
 use crate::mux::tab::{Tab, TabId};
 use crate::mux::window::WindowId;
 use crate::mux::Mux;
-use crate::opengl::render::Renderer;
+use crate::opengl::render::{Bar, Composition, Overlay, Renderer};
 use crate::opengl::textureatlas::OutOfTextureSpace;
 use failure::{bail, ensure, format_err, Error};
 use glium;
 use log::{debug, error};
 use portable_pty::PtySize;
+use std::path::Path;
 use std::rc::Rc;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
+use term::{CellAttributes, MouseButton, MouseEvent, MouseEventKind};
 
 /// When spawning a tab, specify which domain should be used to
 /// host/spawn that tab.
@@ -22,10 +39,151 @@ pub enum SpawnTabDomain {
     /// Use the domain from the current tab in the associated window
     CurrentTabDomain,
     /// Use a specific domain
-    #[allow(dead_code)]
     Domain(DomainId),
 }
 
+/// How to divide a window when splitting it with a sibling window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitDirection {
+    /// Place the sibling to the right of the window
+    Horizontal,
+    /// Place the sibling below the window
+    Vertical,
+}
+
+impl FromStr for SplitDirection {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "Horizontal" => Ok(SplitDirection::Horizontal),
+            "Vertical" => Ok(SplitDirection::Vertical),
+            _ => Err(format_err!("invalid SplitDirection {}", s)),
+        }
+    }
+}
+
+/// The shapes of the mouse cursor that the windows show
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MouseCursor {
+    /// Over the tab bar, the scrollbar and the overlays
+    Arrow,
+    /// Over the text of the terminal
+    Text,
+    /// Over a hyperlink, to give the cue that it is clickable
+    Hand,
+}
+
+/// The factor by which each IncreaseFontSize or DecreaseFontSize
+/// changes the font scale of a window
+const FONT_SCALE_STEP: f64 = 1.1;
+const MIN_FONT_SCALE: f64 = 0.25;
+const MAX_FONT_SCALE: f64 = 8.0;
+
+/// Holding the left button on the new tab button for this long shows
+/// the launcher menu rather than opening a tab
+const LONG_PRESS: Duration = Duration::from_millis(500);
+
+/// Returns the font scale that results from taking `steps` steps
+/// up (positive) or down (negative) from `scale`.  Stepping up and
+/// then back down returns to the same scale, and the result is
+/// kept within a range that leaves the text legible and the cells
+/// smaller than the texture atlas.
+pub fn step_font_scale(scale: f64, steps: i32) -> f64 {
+    (scale * FONT_SCALE_STEP.powi(steps))
+        .max(MIN_FONT_SCALE)
+        .min(MAX_FONT_SCALE)
+}
+
+/// Returns the opacity in `presets` that follows `current`, wrapping
+/// around to the first, or the first if `current` isn't one of them.
+/// Returns None if there are no presets.
+pub fn next_window_opacity(presets: &[f32], current: f32) -> Option<f32> {
+    let first = *presets.first()?;
+    Some(
+        presets
+            .iter()
+            .position(|opacity| (opacity - current).abs() < std::f32::EPSILON)
+            .map(|idx| presets[(idx + 1) % presets.len()])
+            .unwrap_or(first),
+    )
+}
+
+/// Divides a window with dimensions `dims` in two along `direction`,
+/// keeping both parts a whole number of cells in size.
+/// Returns the new (width, height) of the window followed by
+/// the (width, height) of the space that remains for its sibling.
+/// The window keeps the larger part if the cells don't divide evenly.
+pub fn split_dimensions(dims: &Dimensions, direction: SplitDirection) -> ((u16, u16), (u16, u16)) {
+    match direction {
+        SplitDirection::Horizontal => {
+            let cols = dims.width as usize / dims.cell_width;
+            let sibling = (cols / 2 * dims.cell_width) as u16;
+            ((dims.width - sibling, dims.height), (sibling, dims.height))
+        }
+        SplitDirection::Vertical => {
+            let rows = dims.height as usize / dims.cell_height;
+            let sibling = (rows / 2 * dims.cell_height) as u16;
+            ((dims.width, dims.height - sibling), (dims.width, sibling))
+        }
+    }
+}
+
+/// Returns the name and connection state of the domain that hosts `tab`
+pub fn tab_domain_info(tab: &dyn Tab) -> (String, DomainState) {
+    let mux = Mux::get().unwrap();
+    match mux.get_domain(tab.domain_id()) {
+        Some(domain) => (domain.domain_name().to_owned(), domain.state()),
+        None => ("unknown".to_owned(), DomainState::Detached),
+    }
+}
+
+/// Expands `{name}` style placeholders in `template` with the
+/// corresponding values from `vars`.  Unknown placeholders are
+/// left untouched.
+pub fn expand_title_template(template: &str, vars: &[(&str, &str)]) -> String {
+    let mut result = template.to_owned();
+    for (name, value) in vars {
+        result = result.replace(&format!("{{{}}}", name), value);
+    }
+    result
+}
+
+/// Shortens `dir` by replacing the home directory at its start with `~`
+fn abbreviate_home(dir: &str) -> String {
+    if let Some(home) = dirs::home_dir() {
+        if let Ok(rest) = Path::new(dir).strip_prefix(&home) {
+            return if rest.as_os_str().is_empty() {
+                "~".to_owned()
+            } else {
+                format!("~/{}", rest.display())
+            };
+        }
+    }
+    dir.to_owned()
+}
+
+/// Expands the placeholders of `window_title_template` or
+/// `tab_title_template` with the details of `tab`, which is at the
+/// 0-based `tab_idx` of the `num_tabs` tabs in its window
+pub fn format_tab_title(template: &str, tab: &dyn Tab, tab_idx: usize, num_tabs: usize) -> String {
+    let (domain_name, domain_state) = tab_domain_info(tab);
+    let cwd = tab
+        .get_current_dir()
+        .map(|dir| abbreviate_home(&dir))
+        .unwrap_or_default();
+    expand_title_template(
+        template,
+        &[
+            ("title", &tab.get_title()),
+            ("cwd", &cwd),
+            ("tab_index", &(tab_idx + 1).to_string()),
+            ("tab_count", &num_tabs.to_string()),
+            ("domain", &domain_name),
+            ("domain_state", &domain_state.to_string()),
+        ],
+    )
+}
+
 /// Reports the currently configured physical size of the display
 /// surface (physical pixels, not adjusted for dpi) and the current
 /// cell dimensions, also in physical pixels
@@ -45,7 +203,35 @@ pub trait TerminalWindow {
     fn get_mux_window_id(&self) -> WindowId;
     fn frame(&self) -> glium::Frame;
     fn renderer(&mut self) -> &mut Renderer;
+    fn tab_bar(&mut self) -> &mut TabBarState;
+    fn scrollbar(&mut self) -> &mut ScrollbarState;
+    /// Returns the paste history overlay, if it is currently shown
+    fn paste_picker(&self) -> Option<&PastePicker>;
+    /// Returns the tab rename prompt, if it is currently shown
+    fn rename_prompt(&self) -> Option<&LineEditor>;
+    /// Returns the launcher menu, if it is currently shown
+    fn launcher(&self) -> Option<&Launcher>;
+    /// Returns quick select mode, if it currently labels the screen
+    fn quick_select(&self) -> Option<&QuickSelect>;
+    /// Returns the scrollback search, if it is active
+    fn search(&self) -> Option<&Search>;
+    /// Searches the lines of `tab` that changed since the scrollback
+    /// search last looked at them, returning true if it did
+    fn refresh_search(&mut self, tab: &dyn Tab) -> bool;
+    /// Labels the screen of `tab` again if it changed while quick
+    /// select mode is shown, returning true if the labels changed
+    fn refresh_quick_select(&mut self, tab: &dyn Tab) -> bool;
+    fn show_launcher(&mut self);
     fn recreate_texture_atlas(&mut self, size: u32) -> Result<(), Error>;
+    /// Returns true if the OpenGL context of the window has been lost,
+    /// such as by a reset of the GPU.  This is only detected by the
+    /// glutin front end, which asks for a robust context, and only if
+    /// the driver supports robustness; the X11 front end's EGL context
+    /// is created without it, so its context is never reported lost.
+    fn is_context_lost(&self) -> bool;
+    /// Replaces a lost OpenGL context, along with the renderer and its
+    /// texture atlas, which belonged to it
+    fn recreate_context(&mut self) -> Result<(), Error>;
     fn advise_renderer_that_scaling_has_changed(
         &mut self,
         cell_width: usize,
@@ -54,12 +240,45 @@ pub trait TerminalWindow {
     fn advise_renderer_of_resize(&mut self, width: u16, height: u16) -> Result<(), Error>;
     fn config(&self) -> &Arc<Config>;
     fn fonts(&self) -> &Rc<FontConfiguration>;
+    /// Replaces the configuration and fonts used by the window
+    /// and its renderer
+    fn set_config(&mut self, config: &Arc<Config>, fonts: &Rc<FontConfiguration>);
     fn get_dimensions(&self) -> Dimensions;
     fn resize_if_not_full_screen(&mut self, width: u16, height: u16) -> Result<bool, Error>;
+    /// Accesses the clipboard on behalf of a program that used OSC 52
+    fn get_clipboard(&mut self) -> Result<String, Error>;
+    fn set_clipboard(&mut self, clip: Option<String>) -> Result<(), Error>;
     fn check_for_resize(&mut self) -> Result<(), Error> {
         Ok(())
     }
 
+    /// Tells the input method where the top left of the cursor is, in
+    /// pixels, so that it can place its candidate window alongside it
+    fn set_ime_position(&mut self, _x: usize, _y: usize) {}
+    /// Changes the shape of the mouse cursor while it is over the window
+    fn set_mouse_cursor(&mut self, cursor: MouseCursor);
+    /// Returns where the mouse pointer was last seen, in pixels
+    /// relative to the top left of the window
+    fn pointer_position(&self) -> Option<(i64, i64)>;
+    /// Returns the position of the top left of the window on the
+    /// screen, if the windowing system reveals it
+    fn screen_position(&self) -> Option<(i64, i64)> {
+        None
+    }
+
+    /// Returns true if the point `x`, `y` on the screen is inside the
+    /// window
+    fn contains_screen_point(&self, x: i64, y: i64) -> bool {
+        let (left, top) = match self.screen_position() {
+            Some(position) => position,
+            None => return false,
+        };
+        let dims = self.get_dimensions();
+        x >= left
+            && y >= top
+            && x < left + i64::from(dims.width)
+            && y < top + i64::from(dims.height)
+    }
     fn hide_window(&mut self) {}
     fn show_window(&mut self) {}
 
@@ -95,6 +314,81 @@ pub trait TerminalWindow {
         self.activate_tab(tab as usize % max)
     }
 
+    /// Activates the tab that was active before the current one
+    fn activate_last_tab(&mut self) -> Result<(), Error> {
+        let mux = Mux::get().unwrap();
+        let last = mux
+            .get_window(self.get_mux_window_id())
+            .ok_or_else(|| format_err!("no such window"))?
+            .get_last_active_idx();
+        match last {
+            Some(tab_idx) => self.activate_tab(tab_idx),
+            None => Ok(()),
+        }
+    }
+
+    /// Closes the tab with the given index in this window, killing
+    /// the program running in it
+    fn close_tab(&mut self, tab_idx: usize) -> Result<(), Error> {
+        let mux = Mux::get().unwrap();
+        let tab = match mux
+            .get_window(self.get_mux_window_id())
+            .and_then(|window| window.get_by_idx(tab_idx).cloned())
+        {
+            Some(tab) => tab,
+            None => return Ok(()),
+        };
+        mux.remove_tab(tab.tab_id());
+        let is_empty = match mux.get_window_mut(self.get_mux_window_id()) {
+            Some(mut window) => {
+                window.remove_by_id(tab.tab_id());
+                window.is_empty()
+            }
+            None => true,
+        };
+        // An empty window is closed once the loop notices it
+        if is_empty {
+            return Ok(());
+        }
+        self.activate_tab_relative(0)
+    }
+
+    /// Moves the tab at index `from` in this window to index `to`
+    fn move_tab(&mut self, from: usize, to: usize) -> Result<(), Error> {
+        let mux = Mux::get().unwrap();
+        mux.get_window_mut(self.get_mux_window_id())
+            .ok_or_else(|| format_err!("no such window"))?
+            .move_tab(from, to);
+        self.update_title();
+        Ok(())
+    }
+
+    /// Switches the background of the window to the preset opacity
+    /// that follows the current one.  Returns false if there are no
+    /// presets.
+    fn cycle_window_opacity(&mut self) -> bool {
+        let presets = self.config().window_opacity_presets();
+        let current = self.renderer().window_opacity();
+        match next_window_opacity(&presets, current) {
+            Some(next) => {
+                self.renderer().set_window_opacity(Some(next));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Moves the active tab `delta` places to the right, or to the
+    /// left if `delta` is negative
+    fn move_tab_relative(&mut self, delta: isize) -> Result<(), Error> {
+        let mux = Mux::get().unwrap();
+        mux.get_window_mut(self.get_mux_window_id())
+            .ok_or_else(|| format_err!("no such window"))?
+            .move_active_tab_relative(delta);
+        self.update_title();
+        Ok(())
+    }
+
     fn update_title(&mut self) {
         let mux = Mux::get().unwrap();
         let window = match mux.get_window(self.get_mux_window_id()) {
@@ -107,14 +401,33 @@ pub trait TerminalWindow {
             return;
         }
         let tab_no = window.get_active_idx();
-
-        let title = match window.get_active() {
-            Some(tab) => tab.get_title(),
+        let tab = match window.get_active() {
+            Some(tab) => Rc::clone(tab),
             None => return,
         };
-
         drop(window);
 
+        // The titles could reveal what the lock screen hides
+        if lockscreen::is_locked() {
+            self.set_window_title("wezterm (locked)").ok();
+            return;
+        }
+
+        if let Some(template) = self.config().window_title_template.clone() {
+            let title = format_tab_title(&template, &*tab, tab_no, num_tabs);
+            self.set_window_title(&title).ok();
+            return;
+        }
+
+        let title = tab.get_title();
+        let (domain_name, domain_state) = tab_domain_info(&*tab);
+
+        let title = if domain_state != DomainState::Attached {
+            format!("{} ({}: {})", title, domain_name, domain_state)
+        } else {
+            title
+        };
+
         if num_tabs == 1 {
             self.set_window_title(&title).ok();
         } else {
@@ -123,13 +436,245 @@ pub trait TerminalWindow {
         }
     }
 
+    /// Recompute the tab bar from the current state of the window.
+    /// Returns true if it differs from the previously computed tab bar.
+    fn update_tab_bar(&mut self) -> bool {
+        if tab_bar_rows(self.config()) == 0 {
+            return false;
+        }
+        let mux = Mux::get().unwrap();
+        let window = match mux.get_window(self.get_mux_window_id()) {
+            Some(window) => window,
+            None => return false,
+        };
+        // The active tab is being shown, so has the attention it wanted
+        if let Some(tab) = window.get_active() {
+            attention::clear(tab.tab_id());
+        }
+        let dims = self.get_dimensions();
+        let cols = (dims.width as usize + 1) / dims.cell_width;
+        let config = self.config();
+        let new_tab_bar = TabBarState::new(
+            cols,
+            &window,
+            &config.tab_bar_colors(),
+            config.tab_title_template.as_ref().map(String::as_str),
+        );
+        drop(window);
+
+        let mut new_tab_bar = new_tab_bar;
+        let tab_bar = self.tab_bar();
+        new_tab_bar.keep_drag(tab_bar);
+        if *tab_bar == new_tab_bar {
+            false
+        } else {
+            *tab_bar = new_tab_bar;
+            true
+        }
+    }
+
+    /// Takes a mouse event whose coordinates are relative to the top
+    /// left cell of the window and routes it using the hit regions of
+    /// the last paint.  If the event lands on the tab bar or the
+    /// scrollbar it is handled here and None is returned, otherwise
+    /// the event is returned with its coordinates adjusted to be
+    /// relative to the terminal.
+    fn translate_mouse_event(&mut self, event: MouseEvent) -> Result<Option<MouseEvent>, Error> {
+        if lockscreen::is_locked() {
+            return Ok(None);
+        }
+        let (target, terminal_row) = {
+            let regions = self.renderer().hit_regions();
+            (
+                regions.hit_test(event.x, event.y).cloned(),
+                regions.terminal_row(),
+            )
+        };
+        let event = MouseEvent {
+            y: event.y - terminal_row as i64,
+            ..event
+        };
+
+        if self.scrollbar_mouse_event(&event, target == Some(HitTarget::Scrollbar))? {
+            return Ok(None);
+        }
+
+        // Releasing the left button anywhere ends a drag of a tab or a
+        // press of the new tab button
+        let (dragged, new_tab_held) =
+            if event.kind == MouseEventKind::Release && event.button == MouseButton::Left {
+                let mut tab_bar = self.tab_bar();
+                (tab_bar.take_drag(), tab_bar.take_new_tab_press())
+            } else {
+                (None, None)
+            };
+        // Dropping the tab outside of the window tears it off
+        if let Some(from) = dragged {
+            if self.pointer_is_outside() {
+                return self.tear_off_tab(from).map(|_| None);
+            }
+        }
+        let on_tab_bar = match target {
+            Some(HitTarget::Tab(_))
+            | Some(HitTarget::CloseTab(_))
+            | Some(HitTarget::NewTab)
+            | Some(HitTarget::TabBar) => true,
+            _ => false,
+        };
+        if on_tab_bar && event.kind == MouseEventKind::Press {
+            // Scrolling over the tab bar cycles through the tabs
+            match event.button {
+                MouseButton::WheelUp => return self.activate_tab_relative(-1).map(|_| None),
+                MouseButton::WheelDown => return self.activate_tab_relative(1).map(|_| None),
+                _ => {}
+            }
+        }
+
+        match target {
+            Some(HitTarget::Tab(tab_idx)) => {
+                match (event.kind, event.button) {
+                    (MouseEventKind::Press, MouseButton::Left) => {
+                        self.activate_tab(tab_idx)?;
+                        self.tab_bar().start_drag(tab_idx);
+                    }
+                    (MouseEventKind::Press, MouseButton::Middle) => self.close_tab(tab_idx)?,
+                    (MouseEventKind::Release, MouseButton::Left) => match dragged {
+                        Some(from) if from != tab_idx => self.move_tab(from, tab_idx)?,
+                        _ => {}
+                    },
+                    _ => {}
+                }
+                Ok(None)
+            }
+            Some(HitTarget::NewTab) => {
+                // A click opens a new tab, while a right click or a long
+                // press, for those without a right button, shows the
+                // launcher menu
+                let launch = match (event.kind, event.button) {
+                    (MouseEventKind::Press, MouseButton::Left) => {
+                        self.tab_bar().press_new_tab();
+                        false
+                    }
+                    (MouseEventKind::Release, MouseButton::Left) => match new_tab_held {
+                        Some(held) if held >= LONG_PRESS => true,
+                        Some(_) => {
+                            self.spawn_tab(SpawnTabDomain::DefaultDomain)?;
+                            false
+                        }
+                        None => false,
+                    },
+                    (MouseEventKind::Press, MouseButton::Right) => true,
+                    _ => false,
+                };
+                if launch {
+                    self.show_launcher();
+                    let mux = Mux::get().unwrap();
+                    if let Some(tab) = mux.get_active_tab_for_window(self.get_mux_window_id()) {
+                        tab.renderer().make_all_lines_dirty();
+                    }
+                }
+                Ok(None)
+            }
+            Some(HitTarget::CloseTab(tab_idx)) => {
+                if event.kind == MouseEventKind::Press
+                    && (event.button == MouseButton::Left || event.button == MouseButton::Middle)
+                {
+                    self.close_tab(tab_idx)?;
+                }
+                Ok(None)
+            }
+            Some(HitTarget::TabBar) | Some(HitTarget::Scrollbar) | Some(HitTarget::Overlay) => {
+                Ok(None)
+            }
+            Some(HitTarget::Terminal) | Some(HitTarget::Hyperlink(_)) | None => Ok(Some(event)),
+        }
+    }
+
+    /// Returns true if the mouse pointer was last seen outside of the
+    /// window, which happens while a button is held
+    fn pointer_is_outside(&self) -> bool {
+        let dims = self.get_dimensions();
+        match self.pointer_position() {
+            Some((x, y)) => {
+                x < 0 || y < 0 || x >= i64::from(dims.width) || y >= i64::from(dims.height)
+            }
+            None => false,
+        }
+    }
+
+    /// Moves the tab at `tab_idx` into the window that it was dropped
+    /// on, or into a new window of its own
+    fn tear_off_tab(&mut self, tab_idx: usize) -> Result<(), Error> {
+        let mux = Mux::get().unwrap();
+        let tab_id = match mux
+            .get_window(self.get_mux_window_id())
+            .and_then(|window| window.get_by_idx(tab_idx).map(|tab| tab.tab_id()))
+        {
+            Some(tab_id) => tab_id,
+            None => return Ok(()),
+        };
+        let point = match (self.pointer_position(), self.screen_position()) {
+            (Some((x, y)), Some((left, top))) => Some((left + x, top + y)),
+            _ => None,
+        };
+        tear_off_tab(tab_id, self.get_mux_window_id(), point);
+        Ok(())
+    }
+
+    /// Handles clicking on and dragging the scrollbar.  `event` has
+    /// coordinates relative to the terminal.  Returns true if the
+    /// event was consumed by the scrollbar.
+    fn scrollbar_mouse_event(
+        &mut self,
+        event: &MouseEvent,
+        over_scrollbar: bool,
+    ) -> Result<bool, Error> {
+        let mux = Mux::get().unwrap();
+        let tab = match mux.get_active_tab_for_window(self.get_mux_window_id()) {
+            Some(tab) => tab,
+            None => return Ok(false),
+        };
+
+        match (event.kind, event.button) {
+            (MouseEventKind::Press, MouseButton::Left) if over_scrollbar => {
+                self.scrollbar().dragging = true;
+            }
+            (MouseEventKind::Move, _) if self.scrollbar().dragging => {}
+            (MouseEventKind::Release, MouseButton::Left) if self.scrollbar().dragging => {
+                self.scrollbar().dragging = false;
+                return Ok(true);
+            }
+            // Let the wheel scroll the terminal as usual
+            (_, MouseButton::WheelUp) | (_, MouseButton::WheelDown) => return Ok(false),
+            _ => return Ok(over_scrollbar),
+        }
+
+        let rows = tab.renderer().physical_dimensions().0;
+        let row = event.y.max(0).min(rows as i64 - 1) as usize;
+        let mut renderer = tab.renderer();
+        let offset = offset_for_row(renderer.scrollbar_info(), rows, row);
+        renderer.set_viewport_offset(offset);
+        Ok(true)
+    }
+
     fn paint_if_needed(&mut self) -> Result<(), Error> {
+        toast::expire();
+        attention::expire();
         let mux = Mux::get().unwrap();
         let tab = match mux.get_active_tab_for_window(self.get_mux_window_id()) {
             Some(tab) => tab,
             None => return Ok(()),
         };
-        if tab.renderer().has_dirty_lines() {
+        let searched = self.refresh_search(&*tab);
+        let relabelled = self.refresh_quick_select(&*tab);
+        let painted_seqno = self.renderer().painted_seqno();
+        if searched
+            || relabelled
+            || tab.renderer().has_changes_since(painted_seqno)
+            || self.update_tab_bar()
+            || self.renderer().has_rasterized_glyphs()
+            || self.renderer().cursor_needs_repaint()
+        {
             self.paint()?;
         }
         self.update_title();
@@ -137,24 +682,159 @@ pub trait TerminalWindow {
     }
 
     fn paint(&mut self) -> Result<(), Error> {
+        self.paint_frame(false)
+    }
+
+    /// Paints the window.  `context_recreated` is true when painting
+    /// again after replacing a context that was lost while painting,
+    /// in which case losing it again is an error rather than a reason
+    /// to keep replacing it.
+    fn paint_frame(&mut self, context_recreated: bool) -> Result<(), Error> {
         let mux = Mux::get().unwrap();
         let tab = match mux.get_active_tab_for_window(self.get_mux_window_id()) {
             Some(tab) => tab,
             None => return Ok(()),
         };
 
-        let mut target = self.frame();
-        let res = {
-            let renderer = self.renderer();
-            let palette = tab.palette();
-            renderer.paint(&mut target, &mut *tab.renderer(), &palette)
+        let snapshot = tab.snapshot(self.renderer().last_snapshot());
+
+        self.update_tab_bar();
+        let mut composition = Composition::default();
+        composition.flash = attention::is_flashing(self.get_mux_window_id());
+        let lock_screen = lockscreen::lines(snapshot.physical_cols, snapshot.physical_rows);
+        if tab_bar_rows(self.config()) > 0 {
+            let tab_bar = if lock_screen.is_some() {
+                // Hide the tab titles too
+                Bar {
+                    line: text_line("", self.tab_bar().line().cells().len(), &Default::default()),
+                    targets: vec![],
+                }
+            } else {
+                Bar {
+                    line: self.tab_bar().line().clone(),
+                    targets: self.tab_bar().hit_targets(),
+                }
+            };
+            if self.config().tab_bar_at_bottom.unwrap_or(false) {
+                composition.below.push(tab_bar);
+            } else {
+                composition.above.push(tab_bar);
+            }
+        }
+        if let Some(picker) = self.paste_picker() {
+            composition.overlays.push(Overlay {
+                row: 0,
+                col: 0,
+                lines: picker.lines(snapshot.physical_cols, snapshot.physical_rows),
+            });
+        }
+        if let Some(launcher) = self.launcher() {
+            composition.overlays.push(Overlay {
+                row: 0,
+                col: 0,
+                lines: launcher.lines(snapshot.physical_cols, snapshot.physical_rows),
+            });
+        }
+        if let Some(select) = self.quick_select() {
+            composition.overlays.extend(select.overlays());
+        }
+        let search_colors = self.config().search_colors();
+        if let Some(search) = self.search() {
+            composition.overlays.extend(search.overlays(
+                snapshot.scrollbar.viewport_offset,
+                snapshot.physical_rows,
+                &search_colors,
+            ));
+            let mut attrs = CellAttributes::default();
+            attrs.set_reverse(true);
+            composition.overlays.push(Overlay {
+                row: snapshot.physical_rows.saturating_sub(1),
+                col: 0,
+                lines: vec![search.line(snapshot.physical_cols, &attrs)],
+            });
+        }
+        let banner = match tab.held_exit() {
+            Some(status) => Some(format!(
+                " Process {}; press any key to close this tab",
+                status
+            )),
+            None => tab.status_banner().map(|banner| format!(" {}", banner)),
         };
+        if let Some(banner) = banner {
+            let mut attrs = CellAttributes::default();
+            attrs.set_reverse(true);
+            composition.overlays.push(Overlay {
+                row: snapshot.physical_rows.saturating_sub(1),
+                col: 0,
+                lines: vec![text_line(&banner, snapshot.physical_cols, &attrs)],
+            });
+        }
+        if let Some(editor) = self.rename_prompt() {
+            let mut attrs = CellAttributes::default();
+            attrs.set_reverse(true);
+            composition.overlays.push(Overlay {
+                row: snapshot.physical_rows.saturating_sub(1),
+                col: 0,
+                lines: vec![editor.line(" Rename tab: ", snapshot.physical_cols, &attrs)],
+            });
+        }
+        if let Some(overlay) = toast::overlay(
+            self.get_mux_window_id(),
+            snapshot.physical_cols,
+            snapshot.physical_rows,
+            self.config(),
+        ) {
+            composition.overlays.push(overlay);
+        }
+        if lock_screen.is_none() {
+            if let Some(mut lines) = confirm::lines(snapshot.physical_cols) {
+                let skip = lines.len().saturating_sub(snapshot.physical_rows);
+                lines.drain(..skip);
+                composition.overlays.push(Overlay {
+                    row: snapshot.physical_rows - lines.len(),
+                    col: 0,
+                    lines,
+                });
+            }
+        }
+        if let Some(lines) = lock_screen {
+            composition.overlays.push(Overlay {
+                row: 0,
+                col: 0,
+                lines,
+            });
+        }
+        composition.scrollbar = scroll_bar_cols(self.config()) > 0;
+
+        let dims = self.get_dimensions();
+        let cursor_row = snapshot.cursor.y.max(0) as usize + composition.above.len();
+        self.set_ime_position(
+            snapshot.cursor.x * dims.cell_width,
+            cursor_row * dims.cell_height,
+        );
+
+        if self.is_context_lost() {
+            error!("the OpenGL context was lost; recreating it");
+            self.recreate_context()?;
+        }
+
+        let mut target = self.frame();
+        let res = self.renderer().paint(&mut target, snapshot, &composition);
 
         // Ensure that we finish() the target before we let the
         // error bubble up, otherwise we lose the context.
-        target
-            .finish()
-            .expect("target.finish failed and we don't know how to recover");
+        match target.finish() {
+            Ok(()) => {}
+            Err(glium::SwapBuffersError::ContextLost) => {
+                if context_recreated {
+                    bail!("the OpenGL context was lost again after recreating it");
+                }
+                error!("the OpenGL context was lost while painting; recreating it");
+                self.recreate_context()?;
+                return self.paint_frame(true);
+            }
+            Err(err) => bail!("target.finish failed: {:?}", err),
+        }
 
         // The only error we want to catch is texture space related;
         // when that happens we need to blow our glyph cache and
@@ -164,7 +844,6 @@ pub trait TerminalWindow {
                 if let Some(&OutOfTextureSpace { size }) = err.downcast_ref::<OutOfTextureSpace>() {
                     error!("out of texture space, allocating {}", size);
                     self.recreate_texture_atlas(size)?;
-                    tab.renderer().make_all_lines_dirty();
                     // Recursively initiate a new paint
                     return self.paint();
                 }
@@ -177,14 +856,18 @@ pub trait TerminalWindow {
     fn spawn_tab(&mut self, domain: SpawnTabDomain) -> Result<TabId, Error> {
         let dims = self.get_dimensions();
 
-        let rows = (dims.height as usize + 1) / dims.cell_height;
-        let cols = (dims.width as usize + 1) / dims.cell_width;
+        let rows = ((dims.height as usize + 1) / dims.cell_height)
+            .saturating_sub(tab_bar_rows(self.config()))
+            .max(1);
+        let cols = ((dims.width as usize + 1) / dims.cell_width)
+            .saturating_sub(scroll_bar_cols(self.config()))
+            .max(1);
 
         let size = PtySize {
             rows: rows as u16,
             cols: cols as u16,
-            pixel_width: dims.width,
-            pixel_height: dims.height,
+            pixel_width: (cols * dims.cell_width) as u16,
+            pixel_height: (rows * dims.cell_height) as u16,
         };
 
         let mux = Mux::get().unwrap();
@@ -203,7 +886,23 @@ pub trait TerminalWindow {
                 .get_domain(id)
                 .ok_or_else(|| format_err!("spawn_tab called with unresolvable domain id!?"))?,
         };
-        let tab = domain.spawn(size, None, self.get_mux_window_id())?;
+
+        // Start in the directory of the active tab, as reported by its
+        // shell, provided that it belongs to the same domain
+        let mut command = None;
+        if let Some(active) = mux.get_active_tab_for_window(self.get_mux_window_id()) {
+            if active.domain_id() == domain.domain_id() {
+                if let Some(dir) = active.get_current_dir() {
+                    if Path::new(&dir).is_dir() {
+                        let mut cmd = mux.config().build_prog_for_domain(domain.domain_name())?;
+                        cmd.cwd(dir);
+                        command = Some(cmd);
+                    }
+                }
+            }
+        }
+
+        let tab = domain.spawn(size, command, self.get_mux_window_id())?;
         let tab_id = tab.tab_id();
 
         let len = {
@@ -216,6 +915,38 @@ pub trait TerminalWindow {
         Ok(tab_id)
     }
 
+    /// Shrinks the window along `direction` and spawns a new window that
+    /// is sized to fill the space that was given up, so that the window
+    /// manager can tile the pair.  Where the window cannot be resized,
+    /// such as when it is full screen, the new window is instead given
+    /// the same size as this one.
+    fn split_window(&mut self, direction: SplitDirection) -> Result<(), Error> {
+        let dims = self.get_dimensions();
+        let ((width, height), (sibling_width, sibling_height)) = split_dimensions(&dims, direction);
+
+        let (sibling_width, sibling_height) = if self.resize_if_not_full_screen(width, height)? {
+            (sibling_width, sibling_height)
+        } else {
+            debug!("unable to resize the window; not splitting it");
+            (dims.width, dims.height)
+        };
+
+        let rows = (sibling_height as usize / dims.cell_height)
+            .saturating_sub(tab_bar_rows(self.config()))
+            .max(1);
+        let cols = (sibling_width as usize / dims.cell_width)
+            .saturating_sub(scroll_bar_cols(self.config()))
+            .max(1);
+
+        spawn_window(PtySize {
+            rows: rows as u16,
+            cols: cols as u16,
+            pixel_width: sibling_width,
+            pixel_height: sibling_height,
+        });
+        Ok(())
+    }
+
     fn resize_surfaces(&mut self, width: u16, height: u16, force: bool) -> Result<bool, Error> {
         let dims = self.get_dimensions();
 
@@ -228,8 +959,12 @@ pub trait TerminalWindow {
             // When we get N rows with a gap of cell_height - 1 left at
             // the bottom, we can usually squeeze that extra row in there,
             // so optimistically pretend that we have that extra pixel!
-            let rows = ((height as usize + 1) / dims.cell_height) as u16;
-            let cols = ((width as usize + 1) / dims.cell_width) as u16;
+            let rows = ((height as usize + 1) / dims.cell_height)
+                .saturating_sub(tab_bar_rows(self.config()))
+                .max(1) as u16;
+            let cols = ((width as usize + 1) / dims.cell_width)
+                .saturating_sub(scroll_bar_cols(self.config()))
+                .max(1) as u16;
 
             let mux = Mux::get().unwrap();
             let window = mux
@@ -239,8 +974,8 @@ pub trait TerminalWindow {
                 tab.resize(PtySize {
                     rows,
                     cols,
-                    pixel_width: width as u16,
-                    pixel_height: height as u16,
+                    pixel_width: cols * dims.cell_width as u16,
+                    pixel_height: rows * dims.cell_height as u16,
                 })?;
             }
 
@@ -270,7 +1005,6 @@ pub trait TerminalWindow {
             Some(tab) => tab,
             None => return Ok(()),
         };
-        tab.renderer().make_all_lines_dirty();
         fonts.change_scaling(font_scale, dpi_scale);
 
         let metrics = fonts.default_font_metrics()?;
@@ -281,6 +1015,8 @@ pub trait TerminalWindow {
         // If the backend supports `resize_if_not_full_screen` then we'll try
         // to resize the window to match the new cell metrics.
         let (rows, cols) = { tab.renderer().physical_dimensions() };
+        let rows = rows + tab_bar_rows(self.config());
+        let cols = cols + scroll_bar_cols(self.config());
 
         self.advise_renderer_that_scaling_has_changed(
             cell_width.ceil() as usize,
@@ -295,8 +1031,63 @@ pub trait TerminalWindow {
         Ok(())
     }
 
+    /// Applies the configuration held by the mux, if it has changed
+    /// since this window last saw it.  If the font settings changed
+    /// and the reload was forced, the fonts are resolved again for
+    /// this window, preserving its current font and dpi scale, and
+    /// the window is resized to fit the new cell metrics.  Otherwise
+    /// the window keeps its fonts and only lays out its bars again.
+    fn config_was_reloaded(&mut self) -> Result<(), Error> {
+        let mux = Mux::get().unwrap();
+        let config = mux.config();
+        if config.generation == self.config().generation {
+            return Ok(());
+        }
+
+        let old_fonts = Rc::clone(self.fonts());
+        let fonts_changed = old_fonts
+            .config()
+            .changed_settings(&config)?
+            .iter()
+            .any(|name| reload_scope(name) == ReloadScope::Fonts);
+        if !fonts_changed || !mux.fonts_are_forced(config.generation) {
+            self.set_config(&config, &old_fonts);
+            let dims = self.get_dimensions();
+            self.resize_surfaces(dims.width, dims.height, true)?;
+            self.update_title();
+            return Ok(());
+        }
+
+        let fonts = Rc::new(FontConfiguration::new(
+            Arc::clone(&config),
+            FontSystemSelection::get_default(),
+        ));
+        fonts.change_scaling(old_fonts.get_font_scale(), old_fonts.get_dpi_scale());
+        self.set_config(&config, &fonts);
+
+        let dims = self.get_dimensions();
+        self.scaling_changed(None, None, dims.width, dims.height)?;
+        self.update_title();
+        Ok(())
+    }
+
+    /// Records that the window gained or lost the keyboard focus; the
+    /// mux window tells its active tab
+    fn focus_changed(&mut self, focused: bool) -> Result<(), Error> {
+        self.renderer().set_focused(focused);
+        let mux = Mux::get().unwrap();
+        if let Some(mut window) = mux.get_window_mut(self.get_mux_window_id()) {
+            window.set_focused(focused);
+        }
+        Ok(())
+    }
+
     fn tab_did_terminate(&mut self, tab_id: TabId) {
         let mux = Mux::get().unwrap();
+        // The pty reader removes tabs whose program exited, but leaves
+        // tabs that were held open to be removed here once closed.
+        // This does nothing if the reader got there first.
+        mux.remove_tab(tab_id);
         let mut window = match mux.get_window_mut(self.get_mux_window_id()) {
             Some(window) => window,
             None => return,
@@ -340,3 +1131,73 @@ pub trait TerminalWindow {
         empty
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn dims(width: u16, height: u16) -> Dimensions {
+        Dimensions {
+            width,
+            height,
+            cell_width: 10,
+            cell_height: 20,
+        }
+    }
+
+    #[test]
+    fn split_horizontal() {
+        assert_eq!(
+            split_dimensions(&dims(800, 400), SplitDirection::Horizontal),
+            ((400, 400), (400, 400))
+        );
+        // The window keeps the odd column and any partial cell
+        assert_eq!(
+            split_dimensions(&dims(815, 400), SplitDirection::Horizontal),
+            ((415, 400), (400, 400))
+        );
+    }
+
+    #[test]
+    fn split_vertical() {
+        assert_eq!(
+            split_dimensions(&dims(800, 500), SplitDirection::Vertical),
+            ((800, 260), (800, 240))
+        );
+    }
+
+    #[test]
+    fn font_scale_steps() {
+        assert_eq!(step_font_scale(1.0, 0), 1.0);
+        assert!((step_font_scale(step_font_scale(1.0, 1), -1) - 1.0).abs() < 1e-9);
+        assert!(step_font_scale(1.0, 1) > 1.0);
+        assert!(step_font_scale(1.0, -1) < 1.0);
+        assert_eq!(step_font_scale(1.0, -100), MIN_FONT_SCALE);
+        assert_eq!(step_font_scale(1.0, 100), MAX_FONT_SCALE);
+    }
+
+    #[test]
+    fn cycle_window_opacity() {
+        let presets = [1.0, 0.8, 0.6];
+        assert_eq!(next_window_opacity(&presets, 1.0), Some(0.8));
+        assert_eq!(next_window_opacity(&presets, 0.8), Some(0.6));
+        // Wraps around from the last preset
+        assert_eq!(next_window_opacity(&presets, 0.6), Some(1.0));
+        // An opacity that isn't a preset starts over from the first
+        assert_eq!(next_window_opacity(&presets, 0.5), Some(1.0));
+        assert_eq!(next_window_opacity(&[], 1.0), None);
+    }
+
+    #[test]
+    fn parse_direction() {
+        assert_eq!(
+            "Horizontal".parse::<SplitDirection>().unwrap(),
+            SplitDirection::Horizontal
+        );
+        assert_eq!(
+            "Vertical".parse::<SplitDirection>().unwrap(),
+            SplitDirection::Vertical
+        );
+        assert!("Diagonal".parse::<SplitDirection>().is_err());
+    }
+}
@@ -0,0 +1,217 @@
+//! Implements a headless frontend that renders tabs into in-memory
+//! termwiz Surfaces rather than onto a display.  This allows exercising
+//! the full input -> pty -> parser -> render pipeline without requiring
+//! a display server, which is primarily useful for integration tests.
+use crate::config::Config;
+use crate::font::FontConfiguration;
+use crate::frontend::FrontEnd;
+use crate::mux::renderable::RenderableSnapshot;
+use crate::mux::tab::{Tab, TabId};
+use crate::mux::window::WindowId;
+use crate::mux::Mux;
+use failure::{bail, Error, Fallible};
+use log::info;
+use promise::Executor;
+use promise::SpawnFunc;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+#[cfg(test)]
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Arc;
+#[cfg(test)]
+use std::time::Duration;
+use termwiz::surface::{Change, Line, Position, SequenceNo, Surface, SEQ_ZERO};
+
+#[derive(Clone)]
+struct HeadlessExecutor {
+    tx: SyncSender<SpawnFunc>,
+}
+
+impl Executor for HeadlessExecutor {
+    fn execute(&self, f: SpawnFunc) {
+        self.tx.send(f).expect("HeadlessExecutor execute failed");
+    }
+    fn clone_executor(&self) -> Box<dyn Executor> {
+        Box::new(HeadlessExecutor {
+            tx: self.tx.clone(),
+        })
+    }
+}
+
+/// Holds the rendered state of a single tab.
+/// `lines` mirrors the visible lines of the terminal as captured
+/// in the most recent snapshot of the tab, and `surface` is kept
+/// in sync with those lines so that it can be inspected.
+struct HeadlessScreen {
+    lines: Vec<Line>,
+    surface: Surface,
+    /// The sequence number of the tab state that `lines` reflects
+    seqno: SequenceNo,
+    snapshot: Option<RenderableSnapshot>,
+}
+
+impl HeadlessScreen {
+    fn new(rows: usize, cols: usize) -> Self {
+        Self {
+            lines: (0..rows).map(|_| Line::with_width(cols)).collect(),
+            surface: Surface::new(cols, rows),
+            seqno: SEQ_ZERO,
+            snapshot: None,
+        }
+    }
+
+    fn render(&mut self, tab: &Rc<dyn Tab>) {
+        let snapshot = tab.snapshot(self.snapshot.as_ref());
+        let (rows, cols) = (snapshot.physical_rows, snapshot.physical_cols);
+
+        if self.surface.dimensions() != (cols, rows) {
+            self.lines = (0..rows).map(|_| Line::with_width(cols)).collect();
+            self.surface.resize(cols, rows);
+            self.seqno = SEQ_ZERO;
+        }
+
+        for (line_idx, line, _selrange) in snapshot.changed_lines(self.seqno) {
+            let mut line = line.clone();
+            line.resize(cols);
+            self.lines[line_idx] = line;
+        }
+        self.seqno = snapshot.seqno;
+
+        let changes = self.surface.diff_lines(self.lines.iter().collect());
+        self.surface.add_changes(changes);
+
+        let cursor = snapshot.cursor;
+        self.surface.add_change(Change::CursorPosition {
+            x: Position::Absolute(cursor.x),
+            y: Position::Absolute(cursor.y.max(0) as usize),
+        });
+        self.snapshot = Some(snapshot);
+    }
+}
+
+pub struct HeadlessFrontEnd {
+    tx: SyncSender<SpawnFunc>,
+    rx: Receiver<SpawnFunc>,
+    screens: RefCell<HashMap<TabId, HeadlessScreen>>,
+}
+
+impl HeadlessFrontEnd {
+    #[cfg_attr(feature = "cargo-clippy", allow(clippy::new_ret_no_self))]
+    pub fn try_new(_mux: &Rc<Mux>) -> Result<Rc<dyn FrontEnd>, Error> {
+        let (tx, rx) = mpsc::sync_channel(4);
+        Ok(Rc::new(Self {
+            tx,
+            rx,
+            screens: RefCell::new(HashMap::new()),
+        }))
+    }
+
+    /// Brings the surfaces up to date with the current state of
+    /// all of the tabs known to the mux, discarding the surfaces
+    /// of any tabs that no longer exist.
+    pub fn render_all(&self) {
+        let mux = match Mux::get() {
+            Some(mux) => mux,
+            None => return,
+        };
+        let tabs = mux.iter_tabs();
+        let mut screens = self.screens.borrow_mut();
+        screens.retain(|tab_id, _| tabs.iter().any(|tab| tab.tab_id() == *tab_id));
+
+        for tab in &tabs {
+            let (rows, cols) = tab.renderer().physical_dimensions();
+            screens
+                .entry(tab.tab_id())
+                .or_insert_with(|| HeadlessScreen::new(rows, cols))
+                .render(tab);
+        }
+    }
+}
+
+/// The means by which tests drive the front end and inspect what it
+/// rendered
+#[cfg(test)]
+impl HeadlessFrontEnd {
+    /// Runs any queued work, waiting up to `timeout` for the first
+    /// item to arrive, and then re-renders the surfaces.
+    /// Returns true if any work was performed.
+    pub fn pump(&self, timeout: Duration) -> Fallible<bool> {
+        let mut did_work = false;
+        match self.rx.recv_timeout(timeout) {
+            Ok(func) => {
+                func();
+                did_work = true;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(err) => bail!("while waiting for events: {:?}", err),
+        }
+        while let Ok(func) = self.rx.try_recv() {
+            func();
+            did_work = true;
+        }
+        self.render_all();
+        Ok(did_work)
+    }
+
+    /// Calls `func` with the surface for the specified tab.
+    /// Returns None if the tab has not been rendered.
+    pub fn with_surface<F, T>(&self, tab_id: TabId, func: F) -> Option<T>
+    where
+        F: FnOnce(&Surface) -> T,
+    {
+        self.screens
+            .borrow()
+            .get(&tab_id)
+            .map(|screen| func(&screen.surface))
+    }
+
+    /// Returns the visible text of the specified tab, with each
+    /// line separated by a newline.
+    pub fn screen_text(&self, tab_id: TabId) -> Option<String> {
+        self.with_surface(tab_id, Surface::screen_chars_to_string)
+    }
+
+    /// Returns the (x, y) cursor position for the specified tab
+    pub fn cursor_position(&self, tab_id: TabId) -> Option<(usize, usize)> {
+        self.with_surface(tab_id, Surface::cursor_position)
+    }
+}
+
+impl FrontEnd for HeadlessFrontEnd {
+    fn gui_executor(&self) -> Box<dyn Executor> {
+        Box::new(HeadlessExecutor {
+            tx: self.tx.clone(),
+        })
+    }
+
+    fn run_forever(&self) -> Result<(), Error> {
+        loop {
+            match self.rx.recv() {
+                Ok(func) => func(),
+                Err(err) => bail!("while waiting for events: {:?}", err),
+            }
+            self.render_all();
+
+            if Mux::get().unwrap().is_empty() {
+                info!("No more tabs; all done!");
+                return Ok(());
+            }
+        }
+    }
+
+    fn spawn_new_window(
+        &self,
+        _config: &Arc<Config>,
+        _fontconfig: &Rc<FontConfiguration>,
+        tab: &Rc<dyn Tab>,
+        _window_id: WindowId,
+    ) -> Fallible<()> {
+        let (rows, cols) = tab.renderer().physical_dimensions();
+        let mut screen = HeadlessScreen::new(rows, cols);
+        screen.render(tab);
+        self.screens.borrow_mut().insert(tab.tab_id(), screen);
+        Ok(())
+    }
+}
@@ -4,7 +4,7 @@ use crate::mux::tab::Tab;
 use crate::mux::window::WindowId;
 use crate::mux::Mux;
 use downcast_rs::{impl_downcast, Downcast};
-use failure::{format_err, Error, Fallible};
+use failure::{bail, format_err, Error, Fallible};
 use lazy_static::lazy_static;
 use promise::Executor;
 use serde_derive::*;
@@ -14,16 +14,19 @@ use std::sync::{Arc, Mutex};
 
 pub mod glium;
 pub mod guicommon;
+pub mod headless;
 pub mod muxserver;
+pub mod script;
 #[cfg(all(unix, not(feature = "force-glutin"), not(target_os = "macos")))]
 pub mod xwindows;
 
-#[derive(Debug, Deserialize, Clone, Copy)]
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
 pub enum FrontEndSelection {
     Glutin,
     X11,
     MuxServer,
     Null,
+    Headless,
 }
 
 impl Default for FrontEndSelection {
@@ -70,9 +73,10 @@ impl FrontEndSelection {
             #[cfg(all(unix, not(target_os = "macos")))]
             FrontEndSelection::X11 => xwindows::x11loop::X11FrontEnd::try_new(mux),
             #[cfg(not(all(unix, not(target_os = "macos"))))]
-            FrontEndSelection::X11 => failure::bail!("X11 not compiled in"),
+            FrontEndSelection::X11 => bail!("X11 not compiled in"),
             FrontEndSelection::MuxServer => muxserver::MuxServerFrontEnd::try_new(mux),
             FrontEndSelection::Null => muxserver::MuxServerFrontEnd::new_null(mux),
+            FrontEndSelection::Headless => headless::HeadlessFrontEnd::try_new(mux),
         }?;
 
         EXECUTOR.lock().unwrap().replace(front_end.gui_executor());
@@ -83,7 +87,7 @@ impl FrontEndSelection {
 
     // TODO: find or build a proc macro for this
     pub fn variants() -> Vec<&'static str> {
-        vec!["Glutin", "X11", "MuxServer", "Null"]
+        vec!["Glutin", "X11", "MuxServer", "Null", "Headless"]
     }
 }
 
@@ -95,6 +99,7 @@ impl std::str::FromStr for FrontEndSelection {
             "x11" => Ok(FrontEndSelection::X11),
             "muxserver" => Ok(FrontEndSelection::MuxServer),
             "null" => Ok(FrontEndSelection::Null),
+            "headless" => Ok(FrontEndSelection::Headless),
             _ => Err(format_err!(
                 "{} is not a valid FrontEndSelection variant, possible values are {:?}",
                 s,
@@ -118,5 +123,18 @@ pub trait FrontEnd: Downcast {
     ) -> Fallible<()>;
 
     fn gui_executor(&self) -> Box<dyn Executor>;
+
+    /// Returns the contents of the clipboard on behalf of a program
+    /// that queried it via OSC 52.  Front ends without access to a
+    /// clipboard, such as the mux server, return an error.
+    fn get_clipboard(&self) -> Fallible<String> {
+        bail!("this front end has no clipboard")
+    }
+
+    /// Returns the mux window of the gui window that covers the point
+    /// `x`, `y` on the screen, if there is one
+    fn window_at(&self, _x: i64, _y: i64) -> Option<WindowId> {
+        None
+    }
 }
 impl_downcast!(FrontEnd);
@@ -1,13 +1,13 @@
 //! Implements the multiplexer server frontend
-use crate::config::Config;
+use crate::config::{pid_file_path, runtime_dir, Config};
 use crate::font::FontConfiguration;
 use crate::frontend::FrontEnd;
 use crate::mux::tab::Tab;
 use crate::mux::window::WindowId;
 use crate::mux::Mux;
 use crate::server::listener::spawn_listener;
-use failure::{bail, Error, Fallible};
-use log::info;
+use failure::{bail, format_err, Error, Fallible};
+use log::{error, info};
 use promise::Executor;
 use promise::SpawnFunc;
 use std::rc::Rc;
@@ -30,9 +30,32 @@ impl Executor for MuxExecutor {
     }
 }
 
+/// Records the pid of this server in the runtime directory so that
+/// scripts can find it
+fn write_pid_file() -> Fallible<()> {
+    runtime_dir()?;
+    let path = pid_file_path();
+    std::fs::write(&path, format!("{}\n", std::process::id()))
+        .map_err(|e| format_err!("failed to write {}: {}", path.display(), e))
+}
+
+/// Removes the pid file on the way out, unless another server has
+/// since replaced it with its own pid
+fn remove_pid_file() {
+    let path = pid_file_path();
+    let ours = format!("{}\n", std::process::id());
+    if std::fs::read_to_string(&path).ok().as_ref() == Some(&ours) {
+        if let Err(err) = std::fs::remove_file(&path) {
+            error!("failed to remove {}: {}", path.display(), err);
+        }
+    }
+}
+
 pub struct MuxServerFrontEnd {
     tx: SyncSender<SpawnFunc>,
     rx: Receiver<SpawnFunc>,
+    /// Whether this server wrote the pid file, and so removes it
+    wrote_pid_file: bool,
 }
 
 impl MuxServerFrontEnd {
@@ -41,9 +64,14 @@ impl MuxServerFrontEnd {
         let (tx, rx) = mpsc::sync_channel(4);
 
         if start_listener {
-            spawn_listener(mux.config(), Box::new(MuxExecutor { tx: tx.clone() }))?;
+            spawn_listener(&mux.config(), Box::new(MuxExecutor { tx: tx.clone() }))?;
+            write_pid_file()?;
         }
-        Ok(Rc::new(Self { tx, rx }))
+        Ok(Rc::new(Self {
+            tx,
+            rx,
+            wrote_pid_file: start_listener,
+        }))
     }
 
     pub fn try_new(mux: &Rc<Mux>) -> Result<Rc<dyn FrontEnd>, Error> {
@@ -53,16 +81,8 @@ impl MuxServerFrontEnd {
     pub fn new_null(mux: &Rc<Mux>) -> Result<Rc<dyn FrontEnd>, Error> {
         Self::new(mux, false)
     }
-}
-
-impl FrontEnd for MuxServerFrontEnd {
-    fn gui_executor(&self) -> Box<dyn Executor> {
-        Box::new(MuxExecutor {
-            tx: self.tx.clone(),
-        })
-    }
 
-    fn run_forever(&self) -> Result<(), Error> {
+    fn run_until_empty(&self) -> Fallible<()> {
         loop {
             match self.rx.recv() {
                 Ok(func) => func(),
@@ -75,6 +95,22 @@ impl FrontEnd for MuxServerFrontEnd {
             }
         }
     }
+}
+
+impl FrontEnd for MuxServerFrontEnd {
+    fn gui_executor(&self) -> Box<dyn Executor> {
+        Box::new(MuxExecutor {
+            tx: self.tx.clone(),
+        })
+    }
+
+    fn run_forever(&self) -> Result<(), Error> {
+        let result = self.run_until_empty();
+        if self.wrote_pid_file {
+            remove_pid_file();
+        }
+        result
+    }
 
     fn spawn_new_window(
         &self,
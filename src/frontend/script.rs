@@ -0,0 +1,256 @@
+//! Drives a tab from a script, so that a session can be reproduced
+//! without anyone at the keyboard.  This is used with the Null front
+//! end to produce golden output in tests and to reproduce rendering
+//! problems in CI.
+//!
+//! A script is a sequence of commands, one per line:
+//!
+//! ```text
+//! # Blank lines and lines that start with # are ignored.
+//! # Wait for 200 milliseconds
+//! sleep 200
+//! # Type text; \r \n \t \e \\ and \xNN are escapes
+//! send echo hello\r
+//! # Wait until the screen contains the text
+//! wait-for hello
+//! # Change how long wait-for waits; it defaults to 10 seconds
+//! timeout 5000
+//! # Print the screen to stdout
+//! dump
+//! ```
+//!
+//! Once the script is done, the program running in the tab is given
+//! the same timeout to exit.
+use crate::mux::tab::{Tab, TabId};
+use crate::mux::Mux;
+use failure::{bail, format_err, Fallible};
+use portable_pty::ExitStatus;
+use promise::{Executor, Future};
+use std::io::Write;
+use std::path::Path;
+use std::rc::Rc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    Sleep(Duration),
+    Send(Vec<u8>),
+    WaitFor(String),
+    Timeout(Duration),
+    Dump,
+}
+
+/// Parses the text of a script
+pub fn parse_script(script: &str) -> Fallible<Vec<Step>> {
+    let mut steps = vec![];
+    for (idx, line) in script.lines().enumerate() {
+        let line = line.trim_start();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (command, arg) = match line.find(' ') {
+            Some(space) => (&line[..space], &line[space + 1..]),
+            None => (line, ""),
+        };
+        let step = match command {
+            "sleep" => Step::Sleep(parse_millis(arg)?),
+            "send" => Step::Send(unescape(arg)?),
+            "wait-for" => Step::WaitFor(
+                String::from_utf8(unescape(arg)?)
+                    .map_err(|_| format_err!("line {}: wait-for text is not UTF-8", idx + 1))?,
+            ),
+            "timeout" => Step::Timeout(parse_millis(arg)?),
+            "dump" => Step::Dump,
+            _ => bail!("line {}: unknown command `{}`", idx + 1, command),
+        };
+        steps.push(step);
+    }
+    Ok(steps)
+}
+
+fn parse_millis(arg: &str) -> Fallible<Duration> {
+    let millis: u64 = arg
+        .trim()
+        .parse()
+        .map_err(|e| format_err!("invalid number of milliseconds `{}`: {}", arg, e))?;
+    Ok(Duration::from_millis(millis))
+}
+
+/// Expands the escapes in the argument of a `send` or `wait-for`.
+/// `\xNN` produces the byte NN as it is, rather than the character
+/// U+00NN, so that arbitrary bytes can be sent to the program.
+fn unescape(arg: &str) -> Fallible<Vec<u8>> {
+    let mut result = vec![];
+    let mut chars = arg.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0u8; 4];
+            result.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('r') => result.push(b'\r'),
+            Some('n') => result.push(b'\n'),
+            Some('t') => result.push(b'\t'),
+            Some('e') => result.push(b'\x1b'),
+            Some('\\') => result.push(b'\\'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                let value = u8::from_str_radix(&hex, 16)
+                    .map_err(|e| format_err!("invalid escape \\x{}: {}", hex, e))?;
+                result.push(value);
+            }
+            Some(c) => bail!("invalid escape \\{}", c),
+            None => bail!("`{}` ends with a backslash", arg),
+        }
+    }
+    Ok(result)
+}
+
+/// Runs the script at `path` against `tab_id` on a separate thread.
+/// Once the script is done and the program in the tab has exited,
+/// the tab is removed so that the front end stops running.  The
+/// thread returns the exit status of the program.  The tab must be
+/// held open when the program exits, via the `exit_behavior`
+/// setting, so that its status can be collected.
+pub fn spawn_script(
+    path: &Path,
+    tab_id: TabId,
+    executor: Box<dyn Executor>,
+) -> Fallible<JoinHandle<Fallible<ExitStatus>>> {
+    let script = std::fs::read_to_string(path)
+        .map_err(|e| format_err!("failed to read {}: {}", path.display(), e))?;
+    let steps = parse_script(&script).map_err(|e| format_err!("{}: {}", path.display(), e))?;
+    Ok(thread::spawn(move || {
+        let result = run_steps(&steps, tab_id, &*executor);
+        Future::with_executor(executor.clone_executor(), move || {
+            Mux::get().unwrap().remove_tab(tab_id);
+            Ok(())
+        });
+        result
+    }))
+}
+
+fn run_steps(steps: &[Step], tab_id: TabId, executor: &dyn Executor) -> Fallible<ExitStatus> {
+    let mut timeout = DEFAULT_TIMEOUT;
+    for step in steps {
+        match step {
+            Step::Sleep(duration) => thread::sleep(*duration),
+            Step::Timeout(duration) => timeout = *duration,
+            Step::Send(text) => {
+                let text = text.clone();
+                Future::with_executor(executor.clone_executor(), move || {
+                    let tab = get_tab(tab_id)?;
+                    tab.writer().write_all(&text)?;
+                    Ok(())
+                })
+                .wait()?;
+            }
+            Step::WaitFor(text) => {
+                let deadline = Instant::now() + timeout;
+                loop {
+                    let screen = screen_text(tab_id, executor)?;
+                    if screen.contains(text.as_str()) {
+                        break;
+                    }
+                    if Instant::now() >= deadline {
+                        bail!(
+                            "timed out waiting for `{}`; the screen is:\n{}",
+                            text,
+                            screen
+                        );
+                    }
+                    thread::sleep(POLL_INTERVAL);
+                }
+            }
+            Step::Dump => println!("{}", screen_text(tab_id, executor)?),
+        }
+    }
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        let held_exit = Future::with_executor(executor.clone_executor(), move || {
+            let tab = get_tab(tab_id)?;
+            // Checking for death is what notices that the program exited
+            tab.is_dead();
+            Ok(tab.held_exit())
+        })
+        .wait()?;
+        if let Some(status) = held_exit {
+            return Ok(status);
+        }
+        if Instant::now() >= deadline {
+            bail!("timed out waiting for the program in the tab to exit");
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn get_tab(tab_id: TabId) -> Fallible<Rc<dyn Tab>> {
+    Mux::get()
+        .unwrap()
+        .get_tab(tab_id)
+        .ok_or_else(|| format_err!("tab {} went away", tab_id))
+}
+
+/// Returns the visible lines of the tab with trailing whitespace and
+/// trailing blank lines removed
+fn screen_text(tab_id: TabId, executor: &dyn Executor) -> Fallible<String> {
+    Future::with_executor(executor.clone_executor(), move || {
+        let snapshot = get_tab(tab_id)?.snapshot(None);
+        let mut lines: Vec<String> = snapshot
+            .lines
+            .iter()
+            .map(|(line, _)| line.as_str().trim_end().to_string())
+            .collect();
+        while lines.last().map(|l| l.is_empty()).unwrap_or(false) {
+            lines.pop();
+        }
+        Ok(lines.join("\n"))
+    })
+    .wait()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let steps =
+            parse_script("# a comment\n\nsleep 20\nsend ls\\r\ntimeout 500\nwait-for $ \ndump\n")
+                .unwrap();
+        assert_eq!(
+            steps,
+            vec![
+                Step::Sleep(Duration::from_millis(20)),
+                Step::Send(b"ls\r".to_vec()),
+                Step::Timeout(Duration::from_millis(500)),
+                Step::WaitFor("$ ".to_string()),
+                Step::Dump,
+            ]
+        );
+        assert!(parse_script("type hello").is_err());
+        assert!(parse_script("sleep soon").is_err());
+        assert!(parse_script("wait-for \\xff").is_err());
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(
+            unescape("a\\tb\\e[0m\\x03\\\\").unwrap(),
+            b"a\tb\x1b[0m\x03\\".to_vec()
+        );
+        assert_eq!(
+            unescape("\\xff\\x80é").unwrap(),
+            b"\xff\x80\xc3\xa9".to_vec()
+        );
+        assert!(unescape("\\q").is_err());
+        assert!(unescape("trailing\\").is_err());
+        assert!(unescape("\\xZZ").is_err());
+    }
+}
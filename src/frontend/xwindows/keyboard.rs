@@ -1,6 +1,7 @@
 use super::xkeysyms::keysym_to_keycode;
 use super::Error;
 use super::{KeyCode, KeyModifiers};
+use crate::frontend::guicommon::input::{translate_key, ModifierState};
 use failure::{ensure, format_err};
 use libc;
 use log::debug;
@@ -151,7 +152,7 @@ impl Keyboard {
             }
         };
 
-        Some((kc, self.get_key_modifiers()))
+        Some(translate_key(kc, self.get_modifier_state()))
     }
 
     fn mod_is_active(&self, modifier: &str) -> bool {
@@ -161,28 +162,17 @@ impl Keyboard {
             .mod_name_is_active(modifier, xkb::STATE_MODS_EFFECTIVE)
     }
 
-    pub fn get_key_modifiers(&self) -> KeyModifiers {
-        let mut res = KeyModifiers::default();
-
-        if self.mod_is_active(xkb::MOD_NAME_SHIFT) {
-            res |= KeyModifiers::SHIFT;
-        }
-        if self.mod_is_active(xkb::MOD_NAME_CTRL) {
-            res |= KeyModifiers::CTRL;
-        }
-        if self.mod_is_active(xkb::MOD_NAME_ALT) {
+    fn get_modifier_state(&self) -> ModifierState {
+        ModifierState {
+            shift: self.mod_is_active(xkb::MOD_NAME_SHIFT),
+            ctrl: self.mod_is_active(xkb::MOD_NAME_CTRL),
             // Mod1
-            res |= KeyModifiers::ALT;
-        }
-        if self.mod_is_active(xkb::MOD_NAME_LOGO) {
+            alt: self.mod_is_active(xkb::MOD_NAME_ALT),
             // Mod4
-            res |= KeyModifiers::SUPER;
-        }
-        if self.mod_is_active("Mod3") {
-            res |= KeyModifiers::SUPER;
+            logo: self.mod_is_active(xkb::MOD_NAME_LOGO) || self.mod_is_active("Mod3"),
+            // Mod5 is ISO_Level3_Shift; Mod2 is numlock
+            alt_gr: self.mod_is_active("Mod5"),
         }
-        //Mod2 is numlock
-        res
     }
 
     pub fn process_xkb_event(
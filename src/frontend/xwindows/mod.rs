@@ -1,3 +1,4 @@
+use crate::frontend::guicommon::window::MouseCursor;
 use log::debug;
 use term::{KeyCode, KeyModifiers};
 mod keyboard;
@@ -10,6 +11,7 @@ use keyboard::Keyboard;
 use mio::unix::EventedFd;
 use mio::{Evented, Poll, PollOpt, Ready, Token};
 use std::cell::RefCell;
+use std::collections::HashMap;
 use std::io;
 use std::mem;
 use std::ops::Deref;
@@ -27,6 +29,7 @@ pub type Result<T> = result::Result<T, Error>;
 
 mod xkeysyms;
 pub use self::xkeysyms::*;
+pub mod selection;
 pub mod x11loop;
 pub mod xwin;
 
@@ -42,9 +45,20 @@ pub struct Connection {
     pub atom_xsel_data: xcb::Atom,
     pub atom_targets: xcb::Atom,
     pub atom_clipboard: xcb::Atom,
+    atom_resource_manager: xcb::Atom,
+    atom_net_wm_state: xcb::Atom,
+    atom_net_wm_state_above: xcb::Atom,
+    root: xcb::xproto::Window,
+    /// The code of the first RandR event, if the server supports it
+    randr_first_event: Option<u8>,
     keysyms: *mut xcb_key_symbols_t,
     egl_display: Rc<egli::Display>,
     egl_config: egli::FrameBufferConfigRef,
+    /// An EGL config with an alpha channel, together with the 32 bit
+    /// visual that matches it, for windows that are translucent
+    argb_config: Option<(egli::FrameBufferConfigRef, xcb::Visualid)>,
+    /// The mouse cursors created from the cursor font so far
+    cursors: RefCell<HashMap<MouseCursor, xcb::Cursor>>,
 }
 
 impl Deref for Connection {
@@ -119,6 +133,39 @@ impl Connection {
         let atom_clipboard = xcb::intern_atom(&conn, false, "CLIPBOARD")
             .get_reply()?
             .atom();
+        let atom_resource_manager = xcb::intern_atom(&conn, false, "RESOURCE_MANAGER")
+            .get_reply()?
+            .atom();
+        let atom_net_wm_state = xcb::intern_atom(&conn, false, "_NET_WM_STATE")
+            .get_reply()?
+            .atom();
+        let atom_net_wm_state_above = xcb::intern_atom(&conn, false, "_NET_WM_STATE_ABOVE")
+            .get_reply()?
+            .atom();
+
+        let root = conn
+            .get_setup()
+            .roots()
+            .nth(screen_num as usize)
+            .ok_or_else(|| err_msg("no screen?"))?
+            .root();
+
+        // Desktops publish their scale factor as the Xft.dpi resource,
+        // so watch the root window for changes to the resources, and
+        // for changes to the screen configuration, such as a monitor
+        // being attached, after which the scale may be different.
+        xcb::change_window_attributes(
+            &conn,
+            root,
+            &[(xcb::CW_EVENT_MASK, xcb::EVENT_MASK_PROPERTY_CHANGE)],
+        );
+        let randr_first_event = conn
+            .get_extension_data(xcb::randr::id())
+            .filter(|ext| ext.present())
+            .map(|ext| ext.first_event());
+        if randr_first_event.is_some() {
+            xcb::randr::select_input(&conn, root, xcb::randr::NOTIFY_MASK_SCREEN_CHANGE as u16);
+        }
 
         let keysyms = unsafe { xcb_key_symbols_alloc(conn.get_raw_conn()) };
 
@@ -145,6 +192,30 @@ impl Connection {
             .first()
             .ok_or_else(|| err_msg("no compatible EGL configuration was found"))?;
 
+        let argb_config = egl_display
+            .config_filter()
+            .with_red_size(8)
+            .with_green_size(8)
+            .with_blue_size(8)
+            .with_alpha_size(8)
+            .with_depth_size(24)
+            .with_surface_type(egli::SurfaceType::WINDOW)
+            .with_renderable_type(egli::RenderableType::OPENGL_ES2)
+            .with_conformant(egli::RenderableType::OPENGL_ES2)
+            .choose_configs()
+            .ok()
+            .and_then(|configs| {
+                configs.into_iter().find_map(|config| {
+                    let visual_id = config.native_visual_id().ok()? as xcb::Visualid;
+                    if is_argb_visual(&conn, screen_num, visual_id) {
+                        Some((config, visual_id))
+                    } else {
+                        None
+                    }
+                })
+            });
+        debug!("ARGB visual: {:?}", argb_config.map(|(_, visual)| visual));
+
         let (keyboard, kbd_ev) = Keyboard::new(&conn)?;
         Ok(Connection {
             display,
@@ -159,8 +230,15 @@ impl Connection {
             atom_utf8_string,
             atom_xsel_data,
             atom_targets,
+            atom_resource_manager,
+            atom_net_wm_state,
+            atom_net_wm_state_above,
+            root,
+            randr_first_event,
             egl_display: Rc::new(egl_display),
             egl_config: first_config,
+            argb_config,
+            cursors: RefCell::new(HashMap::new()),
         })
     }
 
@@ -176,6 +254,79 @@ impl Connection {
         self.atom_delete
     }
 
+    /// Returns the cursor for `shape`, creating it from the glyphs of
+    /// the standard cursor font the first time that it is used
+    pub fn cursor(&self, shape: MouseCursor) -> xcb::Cursor {
+        *self.cursors.borrow_mut().entry(shape).or_insert_with(|| {
+            // The glyph indices from X11/cursorfont.h; the mask of
+            // each glyph is the one that follows it in the font
+            let glyph = match shape {
+                MouseCursor::Arrow => 68, // XC_left_ptr
+                MouseCursor::Text => 152, // XC_xterm
+                MouseCursor::Hand => 60,  // XC_hand2
+            };
+            let font = self.conn.generate_id();
+            xcb::open_font(&self.conn, font, "cursor");
+            let cursor = self.conn.generate_id();
+            xcb::create_glyph_cursor(
+                &self.conn,
+                cursor,
+                font,
+                font,
+                glyph,
+                glyph + 1,
+                // black foreground, white background
+                0,
+                0,
+                0,
+                0xffff,
+                0xffff,
+                0xffff,
+            );
+            xcb::close_font(&self.conn, font);
+            cursor
+        })
+    }
+
+    /// Returns the scale factor that the desktop asks applications to
+    /// use, which is derived from the Xft.dpi resource; 96 dpi is a
+    /// scale of 1.0.  X11 has a single scale for the whole screen.
+    pub fn dpi_scale(&self) -> f64 {
+        match xcb::get_property(
+            &self.conn,
+            false,
+            self.root,
+            self.atom_resource_manager,
+            xcb::ATOM_STRING,
+            0,
+            1024 * 1024,
+        )
+        .get_reply()
+        {
+            Ok(reply) => {
+                xft_dpi_scale(&String::from_utf8_lossy(reply.value::<u8>())).unwrap_or(1.0)
+            }
+            Err(err) => {
+                debug!("unable to read RESOURCE_MANAGER: {:?}", err);
+                1.0
+            }
+        }
+    }
+
+    /// Returns true if `event` indicates that the dpi scale may
+    /// have changed
+    pub fn is_dpi_change_event(&self, event: &xcb::GenericEvent) -> bool {
+        let r = event.response_type() & 0x7f;
+        if r == xcb::PROPERTY_NOTIFY {
+            let prop: &xcb::PropertyNotifyEvent = unsafe { xcb::cast_event(event) };
+            prop.window() == self.root && prop.atom() == self.atom_resource_manager
+        } else {
+            self.randr_first_event
+                .map(|first| r == first + xcb::randr::SCREEN_CHANGE_NOTIFY)
+                .unwrap_or(false)
+        }
+    }
+
     pub fn xkb_lookup_keysym(&self, event: &xcb::KeyPressEvent) -> Option<(KeyCode, KeyModifiers)> {
         self.keyboard.process_key_event(event)
     }
@@ -189,20 +340,62 @@ impl Drop for Connection {
     }
 }
 
+/// Returns true if `visual_id` is a 32 bit visual of the screen,
+/// which a compositor will blend using its alpha channel
+fn is_argb_visual(conn: &xcb::Connection, screen_num: i32, visual_id: xcb::Visualid) -> bool {
+    conn.get_setup()
+        .roots()
+        .nth(screen_num as usize)
+        .map(|screen| {
+            screen
+                .allowed_depths()
+                .filter(|depth| depth.depth() == 32)
+                .any(|depth| {
+                    depth
+                        .visuals()
+                        .any(|visual| visual.visual_id() == visual_id)
+                })
+        })
+        .unwrap_or(false)
+}
+
+/// Extracts the scale factor from the `Xft.dpi` entry of the
+/// X resources, which are formatted as `name:\tvalue` lines
+fn xft_dpi_scale(resources: &str) -> Option<f64> {
+    resources.lines().find_map(|line| {
+        let mut parts = line.splitn(2, ':');
+        if parts.next()?.trim() != "Xft.dpi" {
+            return None;
+        }
+        let dpi: f64 = parts.next()?.trim().parse().ok()?;
+        if dpi > 0.0 {
+            Some(dpi / 96.0)
+        } else {
+            None
+        }
+    })
+}
+
 struct WindowHolder {
     window_id: xcb::xproto::Window,
     conn: Rc<Connection>,
+    colormap: Option<xcb::Colormap>,
 }
 
 impl Drop for WindowHolder {
     fn drop(&mut self) {
         xcb::destroy_window(self.conn.conn(), self.window_id);
+        if let Some(colormap) = self.colormap {
+            xcb::free_colormap(self.conn.conn(), colormap);
+        }
     }
 }
 
 struct GlState {
     display: Rc<egli::Display>,
-    surface: egli::Surface,
+    /// The surface outlives the context when the context is lost and
+    /// replaced, as a window can only have one surface at a time
+    surface: Rc<egli::Surface>,
     egl_context: egli::Context,
     // It's not dead, it's owning a ref for gl
     #[allow(dead_code)]
@@ -213,14 +406,27 @@ struct GlState {
 pub struct Window {
     window: Rc<WindowHolder>,
     conn: Rc<Connection>,
+    egl_config: egli::FrameBufferConfigRef,
     gl: Rc<GlState>,
     glium_context: Rc<glium::backend::Context>,
 }
 
 impl Window {
     /// Create a new window on the specified screen with the specified
-    /// dimensions
-    pub fn new(conn: &Rc<Connection>, width: u16, height: u16) -> Result<Window> {
+    /// dimensions.  A `translucent` window uses a 32 bit visual, if the
+    /// server has one, so that a compositor can blend it with whatever
+    /// is beneath it.
+    pub fn new(
+        conn: &Rc<Connection>,
+        width: u16,
+        height: u16,
+        translucent: bool,
+    ) -> Result<Window> {
+        let argb_config = if translucent { conn.argb_config } else { None };
+        let egl_config = argb_config
+            .map(|(config, _)| config)
+            .unwrap_or(conn.egl_config);
+
         let window = {
             let setup = conn.conn().get_setup();
             let screen = setup
@@ -229,10 +435,47 @@ impl Window {
                 .ok_or_else(|| err_msg("no screen?"))?;
 
             let window_id = conn.conn().generate_id();
+            let event_mask = xcb::EVENT_MASK_EXPOSURE
+                | xcb::EVENT_MASK_KEY_PRESS
+                | xcb::EVENT_MASK_BUTTON_PRESS
+                | xcb::EVENT_MASK_BUTTON_RELEASE
+                | xcb::EVENT_MASK_POINTER_MOTION
+                | xcb::EVENT_MASK_BUTTON_MOTION
+                | xcb::EVENT_MASK_KEY_RELEASE
+                | xcb::EVENT_MASK_FOCUS_CHANGE
+                | xcb::EVENT_MASK_STRUCTURE_NOTIFY;
+
+            let (depth, visual, colormap) = match argb_config {
+                Some((_, visual)) => {
+                    // A window whose visual differs from that of its
+                    // parent needs its own colormap and border pixel
+                    let colormap = conn.conn().generate_id();
+                    xcb::create_colormap(
+                        conn.conn(),
+                        xcb::COLORMAP_ALLOC_NONE as u8,
+                        colormap,
+                        screen.root(),
+                        visual,
+                    );
+                    (32, visual, Some(colormap))
+                }
+                None => (xcb::COPY_FROM_PARENT as u8, screen.root_visual(), None),
+            };
+
+            // The values must be in the order of their CW_ bits
+            let values = match colormap {
+                Some(colormap) => vec![
+                    (xcb::CW_BACK_PIXEL, 0),
+                    (xcb::CW_BORDER_PIXEL, 0),
+                    (xcb::CW_EVENT_MASK, event_mask),
+                    (xcb::CW_COLORMAP, colormap),
+                ],
+                None => vec![(xcb::CW_EVENT_MASK, event_mask)],
+            };
 
             xcb::create_window_checked(
                 conn.conn(),
-                xcb::COPY_FROM_PARENT as u8,
+                depth,
                 window_id,
                 screen.root(),
                 // x, y
@@ -244,23 +487,14 @@ impl Window {
                 // border width
                 0,
                 xcb::WINDOW_CLASS_INPUT_OUTPUT as u16,
-                screen.root_visual(),
-                &[(
-                    xcb::CW_EVENT_MASK,
-                    xcb::EVENT_MASK_EXPOSURE
-                        | xcb::EVENT_MASK_KEY_PRESS
-                        | xcb::EVENT_MASK_BUTTON_PRESS
-                        | xcb::EVENT_MASK_BUTTON_RELEASE
-                        | xcb::EVENT_MASK_POINTER_MOTION
-                        | xcb::EVENT_MASK_BUTTON_MOTION
-                        | xcb::EVENT_MASK_KEY_RELEASE
-                        | xcb::EVENT_MASK_STRUCTURE_NOTIFY,
-                )],
+                visual,
+                &values,
             )
             .request_check()?;
             Rc::new(WindowHolder {
                 window_id,
                 conn: Rc::clone(conn),
+                colormap,
             })
         };
 
@@ -274,17 +508,33 @@ impl Window {
             &[conn.atom_delete],
         );
 
-        let surface = conn
-            .egl_display
-            .create_window_surface(conn.egl_config, window.window_id as *mut _)
-            .map_err(egli_err)?;
+        let surface = Rc::new(
+            conn.egl_display
+                .create_window_surface(egl_config, window.window_id as *mut _)
+                .map_err(egli_err)?,
+        );
+
+        let (gl, glium_context) = Self::create_context(conn, egl_config, surface, &window)?;
 
+        Ok(Window {
+            conn: Rc::clone(conn),
+            window,
+            egl_config,
+            gl,
+            glium_context,
+        })
+    }
+
+    /// Creates an OpenGL context that renders to `surface`
+    fn create_context(
+        conn: &Rc<Connection>,
+        egl_config: egli::FrameBufferConfigRef,
+        surface: Rc<egli::Surface>,
+        window: &Rc<WindowHolder>,
+    ) -> Result<(Rc<GlState>, Rc<glium::backend::Context>)> {
         let egl_context = conn
             .egl_display
-            .create_context_with_client_version(
-                conn.egl_config,
-                egli::ContextClientVersion::OpenGlEs2,
-            )
+            .create_context_with_client_version(egl_config, egli::ContextClientVersion::OpenGlEs2)
             .map_err(egli_err)?;
 
         conn.egl_display
@@ -297,7 +547,7 @@ impl Window {
             display: Rc::clone(&conn.egl_display),
             egl_context,
             surface,
-            window: Rc::clone(&window),
+            window: Rc::clone(window),
         });
 
         let glium_context = unsafe {
@@ -312,13 +562,29 @@ impl Window {
                 },
             )?
         };
+        Ok((gl_state, glium_context))
+    }
 
-        Ok(Window {
-            conn: Rc::clone(conn),
-            window,
-            gl: gl_state,
-            glium_context,
-        })
+    /// Returns true if the OpenGL context has been lost.  The context
+    /// is created without robustness, so a reset of the GPU goes
+    /// unreported here.
+    pub fn is_context_lost(&self) -> bool {
+        self.glium_context.is_context_lost()
+    }
+
+    /// Replaces the OpenGL context, which has been lost, with a new
+    /// one that renders to the same surface.  Everything created with
+    /// the old context must be created again with the new one.
+    pub fn recreate_context(&mut self) -> Result<()> {
+        let (gl, glium_context) = Self::create_context(
+            &self.conn,
+            self.egl_config,
+            Rc::clone(&self.gl.surface),
+            &self.window,
+        )?;
+        self.gl = gl;
+        self.glium_context = glium_context;
+        Ok(())
     }
 
     /// Change the title for the window manager
@@ -326,6 +592,72 @@ impl Window {
         xcb_util::icccm::set_wm_name(self.conn.conn(), self.window.window_id, title);
     }
 
+    /// Change the mouse cursor shown while the mouse is over the window
+    pub fn set_cursor(&self, shape: MouseCursor) {
+        xcb::change_window_attributes(
+            self.conn.conn(),
+            self.window.window_id,
+            &[(xcb::CW_CURSOR, self.conn.cursor(shape))],
+        );
+        self.conn.flush();
+    }
+
+    /// Ask the window manager to resize the window.  The new size
+    /// is reported back to us via a ConfigureNotify event.
+    pub fn set_inner_size(&self, width: u16, height: u16) {
+        xcb::configure_window(
+            self.conn.conn(),
+            self.window.window_id,
+            &[
+                (xcb::CONFIG_WINDOW_WIDTH as u16, u32::from(width)),
+                (xcb::CONFIG_WINDOW_HEIGHT as u16, u32::from(height)),
+            ],
+        );
+    }
+
+    /// Returns the position of the top left corner of the window on
+    /// the screen
+    pub fn screen_position(&self) -> Option<(i64, i64)> {
+        xcb::translate_coordinates(
+            self.conn.conn(),
+            self.window.window_id,
+            self.conn.root,
+            0,
+            0,
+        )
+        .get_reply()
+        .ok()
+        .map(|reply| (i64::from(reply.dst_x()), i64::from(reply.dst_y())))
+    }
+
+    /// Ask the window manager to keep the window above the windows
+    /// of other programs, or to stop doing so
+    pub fn set_always_on_top(&self, above: bool) {
+        // _NET_WM_STATE_REMOVE is 0 and _NET_WM_STATE_ADD is 1; the
+        // last field says that the request comes from an application
+        let action = if above { 1 } else { 0 };
+        let event = xcb::ClientMessageEvent::new(
+            32,
+            self.window.window_id,
+            self.conn.atom_net_wm_state,
+            xcb::ClientMessageData::from_data32([
+                action,
+                self.conn.atom_net_wm_state_above,
+                0,
+                1,
+                0,
+            ]),
+        );
+        xcb::send_event(
+            self.conn.conn(),
+            false,
+            self.conn.root,
+            xcb::EVENT_MASK_SUBSTRUCTURE_REDIRECT | xcb::EVENT_MASK_SUBSTRUCTURE_NOTIFY,
+            &event,
+        );
+        self.conn.flush();
+    }
+
     /// Display the window
     pub fn show(&self) {
         xcb::map_window(self.conn.conn(), self.window.window_id);
@@ -365,12 +697,17 @@ impl Drop for GlState {
 
 unsafe impl glium::backend::Backend for GlState {
     fn swap_buffers(&self) -> result::Result<(), glium::SwapBuffersError> {
-        self.display.swap_buffers(&self.surface).map_err(|_| {
-            // We're guessing that this is the case as the other option
-            // that glium recognizes is threading related and we're
-            // single threaded.
-            glium::SwapBuffersError::AlreadySwapped
-        })
+        self.display
+            .swap_buffers(&self.surface)
+            .map_err(|err| match err {
+                // This is how EGL reports that a power management
+                // event, such as a suspend, took the context away
+                egli::error::Error::ContextLost => glium::SwapBuffersError::ContextLost,
+                // We're guessing that this is the case as the other
+                // option that glium recognizes is threading related
+                // and we're single threaded.
+                _ => glium::SwapBuffersError::AlreadySwapped,
+            })
     }
 
     unsafe fn get_proc_address(&self, symbol: &str) -> *const os::raw::c_void {
@@ -398,3 +735,19 @@ unsafe impl glium::backend::Backend for GlState {
         CURRENT.with(|id| *id.borrow_mut() = self as *const _);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn xft_dpi() {
+        assert_eq!(
+            xft_dpi_scale("Xft.antialias:\t1\nXft.dpi:\t192\nXft.hinting:\t1\n"),
+            Some(2.0)
+        );
+        assert_eq!(xft_dpi_scale("Xft.dpi: 144"), Some(1.5));
+        assert_eq!(xft_dpi_scale("Xft.antialias:\t1\n"), None);
+        assert_eq!(xft_dpi_scale("Xft.dpi:\tbogus\n"), None);
+    }
+}
@@ -0,0 +1,431 @@
+//! Implements the X11 PRIMARY and CLIPBOARD selections.
+//!
+//! X11 has no clipboard as such: the application that copied the text
+//! owns the selection and hands the text to whichever application
+//! asks for it.  The selections are owned by a hidden window so that
+//! they outlive the terminal window in which the text was selected.
+//! Text that is too large to send in one request is transferred in
+//! chunks using the INCR protocol described in the ICCCM.
+use super::Connection;
+use crate::frontend::guicommon::host::{ClipboardPasteSource, SystemSelections};
+use failure::{bail, format_err, Fallible};
+use log::debug;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
+use xcb;
+
+/// How long to wait for the owner of a selection to respond
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(2);
+/// The largest chunk of text that is sent in a single request
+const MAX_CHUNK_SIZE: usize = 256 * 1024;
+
+/// An incremental transfer of text to another application
+struct OutgoingTransfer {
+    requestor: xcb::Window,
+    property: xcb::Atom,
+    target: xcb::Atom,
+    text: Rc<String>,
+    offset: usize,
+}
+
+pub struct Selections {
+    conn: Rc<Connection>,
+    window: xcb::Window,
+    atom_incr: xcb::Atom,
+    chunk_size: usize,
+    /// The text of the selections that we own, by selection atom
+    owned: RefCell<HashMap<xcb::Atom, Rc<String>>>,
+    transfers: RefCell<Vec<OutgoingTransfer>>,
+    /// Events that arrived while waiting for the owner of a selection
+    /// and that are to be processed by the event loop
+    deferred: RefCell<VecDeque<xcb::GenericEvent>>,
+}
+
+impl Drop for Selections {
+    fn drop(&mut self) {
+        xcb::destroy_window(self.conn.conn(), self.window);
+    }
+}
+
+impl Selections {
+    pub fn new(conn: &Rc<Connection>) -> Fallible<Self> {
+        let atom_incr = xcb::intern_atom(conn, false, "INCR").get_reply()?.atom();
+
+        let window = conn.conn().generate_id();
+        xcb::create_window_checked(
+            conn.conn(),
+            0,
+            window,
+            conn.root,
+            // x, y
+            0,
+            0,
+            // width, height
+            1,
+            1,
+            // border width
+            0,
+            xcb::WINDOW_CLASS_INPUT_ONLY as u16,
+            0,
+            &[(xcb::CW_EVENT_MASK, xcb::EVENT_MASK_PROPERTY_CHANGE)],
+        )
+        .request_check()?;
+
+        // The maximum request length is measured in units of 4 bytes,
+        // so treating it as a number of bytes leaves plenty of room for
+        // the rest of the request
+        let chunk_size = (conn.get_maximum_request_length() as usize).min(MAX_CHUNK_SIZE);
+
+        Ok(Self {
+            conn: Rc::clone(conn),
+            window,
+            atom_incr,
+            chunk_size,
+            owned: RefCell::new(HashMap::new()),
+            transfers: RefCell::new(vec![]),
+            deferred: RefCell::new(VecDeque::new()),
+        })
+    }
+
+    fn atom(&self, source: ClipboardPasteSource) -> xcb::Atom {
+        match source {
+            ClipboardPasteSource::Clipboard => self.conn.atom_clipboard,
+            ClipboardPasteSource::PrimarySelection => xcb::ATOM_PRIMARY,
+        }
+    }
+
+    /// Asks the owner of `selection` to convert it to `target`,
+    /// returning None if it cannot
+    fn convert(&self, selection: xcb::Atom, target: xcb::Atom) -> Fallible<Option<Vec<u8>>> {
+        let property = self.conn.atom_xsel_data;
+        xcb::delete_property(&self.conn, self.window, property);
+        xcb::convert_selection(
+            &self.conn,
+            self.window,
+            selection,
+            target,
+            property,
+            xcb::CURRENT_TIME,
+        );
+        self.conn.flush();
+
+        let window = self.window;
+        let converted = self.wait_for(|event| {
+            if event.response_type() & 0x7f != xcb::SELECTION_NOTIFY {
+                return None;
+            }
+            let notify: &xcb::SelectionNotifyEvent = unsafe { xcb::cast_event(event) };
+            if notify.requestor() == window && notify.selection() == selection {
+                Some(notify.property())
+            } else {
+                None
+            }
+        })?;
+        if converted == xcb::NONE {
+            return Ok(None);
+        }
+
+        let reply = self.read_property(property)?;
+        if reply.type_() != self.atom_incr {
+            return Ok(Some(reply.value::<u8>().to_vec()));
+        }
+
+        // Deleting the INCR property, which read_property did, asks the
+        // owner for the first chunk.  Each chunk is deleted as it is
+        // read and the transfer ends with an empty chunk.
+        let mut data = vec![];
+        loop {
+            self.wait_for(|event| {
+                if event.response_type() & 0x7f != xcb::PROPERTY_NOTIFY {
+                    return None;
+                }
+                let notify: &xcb::PropertyNotifyEvent = unsafe { xcb::cast_event(event) };
+                if notify.window() == window
+                    && notify.atom() == property
+                    && notify.state() == xcb::PROPERTY_NEW_VALUE as u8
+                {
+                    Some(())
+                } else {
+                    None
+                }
+            })?;
+            let reply = self.read_property(property)?;
+            let chunk = reply.value::<u8>();
+            if chunk.is_empty() {
+                return Ok(Some(data));
+            }
+            data.extend_from_slice(chunk);
+        }
+    }
+
+    /// Reads and deletes a property of our window
+    fn read_property(&self, property: xcb::Atom) -> Fallible<xcb::GetPropertyReply> {
+        Ok(xcb::get_property(
+            &self.conn,
+            true,
+            self.window,
+            property,
+            xcb::GET_PROPERTY_TYPE_ANY,
+            0,
+            // The length is measured in units of 4 bytes
+            std::u32::MAX / 4,
+        )
+        .get_reply()?)
+    }
+
+    /// Processes events until `matches` accepts one.  Requests for the
+    /// selections that we own are answered in the meantime, and the
+    /// other events are deferred until the event loop is next idle.
+    fn wait_for<T, F: FnMut(&xcb::GenericEvent) -> Option<T>>(
+        &self,
+        mut matches: F,
+    ) -> Fallible<T> {
+        let deadline = Instant::now() + TRANSFER_TIMEOUT;
+        loop {
+            match self.conn.poll_for_event() {
+                Some(event) => {
+                    if let Some(result) = matches(&event) {
+                        return Ok(result);
+                    }
+                    if !self.process_event(&event)? {
+                        self.deferred.borrow_mut().push_back(event);
+                    }
+                }
+                None => {
+                    self.conn
+                        .has_error()
+                        .map_err(|err| format_err!("X11 connection is broken: {:?}", err))?;
+                    if Instant::now() >= deadline {
+                        bail!("timed out waiting for the owner of the selection");
+                    }
+                    thread::sleep(Duration::from_millis(5));
+                }
+            }
+        }
+    }
+
+    /// Returns the next of the events that arrived while waiting for
+    /// the owner of a selection
+    pub fn pop_deferred_event(&self) -> Option<xcb::GenericEvent> {
+        self.deferred.borrow_mut().pop_front()
+    }
+
+    /// Processes the events that concern the selections, returning
+    /// false for the events that are of no interest
+    pub fn process_event(&self, event: &xcb::GenericEvent) -> Fallible<bool> {
+        match event.response_type() & 0x7f {
+            xcb::SELECTION_REQUEST => {
+                let request: &xcb::SelectionRequestEvent = unsafe { xcb::cast_event(event) };
+                if request.owner() != self.window {
+                    return Ok(false);
+                }
+                self.selection_request(request);
+                Ok(true)
+            }
+            xcb::SELECTION_CLEAR => {
+                let clear: &xcb::SelectionClearEvent = unsafe { xcb::cast_event(event) };
+                if clear.owner() != self.window {
+                    return Ok(false);
+                }
+                // Another application took the selection
+                self.owned.borrow_mut().remove(&clear.selection());
+                Ok(true)
+            }
+            xcb::PROPERTY_NOTIFY => {
+                let notify: &xcb::PropertyNotifyEvent = unsafe { xcb::cast_event(event) };
+                if notify.state() != xcb::PROPERTY_DELETE as u8 {
+                    return Ok(false);
+                }
+                Ok(self.property_deleted(notify.window(), notify.atom()))
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn selection_request(&self, request: &xcb::SelectionRequestEvent) {
+        // Obsolete clients don't name a property in which to store
+        // the text, and expect the target to be used instead
+        let property = if request.property() == xcb::NONE {
+            request.target()
+        } else {
+            request.property()
+        };
+        let text = self.owned.borrow().get(&request.selection()).cloned();
+        let target = request.target();
+
+        let property = match text {
+            Some(_) if target == self.conn.atom_targets => {
+                xcb::change_property(
+                    &self.conn,
+                    xcb::PROP_MODE_REPLACE as u8,
+                    request.requestor(),
+                    property,
+                    xcb::ATOM_ATOM,
+                    32,
+                    &[
+                        self.conn.atom_targets,
+                        self.conn.atom_utf8_string,
+                        xcb::ATOM_STRING,
+                    ],
+                );
+                property
+            }
+            Some(text) if target == self.conn.atom_utf8_string || target == xcb::ATOM_STRING => {
+                self.send_text(request.requestor(), property, target, text);
+                property
+            }
+            _ => {
+                debug!(
+                    "refusing request for selection {} as target {}",
+                    request.selection(),
+                    target
+                );
+                xcb::NONE
+            }
+        };
+
+        let notify = xcb::SelectionNotifyEvent::new(
+            request.time(),
+            request.requestor(),
+            request.selection(),
+            target,
+            property,
+        );
+        xcb::send_event(
+            &self.conn,
+            false,
+            request.requestor(),
+            xcb::EVENT_MASK_NO_EVENT,
+            &notify,
+        );
+        self.conn.flush();
+    }
+
+    /// Stores the text in the property of the requestor, starting an
+    /// incremental transfer if it is too large for a single request
+    fn send_text(
+        &self,
+        requestor: xcb::Window,
+        property: xcb::Atom,
+        target: xcb::Atom,
+        text: Rc<String>,
+    ) {
+        if text.len() <= self.chunk_size {
+            xcb::change_property(
+                &self.conn,
+                xcb::PROP_MODE_REPLACE as u8,
+                requestor,
+                property,
+                target,
+                8,
+                text.as_bytes(),
+            );
+            return;
+        }
+
+        // The requestor deletes the INCR property to ask for the first
+        // chunk, and each chunk after that, so watch for that
+        xcb::change_window_attributes(
+            &self.conn,
+            requestor,
+            &[(xcb::CW_EVENT_MASK, xcb::EVENT_MASK_PROPERTY_CHANGE)],
+        );
+        xcb::change_property(
+            &self.conn,
+            xcb::PROP_MODE_REPLACE as u8,
+            requestor,
+            property,
+            self.atom_incr,
+            32,
+            &[text.len() as u32],
+        );
+        self.transfers.borrow_mut().push(OutgoingTransfer {
+            requestor,
+            property,
+            target,
+            text,
+            offset: 0,
+        });
+    }
+
+    /// Sends the next chunk of an incremental transfer once the
+    /// requestor has consumed the previous one
+    fn property_deleted(&self, window: xcb::Window, property: xcb::Atom) -> bool {
+        let mut transfers = self.transfers.borrow_mut();
+        let idx = match transfers
+            .iter()
+            .position(|t| t.requestor == window && t.property == property)
+        {
+            Some(idx) => idx,
+            None => return false,
+        };
+
+        let transfer = &mut transfers[idx];
+        let end = (transfer.offset + self.chunk_size).min(transfer.text.len());
+        let chunk = &transfer.text.as_bytes()[transfer.offset..end];
+        xcb::change_property(
+            &self.conn,
+            xcb::PROP_MODE_REPLACE as u8,
+            window,
+            property,
+            transfer.target,
+            8,
+            chunk,
+        );
+        if chunk.is_empty() {
+            // That was the empty chunk that ends the transfer
+            xcb::change_window_attributes(
+                &self.conn,
+                window,
+                &[(xcb::CW_EVENT_MASK, xcb::EVENT_MASK_NO_EVENT)],
+            );
+            transfers.remove(idx);
+        } else {
+            transfer.offset = end;
+        }
+        self.conn.flush();
+        true
+    }
+}
+
+impl SystemSelections for Selections {
+    /// Takes ownership of the selection so that other applications
+    /// paste `text` from it
+    fn set(&self, source: ClipboardPasteSource, text: String) -> Fallible<()> {
+        let selection = self.atom(source);
+        xcb::set_selection_owner(&self.conn, self.window, selection, xcb::CURRENT_TIME);
+        let owner = xcb::get_selection_owner(&self.conn, selection)
+            .get_reply()?
+            .owner();
+        if owner != self.window {
+            bail!("unable to take ownership of the {:?} selection", source);
+        }
+        self.owned.borrow_mut().insert(selection, Rc::new(text));
+        Ok(())
+    }
+
+    /// Returns the text of the selection, asking its owner for it if
+    /// that isn't us
+    fn get(&self, source: ClipboardPasteSource) -> Fallible<String> {
+        let selection = self.atom(source);
+        if let Some(text) = self.owned.borrow().get(&selection) {
+            return Ok(text.to_string());
+        }
+        let owner = xcb::get_selection_owner(&self.conn, selection)
+            .get_reply()?
+            .owner();
+        if owner == xcb::NONE {
+            return Ok(String::new());
+        }
+
+        for &target in &[self.conn.atom_utf8_string, xcb::ATOM_STRING] {
+            if let Some(data) = self.convert(selection, target)? {
+                return Ok(String::from_utf8_lossy(&data).into_owned());
+            }
+        }
+        bail!("the owner of the {:?} selection has no text in it", source);
+    }
+}
@@ -1,14 +1,17 @@
 use crate::config::Config;
 use crate::font::FontConfiguration;
+use crate::frontend::guicommon::attention;
+use crate::frontend::guicommon::toast;
 use crate::frontend::guicommon::window::TerminalWindow;
+use crate::frontend::xwindows::selection::Selections;
 use crate::frontend::xwindows::xwin::X11TerminalWindow;
 use crate::frontend::xwindows::Connection;
 use crate::frontend::FrontEnd;
-use crate::mux::tab::Tab;
+use crate::mux::tab::{Tab, TabId};
 use crate::mux::window::WindowId as MuxWindowId;
-use crate::mux::Mux;
+use crate::mux::{Mux, MuxNotification};
 use failure::{bail, Error, Fallible};
-use log::debug;
+use log::{debug, error};
 use mio::{Events, Poll, PollOpt, Ready, Token};
 use mio_extras::channel::{channel, Receiver as GuiReceiver, Sender as GuiSender};
 use promise::{Executor, Future, SpawnFunc};
@@ -46,6 +49,7 @@ impl Executor for X11GuiExecutor {
 pub struct GuiEventLoop {
     poll: Poll,
     pub conn: Rc<Connection>,
+    pub selections: Selections,
     windows: Rc<RefCell<Windows>>,
     interval: Duration,
     gui_rx: GuiReceiver<SpawnFunc>,
@@ -63,6 +67,66 @@ impl X11FrontEnd {
     pub fn try_new(mux: &Rc<Mux>) -> Result<Rc<dyn FrontEnd>, Error> {
         let event_loop = Rc::new(GuiEventLoop::new(mux)?);
         X11_EVENT_LOOP.with(|f| *f.borrow_mut() = Some(Rc::clone(&event_loop)));
+        let executor = event_loop.gui_executor();
+        mux.subscribe(move |notification| {
+            // Apply the change from the gui loop rather than while
+            // the mux is in the middle of notifying its subscribers
+            match notification {
+                MuxNotification::ConfigReloaded => {
+                    Future::with_executor(executor.clone_executor(), || {
+                        let myself = GuiEventLoop::get().expect("to be called on gui thread");
+                        myself.config_was_reloaded();
+                        Ok(())
+                    });
+                }
+                MuxNotification::Resumed => {
+                    Future::with_executor(executor.clone_executor(), || {
+                        let myself = GuiEventLoop::get().expect("to be called on gui thread");
+                        myself.system_resumed();
+                        Ok(())
+                    });
+                }
+                MuxNotification::SetClipboard(tab_id, clip) => {
+                    Future::with_executor(executor.clone_executor(), move || {
+                        let myself = GuiEventLoop::get().expect("to be called on gui thread");
+                        myself.set_clipboard(tab_id, clip)
+                    });
+                }
+                MuxNotification::Bell(tab_id) => {
+                    Future::with_executor(executor.clone_executor(), move || {
+                        attention::bell(tab_id);
+                        Ok(())
+                    });
+                }
+                MuxNotification::CurrentDirChanged(tab_id) => {
+                    Future::with_executor(executor.clone_executor(), move || {
+                        let myself = GuiEventLoop::get().expect("to be called on gui thread");
+                        myself.current_dir_changed(tab_id);
+                        Ok(())
+                    });
+                }
+                MuxNotification::TabOutput(tab_id) => {
+                    Future::with_executor(executor.clone_executor(), move || {
+                        attention::output(tab_id);
+                        Ok(())
+                    });
+                }
+                MuxNotification::TabRemoved(tab_id) => {
+                    Future::with_executor(executor.clone_executor(), move || {
+                        attention::forget(tab_id);
+                        Ok(())
+                    });
+                }
+                MuxNotification::Notice(text) => {
+                    Future::with_executor(executor.clone_executor(), move || {
+                        toast::show(None, &text);
+                        Ok(())
+                    });
+                }
+                _ => {}
+            }
+            true
+        });
         Ok(Rc::new(Self { event_loop }))
     }
 }
@@ -76,10 +140,27 @@ impl FrontEnd for X11FrontEnd {
         self.event_loop.gui_executor()
     }
 
+    fn get_clipboard(&self) -> Fallible<String> {
+        let mut windows = self.event_loop.windows.borrow_mut();
+        match windows.by_id.values_mut().next() {
+            Some(window) => window.get_clipboard(),
+            None => bail!("no window from which to read the clipboard"),
+        }
+    }
+
     fn run_forever(&self) -> Result<(), Error> {
         self.event_loop.run()
     }
 
+    fn window_at(&self, x: i64, y: i64) -> Option<MuxWindowId> {
+        let windows = self.event_loop.windows.borrow();
+        windows
+            .by_id
+            .values()
+            .find(|window| window.contains_screen_point(x, y))
+            .map(|window| window.get_mux_window_id())
+    }
+
     fn spawn_new_window(
         &self,
         config: &Arc<Config>,
@@ -108,8 +189,11 @@ impl GuiEventLoop {
             PollOpt::level(),
         )?;
 
+        let selections = Selections::new(&conn)?;
+
         Ok(Self {
             conn,
+            selections,
             poll,
             gui_tx,
             gui_rx,
@@ -166,6 +250,7 @@ impl GuiEventLoop {
                         } else {
                         }
                     }
+                    self.process_deferred_xcb()?;
                     self.process_sigchld();
                     // Check the window count; if after processing the futures there
                     // are no windows left, then we are done.
@@ -267,6 +352,10 @@ impl GuiEventLoop {
                 let button_press: &xcb::ButtonPressEvent = unsafe { xcb::cast_event(event) };
                 Some(button_press.event())
             }
+            xcb::FOCUS_IN | xcb::FOCUS_OUT => {
+                let focus: &xcb::FocusInEvent = unsafe { xcb::cast_event(event) };
+                Some(focus.event())
+            }
             xcb::CLIENT_MESSAGE => {
                 let msg: &xcb::ClientMessageEvent = unsafe { xcb::cast_event(event) };
                 Some(msg.window())
@@ -276,8 +365,12 @@ impl GuiEventLoop {
     }
 
     fn process_xcb_event(&self, event: &xcb::GenericEvent) -> Result<(), Error> {
-        if let Some(window_id) = Self::window_id_from_event(event) {
+        if self.selections.process_event(event)? {
+            // It was a request for, or about, one of our selections
+        } else if let Some(window_id) = Self::window_id_from_event(event) {
             self.process_window_event(window_id, event)?;
+        } else if self.conn.is_dpi_change_event(event) {
+            self.dpi_may_have_changed();
         } else {
             let r = event.response_type() & 0x7f;
             if r == self.conn.kbd_ev {
@@ -315,6 +408,81 @@ impl GuiEventLoop {
         }
     }
 
+    /// Processes the events that arrived while reading a selection
+    fn process_deferred_xcb(&self) -> Result<(), Error> {
+        while let Some(event) = self.selections.pop_deferred_event() {
+            self.process_xcb_event(&event)?;
+        }
+        self.conn.flush();
+        Ok(())
+    }
+
+    /// Apply the reloaded configuration to all of the windows
+    fn config_was_reloaded(&self) {
+        for window in &mut self.windows.borrow_mut().by_id.values_mut() {
+            if let Err(err) = window.config_was_reloaded() {
+                error!("Failed to apply the reloaded configuration: {}", err);
+            }
+        }
+    }
+
+    /// Reads the dpi scale again and applies it to all of the windows
+    fn dpi_may_have_changed(&self) {
+        let dpi_scale = self.conn.dpi_scale();
+        for window in &mut self.windows.borrow_mut().by_id.values_mut() {
+            if let Err(err) = window.dpi_changed(dpi_scale) {
+                error!("Failed to apply the dpi scale {}: {}", dpi_scale, err);
+            }
+        }
+    }
+
+    /// Paints every window afresh and looks for programs that exited
+    /// while the system was asleep, rather than waiting for something
+    /// else to prompt it after the system resumes
+    fn system_resumed(&self) {
+        for window in self.windows.borrow_mut().by_id.values_mut() {
+            window.renderer().invalidate();
+        }
+        self.process_sigchld();
+        self.do_paint();
+    }
+
+    /// Updates the titles of the windows that hold the tab, since they
+    /// may show its working directory
+    fn current_dir_changed(&self, tab_id: TabId) {
+        let mux = Mux::get().unwrap();
+        for window in self.windows.borrow_mut().by_id.values_mut() {
+            let holds_tab = mux
+                .get_window(window.get_mux_window_id())
+                .map(|w| w.idx_by_id(tab_id).is_some())
+                .unwrap_or(false);
+            if holds_tab {
+                window.update_title();
+            }
+        }
+    }
+
+    /// Sets the clipboard via the window that holds the tab, falling
+    /// back to any window if the tab isn't in one of ours.
+    fn set_clipboard(&self, tab_id: TabId, clip: Option<String>) -> Fallible<()> {
+        let mux = Mux::get().unwrap();
+        let mut windows = self.windows.borrow_mut();
+        let window_id = windows
+            .by_id
+            .iter()
+            .find(|(_, window)| {
+                mux.get_window(window.get_mux_window_id())
+                    .map(|w| w.idx_by_id(tab_id).is_some())
+                    .unwrap_or(false)
+            })
+            .or_else(|| windows.by_id.iter().next())
+            .map(|(window_id, _)| *window_id);
+        match window_id.and_then(|window_id| windows.by_id.get_mut(&window_id)) {
+            Some(window) => window.set_clipboard(clip),
+            None => bail!("no window in which to set the clipboard"),
+        }
+    }
+
     /// Run through all of the windows and cause them to paint if they need it.
     /// This happens ~50ms or so.
     fn do_paint(&self) {
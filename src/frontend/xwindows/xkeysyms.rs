@@ -1,26 +1,19 @@
+use crate::frontend::guicommon::input::ModifierState;
 use term::KeyCode;
 use term::KeyModifiers;
 
 pub fn modifiers_from_state(state: u16) -> KeyModifiers {
     use xcb::xproto::*;
 
-    let mut mods = KeyModifiers::default();
     let state = u32::from(state);
-
-    if state & MOD_MASK_SHIFT != 0 {
-        mods |= KeyModifiers::SHIFT;
-    }
-    if state & MOD_MASK_CONTROL != 0 {
-        mods |= KeyModifiers::CTRL;
-    }
-    if state & MOD_MASK_1 != 0 {
-        mods |= KeyModifiers::ALT;
+    ModifierState {
+        shift: state & MOD_MASK_SHIFT != 0,
+        ctrl: state & MOD_MASK_CONTROL != 0,
+        alt: state & MOD_MASK_1 != 0,
+        logo: state & MOD_MASK_4 != 0,
+        alt_gr: state & MOD_MASK_5 != 0,
     }
-    if state & MOD_MASK_4 != 0 {
-        mods |= KeyModifiers::SUPER;
-    }
-
-    mods
+    .key_modifiers()
 }
 
 /// Translates non-printable X11 keysym to termwiz::KeyCode
@@ -34,7 +27,7 @@ pub fn keysym_to_keycode(keysym: u32) -> Option<KeyCode> {
         KEY_Tab => KeyCode::Tab,
 
         KEY_BackSpace => KeyCode::Backspace,
-        KEY_Return => KeyCode::Char(0xdu8 as char),
+        KEY_Return => KeyCode::Enter,
         KEY_Insert => KeyCode::Insert,
         KEY_Delete => KeyCode::Delete,
         KEY_Clear => KeyCode::Delete,
@@ -81,9 +74,16 @@ pub fn keysym_to_keycode(keysym: u32) -> Option<KeyCode> {
         KEY_F12 => KeyCode::Function(12),
 
         // numeric and function keypad keys
-        KEY_KP_Enter => KeyCode::Char(0xdu8 as char),
+        KEY_KP_Enter => KeyCode::Enter,
         KEY_KP_Delete => KeyCode::Delete,
         KEY_KP_Home => KeyCode::Home,
+        KEY_KP_End => KeyCode::End,
+        KEY_KP_Insert => KeyCode::Insert,
+        KEY_KP_Left => KeyCode::LeftArrow,
+        KEY_KP_Up => KeyCode::UpArrow,
+        KEY_KP_Right => KeyCode::RightArrow,
+        KEY_KP_Down => KeyCode::DownArrow,
+        KEY_KP_Begin => KeyCode::Numpad5,
         KEY_KP_Page_Up => KeyCode::PageUp,
         KEY_KP_Page_Down => KeyCode::PageDown,
         KEY_KP_Multiply => KeyCode::Multiply,
@@ -98,6 +98,7 @@ pub fn keysym_to_keycode(keysym: u32) -> Option<KeyCode> {
         KEY_KP_2 => KeyCode::Numpad2,
         KEY_KP_3 => KeyCode::Numpad3,
         KEY_KP_4 => KeyCode::Numpad4,
+        KEY_KP_5 => KeyCode::Numpad5,
         KEY_KP_6 => KeyCode::Numpad6,
         KEY_KP_7 => KeyCode::Numpad7,
         KEY_KP_8 => KeyCode::Numpad8,
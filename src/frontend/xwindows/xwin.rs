@@ -2,18 +2,27 @@ use super::xkeysyms;
 use super::{Connection, Window};
 use crate::config::Config;
 use crate::font::FontConfiguration;
-use crate::frontend::guicommon::host::{HostHelper, HostImpl, TabHost};
-use crate::frontend::guicommon::window::{Dimensions, TerminalWindow};
+use crate::frontend::guicommon::host::{HostHelper, HostImpl, SystemSelections, TabHost};
+use crate::frontend::guicommon::input;
+use crate::frontend::guicommon::launcher::Launcher;
+use crate::frontend::guicommon::lineeditor::LineEditor;
+use crate::frontend::guicommon::mouse::MouseTranslator;
+use crate::frontend::guicommon::pastehistory::PastePicker;
+use crate::frontend::guicommon::quickselect::QuickSelect;
+use crate::frontend::guicommon::scrollbar::{scroll_bar_cols, ScrollbarState};
+use crate::frontend::guicommon::search::Search;
+use crate::frontend::guicommon::tabbar::{tab_bar_rows, TabBarState};
+use crate::frontend::guicommon::window::{Dimensions, MouseCursor, TerminalWindow};
 use crate::frontend::xwindows::x11loop::{GuiEventLoop, WindowId as X11WindowId};
 use crate::mux::tab::Tab;
 use crate::mux::window::WindowId;
 use crate::mux::{Mux, SessionTerminated};
 use crate::opengl::render::Renderer;
 use failure::Error;
-use log::{debug, error};
+use log::debug;
 use std::rc::Rc;
 use std::sync::Arc;
-use term::{self, KeyCode, KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
+use term::{self, KeyCode, KeyModifiers, MouseEvent, MouseEventKind};
 use xcb;
 
 /// Holds most of the information we need to implement `TerminalHost`
@@ -22,6 +31,7 @@ struct Host {
     event_loop: Rc<GuiEventLoop>,
     fonts: Rc<FontConfiguration>,
     config: Arc<Config>,
+    always_on_top: bool,
 }
 
 impl HostHelper for Host {
@@ -34,18 +44,30 @@ impl HostHelper for Host {
     }
 
     fn toggle_full_screen(&mut self) {}
+
+    fn toggle_always_on_top(&mut self) {
+        self.always_on_top = !self.always_on_top;
+        self.window.set_always_on_top(self.always_on_top);
+    }
+
+    fn selections(&self) -> Option<&dyn SystemSelections> {
+        Some(&self.event_loop.selections)
+    }
 }
 
 pub struct X11TerminalWindow {
     host: HostImpl<Host>,
     conn: Rc<Connection>,
     renderer: Renderer,
+    tab_bar: TabBarState,
+    scrollbar: ScrollbarState,
     width: u16,
     height: u16,
     cell_height: usize,
     cell_width: usize,
     have_pending_resize: Option<(u16, u16)>,
     mux_window_id: WindowId,
+    mouse: MouseTranslator,
 }
 
 impl TerminalWindow for X11TerminalWindow {
@@ -58,6 +80,12 @@ impl TerminalWindow for X11TerminalWindow {
     fn fonts(&self) -> &Rc<FontConfiguration> {
         &self.host.fonts
     }
+    fn set_config(&mut self, config: &Arc<Config>, fonts: &Rc<FontConfiguration>) {
+        self.host.config = Arc::clone(config);
+        self.host.fonts = Rc::clone(fonts);
+        self.renderer.fonts = Rc::clone(fonts);
+        self.host.config_was_reloaded();
+    }
 
     fn set_window_title(&mut self, title: &str) -> Result<(), Error> {
         self.host.window.set_title(title);
@@ -70,9 +98,52 @@ impl TerminalWindow for X11TerminalWindow {
     fn renderer(&mut self) -> &mut Renderer {
         &mut self.renderer
     }
+    fn tab_bar(&mut self) -> &mut TabBarState {
+        &mut self.tab_bar
+    }
+    fn scrollbar(&mut self) -> &mut ScrollbarState {
+        &mut self.scrollbar
+    }
+    fn paste_picker(&self) -> Option<&PastePicker> {
+        self.host.paste_picker()
+    }
+    fn rename_prompt(&self) -> Option<&LineEditor> {
+        self.host.rename_prompt()
+    }
+    fn launcher(&self) -> Option<&Launcher> {
+        self.host.launcher()
+    }
+    fn quick_select(&self) -> Option<&QuickSelect> {
+        self.host.quick_select()
+    }
+    fn search(&self) -> Option<&Search> {
+        self.host.search()
+    }
+    fn refresh_search(&mut self, tab: &dyn Tab) -> bool {
+        self.host.refresh_search(tab)
+    }
+    fn refresh_quick_select(&mut self, tab: &dyn Tab) -> bool {
+        self.host.refresh_quick_select(tab)
+    }
+    fn show_launcher(&mut self) {
+        self.host.show_launcher()
+    }
+    fn get_clipboard(&mut self) -> Result<String, Error> {
+        self.host.get_clipboard()
+    }
+    fn set_clipboard(&mut self, clip: Option<String>) -> Result<(), Error> {
+        self.host.set_clipboard(clip)
+    }
     fn recreate_texture_atlas(&mut self, size: u32) -> Result<(), Error> {
         self.renderer.recreate_atlas(&self.host.window, size)
     }
+    fn is_context_lost(&self) -> bool {
+        self.host.window.is_context_lost()
+    }
+    fn recreate_context(&mut self) -> Result<(), Error> {
+        self.host.window.recreate_context()?;
+        self.renderer.recreate(&self.host.window)
+    }
     fn get_dimensions(&self) -> Dimensions {
         Dimensions {
             width: self.width,
@@ -95,11 +166,25 @@ impl TerminalWindow for X11TerminalWindow {
         self.height = height;
         self.renderer.resize(&self.host.window, width, height)
     }
-    fn resize_if_not_full_screen(&mut self, _width: u16, _height: u16) -> Result<bool, Error> {
-        // FIXME: it would be nice to implement this!
-        // It requires some plumbing to allow sending xcb_configure_window with
-        // XCB_CONFIG_WINDOW_WIDTH and XCB_CONFIG_WINDOW_HEIGHT set.
-        Ok(false)
+    fn resize_if_not_full_screen(&mut self, width: u16, height: u16) -> Result<bool, Error> {
+        // We don't support full screen on X11, so we can always resize.
+        // The window manager may pick a different size, in which case
+        // the ConfigureNotify that follows resizes the surfaces again.
+        self.host.window.set_inner_size(width, height);
+        self.resize_surfaces(width, height, true)?;
+        Ok(true)
+    }
+
+    fn set_mouse_cursor(&mut self, cursor: MouseCursor) {
+        self.host.window.set_cursor(cursor);
+    }
+
+    fn pointer_position(&self) -> Option<(i64, i64)> {
+        self.mouse.last_pixel()
+    }
+
+    fn screen_position(&self) -> Option<(i64, i64)> {
+        self.host.window.screen_position()
     }
 
     fn check_for_resize(&mut self) -> Result<(), Error> {
@@ -120,18 +205,24 @@ impl X11TerminalWindow {
     ) -> Result<X11TerminalWindow, Error> {
         let (physical_rows, physical_cols) = tab.renderer().physical_dimensions();
 
+        fonts.change_scaling(fonts.get_font_scale(), event_loop.conn.dpi_scale());
         let metrics = fonts.default_font_metrics()?;
         let (cell_height, cell_width) = (
             metrics.cell_height.ceil() as usize,
             metrics.cell_width.ceil() as usize,
         );
 
-        let width = cell_width * physical_cols;
-        let height = cell_height * physical_rows;
+        let width = cell_width * (physical_cols + scroll_bar_cols(config));
+        let height = cell_height * (physical_rows + tab_bar_rows(config));
 
         let width = width as u16;
         let height = height as u16;
-        let window = Window::new(&event_loop.conn, width, height)?;
+        let window = Window::new(
+            &event_loop.conn,
+            width,
+            height,
+            config.window_is_translucent(),
+        )?;
         window.set_title("wezterm");
 
         let host = HostImpl::new(Host {
@@ -139,6 +230,7 @@ impl X11TerminalWindow {
             event_loop: Rc::clone(event_loop),
             config: Arc::clone(config),
             fonts: Rc::clone(fonts),
+            always_on_top: false,
         });
 
         let renderer = Renderer::new(&host.window, width, height, fonts)?;
@@ -147,6 +239,8 @@ impl X11TerminalWindow {
         Ok(X11TerminalWindow {
             host,
             renderer,
+            tab_bar: TabBarState::default(),
+            scrollbar: ScrollbarState::default(),
             conn: Rc::clone(&event_loop.conn),
             width,
             height,
@@ -154,6 +248,7 @@ impl X11TerminalWindow {
             cell_width,
             have_pending_resize: None,
             mux_window_id,
+            mouse: MouseTranslator::default(),
         })
     }
 
@@ -165,17 +260,41 @@ impl X11TerminalWindow {
         self.paint()
     }
 
+    /// Applies a new dpi scale, keeping the rows and columns of the
+    /// terminal by resizing the window to fit the new cell size
+    pub fn dpi_changed(&mut self, dpi_scale: f64) -> Result<(), Error> {
+        if (self.host.fonts.get_dpi_scale() - dpi_scale).abs() < std::f64::EPSILON {
+            return Ok(());
+        }
+        self.scaling_changed(None, Some(dpi_scale), self.width, self.height)
+    }
+
     fn decode_key(&self, event: &xcb::KeyPressEvent) -> Option<(KeyCode, KeyModifiers)> {
         self.conn.xkb_lookup_keysym(event)
     }
 
     fn mouse_event(&mut self, event: MouseEvent) -> Result<(), Error> {
+        let event = match self.translate_mouse_event(event)? {
+            Some(event) => event,
+            None => {
+                if let Some(cursor) = self.host.hover(None) {
+                    self.set_mouse_cursor(cursor);
+                }
+                return Ok(());
+            }
+        };
         let mux = Mux::get().unwrap();
         let tab = match mux.get_active_tab_for_window(self.get_mux_window_id()) {
             Some(tab) => tab,
             None => return Ok(()),
         };
+        if self.host.process_mouse_bindings(&*tab, &event)? {
+            return Ok(());
+        }
         tab.mouse_event(event, &mut TabHost::new(&mut *tab.writer(), &mut self.host))?;
+        if let Some(cursor) = self.host.hover(Some(&*tab)) {
+            self.set_mouse_cursor(cursor);
+        }
         Ok(())
     }
 
@@ -212,41 +331,47 @@ impl X11TerminalWindow {
             xcb::MOTION_NOTIFY => {
                 let motion: &xcb::MotionNotifyEvent = unsafe { xcb::cast_event(event) };
 
-                let event = MouseEvent {
-                    kind: MouseEventKind::Move,
-                    button: MouseButton::None,
-                    x: (motion.event_x() as usize / self.cell_width) as usize,
-                    y: (motion.event_y() as usize / self.cell_height) as i64,
-                    modifiers: xkeysyms::modifiers_from_state(motion.state()),
-                };
-                self.mouse_event(event)?;
+                if let Some(event) = self.mouse.motion(
+                    i64::from(motion.event_x()),
+                    i64::from(motion.event_y()),
+                    self.cell_width,
+                    self.cell_height,
+                    xkeysyms::modifiers_from_state(motion.state()),
+                ) {
+                    self.mouse_event(event)?;
+                }
             }
             xcb::BUTTON_PRESS | xcb::BUTTON_RELEASE => {
                 let button_press: &xcb::ButtonPressEvent = unsafe { xcb::cast_event(event) };
 
-                let event = MouseEvent {
-                    kind: match r {
+                self.mouse.set_position(
+                    i64::from(button_press.event_x()),
+                    i64::from(button_press.event_y()),
+                    self.cell_width,
+                    self.cell_height,
+                );
+                let event = self.mouse.button(
+                    match r {
                         xcb::BUTTON_PRESS => MouseEventKind::Press,
                         xcb::BUTTON_RELEASE => MouseEventKind::Release,
                         _ => unreachable!("button event mismatch"),
                     },
-                    x: (button_press.event_x() as usize / self.cell_width) as usize,
-                    y: (button_press.event_y() as usize / self.cell_height) as i64,
-                    button: match button_press.detail() {
-                        1 => MouseButton::Left,
-                        2 => MouseButton::Middle,
-                        3 => MouseButton::Right,
-                        4 => MouseButton::WheelUp,
-                        5 => MouseButton::WheelDown,
-                        _ => {
-                            error!("button {} is not implemented", button_press.detail());
+                    match input::mouse_button_from_number(button_press.detail()) {
+                        Some(button) => button,
+                        None => {
+                            debug!("ignoring button {}", button_press.detail());
                             return Ok(());
                         }
                     },
-                    modifiers: xkeysyms::modifiers_from_state(button_press.state()),
-                };
+                    xkeysyms::modifiers_from_state(button_press.state()),
+                );
 
-                self.mouse_event(event)?;
+                if let Some(event) = event {
+                    self.mouse_event(event)?;
+                }
+            }
+            xcb::FOCUS_IN | xcb::FOCUS_OUT => {
+                self.focus_changed(r == xcb::FOCUS_IN)?;
             }
             xcb::CLIENT_MESSAGE => {
                 let msg: &xcb::ClientMessageEvent = unsafe { xcb::cast_event(event) };
@@ -1,7 +1,7 @@
 // Don't create a new standard console window when launched from the windows GUI.
 #![windows_subsystem = "windows"]
 
-use failure::Error;
+use failure::{err_msg, format_err, Error, Fallible};
 use log::error;
 use std::ffi::OsString;
 use structopt::StructOpt;
@@ -10,17 +10,24 @@ use tabout::{tabulate_output, Alignment, Column};
 use std::rc::Rc;
 use std::sync::Arc;
 
+mod appearance;
 mod config;
 mod frontend;
 mod mux;
 mod opengl;
+mod resume;
 mod server;
+#[cfg(test)]
+mod testing;
 use crate::frontend::FrontEndSelection;
 use crate::mux::domain::{Domain, LocalDomain};
+use crate::mux::layout::{default_layout_path, Layout, TabLayout, WindowLayout};
 use crate::mux::Mux;
 use crate::server::client::Client;
+use crate::server::codec::{KillTab, SendPaste, SetColorScheme, Spawn, WriteToTab};
 use crate::server::domain::ClientDomain;
 use portable_pty::cmdbuilder::CommandBuilder;
+use promise::Future;
 
 mod font;
 use crate::font::{FontConfiguration, FontSystemSelection};
@@ -39,7 +46,6 @@ fn get_shell() -> Result<String, Error> {
         if ent.is_null() {
             Ok("/bin/sh".into())
         } else {
-            use failure::format_err;
             use std::ffi::CStr;
             use std::str;
             let shell = unsafe { CStr::from_ptr((*ent).pw_shell) };
@@ -64,10 +70,16 @@ fn get_shell() -> Result<String, Error> {
 #[structopt(about = "Wez's Terminal Emulator\nhttp://github.com/wez/wezterm")]
 #[structopt(raw(setting = "structopt::clap::AppSettings::ColoredHelp"))]
 struct Opt {
-    /// Skip loading ~/.wezterm.toml
+    /// Skip loading wezterm.toml
     #[structopt(short = "n")]
     skip_config: bool,
 
+    /// Load the configuration from this file rather than searching
+    /// the usual locations.  This takes precedence over the
+    /// WEZTERM_CONFIG_FILE environment variable.
+    #[structopt(long = "config-file", parse(from_os_str))]
+    config_file: Option<std::path::PathBuf>,
+
     #[structopt(subcommand)]
     cmd: Option<SubCommand>,
 }
@@ -99,6 +111,22 @@ struct StartCommand {
     #[structopt(long = "mux-tls-client-as-default-domain")]
     mux_tls_client_as_default_domain: bool,
 
+    /// Drive the first tab from the script in this file and exit
+    /// with the status of its program once it is done.  This is
+    /// intended to be used with `--front-end Null`.
+    #[structopt(long = "script", parse(from_os_str))]
+    script: Option<std::path::PathBuf>,
+
+    /// Start the program in this directory rather than in the
+    /// default directory
+    #[structopt(long = "cwd", parse(from_os_str))]
+    cwd: Option<std::path::PathBuf>,
+
+    /// If wezterm is already running, open a tab in its most recently
+    /// opened window rather than starting another instance
+    #[structopt(long = "new-tab")]
+    new_tab: bool,
+
     /// Instead of executing your shell, run PROG.
     /// For example: `wezterm start -- bash -l` will spawn bash
     /// as if it were a login shell.
@@ -112,9 +140,54 @@ enum SubCommand {
     #[structopt(raw(setting = "structopt::clap::AppSettings::ColoredHelp"))]
     Start(StartCommand),
 
+    #[structopt(
+        name = "attach",
+        about = "Open windows for the tabs of a running mux server"
+    )]
+    #[structopt(raw(setting = "structopt::clap::AppSettings::ColoredHelp"))]
+    Attach(AttachCommand),
+
+    #[structopt(
+        name = "open",
+        about = "Ask the running wezterm to open a window or a tab"
+    )]
+    #[structopt(raw(setting = "structopt::clap::AppSettings::ColoredHelp"))]
+    Open(OpenCommand),
+
     #[structopt(name = "cli", about = "Interact with experimental mux server")]
     #[structopt(raw(setting = "structopt::clap::AppSettings::ColoredHelp"))]
     Cli(CliCommand),
+
+    #[structopt(
+        name = "show-config",
+        about = "Print the effective configuration as TOML"
+    )]
+    #[structopt(raw(setting = "structopt::clap::AppSettings::ColoredHelp"))]
+    ShowConfig,
+}
+
+#[derive(Debug, StructOpt, Clone)]
+struct AttachCommand {
+    /// `unix` for the mux server listening on
+    /// mux_server_unix_domain_socket_path, or `tls` for the one at
+    /// mux_server_remote_address
+    #[structopt(raw(possible_values = r#"&["unix", "tls"]"#))]
+    domain: String,
+}
+
+#[derive(Debug, StructOpt, Clone)]
+struct OpenCommand {
+    /// Open a tab in the most recently opened window rather than
+    /// opening a new window
+    #[structopt(long = "new-tab")]
+    new_tab: bool,
+    /// Start the program in this directory
+    #[structopt(long = "cwd", parse(from_os_str))]
+    cwd: Option<std::path::PathBuf>,
+    /// Instead of executing your shell, run PROG.
+    /// For example: `wezterm open -- htop` will open a window
+    /// running htop.
+    prog: Vec<String>,
 }
 
 #[derive(Debug, StructOpt, Clone)]
@@ -125,26 +198,202 @@ struct CliCommand {
 
 #[derive(Debug, StructOpt, Clone)]
 enum CliSubCommand {
+    #[structopt(
+        name = "info",
+        about = "show where the runtime files of the mux server are kept"
+    )]
+    #[structopt(raw(setting = "structopt::clap::AppSettings::ColoredHelp"))]
+    Info,
+
+    #[structopt(
+        name = "generate-certs",
+        about = "create the certificates for a TLS mux server and its clients"
+    )]
+    #[structopt(raw(setting = "structopt::clap::AppSettings::ColoredHelp"))]
+    GenerateCerts {
+        /// Write the certificates to this directory rather than to
+        /// the certs directory in the wezterm data directory
+        #[structopt(long = "dir", parse(from_os_str))]
+        dir: Option<std::path::PathBuf>,
+        /// The port on which the server listens
+        #[structopt(long = "port", default_value = "8080")]
+        port: u16,
+        /// How many days the certificates remain valid for
+        #[structopt(long = "days", default_value = "365")]
+        days: u32,
+        /// The host name or IP address by which clients reach the server
+        host: String,
+    },
+
+    #[structopt(
+        name = "gpu-info",
+        about = "show the GPU and OpenGL implementation that windows render with"
+    )]
+    #[structopt(raw(setting = "structopt::clap::AppSettings::ColoredHelp"))]
+    GpuInfo,
+
     #[structopt(name = "list", about = "list windows and tabs")]
     #[structopt(raw(setting = "structopt::clap::AppSettings::ColoredHelp"))]
     List,
+
+    #[structopt(
+        name = "set-color-scheme",
+        about = "switch windows or tabs to a color scheme"
+    )]
+    #[structopt(raw(setting = "structopt::clap::AppSettings::ColoredHelp"))]
+    SetColorScheme {
+        /// Switch only the tabs of this window
+        #[structopt(long = "window")]
+        window_id: Option<mux::window::WindowId>,
+        /// Switch only this tab
+        #[structopt(long = "tab")]
+        tab_id: Option<mux::tab::TabId>,
+        /// The name of a color scheme defined in the configuration
+        name: String,
+    },
+
+    #[structopt(name = "spawn", about = "spawn a program into a new tab")]
+    #[structopt(raw(setting = "structopt::clap::AppSettings::ColoredHelp"))]
+    Spawn {
+        /// Spawn into this domain rather than the default domain
+        /// of the server
+        #[structopt(long = "domain")]
+        domain_id: Option<mux::domain::DomainId>,
+        /// Add the tab to this window rather than creating a new window
+        #[structopt(long = "window")]
+        window_id: Option<mux::window::WindowId>,
+        /// Start the program in this directory
+        #[structopt(long = "cwd")]
+        cwd: Option<String>,
+        /// Instead of executing your shell, run PROG.
+        /// For example: `wezterm cli spawn -- bash -l` will spawn bash
+        /// as if it were a login shell.
+        #[structopt(parse(from_os_str))]
+        prog: Vec<OsString>,
+    },
+
+    #[structopt(
+        name = "send-text",
+        about = "send text to a tab as though it were pasted"
+    )]
+    #[structopt(raw(setting = "structopt::clap::AppSettings::ColoredHelp"))]
+    SendText {
+        /// The tab to send to.  Defaults to the tab named by the
+        /// WEZTERM_TAB environment variable.
+        #[structopt(long = "tab")]
+        tab_id: Option<mux::tab::TabId>,
+        /// Write the text directly to the tab rather than pasting it,
+        /// so that it isn't wrapped in bracketed paste sequences
+        #[structopt(long = "no-paste")]
+        no_paste: bool,
+        /// The text to send.  If omitted, the text is read from stdin.
+        text: Option<String>,
+    },
+
+    #[structopt(
+        name = "save-layout",
+        about = "save the windows and tabs of the server to a layout file"
+    )]
+    #[structopt(raw(setting = "structopt::clap::AppSettings::ColoredHelp"))]
+    SaveLayout {
+        /// The file to save to, rather than layout.toml in the wezterm
+        /// data directory
+        #[structopt(parse(from_os_str))]
+        file: Option<std::path::PathBuf>,
+    },
+
+    #[structopt(
+        name = "restore-layout",
+        about = "open the windows and tabs described by a layout file on the server"
+    )]
+    #[structopt(raw(setting = "structopt::clap::AppSettings::ColoredHelp"))]
+    RestoreLayout {
+        /// The file to restore from, rather than layout.toml in the
+        /// wezterm data directory
+        #[structopt(parse(from_os_str))]
+        file: Option<std::path::PathBuf>,
+    },
+
+    #[structopt(name = "kill-tab", about = "kill a tab and the program running in it")]
+    #[structopt(raw(setting = "structopt::clap::AppSettings::ColoredHelp"))]
+    KillTab {
+        /// The tab to kill.  Defaults to the tab named by the
+        /// WEZTERM_TAB environment variable.
+        #[structopt(long = "tab")]
+        tab_id: Option<mux::tab::TabId>,
+    },
+}
+
+/// Resolves the tab that a cli subcommand applies to, falling back
+/// to the tab in which the cli is running
+fn cli_tab_id(tab_id: Option<mux::tab::TabId>) -> Fallible<mux::tab::TabId> {
+    match tab_id {
+        Some(tab_id) => Ok(tab_id),
+        None => {
+            let tab_id = env::var("WEZTERM_TAB")
+                .map_err(|_| err_msg("--tab is required when not running inside wezterm"))?;
+            tab_id
+                .parse()
+                .map_err(|e| format_err!("invalid WEZTERM_TAB {:?}: {}", tab_id, e))
+        }
+    }
+}
+
+/// Asks the wezterm that is already running to open a tab for `opts`,
+/// returning false if there isn't one to ask
+fn open_in_running_gui(opts: &StartCommand) -> bool {
+    let request = || -> Fallible<frontend::guicommon::ipc::OpenRequest> {
+        let cwd = match opts.cwd.as_ref() {
+            Some(cwd) => Some(
+                env::current_dir()?
+                    .join(cwd)
+                    .into_os_string()
+                    .into_string()
+                    .map_err(|_| err_msg("the directory must be valid UTF-8"))?,
+            ),
+            None => None,
+        };
+        let prog = opts
+            .prog
+            .iter()
+            .map(|arg| {
+                arg.clone()
+                    .into_string()
+                    .map_err(|_| err_msg("the arguments must be valid UTF-8"))
+            })
+            .collect::<Fallible<Vec<String>>>()?;
+        Ok(frontend::guicommon::ipc::OpenRequest {
+            new_tab: true,
+            cwd,
+            prog,
+        })
+    };
+    match request().and_then(|request| frontend::guicommon::ipc::send(&request)) {
+        Ok(()) => true,
+        Err(err) => {
+            log::info!("starting a new instance: {}", err);
+            false
+        }
+    }
 }
 
 fn run_terminal_gui(config: Arc<config::Config>, opts: &StartCommand) -> Result<(), Error> {
+    let config = if opts.script.is_some() {
+        // Hold the tab open when its program exits so that the script
+        // can collect the exit status
+        let mut config = (*config).clone();
+        config.exit_behavior = Some(config::ExitBehavior::Hold);
+        Arc::new(config)
+    } else {
+        config
+    };
+
     let font_system = opts.font_system.unwrap_or(config.font_system);
     font_system.set_default();
+    opengl::gpu::apply_preference(&config);
 
     let fontconfig = Rc::new(FontConfiguration::new(Arc::clone(&config), font_system));
 
-    let cmd = if !opts.prog.is_empty() {
-        let argv: Vec<&std::ffi::OsStr> = opts.prog.iter().map(|x| x.as_os_str()).collect();
-        let mut builder = CommandBuilder::new(&argv[0]);
-        builder.args(&argv[1..]);
-        Some(builder)
-    } else {
-        None
-    };
-
     let domain: Arc<dyn Domain> = if opts.mux_client_as_default_domain {
         let client = Client::new_unix_domain(&config)?;
         Arc::new(ClientDomain::new(client))
@@ -155,12 +404,104 @@ fn run_terminal_gui(config: Arc<config::Config>, opts: &StartCommand) -> Result<
         Arc::new(LocalDomain::new(&config)?)
     };
 
+    let cmd = if !opts.prog.is_empty() {
+        let argv: Vec<&std::ffi::OsStr> = opts.prog.iter().map(|x| x.as_os_str()).collect();
+        let mut builder = CommandBuilder::new(&argv[0]);
+        builder.args(&argv[1..]);
+        Some(builder)
+    } else if opts.cwd.is_some() {
+        Some(config.build_prog_for_domain(domain.domain_name())?)
+    } else {
+        None
+    };
+    let cmd = match (cmd, opts.cwd.as_ref()) {
+        (Some(mut cmd), Some(cwd)) => {
+            cmd.cwd(env::current_dir()?.join(cwd));
+            Some(cmd)
+        }
+        (cmd, _) => cmd,
+    };
+
     let mux = Rc::new(mux::Mux::new(&config, &domain));
     Mux::set_mux(&mux);
 
     let front_end = opts.front_end.unwrap_or(config.front_end);
     let gui = front_end.try_new(&mux)?;
 
+    // A reload would undo the exit_behavior override that scripts need
+    if config.automatically_reload_config.unwrap_or(true) && opts.script.is_none() {
+        let executor = gui.gui_executor();
+        if let Err(err) = config::watch_for_changes(move |config| {
+            Future::with_executor(executor.clone_executor(), move || {
+                let mux = Mux::get().unwrap();
+                match config {
+                    Ok(config) => mux.set_config(config, false),
+                    Err(err) => {
+                        let message = format!("Not reloading the configuration: {}", err);
+                        error!("{}", message);
+                        mux.notify_user(&message);
+                    }
+                }
+                Ok(())
+            });
+        }) {
+            error!("Unable to watch the configuration for changes: {}", err);
+        }
+    }
+
+    if config.follows_appearance() {
+        match appearance::get_appearance() {
+            Ok(appearance) => mux.set_appearance(appearance)?,
+            Err(err) => error!("Unable to determine the desktop appearance: {}", err),
+        }
+        let executor = gui.gui_executor();
+        if let Err(err) = appearance::watch_for_changes(move |appearance| {
+            Future::with_executor(executor.clone_executor(), move || {
+                Mux::get().unwrap().set_appearance(appearance)
+            });
+        }) {
+            error!(
+                "Unable to watch the desktop appearance for changes: {}",
+                err
+            );
+        }
+    }
+
+    let executor = gui.gui_executor();
+    resume::watch_for_resume(move || {
+        Future::with_executor(executor.clone_executor(), move || {
+            Mux::get().unwrap().system_resumed();
+            Ok(())
+        });
+    });
+
+    // From now on, ask in the window about certificates that we haven't
+    // seen before or that have changed
+    match front_end {
+        FrontEndSelection::Glutin | FrontEndSelection::X11 => {
+            let executor = gui.gui_executor();
+            server::knownhosts::set_confirm(Box::new(move |question| {
+                Future::with_executor(executor.clone_executor(), move || {
+                    frontend::guicommon::confirm::ask(question)
+                })
+            }));
+        }
+        _ => {}
+    }
+
+    // Let other programs ask us to open windows, unless we're only
+    // here to run a script
+    if opts.script.is_none() {
+        match front_end {
+            FrontEndSelection::Glutin | FrontEndSelection::X11 => {
+                if let Err(err) = frontend::guicommon::ipc::listen(gui.gui_executor()) {
+                    error!("Unable to listen for requests to open windows: {}", err);
+                }
+            }
+            _ => {}
+        }
+    }
+
     domain.attach()?;
 
     if mux.is_empty() {
@@ -168,10 +509,43 @@ fn run_terminal_gui(config: Arc<config::Config>, opts: &StartCommand) -> Result<
         let tab = mux
             .default_domain()
             .spawn(PtySize::default(), cmd, window_id)?;
-        gui.spawn_new_window(mux.config(), &fontconfig, &tab, window_id)?;
+        gui.spawn_new_window(&mux.config(), &fontconfig, &tab, window_id)?;
     }
 
-    gui.run_forever()
+    let script = match opts.script.as_ref() {
+        Some(path) => {
+            let tab = mux
+                .iter_tabs()
+                .into_iter()
+                .min_by_key(|tab| tab.tab_id())
+                .ok_or_else(|| err_msg("there is no tab for the script to drive"))?;
+            Some(frontend::script::spawn_script(
+                path,
+                tab.tab_id(),
+                gui.gui_executor(),
+            )?)
+        }
+        None => None,
+    };
+
+    gui.run_forever()?;
+
+    if let Some(script) = script {
+        let status = script
+            .join()
+            .map_err(|_| err_msg("the script thread panicked"))??;
+        if !status.success() {
+            // Follow the shell in reporting death by a signal as 128
+            // plus the signal number
+            let code = match (status.exit_code(), status.signal()) {
+                (Some(code), _) => code as i32,
+                (None, Some(signal)) => 128 + signal,
+                (None, None) => 1,
+            };
+            std::process::exit(code);
+        }
+    }
+    Ok(())
 }
 
 fn main() -> Result<(), Error> {
@@ -193,6 +567,9 @@ fn main() -> Result<(), Error> {
     };
 
     let opts = Opt::from_args();
+    if let Some(path) = opts.config_file.as_ref() {
+        config::set_config_file_override(path);
+    }
     let config = Arc::new(if opts.skip_config {
         config::Config::default_config()
     } else {
@@ -206,13 +583,102 @@ fn main() -> Result<(), Error> {
         .unwrap_or_else(|| SubCommand::Start(StartCommand::default()))
     {
         SubCommand::Start(start) => {
-            error!("Using configuration: {:#?}\nopts: {:#?}", config, opts);
+            if start.new_tab && open_in_running_gui(&start) {
+                return Ok(());
+            }
+            run_terminal_gui(config, &start)
+        }
+        SubCommand::Attach(attach) => {
+            let start = StartCommand {
+                mux_client_as_default_domain: attach.domain == "unix",
+                mux_tls_client_as_default_domain: attach.domain == "tls",
+                ..Default::default()
+            };
             run_terminal_gui(config, &start)
         }
+        SubCommand::Open(open) => {
+            // The gui resolves relative paths against its own directory
+            let cwd = match open.cwd {
+                Some(cwd) => Some(
+                    env::current_dir()?
+                        .join(cwd)
+                        .to_str()
+                        .ok_or_else(|| err_msg("the directory must be valid UTF-8"))?
+                        .to_owned(),
+                ),
+                None => None,
+            };
+            frontend::guicommon::ipc::send(&frontend::guicommon::ipc::OpenRequest {
+                new_tab: open.new_tab,
+                cwd,
+                prog: open.prog,
+            })
+        }
+        SubCommand::ShowConfig => {
+            // Going via a Value emits plain values ahead of tables,
+            // which serializing the Config directly would not
+            let value = toml::Value::try_from(&*config)?;
+            print!("{}", toml::to_string(&value)?);
+            Ok(())
+        }
         SubCommand::Cli(cli) => {
-            let mut client = Client::new_unix_domain(&config)?;
+            // Programs running in a wezterm tab talk to the server
+            // that spawned them
+            let config = match std::env::var("WEZTERM_UNIX_SOCKET") {
+                Ok(path) => {
+                    let mut config = (*config).clone();
+                    config.mux_server_unix_domain_socket_path = Some(path);
+                    Arc::new(config)
+                }
+                Err(_) => config,
+            };
+            // Only the commands that talk to the server connect to it
+            let connect = || Client::new_unix_domain(&config);
             match cli.sub {
+                CliSubCommand::Info => {
+                    let pid_file = config::pid_file_path();
+                    let pid = std::fs::read_to_string(&pid_file).unwrap_or_default();
+                    println!("runtime dir: {}", config::runtime_dir()?.display());
+                    println!(
+                        "socket:      {}",
+                        config
+                            .mux_server_unix_domain_socket_path
+                            .as_ref()
+                            .map(String::as_str)
+                            .unwrap_or("")
+                    );
+                    println!("pid file:    {}", pid_file.display());
+                    println!("server pid:  {}", pid.trim());
+                }
+                CliSubCommand::GpuInfo => {
+                    let info = opengl::gpu::GpuInfo::probe(&config)?;
+                    println!("vendor:   {}", info.vendor);
+                    println!("renderer: {}", info.renderer);
+                    println!("version:  {}", info.version);
+                }
+                CliSubCommand::GenerateCerts {
+                    dir,
+                    port,
+                    days,
+                    host,
+                } => {
+                    let dir = match dir {
+                        Some(dir) => dir,
+                        None => config::data_dir()?.join("certs"),
+                    };
+                    let certs = server::pki::generate(&dir, &host, days)?;
+                    println!("Created the certificates in {}\n", dir.display());
+                    println!("Add this to the configuration of the mux server:\n");
+                    println!("{}", certs.server_config(&format!("0.0.0.0:{}", port)));
+                    println!(
+                        "Copy client.key, client.pem and ca.pem to each client, \
+                         and add this to its configuration, adjusting the paths \
+                         to where you copied them:\n"
+                    );
+                    print!("{}", certs.client_config(&format!("{}:{}", host, port)));
+                }
                 CliSubCommand::List => {
+                    let client = connect()?;
                     let cols = vec![
                         Column {
                             name: "WINID".to_string(),
@@ -238,6 +704,133 @@ fn main() -> Result<(), Error> {
                     }
                     tabulate_output(&cols, &data, &mut std::io::stdout().lock())?;
                 }
+                CliSubCommand::SetColorScheme {
+                    window_id,
+                    tab_id,
+                    name,
+                } => {
+                    let client = connect()?;
+                    client
+                        .set_color_scheme(SetColorScheme {
+                            window_id,
+                            tab_id,
+                            name,
+                        })
+                        .wait()?;
+                }
+                CliSubCommand::Spawn {
+                    domain_id,
+                    window_id,
+                    cwd,
+                    prog,
+                } => {
+                    let client = connect()?;
+                    let command = if prog.is_empty() {
+                        None
+                    } else {
+                        let mut builder = CommandBuilder::new(&prog[0]);
+                        builder.args(&prog[1..]);
+                        Some(builder)
+                    };
+                    let spawned = client
+                        .spawn(Spawn {
+                            domain_id,
+                            window_id,
+                            command,
+                            cwd,
+                            size: PtySize::default(),
+                        })
+                        .wait()?;
+                    println!("{}", spawned.tab_id);
+                }
+                CliSubCommand::SendText {
+                    tab_id,
+                    no_paste,
+                    text,
+                } => {
+                    let client = connect()?;
+                    let tab_id = cli_tab_id(tab_id)?;
+                    let data = match text {
+                        Some(text) => text,
+                        None => {
+                            use std::io::Read;
+                            let mut text = String::new();
+                            std::io::stdin().read_to_string(&mut text)?;
+                            text
+                        }
+                    };
+                    if no_paste {
+                        client
+                            .write_to_tab(WriteToTab {
+                                tab_id,
+                                data: data.into_bytes(),
+                            })
+                            .wait()?;
+                    } else {
+                        client.send_paste(SendPaste { tab_id, data }).wait()?;
+                    }
+                }
+                CliSubCommand::KillTab { tab_id } => {
+                    let client = connect()?;
+                    let tab_id = cli_tab_id(tab_id)?;
+                    client.kill_tab(KillTab { tab_id }).wait()?;
+                }
+                CliSubCommand::SaveLayout { file } => {
+                    let client = connect()?;
+                    let file = match file {
+                        Some(file) => file,
+                        None => default_layout_path()?,
+                    };
+                    let mut tabs = client.list_tabs().wait()?.tabs;
+                    // Keep the windows in the order in which they were
+                    // created; the sort is stable, so their tabs stay
+                    // in order too
+                    tabs.sort_by_key(|entry| entry.window_id);
+                    let mut layout = Layout::default();
+                    let mut last_window_id = None;
+                    for entry in tabs {
+                        if last_window_id != Some(entry.window_id) {
+                            layout.windows.push(WindowLayout::default());
+                            last_window_id = Some(entry.window_id);
+                        }
+                        if let Some(window) = layout.windows.last_mut() {
+                            window.tabs.push(TabLayout {
+                                cwd: entry.cwd,
+                                prog: None,
+                            });
+                        }
+                    }
+                    layout.save(&file)?;
+                    println!("Saved the layout to {}", file.display());
+                }
+                CliSubCommand::RestoreLayout { file } => {
+                    let client = connect()?;
+                    let file = match file {
+                        Some(file) => file,
+                        None => default_layout_path()?,
+                    };
+                    let layout = Layout::load(&file)?;
+                    for window in &layout.windows {
+                        // The first tab creates the window that the
+                        // others are added to
+                        let mut window_id = None;
+                        for tab in &window.tabs {
+                            // The server shares our configuration, and
+                            // spawns into its local domain by default
+                            let command = tab.command(&config, "local")?;
+                            let spawned = client
+                                .spawn(Spawn {
+                                    domain_id: None,
+                                    window_id,
+                                    command,
+                                    cwd: None,
+                                    size: PtySize::default(),
+                                })
+                                .wait()?;
+                            window_id = Some(spawned.window_id);
+                        }
+                    }
+                }
             }
             Ok(())
         }
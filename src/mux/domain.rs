@@ -7,11 +7,12 @@
 
 use crate::config::Config;
 use crate::frontend::guicommon::localtab::LocalTab;
-use crate::mux::tab::Tab;
+use crate::mux::tab::{alloc_tab_id, Tab};
 use crate::mux::window::WindowId;
 use crate::mux::Mux;
+use crate::server::listener::served_socket_path;
 use downcast_rs::{impl_downcast, Downcast};
-use failure::{Error, Fallible};
+use failure::{bail, Error, Fallible};
 use log::info;
 use portable_pty::cmdbuilder::CommandBuilder;
 use portable_pty::{PtySize, PtySystem};
@@ -25,6 +26,27 @@ pub fn alloc_domain_id() -> DomainId {
     DOMAIN_ID.fetch_add(1, ::std::sync::atomic::Ordering::Relaxed)
 }
 
+/// Describes the connection state of a domain.
+/// Local domains are always `Attached`; client domains transition
+/// through the other states as their connection to the mux server
+/// comes and goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DomainState {
+    Attached,
+    Reconnecting,
+    Detached,
+}
+
+impl std::fmt::Display for DomainState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DomainState::Attached => write!(f, "connected"),
+            DomainState::Reconnecting => write!(f, "reconnecting"),
+            DomainState::Detached => write!(f, "detached"),
+        }
+    }
+}
+
 pub trait Domain: Downcast {
     /// Spawn a new command within this domain
     fn spawn(
@@ -38,27 +60,47 @@ pub trait Domain: Downcast {
     /// a handle on the domain later.
     fn domain_id(&self) -> DomainId;
 
+    /// Returns a short human readable name for this domain, such
+    /// as `local` or `mux:hostname`.  This is used to annotate tabs
+    /// in the gui.
+    fn domain_name(&self) -> &str;
+
+    /// Returns the current connection state of this domain
+    fn state(&self) -> DomainState;
+
     /// Re-attach to any tabs that might be pre-existing in this domain
     fn attach(&self) -> Fallible<()>;
+
+    /// Closes the local tabs of this domain while leaving the tabs
+    /// that they show running, so that they can be attached to again
+    fn detach(&self) -> Fallible<()> {
+        bail!("the {} domain can't be detached", self.domain_name());
+    }
+
+    /// Attaches again to a domain that was detached, from the running
+    /// gui.  Domains that have to wait for a server return straight
+    /// away and finish attaching on the gui thread later, keeping the
+    /// gui responsive meanwhile.
+    fn reattach(&self) -> Fallible<()> {
+        self.attach()
+    }
+
+    /// Called when the system resumed from sleep, so that a domain
+    /// whose connection may have died meanwhile can check it
+    fn system_resumed(&self) {}
 }
 impl_downcast!(Domain);
 
 pub struct LocalDomain {
     pty_system: Box<dyn PtySystem>,
-    config: Arc<Config>,
     id: DomainId,
 }
 
 impl LocalDomain {
     pub fn new(config: &Arc<Config>) -> Result<Self, Error> {
-        let config = Arc::clone(config);
         let pty_system = config.pty.get()?;
         let id = alloc_domain_id();
-        Ok(Self {
-            pty_system,
-            config,
-            id,
-        })
+        Ok(Self { pty_system, id })
     }
 }
 
@@ -69,24 +111,58 @@ impl Domain for LocalDomain {
         command: Option<CommandBuilder>,
         window: WindowId,
     ) -> Result<Rc<dyn Tab>, Error> {
-        let cmd = match command {
-            Some(c) => c,
-            None => self.config.build_prog(None)?,
+        // Use the current configuration so that tabs spawned after the
+        // config has been reloaded pick up the changes
+        let mux = Mux::get().unwrap();
+        let config = mux.config();
+        let mut cmd = match command {
+            Some(mut c) => {
+                config.apply_domain_environment(self.domain_name(), &mut c);
+                c
+            }
+            None => config.build_prog_for_domain(self.domain_name())?,
         };
+        let dumb_mode = config
+            .dumb_terminal_domains
+            .iter()
+            .any(|name| name == self.domain_name());
+        if dumb_mode {
+            cmd.env("TERM", "dumb");
+        }
+        let tab_id = alloc_tab_id();
+        // Let programs in the tab find their way back to it, for
+        // example to pass to `wezterm cli`.  Each tab currently holds
+        // a single pane, so the two ids are the same.
+        cmd.env("WEZTERM_TAB", tab_id.to_string());
+        cmd.env("WEZTERM_PANE", tab_id.to_string());
+        // Only a mux server has a socket that leads back to the tab
+        if let Some(path) = served_socket_path() {
+            cmd.env("WEZTERM_UNIX_SOCKET", path);
+        }
         let pair = self.pty_system.openpty(size)?;
         let child = pair.slave.spawn_command(cmd)?;
         info!("spawned: {:?}", child);
 
-        let terminal = term::Terminal::new(
+        let mut terminal = term::Terminal::new(
             size.rows as usize,
             size.cols as usize,
-            self.config.scrollback_lines.unwrap_or(3500),
-            self.config.hyperlink_rules.clone(),
+            size.pixel_width as usize,
+            size.pixel_height as usize,
+            config.scrollback_lines(),
+            config.hyperlink_rules.clone(),
         );
+        if let Some(word_boundary) = config.selection_word_boundary.as_ref() {
+            terminal.set_word_boundary(word_boundary);
+        }
+        terminal.set_implicit_link_click_modifiers(config.implicit_link_click_modifiers);
+        terminal.set_csi_u_key_encoding(config.enable_csi_u_key_encoding.unwrap_or(false));
+        terminal.set_ansi_art_mode(config.ansi_art_mode.unwrap_or(false));
+        terminal.set_dumb_mode(dumb_mode);
+        terminal.set_uncompressed_scrollback(Some(config.uncompressed_scrollback_lines()));
 
-        let tab: Rc<dyn Tab> = Rc::new(LocalTab::new(terminal, child, pair.master, self.id));
+        let tab: Rc<dyn Tab> =
+            Rc::new(LocalTab::new(tab_id, terminal, child, pair.master, self.id));
 
-        let mux = Mux::get().unwrap();
         mux.add_tab(&tab)?;
         mux.add_tab_to_window(&tab, window)?;
 
@@ -97,6 +173,14 @@ impl Domain for LocalDomain {
         self.id
     }
 
+    fn domain_name(&self) -> &str {
+        "local"
+    }
+
+    fn state(&self) -> DomainState {
+        DomainState::Attached
+    }
+
     fn attach(&self) -> Fallible<()> {
         Ok(())
     }
@@ -0,0 +1,239 @@
+//! Describes the windows of a session and the tabs in them, so that
+//! the layout can be saved to a file and restored later, either by the
+//! gui or against a mux server by `wezterm cli`.  Each tab currently
+//! holds a single pane, so there are no splits to describe yet.
+//!
+//! The file is TOML:
+//!
+//! ```toml
+//! [[windows]]
+//! [[windows.tabs]]
+//! cwd = "/home/me/src/wezterm"
+//! [[windows.tabs]]
+//! cwd = "/home/me"
+//! prog = ["htop"]
+//! ```
+use crate::config::{data_dir, Config};
+use crate::mux::Mux;
+use failure::{bail, format_err, Fallible};
+use portable_pty::CommandBuilder;
+use serde_derive::*;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Layout {
+    #[serde(default)]
+    pub windows: Vec<WindowLayout>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq)]
+pub struct WindowLayout {
+    #[serde(default)]
+    pub tabs: Vec<TabLayout>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq)]
+pub struct TabLayout {
+    /// The directory to start the program in
+    pub cwd: Option<String>,
+    /// The program to run in place of the default program.  This
+    /// isn't known for the tabs of a running session, so it is only
+    /// present if it was added to the file by hand.
+    pub prog: Option<Vec<String>>,
+}
+
+/// Returns the file that layouts are saved to and restored from when
+/// no other file is named
+pub fn default_layout_path() -> Fallible<PathBuf> {
+    Ok(data_dir()?.join("layout.toml"))
+}
+
+impl Layout {
+    pub fn load(path: &Path) -> Fallible<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format_err!("reading {}: {}", path.display(), e))?;
+        let layout: Self =
+            toml::from_str(&text).map_err(|e| format_err!("parsing {}: {}", path.display(), e))?;
+        if layout.windows.iter().all(|window| window.tabs.is_empty()) {
+            bail!("{} doesn't describe any tabs", path.display());
+        }
+        Ok(layout)
+    }
+
+    pub fn save(&self, path: &Path) -> Fallible<()> {
+        let text = toml::to_string(self)?;
+        std::fs::write(path, text).map_err(|e| format_err!("writing {}: {}", path.display(), e))?;
+        Ok(())
+    }
+
+    /// Describes the windows of the mux, in the order in which they
+    /// were created, using the directories that their shells report
+    pub fn from_mux(mux: &Mux) -> Self {
+        let mut window_ids = mux.iter_windows();
+        window_ids.sort();
+        let windows = window_ids
+            .into_iter()
+            .filter_map(|window_id| mux.get_window(window_id))
+            .map(|window| WindowLayout {
+                tabs: window
+                    .iter()
+                    .map(|tab| TabLayout {
+                        cwd: tab.get_current_dir(),
+                        prog: None,
+                    })
+                    .collect(),
+            })
+            .filter(|window| !window.tabs.is_empty())
+            .collect();
+        Self { windows }
+    }
+}
+
+impl TabLayout {
+    /// Returns the command to spawn the tab with in the named domain,
+    /// or None if the tab runs the default program in the default
+    /// directory
+    pub fn command(&self, config: &Config, domain_name: &str) -> Fallible<Option<CommandBuilder>> {
+        let mut command = match self.prog.as_ref() {
+            Some(prog) if prog.is_empty() => bail!("the prog of a tab is empty"),
+            Some(prog) => {
+                let mut command = CommandBuilder::new(&prog[0]);
+                command.args(&prog[1..]);
+                command
+            }
+            None if self.cwd.is_some() => config.build_prog_for_domain(domain_name)?,
+            None => return Ok(None),
+        };
+        if let Some(cwd) = self.cwd.as_ref() {
+            command.cwd(cwd);
+        }
+        Ok(Some(command))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::ffi::OsString;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("wezterm-layout-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn save_and_load() {
+        let layout: Layout = toml::from_str(
+            r#"
+[[windows]]
+[[windows.tabs]]
+cwd = "/home/me/src"
+[[windows.tabs]]
+cwd = "/home/me"
+prog = ["htop"]
+[[windows]]
+[[windows.tabs]]
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            layout,
+            Layout {
+                windows: vec![
+                    WindowLayout {
+                        tabs: vec![
+                            TabLayout {
+                                cwd: Some("/home/me/src".to_owned()),
+                                prog: None,
+                            },
+                            TabLayout {
+                                cwd: Some("/home/me".to_owned()),
+                                prog: Some(vec!["htop".to_owned()]),
+                            },
+                        ],
+                    },
+                    WindowLayout {
+                        tabs: vec![TabLayout::default()],
+                    },
+                ],
+            }
+        );
+
+        let path = temp_path("save");
+        layout.save(&path).unwrap();
+        let loaded = Layout::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(), layout);
+    }
+
+    #[test]
+    fn load_rejects_empty_layouts() {
+        for text in &["", "[[windows]]\n", "[[windows]]\ntabs = []\n"] {
+            let path = temp_path("empty");
+            std::fs::write(&path, text).unwrap();
+            let loaded = Layout::load(&path);
+            std::fs::remove_file(&path).unwrap();
+            assert!(loaded.is_err(), "{:?} was accepted", text);
+        }
+        assert!(Layout::load(&temp_path("missing")).is_err());
+    }
+
+    #[test]
+    fn tab_command() {
+        let config = Config::default_config();
+
+        assert!(TabLayout::default()
+            .command(&config, "local")
+            .unwrap()
+            .is_none());
+
+        let tab = TabLayout {
+            cwd: None,
+            prog: Some(vec![]),
+        };
+        assert!(tab.command(&config, "local").is_err());
+
+        let tab = TabLayout {
+            cwd: Some("/tmp".to_owned()),
+            prog: Some(vec!["htop".to_owned(), "-d".to_owned(), "10".to_owned()]),
+        };
+        let command = tab.command(&config, "local").unwrap().unwrap();
+        assert_eq!(command.get_cwd(), Some(&OsString::from("/tmp")));
+
+        // The default program is started in the directory
+        let tab = TabLayout {
+            cwd: Some("/tmp".to_owned()),
+            prog: None,
+        };
+        let command = tab.command(&config, "local").unwrap().unwrap();
+        assert_eq!(command.get_cwd(), Some(&OsString::from("/tmp")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn from_mux() {
+        use crate::testing::PtyHarness;
+
+        let harness =
+            PtyHarness::spawn_shell_command("printf '\\033]7;file://host/tmp\\007'; sleep 5")
+                .unwrap();
+        let mux = Mux::get().unwrap();
+        harness
+            .wait_for(|_| {
+                mux.get_tab(harness.tab_id())
+                    .and_then(|tab| tab.get_current_dir())
+                    .is_some()
+            })
+            .unwrap();
+        assert_eq!(
+            Layout::from_mux(&mux),
+            Layout {
+                windows: vec![WindowLayout {
+                    tabs: vec![TabLayout {
+                        cwd: Some("/tmp".to_owned()),
+                        prog: None,
+                    }],
+                }],
+            }
+        );
+    }
+}
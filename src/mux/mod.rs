@@ -1,41 +1,162 @@
-use crate::config::Config;
-use crate::frontend::gui_executor;
-use failure::{format_err, Error, Fallible};
+use crate::appearance::Appearance;
+use crate::config::{reload_scope, Config, ReloadScope};
+use crate::frontend::{front_end, gui_executor};
+use failure::{bail, err_msg, format_err, Error, Fallible};
 use failure_derive::*;
-use log::{debug, error, warn};
+use log::{debug, error, info, warn};
 use portable_pty::ExitStatus;
 use promise::{Executor, Future};
-use std::cell::{Ref, RefCell, RefMut};
-use std::collections::HashMap;
+use std::cell::{Cell, Ref, RefCell, RefMut};
+use std::collections::{HashMap, HashSet};
 use std::io::Read;
 use std::rc::Rc;
-use std::sync::Arc;
+use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
+use std::time::Duration;
+use term::color::ColorPalette;
 use term::TerminalHost;
 use termwiz::hyperlink::Hyperlink;
 
 pub mod domain;
+pub mod layout;
+#[cfg(unix)]
+mod ptypoller;
 pub mod renderable;
 pub mod tab;
 pub mod window;
 
 use crate::mux::tab::{Tab, TabId};
-use crate::mux::window::{Window, WindowId};
+use crate::mux::window::{report_focus, Window, WindowId};
 use domain::{Domain, DomainId};
 
+/// Describes a change in the state of the mux that may be of
+/// interest to parties other than the gui, such as the sessions
+/// of the mux server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MuxNotification {
+    /// The tab processed some output from its pty
+    TabOutput(TabId),
+    /// The tab was removed from the mux
+    TabRemoved(TabId),
+    /// The configuration was reloaded; the new configuration is
+    /// available from `Mux::config`
+    ConfigReloaded,
+    /// A program running in the tab asked to set the clipboard
+    /// via OSC 52.  None clears the clipboard.
+    SetClipboard(TabId, Option<String>),
+    /// The tab was switched to the named color scheme
+    SetColorScheme(TabId, String),
+    /// The program running in the tab rang the bell
+    Bell(TabId),
+    /// The program running in the tab reported a new working directory
+    CurrentDirChanged(TabId),
+    /// Something that the user should be told about, such as the
+    /// outcome of reloading the configuration
+    Notice(String),
+    /// The system resumed from sleep, so the windows should be
+    /// painted afresh and their tabs checked for exited programs
+    Resumed,
+}
+
+/// A subscriber returns false to indicate that it is no longer
+/// interested in notifications and should be removed.
+type MuxSubscriber = Box<dyn Fn(MuxNotification) -> bool>;
+
 pub struct Mux {
     tabs: RefCell<HashMap<TabId, Rc<dyn Tab>>>,
+    /// Shared with the thread that reads the pty of each tab
+    flow_control: RefCell<HashMap<TabId, Arc<FlowControl>>>,
+    /// Reads the ptys of the tabs that expose their descriptor,
+    /// started along with the first such tab
+    #[cfg(unix)]
+    pty_poller: RefCell<Option<ptypoller::PtyPoller>>,
     windows: RefCell<HashMap<WindowId, Window>>,
-    config: Arc<Config>,
+    config: RefCell<Arc<Config>>,
     default_domain: Arc<dyn Domain>,
     domains: RefCell<HashMap<DomainId, Arc<dyn Domain>>>,
+    subscribers: RefCell<Vec<MuxSubscriber>>,
+    /// The name of the active color scheme, or None to use the
+    /// palette from the `[colors]` section of the config
+    color_scheme: RefCell<Option<String>>,
+    /// The appearance preferred by the desktop, which chooses between
+    /// the light and dark color schemes of the config
+    appearance: Cell<Appearance>,
+    /// The generation of the most recent config whose font settings
+    /// should also be applied to the existing windows
+    forced_font_generation: Cell<usize>,
+}
+
+/// How many times, and how often, to check whether the program in a
+/// tab has exited after it closed the pty
+const EXIT_POLL_ATTEMPTS: usize = 20;
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Lets the mux clients that have fallen behind on the output of a
+/// tab stop its pty from being read until they have caught up, so
+/// that the program in the tab is slowed down to their pace
+#[derive(Default)]
+pub struct FlowControl {
+    /// The ids of the clients that are catching up
+    paused_by: Mutex<HashSet<usize>>,
+    resumed: Condvar,
+    /// Called once none of the clients are catching up, to wake a
+    /// reader that can't wait on `resumed`
+    on_resume: Mutex<Option<Box<dyn Fn() + Send>>>,
+}
+
+impl FlowControl {
+    pub fn pause(&self, client_id: usize) {
+        self.paused_by.lock().unwrap().insert(client_id);
+    }
+
+    pub fn resume(&self, client_id: usize) {
+        let mut paused_by = self.paused_by.lock().unwrap();
+        if paused_by.remove(&client_id) && paused_by.is_empty() {
+            self.notify_resumed();
+        }
+    }
+
+    /// Sets the function that is called once reading may go on
+    #[cfg(unix)]
+    fn set_on_resume<F: Fn() + Send + 'static>(&self, on_resume: F) {
+        self.on_resume.lock().unwrap().replace(Box::new(on_resume));
+    }
+
+    fn notify_resumed(&self) {
+        self.resumed.notify_all();
+        if let Some(on_resume) = self.on_resume.lock().unwrap().as_ref() {
+            on_resume();
+        }
+    }
+
+    fn is_paused(&self) -> bool {
+        !self.paused_by.lock().unwrap().is_empty()
+    }
+
+    fn resume_all(&self) {
+        self.paused_by.lock().unwrap().clear();
+        self.notify_resumed();
+    }
+
+    /// Blocks until none of the clients are catching up
+    fn wait(&self) {
+        let mut paused_by = self.paused_by.lock().unwrap();
+        while !paused_by.is_empty() {
+            paused_by = self.resumed.wait(paused_by).unwrap();
+        }
+    }
 }
 
-fn read_from_tab_pty(tab_id: TabId, mut reader: Box<dyn std::io::Read>) {
+fn read_from_tab_pty(
+    tab_id: TabId,
+    mut reader: Box<dyn std::io::Read>,
+    flow_control: Arc<FlowControl>,
+) {
     let executor = gui_executor().expect("gui_executor was not registered yet!?");
     const BUFSIZE: usize = 32 * 1024;
     let mut buf = [0; BUFSIZE];
     loop {
+        flow_control.wait();
         match reader.read(&mut buf) {
             Ok(size) if size == 0 => {
                 error!("read_pty EOF: tab_id {}", tab_id);
@@ -45,26 +166,60 @@ fn read_from_tab_pty(tab_id: TabId, mut reader: Box<dyn std::io::Read>) {
                 error!("read_pty failed: tab {} {:?}", tab_id, err);
                 break;
             }
-            Ok(size) => {
-                let data = buf[0..size].to_vec();
-                Future::with_executor(executor.clone_executor(), move || {
-                    let mux = Mux::get().unwrap();
-                    if let Some(tab) = mux.get_tab(tab_id) {
-                        tab.advance_bytes(
-                            &data,
-                            &mut Host {
-                                writer: &mut *tab.writer(),
-                            },
-                        );
-                    }
-                    Ok(())
-                });
-            }
+            Ok(size) => tab_output(&*executor, tab_id, buf[0..size].to_vec()),
+        }
+    }
+    tab_pty_closed(&*executor, tab_id);
+}
+
+/// Applies output read from the pty of a tab to the tab
+fn tab_output(executor: &dyn Executor, tab_id: TabId, data: Vec<u8>) {
+    Future::with_executor(executor.clone_executor(), move || {
+        let mux = Mux::get().unwrap();
+        if let Some(tab) = mux.get_tab(tab_id) {
+            tab.advance_bytes(
+                &data,
+                &mut Host {
+                    tab_id,
+                    writer: &mut *tab.writer(),
+                },
+            );
+            mux.notify(MuxNotification::TabOutput(tab_id));
         }
+        Ok(())
+    });
+}
+
+/// Removes a tab once its pty has been closed, unless the program in
+/// it is still running or the tab is held open.  This may block for
+/// a while, so it must not be called from the gui thread.
+fn tab_pty_closed(executor: &dyn Executor, tab_id: TabId) {
+    // The program usually exits just after it closes the pty.  Give
+    // it a moment so that its exit status can decide whether the
+    // exit_behavior setting holds the tab open.
+    for _ in 0..EXIT_POLL_ATTEMPTS {
+        let settled = Future::with_executor(executor.clone_executor(), move || {
+            let mux = Mux::get().unwrap();
+            Ok(match mux.get_tab(tab_id) {
+                Some(tab) => tab.is_dead() || tab.held_exit().is_some(),
+                None => true,
+            })
+        })
+        .wait()
+        .unwrap_or(true);
+        if settled {
+            break;
+        }
+        thread::sleep(EXIT_POLL_INTERVAL);
     }
+
     Future::with_executor(executor.clone_executor(), move || {
         let mux = Mux::get().unwrap();
-        mux.remove_tab(tab_id);
+        if let Some(tab) = mux.get_tab(tab_id) {
+            if tab.held_exit().is_none() || tab.is_dead() {
+                mux.remove_tab(tab_id);
+            }
+        }
         Ok(())
     });
 }
@@ -74,6 +229,7 @@ fn read_from_tab_pty(tab_id: TabId, mut reader: Box<dyn std::io::Read>) {
 /// As such it only really has Host::writer get called.
 /// The GUI driven flows provide their own impl of TerminalHost.
 struct Host<'a> {
+    tab_id: TabId,
     writer: &'a mut dyn std::io::Write,
 }
 
@@ -90,15 +246,44 @@ impl<'a> TerminalHost for Host<'a> {
     }
 
     fn get_clipboard(&mut self) -> Result<String, Error> {
-        warn!("peer requested clipboard; ignoring");
-        Ok("".into())
+        let mux = Mux::get().unwrap();
+        if !mux.config().osc52_clipboard_access().allows_read() {
+            bail!("tab {} may not read the clipboard", self.tab_id);
+        }
+        front_end()
+            .ok_or_else(|| err_msg("no front end"))?
+            .get_clipboard()
     }
 
-    fn set_clipboard(&mut self, _clip: Option<String>) -> Result<(), Error> {
-        Ok(())
+    fn set_clipboard(&mut self, clip: Option<String>) -> Result<(), Error> {
+        Mux::get().unwrap().set_clipboard(self.tab_id, clip)
     }
 
     fn set_title(&mut self, _title: &str) {}
+
+    fn bell(&mut self) {
+        Mux::get()
+            .unwrap()
+            .notify(MuxNotification::Bell(self.tab_id));
+    }
+
+    fn set_current_dir(&mut self, _dir: &str) {
+        Mux::get()
+            .unwrap()
+            .notify(MuxNotification::CurrentDirChanged(self.tab_id));
+    }
+
+    fn set_color_scheme(&mut self, name: &str) {
+        // The tab is busy processing output; switch once it is done
+        let name = name.to_owned();
+        Future::with_executor(gui_executor().unwrap(), move || {
+            let mux = Mux::get().unwrap();
+            if let Err(err) = mux.set_color_scheme(Some(name)) {
+                error!("{}", err);
+            }
+            Ok(())
+        });
+    }
 }
 
 thread_local! {
@@ -112,11 +297,67 @@ impl Mux {
 
         Self {
             tabs: RefCell::new(HashMap::new()),
+            flow_control: RefCell::new(HashMap::new()),
+            #[cfg(unix)]
+            pty_poller: RefCell::new(None),
             windows: RefCell::new(HashMap::new()),
-            config: Arc::clone(config),
+            config: RefCell::new(Arc::clone(config)),
             default_domain: Arc::clone(default_domain),
             domains: RefCell::new(domains),
+            subscribers: RefCell::new(vec![]),
+            color_scheme: RefCell::new(config.color_scheme_for_appearance(Appearance::default())),
+            appearance: Cell::new(Appearance::default()),
+            forced_font_generation: Cell::new(0),
+        }
+    }
+
+    /// Register a function to be called for each MuxNotification.
+    /// The function is called on the gui thread.
+    pub fn subscribe<F>(&self, subscriber: F)
+    where
+        F: Fn(MuxNotification) -> bool + 'static,
+    {
+        self.subscribers.borrow_mut().push(Box::new(subscriber));
+    }
+
+    /// Lets subscribers know that the renderable state of a tab has
+    /// changed for a reason other than output from its pty, such as
+    /// a mouse event moving the selection or hyperlink highlight.
+    pub fn notify_tab_changed(&self, tab_id: TabId) {
+        self.notify(MuxNotification::TabOutput(tab_id));
+    }
+
+    /// Passes a clipboard change requested by a program running in
+    /// the tab on to the subscribers, provided that the configuration
+    /// permits programs to set the clipboard.
+    pub fn set_clipboard(&self, tab_id: TabId, clip: Option<String>) -> Fallible<()> {
+        if !self.config().osc52_clipboard_access().allows_write() {
+            bail!("tab {} may not set the clipboard", tab_id);
         }
+        self.notify(MuxNotification::SetClipboard(tab_id, clip));
+        Ok(())
+    }
+
+    /// Asks the subscribers to tell the user about something; the
+    /// gui shows it as a toast
+    pub fn notify_user(&self, text: &str) {
+        self.notify(MuxNotification::Notice(text.to_owned()));
+    }
+
+    /// Brings everything up to date after the system resumed from
+    /// sleep: the domains check their connections straight away and
+    /// the subscribers are told, so that the gui can repaint.
+    pub fn system_resumed(&self) {
+        for domain in self.iter_domains() {
+            domain.system_resumed();
+        }
+        self.notify(MuxNotification::Resumed);
+    }
+
+    fn notify(&self, notification: MuxNotification) {
+        self.subscribers
+            .borrow_mut()
+            .retain(|subscriber| subscriber(notification.clone()));
     }
 
     pub fn default_domain(&self) -> &Arc<dyn Domain> {
@@ -127,6 +368,13 @@ impl Mux {
         self.domains.borrow().get(&id).cloned()
     }
 
+    /// Returns the domains, ordered by their ids
+    pub fn iter_domains(&self) -> Vec<Arc<dyn Domain>> {
+        let mut domains: Vec<_> = self.domains.borrow().values().cloned().collect();
+        domains.sort_by_key(|domain| domain.domain_id());
+        domains
+    }
+
     #[allow(dead_code)]
     pub fn add_domain(&self, domain: &Arc<dyn Domain>) {
         self.domains
@@ -134,8 +382,146 @@ impl Mux {
             .insert(domain.domain_id(), Arc::clone(domain));
     }
 
-    pub fn config(&self) -> &Arc<Config> {
-        &self.config
+    pub fn config(&self) -> Arc<Config> {
+        Arc::clone(&self.config.borrow())
+    }
+
+    /// Replaces the configuration, such as after the configuration file
+    /// was edited, and applies it to the existing tabs.  The color scheme
+    /// reverts to the one that the new configuration names for the
+    /// current desktop appearance.
+    /// Subscribers are notified so that they can apply the changes to
+    /// the things that they own, such as the gui windows.
+    /// Changes to the fonts are only applied to the existing windows
+    /// if `force_fonts` is true; otherwise only new windows use them.
+    pub fn set_config(&self, mut config: Config, force_fonts: bool) {
+        let changed = match self.config.borrow().changed_settings(&config) {
+            Ok(changed) => changed,
+            Err(err) => {
+                error!("unable to compare the configurations: {}", err);
+                vec![]
+            }
+        };
+        if changed.is_empty() && !force_fonts {
+            debug!("configuration was reloaded without changes");
+            return;
+        }
+        let messages = report_reload(&changed, force_fonts);
+
+        config.generation = self.config.borrow().generation + 1;
+        if force_fonts {
+            self.forced_font_generation.set(config.generation);
+        }
+        *self.color_scheme.borrow_mut() = config.color_scheme_for_appearance(self.appearance.get());
+        *self.config.borrow_mut() = Arc::new(config);
+        self.clear_window_color_schemes();
+
+        let config = self.config();
+        let palette = self.palette();
+        for tab in self.iter_tabs() {
+            tab.config_was_reloaded(&config);
+            tab.set_palette(palette.clone());
+        }
+        self.notify(MuxNotification::ConfigReloaded);
+        for message in messages {
+            self.notify_user(&message);
+        }
+    }
+
+    /// Returns true if the windows should rebuild their fonts for the
+    /// config of the given generation
+    pub fn fonts_are_forced(&self, generation: usize) -> bool {
+        self.forced_font_generation.get() == generation
+    }
+
+    /// Records the appearance preferred by the desktop and switches
+    /// to the color scheme that the config names for it
+    pub fn set_appearance(&self, appearance: Appearance) -> Fallible<()> {
+        self.appearance.set(appearance);
+        let name = self.config.borrow().color_scheme_for_appearance(appearance);
+        self.set_color_scheme(name)
+    }
+
+    /// Returns the palette of the active color scheme
+    pub fn palette(&self) -> ColorPalette {
+        self.config
+            .borrow()
+            .palette(self.color_scheme.borrow().as_ref().map(String::as_str))
+    }
+
+    /// Switches to the named color scheme, or to the `[colors]`
+    /// palette if `name` is None, and applies it to all tabs,
+    /// including those in windows that chose their own color scheme.
+    pub fn set_color_scheme(&self, name: Option<String>) -> Fallible<()> {
+        if let Some(name) = name.as_ref() {
+            self.color_scheme_palette(name)?;
+        }
+        *self.color_scheme.borrow_mut() = name;
+        self.clear_window_color_schemes();
+        let palette = self.palette();
+        for tab in self.iter_tabs() {
+            tab.set_palette(palette.clone());
+        }
+        Ok(())
+    }
+
+    /// Returns the palette of the named color scheme
+    fn color_scheme_palette(&self, name: &str) -> Fallible<ColorPalette> {
+        let config = self.config.borrow();
+        if config.color_scheme(name).is_none() {
+            bail!("color scheme `{}` is not defined", name);
+        }
+        Ok(config.palette(Some(name)))
+    }
+
+    fn clear_window_color_schemes(&self) {
+        for window in self.windows.borrow_mut().values_mut() {
+            window.set_color_scheme(None);
+        }
+    }
+
+    /// Switches the tabs of a window to the named color scheme.  Tabs
+    /// that are added to the window later use it too, until the global
+    /// color scheme changes or the configuration is reloaded.
+    pub fn set_window_color_scheme(&self, window_id: WindowId, name: &str) -> Fallible<()> {
+        let palette = self.color_scheme_palette(name)?;
+        let tabs: Vec<Rc<dyn Tab>> = {
+            let mut window = self
+                .get_window_mut(window_id)
+                .ok_or_else(|| format_err!("no such window {}", window_id))?;
+            window.set_color_scheme(Some(name.to_owned()));
+            window.iter().map(Rc::clone).collect()
+        };
+        for tab in tabs {
+            tab.set_palette(palette.clone());
+            self.notify(MuxNotification::SetColorScheme(
+                tab.tab_id(),
+                name.to_owned(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Switches a single tab to the named color scheme
+    pub fn set_tab_color_scheme(&self, tab_id: TabId, name: &str) -> Fallible<()> {
+        let palette = self.color_scheme_palette(name)?;
+        let tab = self
+            .get_tab(tab_id)
+            .ok_or_else(|| format_err!("no such tab {}", tab_id))?;
+        tab.set_palette(palette);
+        self.notify(MuxNotification::SetColorScheme(tab_id, name.to_owned()));
+        Ok(())
+    }
+
+    /// Switches to the next color scheme in name order, cycling back
+    /// around to the `[colors]` palette after the last one.
+    pub fn cycle_color_scheme(&self) -> Fallible<()> {
+        let names = self.config.borrow().color_scheme_names();
+        let next = match self.color_scheme.borrow().as_ref() {
+            None => names.first().cloned(),
+            Some(current) => names.iter().find(|name| *name > current).cloned(),
+        };
+        self.set_color_scheme(next)
     }
 
     pub fn set_mux(mux: &Rc<Mux>) {
@@ -159,18 +545,64 @@ impl Mux {
     }
 
     pub fn add_tab(&self, tab: &Rc<dyn Tab>) -> Result<(), Error> {
+        tab.set_palette(self.palette());
         self.tabs.borrow_mut().insert(tab.tab_id(), Rc::clone(tab));
 
-        let reader = tab.reader()?;
         let tab_id = tab.tab_id();
-        thread::spawn(move || read_from_tab_pty(tab_id, reader));
+        let flow_control = Arc::new(FlowControl::default());
+        self.flow_control
+            .borrow_mut()
+            .insert(tab_id, Arc::clone(&flow_control));
+
+        #[cfg(unix)]
+        {
+            if let Some(reader) = tab.pollable_reader()? {
+                return self.poll_tab_pty(tab_id, reader, flow_control);
+            }
+        }
+
+        let reader = tab.reader()?;
+        thread::spawn(move || read_from_tab_pty(tab_id, reader, flow_control));
 
         Ok(())
     }
 
+    /// Reads the pty of the tab on the thread shared by the ptys of
+    /// all such tabs, so that a mux with many tabs needs few threads
+    #[cfg(unix)]
+    fn poll_tab_pty(
+        &self,
+        tab_id: TabId,
+        reader: std::fs::File,
+        flow_control: Arc<FlowControl>,
+    ) -> Fallible<()> {
+        let mut poller = self.pty_poller.borrow_mut();
+        if poller.is_none() {
+            let executor = gui_executor().expect("gui_executor was not registered yet!?");
+            *poller = Some(ptypoller::PtyPoller::start(move |event| match event {
+                ptypoller::PtyEvent::Output(tab_id, data) => tab_output(&*executor, tab_id, data),
+                ptypoller::PtyEvent::Closed(tab_id) => {
+                    let executor = executor.clone_executor();
+                    thread::spawn(move || tab_pty_closed(&*executor, tab_id));
+                }
+            })?);
+        }
+        poller.as_ref().unwrap().add(tab_id, reader, flow_control)
+    }
+
     pub fn remove_tab(&self, tab_id: TabId) {
+        // Both the pty reader and the window that shows the tab try
+        // to remove it once it exits; only the first one counts, so
+        // that subscribers hear about it just once
+        if self.tabs.borrow_mut().remove(&tab_id).is_none() {
+            return;
+        }
         debug!("removing tab {}", tab_id);
-        self.tabs.borrow_mut().remove(&tab_id);
+        if let Some(flow_control) = self.flow_control.borrow_mut().remove(&tab_id) {
+            // Let the reader run into the end of the output
+            flow_control.resume_all();
+        }
+        self.notify(MuxNotification::TabRemoved(tab_id));
         let mut windows = self.windows.borrow_mut();
         let mut dead_windows = vec![];
         for (window_id, win) in windows.iter_mut() {
@@ -215,14 +647,54 @@ impl Mux {
         window_id
     }
 
+    /// Returns the means of pausing the reading of the pty of the tab
+    pub fn flow_control(&self, tab_id: TabId) -> Option<Arc<FlowControl>> {
+        self.flow_control.borrow().get(&tab_id).cloned()
+    }
+
     pub fn add_tab_to_window(&self, tab: &Rc<dyn Tab>, window_id: WindowId) -> Fallible<()> {
         let mut window = self
             .get_window_mut(window_id)
             .ok_or_else(|| format_err!("add_tab_to_window: no such window_id {}", window_id))?;
         window.push(tab);
+        if let Some(name) = window.color_scheme() {
+            tab.set_palette(self.config.borrow().palette(Some(name)));
+        }
         Ok(())
     }
 
+    /// Moves the tab out of the window that holds it and into another
+    /// window, removing the window that it leaves if that is now empty
+    pub fn move_tab_to_window(&self, tab_id: TabId, window_id: WindowId) -> Fallible<()> {
+        let tab = self
+            .get_tab(tab_id)
+            .ok_or_else(|| format_err!("move_tab_to_window: no such tab {}", tab_id))?;
+        {
+            let mut windows = self.windows.borrow_mut();
+            match windows.get(&window_id) {
+                Some(window) if window.idx_by_id(tab_id).is_some() => return Ok(()),
+                Some(_) => {}
+                None => bail!("move_tab_to_window: no such window_id {}", window_id),
+            }
+            let mut dead_windows = vec![];
+            for (id, win) in windows.iter_mut() {
+                // The tab takes its focus with it only if the window
+                // that it joins has the focus
+                if win.is_focused() && win.get_active().map(|t| t.tab_id()) == Some(tab_id) {
+                    report_focus(&tab, false);
+                }
+                if win.remove_by_id(tab_id) && win.is_empty() {
+                    dead_windows.push(*id);
+                }
+            }
+            for id in dead_windows {
+                debug!("removing window {}", id);
+                windows.remove(&id);
+            }
+        }
+        self.add_tab_to_window(&tab, window_id)
+    }
+
     #[allow(dead_code)]
     pub fn is_empty(&self) -> bool {
         self.tabs.borrow().is_empty()
@@ -242,6 +714,50 @@ impl Mux {
     }
 }
 
+/// Logs which of the `changed` settings were applied by a reload of
+/// the configuration, and which only take effect later.  Returns the
+/// logged messages so that they can be shown to the user.
+fn report_reload(changed: &[String], force_fonts: bool) -> Vec<String> {
+    let names_with_scope = |scope: ReloadScope| -> Vec<&str> {
+        changed
+            .iter()
+            .map(String::as_str)
+            .filter(|name| reload_scope(name) == scope)
+            .collect()
+    };
+    let mut applied = names_with_scope(ReloadScope::Immediate);
+    let fonts = names_with_scope(ReloadScope::Fonts);
+    let later = names_with_scope(ReloadScope::NewTabsOrRestart);
+
+    let mut messages = vec![];
+    if force_fonts {
+        applied.extend(fonts.iter());
+    }
+    if !applied.is_empty() {
+        let message = format!("Reloaded the configuration; applied {}", applied.join(", "));
+        info!("{}", message);
+        messages.push(message);
+    }
+    if !force_fonts && !fonts.is_empty() {
+        let message = format!(
+            "Changes to {} only apply to new windows; \
+             use the ReloadConfiguration action to apply them to the existing windows",
+            fonts.join(", ")
+        );
+        warn!("{}", message);
+        messages.push(message);
+    }
+    if !later.is_empty() {
+        let message = format!(
+            "Changes to {} only apply to new tabs or after restarting wezterm",
+            later.join(", ")
+        );
+        warn!("{}", message);
+        messages.push(message);
+    }
+    messages
+}
+
 #[derive(Debug, Fail)]
 #[allow(dead_code)]
 pub enum SessionTerminated {
@@ -252,3 +768,98 @@ pub enum SessionTerminated {
     #[fail(display = "Window Closed")]
     WindowClosed,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mux::domain::LocalDomain;
+    use crate::mux::tab::alloc_tab_id;
+    use crate::testing::FakeTab;
+    use std::sync::mpsc::{channel, Receiver};
+
+    /// Waits on `flow_control` on a thread of its own, returning a
+    /// channel that is sent to once the wait is over
+    fn wait_in_thread(flow_control: &Arc<FlowControl>) -> Receiver<()> {
+        let flow_control = Arc::clone(flow_control);
+        let (tx, rx) = channel();
+        thread::spawn(move || {
+            flow_control.wait();
+            tx.send(()).ok();
+        });
+        rx
+    }
+
+    #[test]
+    fn flow_control() {
+        let flow_control = Arc::new(FlowControl::default());
+        // Nobody is catching up, so there is nothing to wait for
+        flow_control.wait();
+
+        // Reading stays paused until every client has caught up
+        flow_control.pause(1);
+        flow_control.pause(2);
+        let waited = wait_in_thread(&flow_control);
+        flow_control.resume(1);
+        assert!(waited.recv_timeout(Duration::from_millis(100)).is_err());
+        // Resuming a client that isn't catching up changes nothing
+        flow_control.resume(3);
+        assert!(waited.recv_timeout(Duration::from_millis(100)).is_err());
+        flow_control.resume(2);
+        assert!(waited.recv_timeout(Duration::from_secs(5)).is_ok());
+
+        // Removing the tab releases the reader regardless
+        flow_control.pause(1);
+        let waited = wait_in_thread(&flow_control);
+        assert!(waited.recv_timeout(Duration::from_millis(100)).is_err());
+        flow_control.resume_all();
+        assert!(waited.recv_timeout(Duration::from_secs(5)).is_ok());
+    }
+
+    fn window_tabs(mux: &Mux, window_id: WindowId) -> Vec<TabId> {
+        mux.get_window(window_id)
+            .map(|window| window.iter().map(|tab| tab.tab_id()).collect())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn move_tab_to_window() {
+        let config = Arc::new(Config::default_config());
+        let domain: Arc<dyn Domain> = Arc::new(LocalDomain::new(&config).unwrap());
+        let mux = Mux::new(&config, &domain);
+
+        // A FakeTab has no pty to read, so skip add_tab
+        let tabs: Vec<_> = (0..3).map(|_| FakeTab::new()).collect();
+        for tab in &tabs {
+            mux.tabs.borrow_mut().insert(tab.tab_id(), Rc::clone(tab));
+        }
+        let ids: Vec<_> = tabs.iter().map(|tab| tab.tab_id()).collect();
+        let first = mux.new_empty_window();
+        let second = mux.new_empty_window();
+        mux.add_tab_to_window(&tabs[0], first).unwrap();
+        mux.add_tab_to_window(&tabs[1], first).unwrap();
+        mux.add_tab_to_window(&tabs[2], second).unwrap();
+        mux.get_window_mut(first).unwrap().set_focused(true);
+        assert_eq!(FakeTab::focus_changes(&tabs[0]), vec![true]);
+
+        // The focused tab loses the focus when it is torn off, and the
+        // tab left behind gains it
+        mux.move_tab_to_window(ids[0], second).unwrap();
+        assert_eq!(window_tabs(&mux, first), vec![ids[1]]);
+        assert_eq!(window_tabs(&mux, second), vec![ids[2], ids[0]]);
+        assert_eq!(FakeTab::focus_changes(&tabs[0]), vec![true, false]);
+        assert_eq!(FakeTab::focus_changes(&tabs[1]), vec![true]);
+
+        // Dropping a tab back onto its own window changes nothing
+        mux.move_tab_to_window(ids[0], second).unwrap();
+        assert_eq!(window_tabs(&mux, second), vec![ids[2], ids[0]]);
+
+        // The window that is left empty goes away
+        mux.move_tab_to_window(ids[1], second).unwrap();
+        assert!(mux.get_window(first).is_none());
+        assert_eq!(window_tabs(&mux, second), vec![ids[2], ids[0], ids[1]]);
+
+        assert!(mux.move_tab_to_window(ids[0], first).is_err());
+        assert!(mux.move_tab_to_window(alloc_tab_id(), second).is_err());
+        assert_eq!(window_tabs(&mux, second), vec![ids[2], ids[0], ids[1]]);
+    }
+}
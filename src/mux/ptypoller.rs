@@ -0,0 +1,251 @@
+//! Reads the output of the ptys of many tabs from a single thread,
+//! rather than from a thread for each tab, by waiting for any of them
+//! to become readable with `mio`.
+use crate::mux::tab::TabId;
+use crate::mux::FlowControl;
+use failure::{format_err, Fallible};
+use log::error;
+use mio::unix::EventedFd;
+use mio::{Events, Poll, PollOpt, Ready, Token};
+use mio_extras::channel::{channel, Receiver, Sender};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+use std::os::unix::io::AsRawFd;
+use std::sync::mpsc::TryRecvError;
+use std::sync::Arc;
+use std::thread;
+
+const TOK_NEW_TAB: usize = 0xffff_fffe;
+const TOK_RESUMED: usize = 0xffff_ffff;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PtyEvent {
+    /// Output was read from the pty of the tab
+    Output(TabId, Vec<u8>),
+    /// The pty of the tab was closed, or could not be read
+    Closed(TabId),
+}
+
+struct PolledTab {
+    tab_id: TabId,
+    reader: File,
+    flow_control: Arc<FlowControl>,
+}
+
+pub struct PtyPoller {
+    tx: Sender<PolledTab>,
+    /// Tells the thread which tabs their `FlowControl` let go on
+    resumed_tx: Sender<TabId>,
+}
+
+impl PtyPoller {
+    /// Starts the thread that reads the ptys.  `handler` is called
+    /// on that thread with what was read from each of them.  The
+    /// thread stops once the poller has been dropped and each of
+    /// the ptys that it was reading has been closed.
+    pub fn start<F>(handler: F) -> Fallible<Self>
+    where
+        F: FnMut(PtyEvent) + Send + 'static,
+    {
+        let poll = Poll::new()?;
+        let (tx, rx) = channel();
+        let (resumed_tx, resumed_rx) = channel();
+        poll.register(&rx, Token(TOK_NEW_TAB), Ready::readable(), PollOpt::level())?;
+        poll.register(
+            &resumed_rx,
+            Token(TOK_RESUMED),
+            Ready::readable(),
+            PollOpt::level(),
+        )?;
+        thread::Builder::new()
+            .name("pty-poller".into())
+            .spawn(move || {
+                if let Err(err) = run(&poll, &rx, &resumed_rx, handler) {
+                    error!("pty poller failed: {}", err);
+                }
+            })?;
+        Ok(Self { tx, resumed_tx })
+    }
+
+    /// Starts reading the output of a tab from `reader`, which must
+    /// be in blocking mode.  Reads are only made once poll reports
+    /// that the pty is readable, so they never block.
+    pub fn add(&self, tab_id: TabId, reader: File, flow_control: Arc<FlowControl>) -> Fallible<()> {
+        let resumed_tx = self.resumed_tx.clone();
+        flow_control.set_on_resume(move || {
+            // The thread may have stopped, in which case there is
+            // nothing left to wake
+            resumed_tx.send(tab_id).ok();
+        });
+        self.tx
+            .send(PolledTab {
+                tab_id,
+                reader,
+                flow_control,
+            })
+            .map_err(|_| format_err!("the pty poller has stopped"))
+    }
+}
+
+fn run<F: FnMut(PtyEvent)>(
+    poll: &Poll,
+    rx: &Receiver<PolledTab>,
+    resumed_rx: &Receiver<TabId>,
+    mut handler: F,
+) -> Fallible<()> {
+    const BUFSIZE: usize = 32 * 1024;
+    let mut buf = [0; BUFSIZE];
+    let mut tabs: HashMap<TabId, PolledTab> = HashMap::new();
+    // Tabs that are not registered with poll while their output is
+    // paused, as poll would otherwise keep reporting them as readable.
+    // Their FlowControl tells us via resumed_rx when to read them again.
+    let mut paused: HashSet<TabId> = HashSet::new();
+    let mut events = Events::with_capacity(64);
+    let mut accepting = true;
+
+    while accepting || !tabs.is_empty() {
+        poll.poll(&mut events, None)?;
+
+        for event in &events {
+            let tab_id = match event.token() {
+                Token(TOK_RESUMED) => {
+                    while let Ok(tab_id) = resumed_rx.try_recv() {
+                        // It may have been paused again since
+                        if paused.contains(&tab_id) && !tabs[&tab_id].flow_control.is_paused() {
+                            paused.remove(&tab_id);
+                            register(poll, &tabs[&tab_id])?;
+                        }
+                    }
+                    continue;
+                }
+                Token(TOK_NEW_TAB) => {
+                    loop {
+                        match rx.try_recv() {
+                            Ok(tab) => {
+                                register(poll, &tab)?;
+                                tabs.insert(tab.tab_id, tab);
+                            }
+                            Err(TryRecvError::Empty) => break,
+                            Err(TryRecvError::Disconnected) => {
+                                poll.deregister(rx)?;
+                                accepting = false;
+                                break;
+                            }
+                        }
+                    }
+                    continue;
+                }
+                Token(tab_id) => tab_id,
+            };
+            let tab = match tabs.get_mut(&tab_id) {
+                Some(tab) => tab,
+                None => continue,
+            };
+            if tab.flow_control.is_paused() {
+                deregister(poll, tab)?;
+                paused.insert(tab_id);
+                continue;
+            }
+            match tab.reader.read(&mut buf) {
+                Ok(size) if size == 0 => error!("read_pty EOF: tab_id {}", tab_id),
+                Ok(size) => {
+                    handler(PtyEvent::Output(tab_id, buf[0..size].to_vec()));
+                    continue;
+                }
+                Err(ref err)
+                    if err.kind() == ErrorKind::Interrupted
+                        || err.kind() == ErrorKind::WouldBlock =>
+                {
+                    continue;
+                }
+                Err(err) => error!("read_pty failed: tab {} {:?}", tab_id, err),
+            }
+            deregister(poll, tab)?;
+            tabs.remove(&tab_id);
+            handler(PtyEvent::Closed(tab_id));
+        }
+    }
+    Ok(())
+}
+
+fn register(poll: &Poll, tab: &PolledTab) -> Fallible<()> {
+    poll.register(
+        &EventedFd(&tab.reader.as_raw_fd()),
+        Token(tab.tab_id),
+        Ready::readable(),
+        PollOpt::level(),
+    )?;
+    Ok(())
+}
+
+fn deregister(poll: &Poll, tab: &PolledTab) -> Fallible<()> {
+    poll.deregister(&EventedFd(&tab.reader.as_raw_fd()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+    use std::os::unix::io::FromRawFd;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    fn pipe() -> (File, File) {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) }
+    }
+
+    #[test]
+    fn reads_many_ptys() {
+        let (tx, rx) = mpsc::channel();
+        let poller = PtyPoller::start(move |event| tx.send(event).unwrap()).unwrap();
+        let wait = Duration::from_secs(5);
+
+        let (first_reader, mut first) = pipe();
+        let (second_reader, mut second) = pipe();
+        let flow_control = Arc::new(FlowControl::default());
+        poller
+            .add(1, first_reader, Arc::clone(&flow_control))
+            .unwrap();
+        poller.add(2, second_reader, Arc::default()).unwrap();
+
+        first.write_all(b"hello").unwrap();
+        assert_eq!(
+            rx.recv_timeout(wait).unwrap(),
+            PtyEvent::Output(1, b"hello".to_vec())
+        );
+        second.write_all(b"there").unwrap();
+        assert_eq!(
+            rx.recv_timeout(wait).unwrap(),
+            PtyEvent::Output(2, b"there".to_vec())
+        );
+
+        // Output that arrives while paused is left in the pty, without
+        // holding up the other tabs
+        flow_control.pause(7);
+        first.write_all(b"later").unwrap();
+        second.write_all(b"now").unwrap();
+        assert_eq!(
+            rx.recv_timeout(wait).unwrap(),
+            PtyEvent::Output(2, b"now".to_vec())
+        );
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+        flow_control.resume(7);
+        assert_eq!(
+            rx.recv_timeout(wait).unwrap(),
+            PtyEvent::Output(1, b"later".to_vec())
+        );
+
+        drop(first);
+        assert_eq!(rx.recv_timeout(wait).unwrap(), PtyEvent::Closed(1));
+
+        // The thread outlives the poller until the last pty closes
+        drop(poller);
+        drop(second);
+        assert_eq!(rx.recv_timeout(wait).unwrap(), PtyEvent::Closed(2));
+        assert!(rx.recv().is_err());
+    }
+}
@@ -1,9 +1,116 @@
+use crate::mux::tab::TabId;
 use downcast_rs::{impl_downcast, Downcast};
 use std::ops::Range;
 use std::sync::Arc;
-use term::{CursorPosition, Line, Terminal, TerminalState};
+use term::color::ColorPalette;
+use term::{CursorPosition, Line, SequenceNo, Terminal, TerminalState, SEQ_ZERO};
+use termwiz::escape::csi::CursorStyle;
 use termwiz::hyperlink::Hyperlink;
 
+/// Describes the position of the viewport within the scrollback,
+/// which is used to draw the scrollbar
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ScrollbarInfo {
+    /// The number of rows of scrollback above the screen
+    pub scrollback_rows: usize,
+    /// The number of rows that the viewport is scrolled back by
+    pub viewport_offset: usize,
+}
+
+/// Numbers the lines of the screen and its scrollback so that a line
+/// keeps its number as the oldest lines are discarded
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LineNumbering {
+    /// Whether the lines are those of the alternate screen, which are
+    /// numbered apart from those of the primary screen
+    pub alt_screen: bool,
+    /// The number of the topmost line of the scrollback, which is how
+    /// many lines have been discarded before it
+    pub first_row: usize,
+}
+
+/// An immutable copy of the state needed to draw a tab.  The lines
+/// are reference counted so that taking a snapshot only copies the
+/// lines that changed since the prior snapshot, and so that the
+/// snapshot can be painted (or sent to a mux client) while the model
+/// continues to be updated.
+#[derive(Clone)]
+pub struct RenderableSnapshot {
+    pub tab_id: TabId,
+    /// The sequence number at which the snapshot was taken
+    pub seqno: SequenceNo,
+    /// The visible lines, along with their selected column range
+    pub lines: Vec<(Arc<Line>, Range<usize>)>,
+    pub cursor: CursorPosition,
+    pub cursor_style: CursorStyle,
+    pub current_highlight: Option<Arc<Hyperlink>>,
+    pub physical_rows: usize,
+    pub physical_cols: usize,
+    pub scrollbar: ScrollbarInfo,
+    pub palette: ColorPalette,
+    /// Whether the program asked to be sent mouse events
+    pub mouse_grabbed: bool,
+}
+
+impl RenderableSnapshot {
+    /// Captures the state of `renderable`.  Lines that haven't changed
+    /// since `prior` was taken are shared with it rather than copied.
+    /// `prior` is ignored if it was taken from another tab or at
+    /// different dimensions.
+    pub fn capture(
+        tab_id: TabId,
+        renderable: &mut dyn Renderable,
+        palette: ColorPalette,
+        prior: Option<&RenderableSnapshot>,
+    ) -> Self {
+        let (physical_rows, physical_cols) = renderable.physical_dimensions();
+        let prior = prior.filter(|prior| {
+            prior.tab_id == tab_id
+                && prior.physical_rows == physical_rows
+                && prior.physical_cols == physical_cols
+                && prior.lines.len() == physical_rows
+        });
+
+        let since = prior.map(|prior| prior.seqno).unwrap_or(SEQ_ZERO);
+        let seqno = renderable.current_seqno();
+
+        let mut lines = match prior {
+            Some(prior) => prior.lines.clone(),
+            None => vec![(Arc::new(Line::with_width(physical_cols)), 0..0); physical_rows],
+        };
+        for (line_idx, line, selrange) in renderable.get_changed_lines(since) {
+            if let Some(slot) = lines.get_mut(line_idx) {
+                *slot = (Arc::new(line), selrange);
+            }
+        }
+
+        Self {
+            tab_id,
+            seqno,
+            lines,
+            cursor: renderable.get_cursor_position(),
+            cursor_style: renderable.get_cursor_style(),
+            current_highlight: renderable.current_highlight(),
+            physical_rows,
+            physical_cols,
+            scrollbar: renderable.scrollbar_info(),
+            palette,
+            mouse_grabbed: renderable.is_mouse_grabbed(),
+        }
+    }
+
+    /// Returns the lines that changed after `seqno` as a
+    /// Vec<(line_idx, line, selrange)>
+    pub fn changed_lines(&self, seqno: SequenceNo) -> Vec<(usize, &Line, Range<usize>)> {
+        self.lines
+            .iter()
+            .enumerate()
+            .filter(|(_, (line, _))| line.changed_since(seqno))
+            .map(|(idx, (line, selrange))| (idx, &**line, selrange.clone()))
+            .collect()
+    }
+}
+
 /// Renderable allows passing something that isn't an actual term::Terminal
 /// instance into the renderer, which opens up remoting of the terminal
 /// surfaces via a multiplexer.
@@ -12,26 +119,57 @@ pub trait Renderable: Downcast {
     /// the visible screen
     fn get_cursor_position(&self) -> CursorPosition;
 
-    /// Returns the set of visible lines that are dirty.
+    /// Returns the shape of the cursor that the application asked for
+    fn get_cursor_style(&self) -> CursorStyle;
+
+    /// Records the changes made since the previous call and returns
+    /// the sequence number that they were recorded with.  Each party
+    /// that renders the lines keeps its own copy of this value to
+    /// learn what changed since it last looked.
+    fn current_seqno(&mut self) -> SequenceNo;
+
+    /// Returns the set of visible lines that have changed after `seqno`.
     /// The return value is a Vec<(line_idx, line, selrange)>, where
     /// line_idx is relative to the top of the viewport.
     /// The selrange value is the column range representing the selected
     /// columns on this line.
-    fn get_dirty_lines(&self) -> Vec<(usize, Line, Range<usize>)>;
+    fn get_changed_lines(&self, seqno: SequenceNo) -> Vec<(usize, Line, Range<usize>)>;
 
-    fn has_dirty_lines(&self) -> bool;
+    /// Returns true if any of the visible lines have changed after `seqno`
+    fn has_changes_since(&self, seqno: SequenceNo) -> bool;
 
+    /// Marks all of the lines as changed
     fn make_all_lines_dirty(&mut self);
 
-    /// Clear the dirty flag for all dirty lines
-    fn clean_dirty_lines(&mut self);
-
     /// Returns the currently highlighted hyperlink
     fn current_highlight(&self) -> Option<Arc<Hyperlink>>;
 
     /// Returns physical, non-scrollback (rows, cols) for the
     /// terminal screen
     fn physical_dimensions(&self) -> (usize, usize);
+
+    /// Returns the position of the viewport within the scrollback
+    fn scrollbar_info(&self) -> ScrollbarInfo;
+
+    /// Returns how the lines of the scrollback are numbered
+    fn line_numbering(&self) -> LineNumbering;
+
+    /// Scroll the viewport so that it is `offset` rows back from the
+    /// bottom of the scrollback
+    fn set_viewport_offset(&mut self, offset: usize);
+
+    /// Returns up to `rows` lines from the top of the viewport that is
+    /// shown when scrolled back by `offset` rows, without moving the
+    /// viewport
+    fn get_viewport_lines(&mut self, offset: usize, rows: usize) -> Vec<Line>;
+
+    /// Scroll the viewport back (negative) or forward (positive) by
+    /// `delta` shell prompts
+    fn scroll_to_prompt(&mut self, delta: isize);
+
+    /// Returns true if the program asked to be sent mouse events, in
+    /// which case clicks go to it rather than to the mouse bindings
+    fn is_mouse_grabbed(&self) -> bool;
 }
 impl_downcast!(Renderable);
 
@@ -40,15 +178,23 @@ impl Renderable for Terminal {
         self.cursor_pos()
     }
 
-    fn get_dirty_lines(&self) -> Vec<(usize, Line, Range<usize>)> {
-        TerminalState::get_dirty_lines(self)
+    fn get_cursor_style(&self) -> CursorStyle {
+        TerminalState::cursor_style(self)
+    }
+
+    fn current_seqno(&mut self) -> SequenceNo {
+        TerminalState::current_seqno(self)
+    }
+
+    fn get_changed_lines(&self, seqno: SequenceNo) -> Vec<(usize, Line, Range<usize>)> {
+        TerminalState::get_changed_lines(self, seqno)
             .into_iter()
             .map(|(idx, line, range)| (idx, line.clone(), range))
             .collect()
     }
 
-    fn clean_dirty_lines(&mut self) {
-        TerminalState::clean_dirty_lines(self)
+    fn has_changes_since(&self, seqno: SequenceNo) -> bool {
+        TerminalState::has_changes_since(self, seqno)
     }
 
     fn make_all_lines_dirty(&mut self) {
@@ -64,7 +210,34 @@ impl Renderable for Terminal {
         (screen.physical_rows, screen.physical_cols)
     }
 
-    fn has_dirty_lines(&self) -> bool {
-        TerminalState::has_dirty_lines(self)
+    fn scrollbar_info(&self) -> ScrollbarInfo {
+        let screen = self.screen();
+        ScrollbarInfo {
+            scrollback_rows: screen.cold_lines() + screen.lines.len() - screen.physical_rows,
+            viewport_offset: self.get_viewport_offset() as usize,
+        }
+    }
+
+    fn line_numbering(&self) -> LineNumbering {
+        LineNumbering {
+            alt_screen: self.is_alt_screen_active(),
+            first_row: self.screen().discarded_rows(),
+        }
+    }
+
+    fn set_viewport_offset(&mut self, offset: usize) {
+        self.set_scroll_viewport(offset as i64)
+    }
+
+    fn get_viewport_lines(&mut self, offset: usize, rows: usize) -> Vec<Line> {
+        TerminalState::get_viewport_lines(self, offset, rows)
+    }
+
+    fn scroll_to_prompt(&mut self, delta: isize) {
+        TerminalState::scroll_to_prompt(self, delta)
+    }
+
+    fn is_mouse_grabbed(&self) -> bool {
+        TerminalState::is_mouse_grabbed(self)
     }
 }
@@ -1,11 +1,14 @@
+use crate::config::Config;
 use crate::mux::domain::DomainId;
-use crate::mux::renderable::Renderable;
+use crate::mux::renderable::{Renderable, RenderableSnapshot};
 use downcast_rs::{impl_downcast, Downcast};
 use failure::Fallible;
-use portable_pty::PtySize;
+use portable_pty::{ExitStatus, PtySize};
 use std::cell::RefMut;
+use std::sync::Arc;
 use term::color::ColorPalette;
-use term::{KeyCode, KeyModifiers, MouseEvent, TerminalHost};
+use term::{CopyModeAction, KeyCode, KeyModifiers, MouseEvent, TerminalHost};
+use termwiz::hyperlink::Hyperlink;
 
 static TAB_ID: ::std::sync::atomic::AtomicUsize = ::std::sync::atomic::AtomicUsize::new(0);
 pub type TabId = usize;
@@ -17,16 +20,77 @@ pub fn alloc_tab_id() -> TabId {
 pub trait Tab: Downcast {
     fn tab_id(&self) -> TabId;
     fn renderer(&self) -> RefMut<dyn Renderable>;
+
+    /// Takes a snapshot of the state needed to draw the tab, sharing
+    /// the unchanged lines with `prior`
+    fn snapshot(&self, prior: Option<&RenderableSnapshot>) -> RenderableSnapshot {
+        let palette = self.palette();
+        let mut renderer = self.renderer();
+        RenderableSnapshot::capture(self.tab_id(), &mut *renderer, palette, prior)
+    }
     fn get_title(&self) -> String;
+    /// Sets a title that is shown in place of the one chosen by the
+    /// program in the tab, or reverts to that one if None
+    fn set_title(&self, title: Option<String>);
+    /// Returns the working directory that the program in the tab
+    /// reported with OSC 7, if any
+    fn get_current_dir(&self) -> Option<String> {
+        None
+    }
     fn send_paste(&self, text: &str) -> Fallible<()>;
     fn reader(&self) -> Fallible<Box<dyn std::io::Read + Send>>;
+    /// Returns a reader for the pty of the tab that can be waited on
+    /// with poll, so that the mux can read it on a thread shared with
+    /// other tabs.  Tabs that return None are read via `reader` on a
+    /// thread of their own.
+    #[cfg(unix)]
+    fn pollable_reader(&self) -> Fallible<Option<std::fs::File>> {
+        Ok(None)
+    }
     fn writer(&self) -> RefMut<dyn std::io::Write>;
     fn resize(&self, size: PtySize) -> Fallible<()>;
     fn key_down(&self, key: KeyCode, mods: KeyModifiers) -> Fallible<()>;
     fn mouse_event(&self, event: MouseEvent, host: &mut dyn TerminalHost) -> Fallible<()>;
+    /// Tells the tab that the window showing it gained or lost the
+    /// keyboard focus, which the program in it may ask to be told
+    fn focus_changed(&self, focused: bool) -> Fallible<()>;
     fn advance_bytes(&self, buf: &[u8], host: &mut dyn TerminalHost);
     fn is_dead(&self) -> bool;
+
+    /// When the program in the tab has exited but the tab is being
+    /// held open by the `exit_behavior` setting, returns how the
+    /// program exited.  The tab is dead once a key is pressed in it.
+    fn held_exit(&self) -> Option<ExitStatus> {
+        None
+    }
+
+    /// A notice to show along the bottom of the tab, such as while the
+    /// connection to the server that hosts it is being re-established
+    fn status_banner(&self) -> Option<String> {
+        None
+    }
     fn palette(&self) -> ColorPalette;
+    fn set_palette(&self, palette: ColorPalette);
     fn domain_id(&self) -> DomainId;
+
+    /// Performs a copy mode action.  Returns the selected text when
+    /// the action completes a copy.
+    fn copy_mode(&self, action: CopyModeAction) -> Fallible<Option<String>>;
+    fn in_copy_mode(&self) -> bool;
+
+    /// Returns the hyperlink at or preceding the cursor, falling back
+    /// to the last hyperlink on the screen
+    fn hyperlink_near_cursor(&self) -> Fallible<Option<Arc<Hyperlink>>>;
+
+    /// Returns the text that is selected in the tab, for tabs that
+    /// track the selection locally
+    fn selection_text(&self) -> Option<String> {
+        None
+    }
+
+    /// Applies the settings from a reloaded configuration that affect
+    /// the terminal model, such as the hyperlink rules and the size
+    /// of the scrollback
+    fn config_was_reloaded(&self, config: &Config);
 }
 impl_downcast!(Tab);
@@ -1,4 +1,5 @@
 use crate::mux::{Tab, TabId};
+use log::error;
 use std::rc::Rc;
 
 static WIN_ID: ::std::sync::atomic::AtomicUsize = ::std::sync::atomic::AtomicUsize::new(0);
@@ -8,6 +9,14 @@ pub struct Window {
     id: WindowId,
     tabs: Vec<Rc<dyn Tab>>,
     active: usize,
+    /// The tab that was active before the current one, remembered by
+    /// id so that reordering or closing other tabs doesn't lose it
+    last_active: Option<TabId>,
+    /// The color scheme chosen for this window, overriding the
+    /// global color scheme of the mux
+    color_scheme: Option<String>,
+    /// Whether the gui window showing this one has the keyboard focus
+    focused: bool,
 }
 
 impl Window {
@@ -16,6 +25,9 @@ impl Window {
             id: WIN_ID.fetch_add(1, ::std::sync::atomic::Ordering::Relaxed),
             tabs: vec![],
             active: 0,
+            last_active: None,
+            color_scheme: None,
+            focused: false,
         }
     }
 
@@ -23,11 +35,21 @@ impl Window {
         self.id
     }
 
+    pub fn color_scheme(&self) -> Option<&str> {
+        self.color_scheme.as_ref().map(String::as_str)
+    }
+
+    pub fn set_color_scheme(&mut self, name: Option<String>) {
+        self.color_scheme = name;
+    }
+
     pub fn push(&mut self, tab: &Rc<dyn Tab>) {
         for t in &self.tabs {
             assert_ne!(t.tab_id(), tab.tab_id(), "tab already added to this window");
         }
-        self.tabs.push(Rc::clone(tab))
+        let previous = self.active_tab_id();
+        self.tabs.push(Rc::clone(tab));
+        self.active_changed(previous);
     }
 
     pub fn is_empty(&self) -> bool {
@@ -53,17 +75,57 @@ impl Window {
 
     pub fn remove_by_id(&mut self, id: TabId) -> bool {
         if let Some(idx) = self.idx_by_id(id) {
+            let previous = self.active_tab_id();
             self.tabs.remove(idx);
+            if self.last_active == Some(id) {
+                self.last_active = None;
+            }
             let len = self.tabs.len();
-            if len > 0 && self.active == idx && idx >= len {
-                self.set_active(len - 1);
+            if idx < self.active {
+                // Keep the same tab active
+                self.active -= 1;
+            } else if len > 0 && self.active == idx && idx >= len {
+                self.active = len - 1;
+            }
+            if self.active_tab_id() != previous {
+                if let Some(active) = self.get_active() {
+                    active.renderer().make_all_lines_dirty();
+                }
             }
+            self.active_changed(previous);
             true
         } else {
             false
         }
     }
 
+    /// Moves the tab at index `from` to index `to`, keeping the same
+    /// tab active
+    pub fn move_tab(&mut self, from: usize, to: usize) {
+        let len = self.tabs.len();
+        if from >= len || to >= len {
+            return;
+        }
+        let active = self.get_active().map(|tab| tab.tab_id());
+        let tab = self.tabs.remove(from);
+        self.tabs.insert(to, tab);
+        if let Some(idx) = active.and_then(|id| self.idx_by_id(id)) {
+            self.active = idx;
+        }
+    }
+
+    /// Moves the active tab `delta` places to the right, or to the
+    /// left if `delta` is negative, wrapping around at either end
+    pub fn move_active_tab_relative(&mut self, delta: isize) {
+        let len = self.tabs.len() as isize;
+        if len == 0 {
+            return;
+        }
+        let to = (self.active as isize + delta) % len;
+        let to = if to < 0 { len + to } else { to };
+        self.move_tab(self.active, to as usize);
+    }
+
     pub fn get_active(&self) -> Option<&Rc<dyn Tab>> {
         self.get_by_idx(self.active)
     }
@@ -73,16 +135,183 @@ impl Window {
         self.active
     }
 
+    /// Returns the index of the tab that was active before the current
+    /// one, if it is still in this window
+    pub fn get_last_active_idx(&self) -> Option<usize> {
+        self.last_active.and_then(|id| self.idx_by_id(id))
+    }
+
     pub fn set_active(&mut self, idx: usize) {
         assert!(idx < self.tabs.len());
+        let previous = self.active_tab_id();
+        if idx != self.active {
+            self.last_active = previous;
+        }
         self.active = idx;
         self.get_by_idx(idx)
             .unwrap()
             .renderer()
             .make_all_lines_dirty();
+        self.active_changed(previous);
+    }
+
+    pub fn is_focused(&self) -> bool {
+        self.focused
+    }
+
+    /// Records whether the gui window showing this one has the
+    /// keyboard focus, and tells the active tab when that changes
+    pub fn set_focused(&mut self, focused: bool) {
+        if focused == self.focused {
+            return;
+        }
+        self.focused = focused;
+        if let Some(tab) = self.get_active() {
+            report_focus(tab, focused);
+        }
+    }
+
+    fn active_tab_id(&self) -> Option<TabId> {
+        self.get_active().map(|tab| tab.tab_id())
+    }
+
+    /// Called whenever the active tab may have changed from `previous`.
+    /// The focus moves along with the tab that is shown, so while the
+    /// window has the focus the tabs are told that they lost or gained
+    /// it.  A tab that was removed from the window isn't told.
+    fn active_changed(&self, previous: Option<TabId>) {
+        let active = match self.get_active() {
+            Some(active) => active,
+            None => return,
+        };
+        if !self.focused || previous == Some(active.tab_id()) {
+            return;
+        }
+        if let Some(tab) = previous
+            .and_then(|id| self.idx_by_id(id))
+            .and_then(|idx| self.get_by_idx(idx))
+        {
+            report_focus(tab, false);
+        }
+        report_focus(active, true);
     }
 
     pub fn iter(&self) -> impl Iterator<Item = &Rc<dyn Tab>> {
         self.tabs.iter()
     }
 }
+
+/// Tells the tab that it gained or lost the focus
+pub fn report_focus(tab: &Rc<dyn Tab>, focused: bool) {
+    if let Err(err) = tab.focus_changed(focused) {
+        error!(
+            "unable to report the focus to tab {}: {}",
+            tab.tab_id(),
+            err
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::FakeTab;
+
+    fn window_with_tabs(count: usize) -> (Window, Vec<TabId>) {
+        let mut window = Window::new();
+        let mut ids = vec![];
+        for _ in 0..count {
+            let tab = FakeTab::new();
+            ids.push(tab.tab_id());
+            window.push(&tab);
+        }
+        (window, ids)
+    }
+
+    fn order(window: &Window) -> Vec<TabId> {
+        window.iter().map(|tab| tab.tab_id()).collect()
+    }
+
+    #[test]
+    fn move_active_tab_relative_wraps() {
+        let (mut window, ids) = window_with_tabs(3);
+        window.set_active(1);
+
+        window.move_active_tab_relative(1);
+        assert_eq!(order(&window), vec![ids[0], ids[2], ids[1]]);
+        assert_eq!(window.get_active_idx(), 2);
+
+        // Past the right end the tab moves to the far left
+        window.move_active_tab_relative(1);
+        assert_eq!(order(&window), vec![ids[1], ids[0], ids[2]]);
+        assert_eq!(window.get_active_idx(), 0);
+
+        // and past the left end to the far right
+        window.move_active_tab_relative(-1);
+        assert_eq!(order(&window), vec![ids[0], ids[2], ids[1]]);
+        assert_eq!(window.get_active_idx(), 2);
+
+        window.move_active_tab_relative(-4);
+        assert_eq!(order(&window), vec![ids[0], ids[1], ids[2]]);
+        assert_eq!(window.get_active_idx(), 1);
+    }
+
+    #[test]
+    fn last_active_follows_the_tab() {
+        let (mut window, ids) = window_with_tabs(4);
+        assert_eq!(window.get_last_active_idx(), None);
+
+        window.set_active(2);
+        assert_eq!(window.get_last_active_idx(), Some(0));
+
+        // Activating the active tab again doesn't forget the last one
+        window.set_active(2);
+        assert_eq!(window.get_last_active_idx(), Some(0));
+
+        window.move_tab(0, 3);
+        assert_eq!(window.get_active_idx(), 1);
+        assert_eq!(window.get_last_active_idx(), Some(3));
+
+        window.remove_by_id(ids[1]);
+        assert_eq!(window.get_active_idx(), 0);
+        assert_eq!(window.get_last_active_idx(), Some(2));
+
+        window.remove_by_id(ids[0]);
+        assert_eq!(window.get_last_active_idx(), None);
+    }
+
+    #[test]
+    fn focus_follows_the_active_tab() {
+        let (mut window, _) = window_with_tabs(2);
+        let tabs: Vec<_> = window.iter().cloned().collect();
+
+        // Nothing is reported while the window doesn't have the focus
+        window.set_active(1);
+        window.set_focused(true);
+        assert_eq!(FakeTab::focus_changes(&tabs[0]), vec![]);
+        assert_eq!(FakeTab::focus_changes(&tabs[1]), vec![true]);
+
+        window.set_active(0);
+        assert_eq!(FakeTab::focus_changes(&tabs[0]), vec![true]);
+        assert_eq!(FakeTab::focus_changes(&tabs[1]), vec![true, false]);
+
+        // A new tab is only focused once it is activated
+        let spawned = FakeTab::new();
+        window.push(&spawned);
+        assert_eq!(FakeTab::focus_changes(&spawned), vec![]);
+        window.set_active(2);
+        assert_eq!(FakeTab::focus_changes(&spawned), vec![true]);
+        assert_eq!(FakeTab::focus_changes(&tabs[0]), vec![true, false]);
+
+        // The tab left active after closing one gains the focus
+        window.remove_by_id(spawned.tab_id());
+        assert_eq!(FakeTab::focus_changes(&spawned), vec![true]);
+        assert_eq!(FakeTab::focus_changes(&tabs[1]), vec![true, false, true]);
+
+        window.set_focused(false);
+        assert_eq!(
+            FakeTab::focus_changes(&tabs[1]),
+            vec![true, false, true, false]
+        );
+    }
+}
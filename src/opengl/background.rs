@@ -0,0 +1,130 @@
+//! Loads the window background image and works out where to draw it
+use crate::config::BackgroundImageMode;
+use failure::{bail, Error};
+use glium::backend::Facade;
+use glium::texture::{RawImage2d, SrgbTexture2d};
+use std::path::{Path, PathBuf};
+
+/// A rectangle as (left, top, right, bottom)
+pub type Rect = (f32, f32, f32, f32);
+
+pub struct BackgroundImage {
+    pub texture: SrgbTexture2d,
+    path: PathBuf,
+    opacity: f32,
+    width: f32,
+    height: f32,
+}
+
+impl BackgroundImage {
+    /// Loads the image at `path` into a texture, with its alpha
+    /// channel scaled by `opacity`
+    pub fn load<F: Facade>(facade: &F, path: &Path, opacity: f32) -> Result<Self, Error> {
+        let mut image = image::open(path)?.to_rgba();
+        let (width, height) = image.dimensions();
+        if width == 0 || height == 0 {
+            bail!("{} is empty", path.display());
+        }
+        if opacity < 1.0 {
+            for pixel in image.pixels_mut() {
+                pixel[3] = (f32::from(pixel[3]) * opacity) as u8;
+            }
+        }
+        let raw = RawImage2d::from_raw_rgba(image.into_raw(), (width, height));
+        Ok(Self {
+            texture: SrgbTexture2d::new(facade, raw)?,
+            path: path.to_path_buf(),
+            opacity,
+            width: width as f32,
+            height: height as f32,
+        })
+    }
+
+    /// Returns true if this image was loaded from `path` with `opacity`
+    pub fn matches(&self, path: &Path, opacity: f32) -> bool {
+        self.path == path && (self.opacity - opacity).abs() < std::f32::EPSILON
+    }
+
+    pub fn size(&self) -> (f32, f32) {
+        (self.width, self.height)
+    }
+}
+
+/// Works out where to draw an image of size `image` in a window of
+/// size `window`.  Returns the area of the window that the image
+/// covers, in pixels from its top left corner, and the texture
+/// coordinates at the corners of that area.  Texture coordinates
+/// beyond 1.0 repeat the image.
+pub fn image_quad(
+    mode: BackgroundImageMode,
+    window: (f32, f32),
+    image: (f32, f32),
+) -> (Rect, Rect) {
+    let (width, height) = window;
+    let (image_width, image_height) = image;
+    let whole_window = (0.0, 0.0, width, height);
+    let whole_image = (0.0, 0.0, 1.0, 1.0);
+    match mode {
+        BackgroundImageMode::Stretch => (whole_window, whole_image),
+        BackgroundImageMode::Tile => (
+            whole_window,
+            (0.0, 0.0, width / image_width, height / image_height),
+        ),
+        BackgroundImageMode::Fit => {
+            let scale = (width / image_width).min(height / image_height);
+            let (w, h) = (image_width * scale, image_height * scale);
+            let (left, top) = ((width - w) / 2.0, (height - h) / 2.0);
+            ((left, top, left + w, top + h), whole_image)
+        }
+        BackgroundImageMode::Cover => {
+            let scale = (width / image_width).max(height / image_height);
+            // The fraction of the scaled image that fits in the window
+            let visible_x = width / (image_width * scale);
+            let visible_y = height / (image_height * scale);
+            (
+                whole_window,
+                (
+                    (1.0 - visible_x) / 2.0,
+                    (1.0 - visible_y) / 2.0,
+                    (1.0 + visible_x) / 2.0,
+                    (1.0 + visible_y) / 2.0,
+                ),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn stretch_and_tile() {
+        assert_eq!(
+            image_quad(BackgroundImageMode::Stretch, (800.0, 600.0), (100.0, 50.0)),
+            ((0.0, 0.0, 800.0, 600.0), (0.0, 0.0, 1.0, 1.0))
+        );
+        assert_eq!(
+            image_quad(BackgroundImageMode::Tile, (800.0, 600.0), (100.0, 50.0)),
+            ((0.0, 0.0, 800.0, 600.0), (0.0, 0.0, 8.0, 12.0))
+        );
+    }
+
+    #[test]
+    fn fit_letterboxes() {
+        // A wide image is scaled to the width and centered vertically
+        assert_eq!(
+            image_quad(BackgroundImageMode::Fit, (800.0, 600.0), (400.0, 100.0)),
+            ((0.0, 200.0, 800.0, 400.0), (0.0, 0.0, 1.0, 1.0))
+        );
+    }
+
+    #[test]
+    fn cover_crops() {
+        // A wide image is scaled to the height and cropped at the sides
+        assert_eq!(
+            image_quad(BackgroundImageMode::Cover, (800.0, 400.0), (400.0, 100.0)),
+            ((0.0, 0.0, 800.0, 400.0), (0.25, 0.0, 0.75, 1.0))
+        );
+    }
+}
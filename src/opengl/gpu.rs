@@ -0,0 +1,79 @@
+//! Choosing between the GPUs of systems that have more than one, and
+//! describing the GPU that a window renders with, to help with driver
+//! problems.
+use crate::config::{Config, GpuPreference};
+use failure::{format_err, Fallible};
+use glium::backend::{Context, Facade};
+use glium::glutin;
+use log::{error, info};
+use std::fmt;
+
+/// The OpenGL implementation behind a context
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GpuInfo {
+    pub vendor: String,
+    pub renderer: String,
+    pub version: String,
+}
+
+impl GpuInfo {
+    pub fn from_context(context: &Context) -> Self {
+        Self {
+            vendor: context.get_opengl_vendor_string().to_owned(),
+            renderer: context.get_opengl_renderer_string().to_owned(),
+            version: context.get_opengl_version_string().to_owned(),
+        }
+    }
+
+    /// Describes the GPU that a window would render with, by creating
+    /// a hidden window.  This is for `wezterm cli gpu-info`, which has
+    /// no window of its own.
+    pub fn probe(config: &Config) -> Fallible<Self> {
+        apply_preference(config);
+        let events_loop = glutin::EventsLoop::new();
+        let window = glutin::WindowBuilder::new()
+            .with_title("wezterm")
+            .with_visibility(false);
+        let context = glutin::ContextBuilder::new().with_pixel_format(24, 8);
+        let display = glium::Display::new(window, context, &events_loop)
+            .map_err(|e| format_err!("{:?}", e))?;
+        Ok(Self::from_context(display.get_context()))
+    }
+}
+
+impl fmt::Display for GpuInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} by {}, OpenGL {}",
+            self.renderer, self.vendor, self.version
+        )
+    }
+}
+
+/// Asks the system for the preferred GPU.  This has to happen before
+/// the first OpenGL context is created.
+pub fn apply_preference(config: &Config) {
+    let preference = match config.prefer_gpu {
+        Some(preference) => preference,
+        None => return,
+    };
+    if cfg!(all(unix, not(target_os = "macos"))) {
+        // Mesa renders with the GPU that DRI_PRIME selects; leave it
+        // alone if the user has already chosen
+        if std::env::var_os("DRI_PRIME").is_none() {
+            let prime = match preference {
+                GpuPreference::LowPower => "0",
+                GpuPreference::HighPerformance => "1",
+            };
+            info!("prefer_gpu = {:?}: setting DRI_PRIME={}", preference, prime);
+            std::env::set_var("DRI_PRIME", prime);
+        }
+    } else {
+        error!(
+            "prefer_gpu = {:?} isn't supported on this system; \
+             choose the GPU in the system settings instead",
+            preference
+        );
+    }
+}
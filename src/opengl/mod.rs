@@ -1,2 +1,5 @@
+pub mod background;
+pub mod gpu;
+pub mod rasterizer;
 pub mod render;
 pub mod textureatlas;
@@ -0,0 +1,101 @@
+//! Rasterizes glyphs on a background thread, so that the gui thread
+//! doesn't stall when a lot of new glyphs are needed at once, such as
+//! when a screenful of CJK text is first displayed.
+//! The fonts are not safe to share between threads, so the background
+//! thread loads its own copy of the fonts from the same configuration
+//! and at the same scale as those used by the renderer.  This means
+//! that the font and fallback indices produced by shaping on the gui
+//! thread identify the same fonts on the background thread.
+use crate::config::{Config, TextStyle};
+use crate::font::{FontConfiguration, FontSystemSelection, RasterizedGlyph};
+use failure::Fallible;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+
+/// Identifies a glyph to be rasterized
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GlyphKey {
+    pub font_idx: usize,
+    pub glyph_pos: u32,
+    pub style: TextStyle,
+}
+
+/// The outcome of rasterizing a glyph on the background thread
+pub struct Rasterized {
+    pub key: GlyphKey,
+    pub has_color: bool,
+    /// The cell dimensions of the primary font for the style
+    pub cell_width: f64,
+    pub cell_height: f64,
+    pub glyph: Fallible<RasterizedGlyph>,
+}
+
+pub struct GlyphRasterizer {
+    tx: Sender<GlyphKey>,
+    rx: Receiver<Rasterized>,
+}
+
+impl GlyphRasterizer {
+    /// Spawns the thread that rasterizes glyphs using fonts that are
+    /// loaded from `config` at the specified scale.
+    /// The thread terminates once the rasterizer is dropped.
+    pub fn new(
+        config: &Arc<Config>,
+        system: FontSystemSelection,
+        font_scale: f64,
+        dpi_scale: f64,
+    ) -> Self {
+        let (tx, request_rx) = channel::<GlyphKey>();
+        let (result_tx, rx) = channel();
+        let config = Arc::clone(config);
+
+        std::thread::spawn(move || {
+            let fonts = FontConfiguration::new(config, system);
+            fonts.change_scaling(font_scale, dpi_scale);
+            for key in request_rx {
+                let result = rasterize(&fonts, key);
+                if result_tx.send(result).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self { tx, rx }
+    }
+
+    /// Queues `key` to be rasterized
+    pub fn request(&self, key: GlyphKey) {
+        // This only fails if the thread has died, in which case
+        // the glyph will simply never be drawn
+        self.tx.send(key).ok();
+    }
+
+    /// Returns the glyphs that have been rasterized since the
+    /// last call, without waiting for any that are in progress
+    pub fn completed(&self) -> Vec<Rasterized> {
+        self.rx.try_iter().collect()
+    }
+}
+
+fn rasterize(fonts: &FontConfiguration, key: GlyphKey) -> Rasterized {
+    let mut has_color = false;
+    let mut cell_width = 0.0;
+    let mut cell_height = 0.0;
+    let glyph = (|| {
+        let font = fonts.cached_font(&key.style)?;
+        let mut font = font.borrow_mut();
+        let metrics = font.get_fallback(0)?.metrics();
+        cell_width = metrics.cell_width;
+        cell_height = metrics.cell_height;
+        let active_font = font.get_fallback(key.font_idx)?;
+        has_color = active_font.has_color();
+        active_font.rasterize_glyph(key.glyph_pos)
+    })();
+    Rasterized {
+        key,
+        has_color,
+        cell_width,
+        cell_height,
+        glyph,
+    }
+}
@@ -1,36 +1,38 @@
 //! This module is responsible for rendering a terminal to an OpenGL context
 
+use super::background::{image_quad, BackgroundImage};
+use super::gpu::GpuInfo;
+use super::rasterizer::{GlyphKey, GlyphRasterizer, Rasterized};
 use super::textureatlas::{Atlas, Sprite, SpriteSlice, TEX_SIZE};
-use crate::config::TextStyle;
-use crate::font::{FontConfiguration, GlyphInfo};
-use crate::mux::renderable::Renderable;
+use crate::config::{BackgroundImageMode, Config, TextStyle};
+use crate::font::{FontConfiguration, FontSystemSelection, GlyphInfo};
+use crate::frontend::guicommon::hitregion::{HitRegions, HitTarget};
+use crate::frontend::guicommon::scrollbar::thumb_range;
+use crate::mux::renderable::{RenderableSnapshot, ScrollbarInfo};
 use euclid;
 use failure::{err_msg, Error};
 use glium::backend::Facade;
-use glium::texture::SrgbTexture2d;
+use glium::uniforms::SamplerWrapFunction;
 use glium::{self, IndexBuffer, Surface, VertexBuffer};
-use glium::{implement_vertex, uniform};
-use log::debug;
-use std::cell::RefCell;
-use std::collections::HashMap;
+use glium::{implement_vertex, uniform, BlendingFunction, LinearBlendingFactor};
+use log::{debug, error};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
 use std::mem;
 use std::ops::{Deref, Range};
 use std::rc::Rc;
-use term::color::{ColorPalette, RgbaTuple};
-use term::{self, CursorPosition, Line, Underline};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use term::color::{ColorPalette, RgbColor, RgbaTuple};
+use term::{self, CursorPosition, Line, SequenceNo, Underline, SEQ_ZERO};
+use termwiz::escape::csi::CursorStyle;
+use termwiz::image::ImageCell;
 
 type Transform3D = euclid::Transform3D<f32>;
 
 #[derive(Copy, Clone, Debug)]
 struct Point(euclid::Point2D<f32>);
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-struct GlyphKey {
-    font_idx: usize,
-    glyph_pos: u32,
-    style: TextStyle,
-}
-
 /// Caches a rendered glyph.
 /// The image data may be None for whitespace glyphs.
 #[derive(Debug)]
@@ -44,6 +46,21 @@ struct CachedGlyph {
     scale: f64,
 }
 
+impl CachedGlyph {
+    /// Stands in for a glyph that is still being rasterized
+    fn pending() -> Self {
+        Self {
+            has_color: false,
+            x_offset: 0.0,
+            y_offset: 0.0,
+            bearing_x: 0.0,
+            bearing_y: 0.0,
+            texture: None,
+            scale: 1.0,
+        }
+    }
+}
+
 impl Default for Point {
     fn default() -> Point {
         Point::new(0.0, 0.0)
@@ -75,6 +92,7 @@ impl Point {
 /// Each cell is composed of two triangles built from 4 vertices.
 /// The buffer is organized row by row.
 const VERTICES_PER_CELL: usize = 4;
+const INDICES_PER_CELL: usize = 6;
 const V_TOP_LEFT: usize = 0;
 const V_TOP_RIGHT: usize = 1;
 const V_BOT_LEFT: usize = 2;
@@ -95,7 +113,9 @@ struct Vertex {
     has_color: f32,
     /// Count of how many underlines there are
     underline: f32,
+    /// Nominally booleans
     strikethrough: f32,
+    overline: f32,
     v_idx: f32,
 }
 
@@ -109,9 +129,292 @@ implement_vertex!(
     has_color,
     underline,
     strikethrough,
+    overline,
     v_idx,
 );
 
+/// The background color and line decorations of a cell, which are
+/// drawn in the first pass.  Adjacent cells in a row that look the
+/// same are drawn as a single quad, so that a line of uniform color
+/// costs one quad rather than one per column.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+struct CellBackground {
+    bg_color: RgbaTuple,
+    /// The color of the line decorations.  Cells without decorations
+    /// use their background color here so that they merge regardless
+    /// of their foreground color.
+    line_color: RgbaTuple,
+    underline: f32,
+    strikethrough: f32,
+    overline: f32,
+}
+
+impl CellBackground {
+    fn new(
+        bg_color: RgbaTuple,
+        fg_color: RgbaTuple,
+        underline: f32,
+        strikethrough: f32,
+        overline: f32,
+    ) -> Self {
+        let decorated = underline > 0.0 || strikethrough > 0.0 || overline > 0.0;
+        Self {
+            bg_color,
+            line_color: if decorated { fg_color } else { bg_color },
+            underline,
+            strikethrough,
+            overline,
+        }
+    }
+
+    fn plain(bg_color: RgbaTuple) -> Self {
+        Self::new(bg_color, bg_color, 0.0, 0.0, 0.0)
+    }
+
+    /// Returns true if drawing the cell would not change any pixels
+    fn is_invisible(&self) -> bool {
+        self.bg_color.3 <= 0.0 && self.line_color.3 <= 0.0
+    }
+}
+
+/// Splits each row of `cells`, which are `cols` wide, into runs of
+/// identical backgrounds, leaving out the runs that are invisible.
+/// Returns the row, the columns and the background of each run.
+fn background_runs(
+    cells: &[CellBackground],
+    cols: usize,
+) -> Vec<(usize, Range<usize>, CellBackground)> {
+    let mut runs = vec![];
+    if cols == 0 {
+        return runs;
+    }
+    for (row, cells) in cells.chunks(cols).enumerate() {
+        let mut start = 0;
+        for col in 1..=cells.len() {
+            if col == cells.len() || cells[col] != cells[start] {
+                if !cells[start].is_invisible() {
+                    runs.push((row, start..col, cells[start]));
+                }
+                start = col;
+            }
+        }
+    }
+    runs
+}
+
+/// Returns the indices that draw the quads of the cells in `glyphs`
+/// that hold something to draw in the text layer, such as a glyph or
+/// an image, so that blank cells cost nothing to draw
+fn text_indices(glyphs: &[bool]) -> Vec<u32> {
+    let mut indices = vec![];
+    for (cell_idx, _) in glyphs.iter().enumerate().filter(|(_, &glyph)| glyph) {
+        let idx = (cell_idx * VERTICES_PER_CELL) as u32;
+        indices.extend_from_slice(&[idx, idx + 1, idx + 2, idx + 1, idx + 2, idx + 3]);
+    }
+    indices
+}
+
+/// Records in `glyphs` which of the cells whose quads are in `vertices`
+/// have something to draw in the text layer; blank cells sample the
+/// clear pixel at the origin of the atlas.  Returns true if any cell
+/// changed.
+fn update_glyph_cells(glyphs: &mut [bool], vertices: &[Vertex]) -> bool {
+    let mut changed = false;
+    for (glyph, quad) in glyphs.iter_mut().zip(vertices.chunks(VERTICES_PER_CELL)) {
+        let has_glyph = quad.iter().any(|vert| vert.tex != (0.0, 0.0));
+        if *glyph != has_glyph {
+            *glyph = has_glyph;
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// Which of the batched buffers that the layers are drawn from are
+/// out of date because cells changed since they were built
+#[derive(Copy, Clone, Debug, Default)]
+struct Damage {
+    backgrounds: bool,
+    glyphs: bool,
+}
+
+impl Damage {
+    fn all() -> Self {
+        Self {
+            backgrounds: true,
+            glyphs: true,
+        }
+    }
+}
+
+/// How the cursor is drawn in a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CursorShape {
+    /// The cursor cell is drawn in the cursor colors
+    Block,
+    /// A line is drawn along the bottom of the cursor cell
+    Underline,
+    /// A line is drawn along the left of the cursor cell
+    Bar,
+    /// Lines are drawn along the edges of the cursor cell, which
+    /// keeps its own colors
+    Outline,
+    /// A blinking cursor in the hidden half of its cycle
+    Hidden,
+}
+
+/// Works out how to draw a cursor of `style`, `elapsed` after its
+/// blink cycle began.  The cursor starts out visible and then spends
+/// `blink_rate` in each phase; None stops it from blinking.
+fn cursor_shape(
+    style: CursorStyle,
+    blink_rate: Option<Duration>,
+    elapsed: Duration,
+) -> CursorShape {
+    let (shape, blinks) = match style {
+        CursorStyle::Default | CursorStyle::SteadyBlock => (CursorShape::Block, false),
+        CursorStyle::BlinkingBlock => (CursorShape::Block, true),
+        CursorStyle::SteadyUnderline => (CursorShape::Underline, false),
+        CursorStyle::BlinkingUnderline => (CursorShape::Underline, true),
+        CursorStyle::SteadyBar => (CursorShape::Bar, false),
+        CursorStyle::BlinkingBar => (CursorShape::Bar, true),
+    };
+    match blink_rate {
+        Some(rate) if blinks && rate.as_millis() > 0 => {
+            if (elapsed.as_millis() / rate.as_millis()) % 2 == 0 {
+                shape
+            } else {
+                CursorShape::Hidden
+            }
+        }
+        _ => shape,
+    }
+}
+
+/// Returns how long after `elapsed` a cursor of `style` next blinks,
+/// or None if it doesn't blink
+fn next_cursor_blink(
+    style: CursorStyle,
+    blink_rate: Option<Duration>,
+    elapsed: Duration,
+) -> Option<Duration> {
+    let blinks = match style {
+        CursorStyle::BlinkingBlock | CursorStyle::BlinkingUnderline | CursorStyle::BlinkingBar => {
+            true
+        }
+        _ => false,
+    };
+    match blink_rate {
+        Some(rate) if blinks && rate.as_millis() > 0 => {
+            let rate = rate.as_millis();
+            let until = rate - elapsed.as_millis() % rate;
+            Some(Duration::from_millis(until as u64))
+        }
+        _ => None,
+    }
+}
+
+/// How often to look for glyphs that the rasterizer has completed
+/// while some are still pending
+const GLYPH_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The most quads that it takes to draw the cursor
+const MAX_CURSOR_QUADS: usize = 4;
+
+/// The widths, in pixels, of the lines that draw the cursor shapes
+/// other than the filled block
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct CursorThickness {
+    bar: f32,
+    underline: f32,
+    outline: f32,
+}
+
+impl CursorThickness {
+    /// Scales the widths from the config by `dpi_scale`, so that the
+    /// cursor looks the same on any display.  The widths that aren't
+    /// configured are a sixteenth of the cell height, which already
+    /// accounts for the dpi.  Every line is at least a pixel wide.
+    fn new(config: &Config, cell_height: f32, dpi_scale: f32) -> Self {
+        let default = (cell_height / 16.0).ceil().max(1.0);
+        let scaled = |width: Option<f32>| match width {
+            Some(width) => (width * dpi_scale).round().max(1.0),
+            None => default,
+        };
+        Self {
+            bar: scaled(config.cursor_bar_thickness),
+            underline: scaled(config.cursor_underline_height),
+            outline: scaled(config.cursor_block_outline_width),
+        }
+    }
+}
+
+/// Returns the (left, top, right, bottom) of the lines that draw the
+/// cursor in a cell with the given bounds; none for the shapes that
+/// aren't drawn with lines.  The lines are kept within the cell.
+fn cursor_rects(
+    shape: CursorShape,
+    cell: (f32, f32, f32, f32),
+    thickness: CursorThickness,
+) -> Vec<(f32, f32, f32, f32)> {
+    let (left, top, right, bottom) = cell;
+    let (width, height) = (right - left, bottom - top);
+    match shape {
+        CursorShape::Underline => {
+            let t = thickness.underline.min(height);
+            vec![(left, bottom - t, right, bottom)]
+        }
+        CursorShape::Bar => {
+            let t = thickness.bar.min(width);
+            vec![(left, top, left + t, bottom)]
+        }
+        CursorShape::Outline => {
+            let t = thickness.outline.min(width / 2.0).min(height / 2.0);
+            vec![
+                (left, top, right, top + t),
+                (left, bottom - t, right, bottom),
+                (left, top + t, left + t, bottom - t),
+                (right - t, top + t, right, bottom - t),
+            ]
+        }
+        CursorShape::Block | CursorShape::Hidden => vec![],
+    }
+}
+
+/// Fades `fg` towards `bg`, keeping `brightness` of the difference
+/// between them
+fn fade_towards(fg: RgbColor, bg: RgbColor, brightness: f32) -> RgbColor {
+    let fade = |fg: u8, bg: u8| {
+        (f32::from(bg) + (f32::from(fg) - f32::from(bg)) * brightness).round() as u8
+    };
+    RgbColor::new(
+        fade(fg.red, bg.red),
+        fade(fg.green, bg.green),
+        fade(fg.blue, bg.blue),
+    )
+}
+
+fn with_alpha(color: RgbaTuple, alpha: f32) -> RgbaTuple {
+    (color.0, color.1, color.2, color.3 * alpha)
+}
+
+/// Blends the source over the destination, accumulating the alpha
+/// channel in the same way, so that the window stays opaque wherever
+/// something opaque was drawn when it is shown by a compositor
+fn blend_over() -> glium::Blend {
+    glium::Blend {
+        color: BlendingFunction::Addition {
+            source: LinearBlendingFactor::SourceAlpha,
+            destination: LinearBlendingFactor::OneMinusSourceAlpha,
+        },
+        alpha: BlendingFunction::Addition {
+            source: LinearBlendingFactor::One,
+            destination: LinearBlendingFactor::OneMinusSourceAlpha,
+        },
+        constant_value: (0.0, 0.0, 0.0, 0.0),
+    }
+}
+
 struct ShaderSource {
     pub version: &'static str,
 }
@@ -144,49 +447,42 @@ in vec4 fg_color;
 in vec4 bg_color;
 in float has_color;
 in float underline;
+in float strikethrough;
+in float overline;
 in float v_idx;
 
 uniform mat4 projection;
 uniform mat4 translation;
 uniform bool bg_and_line_layer;
+uniform float cell_height;
 
 out vec2 tex_coords;
-out vec2 underline_coords;
+out float cell_y;
 out vec4 o_fg_color;
 out vec4 o_bg_color;
 out float o_has_color;
 out float o_underline;
-
-// Offset from the RHS texture coordinate to the LHS.
-// This is an underestimation to avoid the shader interpolating
-// the underline gylph into its neighbor.
-const float underline_offset = (1.0 / 5.0);
+out float o_strikethrough;
+out float o_overline;
 
 void main() {{
     o_fg_color = fg_color;
     o_bg_color = bg_color;
     o_has_color = has_color;
     o_underline = underline;
+    o_strikethrough = strikethrough;
+    o_overline = overline;
 
     if (bg_and_line_layer) {{
         gl_Position = projection * vec4(position, 0.0, 1.0);
 
-        if (underline != 0.0) {{
-            // Populate the underline texture coordinates based on the
-            // v_idx (which tells us which corner of the cell we're
-            // looking at) and o_underline which corresponds to one
-            // of the U_XXX constants defined in the rust code below
-            // and which holds the RHS position in the texture coordinate
-            // space for the underline texture layer.
-            if (v_idx == 0.0) {{ // top left
-                underline_coords = vec2(o_underline - underline_offset, -1.0);
-            }} else if (v_idx == 1.0) {{ // top right
-                underline_coords = vec2(o_underline, -1.0);
-            }} else if (v_idx == 2.0) {{ // bot left
-                underline_coords = vec2(o_underline- underline_offset, 0.0);
-            }} else {{ // bot right
-                underline_coords = vec2(o_underline, 0.0);
-            }}
+        // The distance from the top of the cell, which the fragment
+        // shader uses to decide whether to draw a line decoration.
+        // v_idx tells us which corner of the cell we're looking at.
+        if (v_idx == 0.0 || v_idx == 1.0) {{ // top left, top right
+            cell_y = 0.0;
+        }} else {{ // bot left, bot right
+            cell_y = cell_height;
         }}
 
     }} else {{
@@ -195,25 +491,44 @@ void main() {{
     }}
 }}
     "#,
-        version = src.version
+        version = src.version,
     )
 }
 
-/// How many columns the underline texture has
-const U_COLS: f32 = 5.0;
-/// The glyph has no underline or strikethrough
-const U_NONE: f32 = 0.0;
-/// The glyph has a single underline.  This value is actually the texture
-/// coordinate for the right hand side of the underline.
-const U_ONE: f32 = 1.0 / U_COLS;
-/// Texture coord for the RHS of the double underline glyph
-const U_TWO: f32 = 2.0 / U_COLS;
-/// Texture coord for the RHS of the strikethrough glyph
-const U_STRIKE: f32 = 3.0 / U_COLS;
-/// Texture coord for the RHS of the strikethrough + single underline glyph
-const U_STRIKE_ONE: f32 = 4.0 / U_COLS;
-/// Texture coord for the RHS of the strikethrough + double underline glyph
-const U_STRIKE_TWO: f32 = 5.0 / U_COLS;
+/// The rows of pixels within a cell, counting down from the top,
+/// at which the line decorations are drawn
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct DecorationRows {
+    underline: f32,
+    double_underline: (f32, f32),
+    strikethrough: f32,
+    overline: f32,
+}
+
+/// Computes where the line decorations go in a cell of the given
+/// height, relative to the descender of the font
+fn decoration_rows(cell_height: f64, descender: f64) -> DecorationRows {
+    let cell_height = cell_height.ceil() as isize;
+    let descender = if descender.is_sign_positive() {
+        (descender / 64.0).ceil() as isize
+    } else {
+        (descender / 64.0).floor() as isize
+    };
+
+    let last_row = cell_height - 1;
+    let descender_row = (cell_height + descender).min(last_row);
+    DecorationRows {
+        // The single underline is placed just under the descender position
+        underline: (descender_row + 1).min(last_row) as f32,
+        // The double underline is at and just below the descender
+        double_underline: (
+            descender_row as f32,
+            (descender_row + 2).min(last_row) as f32,
+        ),
+        strikethrough: (descender_row / 2) as f32,
+        overline: 0.0,
+    }
+}
 
 fn fragment_shader() -> String {
     let src = ShaderSource::new();
@@ -222,16 +537,21 @@ fn fragment_shader() -> String {
 #version {version}
 precision mediump float;
 in vec2 tex_coords;
-in vec2 underline_coords;
+in float cell_y;
 in vec4 o_fg_color;
 in vec4 o_bg_color;
 in float o_has_color;
 in float o_underline;
+in float o_strikethrough;
+in float o_overline;
 
 out vec4 color;
 uniform sampler2D glyph_tex;
-uniform sampler2D underline_tex;
 uniform bool bg_and_line_layer;
+uniform float underline_row;
+uniform vec2 double_underline_rows;
+uniform float strikethrough_row;
+uniform float overline_row;
 
 float multiply_one(float src, float dst, float inv_dst_alpha, float inv_src_alpha) {{
     return (src * dst) + (src * (inv_dst_alpha)) + (dst * (inv_src_alpha));
@@ -254,18 +574,27 @@ vec4 multiply(vec4 src, vec4 dst) {{
 void main() {{
     if (bg_and_line_layer) {{
         color = o_bg_color;
-        // If there's an underline/strike glyph, extract the pixel color
-        // from the texture.  If the alpha value is non-zero then we'll
-        // take that pixel, otherwise we'll use the background color.
-        if (o_underline != 0.0) {{
-            // Compute the pixel color for this location
-            vec4 under_color = multiply(o_fg_color, texture(underline_tex, underline_coords));
-            if (under_color.a != 0.0) {{
-                // if the line glyph isn't transparent in this position then
-                // we take this pixel color, otherwise we'll leave the color
-                // at the background color.
-                color = under_color;
-            }}
+        // The decorations are each a single row of pixels; work out
+        // whether this fragment falls on one that is enabled for the
+        // cell and if so, draw it in the foreground color.
+        // The attributes are the same at each corner of the cell, but
+        // we compare against the half way points in case the
+        // interpolation is imprecise.
+        float row = floor(cell_y);
+        bool line = false;
+        if (o_underline > 1.5) {{
+            line = row == double_underline_rows.x || row == double_underline_rows.y;
+        }} else if (o_underline > 0.5) {{
+            line = row == underline_row;
+        }}
+        if (o_strikethrough > 0.5 && row == strikethrough_row) {{
+            line = true;
+        }}
+        if (o_overline > 0.5 && row == overline_row) {{
+            line = true;
+        }}
+        if (line) {{
+            color = o_fg_color;
         }}
     }} else {{
         color = texture(glyph_tex, tex_coords);
@@ -281,6 +610,115 @@ void main() {{
     )
 }
 
+/// The layers of a frame, from the bottom up.  Each is drawn in a
+/// single pass by `Renderer::draw_layer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Layer {
+    /// The window background image
+    BackgroundImage,
+    /// The background colors of the cells, including the selection
+    /// and the cursor, and the line decorations
+    CellBackgrounds,
+    /// The glyphs and the inline images
+    Text,
+    /// The underline, bar and outline cursors, which are drawn over
+    /// the text.  The filled block cursor is part of the cell
+    /// backgrounds.
+    Cursor,
+}
+
+const LAYERS: [Layer; 4] = [
+    Layer::BackgroundImage,
+    Layer::CellBackgrounds,
+    Layer::Text,
+    Layer::Cursor,
+];
+
+/// A row drawn above or below the terminal, such as the tab bar,
+/// along with the columns of it that respond to the mouse
+pub struct Bar {
+    pub line: Line,
+    pub targets: Vec<(Range<usize>, HitTarget)>,
+}
+
+/// Lines that are drawn over the terminal, starting at `row` and `col`.
+/// Lines narrower than the terminal are laid over it, so that the
+/// terminal shows around them.
+pub struct Overlay {
+    pub row: usize,
+    pub col: usize,
+    pub lines: Vec<Line>,
+}
+
+impl Overlay {
+    /// Returns the columns covered by the overlay
+    fn cols(&self, num_cols: usize) -> Range<usize> {
+        let width = self
+            .lines
+            .iter()
+            .map(|line| line.cells().len())
+            .max()
+            .unwrap_or(0);
+        self.col.min(num_cols)..(self.col + width).min(num_cols)
+    }
+}
+
+/// Lays the overlays over the visible `lines` of the terminal and
+/// returns the rows that they cover.  An overlay as wide as the
+/// terminal replaces its rows, while the cells of narrower ones are
+/// laid over whatever is beneath them, so that any number of them can
+/// share a row.
+fn merge_overlays(
+    overlays: &[Overlay],
+    lines: &[(Arc<Line>, Range<usize>)],
+    num_rows: usize,
+    num_cols: usize,
+) -> BTreeMap<usize, Line> {
+    let mut merged = BTreeMap::new();
+    for overlay in overlays {
+        for (idx, line) in overlay.lines.iter().enumerate() {
+            let line_idx = overlay.row + idx;
+            if line_idx >= num_rows {
+                break;
+            }
+            if overlay.col == 0 && line.cells().len() >= num_cols {
+                merged.insert(line_idx, line.clone());
+                continue;
+            }
+            let row = merged
+                .entry(line_idx)
+                .or_insert_with(|| match lines.get(line_idx) {
+                    Some((under, _)) => Line::clone(under),
+                    None => Line::with_width(num_cols),
+                });
+            for (x, cell) in line.cells().iter().enumerate() {
+                row.set_cell(overlay.col + x, cell.clone());
+            }
+        }
+    }
+    merged
+}
+
+/// Describes what is drawn around and over the terminal in a frame.
+/// UI elements such as the tab bar, pickers and search bars are
+/// described here rather than each being passed to `Renderer::paint`
+/// and drawn in its own way.
+#[derive(Default)]
+pub struct Composition {
+    /// Rows drawn above the terminal, which is moved down to make room
+    pub above: Vec<Bar>,
+    /// Rows drawn below the terminal
+    pub below: Vec<Bar>,
+    /// Lines drawn over the terminal, in order; where they overlap,
+    /// the later overlay wins
+    pub overlays: Vec<Overlay>,
+    /// Whether to draw the scrollbar in the rightmost column
+    pub scrollbar: bool,
+    /// Whether to flash the background of the window, for the
+    /// visual bell
+    pub flash: bool,
+}
+
 pub struct Renderer {
     width: u16,
     height: u16,
@@ -289,12 +727,69 @@ pub struct Renderer {
     cell_width: f64,
     descender: f64,
     glyph_cache: RefCell<HashMap<GlyphKey, Rc<CachedGlyph>>>,
+    rasterizer: GlyphRasterizer,
+    /// The glyphs that have been requested from the rasterizer, along
+    /// with the shaping information that they were first needed for
+    pending_glyphs: RefCell<HashMap<GlyphKey, GlyphInfo>>,
+    /// Glyphs that have been rasterized but not yet uploaded to the atlas
+    rasterized_glyphs: RefCell<Vec<Rasterized>>,
+    /// The images that have been uploaded to the atlas, keyed by the
+    /// id of their ImageData
+    image_cache: RefCell<HashMap<usize, Rc<Sprite>>>,
     program: glium::Program,
     glyph_vertex_buffer: RefCell<VertexBuffer<Vertex>>,
     glyph_index_buffer: IndexBuffer<u32>,
+    /// The background of each cell in the window, row by row
+    backgrounds: RefCell<Vec<CellBackground>>,
+    /// Holds the merged background quads for the frame being drawn;
+    /// it has room for one quad per cell, which is the worst case
+    background_vertex_buffer: VertexBuffer<Vertex>,
+    /// The number of quads in background_vertex_buffer
+    num_background_quads: usize,
+    /// Whether each cell in the window has a glyph or image to draw
+    glyphs: RefCell<Vec<bool>>,
+    /// Indexes the quads of the cells of glyph_vertex_buffer that have
+    /// something to draw, so that the text layer is a single draw call
+    /// that skips the blank cells
+    text_index_buffer: IndexBuffer<u32>,
+    /// The number of quads in text_index_buffer
+    num_text_quads: usize,
+    /// Which batches need to be rebuilt before the next draw
+    damage: Cell<Damage>,
+    /// The configured window background image, along with the quad
+    /// that draws it, which is recomputed when the window is resized
+    background_image: Option<(BackgroundImage, VertexBuffer<Vertex>)>,
+    /// The opacity of the background of this window, overriding
+    /// `window_background_opacity` from the config
+    window_opacity: Option<f32>,
+    /// The OpenGL implementation that this window renders with
+    gpu_info: GpuInfo,
+    /// Holds the quads of the lines that draw the cursor, unless it
+    /// is a filled block
+    cursor_vertex_buffer: VertexBuffer<Vertex>,
+    cursor_index_buffer: IndexBuffer<u32>,
+    /// How the cursor was drawn in the last paint
+    cursor_shape: CursorShape,
+    /// The number of quads that the last paint placed in
+    /// cursor_vertex_buffer
+    num_cursor_quads: usize,
+    /// When the cursor last moved; blinking restarts from here so that
+    /// the cursor stays visible while typing
+    cursor_blink_start: Instant,
+    /// Whether the window has the keyboard focus; the text of windows
+    /// that don't is dimmed per `unfocused_window_text_brightness`
+    focused: bool,
     projection: Transform3D,
     atlas: RefCell<Atlas>,
-    underline_tex: SrgbTexture2d,
+    decorations: DecorationRows,
+    /// The sequence number of the terminal state that was last painted;
+    /// reset to SEQ_ZERO when every line needs to be painted again
+    painted_seqno: SequenceNo,
+    /// The snapshot that was last painted, whose unchanged lines are
+    /// shared with the next snapshot
+    snapshot: Option<RenderableSnapshot>,
+    /// What each part of the window held in the last paint
+    hit_regions: HitRegions,
 }
 
 impl Renderer {
@@ -312,8 +807,6 @@ impl Renderer {
             cell_height, cell_width, descender
         );
 
-        let underline_tex = Self::compute_underlines(facade, cell_width, cell_height, descender)?;
-
         let (glyph_vertex_buffer, glyph_index_buffer) = Self::compute_vertices(
             facade,
             cell_width as f32,
@@ -333,14 +826,32 @@ impl Renderer {
             geometry_shader: None,
         };
         let program = glium::Program::new(facade, source)?;
+        let num_cells = glyph_vertex_buffer.len() / VERTICES_PER_CELL;
+        let background_vertex_buffer =
+            VertexBuffer::empty_dynamic(facade, num_cells * VERTICES_PER_CELL)?;
+        let text_index_buffer = Self::empty_index_buffer(facade, num_cells)?;
+        let cursor_vertex_buffer =
+            VertexBuffer::empty_dynamic(facade, MAX_CURSOR_QUADS * VERTICES_PER_CELL)?;
+        let cursor_index_buffer = IndexBuffer::new(
+            facade,
+            glium::index::PrimitiveType::TrianglesList,
+            &text_indices(&[true; MAX_CURSOR_QUADS]),
+        )?;
 
         let atlas = RefCell::new(Atlas::new(facade, TEX_SIZE)?);
 
-        Ok(Self {
+        let mut renderer = Self {
             atlas,
             program,
             glyph_vertex_buffer: RefCell::new(glyph_vertex_buffer),
             glyph_index_buffer,
+            backgrounds: RefCell::new(vec![CellBackground::default(); num_cells]),
+            background_vertex_buffer,
+            num_background_quads: 0,
+            glyphs: RefCell::new(vec![false; num_cells]),
+            text_index_buffer,
+            num_text_quads: 0,
+            damage: Cell::new(Damage::all()),
             width,
             height,
             fonts: Rc::clone(fonts),
@@ -348,93 +859,98 @@ impl Renderer {
             cell_width,
             descender,
             glyph_cache: RefCell::new(HashMap::new()),
+            rasterizer: Self::new_rasterizer(fonts),
+            pending_glyphs: RefCell::new(HashMap::new()),
+            rasterized_glyphs: RefCell::new(vec![]),
+            image_cache: RefCell::new(HashMap::new()),
             projection: Self::compute_projection(f32::from(width), f32::from(height)),
-            underline_tex,
-        })
+            decorations: decoration_rows(cell_height, descender),
+            painted_seqno: SEQ_ZERO,
+            snapshot: None,
+            hit_regions: HitRegions::default(),
+            background_image: None,
+            window_opacity: None,
+            gpu_info: GpuInfo::from_context(facade.get_context()),
+            cursor_vertex_buffer,
+            cursor_index_buffer,
+            cursor_shape: CursorShape::Block,
+            num_cursor_quads: 0,
+            cursor_blink_start: Instant::now(),
+            focused: true,
+        };
+        renderer.update_background_image(facade)?;
+        Ok(renderer)
     }
 
-    /// Create the texture atlas for the line decoration layer.
-    /// This is a bitmap with columns to accomodate the U_XXX
-    /// constants defined above.
-    fn compute_underlines<F: Facade>(
-        facade: &F,
-        cell_width: f64,
-        cell_height: f64,
-        descender: f64,
-    ) -> Result<SrgbTexture2d, glium::texture::TextureCreationError> {
-        let cell_width = cell_width.ceil() as usize;
-        let cell_height = cell_height.ceil() as usize;
-        let descender = if descender.is_sign_positive() {
-            (descender / 64.0).ceil() as isize
-        } else {
-            (descender / 64.0).floor() as isize
+    /// Loads the background image named by the config, unless it is
+    /// already loaded, and positions it in the window.  An image that
+    /// can't be loaded is logged and left out.
+    fn update_background_image<F: Facade>(&mut self, facade: &F) -> Result<(), Error> {
+        let config = Arc::clone(self.fonts.config());
+        let opacity = config.window_background_opacity();
+        let image = match config.window_background_image.as_ref() {
+            None => None,
+            Some(path) => match self.background_image.take() {
+                Some((image, _)) if image.matches(path, opacity) => Some(image),
+                _ => match BackgroundImage::load(facade, path, opacity) {
+                    Ok(image) => Some(image),
+                    Err(err) => {
+                        error!(
+                            "Unable to load the background image {}: {}",
+                            path.display(),
+                            err
+                        );
+                        None
+                    }
+                },
+            },
         };
-
-        let width = 5 * cell_width;
-        let mut underline_data = vec![0u8; width * cell_height * 4];
-
-        let descender_row = (cell_height as isize + descender) as usize;
-        let descender_plus_one = (1 + descender_row).min(cell_height - 1);
-        let descender_plus_two = (2 + descender_row).min(cell_height - 1);
-        let strike_row = descender_row / 2;
-
-        // First, the single underline.
-        // We place this just under the descender position.
-        {
-            let col = 0;
-            let offset = ((width * 4) * descender_plus_one) + (col * 4 * cell_width);
-            for i in 0..4 * cell_width {
-                underline_data[offset + i] = 0xff;
-            }
-        }
-        // Double underline,
-        // We place this at and just below the descender
-        {
-            let col = 1;
-            let offset_one = ((width * 4) * (descender_row)) + (col * 4 * cell_width);
-            let offset_two = ((width * 4) * (descender_plus_two)) + (col * 4 * cell_width);
-            for i in 0..4 * cell_width {
-                underline_data[offset_one + i] = 0xff;
-                underline_data[offset_two + i] = 0xff;
-            }
-        }
-        // Strikethrough
-        {
-            let col = 2;
-            let offset = (width * 4) * strike_row + (col * 4 * cell_width);
-            for i in 0..4 * cell_width {
-                underline_data[offset + i] = 0xff;
-            }
-        }
-        // Strikethrough and single underline
-        {
-            let col = 3;
-            let offset_one = ((width * 4) * descender_plus_one) + (col * 4 * cell_width);
-            let offset_two = ((width * 4) * strike_row) + (col * 4 * cell_width);
-            for i in 0..4 * cell_width {
-                underline_data[offset_one + i] = 0xff;
-                underline_data[offset_two + i] = 0xff;
+        self.background_image = match image {
+            Some(image) => {
+                let vertices = self.background_image_vertices(
+                    facade,
+                    &image,
+                    config.window_background_image_mode(),
+                )?;
+                Some((image, vertices))
             }
-        }
-        // Strikethrough and double underline
-        {
-            let col = 4;
-            let offset_one = ((width * 4) * (descender_row)) + (col * 4 * cell_width);
-            let offset_two = ((width * 4) * strike_row) + (col * 4 * cell_width);
-            let offset_three = ((width * 4) * (descender_plus_two)) + (col * 4 * cell_width);
-            for i in 0..4 * cell_width {
-                underline_data[offset_one + i] = 0xff;
-                underline_data[offset_two + i] = 0xff;
-                underline_data[offset_three + i] = 0xff;
-            }
-        }
+            None => None,
+        };
+        Ok(())
+    }
 
-        glium::texture::SrgbTexture2d::new(
-            facade,
-            glium::texture::RawImage2d::from_raw_rgba(
-                underline_data,
-                (width as u32, cell_height as u32),
-            ),
+    /// Computes the quad that draws `image` in the window
+    fn background_image_vertices<F: Facade>(
+        &self,
+        facade: &F,
+        image: &BackgroundImage,
+        mode: BackgroundImageMode,
+    ) -> Result<VertexBuffer<Vertex>, Error> {
+        let (width, height) = (f32::from(self.width), f32::from(self.height));
+        let ((left, top, right, bottom), (tex_left, tex_top, tex_right, tex_bottom)) =
+            image_quad(mode, (width, height), image.size());
+        // The origin of the projection is the center of the window
+        let corner = |x: f32, y: f32, tex: (f32, f32)| Vertex {
+            position: Point::new(x - width / 2.0, y - height / 2.0),
+            tex,
+            has_color: 1.0,
+            ..Default::default()
+        };
+        let verts = [
+            corner(left, top, (tex_left, tex_top)),
+            corner(right, top, (tex_right, tex_top)),
+            corner(left, bottom, (tex_left, tex_bottom)),
+            corner(right, bottom, (tex_right, tex_bottom)),
+        ];
+        Ok(VertexBuffer::new(facade, &verts)?)
+    }
+
+    fn new_rasterizer(fonts: &FontConfiguration) -> GlyphRasterizer {
+        GlyphRasterizer::new(
+            fonts.config(),
+            FontSystemSelection::get_default(),
+            fonts.get_font_scale(),
+            fonts.get_dpi_scale(),
         )
     }
 
@@ -444,10 +960,18 @@ impl Renderer {
         self.cell_width = metrics.cell_width;
         self.descender = metrics.descender;
 
+        // Glyphs that are in flight are for the old fonts; discard
+        // them along with the rasterizer that is producing them
+        self.rasterizer = Self::new_rasterizer(&self.fonts);
+        self.pending_glyphs.borrow_mut().clear();
+        self.rasterized_glyphs.borrow_mut().clear();
         self.glyph_cache.borrow_mut().clear();
+        self.image_cache.borrow_mut().clear();
         self.atlas = RefCell::new(Atlas::new(facade, TEX_SIZE)?);
-        self.underline_tex =
-            Self::compute_underlines(facade, self.cell_width, self.cell_height, self.descender)?;
+        self.decorations = decoration_rows(self.cell_height, self.descender);
+        self.painted_seqno = SEQ_ZERO;
+        // This is also how a reloaded config reaches us
+        self.update_background_image(facade)?;
         Ok(())
     }
 
@@ -455,9 +979,127 @@ impl Renderer {
         let atlas = RefCell::new(Atlas::new(facade, size)?);
         self.atlas = atlas;
         self.glyph_cache.borrow_mut().clear();
+        self.image_cache.borrow_mut().clear();
+        self.painted_seqno = SEQ_ZERO;
+        Ok(())
+    }
+
+    /// Replaces everything that the renderer holds on the GPU with
+    /// objects created by `facade`, after the OpenGL context that they
+    /// belonged to was lost.  The opacity chosen for this window and
+    /// whether it has the focus are kept.
+    pub fn recreate<F: Facade>(&mut self, facade: &F) -> Result<(), Error> {
+        let mut renderer = Renderer::new(facade, self.width, self.height, &self.fonts)?;
+        renderer.window_opacity = self.window_opacity;
+        renderer.focused = self.focused;
+        *self = renderer;
         Ok(())
     }
 
+    /// Returns the OpenGL implementation that this window renders with
+    pub fn gpu_info(&self) -> &GpuInfo {
+        &self.gpu_info
+    }
+
+    /// Records whether the window has the keyboard focus
+    pub fn set_focused(&mut self, focused: bool) {
+        if focused != self.focused {
+            self.focused = focused;
+            self.painted_seqno = SEQ_ZERO;
+        }
+    }
+
+    /// Returns whether the window has the keyboard focus
+    pub fn is_focused(&self) -> bool {
+        self.focused
+    }
+
+    /// Makes the next paint draw every line again rather than only
+    /// those that changed
+    pub fn invalidate(&mut self) {
+        self.painted_seqno = SEQ_ZERO;
+    }
+
+    /// Returns the sequence number of the terminal state that was
+    /// last painted, for comparison with `Renderable::has_changes_since`
+    pub fn painted_seqno(&self) -> SequenceNo {
+        self.painted_seqno
+    }
+
+    /// Returns the opacity of the background of this window
+    pub fn window_opacity(&self) -> f32 {
+        self.window_opacity
+            .unwrap_or_else(|| self.fonts.config().window_background_opacity())
+    }
+
+    /// Overrides the opacity of the background of this window, or
+    /// goes back to the configured opacity if `None`
+    pub fn set_window_opacity(&mut self, opacity: Option<f32>) {
+        self.window_opacity = opacity;
+        self.painted_seqno = SEQ_ZERO;
+    }
+
+    /// Works out how to draw the cursor of `snapshot` right now
+    fn current_cursor_shape(&self, snapshot: &RenderableSnapshot) -> CursorShape {
+        let config = self.fonts.config();
+        match cursor_shape(
+            config.cursor_style(snapshot.cursor_style),
+            config.cursor_blink_rate(),
+            self.cursor_blink_start.elapsed(),
+        ) {
+            CursorShape::Block if config.cursor_block_outline_width.is_some() => {
+                CursorShape::Outline
+            }
+            shape => shape,
+        }
+    }
+
+    /// Returns true if the cursor has blinked since the last paint,
+    /// in which case a paint is needed to show it
+    pub fn cursor_needs_repaint(&self) -> bool {
+        match self.snapshot.as_ref() {
+            Some(snapshot) => self.current_cursor_shape(snapshot) != self.cursor_shape,
+            None => false,
+        }
+    }
+
+    /// Returns when the window next needs to be painted even if the
+    /// terminal doesn't change, such as when the cursor blinks or
+    /// pending glyphs may have been rasterized, or None if it can
+    /// wait until the terminal changes
+    pub fn next_repaint(&self) -> Option<Instant> {
+        let now = Instant::now();
+        let glyphs = if self.pending_glyphs.borrow().is_empty() {
+            None
+        } else {
+            Some(now + GLYPH_POLL_INTERVAL)
+        };
+        let cursor = self.snapshot.as_ref().and_then(|snapshot| {
+            let config = self.fonts.config();
+            next_cursor_blink(
+                config.cursor_style(snapshot.cursor_style),
+                config.cursor_blink_rate(),
+                self.cursor_blink_start.elapsed(),
+            )
+            .map(|until| now + until)
+        });
+        match (glyphs, cursor) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Returns what each part of the window held in the last paint,
+    /// for routing mouse events
+    pub fn hit_regions(&self) -> &HitRegions {
+        &self.hit_regions
+    }
+
+    /// Returns the snapshot that was last painted
+    pub fn last_snapshot(&self) -> Option<&RenderableSnapshot> {
+        self.snapshot.as_ref()
+    }
+
     pub fn resize<F: Facade>(&mut self, facade: &F, width: u16, height: u16) -> Result<(), Error> {
         debug!("Renderer resize {},{}", width, height);
 
@@ -472,14 +1114,24 @@ impl Renderer {
             f32::from(width),
             f32::from(height),
         )?;
+        let num_cells = glyph_vertex_buffer.len() / VERTICES_PER_CELL;
+        self.background_vertex_buffer =
+            VertexBuffer::empty_dynamic(facade, num_cells * VERTICES_PER_CELL)?;
+        *self.backgrounds.borrow_mut() = vec![CellBackground::default(); num_cells];
+        *self.glyphs.borrow_mut() = vec![false; num_cells];
+        self.text_index_buffer = Self::empty_index_buffer(facade, num_cells)?;
+        self.damage.set(Damage::all());
         self.glyph_vertex_buffer = RefCell::new(glyph_vertex_buffer);
         self.glyph_index_buffer = glyph_index_buffer;
+        self.painted_seqno = SEQ_ZERO;
+        self.update_background_image(facade)?;
 
         Ok(())
     }
 
-    /// Resolve a glyph from the cache, rendering the glyph on-demand if
-    /// the cache doesn't already hold the desired glyph.
+    /// Resolve a glyph from the cache.  A glyph that isn't in the cache
+    /// is rasterized on a background thread and drawn as blank space
+    /// until it has been uploaded at the start of a later frame.
     fn cached_glyph(&self, info: &GlyphInfo, style: &TextStyle) -> Result<Rc<CachedGlyph>, Error> {
         let key = GlyphKey {
             font_idx: info.font_idx,
@@ -487,28 +1139,64 @@ impl Renderer {
             style: style.clone(),
         };
 
-        let mut cache = self.glyph_cache.borrow_mut();
-
-        if let Some(entry) = cache.get(&key) {
+        if let Some(entry) = self.glyph_cache.borrow().get(&key) {
             return Ok(Rc::clone(entry));
         }
 
-        let glyph = self.load_glyph(info, style)?;
-        cache.insert(key, Rc::clone(&glyph));
-        Ok(glyph)
+        let mut pending = self.pending_glyphs.borrow_mut();
+        if !pending.contains_key(&key) {
+            pending.insert(key.clone(), info.clone());
+            self.rasterizer.request(key);
+        }
+        Ok(Rc::new(CachedGlyph::pending()))
     }
 
-    /// Perform the load and render of a glyph
-    fn load_glyph(&self, info: &GlyphInfo, style: &TextStyle) -> Result<Rc<CachedGlyph>, Error> {
-        let (has_color, glyph, cell_width, cell_height) = {
-            let font = self.fonts.cached_font(style)?;
-            let mut font = font.borrow_mut();
-            let metrics = font.get_fallback(0)?.metrics();
-            let active_font = font.get_fallback(info.font_idx)?;
-            let has_color = active_font.has_color();
-            let glyph = active_font.rasterize_glyph(info.glyph_pos)?;
-            (has_color, glyph, metrics.cell_width, metrics.cell_height)
-        };
+    /// Collects the glyphs that the rasterizer has completed.
+    /// Returns true if there are any waiting to be uploaded, in which
+    /// case a paint is needed to show them.
+    pub fn has_rasterized_glyphs(&self) -> bool {
+        let mut rasterized = self.rasterized_glyphs.borrow_mut();
+        rasterized.extend(self.rasterizer.completed());
+        !rasterized.is_empty()
+    }
+
+    /// Uploads the glyphs that have been rasterized since the last
+    /// frame to the atlas and adds them to the cache.
+    /// Returns true if any were uploaded, in which case the lines that
+    /// were drawn while they were pending need to be drawn again.
+    fn upload_rasterized_glyphs(&self) -> Result<bool, Error> {
+        if !self.has_rasterized_glyphs() {
+            return Ok(false);
+        }
+        let mut rasterized = self.rasterized_glyphs.borrow_mut();
+        while let Some(glyph) = rasterized.pop() {
+            let info = match self.pending_glyphs.borrow_mut().remove(&glyph.key) {
+                Some(info) => info,
+                None => continue,
+            };
+            let key = glyph.key.clone();
+            // If the atlas is full this fails, and the glyph is requested
+            // again once the atlas has been recreated
+            let cached = self.load_glyph(&info, glyph)?;
+            self.glyph_cache.borrow_mut().insert(key, cached);
+        }
+        Ok(true)
+    }
+
+    /// Place a glyph that was rasterized in the background into the atlas
+    fn load_glyph(
+        &self,
+        info: &GlyphInfo,
+        rasterized: Rasterized,
+    ) -> Result<Rc<CachedGlyph>, Error> {
+        let Rasterized {
+            has_color,
+            cell_width,
+            cell_height,
+            glyph,
+            ..
+        } = rasterized;
+        let glyph = glyph?;
 
         let scale = if (info.x_advance / f64::from(info.num_cells)).floor() > cell_width {
             f64::from(info.num_cells) * (cell_width / info.x_advance)
@@ -563,6 +1251,60 @@ impl Renderer {
         Ok(Rc::new(glyph))
     }
 
+    /// Returns the sprite holding `image`.  The first time that an
+    /// image is seen it is decoded, scaled to the size of the cells
+    /// that it spans and uploaded to the atlas.
+    fn cached_image(&self, image: &ImageCell) -> Result<Rc<Sprite>, Error> {
+        let data = image.image_data();
+        if let Some(sprite) = self.image_cache.borrow().get(&data.id()) {
+            return Ok(Rc::clone(sprite));
+        }
+
+        // Each cell shows the same fraction of the image, which tells
+        // us how many cells it spans
+        let (top_left, bottom_right) = (image.top_left(), image.bottom_right());
+        let cells_wide = (1.0 / (bottom_right.x - top_left.x).into_inner()).round();
+        let cells_high = (1.0 / (bottom_right.y - top_left.y).into_inner()).round();
+        // The image can't be shown larger than the window, and capping
+        // the texture to that keeps an absurd number of cells, such as
+        // from a remote terminal, from exhausting memory
+        let width = (cells_wide.max(1.0) * self.cell_width.ceil() as f32)
+            .min(f32::from(self.width.max(1))) as u32;
+        let height = (cells_high.max(1.0) * self.cell_height.ceil() as f32)
+            .min(f32::from(self.height.max(1))) as u32;
+
+        let decoded = image::load_from_memory(data.data())?
+            .resize_exact(width, height, image::FilterType::CatmullRom)
+            .to_rgba();
+        let raw_im = glium::texture::RawImage2d::from_raw_rgba(decoded.into_raw(), (width, height));
+        let sprite = Rc::new(self.atlas.borrow_mut().allocate(width, height, raw_im)?);
+
+        self.image_cache
+            .borrow_mut()
+            .insert(data.id(), Rc::clone(&sprite));
+        Ok(sprite)
+    }
+
+    /// Points the vertices of a cell at its slice of `image`
+    fn render_image_cell(&self, vert: &mut [Vertex], image: &ImageCell) -> Result<(), Error> {
+        let sprite = self.cached_image(image)?;
+        let (top_left, bottom_right) = (image.top_left(), image.bottom_right());
+        let (left, top) = sprite.point(top_left.x.into_inner(), top_left.y.into_inner());
+        let (right, bottom) =
+            sprite.point(bottom_right.x.into_inner(), bottom_right.y.into_inner());
+
+        vert[V_TOP_LEFT].tex = (left, top);
+        vert[V_TOP_RIGHT].tex = (right, top);
+        vert[V_BOT_LEFT].tex = (left, bottom);
+        vert[V_BOT_RIGHT].tex = (right, bottom);
+
+        for v in vert.iter_mut() {
+            v.adjust = Default::default();
+            v.has_color = 1.0;
+        }
+        Ok(())
+    }
+
     /// Compute a vertex buffer to hold the quads that comprise the visible
     /// portion of the screen.   We recreate this when the screen is resized.
     /// The idea is that we want to minimize and heavy lifting and computation
@@ -631,6 +1373,213 @@ impl Renderer {
         ))
     }
 
+    /// Makes an index buffer with room for the quads of `num_cells` cells
+    fn empty_index_buffer<F: Facade>(
+        facade: &F,
+        num_cells: usize,
+    ) -> Result<IndexBuffer<u32>, Error> {
+        Ok(IndexBuffer::empty_dynamic(
+            facade,
+            glium::index::PrimitiveType::TrianglesList,
+            num_cells * INDICES_PER_CELL,
+        )?)
+    }
+
+    /// Rebuilds the batches that the cell backgrounds and text layers
+    /// are drawn from, but only those whose cells changed since they
+    /// were last built; an idle terminal rebuilds nothing.
+    fn update_batches(&mut self) -> Result<(), Error> {
+        let damage = self.damage.replace(Damage::default());
+        if damage.backgrounds {
+            self.num_background_quads = self.compute_background_vertices()?;
+        }
+        if damage.glyphs {
+            let indices = text_indices(&self.glyphs.borrow());
+            if !indices.is_empty() {
+                self.text_index_buffer
+                    .slice(0..indices.len())
+                    .ok_or_else(|| err_msg("we're confused about the screen size"))?
+                    .write(&indices);
+            }
+            self.num_text_quads = indices.len() / INDICES_PER_CELL;
+        }
+        Ok(())
+    }
+
+    /// Merges the cell backgrounds into runs and writes a quad for each
+    /// into the background vertex buffer.  The quads are laid out in the
+    /// same way as the cells of the glyph vertex buffer, so the glyph
+    /// index buffer can be used to draw them.
+    /// Returns the number of quads.
+    fn compute_background_vertices(&self) -> Result<usize, Error> {
+        let runs = background_runs(&self.backgrounds.borrow(), self.grid_cols());
+        if runs.is_empty() {
+            return Ok(0);
+        }
+        let cell_width = self.cell_width.ceil() as f32;
+        let cell_height = self.cell_height.ceil() as f32;
+        let left = f32::from(self.width) / -2.0;
+        let top = f32::from(self.height) / -2.0;
+
+        let mut verts = Vec::with_capacity(runs.len() * VERTICES_PER_CELL);
+        for (row, cols, background) in &runs {
+            let y_top = top + *row as f32 * cell_height;
+            let y_bot = y_top + cell_height;
+            let x_left = left + cols.start as f32 * cell_width;
+            let x_right = left + cols.end as f32 * cell_width;
+            for &(v_idx, x, y) in &[
+                (V_TOP_LEFT, x_left, y_top),
+                (V_TOP_RIGHT, x_right, y_top),
+                (V_BOT_LEFT, x_left, y_bot),
+                (V_BOT_RIGHT, x_right, y_bot),
+            ] {
+                verts.push(Vertex {
+                    position: Point::new(x, y),
+                    fg_color: background.line_color,
+                    bg_color: background.bg_color,
+                    underline: background.underline,
+                    strikethrough: background.strikethrough,
+                    overline: background.overline,
+                    v_idx: v_idx as f32,
+                    ..Default::default()
+                });
+            }
+        }
+
+        self.background_vertex_buffer
+            .slice(0..verts.len())
+            .ok_or_else(|| err_msg("we're confused about the screen size"))?
+            .write(&verts);
+        Ok(runs.len())
+    }
+
+    /// Records where the parts of the frame described by `snapshot`
+    /// and `composition` are placed, in the order that they're drawn
+    fn compute_hit_regions(
+        &self,
+        snapshot: &RenderableSnapshot,
+        row_offset: usize,
+        composition: &Composition,
+    ) -> HitRegions {
+        let grid_cols = self.grid_cols();
+        let num_rows = snapshot.physical_rows;
+        let num_cols = snapshot.physical_cols.min(grid_cols);
+        let mut regions = HitRegions::new(row_offset);
+
+        regions.add(
+            row_offset..row_offset + num_rows,
+            0..num_cols,
+            HitTarget::Terminal,
+        );
+        for (line_idx, (line, _)) in snapshot.lines.iter().enumerate() {
+            regions.add_hyperlinks(line_idx + row_offset, line, num_cols);
+        }
+
+        for overlay in &composition.overlays {
+            let start = overlay.row.min(num_rows);
+            let end = (overlay.row + overlay.lines.len()).min(num_rows);
+            regions.add(
+                start + row_offset..end + row_offset,
+                overlay.cols(num_cols),
+                HitTarget::Overlay,
+            );
+        }
+
+        let bars = composition.above.iter().enumerate().chain(
+            composition
+                .below
+                .iter()
+                .enumerate()
+                .map(|(idx, bar)| (row_offset + num_rows + idx, bar)),
+        );
+        for (row, bar) in bars {
+            for (cols, target) in &bar.targets {
+                regions.add(row..row + 1, cols.clone(), target.clone());
+            }
+        }
+
+        if composition.scrollbar && grid_cols > 0 {
+            regions.add(
+                row_offset..row_offset + num_rows,
+                grid_cols - 1..grid_cols,
+                HitTarget::Scrollbar,
+            );
+        }
+        regions
+    }
+
+    /// Places the quads of the lines that draw the cursor in the
+    /// cursor vertex buffer.  Returns the number of quads, which is 0
+    /// if there are no lines to draw, including when an overlay covers
+    /// the cursor.
+    fn compute_cursor_vertices(
+        &self,
+        shape: CursorShape,
+        snapshot: &RenderableSnapshot,
+        row_offset: usize,
+        composition: &Composition,
+    ) -> Result<usize, Error> {
+        let cursor = snapshot.cursor;
+        if cursor.y < 0
+            || cursor.y as usize >= snapshot.physical_rows
+            || cursor.x >= snapshot.physical_cols.min(self.grid_cols())
+        {
+            return Ok(0);
+        }
+        let line_idx = cursor.y as usize;
+        let num_cols = snapshot.physical_cols;
+        let covered = composition.overlays.iter().any(|overlay| {
+            line_idx >= overlay.row
+                && line_idx < overlay.row + overlay.lines.len()
+                && overlay.cols(num_cols).contains(&cursor.x)
+        });
+        if covered {
+            return Ok(0);
+        }
+
+        let cell_width = self.cell_width.ceil() as f32;
+        let cell_height = self.cell_height.ceil() as f32;
+        let left = f32::from(self.width) / -2.0 + cursor.x as f32 * cell_width;
+        let top = f32::from(self.height) / -2.0 + (line_idx + row_offset) as f32 * cell_height;
+        let thickness = CursorThickness::new(
+            &self.fonts.config(),
+            cell_height,
+            self.fonts.get_dpi_scale() as f32,
+        );
+        let rects = cursor_rects(
+            shape,
+            (left, top, left + cell_width, top + cell_height),
+            thickness,
+        );
+        if rects.is_empty() {
+            return Ok(0);
+        }
+
+        let color = snapshot.palette.cursor_bg.to_tuple_rgba();
+        let verts: Vec<Vertex> = rects
+            .iter()
+            .flat_map(|&(left, top, right, bottom)| {
+                vec![
+                    (V_TOP_LEFT, left, top),
+                    (V_TOP_RIGHT, right, top),
+                    (V_BOT_LEFT, left, bottom),
+                    (V_BOT_RIGHT, right, bottom),
+                ]
+            })
+            .map(|(v_idx, x, y)| Vertex {
+                position: Point::new(x, y),
+                bg_color: color,
+                v_idx: v_idx as f32,
+                ..Default::default()
+            })
+            .collect();
+        self.cursor_vertex_buffer
+            .slice(0..verts.len())
+            .ok_or_else(|| err_msg("too many quads for the cursor"))?
+            .write(&verts);
+        Ok(rects.len())
+    }
+
     /// The projection corrects for the aspect ratio and flips the y-axis
     fn compute_projection(width: f32, height: f32) -> Transform3D {
         Transform3D::ortho(
@@ -647,26 +1596,45 @@ impl Renderer {
     /// This is nominally a matter of setting the fg/bg color and the
     /// texture coordinates for a given glyph.  There's a little bit
     /// of extra complexity to deal with multi-cell glyphs.
+    /// `vb_row` is the row of the window in which the line is placed,
+    /// which differs from `line_idx` when the tab bar is at the top.
+    #[cfg_attr(feature = "cargo-clippy", allow(clippy::too_many_arguments))]
     fn render_screen_line(
         &self,
+        vb_row: usize,
         line_idx: usize,
         line: &Line,
         selection: Range<usize>,
-        cursor: &CursorPosition,
-        terminal: &dyn Renderable,
-        palette: &ColorPalette,
+        cursor: Option<&CursorPosition>,
+        snapshot: &RenderableSnapshot,
     ) -> Result<(), Error> {
-        let (_num_rows, num_cols) = terminal.physical_dimensions();
+        let palette = &snapshot.palette;
+        let grid_cols = self.grid_cols();
+        let num_cols = snapshot.physical_cols;
+        // The window may be wider than the terminal, for example when
+        // the scrollbar occupies the rightmost column
+        let num_cols = num_cols.min(grid_cols);
         let mut vb = self.glyph_vertex_buffer.borrow_mut();
         let mut vertices = {
-            let per_line = num_cols * VERTICES_PER_CELL;
-            let start_pos = line_idx * per_line;
+            let per_line = grid_cols * VERTICES_PER_CELL;
+            let start_pos = vb_row * per_line;
             vb.slice_mut(start_pos..start_pos + per_line)
                 .ok_or_else(|| err_msg("we're confused about the screen size"))?
                 .map()
         };
-
-        let current_highlight = terminal.current_highlight();
+        let mut backgrounds = self.backgrounds.borrow_mut();
+        let backgrounds = backgrounds
+            .get_mut(vb_row * grid_cols..(vb_row + 1) * grid_cols)
+            .ok_or_else(|| err_msg("we're confused about the screen size"))?;
+        let prior_backgrounds = backgrounds.to_vec();
+
+        let current_highlight = snapshot.current_highlight.clone();
+        let text_background_opacity = self.fonts.config().text_background_opacity();
+        let text_brightness = if self.focused {
+            1.0
+        } else {
+            self.fonts.config().unfocused_window_text_brightness()
+        };
 
         // Break the line into clusters of cells with the same attributes
         let cell_clusters = line.cluster();
@@ -702,6 +1670,12 @@ impl Renderer {
                 _ => palette.resolve_fg(attrs.foreground),
             };
 
+            let fg_color = if attrs.intensity() == term::Intensity::Half {
+                palette.resolve_dim_fg(attrs.foreground, fg_color, bg_color)
+            } else {
+                fg_color
+            };
+
             let (fg_color, bg_color) = {
                 let mut fg = fg_color;
                 let mut bg = bg_color;
@@ -710,18 +1684,26 @@ impl Renderer {
                     mem::swap(&mut fg, &mut bg);
                 }
 
+                if text_brightness < 1.0 {
+                    fg = fade_towards(fg, bg, text_brightness);
+                }
+
                 (fg, bg)
             };
 
             let glyph_color = fg_color.to_tuple_rgba();
-            let bg_color = bg_color.to_tuple_rgba();
+            // Cells with the default background color are left clear
+            // so that the window background, which may be an image or
+            // translucent, shows through them
+            let bg_color =
+                if attrs.background == term::color::ColorAttribute::Default && !attrs.reverse() {
+                    with_alpha(bg_color.to_tuple_rgba(), 0.0)
+                } else {
+                    with_alpha(bg_color.to_tuple_rgba(), text_background_opacity)
+                };
 
             // Shape the printable text from this cluster
-            let glyph_info = {
-                let font = self.fonts.cached_font(style)?;
-                let mut font = font.borrow_mut();
-                font.shape(&cluster.text)?
-            };
+            let glyph_info = self.fonts.shape(style, &cluster.text)?;
 
             for info in &glyph_info {
                 let cell_idx = cluster.byte_to_cell_idx[info.cluster as usize];
@@ -731,29 +1713,23 @@ impl Renderer {
                 let top = ((self.cell_height + self.descender) - (glyph.y_offset + glyph.bearing_y))
                     as f32;
 
-                // underline and strikethrough
+                // underline, strikethrough and overline
                 // Figure out what we're going to draw for the underline.
                 // If the current cell is part of the current URL highlight
                 // then we want to show the underline.
-                #[cfg_attr(feature = "cargo-clippy", allow(clippy::match_same_arms))]
-                let underline: f32 = match (
-                    is_highlited_hyperlink,
-                    attrs.strikethrough(),
-                    attrs.underline(),
-                ) {
-                    (true, false, Underline::None) => U_ONE,
-                    (true, false, Underline::Single) => U_TWO,
-                    (true, false, Underline::Double) => U_ONE,
-                    (true, true, Underline::None) => U_STRIKE_ONE,
-                    (true, true, Underline::Single) => U_STRIKE_TWO,
-                    (true, true, Underline::Double) => U_STRIKE_ONE,
-                    (false, false, Underline::None) => U_NONE,
-                    (false, false, Underline::Single) => U_ONE,
-                    (false, false, Underline::Double) => U_TWO,
-                    (false, true, Underline::None) => U_STRIKE,
-                    (false, true, Underline::Single) => U_STRIKE_ONE,
-                    (false, true, Underline::Double) => U_STRIKE_TWO,
+                let underline = match (is_highlited_hyperlink, attrs.underline()) {
+                    (true, Underline::None) => Underline::Single,
+                    (true, Underline::Single) => Underline::Double,
+                    (true, Underline::Double) => Underline::Single,
+                    (false, underline) => underline,
+                };
+                let underline = match underline {
+                    Underline::None => 0.0,
+                    Underline::Single => 1.0,
+                    Underline::Double => 2.0,
                 };
+                let strikethrough = if attrs.strikethrough() { 1.0 } else { 0.0 };
+                let overline = if attrs.overline() { 1.0 } else { 0.0 };
 
                 // Iterate each cell that comprises this glyph.  There is usually
                 // a single cell per glyph but combining characters, ligatures
@@ -787,15 +1763,13 @@ impl Renderer {
                     vert[V_BOT_LEFT].fg_color = glyph_color;
                     vert[V_BOT_RIGHT].fg_color = glyph_color;
 
-                    vert[V_TOP_LEFT].bg_color = bg_color;
-                    vert[V_TOP_RIGHT].bg_color = bg_color;
-                    vert[V_BOT_LEFT].bg_color = bg_color;
-                    vert[V_BOT_RIGHT].bg_color = bg_color;
-
-                    vert[V_TOP_LEFT].underline = underline;
-                    vert[V_TOP_RIGHT].underline = underline;
-                    vert[V_BOT_LEFT].underline = underline;
-                    vert[V_BOT_RIGHT].underline = underline;
+                    backgrounds[cell_idx] = CellBackground::new(
+                        bg_color,
+                        glyph_color,
+                        underline,
+                        strikethrough,
+                        overline,
+                    );
 
                     match glyph.texture {
                         Some(ref texture) => {
@@ -839,7 +1813,7 @@ impl Renderer {
                             let zero = (0.0, 0.0f32);
 
                             // Note: these 0 coords refer to the blank pixel
-                            // in the bottom left of the underline texture!
+                            // in the bottom left of the glyph atlas!
                             vert[V_TOP_LEFT].tex = zero;
                             vert[V_TOP_RIGHT].tex = zero;
                             vert[V_BOT_LEFT].tex = zero;
@@ -856,6 +1830,10 @@ impl Renderer {
                             vert[V_BOT_RIGHT].has_color = 0.0;
                         }
                     }
+
+                    if let Some(image) = attrs.image.as_ref() {
+                        self.render_image_cell(vert, image)?;
+                    }
                 }
             }
         }
@@ -880,22 +1858,103 @@ impl Renderer {
                 cursor,
                 &selection,
                 palette.foreground.to_tuple_rgba(),
-                palette.background.to_tuple_rgba(),
+                with_alpha(palette.background.to_tuple_rgba(), 0.0),
                 palette,
             );
 
+            backgrounds[cell_idx] = CellBackground::plain(bg_color);
             for vert in vert_slice.iter_mut() {
-                vert.bg_color = bg_color;
                 vert.fg_color = glyph_color;
-                vert.underline = U_NONE;
                 // Note: these 0 coords refer to the blank pixel
-                // in the bottom left of the underline texture!
+                // in the bottom left of the glyph atlas!
                 vert.tex = (0.0, 0.0);
                 vert.adjust = Default::default();
                 vert.has_color = 0.0;
             }
         }
 
+        let mut glyphs = self.glyphs.borrow_mut();
+        let glyphs = glyphs
+            .get_mut(vb_row * grid_cols..vb_row * grid_cols + num_cols)
+            .ok_or_else(|| err_msg("we're confused about the screen size"))?;
+        let mut damage = self.damage.get();
+        damage.backgrounds |= *backgrounds != prior_backgrounds[..];
+        damage.glyphs |= update_glyph_cells(glyphs, &vertices);
+        self.damage.set(damage);
+
+        Ok(())
+    }
+
+    /// Returns the number of columns of cells in the vertex buffer,
+    /// which spans the full width of the window
+    fn grid_cols(&self) -> usize {
+        (self.width as usize + 1) / self.cell_width.ceil() as usize
+    }
+
+    /// Draw the scrollbar into the rightmost column of the window,
+    /// alongside the `num_rows` rows of the terminal that start at
+    /// `row_offset`.
+    fn render_scrollbar(
+        &self,
+        row_offset: usize,
+        num_rows: usize,
+        info: ScrollbarInfo,
+        palette: &ColorPalette,
+    ) -> Result<(), Error> {
+        let grid_cols = self.grid_cols();
+        if grid_cols == 0 {
+            return Ok(());
+        }
+        let thumb = thumb_range(info, num_rows);
+
+        // The thumb is drawn a third of the way from the background
+        // to the foreground color so that it is visible but subdued
+        let blend = |fg: u8, bg: u8| ((u16::from(fg) + 2 * u16::from(bg)) / 3) as u8;
+        let (fg, bg) = (palette.foreground, palette.background);
+        let thumb_color = term::color::RgbColor::new(
+            blend(fg.red, bg.red),
+            blend(fg.green, bg.green),
+            blend(fg.blue, bg.blue),
+        )
+        .to_tuple_rgba();
+        // The track is left clear, like the default background
+        let track_color = with_alpha(bg.to_tuple_rgba(), 0.0);
+
+        let mut vb = self.glyph_vertex_buffer.borrow_mut();
+        let mut backgrounds = self.backgrounds.borrow_mut();
+        let mut glyphs = self.glyphs.borrow_mut();
+        let mut damage = self.damage.get();
+        for row in 0..num_rows {
+            let cell_idx = (row_offset + row) * grid_cols + grid_cols - 1;
+            let vert_idx = cell_idx * VERTICES_PER_CELL;
+            let mut vertices = vb
+                .slice_mut(vert_idx..vert_idx + VERTICES_PER_CELL)
+                .ok_or_else(|| err_msg("we're confused about the screen size"))?
+                .map();
+            let color = if thumb.contains(&row) {
+                thumb_color
+            } else {
+                track_color
+            };
+            let background = backgrounds
+                .get_mut(cell_idx)
+                .ok_or_else(|| err_msg("we're confused about the screen size"))?;
+            if *background != CellBackground::plain(color) {
+                *background = CellBackground::plain(color);
+                damage.backgrounds = true;
+            }
+            for vert in vertices.iter_mut() {
+                vert.fg_color = color;
+                vert.tex = (0.0, 0.0);
+                vert.adjust = Default::default();
+                vert.has_color = 0.0;
+            }
+            if let Some(glyph) = glyphs.get_mut(cell_idx) {
+                damage.glyphs |= *glyph;
+                *glyph = false;
+            }
+        }
+        self.damage.set(damage);
         Ok(())
     }
 
@@ -904,14 +1963,17 @@ impl Renderer {
         &self,
         line_idx: usize,
         cell_idx: usize,
-        cursor: &CursorPosition,
+        cursor: Option<&CursorPosition>,
         selection: &Range<usize>,
         fg_color: RgbaTuple,
         bg_color: RgbaTuple,
         palette: &ColorPalette,
     ) -> (RgbaTuple, RgbaTuple) {
         let selected = selection.contains(&cell_idx);
-        let is_cursor = line_idx as i64 == cursor.y && cursor.x == cell_idx;
+        let is_cursor = match cursor {
+            Some(cursor) => line_idx as i64 == cursor.y && cursor.x == cell_idx,
+            None => false,
+        };
 
         let (fg_color, bg_color) = match (selected, is_cursor) {
             // Normally, render the cell as configured
@@ -931,61 +1993,484 @@ impl Renderer {
         (fg_color, bg_color)
     }
 
+    /// Paint the lines of `snapshot` that changed since the last paint,
+    /// along with the things that `composition` places around and over
+    /// the terminal, and then draw the layers of the frame.
     pub fn paint(
         &mut self,
         target: &mut glium::Frame,
-        term: &mut dyn Renderable,
-        palette: &ColorPalette,
+        snapshot: RenderableSnapshot,
+        composition: &Composition,
     ) -> Result<(), Error> {
+        let palette = &snapshot.palette;
         let background_color = palette.resolve_bg(term::color::ColorAttribute::Default);
-        let (r, g, b, a) = background_color.to_tuple_rgba();
-        target.clear_color(r, g, b, a);
+        let (r, g, b, _) = background_color.to_tuple_rgba();
+        // The visual bell shows the cells that have the default
+        // background color, which are left clear, halfway between
+        // the background and foreground colors
+        let (r, g, b) = if composition.flash {
+            let (fg_r, fg_g, fg_b, _) = palette
+                .resolve_fg(term::color::ColorAttribute::Default)
+                .to_tuple_rgba();
+            ((r + fg_r) / 2., (g + fg_g) / 2., (b + fg_b) / 2.)
+        } else {
+            (r, g, b)
+        };
+        let opacity = self.window_opacity();
+        // A compositor expects the colors to be premultiplied by the alpha
+        target.clear_color(r * opacity, g * opacity, b * opacity, opacity);
 
-        let cursor = term.get_cursor_position();
-        {
-            let dirty_lines = term.get_dirty_lines();
+        let num_rows = snapshot.physical_rows;
+        let row_offset = composition.above.len();
+
+        if self.upload_rasterized_glyphs()? {
+            self.painted_seqno = SEQ_ZERO;
+        }
+
+        // Restart the blink cycle when the cursor moves
+        if self.snapshot.as_ref().map(|prior| prior.cursor) != Some(snapshot.cursor) {
+            self.cursor_blink_start = Instant::now();
+        }
+        let cursor_shape = self.current_cursor_shape(&snapshot);
+        // Only the block cursor changes the colors of its cell
+        let cell_cursor = if cursor_shape == CursorShape::Block {
+            Some(&snapshot.cursor)
+        } else {
+            None
+        };
+
+        for (line_idx, line, selrange) in snapshot.changed_lines(self.painted_seqno) {
+            self.render_screen_line(
+                line_idx + row_offset,
+                line_idx,
+                line,
+                selrange,
+                cell_cursor,
+                &snapshot,
+            )?;
+        }
 
-            for (line_idx, line, selrange) in dirty_lines {
-                self.render_screen_line(line_idx, &line, selrange, &cursor, term, palette)?;
+        // The cursor line may be unchanged while the cursor blinked
+        // or changed shape
+        if cursor_shape != self.cursor_shape && snapshot.cursor.y >= 0 {
+            let line_idx = snapshot.cursor.y as usize;
+            if let Some((line, selrange)) = snapshot.lines.get(line_idx) {
+                self.render_screen_line(
+                    line_idx + row_offset,
+                    line_idx,
+                    line,
+                    selrange.clone(),
+                    cell_cursor,
+                    &snapshot,
+                )?;
             }
         }
 
-        let tex = self.atlas.borrow().texture();
+        let overlaid = merge_overlays(
+            &composition.overlays,
+            &snapshot.lines,
+            num_rows,
+            snapshot.physical_cols,
+        );
+        for (line_idx, line) in &overlaid {
+            self.render_screen_line(
+                line_idx + row_offset,
+                *line_idx,
+                line,
+                0..0,
+                None,
+                &snapshot,
+            )?;
+        }
+
+        for (row, bar) in composition.above.iter().enumerate() {
+            self.render_screen_line(row, 0, &bar.line, 0..0, None, &snapshot)?;
+        }
+
+        for (idx, bar) in composition.below.iter().enumerate() {
+            let row = row_offset + num_rows + idx;
+            self.render_screen_line(row, 0, &bar.line, 0..0, None, &snapshot)?;
+        }
+
+        if composition.scrollbar {
+            self.render_scrollbar(row_offset, num_rows, snapshot.scrollbar, palette)?;
+        }
+
+        self.num_cursor_quads =
+            self.compute_cursor_vertices(cursor_shape, &snapshot, row_offset, composition)?;
+        self.cursor_shape = cursor_shape;
+        self.hit_regions = self.compute_hit_regions(&snapshot, row_offset, composition);
+        self.update_batches()?;
+
+        for layer in &LAYERS {
+            self.draw_layer(target, *layer)?;
+        }
+
+        self.painted_seqno = snapshot.seqno;
+        self.snapshot = Some(snapshot);
+        Ok(())
+    }
+
+    /// Draws one of the layers of the frame from the vertices that
+    /// `paint` prepared
+    fn draw_layer(&self, target: &mut glium::Frame, layer: Layer) -> Result<(), Error> {
+        let params = glium::DrawParameters {
+            blend: blend_over(),
+            ..Default::default()
+        };
+        match layer {
+            Layer::BackgroundImage => {
+                if let Some((image, vertices)) = self.background_image.as_ref() {
+                    if let Some(indices) = self.glyph_index_buffer.slice(0..INDICES_PER_CELL) {
+                        target.draw(
+                            vertices,
+                            indices,
+                            &self.program,
+                            &uniform! {
+                                projection: self.projection.to_column_arrays(),
+                                glyph_tex: image
+                                    .texture
+                                    .sampled()
+                                    .wrap_function(SamplerWrapFunction::Repeat),
+                                bg_and_line_layer: false,
+                            },
+                            &params,
+                        )?;
+                    }
+                }
+            }
+            Layer::CellBackgrounds => {
+                let num_quads = self.num_background_quads;
+                // The cells can all be transparent, such as before the
+                // first paint
+                if num_quads == 0 {
+                    return Ok(());
+                }
+                let tex = self.atlas.borrow().texture();
+                let decorations = self.decorations;
+                target.draw(
+                    self.background_vertex_buffer
+                        .slice(0..num_quads * VERTICES_PER_CELL)
+                        .ok_or_else(|| err_msg("we're confused about the screen size"))?,
+                    self.glyph_index_buffer
+                        .slice(0..num_quads * INDICES_PER_CELL)
+                        .ok_or_else(|| err_msg("we're confused about the screen size"))?,
+                    &self.program,
+                    &uniform! {
+                        projection: self.projection.to_column_arrays(),
+                        glyph_tex: &*tex,
+                        bg_and_line_layer: true,
+                        cell_height: self.cell_height as f32,
+                        underline_row: decorations.underline,
+                        double_underline_rows: decorations.double_underline,
+                        strikethrough_row: decorations.strikethrough,
+                        overline_row: decorations.overline,
+                    },
+                    &params,
+                )?;
+            }
+            Layer::Cursor => {
+                if self.num_cursor_quads == 0 {
+                    return Ok(());
+                }
+                let tex = self.atlas.borrow().texture();
+                let decorations = self.decorations;
+                target.draw(
+                    self.cursor_vertex_buffer
+                        .slice(0..self.num_cursor_quads * VERTICES_PER_CELL)
+                        .ok_or_else(|| err_msg("too many quads for the cursor"))?,
+                    self.cursor_index_buffer
+                        .slice(0..self.num_cursor_quads * INDICES_PER_CELL)
+                        .ok_or_else(|| err_msg("too many quads for the cursor"))?,
+                    &self.program,
+                    &uniform! {
+                        projection: self.projection.to_column_arrays(),
+                        glyph_tex: &*tex,
+                        bg_and_line_layer: true,
+                        cell_height: self.cell_height as f32,
+                        underline_row: decorations.underline,
+                        double_underline_rows: decorations.double_underline,
+                        strikethrough_row: decorations.strikethrough,
+                        overline_row: decorations.overline,
+                    },
+                    &params,
+                )?;
+            }
+            Layer::Text => {
+                if self.num_text_quads == 0 {
+                    return Ok(());
+                }
+                let tex = self.atlas.borrow().texture();
+                target.draw(
+                    &*self.glyph_vertex_buffer.borrow(),
+                    self.text_index_buffer
+                        .slice(0..self.num_text_quads * INDICES_PER_CELL)
+                        .ok_or_else(|| err_msg("we're confused about the screen size"))?,
+                    &self.program,
+                    &uniform! {
+                        projection: self.projection.to_column_arrays(),
+                        glyph_tex: &*tex,
+                        bg_and_line_layer: false,
+                    },
+                    &params,
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
 
-        // Pass 1: Draw backgrounds, strikethrough and underline
-        target.draw(
-            &*self.glyph_vertex_buffer.borrow(),
-            &self.glyph_index_buffer,
-            &self.program,
-            &uniform! {
-                projection: self.projection.to_column_arrays(),
-                glyph_tex: &*tex,
-                bg_and_line_layer: true,
-                underline_tex: &self.underline_tex,
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn overlays_share_rows() {
+        let attrs = term::CellAttributes::default();
+        let text = |text: &str| Line::from_text(text, &attrs);
+        let lines = vec![
+            (Arc::new(text("abcdef")), 0..0),
+            (Arc::new(text("ghijkl")), 0..0),
+        ];
+        let overlays = vec![
+            // Narrow overlays in the first column and further along
+            // the same row are both laid over the terminal
+            Overlay {
+                row: 0,
+                col: 0,
+                lines: vec![text("X")],
             },
-            &glium::DrawParameters {
-                blend: glium::Blend::alpha_blending(),
-                ..Default::default()
+            Overlay {
+                row: 0,
+                col: 3,
+                lines: vec![text("YZ")],
             },
-        )?;
-
-        // Pass 2: Draw glyphs
-        target.draw(
-            &*self.glyph_vertex_buffer.borrow(),
-            &self.glyph_index_buffer,
-            &self.program,
-            &uniform! {
-                projection: self.projection.to_column_arrays(),
-                glyph_tex: &*tex,
-                bg_and_line_layer: false,
+            // One as wide as the terminal replaces the row, and later
+            // overlays are laid over it in turn
+            Overlay {
+                row: 1,
+                col: 0,
+                lines: vec![text("prompt")],
             },
-            &glium::DrawParameters {
-                blend: glium::Blend::alpha_blending(),
-                ..Default::default()
+            Overlay {
+                row: 1,
+                col: 5,
+                lines: vec![text("!")],
             },
-        )?;
+            // Out of view
+            Overlay {
+                row: 2,
+                col: 0,
+                lines: vec![text("gone")],
+            },
+        ];
+        let merged: Vec<(usize, String)> = merge_overlays(&overlays, &lines, 2, 6)
+            .into_iter()
+            .map(|(row, line)| (row, line.as_str()))
+            .collect();
+        assert_eq!(
+            merged,
+            vec![(0, "XbcYZf".to_owned()), (1, "promp!".to_owned())]
+        );
+    }
 
-        term.clean_dirty_lines();
-        Ok(())
+    fn cell(bg: f32) -> CellBackground {
+        CellBackground::plain((bg, bg, bg, 1.0))
+    }
+
+    #[test]
+    fn cursor_blinks() {
+        let rate = Some(Duration::from_millis(500));
+        let at = Duration::from_millis;
+        assert_eq!(
+            cursor_shape(CursorStyle::BlinkingBar, rate, at(0)),
+            CursorShape::Bar
+        );
+        assert_eq!(
+            cursor_shape(CursorStyle::BlinkingBar, rate, at(600)),
+            CursorShape::Hidden
+        );
+        assert_eq!(
+            cursor_shape(CursorStyle::BlinkingBar, rate, at(1100)),
+            CursorShape::Bar
+        );
+        // Steady cursors, and blinking ones with blinking disabled,
+        // are always shown
+        assert_eq!(
+            cursor_shape(CursorStyle::SteadyUnderline, rate, at(600)),
+            CursorShape::Underline
+        );
+        assert_eq!(
+            cursor_shape(CursorStyle::BlinkingBlock, None, at(600)),
+            CursorShape::Block
+        );
+    }
+
+    #[test]
+    fn next_blink() {
+        let rate = Some(Duration::from_millis(500));
+        let at = Duration::from_millis;
+        assert_eq!(
+            next_cursor_blink(CursorStyle::BlinkingBar, rate, at(0)),
+            Some(at(500))
+        );
+        assert_eq!(
+            next_cursor_blink(CursorStyle::BlinkingBar, rate, at(1100)),
+            Some(at(400))
+        );
+        assert_eq!(
+            next_cursor_blink(CursorStyle::SteadyBar, rate, at(100)),
+            None
+        );
+        assert_eq!(
+            next_cursor_blink(CursorStyle::BlinkingBlock, None, at(100)),
+            None
+        );
+    }
+
+    #[test]
+    fn fade_text() {
+        let fg = RgbColor::new(0xff, 0x80, 0x00);
+        let bg = RgbColor::new(0x00, 0x00, 0x40);
+        assert_eq!(fade_towards(fg, bg, 1.0), fg);
+        assert_eq!(fade_towards(fg, bg, 0.0), bg);
+        assert_eq!(fade_towards(fg, bg, 0.5), RgbColor::new(0x80, 0x40, 0x20));
+    }
+
+    #[test]
+    fn cursor_lines() {
+        let mut config = Config::default();
+        let cell = (10.0, 20.0, 18.0, 36.0);
+        let thickness = CursorThickness::new(&config, 16.0, 1.0);
+        assert_eq!(
+            cursor_rects(CursorShape::Underline, cell, thickness),
+            vec![(10.0, 35.0, 18.0, 36.0)]
+        );
+        // Taller cells get thicker lines
+        assert_eq!(
+            cursor_rects(
+                CursorShape::Bar,
+                (10.0, 20.0, 18.0, 52.0),
+                CursorThickness::new(&config, 32.0, 1.0)
+            ),
+            vec![(10.0, 20.0, 12.0, 52.0)]
+        );
+        assert!(cursor_rects(CursorShape::Block, cell, thickness).is_empty());
+
+        // Configured widths are scaled by the dpi, but kept in the cell
+        config.cursor_bar_thickness = Some(3.0);
+        config.cursor_underline_height = Some(20.0);
+        config.cursor_block_outline_width = Some(1.5);
+        let thickness = CursorThickness::new(&config, 16.0, 2.0);
+        assert_eq!(
+            cursor_rects(CursorShape::Bar, cell, thickness),
+            vec![(10.0, 20.0, 16.0, 36.0)]
+        );
+        assert_eq!(
+            cursor_rects(CursorShape::Underline, cell, thickness),
+            vec![(10.0, 20.0, 18.0, 36.0)]
+        );
+        assert_eq!(
+            cursor_rects(CursorShape::Outline, cell, thickness),
+            vec![
+                (10.0, 20.0, 18.0, 23.0),
+                (10.0, 33.0, 18.0, 36.0),
+                (10.0, 23.0, 13.0, 33.0),
+                (15.0, 23.0, 18.0, 33.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_background_runs() {
+        let underlined =
+            CellBackground::new((1.0, 1.0, 1.0, 1.0), (0.0, 0.0, 0.0, 1.0), 1.0, 0.0, 0.0);
+        let cells = vec![
+            // row 0
+            cell(1.0),
+            cell(1.0),
+            cell(0.5),
+            underlined,
+            // row 1 is a single run; it doesn't join the end of row 0
+            cell(0.5),
+            cell(0.5),
+            cell(0.5),
+            cell(0.5),
+            // row 2 was never painted
+            CellBackground::default(),
+            CellBackground::default(),
+            CellBackground::default(),
+            cell(1.0),
+        ];
+        assert_eq!(
+            background_runs(&cells, 4),
+            vec![
+                (0, 0..2, cell(1.0)),
+                (0, 2..3, cell(0.5)),
+                (0, 3..4, underlined),
+                (1, 0..4, cell(0.5)),
+                (2, 3..4, cell(1.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn undecorated_cells_merge_regardless_of_fg() {
+        let bg = (1.0, 1.0, 1.0, 1.0);
+        let a = CellBackground::new(bg, (1.0, 0.0, 0.0, 1.0), 0.0, 0.0, 0.0);
+        let b = CellBackground::new(bg, (0.0, 0.0, 1.0, 1.0), 0.0, 0.0, 0.0);
+        assert_eq!(a, b);
+        assert_eq!(background_runs(&[a, b], 2), vec![(0, 0..2, a)]);
+    }
+
+    #[test]
+    fn decorations() {
+        // The descender is in 26.6 fixed point
+        assert_eq!(
+            decoration_rows(16.0, -4.0 * 64.0),
+            DecorationRows {
+                underline: 13.0,
+                double_underline: (12.0, 14.0),
+                strikethrough: 6.0,
+                overline: 0.0,
+            }
+        );
+
+        // The lines are kept within the cell
+        assert_eq!(
+            decoration_rows(15.5, -64.0),
+            DecorationRows {
+                underline: 15.0,
+                double_underline: (15.0, 15.0),
+                strikethrough: 7.0,
+                overline: 0.0,
+            }
+        );
+    }
+
+    #[test]
+    fn text_indices_skip_blank_cells() {
+        assert!(text_indices(&[false, false]).is_empty());
+        assert_eq!(
+            text_indices(&[false, true, false, true]),
+            vec![4, 5, 6, 5, 6, 7, 12, 13, 14, 13, 14, 15]
+        );
+    }
+
+    #[test]
+    fn glyph_cells_track_changes() {
+        let mut vertices = vec![Vertex::default(); 3 * VERTICES_PER_CELL];
+        let mut glyphs = vec![false; 3];
+        assert!(!update_glyph_cells(&mut glyphs, &vertices));
+
+        vertices[VERTICES_PER_CELL + V_BOT_RIGHT].tex = (0.5, 0.5);
+        assert!(update_glyph_cells(&mut glyphs, &vertices));
+        assert_eq!(glyphs, vec![false, true, false]);
+        assert!(!update_glyph_cells(&mut glyphs, &vertices));
+
+        // A row narrower than the window leaves the cells past it alone
+        let mut glyphs = vec![false, false, true];
+        assert!(update_glyph_cells(&mut glyphs[..2], &vertices[..8]));
+        assert_eq!(glyphs, vec![false, true, true]);
     }
 }
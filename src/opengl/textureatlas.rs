@@ -216,6 +216,17 @@ impl Sprite {
         (self.coords.bottom + self.coords.height) as f32 / self.texture.height() as f32
     }
 
+    /// Returns the texture coordinate of the point that is the fraction
+    /// `x` across and `y` down from the top left of the sprite
+    #[inline]
+    pub fn point(&self, x: f32, y: f32) -> (f32, f32) {
+        (
+            (self.coords.left as f32 + x * self.coords.width as f32) / self.texture.width() as f32,
+            (self.coords.bottom as f32 + y * self.coords.height as f32)
+                / self.texture.height() as f32,
+        )
+    }
+
     /// Returns the top-left coordinate for a slice in texture coordinate space
     #[inline]
     pub fn top_left(&self, slice: &SpriteSlice) -> (f32, f32) {
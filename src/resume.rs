@@ -0,0 +1,66 @@
+//! Notices when the system resumes from sleep, so that the windows,
+//! the tabs and the connections to mux servers can be brought up to
+//! date straight away rather than whenever they next happen to poll.
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often the watcher thread wakes up to compare the clocks
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How far the wall clock has to get ahead of the monotonic clock
+/// before we conclude that the system was asleep.  This is generous
+/// enough that a busy system that is slow to wake the thread, or a
+/// wall clock adjusted by ntp, isn't mistaken for a suspension.
+const SUSPEND_THRESHOLD: Duration = Duration::from_secs(5);
+
+/// Spawns a thread that calls `on_resume` each time that the system
+/// resumes from sleep.
+/// The monotonic clock doesn't advance while the system is suspended
+/// on Linux and macOS, while the wall clock does, so a jump in the
+/// difference between them reveals that the system was asleep.
+pub fn watch_for_resume<F>(on_resume: F)
+where
+    F: Fn() + Send + 'static,
+{
+    std::thread::spawn(move || {
+        let mut instant = Instant::now();
+        let mut wall = SystemTime::now();
+        loop {
+            std::thread::sleep(POLL_INTERVAL);
+            let now_instant = Instant::now();
+            let now_wall = SystemTime::now();
+            if was_suspended(
+                now_instant - instant,
+                now_wall.duration_since(wall).unwrap_or_default(),
+            ) {
+                log::info!("the system resumed from sleep");
+                on_resume();
+            }
+            instant = now_instant;
+            wall = now_wall;
+        }
+    });
+}
+
+/// Returns true if the wall clock advanced far enough beyond the
+/// monotonic clock that the system must have been asleep
+fn was_suspended(monotonic: Duration, wall: Duration) -> bool {
+    wall > monotonic + SUSPEND_THRESHOLD
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn suspension() {
+        let secs = Duration::from_secs;
+        assert!(!was_suspended(secs(2), secs(2)));
+        // The thread was woken late, but both clocks agree
+        assert!(!was_suspended(secs(20), secs(20)));
+        // A small correction of the wall clock
+        assert!(!was_suspended(secs(2), secs(4)));
+        assert!(was_suspended(secs(2), secs(3600)));
+        // The wall clock was set back
+        assert!(!was_suspended(secs(2), secs(0)));
+    }
+}
@@ -1,6 +1,9 @@
 #![allow(dead_code)]
-use crate::config::Config;
+use crate::config::{known_hosts_path, Config};
+use crate::frontend::gui_executor;
+use crate::mux::Mux;
 use crate::server::codec::*;
+use crate::server::knownhosts;
 use crate::server::listener::IdentitySource;
 use crate::server::UnixStream;
 use failure::{bail, err_msg, format_err, Fallible};
@@ -9,30 +12,212 @@ use native_tls::TlsConnector;
 use promise::{Future, Promise};
 use std::collections::HashMap;
 use std::convert::TryInto;
-use std::net::TcpStream;
+use std::net::{Shutdown, TcpStream};
 use std::path::Path;
-use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
 use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant};
 
-pub trait ReadAndWrite: std::io::Read + std::io::Write + Send {}
-impl ReadAndWrite for UnixStream {}
-impl ReadAndWrite for native_tls::TlsStream<std::net::TcpStream> {}
+pub trait ReadAndWrite: std::io::Read + std::io::Write + Send {
+    /// Set the timeout applied to blocking reads from the stream,
+    /// such as while waiting for the answer to a Hello.
+    fn set_read_timeout(&self, duration: Option<Duration>) -> std::io::Result<()>;
+
+    /// Returns another handle on the socket beneath the stream, which
+    /// a `ReadinessWatcher` uses to wait for data from the peer.
+    /// Unix domain sockets are returned as a TcpStream too: it is only
+    /// used to peek at the socket, to shut it down and to switch it in
+    /// and out of non-blocking mode, which work the same way for any
+    /// kind of stream socket.
+    fn try_clone_socket(&self) -> std::io::Result<TcpStream>;
+}
+impl ReadAndWrite for UnixStream {
+    fn set_read_timeout(&self, duration: Option<Duration>) -> std::io::Result<()> {
+        UnixStream::set_read_timeout(self, duration)
+    }
+
+    #[cfg(unix)]
+    fn try_clone_socket(&self) -> std::io::Result<TcpStream> {
+        use std::os::unix::io::{FromRawFd, IntoRawFd};
+        let fd = self.try_clone()?.into_raw_fd();
+        Ok(unsafe { TcpStream::from_raw_fd(fd) })
+    }
+
+    #[cfg(windows)]
+    fn try_clone_socket(&self) -> std::io::Result<TcpStream> {
+        use std::os::windows::io::{FromRawSocket, IntoRawSocket};
+        let socket = self.try_clone()?.into_raw_socket();
+        Ok(unsafe { TcpStream::from_raw_socket(socket) })
+    }
+}
+impl ReadAndWrite for native_tls::TlsStream<std::net::TcpStream> {
+    fn set_read_timeout(&self, duration: Option<Duration>) -> std::io::Result<()> {
+        self.get_ref().set_read_timeout(duration)
+    }
+
+    fn try_clone_socket(&self) -> std::io::Result<TcpStream> {
+        self.get_ref().try_clone()
+    }
+}
+
+/// Waits for data from the peer of a connection on a thread of its
+/// own, and sends a message through a channel when some arrives.  This
+/// lets the thread that owns the connection block on that channel for
+/// both the data and its other work, rather than polling the socket.
+/// TLS streams can't be read and written from different threads, so
+/// the watcher only peeks at the socket and leaves the reading to the
+/// owner, waiting to be rearmed until it has done so.
+pub struct ReadinessWatcher {
+    socket: TcpStream,
+    rearm: Sender<()>,
+}
+
+impl ReadinessWatcher {
+    /// Starts watching the socket of `stream`, sending `message()`
+    /// through `tx` when there is data to read
+    pub fn spawn<S, T, F>(stream: &S, tx: Sender<T>, message: F) -> Fallible<Self>
+    where
+        S: ReadAndWrite + ?Sized,
+        T: Send + 'static,
+        F: Fn() -> T + Send + 'static,
+    {
+        let socket = stream.try_clone_socket()?;
+        let watched = socket.try_clone()?;
+        let (rearm, rearmed) = channel();
+        thread::spawn(move || loop {
+            // Errors and the end of the stream are left for the owner
+            // to discover when it reads, after which there is nothing
+            // left to watch
+            let open = match watched.peek(&mut [0u8]) {
+                Ok(0) | Err(_) => false,
+                Ok(_) => true,
+            };
+            if tx.send(message()).is_err() || !open || rearmed.recv().is_err() {
+                break;
+            }
+        });
+        Ok(Self { socket, rearm })
+    }
+
+    /// Reads everything that is available from `stream` into
+    /// `read_buffer` without blocking, and then lets the watcher wait
+    /// for more.  Returns false if the peer closed the connection.
+    pub fn read_available<S: ReadAndWrite + ?Sized>(
+        &self,
+        stream: &mut S,
+        read_buffer: &mut Vec<u8>,
+    ) -> std::io::Result<bool> {
+        self.socket.set_nonblocking(true)?;
+        let result = read_nonblocking(stream, read_buffer);
+        self.socket.set_nonblocking(false)?;
+        self.rearm.send(()).ok();
+        result
+    }
+}
+
+impl Drop for ReadinessWatcher {
+    fn drop(&mut self) {
+        // Wake the watcher thread if it is still waiting on the socket
+        self.socket.shutdown(Shutdown::Both).ok();
+    }
+}
+
+/// Reads from `stream` until it would block.  Reading until then,
+/// rather than once, also drains whatever a TLS stream has decrypted
+/// but not yet returned, which peeking at the socket can't see.
+fn read_nonblocking<S: ReadAndWrite + ?Sized>(
+    stream: &mut S,
+    read_buffer: &mut Vec<u8>,
+) -> std::io::Result<bool> {
+    let mut buf = [0u8; 8192];
+    loop {
+        match stream.read(&mut buf) {
+            Ok(0) => return Ok(false),
+            Ok(size) => read_buffer.extend_from_slice(&buf[..size]),
+            Err(ref err) if err.kind() == std::io::ErrorKind::WouldBlock => return Ok(true),
+            Err(ref err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// A callback that is passed PDUs that the server sends without
+/// a corresponding request.
+pub type UnsolicitedHandler = Box<dyn FnMut(Pdu) + Send>;
+
+/// A callback that is invoked once the connection to the server has
+/// been re-established after it was lost.
+pub type ReconnectHandler = Box<dyn FnMut() + Send>;
+
+/// Opens a new connection to the server, used to re-establish the
+/// connection when it is lost.
+pub type Connector = Box<dyn Fn() -> Fallible<Box<dyn ReadAndWrite>> + Send>;
 
 enum ReaderMessage {
-    SendPdu { pdu: Pdu, promise: Promise<Pdu> },
+    SendPdu {
+        pdu: Pdu,
+        promise: Promise<Pdu>,
+    },
+    SetUnsolicitedHandler(UnsolicitedHandler),
+    SetReconnectHandler(ReconnectHandler),
+    Resumed,
+    /// The server sent something, which the ReadinessWatcher noticed
+    Readable,
+    /// The Client was dropped
+    Shutdown,
+}
+
+/// The callbacks registered by the owner of the client, which are
+/// kept across reconnections.
+#[derive(Default)]
+struct Handlers {
+    unsolicited: Option<UnsolicitedHandler>,
+    reconnected: Option<ReconnectHandler>,
+}
+
+/// A request that has been sent to the server but that has not
+/// yet been answered.
+struct PendingRequest {
+    promise: Promise<Pdu>,
+    deadline: Instant,
 }
 
+/// How long we wait before the first attempt to re-establish a lost
+/// connection.  The wait doubles after each failed attempt, up to
+/// MAX_RECONNECT_DELAY.
+const INITIAL_RECONNECT_DELAY: Duration = Duration::from_millis(250);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// Returns how long we are prepared to wait for the response to `pdu`.
+/// Spawning needs to start a new process on the server and so is
+/// granted more time than requests that are serviced by the mux alone.
+fn response_timeout(pdu: &Pdu) -> Duration {
+    match pdu {
+        Pdu::Spawn(_) => Duration::from_secs(60),
+        Pdu::Ping(_) | Pdu::Hello(_) => Duration::from_secs(5),
+        _ => Duration::from_secs(30),
+    }
+}
+
+/// Requests are queued for the client thread over a channel, so a
+/// Client can be shared without a lock.  It is meant to be used from
+/// the thread that created it, which is the gui thread for the
+/// ClientDomain, and is not Sync.
 pub struct Client {
     sender: Sender<ReaderMessage>,
+    name: String,
+    connected: Arc<AtomicBool>,
 }
 
 macro_rules! rpc {
     ($method_name:ident, $request_type:ident, $response_type:ident) => {
-        pub fn $method_name(&mut self, pdu: $request_type) -> Future<$response_type> {
+        pub fn $method_name(&self, pdu: $request_type) -> Future<$response_type> {
             self.send_pdu(Pdu::$request_type(pdu)).then(|result| {
             match result {
                 Ok(Pdu::$response_type(res)) => Ok(res),
+                Ok(Pdu::ErrorResponse(err)) => bail!("{}", err.reason),
                 Ok(_) => bail!("unexpected response {:?}", result),
                 Err(err) => Err(err),
             }
@@ -44,10 +229,11 @@ macro_rules! rpc {
     // in the case where the struct is empty and present only for the purpose
     // of typing the request.
     ($method_name:ident, $request_type:ident=(), $response_type:ident) => {
-        pub fn $method_name(&mut self) -> Future<$response_type> {
+        pub fn $method_name(&self) -> Future<$response_type> {
             self.send_pdu(Pdu::$request_type($request_type{})).then(|result| {
             match result {
                 Ok(Pdu::$response_type(res)) => Ok(res),
+                Ok(Pdu::ErrorResponse(err)) => bail!("{}", err.reason),
                 Ok(_) => bail!("unexpected response {:?}", result),
                 Err(err) => Err(err),
             }
@@ -56,81 +242,372 @@ macro_rules! rpc {
     };
 }
 
+/// Fail any requests whose deadline has passed
+fn expire_requests(pending: &mut HashMap<u64, PendingRequest>) {
+    let now = Instant::now();
+    let expired: Vec<u64> = pending
+        .iter()
+        .filter(|(_, request)| request.deadline <= now)
+        .map(|(serial, _)| *serial)
+        .collect();
+    for serial in expired {
+        if let Some(mut request) = pending.remove(&serial) {
+            request.promise.err(format_err!(
+                "timed out waiting for response to request {}",
+                serial
+            ));
+        }
+    }
+}
+
+/// Returns the earliest instant by which we need to hear from the
+/// server, if we are waiting for it at all
+fn next_deadline(
+    pending: &HashMap<u64, PendingRequest>,
+    liveness_ping: Option<(u64, Instant)>,
+) -> Option<Instant> {
+    pending
+        .values()
+        .map(|request| request.deadline)
+        .chain(liveness_ping.map(|(_, deadline)| deadline))
+        .min()
+}
+
 fn client_thread_inner(
     mut stream: Box<dyn ReadAndWrite>,
-    rx: Receiver<ReaderMessage>,
-    promises: &mut HashMap<u64, Promise<Pdu>>,
+    tx: &Sender<ReaderMessage>,
+    rx: &Receiver<ReaderMessage>,
+    pending: &mut HashMap<u64, PendingRequest>,
+    handlers: &mut Handlers,
+    max_frame_size: usize,
+    compression: &Compression,
 ) -> Fallible<()> {
-    let mut next_serial = 0u64;
+    // Returning Ok indicates that the Client was destroyed, while
+    // errors indicate that the connection was lost
+    let mut next_serial = UNSOLICITED_SERIAL + 1;
+    let mut read_buffer = vec![];
+    // The serial and deadline of the ping that checks that the
+    // connection survived a suspension of the system
+    let mut liveness_ping: Option<(u64, Instant)> = None;
+
+    stream.set_read_timeout(None)?;
+    let watcher = ReadinessWatcher::spawn(&*stream, tx.clone(), || ReaderMessage::Readable)?;
+
     loop {
-        let msg = if promises.is_empty() {
-            // If we don't have any results to read back, then we can and
-            // should block on an incoming request, otherwise we'll busy
-            // wait in this loop
-            match rx.recv() {
+        // Requests, data from the server and the wakeups for expiring
+        // requests all arrive through the channel
+        let msg = match next_deadline(pending, liveness_ping) {
+            None => match rx.recv() {
                 Ok(msg) => Some(msg),
-                Err(err) => bail!("Client was destroyed: {}", err),
-            }
-        } else {
-            match rx.try_recv() {
-                Ok(msg) => Some(msg),
-                Err(TryRecvError::Empty) => None,
-                Err(TryRecvError::Disconnected) => bail!("Client was destroyed"),
+                Err(_) => return Ok(()),
+            },
+            Some(deadline) => {
+                let now = Instant::now();
+                let timeout = if deadline > now {
+                    deadline - now
+                } else {
+                    Duration::from_secs(0)
+                };
+                match rx.recv_timeout(timeout) {
+                    Ok(msg) => Some(msg),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                }
             }
         };
-        if let Some(msg) = msg {
-            match msg {
-                ReaderMessage::SendPdu { pdu, promise } => {
-                    let serial = next_serial;
-                    next_serial += 1;
-                    promises.insert(serial, promise);
-
-                    pdu.encode(&mut stream, serial)?;
-                    stream.flush()?;
+        match msg {
+            Some(ReaderMessage::SendPdu { pdu, promise }) => {
+                let serial = next_serial;
+                next_serial += 1;
+                pending.insert(
+                    serial,
+                    PendingRequest {
+                        promise,
+                        deadline: Instant::now() + response_timeout(&pdu),
+                    },
+                );
+
+                pdu.encode_with_compression(&mut stream, serial, compression)?;
+                stream.flush()?;
+            }
+            Some(ReaderMessage::SetUnsolicitedHandler(handler)) => {
+                handlers.unsolicited = Some(handler);
+            }
+            Some(ReaderMessage::SetReconnectHandler(handler)) => {
+                handlers.reconnected = Some(handler);
+            }
+            Some(ReaderMessage::Resumed) => {
+                // A connection whose peer went away while we were
+                // asleep can take a long time to report an error,
+                // so ask the server to prove that it is still there
+                let ping = Pdu::Ping(Ping {});
+                let serial = next_serial;
+                next_serial += 1;
+                liveness_ping = Some((serial, Instant::now() + response_timeout(&ping)));
+                ping.encode_with_compression(&mut stream, serial, compression)?;
+                stream.flush()?;
+            }
+            Some(ReaderMessage::Readable) => {
+                if !watcher.read_available(&mut *stream, &mut read_buffer)? {
+                    bail!("server closed the connection");
+                }
+
+                // Responses may arrive in any order, so we match them up
+                // with their requests via the serial number
+                while let Some(decoded) = Pdu::stream_decode(&mut read_buffer, max_frame_size)? {
+                    if let Some(mut request) = pending.remove(&decoded.serial) {
+                        request.promise.result(Ok(decoded.pdu));
+                    } else if liveness_ping.map(|(serial, _)| serial) == Some(decoded.serial) {
+                        liveness_ping = None;
+                    } else if decoded.serial == UNSOLICITED_SERIAL {
+                        match handlers.unsolicited.as_mut() {
+                            Some(handler) => handler(decoded.pdu),
+                            None => log::debug!("ignoring unsolicited pdu {:?}", decoded.pdu),
+                        }
+                    } else {
+                        log::error!(
+                            "got serial {} without a corresponding request; it may have timed out",
+                            decoded.serial
+                        );
+                    }
                 }
             }
+            Some(ReaderMessage::Shutdown) => return Ok(()),
+            None => {}
         }
 
-        if !promises.is_empty() {
-            let decoded = Pdu::decode(&mut stream)?;
-            if let Some(mut promise) = promises.remove(&decoded.serial) {
-                promise.result(Ok(decoded.pdu));
-            } else {
-                log::error!(
-                    "got serial {} without a corresponding promise",
-                    decoded.serial
-                );
+        expire_requests(pending);
+        if let Some((_, deadline)) = liveness_ping {
+            if deadline <= Instant::now() {
+                bail!("the server didn't answer after the system resumed");
             }
         }
     }
 }
 
-fn client_thread(stream: Box<dyn ReadAndWrite>, rx: Receiver<ReaderMessage>) -> Fallible<()> {
-    let mut promises = HashMap::new();
+/// Opens a connection with `connect` and introduces ourselves to the
+/// server, which must happen before any other request will be accepted.
+/// This is done both for the first connection and when reconnecting,
+/// before the client thread takes the connection over.
+fn connect_and_handshake(
+    connect: &Connector,
+    name: &str,
+    auth_token: Option<String>,
+    max_frame_size: usize,
+) -> Fallible<Box<dyn ReadAndWrite>> {
+    let mut stream = connect()?;
+    handshake(&mut *stream, auth_token, max_frame_size)
+        .map_err(|e| format_err!("handshake with {} failed: {}", name, e))?;
+    Ok(stream)
+}
+
+fn handshake(
+    stream: &mut dyn ReadAndWrite,
+    auth_token: Option<String>,
+    max_frame_size: usize,
+) -> Fallible<()> {
+    let hello = Pdu::Hello(Hello {
+        protocol_version: PROTOCOL_VERSION,
+        auth_token,
+    });
+    hello.encode(&mut *stream, UNSOLICITED_SERIAL + 1)?;
+    stream.flush()?;
+
+    stream.set_read_timeout(Some(response_timeout(&hello)))?;
+    let mut read_buffer = vec![];
+    let mut buf = [0u8; 8192];
+    let decoded = loop {
+        if let Some(decoded) = Pdu::stream_decode(&mut read_buffer, max_frame_size)? {
+            break decoded;
+        }
+        match stream.read(&mut buf)? {
+            0 => bail!("server closed the connection"),
+            size => read_buffer.extend_from_slice(&buf[..size]),
+        }
+    };
+    match decoded.pdu {
+        Pdu::HelloResponse(response) if response.protocol_version == PROTOCOL_VERSION => Ok(()),
+        Pdu::HelloResponse(response) => bail!(
+            "server speaks protocol version {}, but we need {}",
+            response.protocol_version,
+            PROTOCOL_VERSION
+        ),
+        Pdu::ErrorResponse(err) => bail!("{}", err.reason),
+        pdu => bail!("unexpected response to hello: {:?}", pdu),
+    }
+}
 
-    let res = client_thread_inner(stream, rx, &mut promises);
+/// Waits for `delay` before the next attempt to reconnect.  Requests
+/// that are made in the meantime fail straight away, while handlers
+/// are kept for when the connection is back.  If the system resumes
+/// from sleep, the wait is cut short and `delay` starts over from
+/// INITIAL_RECONNECT_DELAY, since the network may well be back.
+/// Returns false if the Client has been destroyed.
+fn wait_to_reconnect(
+    rx: &Receiver<ReaderMessage>,
+    handlers: &mut Handlers,
+    delay: &mut Duration,
+) -> bool {
+    let deadline = Instant::now() + *delay;
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+        match rx.recv_timeout(deadline - now) {
+            Ok(ReaderMessage::SendPdu { mut promise, .. }) => {
+                promise.err(err_msg("not connected to the server"));
+            }
+            Ok(ReaderMessage::SetUnsolicitedHandler(handler)) => {
+                handlers.unsolicited = Some(handler);
+            }
+            Ok(ReaderMessage::SetReconnectHandler(handler)) => {
+                handlers.reconnected = Some(handler);
+            }
+            Ok(ReaderMessage::Resumed) => {
+                *delay = INITIAL_RECONNECT_DELAY;
+                return true;
+            }
+            // Left over from the connection that was lost
+            Ok(ReaderMessage::Readable) => {}
+            Ok(ReaderMessage::Shutdown) | Err(RecvTimeoutError::Disconnected) => return false,
+            Err(RecvTimeoutError::Timeout) => return true,
+        }
+    }
+}
 
-    // be sure to fail any extant promises: on macos at least, the
-    // rust condvar implementation doesn't wake any waiters when
-    // it is destroyed, which can lead to a deadlock on shutdown.
-    for promise in promises.values_mut() {
-        promise.err(err_msg("client thread ended"));
+/// Tells the user about the state of the connection
+fn notify_user(text: String) {
+    if let Some(executor) = gui_executor() {
+        Future::with_executor(executor, move || {
+            if let Some(mux) = Mux::get() {
+                mux.notify_user(&text);
+            }
+            Ok(())
+        });
     }
+}
+
+fn client_thread(
+    mut stream: Box<dyn ReadAndWrite>,
+    tx: Sender<ReaderMessage>,
+    rx: Receiver<ReaderMessage>,
+    connect: Connector,
+    auth_token: Option<String>,
+    name: String,
+    connected: Arc<AtomicBool>,
+    max_frame_size: usize,
+    compression: Compression,
+) -> Fallible<()> {
+    let mut pending = HashMap::new();
+    let mut handlers = Handlers::default();
+
+    loop {
+        let res = client_thread_inner(
+            stream,
+            &tx,
+            &rx,
+            &mut pending,
+            &mut handlers,
+            max_frame_size,
+            &compression,
+        );
+
+        // Clear the flag before failing the requests, so that whoever
+        // is waiting on them can tell that the tab itself is fine
+        if res.is_err() {
+            connected.store(false, Ordering::SeqCst);
+        }
+
+        // be sure to fail any extant promises: on macos at least, the
+        // rust condvar implementation doesn't wake any waiters when
+        // it is destroyed, which can lead to a deadlock on shutdown.
+        for (_, mut request) in pending.drain() {
+            request.promise.err(err_msg("client thread ended"));
+        }
+
+        let err = match res {
+            Ok(()) => return Ok(()),
+            Err(err) => err,
+        };
+        log::error!("lost the connection to {}: {}", name, err);
+        notify_user(format!("Lost the connection to {}; retrying", name));
+
+        let mut delay = INITIAL_RECONNECT_DELAY;
+        stream = loop {
+            if !wait_to_reconnect(&rx, &mut handlers, &mut delay) {
+                return Ok(());
+            }
+            match connect_and_handshake(&connect, &name, auth_token.clone(), max_frame_size) {
+                Ok(stream) => break stream,
+                Err(err) => {
+                    log::warn!("unable to reconnect to {}: {}", name, err);
+                    delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+                }
+            }
+        };
 
-    res
+        info!("reconnected to {}", name);
+        connected.store(true, Ordering::SeqCst);
+        notify_user(format!("Reconnected to {}", name));
+        if let Some(reconnected) = handlers.reconnected.as_mut() {
+            reconnected();
+        }
+    }
 }
 
 impl Client {
-    pub fn new(stream: Box<dyn ReadAndWrite>) -> Self {
+    /// Connects to the server with `connect` and starts talking to it.
+    /// Should the connection be lost, `connect` is used again to
+    /// re-establish it.
+    pub fn new(connect: Connector, name: String, config: &Config) -> Fallible<Self> {
+        let auth_token = config.mux_auth_token.clone();
+        let max_frame_size = config.mux_max_frame_size.unwrap_or(DEFAULT_MAX_FRAME_SIZE);
+        let stream = connect_and_handshake(&connect, &name, auth_token.clone(), max_frame_size)?;
         let (sender, receiver) = channel();
+        let connected = Arc::new(AtomicBool::new(true));
 
-        thread::spawn(move || {
-            if let Err(e) = client_thread(stream, receiver) {
-                log::error!("client thread ended: {}", e);
-            }
-        });
+        {
+            let connected = Arc::clone(&connected);
+            let name = name.clone();
+            let compression = Compression::new(config);
+            let sender = sender.clone();
+            thread::spawn(move || {
+                if let Err(e) = client_thread(
+                    stream,
+                    sender,
+                    receiver,
+                    connect,
+                    auth_token,
+                    name,
+                    Arc::clone(&connected),
+                    max_frame_size,
+                    compression,
+                ) {
+                    log::error!("client thread ended: {}", e);
+                }
+                connected.store(false, Ordering::SeqCst);
+            });
+        }
 
-        Self { sender }
+        Ok(Self {
+            sender,
+            name,
+            connected,
+        })
+    }
+
+    /// Returns a human readable description of the server that we
+    /// are connected to, such as `mux:hostname`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns a handle on the flag that tracks whether the connection
+    /// to the server is still alive.  The flag is cleared when the
+    /// client thread terminates.
+    pub fn connected_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.connected)
     }
 
     pub fn new_unix_domain(config: &Arc<Config>) -> Fallible<Self> {
@@ -141,8 +618,14 @@ impl Client {
                 .ok_or_else(|| err_msg("no mux_server_unix_domain_socket_path"))?,
         );
         info!("connect to {}", sock_path.display());
-        let stream = Box::new(UnixStream::connect(sock_path)?);
-        Ok(Self::new(stream))
+        let connect: Connector = {
+            let sock_path = sock_path.to_path_buf();
+            Box::new(move || {
+                let stream: Box<dyn ReadAndWrite> = Box::new(UnixStream::connect(&sock_path)?);
+                Ok(stream)
+            })
+        };
+        Self::new(connect, format!("mux:{}", sock_path.display()), config)
     }
 
     pub fn new_tls(config: &Arc<Config>) -> Fallible<Self> {
@@ -175,11 +658,36 @@ impl Client {
             )
             .build()?;
 
+        let connect: Connector = {
+            let remote_address = remote_address.clone();
+            let remote_host_name = remote_host_name.to_owned();
+            let trust_new_hosts = config.mux_client_trust_new_hosts.unwrap_or(true);
+            Box::new(move || {
+                let stream: Box<dyn ReadAndWrite> = Box::new(Self::connect_tls(
+                    &connector,
+                    &remote_address,
+                    &remote_host_name,
+                    trust_new_hosts,
+                )?);
+                Ok(stream)
+            })
+        };
+        Self::new(connect, format!("mux:{}", remote_host_name), config)
+    }
+
+    /// Opens a TLS connection to the server, checking its certificate
+    /// against the known hosts
+    fn connect_tls(
+        connector: &TlsConnector,
+        remote_address: &str,
+        remote_host_name: &str,
+        trust_new_hosts: bool,
+    ) -> Fallible<native_tls::TlsStream<TcpStream>> {
         let stream = TcpStream::connect(remote_address)
             .map_err(|e| format_err!("connecting to {}: {}", remote_address, e))?;
         stream.set_nodelay(true)?;
 
-        let stream = Box::new(connector.connect(remote_host_name, stream).map_err(|e| {
+        let stream = connector.connect(remote_host_name, stream).map_err(|e| {
             format_err!(
                 "TlsConnector for {} with host name {}: {} ({:?})",
                 remote_address,
@@ -187,11 +695,24 @@ impl Client {
                 e,
                 e
             )
-        })?);
-        Ok(Self::new(stream))
+        })?;
+
+        let cert = stream
+            .peer_certificate()?
+            .ok_or_else(|| format_err!("{} didn't present a certificate", remote_address))?;
+        knownhosts::verify(
+            &known_hosts_path()?,
+            remote_address,
+            &cert.to_der()?,
+            trust_new_hosts,
+        )?;
+        Ok(stream)
     }
 
-    pub fn send_pdu(&mut self, pdu: Pdu) -> Future<Pdu> {
+    /// Queue `pdu` to be sent to the server.  The returned future is
+    /// fulfilled on the client thread when the response arrives, so
+    /// any number of requests may be in flight at the same time.
+    pub fn send_pdu(&self, pdu: Pdu) -> Future<Pdu> {
         let mut promise = Promise::new();
         let future = promise.get_future().expect("future already taken!?");
         match self.sender.send(ReaderMessage::SendPdu { pdu, promise }) {
@@ -200,6 +721,31 @@ impl Client {
         }
     }
 
+    /// Register a callback to be invoked on the client thread for each
+    /// PDU that the server pushes to us without a corresponding request.
+    /// Any previously registered callback is replaced.
+    pub fn on_unsolicited<F: FnMut(Pdu) + Send + 'static>(&self, func: F) -> Fallible<()> {
+        self.sender
+            .send(ReaderMessage::SetUnsolicitedHandler(Box::new(func)))
+            .map_err(|err| format_err!("{}", err))
+    }
+
+    /// Register a callback to be invoked on the client thread each
+    /// time that the connection to the server is re-established, by
+    /// which point requests may be sent again.
+    pub fn on_reconnect<F: FnMut() + Send + 'static>(&self, func: F) -> Fallible<()> {
+        self.sender
+            .send(ReaderMessage::SetReconnectHandler(Box::new(func)))
+            .map_err(|err| format_err!("{}", err))
+    }
+
+    /// Tells the client thread that the system resumed from sleep, so
+    /// that it checks the connection straight away, or tries to
+    /// re-establish a lost connection without waiting any longer
+    pub fn system_resumed(&self) {
+        self.sender.send(ReaderMessage::Resumed).ok();
+    }
+
     rpc!(ping, Ping = (), Pong);
     rpc!(list_tabs, ListTabs = (), ListTabsResponse);
     rpc!(
@@ -207,10 +753,35 @@ impl Client {
         GetCoarseTabRenderableData,
         GetCoarseTabRenderableDataResponse
     );
+    rpc!(
+        get_tab_scrollback,
+        GetTabScrollback,
+        GetTabScrollbackResponse
+    );
     rpc!(spawn, Spawn, SpawnResponse);
     rpc!(write_to_tab, WriteToTab, UnitResponse);
+    rpc!(kill_tab, KillTab, UnitResponse);
     rpc!(send_paste, SendPaste, UnitResponse);
     rpc!(key_down, SendKeyDown, UnitResponse);
     rpc!(mouse_event, SendMouseEvent, SendMouseEventResponse);
+    rpc!(focus_changed, SendFocus, UnitResponse);
     rpc!(resize, Resize, UnitResponse);
+    rpc!(subscribe, Subscribe, UnitResponse);
+    rpc!(unsubscribe, Unsubscribe, UnitResponse);
+    rpc!(ack_tab_delta, AckTabDelta, UnitResponse);
+    rpc!(copy_mode, CopyMode, CopyModeResponse);
+    rpc!(set_color_scheme, SetColorScheme, UnitResponse);
+    rpc!(
+        hyperlink_near_cursor,
+        GetHyperlinkNearCursor,
+        GetHyperlinkNearCursorResponse
+    );
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        // The client thread holds a sender of its own, so the channel
+        // doesn't disconnect when we go away
+        self.sender.send(ReaderMessage::Shutdown).ok();
+    }
 }
@@ -8,19 +8,19 @@
 //! client and server instances that are built from different versions
 //! of this code; in this way the client and server can more gracefully
 //! manage unknown enum variants.
-#![allow(dead_code)]
 
+use crate::config::Config;
 use crate::mux::domain::DomainId;
 use crate::mux::tab::TabId;
 use crate::mux::window::WindowId;
-use failure::{bail, Error};
+use crate::server::wire::{WireCursorPosition, WireHyperlink, WireLine};
+use failure::{bail, Error, Fallible};
+use failure_derive::*;
 use leb128;
 use log::debug;
 use portable_pty::{CommandBuilder, PtySize};
 use serde_derive::*;
-use std::sync::Arc;
-use term::{CursorPosition, Line};
-use termwiz::hyperlink::Hyperlink;
+use std::collections::HashMap;
 use varbincode;
 
 /// Returns the encoded length of the leb128 representation of value
@@ -73,23 +73,73 @@ fn encode_raw<W: std::io::Write>(
     w.write_all(&buffer)
 }
 
+/// The default upper bound on the length of a single frame.
+/// This can be overridden via the `mux_max_frame_size` config option.
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 64 * 1024 * 1024;
+
+/// The upper bound on the length of the frames that the server accepts
+/// from a client before it has said Hello, which is ample for a Hello
+/// but keeps unauthenticated peers from making the server buffer much.
+pub const HELLO_MAX_FRAME_SIZE: usize = 64 * 1024;
+
+/// Describes a frame whose header cannot be valid.  Since the frame
+/// length is untrustworthy, the stream cannot be resynchronized after
+/// one of these has been encountered.
+#[derive(Debug, Fail)]
+pub enum CorruptFrame {
+    #[fail(
+        display = "frame length {} exceeds the maximum of {} bytes",
+        len, max_frame_size
+    )]
+    TooLarge {
+        serial: u64,
+        len: u64,
+        max_frame_size: usize,
+    },
+    #[fail(
+        display = "frame length {} is smaller than its {} byte header",
+        len, header_len
+    )]
+    TooSmall {
+        serial: u64,
+        len: u64,
+        header_len: usize,
+    },
+}
+
+impl CorruptFrame {
+    /// Returns the serial number that was parsed from the frame header
+    pub fn serial(&self) -> u64 {
+        match self {
+            CorruptFrame::TooLarge { serial, .. } | CorruptFrame::TooSmall { serial, .. } => {
+                *serial
+            }
+        }
+    }
+}
+
 /// Read a single leb128 encoded value from the stream
 fn read_u64<R: std::io::Read>(mut r: R) -> Result<u64, std::io::Error> {
-    leb128::read::unsigned(&mut r)
-        .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, format!("{}", err)))
+    leb128::read::unsigned(&mut r).map_err(|err| match err {
+        leb128::read::Error::IoError(err) => err,
+        err => std::io::Error::new(std::io::ErrorKind::Other, format!("{}", err)),
+    })
 }
 
 #[derive(Debug)]
-struct Decoded {
+struct FrameHeader {
     ident: u64,
     serial: u64,
-    data: Vec<u8>,
+    /// The length of the data that follows the header
+    data_len: usize,
     is_compressed: bool,
 }
 
-/// Decode a frame.
+/// Decode the header of a frame.
 /// See encode_raw() for the frame format.
-fn decode_raw<R: std::io::Read>(mut r: R) -> Result<Decoded, std::io::Error> {
+/// Frames whose length exceeds max_frame_size are rejected here,
+/// before any buffer is allocated for their data.
+fn decode_header<R: std::io::Read>(mut r: R, max_frame_size: usize) -> Result<FrameHeader, Error> {
     let len = read_u64(r.by_ref())?;
     let (len, is_compressed) = if (len & COMPRESSED_MASK) != 0 {
         (len & !COMPRESSED_MASK, true)
@@ -98,17 +148,59 @@ fn decode_raw<R: std::io::Read>(mut r: R) -> Result<Decoded, std::io::Error> {
     };
     let serial = read_u64(r.by_ref())?;
     let ident = read_u64(r.by_ref())?;
-    let data_len = len as usize - (encoded_length(ident) + encoded_length(serial));
-    let mut data = vec![0u8; data_len];
-    r.read_exact(&mut data)?;
-    Ok(Decoded {
+
+    if len > max_frame_size as u64 {
+        return Err(CorruptFrame::TooLarge {
+            serial,
+            len,
+            max_frame_size,
+        }
+        .into());
+    }
+    let header_len = encoded_length(ident) + encoded_length(serial);
+    let data_len =
+        (len as usize)
+            .checked_sub(header_len)
+            .ok_or_else(|| CorruptFrame::TooSmall {
+                serial,
+                len,
+                header_len,
+            })?;
+
+    Ok(FrameHeader {
         ident,
         serial,
-        data,
+        data_len,
         is_compressed,
     })
 }
 
+#[cfg(test)]
+#[derive(Debug)]
+struct Decoded {
+    ident: u64,
+    serial: u64,
+    data: Vec<u8>,
+}
+
+/// Decode a complete frame from `r`
+#[cfg(test)]
+fn decode_raw<R: std::io::Read>(mut r: R, max_frame_size: usize) -> Result<Decoded, Error> {
+    let header = decode_header(r.by_ref(), max_frame_size)?;
+    let mut data = vec![0u8; header.data_len];
+    r.read_exact(&mut data)?;
+    Ok(Decoded {
+        ident: header.ident,
+        serial: header.serial,
+        data,
+    })
+}
+
+/// The serial number used for PDUs that the server sends of its own
+/// accord, rather than in response to a request.  Clients allocate
+/// request serials starting from 1 so that these cannot collide.
+pub const UNSOLICITED_SERIAL: u64 = 0;
+
 #[derive(Debug, PartialEq)]
 pub struct DecodedPdu {
     pub serial: u64,
@@ -116,19 +208,105 @@ pub struct DecodedPdu {
 }
 
 /// If the serialized size is larger than this, then we'll consider compressing it
-const COMPRESS_THRESH: usize = 32;
+pub const DEFAULT_COMPRESS_THRESH: usize = 32;
+
+/// Controls whether a particular type of PDU is compressed
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionPolicy {
+    /// Never compress; appropriate for small, latency sensitive PDUs
+    /// such as keystrokes
+    Never,
+    /// Compress if the serialized size exceeds the threshold
+    Auto,
+    /// Compress regardless of the serialized size
+    Always,
+}
+
+/// Tunables that trade CPU time for bandwidth when encoding PDUs.
+/// Regardless of the policy, the compressed form is only sent if it
+/// is smaller than the uncompressed form.
+#[derive(Debug, Clone)]
+pub struct Compression {
+    pub threshold: usize,
+    pub level: i32,
+    /// Overrides the policy for PDUs, keyed by PDU name
+    overrides: HashMap<String, CompressionPolicy>,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self {
+            threshold: DEFAULT_COMPRESS_THRESH,
+            level: zstd::DEFAULT_COMPRESSION_LEVEL,
+            overrides: HashMap::new(),
+        }
+    }
+}
+
+impl Compression {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            threshold: config
+                .mux_compression_threshold
+                .unwrap_or(DEFAULT_COMPRESS_THRESH),
+            level: config
+                .mux_compression_level
+                .unwrap_or(zstd::DEFAULT_COMPRESSION_LEVEL),
+            overrides: config.mux_compression_policy.clone().unwrap_or_default(),
+        }
+    }
+
+    /// Checks that the overrides in mux_compression_policy are all
+    /// named after PDUs, so that a typo doesn't go unnoticed
+    pub fn validate_policy(overrides: &HashMap<String, CompressionPolicy>) -> Fallible<()> {
+        for name in overrides.keys() {
+            if !Pdu::NAMES.contains(&name.as_str()) {
+                bail!(
+                    "mux_compression_policy names `{}`, which is not a PDU; the PDUs are {:?}",
+                    name,
+                    Pdu::NAMES
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the policy for the PDU with the specified name
+    pub fn policy(&self, pdu_name: &str) -> CompressionPolicy {
+        if let Some(policy) = self.overrides.get(pdu_name) {
+            return *policy;
+        }
+        match pdu_name {
+            "SendKeyDown" | "SendMouseEvent" | "SendFocus" | "WriteToTab" | "CopyMode"
+            | "AckTabDelta" => CompressionPolicy::Never,
+            "GetCoarseTabRenderableDataResponse" | "GetTabScrollbackResponse" => {
+                CompressionPolicy::Always
+            }
+            _ => CompressionPolicy::Auto,
+        }
+    }
+}
 
-fn serialize<T: serde::Serialize>(t: &T) -> Result<(Vec<u8>, bool), Error> {
+fn serialize<T: serde::Serialize>(
+    t: &T,
+    policy: CompressionPolicy,
+    compression: &Compression,
+) -> Result<(Vec<u8>, bool), Error> {
     let mut uncompressed = Vec::new();
     let mut encode = varbincode::Serializer::new(&mut uncompressed);
     t.serialize(&mut encode)?;
 
-    if uncompressed.len() <= COMPRESS_THRESH {
+    let try_compress = match policy {
+        CompressionPolicy::Never => false,
+        CompressionPolicy::Auto => uncompressed.len() > compression.threshold,
+        CompressionPolicy::Always => true,
+    };
+    if !try_compress {
         return Ok((uncompressed, false));
     }
     // It's a little heavy; let's try compressing it
     let mut compressed = Vec::new();
-    let mut compress = zstd::Encoder::new(&mut compressed, zstd::DEFAULT_COMPRESSION_LEVEL)?;
+    let mut compress = zstd::Encoder::new(&mut compressed, compression.level)?;
     let mut encode = varbincode::Serializer::new(&mut compress);
     t.serialize(&mut encode)?;
     drop(encode);
@@ -150,9 +328,12 @@ fn serialize<T: serde::Serialize>(t: &T) -> Result<(Vec<u8>, bool), Error> {
 fn deserialize<T: serde::de::DeserializeOwned, R: std::io::Read>(
     mut r: R,
     is_compressed: bool,
+    max_frame_size: usize,
 ) -> Result<T, Error> {
     if is_compressed {
-        let mut decompress = zstd::Decoder::new(r)?;
+        // Bound the decompressed size as well, so that a small frame
+        // cannot expand into an arbitrarily large allocation
+        let mut decompress = std::io::Read::take(zstd::Decoder::new(r)?, max_frame_size as u64);
         let mut decode = varbincode::Deserializer::new(&mut decompress);
         serde::Deserialize::deserialize(&mut decode).map_err(Into::into)
     } else {
@@ -172,12 +353,32 @@ macro_rules! pdu {
         }
 
         impl Pdu {
+            /// The names of the PDU types, which are the keys of
+            /// mux_compression_policy
+            pub const NAMES: &'static [&'static str] = &[$(stringify!($name),)*];
+
+            /// Returns the name of the PDU type, as used to configure
+            /// its compression policy
+            pub fn pdu_name(&self) -> &'static str {
+                match self {
+                    Pdu::Invalid{..} => "Invalid",
+                    $(
+                        Pdu::$name(_) => stringify!($name),
+                    )*
+                }
+            }
+
             pub fn encode<W: std::io::Write>(&self, w: W, serial: u64) -> Result<(), Error> {
+                self.encode_with_compression(w, serial, &Compression::default())
+            }
+
+            pub fn encode_with_compression<W: std::io::Write>(&self, w: W, serial: u64, compression: &Compression) -> Result<(), Error> {
                 match self {
                     Pdu::Invalid{..} => bail!("attempted to serialize Pdu::Invalid"),
                     $(
                         Pdu::$name(s) => {
-                            let (data, is_compressed) = serialize(s)?;
+                            let policy = compression.policy(self.pdu_name());
+                            let (data, is_compressed) = serialize(s, policy, compression)?;
                             encode_raw($vers, serial, &data, is_compressed, w)?;
                             Ok(())
                         }
@@ -185,23 +386,59 @@ macro_rules! pdu {
                 }
             }
 
-            pub fn decode<R: std::io::Read>(r:R) -> Result<DecodedPdu, Error> {
-                let decoded = decode_raw(r)?;
-                match decoded.ident {
+            #[cfg(test)]
+            pub fn decode<R: std::io::Read>(mut r:R) -> Result<DecodedPdu, Error> {
+                let header = decode_header(r.by_ref(), DEFAULT_MAX_FRAME_SIZE)?;
+                let mut data = vec![0u8; header.data_len];
+                r.read_exact(&mut data)?;
+                Self::from_raw(&header, &data, DEFAULT_MAX_FRAME_SIZE)
+            }
+
+            fn from_raw(header: &FrameHeader, data: &[u8], max_frame_size: usize) -> Result<DecodedPdu, Error> {
+                match header.ident {
                     $(
                         $vers => {
                             Ok(DecodedPdu {
-                                serial: decoded.serial,
-                                pdu: Pdu::$name(deserialize(decoded.data.as_slice(), decoded.is_compressed)?)
+                                serial: header.serial,
+                                pdu: Pdu::$name(deserialize(data, header.is_compressed, max_frame_size)?)
                             })
                         }
                     ,)*
                     _ => Ok(DecodedPdu {
-                        serial: decoded.serial,
-                        pdu: Pdu::Invalid{ident:decoded.ident}
+                        serial: header.serial,
+                        pdu: Pdu::Invalid{ident:header.ident}
                     }),
                 }
             }
+
+            /// Attempt to decode a single frame from the start of `buffer`.
+            /// If the buffer holds a complete frame, it is removed from the
+            /// buffer and returned.  If the buffer holds only a partial
+            /// frame, returns None and leaves the buffer untouched so that
+            /// more data can be appended to it.  Nothing is allocated for
+            /// the frame until all of it has arrived.
+            pub fn stream_decode(buffer: &mut Vec<u8>, max_frame_size: usize) -> Result<Option<DecodedPdu>, Error> {
+                let mut cursor = std::io::Cursor::new(buffer.as_slice());
+                let header = match decode_header(&mut cursor, max_frame_size) {
+                    Ok(header) => header,
+                    Err(err) => {
+                        if let Some(ioerr) = err.downcast_ref::<std::io::Error>() {
+                            if ioerr.kind() == std::io::ErrorKind::UnexpectedEof {
+                                return Ok(None);
+                            }
+                        }
+                        return Err(err);
+                    }
+                };
+                let start = cursor.position() as usize;
+                let end = start + header.data_len;
+                if buffer.len() < end {
+                    return Ok(None);
+                }
+                let decoded = Self::from_raw(&header, &buffer[start..end], max_frame_size);
+                buffer.drain(0..end);
+                decoded.map(Some)
+            }
         }
     }
 }
@@ -217,7 +454,6 @@ pdu! {
     ListTabs: 3,
     ListTabsResponse: 4,
     GetCoarseTabRenderableData: 5,
-    GetCoarseTabRenderableDataResponse: 6,
     Spawn: 7,
     SpawnResponse: 8,
     WriteToTab: 9,
@@ -227,8 +463,31 @@ pdu! {
     SendPaste: 13,
     Resize: 14,
     SendMouseEventResponse: 17,
+    GetCoarseTabRenderableDataResponse: 18,
+    Subscribe: 19,
+    PushTabDelta: 20,
+    PushTabRemoved: 21,
+    CopyMode: 22,
+    CopyModeResponse: 23,
+    GetHyperlinkNearCursor: 24,
+    GetHyperlinkNearCursorResponse: 25,
+    Hello: 26,
+    HelloResponse: 27,
+    SetClipboard: 28,
+    SetColorScheme: 29,
+    PushColorScheme: 30,
+    KillTab: 31,
+    GetTabScrollback: 32,
+    GetTabScrollbackResponse: 33,
+    AckTabDelta: 34,
+    Unsubscribe: 35,
+    SendFocus: 36,
 }
 
+/// The version of the protocol spoken by this build.  It is bumped
+/// whenever a change is made that older peers cannot understand.
+pub const PROTOCOL_VERSION: u32 = 14;
+
 #[derive(Deserialize, Serialize, PartialEq, Debug)]
 pub struct UnitResponse {}
 
@@ -250,6 +509,8 @@ pub struct WindowAndTabEntry {
     pub window_id: WindowId,
     pub tab_id: TabId,
     pub title: String,
+    /// The directory that the shell in the tab reports being in
+    pub cwd: Option<String>,
 }
 
 #[derive(Deserialize, Serialize, PartialEq, Debug)]
@@ -270,27 +531,125 @@ pub struct GetCoarseTabRenderableData {
 #[derive(Deserialize, Serialize, PartialEq, Debug)]
 pub struct DirtyLine {
     pub line_idx: usize,
-    pub line: Line,
+    pub line: WireLine,
     pub selection_col_from: usize,
     pub selection_col_to: usize,
 }
 
 #[derive(Deserialize, Serialize, PartialEq, Debug)]
 pub struct GetCoarseTabRenderableDataResponse {
-    pub cursor_position: CursorPosition,
+    pub cursor_position: WireCursorPosition,
     pub physical_rows: usize,
     pub physical_cols: usize,
-    pub current_highlight: Option<Arc<Hyperlink>>,
+    pub current_highlight: Option<WireHyperlink>,
     pub dirty_lines: Vec<DirtyLine>,
     pub title: String,
+    /// The directory that the program in the tab reports being in
+    pub cwd: Option<String>,
+    /// Whether the program in the tab asked to be sent mouse events
+    pub mouse_grabbed: bool,
+    /// The number of lines of scrollback above the screen, which can
+    /// be fetched with GetTabScrollback
+    pub scrollback_rows: usize,
+}
+
+/// Fetches the `rows` lines that the tab shows when its viewport is
+/// scrolled back by `viewport_offset` rows from the bottom
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct GetTabScrollback {
+    pub tab_id: TabId,
+    pub viewport_offset: usize,
+    pub rows: usize,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct GetTabScrollbackResponse {
+    pub scrollback_rows: usize,
+    pub lines: Vec<WireLine>,
+}
+
+impl GetCoarseTabRenderableDataResponse {
+    /// The amount that a pushed delta counts against the flow control
+    /// window: the number of lines that it holds, or one for a delta
+    /// that only moves the cursor
+    pub fn flow_control_cost(&self) -> usize {
+        self.dirty_lines.len().max(1)
+    }
+}
+
+/// Asks the server to push a PushTabDelta whenever the content of
+/// the tab changes, rather than requiring the client to poll for it.
+/// The pushed PDUs use UNSOLICITED_SERIAL.  Once the deltas that the
+/// client hasn't acknowledged with AckTabDelta add up to the flow
+/// control window, the server waits before pushing any more.
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct Subscribe {
+    pub tab_id: TabId,
+}
+
+/// Asks the server to stop pushing changes to the tab, such as when
+/// the client detaches from it
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct Unsubscribe {
+    pub tab_id: TabId,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct PushTabDelta {
+    pub tab_id: TabId,
+    pub delta: GetCoarseTabRenderableDataResponse,
+}
+
+/// Acknowledges that the client has applied pushed deltas whose
+/// `flow_control_cost` adds up to `cost`
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct AckTabDelta {
+    pub tab_id: TabId,
+    pub cost: usize,
+}
+
+/// Informs a subscribed client that the tab no longer exists
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct PushTabRemoved {
+    pub tab_id: TabId,
+}
+
+/// Informs a subscribed client that a program running in the tab
+/// used OSC 52 to set the clipboard.  None clears the clipboard.
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct SetClipboard {
+    pub tab_id: TabId,
+    pub clipboard: Option<String>,
+}
+
+/// Switches to the named color scheme.  When `tab_id` is given only
+/// that tab is switched, otherwise when `window_id` is given the tabs
+/// of that window are switched, otherwise every window is switched.
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct SetColorScheme {
+    pub window_id: Option<WindowId>,
+    pub tab_id: Option<TabId>,
+    pub name: String,
+}
+
+/// Informs a subscribed client that the tab was switched to the
+/// named color scheme
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct PushColorScheme {
+    pub tab_id: TabId,
+    pub name: String,
 }
 
 #[derive(Deserialize, Serialize, PartialEq, Debug)]
 pub struct Spawn {
-    pub domain_id: DomainId,
+    /// If None, spawn into the default domain of the server
+    pub domain_id: Option<DomainId>,
     /// If None, create a new window for this new tab
     pub window_id: Option<WindowId>,
     pub command: Option<CommandBuilder>,
+    /// The directory to start the program in.  This applies to the
+    /// default program of the server when `command` is None.
+    pub cwd: Option<String>,
     pub size: PtySize,
 }
 
@@ -300,6 +659,12 @@ pub struct SpawnResponse {
     pub window_id: WindowId,
 }
 
+/// Removes the tab from the server, killing the program running in it
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct KillTab {
+    pub tab_id: TabId,
+}
+
 #[derive(Deserialize, Serialize, PartialEq, Debug)]
 pub struct WriteToTab {
     pub tab_id: TabId,
@@ -324,6 +689,14 @@ pub struct SendMouseEvent {
     pub event: term::input::MouseEvent,
 }
 
+/// Tells the server that the window showing the tab gained or lost
+/// the keyboard focus
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct SendFocus {
+    pub tab_id: TabId,
+    pub focused: bool,
+}
+
 #[derive(Deserialize, Serialize, PartialEq, Debug)]
 pub struct SendMouseEventResponse {
     pub clipboard: Option<String>,
@@ -335,6 +708,44 @@ pub struct Resize {
     pub size: PtySize,
 }
 
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct CopyMode {
+    pub tab_id: TabId,
+    pub action: term::CopyModeAction,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct CopyModeResponse {
+    /// The selected text, if the action completed a copy
+    pub clipboard: Option<String>,
+    /// Whether the tab remains in copy mode
+    pub active: bool,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct GetHyperlinkNearCursor {
+    pub tab_id: TabId,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct GetHyperlinkNearCursorResponse {
+    pub link: Option<WireHyperlink>,
+}
+
+/// The first PDU that a client sends after connecting.  The server
+/// won't process any other request until it has accepted this one.
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct Hello {
+    pub protocol_version: u32,
+    /// Must match the mux_auth_token configured on the server
+    pub auth_token: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct HelloResponse {
+    pub protocol_version: u32,
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -344,7 +755,7 @@ mod test {
         let mut encoded = Vec::new();
         encode_raw(0x81, 0x42, b"hello", false, &mut encoded).unwrap();
         assert_eq!(&encoded, b"\x08\x42\x81\x01hello");
-        let decoded = decode_raw(encoded.as_slice()).unwrap();
+        let decoded = decode_raw(encoded.as_slice(), DEFAULT_MAX_FRAME_SIZE).unwrap();
         assert_eq!(decoded.ident, 0x81);
         assert_eq!(decoded.serial, 0x42);
         assert_eq!(decoded.data, b"hello");
@@ -358,7 +769,7 @@ mod test {
             payload.resize(*target_len, b'a');
             let mut encoded = Vec::new();
             encode_raw(0x42, serial, payload.as_slice(), false, &mut encoded).unwrap();
-            let decoded = decode_raw(encoded.as_slice()).unwrap();
+            let decoded = decode_raw(encoded.as_slice(), DEFAULT_MAX_FRAME_SIZE).unwrap();
             assert_eq!(decoded.ident, 0x42);
             assert_eq!(decoded.serial, serial);
             assert_eq!(decoded.data, payload);
@@ -412,6 +823,25 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_pdu_hello() {
+        let hello = || {
+            Pdu::Hello(Hello {
+                protocol_version: PROTOCOL_VERSION,
+                auth_token: Some("sekrit".to_owned()),
+            })
+        };
+        let mut encoded = Vec::new();
+        hello().encode(&mut encoded, 0x43).unwrap();
+        assert_eq!(
+            DecodedPdu {
+                serial: 0x43,
+                pdu: hello(),
+            },
+            Pdu::decode(encoded.as_slice()).unwrap()
+        );
+    }
+
     #[test]
     fn test_bogus_pdu() {
         let mut encoded = Vec::new();
@@ -424,4 +854,172 @@ mod test {
             Pdu::decode(encoded.as_slice()).unwrap()
         );
     }
+
+    #[test]
+    fn test_frame_too_large() {
+        let mut encoded = Vec::new();
+        encode_raw(0x42, 0x10, &[b'a'; 64], false, &mut encoded).unwrap();
+        let err = decode_raw(encoded.as_slice(), 32).unwrap_err();
+        match err.downcast_ref::<CorruptFrame>() {
+            Some(CorruptFrame::TooLarge {
+                serial: 0x10,
+                len: 66,
+                max_frame_size: 32,
+            }) => {}
+            _ => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn test_frame_too_small() {
+        // A length of 1 cannot hold both the serial and ident
+        let encoded = b"\x01\x10\x42";
+        let err = decode_raw(&encoded[..], DEFAULT_MAX_FRAME_SIZE).unwrap_err();
+        match err.downcast_ref::<CorruptFrame>() {
+            Some(CorruptFrame::TooSmall {
+                serial: 0x10,
+                len: 1,
+                header_len: 2,
+            }) => {}
+            _ => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn test_stream_decode_partial_large_frame() {
+        let payload = vec![b'a'; 1024 * 1024];
+        let mut encoded = Vec::new();
+        encode_raw(0x42, 0x10, &payload, false, &mut encoded).unwrap();
+
+        // A frame that has only partly arrived is left in the buffer
+        let mut buffer = encoded[..4096].to_vec();
+        assert!(Pdu::stream_decode(&mut buffer, DEFAULT_MAX_FRAME_SIZE)
+            .unwrap()
+            .is_none());
+        assert_eq!(buffer, &encoded[..4096]);
+
+        buffer.extend_from_slice(&encoded[4096..]);
+        let decoded = Pdu::stream_decode(&mut buffer, DEFAULT_MAX_FRAME_SIZE)
+            .unwrap()
+            .unwrap();
+        assert_eq!(decoded.serial, 0x10);
+        assert!(buffer.is_empty());
+
+        // Before Hello, the same frame is refused from its header alone
+        let mut buffer = encoded[..16].to_vec();
+        let err = Pdu::stream_decode(&mut buffer, HELLO_MAX_FRAME_SIZE).unwrap_err();
+        match err.downcast_ref::<CorruptFrame>() {
+            Some(CorruptFrame::TooLarge { serial: 0x10, .. }) => {}
+            _ => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn test_stream_decode() {
+        let mut encoded = Vec::new();
+        Pdu::Ping(Ping {}).encode(&mut encoded, 0x40).unwrap();
+        Pdu::Pong(Pong {}).encode(&mut encoded, 0x41).unwrap();
+
+        // Feed the bytes in one at a time; nothing should be decoded
+        // until a complete frame has been accumulated
+        let mut buffer = Vec::new();
+        let mut decoded = vec![];
+        for b in encoded {
+            buffer.push(b);
+            while let Some(pdu) = Pdu::stream_decode(&mut buffer, DEFAULT_MAX_FRAME_SIZE).unwrap() {
+                decoded.push(pdu);
+            }
+        }
+        assert!(buffer.is_empty());
+        assert_eq!(
+            decoded,
+            vec![
+                DecodedPdu {
+                    serial: 0x40,
+                    pdu: Pdu::Ping(Ping {})
+                },
+                DecodedPdu {
+                    serial: 0x41,
+                    pdu: Pdu::Pong(Pong {})
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compression_policy() {
+        let pdu = Pdu::WriteToTab(WriteToTab {
+            tab_id: 0,
+            data: vec![b'a'; 1024],
+        });
+        // Keystroke data is never compressed by default
+        let mut never = Vec::new();
+        pdu.encode(&mut never, 1).unwrap();
+
+        let mut config = Config::default_config();
+        let mut policy = HashMap::new();
+        policy.insert("WriteToTab".to_string(), CompressionPolicy::Auto);
+        config.mux_compression_policy = Some(policy);
+        let mut auto = Vec::new();
+        pdu.encode_with_compression(&mut auto, 1, &Compression::new(&config))
+            .unwrap();
+
+        assert!(auto.len() < never.len());
+        assert_eq!(Pdu::decode(auto.as_slice()).unwrap().pdu, pdu);
+        assert_eq!(Pdu::decode(never.as_slice()).unwrap().pdu, pdu);
+
+        assert!(
+            Compression::validate_policy(config.mux_compression_policy.as_ref().unwrap()).is_ok()
+        );
+        let mut typo = HashMap::new();
+        typo.insert("WriteTab".to_string(), CompressionPolicy::Never);
+        assert!(Compression::validate_policy(&typo).is_err());
+    }
+
+    #[test]
+    fn test_push_tab_removed() {
+        let mut encoded = Vec::new();
+        Pdu::PushTabRemoved(PushTabRemoved { tab_id: 3 })
+            .encode(&mut encoded, UNSOLICITED_SERIAL)
+            .unwrap();
+        assert_eq!(
+            DecodedPdu {
+                serial: UNSOLICITED_SERIAL,
+                pdu: Pdu::PushTabRemoved(PushTabRemoved { tab_id: 3 })
+            },
+            Pdu::decode(encoded.as_slice()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_set_clipboard() {
+        let mut encoded = Vec::new();
+        let pdu = Pdu::SetClipboard(SetClipboard {
+            tab_id: 2,
+            clipboard: Some("hello".to_owned()),
+        });
+        pdu.encode(&mut encoded, UNSOLICITED_SERIAL).unwrap();
+        assert_eq!(
+            DecodedPdu {
+                serial: UNSOLICITED_SERIAL,
+                pdu,
+            },
+            Pdu::decode(encoded.as_slice()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_set_color_scheme() {
+        let mut encoded = Vec::new();
+        let pdu = Pdu::SetColorScheme(SetColorScheme {
+            window_id: Some(1),
+            tab_id: None,
+            name: "light".to_owned(),
+        });
+        pdu.encode(&mut encoded, 0x41).unwrap();
+        assert_eq!(
+            DecodedPdu { serial: 0x41, pdu },
+            Pdu::decode(encoded.as_slice()).unwrap()
+        );
+    }
 }
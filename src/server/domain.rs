@@ -1,23 +1,29 @@
 use crate::font::{FontConfiguration, FontSystemSelection};
-use crate::frontend::front_end;
-use crate::mux::domain::{alloc_domain_id, Domain, DomainId};
+use crate::frontend::{front_end, gui_executor};
+use crate::mux::domain::{alloc_domain_id, Domain, DomainId, DomainState};
 use crate::mux::tab::Tab;
 use crate::mux::window::WindowId;
 use crate::mux::Mux;
 use crate::server::client::Client;
-use crate::server::codec::Spawn;
-use crate::server::tab::ClientTab;
+use crate::server::codec::{ListTabsResponse, Spawn};
+use crate::server::tab::{process_reconnect, process_unsolicited, ClientTab};
 use failure::Fallible;
 use portable_pty::{CommandBuilder, PtySize};
+use promise::Future;
 use std::collections::HashMap;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 
 pub struct ClientInner {
-    pub client: Mutex<Client>,
+    pub client: Client,
     pub local_domain_id: DomainId,
     pub remote_domain_id: DomainId,
     remote_to_local_window: Mutex<HashMap<WindowId, WindowId>>,
+    name: String,
+    connected: Arc<AtomicBool>,
+    /// Set while the user has detached from the domain
+    detached: AtomicBool,
 }
 
 impl ClientInner {
@@ -49,6 +55,14 @@ impl ClientInner {
         }
         None
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
 }
 
 pub struct ClientDomain {
@@ -63,11 +77,43 @@ impl ClientInner {
         // we'll add a way to discover/enumerate domains to populate
         // this a bit rigorously.
         let remote_domain_id = 0;
+        let name = client.name().to_owned();
+        let connected = client.connected_flag();
+
+        // Updates pushed by the server arrive on the client thread;
+        // route them to the gui thread where the tabs live
+        if let Err(err) = client.on_unsolicited(move |pdu| match gui_executor() {
+            Some(executor) => {
+                Future::with_executor(executor, move || {
+                    process_unsolicited(local_domain_id, pdu);
+                    Future::ok(())
+                });
+            }
+            None => log::error!("no gui executor to process {:?}", pdu),
+        }) {
+            log::error!("failed to register for server updates: {}", err);
+        }
+        // The server has forgotten about us by the time that the
+        // connection is re-established, so the tabs need to catch up
+        if let Err(err) = client.on_reconnect(move || match gui_executor() {
+            Some(executor) => {
+                Future::with_executor(executor, move || {
+                    process_reconnect(local_domain_id);
+                    Future::ok(())
+                });
+            }
+            None => log::error!("no gui executor to resync the tabs"),
+        }) {
+            log::error!("failed to register for reconnection: {}", err);
+        }
         Self {
-            client: Mutex::new(client),
+            client,
             local_domain_id,
             remote_domain_id,
             remote_to_local_window: Mutex::new(HashMap::new()),
+            name,
+            connected,
+            detached: AtomicBool::new(false),
         }
     }
 }
@@ -77,6 +123,71 @@ impl ClientDomain {
         let inner = Arc::new(ClientInner::new(client));
         Self { inner }
     }
+
+    /// Adds the tabs that the server listed, opening windows for them
+    /// where needed
+    fn attach_tabs(&self, tabs: &ListTabsResponse) -> Fallible<()> {
+        let mux = Mux::get().unwrap();
+        self.inner.detached.store(false, Ordering::SeqCst);
+        log::error!("ListTabs result {:#?}", tabs);
+
+        for entry in tabs.tabs.iter() {
+            log::error!(
+                "attaching to remote tab {} in remote window {} {}",
+                entry.tab_id,
+                entry.window_id,
+                entry.title
+            );
+            let tab: Rc<dyn Tab> = Rc::new(ClientTab::new(&self.inner, entry.tab_id));
+            mux.add_tab(&tab)?;
+
+            if let Some(local_window_id) = self.inner.remote_to_local_window(entry.window_id) {
+                let mut window = mux
+                    .get_window_mut(local_window_id)
+                    .expect("no such window!?");
+                log::error!("already have a local window for this one");
+                window.push(&tab);
+            } else {
+                log::error!("spawn new local window");
+                let fonts = Rc::new(FontConfiguration::new(
+                    mux.config(),
+                    FontSystemSelection::get_default(),
+                ));
+                let local_window_id = mux.new_empty_window();
+                self.inner
+                    .record_remote_to_local_window_mapping(entry.window_id, local_window_id);
+                mux.add_tab_to_window(&tab, local_window_id)?;
+
+                front_end()
+                    .unwrap()
+                    .spawn_new_window(&mux.config(), &fonts, &tab, local_window_id)
+                    .unwrap();
+            }
+        }
+        mux.notify_user(&format!("Attached to {}", self.inner.name));
+        Ok(())
+    }
+}
+
+/// Finishes reattaching to the domain once the server has listed its
+/// tabs.  This must be called on the gui thread.
+fn finish_reattach(local_domain_id: DomainId, tabs: Fallible<ListTabsResponse>) {
+    let mux = Mux::get().unwrap();
+    let domain = match mux.get_domain(local_domain_id) {
+        Some(domain) => domain,
+        None => return,
+    };
+    let domain = match domain.downcast_ref::<ClientDomain>() {
+        Some(domain) => domain,
+        None => return,
+    };
+    if let Err(err) = tabs.and_then(|tabs| domain.attach_tabs(&tabs)) {
+        mux.notify_user(&format!(
+            "Unable to attach to {}: {}",
+            domain.domain_name(),
+            err
+        ));
+    }
 }
 
 impl Domain for ClientDomain {
@@ -84,21 +195,62 @@ impl Domain for ClientDomain {
         self.inner.local_domain_id
     }
 
+    fn domain_name(&self) -> &str {
+        &self.inner.name
+    }
+
+    fn state(&self) -> DomainState {
+        if self.inner.detached.load(Ordering::SeqCst) {
+            DomainState::Detached
+        } else if self.inner.is_connected() {
+            DomainState::Attached
+        } else {
+            DomainState::Reconnecting
+        }
+    }
+
     fn spawn(
         &self,
         size: PtySize,
         command: Option<CommandBuilder>,
         window: WindowId,
     ) -> Fallible<Rc<dyn Tab>> {
+        // The server picks the program to run unless one was given or
+        // this domain is configured with its own; TERM and the
+        // environment of the domain can only be passed along with one
+        let config = Mux::get().unwrap().config();
+        let name = self.domain_name();
+        let command = match command {
+            Some(mut command) => {
+                config.apply_domain_environment(name, &mut command);
+                Some(command)
+            }
+            None => match config.domains.get(name) {
+                Some(settings) if settings.default_prog.is_some() => {
+                    Some(config.build_prog_for_domain(name)?)
+                }
+                _ => None,
+            },
+        };
+        // Without a command, the directory of the domain has to be
+        // passed along on its own
+        let cwd = match command {
+            Some(_) => None,
+            None => config
+                .domains
+                .get(name)
+                .and_then(|settings| settings.cwd.clone()),
+        };
         let remote_tab_id = {
-            let mut client = self.inner.client.lock().unwrap();
-
-            let result = client
+            let result = self
+                .inner
+                .client
                 .spawn(Spawn {
-                    domain_id: self.inner.remote_domain_id,
+                    domain_id: Some(self.inner.remote_domain_id),
                     window_id: self.inner.local_to_remote_window(window),
                     size,
                     command,
+                    cwd,
                 })
                 .wait()?;
 
@@ -116,44 +268,50 @@ impl Domain for ClientDomain {
     }
 
     fn attach(&self) -> Fallible<()> {
-        let mux = Mux::get().unwrap();
-        let mut client = self.inner.client.lock().unwrap();
-        let tabs = client.list_tabs().wait()?;
-        log::error!("ListTabs result {:#?}", tabs);
-
-        for entry in tabs.tabs.iter() {
-            log::error!(
-                "attaching to remote tab {} in remote window {} {}",
-                entry.tab_id,
-                entry.window_id,
-                entry.title
-            );
-            let tab: Rc<dyn Tab> = Rc::new(ClientTab::new(&self.inner, entry.tab_id));
-            mux.add_tab(&tab)?;
-
-            if let Some(local_window_id) = self.inner.remote_to_local_window(entry.window_id) {
-                let mut window = mux
-                    .get_window_mut(local_window_id)
-                    .expect("no such window!?");
-                log::error!("already have a local window for this one");
-                window.push(&tab);
-            } else {
-                log::error!("spawn new local window");
-                let fonts = Rc::new(FontConfiguration::new(
-                    Arc::clone(mux.config()),
-                    FontSystemSelection::get_default(),
-                ));
-                let local_window_id = mux.new_empty_window();
-                self.inner
-                    .record_remote_to_local_window_mapping(entry.window_id, local_window_id);
-                mux.add_tab_to_window(&tab, local_window_id)?;
+        let tabs = self.inner.client.list_tabs().wait()?;
+        self.attach_tabs(&tabs)
+    }
 
-                front_end()
-                    .unwrap()
-                    .spawn_new_window(mux.config(), &fonts, &tab, local_window_id)
-                    .unwrap();
+    fn detach(&self) -> Fallible<()> {
+        let mux = Mux::get().unwrap();
+        self.inner.detached.store(true, Ordering::SeqCst);
+        for tab in mux.iter_tabs() {
+            if let Some(client_tab) = tab.downcast_ref::<ClientTab>() {
+                if client_tab.domain_id() == self.inner.local_domain_id {
+                    client_tab.unsubscribe();
+                    // Windows that are left empty close by themselves
+                    mux.remove_tab(tab.tab_id());
+                }
             }
         }
+        self.inner.remote_to_local_window.lock().unwrap().clear();
+        mux.notify_user(&format!(
+            "Detached from {}; its tabs are still running",
+            self.inner.name
+        ));
         Ok(())
     }
+
+    fn reattach(&self) -> Fallible<()> {
+        // Listing the tabs may have to wait for the client to reconnect,
+        // which can ask about the certificate of the server in the gui
+        let local_domain_id = self.inner.local_domain_id;
+        self.inner.client.list_tabs().then(move |tabs| {
+            match gui_executor() {
+                Some(executor) => {
+                    Future::with_executor(executor, move || {
+                        finish_reattach(local_domain_id, tabs);
+                        Future::ok(())
+                    });
+                }
+                None => log::error!("no gui executor to attach the tabs"),
+            }
+            Future::ok(())
+        });
+        Ok(())
+    }
+
+    fn system_resumed(&self) {
+        self.inner.client.system_resumed();
+    }
 }
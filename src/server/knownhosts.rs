@@ -0,0 +1,268 @@
+//! Remembers the certificates presented by the mux servers that we
+//! have connected to over TLS, in the manner of the ssh known_hosts
+//! file, so that a server that starts presenting a different
+//! certificate is refused rather than silently trusted.
+//!
+//! Each line of the file holds the `host:port` of a server followed
+//! by its certificate in DER form, base91 encoded.  Blank lines and
+//! lines starting with `#` are ignored.
+use failure::{bail, format_err, Fallible};
+use lazy_static::lazy_static;
+use log::{error, warn};
+use promise::Future;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Asks the user a yes or no question, resolving to true for yes
+pub type Confirm = Box<dyn Fn(String) -> Future<bool> + Send>;
+
+lazy_static! {
+    /// Set by the gui once it can show questions in its windows
+    static ref CONFIRM: Mutex<Option<Confirm>> = Mutex::new(None);
+}
+
+/// Routes the questions about new and changed certificates to
+/// `confirm`.  Until this is called they are answered from the config.
+pub fn set_confirm(confirm: Confirm) {
+    CONFIRM.lock().unwrap().replace(confirm);
+}
+
+/// Asks the user `question`, blocking until they answer.  Returns
+/// None if nothing has been set up to ask them.  This must not be
+/// called on the thread that answers the question.
+fn ask(question: String) -> Option<bool> {
+    let future = {
+        let confirm = CONFIRM.lock().unwrap();
+        confirm.as_ref()?(question)
+    };
+    match future.wait() {
+        Ok(answer) => Some(answer),
+        Err(err) => {
+            error!("unable to ask about the certificate: {}", err);
+            Some(false)
+        }
+    }
+}
+
+/// The outcome of looking up the certificate of a server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostStatus {
+    /// The certificate matches the one that was recorded
+    Known,
+    /// Nothing has been recorded for the server
+    Unknown,
+    /// A different certificate was recorded on this line of the file
+    Changed { line: usize },
+}
+
+pub struct KnownHosts {
+    path: PathBuf,
+    /// The host and encoded certificate, along with the line number
+    entries: Vec<(String, String, usize)>,
+}
+
+impl KnownHosts {
+    /// Loads the file at `path`; a missing file holds no entries
+    pub fn load(path: &Path) -> Fallible<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => bail!("reading {}: {}", path.display(), err),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            entries: parse(&text),
+        })
+    }
+
+    pub fn status(&self, host: &str, der: &[u8]) -> HostStatus {
+        let encoded = encode(der);
+        let mut status = HostStatus::Unknown;
+        for (entry_host, entry_cert, line) in &self.entries {
+            if entry_host != host {
+                continue;
+            }
+            if *entry_cert == encoded {
+                return HostStatus::Known;
+            }
+            status = HostStatus::Changed { line: *line };
+        }
+        status
+    }
+
+    /// Records the certificate of the server at the end of the file
+    pub fn add(&mut self, host: &str, der: &[u8]) -> Fallible<()> {
+        let encoded = encode(der);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| format_err!("opening {}: {}", self.path.display(), e))?;
+        writeln!(file, "{} {}", host, encoded)?;
+        let line = self.entries.last().map(|entry| entry.2).unwrap_or(0) + 1;
+        self.entries.push((host.to_owned(), encoded, line));
+        Ok(())
+    }
+
+    /// Removes the certificates recorded for the server and then
+    /// records `der` in their place
+    pub fn replace(&mut self, host: &str, der: &[u8]) -> Fallible<()> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => bail!("reading {}: {}", self.path.display(), err),
+        };
+        let mut kept = String::new();
+        for line in text.lines() {
+            if line.split_whitespace().next() != Some(host) {
+                kept.push_str(line);
+                kept.push('\n');
+            }
+        }
+        fs::write(&self.path, &kept)
+            .map_err(|e| format_err!("writing {}: {}", self.path.display(), e))?;
+        self.entries = parse(&kept);
+        self.add(host, der)
+    }
+}
+
+fn encode(der: &[u8]) -> String {
+    String::from_utf8(base91::encode(der)).expect("base91 is ascii")
+}
+
+fn parse(text: &str) -> Vec<(String, String, usize)> {
+    let mut entries = vec![];
+    for (idx, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        match (fields.next(), fields.next()) {
+            (Some(host), Some(cert)) => entries.push((host.to_owned(), cert.to_owned(), idx + 1)),
+            _ => warn!("ignoring malformed known_hosts line {}", idx + 1),
+        }
+    }
+    entries
+}
+
+/// Checks the certificate that the server at `host` presented against
+/// the known hosts file at `path`.
+///
+/// While the gui is running the user is asked whether to trust the
+/// certificate of a server that we haven't seen before, and whether to
+/// replace a recorded certificate that differs from the presented one.
+/// Before then, such as for the connection made at startup, a new
+/// certificate is recorded if `trust_new` is set and refused otherwise,
+/// and a changed certificate is always refused.
+pub fn verify(path: &Path, host: &str, der: &[u8], trust_new: bool) -> Fallible<()> {
+    let mut known_hosts = KnownHosts::load(path)?;
+    match known_hosts.status(host, der) {
+        HostStatus::Known => Ok(()),
+        HostStatus::Unknown if trust_new => {
+            let question = format!(
+                "{} presented a certificate that we haven't seen before.  \
+                 Trust it and record it in {}?",
+                host,
+                path.display()
+            );
+            match ask(question) {
+                Some(false) => bail!("the certificate of {} was not trusted", host),
+                Some(true) => {}
+                None => warn!(
+                    "recording the certificate of {} in {}",
+                    host,
+                    path.display()
+                ),
+            }
+            known_hosts.add(host, der)
+        }
+        HostStatus::Unknown => bail!(
+            "the certificate of {} is not in {} and \
+             mux_client_trust_new_hosts is false",
+            host,
+            path.display()
+        ),
+        HostStatus::Changed { line } => {
+            let question = format!(
+                "WARNING: the certificate of {} has changed since it was \
+                 recorded on line {} of {}.  This could mean that someone is \
+                 intercepting the connection.  Replace the recorded \
+                 certificate with the new one?",
+                host,
+                line,
+                path.display()
+            );
+            if ask(question) == Some(true) {
+                warn!(
+                    "replacing the certificate of {} in {}",
+                    host,
+                    path.display()
+                );
+                return known_hosts.replace(host, der);
+            }
+            bail!(
+                "the certificate of {} has changed since it was recorded on \
+                 line {} of {}.  This could mean that someone is intercepting \
+                 the connection.  If the server was given a new certificate, \
+                 remove that line to accept it.",
+                host,
+                line,
+                path.display()
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn known_unknown_and_changed() {
+        let cert = encode(b"certificate");
+        let text = format!("# comment\n\nserver:8080 {}\nbogus\n", cert);
+        let known_hosts = KnownHosts {
+            path: PathBuf::new(),
+            entries: parse(&text),
+        };
+        assert_eq!(
+            known_hosts.status("server:8080", b"certificate"),
+            HostStatus::Known
+        );
+        assert_eq!(
+            known_hosts.status("server:8081", b"certificate"),
+            HostStatus::Unknown
+        );
+        assert_eq!(
+            known_hosts.status("server:8080", b"other"),
+            HostStatus::Changed { line: 3 }
+        );
+    }
+
+    #[test]
+    fn replace_changed_certificate() {
+        let path =
+            std::env::temp_dir().join(format!("wezterm-known-hosts-test-{}", std::process::id()));
+        fs::write(
+            &path,
+            format!(
+                "# comment\nserver:8080 {}\nother:8080 {}\n",
+                encode(b"old"),
+                encode(b"other")
+            ),
+        )
+        .unwrap();
+        let mut known_hosts = KnownHosts::load(&path).unwrap();
+        known_hosts.replace("server:8080", b"new").unwrap();
+        assert_eq!(known_hosts.status("server:8080", b"new"), HostStatus::Known);
+
+        let reloaded = KnownHosts::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(reloaded.status("server:8080", b"new"), HostStatus::Known);
+        assert_eq!(reloaded.status("other:8080", b"other"), HostStatus::Known);
+        assert_eq!(reloaded.entries.len(), 2);
+    }
+}
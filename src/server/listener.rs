@@ -1,40 +1,73 @@
-use crate::config::Config;
-use crate::mux::Mux;
+use crate::config::{create_user_owned_dirs, Config};
+use crate::mux::renderable::RenderableSnapshot;
+use crate::mux::tab::TabId;
+use crate::mux::{FlowControl, Mux, MuxNotification};
+use crate::server::client::{ReadAndWrite, ReadinessWatcher};
 use crate::server::codec::*;
+use crate::server::ratelimit::{ClientSlot, RateLimiter, SessionLimits};
+use crate::server::wire::{WireHyperlink, WireLine};
 use crate::server::UnixListener;
 use failure::{bail, err_msg, format_err, Error, Fallible};
+use lazy_static::lazy_static;
 #[cfg(unix)]
 use libc::{mode_t, umask};
 use log::{debug, error, warn};
 use native_tls::{Identity, TlsAcceptor};
 use promise::{Executor, Future};
+use std::collections::{HashMap, HashSet};
 use std::convert::{TryFrom, TryInto};
-use std::fs::{remove_file, DirBuilder};
+use std::fs::remove_file;
 use std::io::Read;
 use std::net::TcpListener;
-#[cfg(unix)]
-use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Instant;
+use std::time::{Duration, Instant};
+use term::{SequenceNo, SEQ_ZERO};
 
 struct LocalListener {
     listener: UnixListener,
     executor: Box<dyn Executor>,
+    limits: SessionLimits,
+    compression: Compression,
 }
 
 impl LocalListener {
-    pub fn new(listener: UnixListener, executor: Box<dyn Executor>) -> Self {
-        Self { listener, executor }
+    pub fn new(
+        listener: UnixListener,
+        executor: Box<dyn Executor>,
+        limits: SessionLimits,
+        compression: Compression,
+    ) -> Self {
+        Self {
+            listener,
+            executor,
+            limits,
+            compression,
+        }
     }
 
     fn run(&mut self) {
         for stream in self.listener.incoming() {
             match stream {
                 Ok(stream) => {
+                    let slot = match self.limits.try_connect() {
+                        Some(slot) => slot,
+                        None => {
+                            error!("rejecting connection: too many clients");
+                            continue;
+                        }
+                    };
                     let executor = self.executor.clone_executor();
-                    let mut session = ClientSession::new(stream, executor);
+                    let mut session = ClientSession::new(
+                        stream,
+                        executor,
+                        self.limits.clone(),
+                        self.compression.clone(),
+                        slot,
+                    );
                     thread::spawn(move || session.run());
                 }
                 Err(err) => {
@@ -146,14 +179,24 @@ struct NetListener {
     acceptor: Arc<TlsAcceptor>,
     listener: TcpListener,
     executor: Box<dyn Executor>,
+    limits: SessionLimits,
+    compression: Compression,
 }
 
 impl NetListener {
-    pub fn new(listener: TcpListener, acceptor: TlsAcceptor, executor: Box<dyn Executor>) -> Self {
+    pub fn new(
+        listener: TcpListener,
+        acceptor: TlsAcceptor,
+        executor: Box<dyn Executor>,
+        limits: SessionLimits,
+        compression: Compression,
+    ) -> Self {
         Self {
             listener,
             acceptor: Arc::new(acceptor),
             executor,
+            limits,
+            compression,
         }
     }
 
@@ -161,12 +204,27 @@ impl NetListener {
         for stream in self.listener.incoming() {
             match stream {
                 Ok(stream) => {
+                    // Check the limit before the TLS handshake so that
+                    // we don't spend effort on clients we will reject
+                    let slot = match self.limits.try_connect() {
+                        Some(slot) => slot,
+                        None => {
+                            error!(
+                                "rejecting connection from {:?}: too many clients",
+                                stream.peer_addr()
+                            );
+                            continue;
+                        }
+                    };
                     stream.set_nodelay(true).ok();
                     let executor = self.executor.clone_executor();
                     let acceptor = self.acceptor.clone();
+                    let limits = self.limits.clone();
+                    let compression = self.compression.clone();
                     thread::spawn(move || match acceptor.accept(stream) {
                         Ok(stream) => {
-                            let mut session = ClientSession::new(stream, executor);
+                            let mut session =
+                                ClientSession::new(stream, executor, limits, compression, slot);
                             session.run();
                         }
                         Err(e) => {
@@ -183,9 +241,43 @@ impl NetListener {
     }
 }
 
-pub struct ClientSession<S: std::io::Read + std::io::Write> {
+static NEXT_CLIENT_ID: AtomicUsize = AtomicUsize::new(0);
+
+pub struct ClientSession<S: ReadAndWrite> {
+    /// Identifies the session in the logs
+    client_id: usize,
+    /// Set once the client has sent an acceptable Hello
+    established: bool,
     stream: S,
     executor: Box<dyn Executor>,
+    limits: SessionLimits,
+    compression: Compression,
+    pdu_limiter: RateLimiter,
+    _slot: ClientSlot,
+    /// The tabs for which the client wants PushTabDelta updates
+    subscriptions: HashSet<TabId>,
+    /// The snapshot of each tab that was most recently sent to the
+    /// client; the next snapshot shares its unchanged lines
+    snapshots: HashMap<TabId, RenderableSnapshot>,
+    /// The cost of the deltas pushed for each tab that the client has
+    /// yet to acknowledge
+    unacked: HashMap<TabId, usize>,
+    /// The tabs with changes that are waiting for the client to
+    /// acknowledge earlier deltas
+    deferred: HashSet<TabId>,
+    /// The tabs whose pty reading is paused until the client catches up
+    paused: HashMap<TabId, Arc<FlowControl>>,
+    /// Set once we have subscribed to notifications from the mux
+    mux_subscribed: bool,
+    event_tx: Sender<SessionEvent>,
+    event_rx: Receiver<SessionEvent>,
+}
+
+/// What wakes a session up
+enum SessionEvent {
+    /// The client sent something, which the ReadinessWatcher noticed
+    Readable,
+    Notification(MuxNotification),
 }
 
 struct BufferedTerminalHost<'a> {
@@ -220,20 +312,314 @@ impl<'a> term::TerminalHost for BufferedTerminalHost<'a> {
     }
 }
 
-impl<S: std::io::Read + std::io::Write> ClientSession<S> {
-    fn new(stream: S, executor: Box<dyn Executor>) -> Self {
-        Self { stream, executor }
+/// Takes a snapshot of a tab, sharing the unchanged lines with
+/// `prior`, and returns it along with the title and working
+/// directory of the tab.  This must be called on the gui thread.
+fn tab_snapshot(
+    tab_id: TabId,
+    prior: Option<RenderableSnapshot>,
+) -> Fallible<(RenderableSnapshot, String, Option<String>)> {
+    let mux = Mux::get().unwrap();
+    let tab = mux
+        .get_tab(tab_id)
+        .ok_or_else(|| format_err!("no such tab {}", tab_id))?;
+    Ok((
+        tab.snapshot(prior.as_ref()),
+        tab.get_title(),
+        tab.get_current_dir(),
+    ))
+}
+
+/// Captures the lines of a snapshot that changed after `since`
+/// for transmission to a client.
+fn coarse_snapshot_data(
+    snapshot: &RenderableSnapshot,
+    title: String,
+    cwd: Option<String>,
+    since: SequenceNo,
+) -> GetCoarseTabRenderableDataResponse {
+    let dirty_lines = snapshot
+        .changed_lines(since)
+        .into_iter()
+        .map(|(line_idx, line, sel)| DirtyLine {
+            line_idx,
+            line: line.into(),
+            selection_col_from: sel.start,
+            selection_col_to: sel.end,
+        })
+        .collect();
+
+    GetCoarseTabRenderableDataResponse {
+        dirty_lines,
+        current_highlight: snapshot
+            .current_highlight
+            .as_ref()
+            .map(|link| WireHyperlink::from(&**link)),
+        cursor_position: snapshot.cursor.into(),
+        physical_rows: snapshot.physical_rows,
+        physical_cols: snapshot.physical_cols,
+        title,
+        cwd,
+        mouse_grabbed: snapshot.mouse_grabbed,
+        scrollback_rows: snapshot.scrollbar.scrollback_rows,
+    }
+}
+
+impl<S: ReadAndWrite> ClientSession<S> {
+    fn new(
+        stream: S,
+        executor: Box<dyn Executor>,
+        limits: SessionLimits,
+        compression: Compression,
+        slot: ClientSlot,
+    ) -> Self {
+        let pdu_limiter = limits.pdu_limiter();
+        let (event_tx, event_rx) = channel();
+        Self {
+            client_id: NEXT_CLIENT_ID.fetch_add(1, Ordering::SeqCst),
+            established: false,
+            stream,
+            executor,
+            limits,
+            compression,
+            pdu_limiter,
+            _slot: slot,
+            subscriptions: HashSet::new(),
+            snapshots: HashMap::new(),
+            unacked: HashMap::new(),
+            deferred: HashSet::new(),
+            paused: HashMap::new(),
+            mux_subscribed: false,
+            event_tx,
+            event_rx,
+        }
     }
 
     fn process(&mut self) -> Result<(), Error> {
+        // Requests from the client and notifications from the mux both
+        // arrive through the event channel, so that we can sleep until
+        // there is something to do
+        let watcher = ReadinessWatcher::spawn(&self.stream, self.event_tx.clone(), || {
+            SessionEvent::Readable
+        })?;
+        let mut read_buffer = vec![];
+
         loop {
-            self.process_one()?;
+            let first = self
+                .event_rx
+                .recv()
+                .map_err(|_| err_msg("session event channel closed"))?;
+            let mut readable = false;
+            let mut notifications = vec![];
+            for event in std::iter::once(first).chain(self.event_rx.try_iter()) {
+                match event {
+                    SessionEvent::Readable => readable = true,
+                    SessionEvent::Notification(notification) => notifications.push(notification),
+                }
+            }
+
+            if readable {
+                if !watcher.read_available(&mut self.stream, &mut read_buffer)? {
+                    bail!("client closed the connection");
+                }
+                while let Some(decoded) = self.decode_buffered(&mut read_buffer)? {
+                    self.process_one(decoded)?;
+                }
+            }
+
+            // Acknowledgements from the client may have freed up
+            // deferred tabs, so this runs for every event
+            self.push_updates(notifications)?;
+        }
+    }
+
+    /// Decode the next complete PDU from the data that has been read
+    /// from the client so far.
+    fn decode_buffered(&mut self, read_buffer: &mut Vec<u8>) -> Fallible<Option<DecodedPdu>> {
+        let max_frame_size = if self.established {
+            self.limits.max_frame_size
+        } else {
+            self.limits.max_frame_size.min(HELLO_MAX_FRAME_SIZE)
+        };
+        match Pdu::stream_decode(read_buffer, max_frame_size) {
+            Ok(decoded) => Ok(decoded),
+            Err(err) => {
+                if let Some(corrupt) = err.downcast_ref::<CorruptFrame>() {
+                    // We can't resynchronize with the stream after a bad
+                    // frame, but we can tell the client why we're about
+                    // to hang up on it.
+                    Pdu::ErrorResponse(ErrorResponse {
+                        reason: format!("Error: {}", corrupt),
+                    })
+                    .encode(&mut self.stream, corrupt.serial())?;
+                    self.stream.flush()?;
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Send a PushTabDelta for each subscribed tab that has produced
+    /// output since the last call, a PushTabRemoved for each
+    /// subscribed tab that has gone away, a SetClipboard for each
+    /// clipboard change requested by a subscribed tab and a
+    /// PushColorScheme for each subscribed tab that changed its
+    /// color scheme.
+    fn push_updates(&mut self, notifications: Vec<MuxNotification>) -> Fallible<()> {
+        let mut changed = HashSet::new();
+        for notification in notifications {
+            match notification {
+                MuxNotification::TabOutput(tab_id) => {
+                    if self.subscriptions.contains(&tab_id) {
+                        changed.insert(tab_id);
+                    }
+                }
+                MuxNotification::TabRemoved(tab_id) => {
+                    changed.remove(&tab_id);
+                    self.snapshots.remove(&tab_id);
+                    self.unacked.remove(&tab_id);
+                    self.deferred.remove(&tab_id);
+                    self.paused.remove(&tab_id);
+                    if self.subscriptions.remove(&tab_id) {
+                        Pdu::PushTabRemoved(PushTabRemoved { tab_id })
+                            .encode(&mut self.stream, UNSOLICITED_SERIAL)?;
+                    }
+                }
+                MuxNotification::SetClipboard(tab_id, clipboard) => {
+                    if self.subscriptions.contains(&tab_id) {
+                        Pdu::SetClipboard(SetClipboard { tab_id, clipboard })
+                            .encode(&mut self.stream, UNSOLICITED_SERIAL)?;
+                    }
+                }
+                MuxNotification::SetColorScheme(tab_id, name) => {
+                    if self.subscriptions.contains(&tab_id) {
+                        Pdu::PushColorScheme(PushColorScheme { tab_id, name })
+                            .encode(&mut self.stream, UNSOLICITED_SERIAL)?;
+                    }
+                }
+                MuxNotification::ConfigReloaded
+                | MuxNotification::Bell(_)
+                | MuxNotification::CurrentDirChanged(_)
+                | MuxNotification::Notice(_)
+                | MuxNotification::Resumed => {}
+            }
+        }
+
+        changed.extend(self.deferred.drain());
+        for tab_id in changed {
+            if self.is_over_window(tab_id) {
+                self.deferred.insert(tab_id);
+                self.pause_tab(tab_id)?;
+                continue;
+            }
+            let since = self.last_seqno(tab_id);
+            let delta = match self.coarse_tab_data(tab_id, since) {
+                Ok(delta) => delta,
+                Err(err) => {
+                    debug!("unable to push delta for tab {}: {}", tab_id, err);
+                    continue;
+                }
+            };
+            *self.unacked.entry(tab_id).or_insert(0) += delta.flow_control_cost();
+            Pdu::PushTabDelta(PushTabDelta { tab_id, delta }).encode_with_compression(
+                &mut self.stream,
+                UNSOLICITED_SERIAL,
+                &self.compression,
+            )?;
+        }
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    /// Returns true if the client has yet to acknowledge a full
+    /// window of deltas for the tab
+    fn is_over_window(&self, tab_id: TabId) -> bool {
+        let window = self.limits.flow_control_window;
+        window > 0 && self.unacked.get(&tab_id).cloned().unwrap_or(0) >= window
+    }
+
+    /// Stops the output of the tab from being read until the client
+    /// catches up
+    fn pause_tab(&mut self, tab_id: TabId) -> Fallible<()> {
+        if self.paused.contains_key(&tab_id) {
+            return Ok(());
+        }
+        let flow_control = Future::with_executor(self.executor.clone_executor(), move || {
+            Ok(Mux::get().unwrap().flow_control(tab_id))
+        })
+        .wait()?;
+        if let Some(flow_control) = flow_control {
+            debug!(
+                "client {} is behind on tab {}; pausing it",
+                self.client_id, tab_id
+            );
+            flow_control.pause(self.client_id);
+            self.paused.insert(tab_id, flow_control);
+        }
+        Ok(())
+    }
+
+    /// Returns the sequence number of the state of the tab that was
+    /// most recently sent to the client, or SEQ_ZERO if the client
+    /// hasn't been sent anything for the tab yet.
+    fn last_seqno(&self, tab_id: TabId) -> SequenceNo {
+        self.snapshots
+            .get(&tab_id)
+            .map(|snapshot| snapshot.seqno)
+            .unwrap_or(SEQ_ZERO)
+    }
+
+    /// Snapshots the tab on the gui thread and then describes the lines
+    /// that changed after `since`.  The snapshot is retained so that
+    /// the next one can share its unchanged lines.
+    fn coarse_tab_data(
+        &mut self,
+        tab_id: TabId,
+        since: SequenceNo,
+    ) -> Fallible<GetCoarseTabRenderableDataResponse> {
+        let prior = self.snapshots.get(&tab_id).cloned();
+        let (snapshot, title, cwd) =
+            Future::with_executor(self.executor.clone_executor(), move || {
+                tab_snapshot(tab_id, prior)
+            })
+            .wait()?;
+        let response = coarse_snapshot_data(&snapshot, title, cwd, since);
+        self.snapshots.insert(tab_id, snapshot);
+        Ok(response)
+    }
+
+    /// Checks the Hello that opens the session.  Any other PDU, an
+    /// incompatible protocol version or a bad token is an error that
+    /// ends the session.
+    fn process_hello(&mut self, pdu: Pdu) -> Fallible<Pdu> {
+        let hello = match pdu {
+            Pdu::Hello(hello) => hello,
+            _ => bail!("expected Hello, got {:?}", pdu),
+        };
+        if hello.protocol_version != PROTOCOL_VERSION {
+            bail!(
+                "protocol version mismatch: client has {}, server has {}",
+                hello.protocol_version,
+                PROTOCOL_VERSION
+            );
+        }
+        if !self
+            .limits
+            .authenticate(hello.auth_token.as_ref().map(String::as_str))
+        {
+            bail!("authentication failed");
         }
+        self.established = true;
+        debug!("client {} established session", self.client_id);
+        Ok(Pdu::HelloResponse(HelloResponse {
+            protocol_version: PROTOCOL_VERSION,
+        }))
     }
 
     fn process_pdu(&mut self, pdu: Pdu) -> Fallible<Pdu> {
         Ok(match pdu {
             Pdu::Ping(Ping {}) => Pdu::Pong(Pong {}),
+            Pdu::Hello { .. } => bail!("session is already established"),
             Pdu::ListTabs(ListTabs {}) => {
                 let result = Future::with_executor(self.executor.clone_executor(), move || {
                     let mux = Mux::get().unwrap();
@@ -245,6 +631,7 @@ impl<S: std::io::Read + std::io::Write> ClientSession<S> {
                                 window_id,
                                 tab_id: tab.tab_id(),
                                 title: tab.get_title(),
+                                cwd: tab.get_current_dir(),
                             });
                         }
                     }
@@ -255,42 +642,81 @@ impl<S: std::io::Read + std::io::Write> ClientSession<S> {
                 Pdu::ListTabsResponse(result)
             }
             Pdu::GetCoarseTabRenderableData(GetCoarseTabRenderableData { tab_id, dirty_all }) => {
-                let result = Future::with_executor(self.executor.clone_executor(), move || {
+                let since = if dirty_all {
+                    SEQ_ZERO
+                } else {
+                    self.last_seqno(tab_id)
+                };
+                Pdu::GetCoarseTabRenderableDataResponse(self.coarse_tab_data(tab_id, since)?)
+            }
+
+            Pdu::Subscribe(Subscribe { tab_id }) => {
+                let subscribe = !self.mux_subscribed;
+                let event_tx = self.event_tx.clone();
+                Future::with_executor(self.executor.clone_executor(), move || {
                     let mux = Mux::get().unwrap();
-                    let tab = mux
-                        .get_tab(tab_id)
+                    mux.get_tab(tab_id)
                         .ok_or_else(|| format_err!("no such tab {}", tab_id))?;
-                    let title = tab.get_title();
-                    let mut renderable = tab.renderer();
-                    if dirty_all {
-                        renderable.make_all_lines_dirty();
+                    if subscribe {
+                        // The subscription is dropped by the mux once the
+                        // session ends and the receiver goes away
+                        mux.subscribe(move |notification| {
+                            event_tx
+                                .send(SessionEvent::Notification(notification))
+                                .is_ok()
+                        });
                     }
+                    Ok(())
+                })
+                .wait()?;
+                self.mux_subscribed = true;
+                self.subscriptions.insert(tab_id);
+                Pdu::UnitResponse(UnitResponse {})
+            }
 
-                    let dirty_lines = renderable
-                        .get_dirty_lines()
-                        .iter()
-                        .map(|(line_idx, line, sel)| DirtyLine {
-                            line_idx: *line_idx,
-                            line: (*line).clone(),
-                            selection_col_from: sel.start,
-                            selection_col_to: sel.end,
-                        })
-                        .collect();
-                    renderable.clean_dirty_lines();
-
-                    let (physical_rows, physical_cols) = renderable.physical_dimensions();
-
-                    Ok(GetCoarseTabRenderableDataResponse {
-                        dirty_lines,
-                        current_highlight: renderable.current_highlight(),
-                        cursor_position: renderable.get_cursor_position(),
-                        physical_rows,
-                        physical_cols,
-                        title,
-                    })
+            Pdu::Unsubscribe(Unsubscribe { tab_id }) => {
+                self.subscriptions.remove(&tab_id);
+                self.snapshots.remove(&tab_id);
+                self.unacked.remove(&tab_id);
+                self.deferred.remove(&tab_id);
+                if let Some(flow_control) = self.paused.remove(&tab_id) {
+                    flow_control.resume(self.client_id);
+                }
+                Pdu::UnitResponse(UnitResponse {})
+            }
+
+            Pdu::AckTabDelta(AckTabDelta { tab_id, cost }) => {
+                if let Some(unacked) = self.unacked.get_mut(&tab_id) {
+                    *unacked = unacked.saturating_sub(cost);
+                }
+                if !self.is_over_window(tab_id) {
+                    if let Some(flow_control) = self.paused.remove(&tab_id) {
+                        flow_control.resume(self.client_id);
+                    }
+                }
+                Pdu::UnitResponse(UnitResponse {})
+            }
+
+            Pdu::SetColorScheme(SetColorScheme {
+                window_id,
+                tab_id,
+                name,
+            }) => {
+                Future::with_executor(self.executor.clone_executor(), move || {
+                    let mux = Mux::get().unwrap();
+                    match (tab_id, window_id) {
+                        (Some(tab_id), _) => mux.set_tab_color_scheme(tab_id, &name)?,
+                        (None, Some(window_id)) => mux.set_window_color_scheme(window_id, &name)?,
+                        (None, None) => {
+                            for window_id in mux.iter_windows() {
+                                mux.set_window_color_scheme(window_id, &name)?;
+                            }
+                        }
+                    }
+                    Ok(())
                 })
                 .wait()?;
-                Pdu::GetCoarseTabRenderableDataResponse(result)
+                Pdu::UnitResponse(UnitResponse {})
             }
 
             Pdu::WriteToTab(WriteToTab { tab_id, data }) => {
@@ -305,6 +731,18 @@ impl<S: std::io::Read + std::io::Write> ClientSession<S> {
                 .wait()?;
                 Pdu::UnitResponse(UnitResponse {})
             }
+            Pdu::KillTab(KillTab { tab_id }) => {
+                Future::with_executor(self.executor.clone_executor(), move || {
+                    let mux = Mux::get().unwrap();
+                    mux.get_tab(tab_id)
+                        .ok_or_else(|| format_err!("no such tab {}", tab_id))?;
+                    // Dropping the last reference to the tab kills its process
+                    mux.remove_tab(tab_id);
+                    Ok(())
+                })
+                .wait()?;
+                Pdu::UnitResponse(UnitResponse {})
+            }
             Pdu::SendPaste(SendPaste { tab_id, data }) => {
                 Future::with_executor(self.executor.clone_executor(), move || {
                     let mux = Mux::get().unwrap();
@@ -325,6 +763,7 @@ impl<S: std::io::Read + std::io::Write> ClientSession<S> {
                         .get_tab(tab_id)
                         .ok_or_else(|| format_err!("no such tab {}", tab_id))?;
                     tab.resize(size)?;
+                    mux.notify_tab_changed(tab_id);
                     Ok(())
                 })
                 .wait()?;
@@ -338,6 +777,11 @@ impl<S: std::io::Read + std::io::Write> ClientSession<S> {
                         .get_tab(tab_id)
                         .ok_or_else(|| format_err!("no such tab {}", tab_id))?;
                     tab.key_down(event.key, event.modifiers)?;
+                    // A key press closes a tab that was held open
+                    // after its program exited
+                    if tab.held_exit().is_some() && tab.is_dead() {
+                        mux.remove_tab(tab_id);
+                    }
                     Ok(())
                 })
                 .wait()?;
@@ -355,18 +799,39 @@ impl<S: std::io::Read + std::io::Write> ClientSession<S> {
                         title: None,
                     };
                     tab.mouse_event(event, &mut host)?;
+                    // The selection or hyperlink highlight may have
+                    // changed without any output from the pty
+                    mux.notify_tab_changed(tab_id);
                     Ok(host.clipboard)
                 })
                 .wait()?;
                 Pdu::SendMouseEventResponse(SendMouseEventResponse { clipboard })
             }
 
+            Pdu::SendFocus(SendFocus { tab_id, focused }) => {
+                Future::with_executor(self.executor.clone_executor(), move || {
+                    let mux = Mux::get().unwrap();
+                    let tab = mux
+                        .get_tab(tab_id)
+                        .ok_or_else(|| format_err!("no such tab {}", tab_id))?;
+                    tab.focus_changed(focused)
+                })
+                .wait()?;
+                Pdu::UnitResponse(UnitResponse {})
+            }
+
             Pdu::Spawn(spawn) => {
+                if !self.limits.try_spawn() {
+                    bail!("spawn rate limit exceeded; try again later");
+                }
                 let result = Future::with_executor(self.executor.clone_executor(), move || {
                     let mux = Mux::get().unwrap();
-                    let domain = mux.get_domain(spawn.domain_id).ok_or_else(|| {
-                        format_err!("domain {} not found on this server", spawn.domain_id)
-                    })?;
+                    let domain = match spawn.domain_id {
+                        Some(domain_id) => mux.get_domain(domain_id).ok_or_else(|| {
+                            format_err!("domain {} not found on this server", domain_id)
+                        })?,
+                        None => Arc::clone(mux.default_domain()),
+                    };
 
                     let window_id = if let Some(window_id) = spawn.window_id {
                         mux.get_window_mut(window_id).ok_or_else(|| {
@@ -377,7 +842,18 @@ impl<S: std::io::Read + std::io::Write> ClientSession<S> {
                         mux.new_empty_window()
                     };
 
-                    let tab = domain.spawn(spawn.size, spawn.command, window_id)?;
+                    let command = match spawn.cwd {
+                        Some(cwd) => {
+                            let mut command = match spawn.command {
+                                Some(command) => command,
+                                None => mux.config().build_prog_for_domain(domain.domain_name())?,
+                            };
+                            command.cwd(cwd);
+                            Some(command)
+                        }
+                        None => spawn.command,
+                    };
+                    let tab = domain.spawn(spawn.size, command, window_id)?;
                     Ok(SpawnResponse {
                         tab_id: tab.tab_id(),
                         window_id,
@@ -387,21 +863,105 @@ impl<S: std::io::Read + std::io::Write> ClientSession<S> {
                 Pdu::SpawnResponse(result)
             }
 
+            Pdu::CopyMode(CopyMode { tab_id, action }) => {
+                let result = Future::with_executor(self.executor.clone_executor(), move || {
+                    let mux = Mux::get().unwrap();
+                    let tab = mux
+                        .get_tab(tab_id)
+                        .ok_or_else(|| format_err!("no such tab {}", tab_id))?;
+                    let clipboard = tab.copy_mode(action)?;
+                    mux.notify_tab_changed(tab_id);
+                    Ok(CopyModeResponse {
+                        clipboard,
+                        active: tab.in_copy_mode(),
+                    })
+                })
+                .wait()?;
+                Pdu::CopyModeResponse(result)
+            }
+
+            Pdu::GetHyperlinkNearCursor(GetHyperlinkNearCursor { tab_id }) => {
+                let link = Future::with_executor(self.executor.clone_executor(), move || {
+                    let mux = Mux::get().unwrap();
+                    let tab = mux
+                        .get_tab(tab_id)
+                        .ok_or_else(|| format_err!("no such tab {}", tab_id))?;
+                    Ok(tab
+                        .hyperlink_near_cursor()?
+                        .map(|link| WireHyperlink::from(&*link)))
+                })
+                .wait()?;
+                Pdu::GetHyperlinkNearCursorResponse(GetHyperlinkNearCursorResponse { link })
+            }
+
+            Pdu::GetTabScrollback(GetTabScrollback {
+                tab_id,
+                viewport_offset,
+                rows,
+            }) => {
+                let response = Future::with_executor(self.executor.clone_executor(), move || {
+                    let mux = Mux::get().unwrap();
+                    let tab = mux
+                        .get_tab(tab_id)
+                        .ok_or_else(|| format_err!("no such tab {}", tab_id))?;
+                    let mut renderer = tab.renderer();
+                    Ok(GetTabScrollbackResponse {
+                        scrollback_rows: renderer.scrollbar_info().scrollback_rows,
+                        lines: renderer
+                            .get_viewport_lines(viewport_offset, rows)
+                            .iter()
+                            .map(WireLine::from)
+                            .collect(),
+                    })
+                })
+                .wait()?;
+                Pdu::GetTabScrollbackResponse(response)
+            }
+
             Pdu::Invalid { .. } => bail!("invalid PDU {:?}", pdu),
             Pdu::Pong { .. }
             | Pdu::ListTabsResponse { .. }
             | Pdu::SendMouseEventResponse { .. }
+            | Pdu::CopyModeResponse { .. }
+            | Pdu::GetHyperlinkNearCursorResponse { .. }
+            | Pdu::HelloResponse { .. }
             | Pdu::GetCoarseTabRenderableDataResponse { .. }
+            | Pdu::GetTabScrollbackResponse { .. }
+            | Pdu::PushTabDelta { .. }
+            | Pdu::PushTabRemoved { .. }
+            | Pdu::SetClipboard { .. }
+            | Pdu::PushColorScheme { .. }
             | Pdu::SpawnResponse { .. }
             | Pdu::UnitResponse { .. }
             | Pdu::ErrorResponse { .. } => bail!("expected a request, got {:?}", pdu),
         })
     }
 
-    fn process_one(&mut self) -> Fallible<()> {
-        let start = Instant::now();
-        let decoded = Pdu::decode(&mut self.stream)?;
-        debug!("got pdu {:?} from client in {:?}", decoded, start.elapsed());
+    fn process_one(&mut self, decoded: DecodedPdu) -> Fallible<()> {
+        debug!("got pdu {:?} from client {}", decoded, self.client_id);
+
+        if !self.established {
+            let response = match self.process_hello(decoded.pdu) {
+                Ok(response) => response,
+                Err(err) => {
+                    Pdu::ErrorResponse(ErrorResponse {
+                        reason: format!("Error: {}", err),
+                    })
+                    .encode(&mut self.stream, decoded.serial)?;
+                    self.stream.flush()?;
+                    return Err(err);
+                }
+            };
+            response.encode(&mut self.stream, decoded.serial)?;
+            self.stream.flush()?;
+            return Ok(());
+        }
+
+        let delay = self.pdu_limiter.acquire_delay();
+        if delay > Duration::from_secs(0) {
+            debug!("client exceeded pdu rate; delaying for {:?}", delay);
+            thread::sleep(delay);
+        }
 
         let start = Instant::now();
         let response = self.process_pdu(decoded.pdu).unwrap_or_else(|e| {
@@ -412,7 +972,7 @@ impl<S: std::io::Read + std::io::Write> ClientSession<S> {
         log::trace!("processing time {:?}", start.elapsed());
 
         let start = Instant::now();
-        response.encode(&mut self.stream, decoded.serial)?;
+        response.encode_with_compression(&mut self.stream, decoded.serial, &self.compression)?;
         self.stream.flush()?;
         log::trace!("encode and send in {:?}", start.elapsed());
 
@@ -421,7 +981,27 @@ impl<S: std::io::Read + std::io::Write> ClientSession<S> {
 
     fn run(&mut self) {
         if let Err(e) = self.process() {
-            error!("While processing session loop: {}", e);
+            error!(
+                "While processing session loop for client {}: {}",
+                self.client_id, e
+            );
+        }
+    }
+}
+
+impl<S: ReadAndWrite> Drop for ClientSession<S> {
+    fn drop(&mut self) {
+        // Dropping event_rx causes the mux to discard our subscriber
+        // the next time it sends a notification, and dropping the
+        // slot makes room for another client.  Tabs are left running
+        // so that another client can pick them up.
+        debug!(
+            "client {} disconnected; dropping subscriptions to {:?}",
+            self.client_id, self.subscriptions
+        );
+        // Don't leave tabs paused waiting for us to catch up
+        for flow_control in self.paused.values() {
+            flow_control.resume(self.client_id);
         }
     }
 }
@@ -470,33 +1050,7 @@ fn safely_create_sock_path(sock_path: &str) -> Result<UnixListener, Error> {
         .parent()
         .ok_or_else(|| format_err!("sock_path {} has no parent dir", sock_path.display()))?;
 
-    let mut builder = DirBuilder::new();
-    builder.recursive(true);
-
-    #[cfg(unix)]
-    {
-        builder.mode(0o700);
-    }
-
-    builder.create(sock_dir)?;
-
-    #[cfg(unix)]
-    {
-        if std::env::var_os("WEZTERM_SKIP_MUX_SOCK_PERMISSIONS_CHECK").is_none() {
-            // Let's be sure that the ownership looks sane
-            let meta = sock_dir.symlink_metadata()?;
-
-            let permissions = meta.permissions();
-            if (permissions.mode() & 0o22) != 0 {
-                bail!(
-                    "The permissions for {} are insecure and currently
-                allow other users to write to it (permissions={:?})",
-                    sock_dir.display(),
-                    permissions
-                );
-            }
-        }
-    }
+    create_user_owned_dirs(sock_dir)?;
 
     if sock_path.exists() {
         remove_file(sock_path)?;
@@ -506,18 +1060,35 @@ fn safely_create_sock_path(sock_path: &str) -> Result<UnixListener, Error> {
         .map_err(|e| format_err!("Failed to bind to {}: {}", sock_path.display(), e))
 }
 
+lazy_static! {
+    /// The unix domain socket that `spawn_listener` is serving the mux
+    /// on, if it was called
+    static ref SERVED_SOCKET_PATH: Mutex<Option<String>> = Mutex::new(None);
+}
+
+/// Returns the path of the unix domain socket that this process serves
+/// the mux on, or None if it isn't a mux server
+pub fn served_socket_path() -> Option<String> {
+    SERVED_SOCKET_PATH.lock().unwrap().clone()
+}
+
 pub fn spawn_listener(config: &Arc<Config>, executor: Box<dyn Executor>) -> Result<(), Error> {
     let sock_path = config
         .mux_server_unix_domain_socket_path
         .as_ref()
         .ok_or_else(|| err_msg("no mux_server_unix_domain_socket_path"))?;
+    let limits = SessionLimits::new(config);
+    let compression = Compression::new(config);
     let mut listener = LocalListener::new(
         safely_create_sock_path(sock_path)?,
         executor.clone_executor(),
+        limits.clone(),
+        compression.clone(),
     );
     thread::spawn(move || {
         listener.run();
     });
+    *SERVED_SOCKET_PATH.lock().unwrap() = Some(sock_path.clone());
 
     if let Some(address) = &config.mux_server_bind_address {
         let identity = IdentitySource::PemFiles {
@@ -540,6 +1111,8 @@ pub fn spawn_listener(config: &Arc<Config>, executor: Box<dyn Executor>) -> Resu
             })?,
             TlsAcceptor::new(identity.try_into()?)?,
             executor,
+            limits,
+            compression,
         );
         thread::spawn(move || {
             net_listener.run();
@@ -548,3 +1121,102 @@ pub fn spawn_listener(config: &Arc<Config>, executor: Box<dyn Executor>) -> Resu
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mux::renderable::Renderable;
+    use term::color::ColorPalette;
+    use term::{CursorPosition, Terminal, TerminalHost};
+    use termwiz::hyperlink::Hyperlink;
+
+    struct TestHost {}
+
+    impl std::io::Write for TestHost {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, std::io::Error> {
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> Result<(), std::io::Error> {
+            Ok(())
+        }
+    }
+
+    impl TerminalHost for TestHost {
+        fn set_title(&mut self, _title: &str) {}
+        fn set_clipboard(&mut self, _clip: Option<String>) -> Fallible<()> {
+            Ok(())
+        }
+        fn get_clipboard(&mut self) -> Fallible<String> {
+            bail!("no clipboard")
+        }
+        fn writer(&mut self) -> &mut dyn std::io::Write {
+            self
+        }
+        fn click_link(&mut self, _link: &Arc<Hyperlink>) {}
+    }
+
+    /// Sends the lines of a local terminal through the same
+    /// encoding that a remote ClientTab receives and verifies that
+    /// the resulting cells are identical.
+    fn assert_round_trip(text: &str) {
+        let mut term = Terminal::new(4, 20, 0, 0, 0, vec![]);
+        term.advance_bytes(text, &mut TestHost {});
+
+        let expected: Vec<_> = Renderable::get_changed_lines(&term, SEQ_ZERO)
+            .into_iter()
+            .map(|(idx, line, _)| (idx, line))
+            .collect();
+
+        let snapshot = RenderableSnapshot::capture(0, &mut term, ColorPalette::default(), None);
+        let response = coarse_snapshot_data(&snapshot, "title".to_string(), None, SEQ_ZERO);
+        let mut encoded = Vec::new();
+        Pdu::GetCoarseTabRenderableDataResponse(response)
+            .encode(&mut encoded, 1)
+            .unwrap();
+        let response = match Pdu::decode(encoded.as_slice()).unwrap().pdu {
+            Pdu::GetCoarseTabRenderableDataResponse(response) => response,
+            pdu => panic!("unexpected pdu {:?}", pdu),
+        };
+
+        assert_eq!(response.dirty_lines.len(), expected.len());
+        for (dl, (idx, line)) in response.dirty_lines.iter().zip(expected.iter()) {
+            assert_eq!(dl.line_idx, *idx);
+            let remote = dl.line.to_line();
+            assert_eq!(remote.cells(), line.cells(), "line {}", idx);
+            assert_eq!(remote.has_hyperlink(), line.has_hyperlink());
+            assert_eq!(remote.as_str(), line.as_str());
+        }
+        assert_eq!(
+            CursorPosition::from(response.cursor_position),
+            term.cursor_pos()
+        );
+    }
+
+    #[test]
+    fn sgr_attributes() {
+        assert_round_trip(concat!(
+            "\x1b[1mbold\x1b[0m \x1b[2mhalf\x1b[0m \x1b[3mitalic\x1b[0m\r\n",
+            "\x1b[4mul\x1b[21mdbl\x1b[5mblink\x1b[6mrapid\x1b[0m\r\n",
+            "\x1b[7mrev\x1b[9mstrike\x1b[53mover\x1b[8mhidden\x1b[0m\r\n",
+            "\x1b[31mred\x1b[38;5;200mpal\x1b[38;2;1;2;3mrgb\x1b[48;2;4;5;6mbg\x1b[0m",
+        ));
+    }
+
+    #[test]
+    fn wide_and_combining() {
+        assert_round_trip("\u{4e2d}\u{6587}x\u{1f468}\u{200d}\u{1f469}e\u{301}a\u{302}\u{303}");
+    }
+
+    #[test]
+    fn hyperlinks() {
+        assert_round_trip(concat!(
+            "\x1b]8;id=foo;http://example.com\x1b\\link\x1b]8;;\x1b\\ plain\r\n",
+            "\x1b]8;;http://example.com/\u{4e2d}\x1b\\\u{4e2d}\x1b]8;;\x1b\\",
+        ));
+    }
+
+    #[test]
+    fn wrapped_lines() {
+        assert_round_trip("\x1b[1mthis text is longer than a single line");
+    }
+}
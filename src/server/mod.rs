@@ -6,5 +6,9 @@ use uds_windows::{UnixListener, UnixStream};
 pub mod client;
 pub mod codec;
 pub mod domain;
+pub mod knownhosts;
 pub mod listener;
+pub mod pki;
+pub mod ratelimit;
 pub mod tab;
+pub mod wire;
@@ -0,0 +1,266 @@
+//! Creates a certificate authority, along with a server and a client
+//! certificate signed by it, for `wezterm cli generate-certs`, so that
+//! a TLS mux server can be set up without a working knowledge of
+//! the openssl tools.
+use failure::{bail, format_err, Fallible};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// The files written by `generate`, all PEM encoded
+pub struct GeneratedCerts {
+    pub ca_cert: PathBuf,
+    pub server_key: PathBuf,
+    pub server_cert: PathBuf,
+    pub client_key: PathBuf,
+    pub client_cert: PathBuf,
+}
+
+impl GeneratedCerts {
+    fn in_dir(dir: &Path) -> Self {
+        Self {
+            ca_cert: dir.join("ca.pem"),
+            server_key: dir.join("server.key"),
+            server_cert: dir.join("server.pem"),
+            client_key: dir.join("client.key"),
+            client_cert: dir.join("client.pem"),
+        }
+    }
+
+    /// Returns the configuration for the mux server, which listens
+    /// on `bind_address`
+    pub fn server_config(&self, bind_address: &str) -> String {
+        format!(
+            "mux_server_bind_address = {:?}\n\
+             mux_server_pem_private_key = {:?}\n\
+             mux_server_pem_cert = {:?}\n\
+             mux_server_pem_ca = {:?}\n",
+            bind_address,
+            self.server_key.display().to_string(),
+            self.server_cert.display().to_string(),
+            self.ca_cert.display().to_string(),
+        )
+    }
+
+    /// Returns the configuration for a client that connects to the
+    /// server at `remote_address`
+    pub fn client_config(&self, remote_address: &str) -> String {
+        format!(
+            "mux_server_remote_address = {:?}\n\
+             mux_client_pem_private_key = {:?}\n\
+             mux_client_pem_cert = {:?}\n\
+             mux_client_pem_ca = {:?}\n",
+            remote_address,
+            self.client_key.display().to_string(),
+            self.client_cert.display().to_string(),
+            self.ca_cert.display().to_string(),
+        )
+    }
+}
+
+/// Writes `data` to a new file, refusing to replace an existing one.
+/// Private keys are made readable only by the current user.
+fn write_new_file(path: &Path, data: &[u8], private: bool) -> Fallible<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(if private { 0o600 } else { 0o644 });
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+    let mut file = options
+        .open(path)
+        .map_err(|e| format_err!("creating {}: {}", path.display(), e))?;
+    file.write_all(data)?;
+    Ok(())
+}
+
+/// Creates the certificates in `dir`, valid for `days`.  The server
+/// certificate names `host`, which may be a host name or an IP
+/// address and which clients must use to reach the server.
+pub fn generate(dir: &Path, host: &str, days: u32) -> Fallible<GeneratedCerts> {
+    // A certificate that is valid for no days has expired already
+    if days < 1 {
+        bail!("the certificates must be valid for at least one day");
+    }
+    let certs = GeneratedCerts::in_dir(dir);
+    for path in &[
+        &certs.ca_cert,
+        &certs.server_key,
+        &certs.server_cert,
+        &certs.client_key,
+        &certs.client_cert,
+    ] {
+        if path.exists() {
+            bail!("{} already exists; not replacing it", path.display());
+        }
+    }
+    std::fs::create_dir_all(dir).map_err(|e| format_err!("creating {}: {}", dir.display(), e))?;
+
+    let pem = generate_pem(host, days)?;
+    write_new_file(&certs.ca_cert, &pem.ca_cert, false)?;
+    write_new_file(&certs.server_key, &pem.server_key, true)?;
+    write_new_file(&certs.server_cert, &pem.server_cert, false)?;
+    write_new_file(&certs.client_key, &pem.client_key, true)?;
+    write_new_file(&certs.client_cert, &pem.client_cert, false)?;
+    Ok(certs)
+}
+
+struct GeneratedPem {
+    ca_cert: Vec<u8>,
+    server_key: Vec<u8>,
+    server_cert: Vec<u8>,
+    client_key: Vec<u8>,
+    client_cert: Vec<u8>,
+}
+
+#[cfg(any(feature = "openssl", all(unix, not(target_os = "macos"))))]
+fn generate_pem(host: &str, days: u32) -> Fallible<GeneratedPem> {
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::{BigNum, MsbOption};
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::{PKey, Private};
+    use openssl::rsa::Rsa;
+    use openssl::x509::extension::{
+        AuthorityKeyIdentifier, BasicConstraints, ExtendedKeyUsage, KeyUsage,
+        SubjectAlternativeName, SubjectKeyIdentifier,
+    };
+    use openssl::x509::{X509Builder, X509NameBuilder, X509NameRef, X509};
+
+    fn new_key() -> Fallible<PKey<Private>> {
+        Ok(PKey::from_rsa(Rsa::generate(2048)?)?)
+    }
+
+    /// Starts a certificate for `common_name`, issued by `issuer` or,
+    /// if that is None, by itself
+    fn new_builder(
+        common_name: &str,
+        issuer: Option<&X509NameRef>,
+        key: &PKey<Private>,
+        days: u32,
+    ) -> Fallible<X509Builder> {
+        let mut name = X509NameBuilder::new()?;
+        name.append_entry_by_text("CN", common_name)?;
+        let name = name.build();
+
+        let mut serial = BigNum::new()?;
+        serial.rand(159, MsbOption::MAYBE_ZERO, false)?;
+
+        let mut builder = X509Builder::new()?;
+        builder.set_version(2)?;
+        builder.set_serial_number(&serial.to_asn1_integer()?)?;
+        builder.set_subject_name(&name)?;
+        builder.set_issuer_name(issuer.unwrap_or(&name))?;
+        builder.set_pubkey(key)?;
+        builder.set_not_before(&Asn1Time::days_from_now(0)?)?;
+        builder.set_not_after(&Asn1Time::days_from_now(days)?)?;
+        Ok(builder)
+    }
+
+    /// Creates a certificate for `common_name` signed by the CA
+    fn leaf(
+        common_name: &str,
+        host: Option<&str>,
+        ca_cert: &X509,
+        ca_key: &PKey<Private>,
+        days: u32,
+    ) -> Fallible<(PKey<Private>, X509)> {
+        let key = new_key()?;
+        let mut builder = new_builder(common_name, Some(ca_cert.subject_name()), &key, days)?;
+        builder.append_extension(BasicConstraints::new().build()?)?;
+        builder.append_extension(
+            KeyUsage::new()
+                .critical()
+                .digital_signature()
+                .key_encipherment()
+                .build()?,
+        )?;
+        let mut usage = ExtendedKeyUsage::new();
+        match host {
+            Some(_) => usage.server_auth(),
+            None => usage.client_auth(),
+        };
+        builder.append_extension(usage.build()?)?;
+        let key_id =
+            SubjectKeyIdentifier::new().build(&builder.x509v3_context(Some(ca_cert), None))?;
+        builder.append_extension(key_id)?;
+        let authority_id = AuthorityKeyIdentifier::new()
+            .keyid(false)
+            .build(&builder.x509v3_context(Some(ca_cert), None))?;
+        builder.append_extension(authority_id)?;
+        if let Some(host) = host {
+            let mut names = SubjectAlternativeName::new();
+            if host.parse::<std::net::IpAddr>().is_ok() {
+                names.ip(host);
+            } else {
+                names.dns(host);
+            }
+            let names = names.build(&builder.x509v3_context(Some(ca_cert), None))?;
+            builder.append_extension(names)?;
+        }
+        builder.sign(ca_key, MessageDigest::sha256())?;
+        Ok((key, builder.build()))
+    }
+
+    let ca_key = new_key()?;
+    let mut ca = new_builder("wezterm mux CA", None, &ca_key, days)?;
+    ca.append_extension(BasicConstraints::new().critical().ca().build()?)?;
+    ca.append_extension(
+        KeyUsage::new()
+            .critical()
+            .key_cert_sign()
+            .crl_sign()
+            .build()?,
+    )?;
+    let key_id = SubjectKeyIdentifier::new().build(&ca.x509v3_context(None, None))?;
+    ca.append_extension(key_id)?;
+    ca.sign(&ca_key, MessageDigest::sha256())?;
+    let ca_cert = ca.build();
+
+    let (server_key, server_cert) = leaf(host, Some(host), &ca_cert, &ca_key, days)?;
+    let (client_key, client_cert) = leaf("wezterm mux client", None, &ca_cert, &ca_key, days)?;
+
+    Ok(GeneratedPem {
+        ca_cert: ca_cert.to_pem()?,
+        server_key: server_key.private_key_to_pem_pkcs8()?,
+        server_cert: server_cert.to_pem()?,
+        client_key: client_key.private_key_to_pem_pkcs8()?,
+        client_cert: client_cert.to_pem()?,
+    })
+}
+
+#[cfg(not(any(feature = "openssl", all(unix, not(target_os = "macos")))))]
+fn generate_pem(_host: &str, _days: u32) -> Fallible<GeneratedPem> {
+    bail!("recompile wezterm using --features openssl")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn config_snippets_parse() {
+        let certs = GeneratedCerts::in_dir(Path::new("/certs"));
+        let server: Config = toml::from_str(&certs.server_config("0.0.0.0:8080")).unwrap();
+        assert_eq!(
+            server.mux_server_pem_cert,
+            Some(PathBuf::from("/certs/server.pem"))
+        );
+        let client: Config = toml::from_str(&certs.client_config("host:8080")).unwrap();
+        assert_eq!(client.mux_server_remote_address.unwrap(), "host:8080");
+        assert_eq!(
+            client.mux_client_pem_ca,
+            Some(PathBuf::from("/certs/ca.pem"))
+        );
+    }
+
+    #[test]
+    fn reject_zero_days() {
+        let dir = std::env::temp_dir().join("wezterm-pki-zero-days");
+        assert!(generate(&dir, "localhost", 0).is_err());
+        assert!(!dir.exists());
+    }
+}
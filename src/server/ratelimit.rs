@@ -0,0 +1,225 @@
+//! Limits that protect the mux server from misbehaving or
+//! abusive clients.
+use crate::config::Config;
+use crate::server::codec::DEFAULT_MAX_FRAME_SIZE;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_MAX_CLIENTS: usize = 32;
+pub const DEFAULT_MAX_PDUS_PER_SECOND: u32 = 1000;
+pub const DEFAULT_MAX_SPAWNS_PER_MINUTE: u32 = 60;
+pub const DEFAULT_FLOW_CONTROL_WINDOW: usize = 2000;
+
+/// A token bucket that permits bursts of up to `capacity` events,
+/// refilling at a rate of `capacity` tokens per `period`.
+#[derive(Debug)]
+pub struct RateLimiter {
+    capacity: f64,
+    tokens: f64,
+    tokens_per_second: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(capacity: u32, period: Duration) -> Self {
+        let capacity = f64::from(capacity.max(1));
+        let secs = period.as_secs() as f64 + f64::from(period.subsec_nanos()) / 1_000_000_000.0;
+        Self {
+            capacity,
+            tokens: capacity,
+            tokens_per_second: capacity / secs,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.last_refill);
+        let elapsed =
+            elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1_000_000_000.0;
+        self.tokens = (self.tokens + elapsed * self.tokens_per_second).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    /// Consume a token if one is available.
+    /// Returns true if the event is permitted.
+    pub fn try_acquire(&mut self) -> bool {
+        self.refill(Instant::now());
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns how long the caller needs to wait before a token
+    /// will be available, consuming that token.
+    pub fn acquire_delay(&mut self) -> Duration {
+        self.refill(Instant::now());
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            Duration::from_secs(0)
+        } else {
+            let secs = -self.tokens / self.tokens_per_second;
+            Duration::from_nanos((secs * 1_000_000_000.0) as u64)
+        }
+    }
+}
+
+/// The limits that are applied to connections accepted by the
+/// listeners.  The client count and spawn limiter are shared by
+/// all sessions, whereas the pdu rate applies to each connection.
+#[derive(Clone)]
+pub struct SessionLimits {
+    pub max_frame_size: usize,
+    /// The number of lines pushed for a tab that may await an
+    /// acknowledgement from the client, or 0 for no limit
+    pub flow_control_window: usize,
+    max_clients: usize,
+    max_pdus_per_second: u32,
+    active_clients: Arc<AtomicUsize>,
+    spawns: Arc<Mutex<RateLimiter>>,
+    auth_token: Option<Arc<String>>,
+}
+
+impl SessionLimits {
+    pub fn new(config: &Config) -> Self {
+        let max_spawns_per_minute = config
+            .mux_server_max_spawns_per_minute
+            .unwrap_or(DEFAULT_MAX_SPAWNS_PER_MINUTE);
+        Self {
+            max_frame_size: config.mux_max_frame_size.unwrap_or(DEFAULT_MAX_FRAME_SIZE),
+            flow_control_window: config
+                .mux_flow_control_window
+                .unwrap_or(DEFAULT_FLOW_CONTROL_WINDOW),
+            max_clients: config.mux_server_max_clients.unwrap_or(DEFAULT_MAX_CLIENTS),
+            max_pdus_per_second: config
+                .mux_server_max_pdus_per_second
+                .unwrap_or(DEFAULT_MAX_PDUS_PER_SECOND),
+            active_clients: Arc::new(AtomicUsize::new(0)),
+            spawns: Arc::new(Mutex::new(RateLimiter::new(
+                max_spawns_per_minute,
+                Duration::from_secs(60),
+            ))),
+            auth_token: config.mux_auth_token.clone().map(Arc::new),
+        }
+    }
+
+    /// Attempt to reserve a slot for a new client connection.
+    /// Returns None if the server is already at capacity.
+    /// The slot is released when the returned value is dropped.
+    pub fn try_connect(&self) -> Option<ClientSlot> {
+        let mut current = self.active_clients.load(Ordering::SeqCst);
+        loop {
+            if current >= self.max_clients {
+                return None;
+            }
+            match self.active_clients.compare_exchange(
+                current,
+                current + 1,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ) {
+                Ok(_) => {
+                    return Some(ClientSlot {
+                        active_clients: Arc::clone(&self.active_clients),
+                    })
+                }
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    /// Returns a limiter for the pdu rate of a single connection
+    pub fn pdu_limiter(&self) -> RateLimiter {
+        RateLimiter::new(self.max_pdus_per_second, Duration::from_secs(1))
+    }
+
+    /// Returns true if a spawn request is permitted right now
+    pub fn try_spawn(&self) -> bool {
+        self.spawns.lock().unwrap().try_acquire()
+    }
+
+    /// Returns true if `token` grants access to the server.
+    /// Any token is accepted when the server has none configured.
+    pub fn authenticate(&self, token: Option<&str>) -> bool {
+        match (self.auth_token.as_ref(), token) {
+            (None, _) => true,
+            (Some(expected), Some(token)) => {
+                constant_time_eq(expected.as_bytes(), token.as_bytes())
+            }
+            (Some(_), None) => false,
+        }
+    }
+}
+
+/// Compares two byte strings in a time that doesn't depend on the
+/// position of the first difference, so that a client can't discover
+/// the token a byte at a time.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Represents a connected client for the purposes of enforcing
+/// the max_clients limit.
+pub struct ClientSlot {
+    active_clients: Arc<AtomicUsize>,
+}
+
+impl Drop for ClientSlot {
+    fn drop(&mut self) {
+        self.active_clients.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn burst_limit() {
+        let mut limiter = RateLimiter::new(3, Duration::from_secs(60));
+        assert!(limiter.try_acquire());
+        assert!(limiter.try_acquire());
+        assert!(limiter.try_acquire());
+        assert!(!limiter.try_acquire());
+    }
+
+    #[test]
+    fn refill() {
+        let mut limiter = RateLimiter::new(10, Duration::from_secs(1));
+        let start = limiter.last_refill;
+        limiter.tokens = 0.0;
+
+        limiter.refill(start + Duration::from_millis(500));
+        assert!((limiter.tokens - 5.0).abs() < 1e-6);
+
+        // Idling doesn't build up more than a burst
+        limiter.refill(start + Duration::from_secs(60));
+        assert!((limiter.tokens - 10.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn delay_until_a_token_refills() {
+        let mut limiter = RateLimiter::new(10, Duration::from_secs(1));
+        limiter.tokens = 0.0;
+        let delay = limiter.acquire_delay();
+        assert!(delay > Duration::from_millis(90) && delay <= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn client_slots() {
+        let mut config = Config::default_config();
+        config.mux_server_max_clients = Some(1);
+        let limits = SessionLimits::new(&config);
+        let slot = limits.try_connect();
+        assert!(slot.is_some());
+        assert!(limits.try_connect().is_none());
+        drop(slot);
+        assert!(limits.try_connect().is_some());
+    }
+}
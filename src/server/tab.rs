@@ -1,9 +1,12 @@
+use crate::config::Config;
+use crate::frontend::gui_executor;
 use crate::mux::domain::DomainId;
-use crate::mux::renderable::Renderable;
+use crate::mux::renderable::{LineNumbering, Renderable, ScrollbarInfo};
 use crate::mux::tab::{alloc_tab_id, Tab, TabId};
+use crate::mux::Mux;
 use crate::server::codec::*;
 use crate::server::domain::ClientInner;
-use failure::Fallible;
+use failure::{err_msg, Fallible};
 use filedescriptor::Pipe;
 use log::error;
 use portable_pty::PtySize;
@@ -11,11 +14,13 @@ use promise::Future;
 use std::cell::RefCell;
 use std::cell::RefMut;
 use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use term::color::ColorPalette;
-use term::{CursorPosition, Line};
-use term::{KeyCode, KeyModifiers, MouseEvent, TerminalHost};
+use term::{alloc_seqno, CursorPosition, Line, SequenceNo};
+use term::{CopyModeAction, KeyCode, KeyModifiers, MouseEvent, TerminalHost};
+use termwiz::escape::csi::CursorStyle;
 use termwiz::hyperlink::Hyperlink;
 use termwiz::input::KeyEvent;
 
@@ -26,6 +31,16 @@ pub struct ClientTab {
     renderable: RefCell<RenderableState>,
     writer: RefCell<TabWriter>,
     reader: Pipe,
+    /// Mirrors the copy mode state of the remote tab
+    copy_mode: RefCell<bool>,
+    /// Colors are a local preference, so the palette is managed
+    /// here rather than by the server
+    palette: RefCell<ColorPalette>,
+    /// The most recent size sent to the server
+    size: RefCell<Option<PtySize>>,
+    /// The title chosen by the user, if any; this is a local
+    /// preference that isn't sent to the server
+    title: RefCell<Option<String>>,
 }
 
 impl ClientTab {
@@ -35,14 +50,24 @@ impl ClientTab {
             client: Arc::clone(client),
             remote_tab_id,
         };
+        let subscribed = Arc::new(AtomicBool::new(false));
+        subscribe(client, remote_tab_id, &subscribed);
+
         let render = RenderableState {
             client: Arc::clone(client),
+            local_tab_id,
             remote_tab_id,
             coarse: RefCell::new(None),
+            lines: RefCell::new(vec![]),
+            have_screen: RefCell::new(false),
             last_poll: RefCell::new(Instant::now()),
             dirty_all: RefCell::new(true),
             dead: RefCell::new(false),
-            poll_future: RefCell::new(None),
+            poll_in_progress: RefCell::new(false),
+            subscribed,
+            viewport_offset: RefCell::new(0),
+            history: RefCell::new(None),
+            history_in_progress: RefCell::new(false),
         };
 
         let reader = Pipe::new().expect("Pipe::new failed");
@@ -54,10 +79,159 @@ impl ClientTab {
             renderable: RefCell::new(render),
             writer: RefCell::new(writer),
             reader,
+            copy_mode: RefCell::new(false),
+            palette: RefCell::new(ColorPalette::default()),
+            size: RefCell::new(None),
+            title: RefCell::new(None),
+        }
+    }
+
+    /// Brings the tab up to date once the connection to the server has
+    /// been re-established.  The server has forgotten our subscription
+    /// and the size that we asked for, and didn't send us the changes
+    /// that were made while we were away.
+    fn resync(&self) {
+        let renderable = self.renderable.borrow();
+        renderable.subscribed.store(false, Ordering::SeqCst);
+        subscribe(&self.client, self.remote_tab_id, &renderable.subscribed);
+        *renderable.dirty_all.borrow_mut() = true;
+
+        if let Some(size) = *self.size.borrow() {
+            self.client.client.resize(Resize {
+                tab_id: self.remote_tab_id,
+                size,
+            });
+        }
+        let offset = *renderable.viewport_offset.borrow();
+        if offset > 0 {
+            renderable.history.borrow_mut().take();
+            if let Err(err) = renderable.scroll_viewport(offset) {
+                error!("unable to fetch the scrollback: {}", err);
+            }
+        }
+    }
+
+    /// Asks the server to stop pushing changes to us, before the tab is
+    /// removed without killing the remote tab
+    pub fn unsubscribe(&self) {
+        let remote_tab_id = self.remote_tab_id;
+        self.client
+            .client
+            .unsubscribe(Unsubscribe {
+                tab_id: remote_tab_id,
+            })
+            .then(move |result| {
+                if let Err(err) = result {
+                    error!("unable to unsubscribe from tab {}: {}", remote_tab_id, err);
+                }
+                Future::ok(())
+            });
+    }
+}
+
+/// Asks the server to push changes to the tab to us, setting
+/// `subscribed` once it agrees.  If it doesn't understand the
+/// request, we fall back to polling.
+fn subscribe(client: &Arc<ClientInner>, remote_tab_id: TabId, subscribed: &Arc<AtomicBool>) {
+    let subscribed = Arc::clone(subscribed);
+    client
+        .client
+        .subscribe(Subscribe {
+            tab_id: remote_tab_id,
+        })
+        .then(move |result| {
+            match result {
+                Ok(_) => subscribed.store(true, Ordering::SeqCst),
+                Err(err) => log::warn!(
+                    "unable to subscribe to tab {}, will poll instead: {}",
+                    remote_tab_id,
+                    err
+                ),
+            }
+            Future::ok(())
+        });
+}
+
+/// Resyncs the tabs of the domain after the connection to the server
+/// was re-established.  This must be called on the gui thread.
+pub fn process_reconnect(local_domain_id: DomainId) {
+    let mux = match Mux::get() {
+        Some(mux) => mux,
+        None => return,
+    };
+    for tab in mux.iter_tabs() {
+        if let Some(tab) = tab.downcast_ref::<ClientTab>() {
+            if tab.client.local_domain_id == local_domain_id {
+                tab.resync();
+            }
         }
     }
 }
 
+/// Applies a PDU that was pushed by the server to the matching tab.
+/// This must be called on the gui thread.
+pub fn process_unsolicited(local_domain_id: DomainId, pdu: Pdu) {
+    let mux = match Mux::get() {
+        Some(mux) => mux,
+        None => return,
+    };
+    let remote_tab_id = match &pdu {
+        Pdu::PushTabDelta(PushTabDelta { tab_id, .. })
+        | Pdu::PushTabRemoved(PushTabRemoved { tab_id })
+        | Pdu::SetClipboard(SetClipboard { tab_id, .. })
+        | Pdu::PushColorScheme(PushColorScheme { tab_id, .. }) => *tab_id,
+        _ => {
+            log::debug!("ignoring unsolicited pdu {:?}", pdu);
+            return;
+        }
+    };
+
+    for tab in mux.iter_tabs() {
+        let tab = match tab.downcast_ref::<ClientTab>() {
+            Some(tab) => tab,
+            None => continue,
+        };
+        if tab.client.local_domain_id != local_domain_id || tab.remote_tab_id != remote_tab_id {
+            continue;
+        }
+        match pdu {
+            Pdu::PushTabDelta(PushTabDelta { delta, .. }) => {
+                let cost = delta.flow_control_cost();
+                tab.renderable.borrow().merge(delta, false);
+                // Let the server push more now that we have caught up
+                tab.client
+                    .client
+                    .ack_tab_delta(AckTabDelta {
+                        tab_id: remote_tab_id,
+                        cost,
+                    })
+                    .then(|result| {
+                        if let Err(err) = result {
+                            error!("ack_tab_delta failed: {}", err);
+                        }
+                        Future::ok(())
+                    });
+            }
+            Pdu::PushTabRemoved(_) => *tab.renderable.borrow().dead.borrow_mut() = true,
+            Pdu::SetClipboard(SetClipboard { clipboard, .. }) => {
+                // Our own configuration decides whether the remote
+                // program may reach the local clipboard
+                if let Err(err) = mux.set_clipboard(tab.local_tab_id, clipboard) {
+                    log::debug!("{}", err);
+                }
+            }
+            Pdu::PushColorScheme(PushColorScheme { name, .. }) => {
+                // The scheme is looked up in our own configuration
+                if let Err(err) = mux.set_tab_color_scheme(tab.local_tab_id, &name) {
+                    log::error!("{}", err);
+                }
+            }
+            _ => {}
+        }
+        return;
+    }
+}
+
 impl Tab for ClientTab {
     fn tab_id(&self) -> TabId {
         self.local_tab_id
@@ -67,6 +241,9 @@ impl Tab for ClientTab {
     }
 
     fn get_title(&self) -> String {
+        if let Some(title) = self.title.borrow().as_ref() {
+            return title.clone();
+        }
         let renderable = self.renderable.borrow();
         let coarse = renderable.coarse.borrow();
         format!(
@@ -78,9 +255,18 @@ impl Tab for ClientTab {
         )
     }
 
+    fn set_title(&self, title: Option<String>) {
+        *self.title.borrow_mut() = title;
+    }
+
+    fn get_current_dir(&self) -> Option<String> {
+        let renderable = self.renderable.borrow();
+        let coarse = renderable.coarse.borrow();
+        coarse.as_ref().and_then(|coarse| coarse.cwd.clone())
+    }
+
     fn send_paste(&self, text: &str) -> Fallible<()> {
-        let mut client = self.client.client.lock().unwrap();
-        client.send_paste(SendPaste {
+        self.client.client.send_paste(SendPaste {
             tab_id: self.remote_tab_id,
             data: text.to_owned(),
         });
@@ -97,8 +283,15 @@ impl Tab for ClientTab {
     }
 
     fn resize(&self, size: PtySize) -> Fallible<()> {
-        let mut client = self.client.client.lock().unwrap();
-        client.resize(Resize {
+        // Changing the font size resizes the window to preserve the
+        // rows and columns; there is no need to involve the server
+        // in that case.
+        if let Some(prior) = self.size.borrow_mut().replace(size) {
+            if prior.rows == size.rows && prior.cols == size.cols {
+                return Ok(());
+            }
+        }
+        self.client.client.resize(Resize {
             tab_id: self.remote_tab_id,
             size,
         });
@@ -106,8 +299,7 @@ impl Tab for ClientTab {
     }
 
     fn key_down(&self, key: KeyCode, mods: KeyModifiers) -> Fallible<()> {
-        let mut client = self.client.client.lock().unwrap();
-        client.key_down(SendKeyDown {
+        self.client.client.key_down(SendKeyDown {
             tab_id: self.remote_tab_id,
             event: KeyEvent {
                 key,
@@ -118,8 +310,9 @@ impl Tab for ClientTab {
     }
 
     fn mouse_event(&self, event: MouseEvent, host: &mut dyn TerminalHost) -> Fallible<()> {
-        let mut client = self.client.client.lock().unwrap();
-        let resp = client
+        let resp = self
+            .client
+            .client
             .mouse_event(SendMouseEvent {
                 tab_id: self.remote_tab_id,
                 event,
@@ -133,6 +326,14 @@ impl Tab for ClientTab {
         Ok(())
     }
 
+    fn focus_changed(&self, focused: bool) -> Fallible<()> {
+        self.client.client.focus_changed(SendFocus {
+            tab_id: self.remote_tab_id,
+            focused,
+        });
+        Ok(())
+    }
+
     fn advance_bytes(&self, _buf: &[u8], _host: &mut dyn TerminalHost) {
         panic!("ClientTab::advance_bytes not impl");
     }
@@ -145,128 +346,437 @@ impl Tab for ClientTab {
         dead
     }
 
+    fn status_banner(&self) -> Option<String> {
+        if self.client.is_connected() {
+            None
+        } else {
+            Some(format!(
+                "Connection to {} lost; retrying",
+                self.client.name()
+            ))
+        }
+    }
+
     fn palette(&self) -> ColorPalette {
-        Default::default()
+        self.palette.borrow().clone()
+    }
+
+    fn set_palette(&self, palette: ColorPalette) {
+        *self.palette.borrow_mut() = palette;
+        self.renderable.borrow_mut().make_all_lines_dirty();
     }
 
     fn domain_id(&self) -> DomainId {
         self.client.local_domain_id
     }
+
+    fn copy_mode(&self, action: CopyModeAction) -> Fallible<Option<String>> {
+        let resp = self
+            .client
+            .client
+            .copy_mode(CopyMode {
+                tab_id: self.remote_tab_id,
+                action,
+            })
+            .wait()?;
+        *self.copy_mode.borrow_mut() = resp.active;
+        Ok(resp.clipboard)
+    }
+
+    fn in_copy_mode(&self) -> bool {
+        *self.copy_mode.borrow()
+    }
+
+    fn hyperlink_near_cursor(&self) -> Fallible<Option<Arc<Hyperlink>>> {
+        let resp = self
+            .client
+            .client
+            .hyperlink_near_cursor(GetHyperlinkNearCursor {
+                tab_id: self.remote_tab_id,
+            })
+            .wait()?;
+        Ok(resp.link.map(|link| Arc::new(link.to_hyperlink())))
+    }
+
+    fn config_was_reloaded(&self, _config: &Config) {
+        // The terminal model lives in the server, which applies
+        // its own configuration to it
+    }
 }
 
 struct RenderableState {
     client: Arc<ClientInner>,
+    local_tab_id: TabId,
     remote_tab_id: TabId,
+    /// The most recent update from the server.  Its dirty lines are
+    /// moved into `lines` as they arrive.
     coarse: RefCell<Option<GetCoarseTabRenderableDataResponse>>,
+    /// The local copy of the screen, and the selected range of each line.
+    /// Lines that arrive from the server are dirty until they are
+    /// assigned a sequence number by `current_seqno`.
+    lines: RefCell<Vec<(Line, Range<usize>)>>,
+    /// Set once `lines` holds a complete copy of the screen
+    have_screen: RefCell<bool>,
     last_poll: RefCell<Instant>,
     dirty_all: RefCell<bool>,
     dead: RefCell<bool>,
-    poll_future: RefCell<Option<Future<GetCoarseTabRenderableDataResponse>>>,
+    poll_in_progress: RefCell<bool>,
+    /// Set once the server has agreed to push changes to us
+    subscribed: Arc<AtomicBool>,
+    /// How many rows the viewport is scrolled back by.  The server's
+    /// viewport isn't moved; the lines shown are fetched from it.
+    viewport_offset: RefCell<usize>,
+    /// The lines fetched for the viewport, along with the offset that
+    /// they were fetched for
+    history: RefCell<Option<(usize, Vec<Line>)>>,
+    history_in_progress: RefCell<bool>,
 }
 
 const POLL_INTERVAL: Duration = Duration::from_millis(50);
 
 impl RenderableState {
     fn poll(&self) -> Fallible<()> {
-        let ready = self
-            .poll_future
-            .borrow()
-            .as_ref()
-            .map(Future::is_ready)
-            .unwrap_or(false);
-        if ready {
-            let coarse = self.poll_future.borrow_mut().take().unwrap().wait()?;
-            self.coarse.borrow_mut().replace(coarse);
-            log::trace!(
-                "poll: got coarse data in {:?}",
-                self.last_poll.borrow().elapsed()
-            );
-            *self.last_poll.borrow_mut() = Instant::now();
-        } else if self.poll_future.borrow().is_some() {
-            // We have a poll in progress
+        if *self.poll_in_progress.borrow() || !self.client.is_connected() {
+            // We'll catch up once the connection is re-established
             return Ok(());
         }
 
         let dirty_all = *self.dirty_all.borrow();
 
         if !dirty_all {
+            if self.subscribed.load(Ordering::SeqCst) {
+                // The server will let us know when there are changes
+                return Ok(());
+            }
             let last = *self.last_poll.borrow();
             if last.elapsed() < POLL_INTERVAL {
                 return Ok(());
             }
         }
 
-        {
-            let mut client = self.client.client.lock().unwrap();
-            *self.poll_future.borrow_mut() = Some(client.get_coarse_tab_renderable_data(
-                GetCoarseTabRenderableData {
-                    tab_id: self.remote_tab_id,
-                    dirty_all,
-                },
-            ));
-        }
+        let executor = gui_executor().ok_or_else(|| err_msg("no gui executor"))?;
+        let local_tab_id = self.local_tab_id;
+        self.client
+            .client
+            .get_coarse_tab_renderable_data(GetCoarseTabRenderableData {
+                tab_id: self.remote_tab_id,
+                dirty_all,
+            })
+            .then(move |result| {
+                // The response arrives on the client thread, but the
+                // tab can only be accessed from the gui thread
+                Future::with_executor(executor, move || {
+                    Self::apply_coarse_data(local_tab_id, result, dirty_all);
+                    Future::ok(())
+                })
+            });
+
+        *self.poll_in_progress.borrow_mut() = true;
         *self.dirty_all.borrow_mut() = false;
         Ok(())
     }
+
+    /// Incorporate an update from the server into the local copy of
+    /// the screen.  `complete` is true if the update contains all of
+    /// the lines of the screen.
+    fn merge(&self, mut coarse: GetCoarseTabRenderableDataResponse, complete: bool) {
+        let rows = coarse.physical_rows;
+        let cols = coarse.physical_cols;
+        let mut lines = self.lines.borrow_mut();
+
+        lines.resize_with(rows, || (Line::with_width(cols), 0..0));
+
+        for dl in coarse.dirty_lines.drain(..) {
+            if dl.line_idx < rows {
+                lines[dl.line_idx] = (
+                    dl.line.to_line(),
+                    dl.selection_col_from..dl.selection_col_to,
+                );
+            }
+        }
+
+        if complete {
+            *self.have_screen.borrow_mut() = true;
+        }
+
+        // Keep the same lines in view while scrolled back, even as
+        // more of them scroll off the top of the screen
+        let prior_scrollback = self
+            .coarse
+            .borrow()
+            .as_ref()
+            .map(|prior| prior.scrollback_rows)
+            .unwrap_or(0);
+        let scrollback_rows = coarse.scrollback_rows;
+        self.coarse.borrow_mut().replace(coarse);
+        let offset = *self.viewport_offset.borrow();
+        if offset > 0 && scrollback_rows != prior_scrollback {
+            let offset = (offset + scrollback_rows)
+                .saturating_sub(prior_scrollback)
+                .min(scrollback_rows);
+            if let Err(err) = self.scroll_viewport(offset) {
+                error!("unable to fetch the scrollback: {}", err);
+            }
+        }
+    }
+
+    /// Moves the viewport and, unless it is at the bottom, asks the
+    /// server for the lines that it shows
+    fn scroll_viewport(&self, offset: usize) -> Fallible<()> {
+        *self.viewport_offset.borrow_mut() = offset;
+        if offset == 0 {
+            self.history.borrow_mut().take();
+            for (line, _) in self.lines.borrow_mut().iter_mut() {
+                line.set_dirty();
+            }
+            return Ok(());
+        }
+        if *self.history_in_progress.borrow() {
+            // The response is checked against the current offset when
+            // it arrives, and fetched again if it is out of date
+            return Ok(());
+        }
+
+        let executor = gui_executor().ok_or_else(|| err_msg("no gui executor"))?;
+        let local_tab_id = self.local_tab_id;
+        // Only the rows above the screen are fetched; when scrolled back
+        // by fewer rows than the screen has, the rest of the viewport
+        // shows the top of the screen, which is kept up to date here
+        let (rows, _cols) = self.physical_dimensions();
+        self.client
+            .client
+            .get_tab_scrollback(GetTabScrollback {
+                tab_id: self.remote_tab_id,
+                viewport_offset: offset,
+                rows: rows.min(offset),
+            })
+            .then(move |result| {
+                Future::with_executor(executor, move || {
+                    Self::apply_scrollback(local_tab_id, offset, result);
+                    Future::ok(())
+                })
+            });
+        *self.history_in_progress.borrow_mut() = true;
+        Ok(())
+    }
+
+    /// Called on the gui thread when the response to a request for
+    /// the scrollback arrives
+    fn apply_scrollback(
+        local_tab_id: TabId,
+        offset: usize,
+        result: Fallible<GetTabScrollbackResponse>,
+    ) {
+        let mux = match Mux::get() {
+            Some(mux) => mux,
+            None => return,
+        };
+        let tab = match mux.get_tab(local_tab_id) {
+            Some(tab) => tab,
+            None => return,
+        };
+        let tab = match tab.downcast_ref::<ClientTab>() {
+            Some(tab) => tab,
+            None => return,
+        };
+        let renderable = tab.renderable.borrow();
+        *renderable.history_in_progress.borrow_mut() = false;
+        let response = match result {
+            Ok(response) => response,
+            Err(err) => {
+                error!("remote tab {} scrollback failed: {}", local_tab_id, err);
+                return;
+            }
+        };
+        let current = *renderable.viewport_offset.borrow();
+        if current == 0 {
+            return;
+        }
+        if current != offset {
+            // The viewport moved while the request was in flight
+            if let Err(err) = renderable.scroll_viewport(current) {
+                error!("unable to fetch the scrollback: {}", err);
+            }
+            return;
+        }
+        let mut lines: Vec<Line> = response
+            .lines
+            .into_iter()
+            .map(|line| line.to_line())
+            .collect();
+        lines.truncate(offset);
+        for line in lines.iter_mut() {
+            line.set_dirty();
+        }
+        // The lines of the screen below the history have moved
+        for (line, _) in renderable.lines.borrow_mut().iter_mut() {
+            line.set_dirty();
+        }
+        renderable.history.borrow_mut().replace((offset, lines));
+    }
+
+    /// Returns the lines fetched for the viewport, if it is scrolled
+    /// back and they have arrived, along with the lines at the top of
+    /// the screen that fill the rest of the viewport below them
+    fn with_history<R, F>(&self, func: F) -> Option<R>
+    where
+        F: FnOnce(&mut Vec<Line>, &mut [(Line, Range<usize>)]) -> R,
+    {
+        let offset = *self.viewport_offset.borrow();
+        let mut history = self.history.borrow_mut();
+        match history.as_mut() {
+            Some((fetched, lines)) => {
+                if offset > 0 && *fetched == offset {
+                    let mut screen = self.lines.borrow_mut();
+                    let (rows, _cols) = self.physical_dimensions();
+                    let below = rows.saturating_sub(lines.len()).min(screen.len());
+                    Some(func(lines, &mut screen[..below]))
+                } else {
+                    None
+                }
+            }
+            None => None,
+        }
+    }
+
+    /// Called on the gui thread when the response to a poll arrives
+    fn apply_coarse_data(
+        local_tab_id: TabId,
+        result: Fallible<GetCoarseTabRenderableDataResponse>,
+        complete: bool,
+    ) {
+        let mux = match Mux::get() {
+            Some(mux) => mux,
+            None => return,
+        };
+        let tab = match mux.get_tab(local_tab_id) {
+            Some(tab) => tab,
+            None => return,
+        };
+        let tab = match tab.downcast_ref::<ClientTab>() {
+            Some(tab) => tab,
+            None => return,
+        };
+        let renderable = tab.renderable.borrow();
+        *renderable.poll_in_progress.borrow_mut() = false;
+        match result {
+            Ok(coarse) => {
+                renderable.merge(coarse, complete);
+                log::trace!(
+                    "poll: got coarse data in {:?}",
+                    renderable.last_poll.borrow().elapsed()
+                );
+                *renderable.last_poll.borrow_mut() = Instant::now();
+            }
+            Err(err) => {
+                error!("remote tab {} poll failed: {}", local_tab_id, err);
+                if renderable.client.is_connected() {
+                    *renderable.dead.borrow_mut() = true;
+                }
+            }
+        }
+    }
 }
 
 impl Renderable for RenderableState {
     fn get_cursor_position(&self) -> CursorPosition {
         let coarse = self.coarse.borrow();
         if let Some(coarse) = coarse.as_ref() {
-            coarse.cursor_position
+            let mut cursor: CursorPosition = coarse.cursor_position.into();
+            // The cursor moves down along with the screen as the
+            // viewport is scrolled back
+            cursor.y += *self.viewport_offset.borrow() as i64;
+            cursor
         } else {
             CursorPosition::default()
         }
     }
 
-    fn get_dirty_lines(&self) -> Vec<(usize, Line, Range<usize>)> {
-        let coarse = self.coarse.borrow();
-        if let Some(coarse) = coarse.as_ref() {
-            coarse
-                .dirty_lines
+    fn get_cursor_style(&self) -> CursorStyle {
+        // The cursor style isn't sent by the server, so remote tabs
+        // use the style from the local configuration
+        CursorStyle::Default
+    }
+
+    fn current_seqno(&mut self) -> SequenceNo {
+        let seqno = alloc_seqno();
+        for (line, _) in self.lines.borrow_mut().iter_mut() {
+            line.update_last_change_seqno(seqno);
+        }
+        self.with_history(|lines, _| {
+            for line in lines.iter_mut() {
+                line.update_last_change_seqno(seqno);
+            }
+        });
+        seqno
+    }
+
+    fn get_changed_lines(&self, seqno: SequenceNo) -> Vec<(usize, Line, Range<usize>)> {
+        if let Some(changed) = self.with_history(|lines, screen| {
+            let screen = screen.iter().map(|(line, sel)| (line, sel.clone()));
+            lines
                 .iter()
-                .map(|dl| {
-                    (
-                        dl.line_idx,
-                        dl.line.clone(),
-                        dl.selection_col_from..dl.selection_col_to,
-                    )
-                })
-                .collect()
-        } else {
-            vec![]
+                .map(|line| (line, 0..0))
+                .chain(screen)
+                .enumerate()
+                .filter(|(_, (line, _))| line.changed_since(seqno))
+                .map(|(idx, (line, sel))| (idx, line.clone(), sel))
+                .collect::<Vec<_>>()
+        }) {
+            return changed;
         }
+        self.lines
+            .borrow()
+            .iter()
+            .enumerate()
+            .filter(|(_, (line, _))| line.changed_since(seqno))
+            .map(|(idx, (line, sel))| (idx, line.clone(), sel.clone()))
+            .collect()
     }
 
-    fn has_dirty_lines(&self) -> bool {
+    fn has_changes_since(&self, seqno: SequenceNo) -> bool {
         if self.poll().is_err() {
             *self.dead.borrow_mut() = true;
         }
 
-        let coarse = self.coarse.borrow();
-        if let Some(coarse) = coarse.as_ref() {
-            !coarse.dirty_lines.is_empty()
-        } else {
-            false
+        if let Some(changed) = self.with_history(|lines, screen| {
+            lines.iter().any(|line| line.changed_since(seqno))
+                || screen.iter().any(|(line, _)| line.changed_since(seqno))
+        }) {
+            return changed;
         }
+        self.lines
+            .borrow()
+            .iter()
+            .any(|(line, _)| line.changed_since(seqno))
     }
 
     fn make_all_lines_dirty(&mut self) {
-        *self.dirty_all.borrow_mut() = true;
-    }
-
-    fn clean_dirty_lines(&mut self) {
-        if let Some(c) = self.coarse.borrow_mut().as_mut() {
-            c.dirty_lines.clear()
+        self.with_history(|lines, _| {
+            for line in lines.iter_mut() {
+                line.set_dirty();
+            }
+        });
+        if *self.have_screen.borrow() {
+            // We already have a copy of the screen, so there is no
+            // need to ask the server for it again.  This keeps
+            // things like font size changes local to the client.
+            for (line, _) in self.lines.borrow_mut().iter_mut() {
+                line.set_dirty();
+            }
+        } else {
+            *self.dirty_all.borrow_mut() = true;
         }
     }
 
     fn current_highlight(&self) -> Option<Arc<Hyperlink>> {
         let coarse = self.coarse.borrow();
-        coarse
-            .as_ref()
-            .and_then(|coarse| coarse.current_highlight.clone())
+        coarse.as_ref().and_then(|coarse| {
+            coarse
+                .current_highlight
+                .as_ref()
+                .map(|link| Arc::new(link.to_hyperlink()))
+        })
     }
 
     fn physical_dimensions(&self) -> (usize, usize) {
@@ -277,6 +787,65 @@ impl Renderable for RenderableState {
             (24, 80)
         }
     }
+
+    fn scrollbar_info(&self) -> ScrollbarInfo {
+        let coarse = self.coarse.borrow();
+        ScrollbarInfo {
+            scrollback_rows: coarse
+                .as_ref()
+                .map(|coarse| coarse.scrollback_rows)
+                .unwrap_or(0),
+            viewport_offset: *self.viewport_offset.borrow(),
+        }
+    }
+
+    fn line_numbering(&self) -> LineNumbering {
+        // The server doesn't say how many lines it discarded, so only the
+        // lines in view can be told apart
+        LineNumbering::default()
+    }
+
+    fn set_viewport_offset(&mut self, offset: usize) {
+        let offset = offset.min(self.scrollbar_info().scrollback_rows);
+        if offset == *self.viewport_offset.borrow() {
+            return;
+        }
+        if let Err(err) = self.scroll_viewport(offset) {
+            error!("unable to fetch the scrollback: {}", err);
+        }
+    }
+
+    fn get_viewport_lines(&mut self, offset: usize, rows: usize) -> Vec<Line> {
+        // Only the lines that are currently held here are available
+        if let Some(lines) = self.with_history(|lines, screen| {
+            let screen = screen.iter().map(|(line, _)| line.clone());
+            lines.iter().cloned().chain(screen).collect::<Vec<_>>()
+        }) {
+            if offset == *self.viewport_offset.borrow() {
+                return lines.into_iter().take(rows).collect();
+            }
+        }
+        if offset == 0 {
+            return self
+                .lines
+                .borrow()
+                .iter()
+                .take(rows)
+                .map(|(line, _)| line.clone())
+                .collect();
+        }
+        vec![]
+    }
+
+    fn scroll_to_prompt(&mut self, _delta: isize) {}
+
+    fn is_mouse_grabbed(&self) -> bool {
+        self.coarse
+            .borrow()
+            .as_ref()
+            .map(|coarse| coarse.mouse_grabbed)
+            .unwrap_or(false)
+    }
 }
 
 struct TabWriter {
@@ -286,14 +855,22 @@ struct TabWriter {
 
 impl std::io::Write for TabWriter {
     fn write(&mut self, data: &[u8]) -> Result<usize, std::io::Error> {
-        let mut client = self.client.client.lock().unwrap();
-        client
+        // Don't wait for the response; doing so would stall the
+        // gui thread for a full round trip on every keystroke.
+        // Writes are delivered to the server in the order that
+        // they are issued, so failures are merely logged.
+        self.client
+            .client
             .write_to_tab(WriteToTab {
                 tab_id: self.remote_tab_id,
                 data: data.to_vec(),
             })
-            .wait()
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("{}", e)))?;
+            .then(|result| {
+                if let Err(err) = result {
+                    error!("write_to_tab failed: {}", err);
+                }
+                Future::ok(())
+            });
         Ok(data.len())
     }
 
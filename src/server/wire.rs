@@ -0,0 +1,467 @@
+//! Stable wire representations of the terminal model types that are
+//! exchanged by the mux client and server.
+//! The term and termwiz types derive their serde implementations from
+//! their in-memory layout, so any change to those crates would silently
+//! change the protocol.  The types in this module are decoupled from
+//! that layout and are versioned: a new representation is added as a
+//! new variant, and older variants continue to be accepted so that
+//! mismatched client and server versions can interoperate.
+use serde_derive::*;
+use std::collections::HashMap;
+use std::sync::Arc;
+use term::color::{ColorAttribute, RgbColor};
+use term::{Blink, Cell, CellAttributes, CursorPosition, Intensity, Line, Underline};
+use termwiz::hyperlink::Hyperlink;
+
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub struct WireCursorPosition {
+    pub x: u64,
+    pub y: i64,
+}
+
+impl From<CursorPosition> for WireCursorPosition {
+    fn from(pos: CursorPosition) -> Self {
+        Self {
+            x: pos.x as u64,
+            y: pos.y,
+        }
+    }
+}
+
+impl From<WireCursorPosition> for CursorPosition {
+    fn from(pos: WireCursorPosition) -> Self {
+        Self {
+            x: pos.x as usize,
+            y: pos.y,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+pub enum WireHyperlink {
+    V1(HyperlinkV1),
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+pub struct HyperlinkV1 {
+    pub uri: String,
+    /// Sorted by key so that the encoding is deterministic
+    pub params: Vec<(String, String)>,
+    pub implicit: bool,
+}
+
+impl From<&Hyperlink> for WireHyperlink {
+    fn from(link: &Hyperlink) -> Self {
+        let mut params: Vec<(String, String)> = link
+            .params()
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        params.sort();
+        WireHyperlink::V1(HyperlinkV1 {
+            uri: link.uri().to_owned(),
+            params,
+            implicit: link.is_implicit(),
+        })
+    }
+}
+
+impl WireHyperlink {
+    pub fn to_hyperlink(&self) -> Hyperlink {
+        match self {
+            WireHyperlink::V1(link) => {
+                if link.implicit {
+                    Hyperlink::new_implicit(link.uri.as_str())
+                } else {
+                    let params: HashMap<String, String> = link.params.iter().cloned().collect();
+                    Hyperlink::new_with_params(link.uri.as_str(), params)
+                }
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ColorV1 {
+    Default,
+    PaletteIndex(u8),
+    TrueColor(u8, u8, u8),
+    TrueColorWithPaletteFallback(u8, u8, u8, u8),
+}
+
+impl From<ColorAttribute> for ColorV1 {
+    fn from(color: ColorAttribute) -> Self {
+        match color {
+            ColorAttribute::Default => ColorV1::Default,
+            ColorAttribute::PaletteIndex(idx) => ColorV1::PaletteIndex(idx),
+            ColorAttribute::TrueColorWithDefaultFallback(c) => {
+                ColorV1::TrueColor(c.red, c.green, c.blue)
+            }
+            ColorAttribute::TrueColorWithPaletteFallback(c, idx) => {
+                ColorV1::TrueColorWithPaletteFallback(c.red, c.green, c.blue, idx)
+            }
+        }
+    }
+}
+
+impl From<ColorV1> for ColorAttribute {
+    fn from(color: ColorV1) -> Self {
+        match color {
+            ColorV1::Default => ColorAttribute::Default,
+            ColorV1::PaletteIndex(idx) => ColorAttribute::PaletteIndex(idx),
+            ColorV1::TrueColor(r, g, b) => {
+                ColorAttribute::TrueColorWithDefaultFallback(RgbColor::new(r, g, b))
+            }
+            ColorV1::TrueColorWithPaletteFallback(r, g, b, idx) => {
+                ColorAttribute::TrueColorWithPaletteFallback(RgbColor::new(r, g, b), idx)
+            }
+        }
+    }
+}
+
+/// The attributes of a cell.  Image attachments are not transmitted.
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+pub struct CellAttributesV1 {
+    /// 0 is normal, 1 is bold, 2 is half-bright
+    pub intensity: u8,
+    /// 0 is none, 1 is single, 2 is double
+    pub underline: u8,
+    /// 0 is none, 1 is slow, 2 is rapid
+    pub blink: u8,
+    pub italic: bool,
+    pub reverse: bool,
+    pub strikethrough: bool,
+    pub invisible: bool,
+    pub wrapped: bool,
+    pub foreground: ColorV1,
+    pub background: ColorV1,
+    pub hyperlink: Option<WireHyperlink>,
+}
+
+impl From<&CellAttributes> for CellAttributesV1 {
+    fn from(attrs: &CellAttributes) -> Self {
+        Self {
+            intensity: match attrs.intensity() {
+                Intensity::Normal => 0,
+                Intensity::Bold => 1,
+                Intensity::Half => 2,
+            },
+            underline: match attrs.underline() {
+                Underline::None => 0,
+                Underline::Single => 1,
+                Underline::Double => 2,
+            },
+            blink: match attrs.blink() {
+                Blink::None => 0,
+                Blink::Slow => 1,
+                Blink::Rapid => 2,
+            },
+            italic: attrs.italic(),
+            reverse: attrs.reverse(),
+            strikethrough: attrs.strikethrough(),
+            invisible: attrs.invisible(),
+            wrapped: attrs.wrapped(),
+            foreground: attrs.foreground.into(),
+            background: attrs.background.into(),
+            hyperlink: attrs
+                .hyperlink
+                .as_ref()
+                .map(|link| WireHyperlink::from(&**link)),
+        }
+    }
+}
+
+impl CellAttributesV1 {
+    /// Unrecognized enum values are mapped to the default for that
+    /// attribute rather than being treated as an error
+    pub fn to_cell_attributes(&self) -> CellAttributes {
+        let mut attrs = CellAttributes::default();
+        attrs
+            .set_intensity(match self.intensity {
+                1 => Intensity::Bold,
+                2 => Intensity::Half,
+                _ => Intensity::Normal,
+            })
+            .set_underline(match self.underline {
+                1 => Underline::Single,
+                2 => Underline::Double,
+                _ => Underline::None,
+            })
+            .set_blink(match self.blink {
+                1 => Blink::Slow,
+                2 => Blink::Rapid,
+                _ => Blink::None,
+            })
+            .set_italic(self.italic)
+            .set_reverse(self.reverse)
+            .set_strikethrough(self.strikethrough)
+            .set_invisible(self.invisible)
+            .set_wrapped(self.wrapped)
+            .set_foreground(self.foreground)
+            .set_background(self.background)
+            .set_hyperlink(
+                self.hyperlink
+                    .as_ref()
+                    .map(|link| Arc::new(link.to_hyperlink())),
+            );
+        attrs
+    }
+}
+
+/// The attributes of a cell, including those that were added after
+/// `CellAttributesV1` was frozen
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+pub struct CellAttributesV2 {
+    pub base: CellAttributesV1,
+    pub overline: bool,
+}
+
+impl From<&CellAttributes> for CellAttributesV2 {
+    fn from(attrs: &CellAttributes) -> Self {
+        Self {
+            base: attrs.into(),
+            overline: attrs.overline(),
+        }
+    }
+}
+
+impl CellAttributesV2 {
+    pub fn to_cell_attributes(&self) -> CellAttributes {
+        let mut attrs = self.base.to_cell_attributes();
+        attrs.set_overline(self.overline);
+        attrs
+    }
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+pub struct CellV1 {
+    pub text: String,
+    /// Index into the `attrs` of the containing line
+    pub attr_idx: u32,
+}
+
+/// Runs of cells tend to share the same attributes, so each distinct
+/// set of attributes is sent once per line and referenced by index.
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+pub struct LineV1 {
+    pub attrs: Vec<CellAttributesV1>,
+    pub cells: Vec<CellV1>,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+pub struct LineV2 {
+    pub attrs: Vec<CellAttributesV2>,
+    pub cells: Vec<CellV1>,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+pub enum WireLine {
+    V1(LineV1),
+    V2(LineV2),
+}
+
+impl From<&Line> for WireLine {
+    fn from(line: &Line) -> Self {
+        let mut distinct: Vec<&CellAttributes> = vec![];
+        let mut cells = Vec::with_capacity(line.cells().len());
+
+        for cell in line.cells() {
+            let attr_idx = match distinct.iter().rposition(|a| *a == cell.attrs()) {
+                Some(idx) => idx,
+                None => {
+                    distinct.push(cell.attrs());
+                    distinct.len() - 1
+                }
+            };
+            cells.push(CellV1 {
+                text: cell.str().to_owned(),
+                attr_idx: attr_idx as u32,
+            });
+        }
+
+        WireLine::V2(LineV2 {
+            attrs: distinct.into_iter().map(CellAttributesV2::from).collect(),
+            cells,
+        })
+    }
+}
+
+impl WireLine {
+    pub fn to_line(&self) -> Line {
+        match self {
+            WireLine::V1(wire) => line_from_cells(
+                wire.attrs
+                    .iter()
+                    .map(CellAttributesV1::to_cell_attributes)
+                    .collect(),
+                &wire.cells,
+            ),
+            WireLine::V2(wire) => line_from_cells(
+                wire.attrs
+                    .iter()
+                    .map(CellAttributesV2::to_cell_attributes)
+                    .collect(),
+                &wire.cells,
+            ),
+        }
+    }
+}
+
+fn line_from_cells(attrs: Vec<CellAttributes>, cells: &[CellV1]) -> Line {
+    let cells = cells
+        .iter()
+        .map(|cell| {
+            let cell_attrs = attrs
+                .get(cell.attr_idx as usize)
+                .cloned()
+                .unwrap_or_default();
+            Cell::new_grapheme(&cell.text, cell_attrs)
+        })
+        .collect();
+    Line::from_cells(cells)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn round_trip(line: &Line) -> Line {
+        let wire = WireLine::from(line);
+        let mut encoded = vec![];
+        {
+            let mut encode = varbincode::Serializer::new(&mut encoded);
+            serde::Serialize::serialize(&wire, &mut encode).unwrap();
+        }
+        let mut decode = varbincode::Deserializer::new(encoded.as_slice());
+        let decoded: WireLine = serde::Deserialize::deserialize(&mut decode).unwrap();
+        assert_eq!(decoded, wire);
+        decoded.to_line()
+    }
+
+    #[test]
+    fn plain_line() {
+        let line = Line::from_text("hello world", &CellAttributes::default());
+        assert_eq!(round_trip(&line), line);
+    }
+
+    #[test]
+    fn attributes_are_shared() {
+        let mut bold = CellAttributes::default();
+        bold.set_intensity(Intensity::Bold);
+        let mut line = Line::from_text("abcd", &CellAttributes::default());
+        line.set_cell(1, Cell::new('b', bold.clone()));
+        line.set_cell(3, Cell::new('d', bold));
+
+        match WireLine::from(&line) {
+            WireLine::V2(wire) => {
+                assert_eq!(wire.attrs.len(), 2);
+                let indices: Vec<u32> = wire.cells.iter().map(|c| c.attr_idx).collect();
+                assert_eq!(indices, vec![0, 1, 0, 1]);
+            }
+            other => panic!("expected a V2 line, got {:?}", other),
+        }
+        assert_eq!(round_trip(&line), line);
+    }
+
+    #[test]
+    fn overline() {
+        let mut attrs = CellAttributes::default();
+        attrs.set_overline(true).set_strikethrough(true);
+        let line = Line::from_text("over", &attrs);
+        assert_eq!(round_trip(&line), line);
+    }
+
+    #[test]
+    fn hyperlinks_and_wide_graphemes() {
+        let mut attrs = CellAttributes::default();
+        attrs
+            .set_hyperlink(Some(Arc::new(Hyperlink::new_with_id(
+                "https://example.com",
+                "an-id",
+            ))))
+            .set_foreground(ColorAttribute::TrueColorWithPaletteFallback(
+                RgbColor::new(1, 2, 3),
+                4,
+            ));
+        let mut line = Line::with_width(4);
+        line.set_cell(0, Cell::new_grapheme("\u{1f468}", attrs));
+        line.set_cell(2, Cell::new('x', CellAttributes::default()));
+        assert_eq!(round_trip(&line), line);
+    }
+
+    /// The V1 representation is frozen: a peer built from an older
+    /// version of the code will send exactly this structure, and it
+    /// must continue to decode to the same line.
+    #[test]
+    fn decode_v1() {
+        let wire = WireLine::V1(LineV1 {
+            attrs: vec![
+                CellAttributesV1 {
+                    intensity: 0,
+                    underline: 0,
+                    blink: 0,
+                    italic: false,
+                    reverse: false,
+                    strikethrough: false,
+                    invisible: false,
+                    wrapped: false,
+                    foreground: ColorV1::Default,
+                    background: ColorV1::Default,
+                    hyperlink: None,
+                },
+                CellAttributesV1 {
+                    intensity: 1,
+                    underline: 2,
+                    blink: 0,
+                    italic: true,
+                    reverse: false,
+                    strikethrough: false,
+                    invisible: false,
+                    wrapped: true,
+                    foreground: ColorV1::PaletteIndex(1),
+                    background: ColorV1::TrueColor(0x10, 0x20, 0x30),
+                    hyperlink: Some(WireHyperlink::V1(HyperlinkV1 {
+                        uri: "http://localhost".to_owned(),
+                        params: vec![],
+                        implicit: true,
+                    })),
+                },
+            ],
+            cells: vec![
+                CellV1 {
+                    text: "o".to_owned(),
+                    attr_idx: 0,
+                },
+                CellV1 {
+                    text: "k".to_owned(),
+                    attr_idx: 1,
+                },
+            ],
+        });
+
+        let mut styled = CellAttributes::default();
+        styled
+            .set_intensity(Intensity::Bold)
+            .set_underline(Underline::Double)
+            .set_italic(true)
+            .set_wrapped(true)
+            .set_foreground(ColorAttribute::PaletteIndex(1))
+            .set_background(ColorAttribute::TrueColorWithDefaultFallback(RgbColor::new(
+                0x10, 0x20, 0x30,
+            )))
+            .set_hyperlink(Some(Arc::new(Hyperlink::new_implicit("http://localhost"))));
+        let mut expected = Line::with_width(2);
+        expected.set_cell(0, Cell::new('o', CellAttributes::default()));
+        expected.set_cell(1, Cell::new('k', styled));
+
+        assert_eq!(wire.to_line(), expected);
+    }
+
+    #[test]
+    fn cursor_position() {
+        let pos = CursorPosition { x: 12, y: -3 };
+        let wire: WireCursorPosition = pos.into();
+        let back: CursorPosition = wire.into();
+        assert_eq!(back, pos);
+    }
+}
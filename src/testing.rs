@@ -0,0 +1,316 @@
+//! Test support for driving real commands under the portable pty.
+//! A `PtyHarness` wires up a `LocalDomain`, a `Mux` and the headless
+//! frontend so that tests can spawn a command, feed it scripted input
+//! and then make assertions about the resulting terminal model.
+//! A `FakeTab` stands in for a tab where no program is needed.
+use crate::config::Config;
+use crate::frontend::headless::HeadlessFrontEnd;
+use crate::frontend::{FrontEnd, FrontEndSelection};
+use crate::mux::domain::{Domain, DomainId, LocalDomain};
+use crate::mux::renderable::Renderable;
+use crate::mux::tab::{alloc_tab_id, Tab, TabId};
+use crate::mux::Mux;
+use failure::{bail, Fallible};
+use lazy_static::lazy_static;
+use portable_pty::cmdbuilder::CommandBuilder;
+use portable_pty::PtySize;
+use std::cell::{RefCell, RefMut};
+use std::io::Write;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use term::color::ColorPalette;
+use term::{CopyModeAction, KeyCode, KeyModifiers, MouseEvent, Terminal, TerminalHost};
+use termwiz::hyperlink::Hyperlink;
+
+lazy_static! {
+    /// The gui executor is process-wide state, so only a single
+    /// harness may be active at a time.
+    static ref HARNESS_LOCK: Mutex<()> = Mutex::new(());
+}
+
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct PtyHarness {
+    front_end: Rc<dyn FrontEnd>,
+    mux: Rc<Mux>,
+    tab: Rc<dyn Tab>,
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl PtyHarness {
+    /// Spawn `argv` in a pty of the specified size
+    pub fn spawn(argv: &[&str], rows: u16, cols: u16) -> Fallible<Self> {
+        let guard = HARNESS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        let config = Arc::new(Config::default_config());
+        let domain: Arc<dyn Domain> = Arc::new(LocalDomain::new(&config)?);
+        let mux = Rc::new(Mux::new(&config, &domain));
+        Mux::set_mux(&mux);
+        let front_end = FrontEndSelection::Headless.try_new(&mux)?;
+
+        let mut cmd = CommandBuilder::new(argv[0]);
+        cmd.args(&argv[1..]);
+
+        let size = PtySize {
+            rows,
+            cols,
+            pixel_width: 0,
+            pixel_height: 0,
+        };
+        let window_id = mux.new_empty_window();
+        let tab = domain.spawn(size, Some(cmd), window_id)?;
+
+        Ok(Self {
+            front_end,
+            mux,
+            tab,
+            _guard: guard,
+        })
+    }
+
+    /// Spawn a command via the platform shell
+    pub fn spawn_shell_command(command: &str) -> Fallible<Self> {
+        if cfg!(windows) {
+            Self::spawn(&["cmd.exe", "/c", command], 24, 80)
+        } else {
+            Self::spawn(&["/bin/sh", "-c", command], 24, 80)
+        }
+    }
+
+    fn headless(&self) -> &HeadlessFrontEnd {
+        self.front_end
+            .downcast_ref::<HeadlessFrontEnd>()
+            .expect("harness front end is headless")
+    }
+
+    pub fn tab_id(&self) -> TabId {
+        self.tab.tab_id()
+    }
+
+    /// Write raw input to the pty, as though it had been typed
+    pub fn send_text(&self, text: &str) -> Fallible<()> {
+        self.tab.writer().write_all(text.as_bytes())?;
+        Ok(())
+    }
+
+    /// Route a key press through the terminal key encoder
+    pub fn key_down(&self, key: KeyCode, mods: KeyModifiers) -> Fallible<()> {
+        self.tab.key_down(key, mods)
+    }
+
+    /// Returns the rendered screen contents, one line per row with
+    /// trailing whitespace removed.
+    pub fn snapshot(&self) -> String {
+        self.headless().render_all();
+        let text = self
+            .headless()
+            .screen_text(self.tab_id())
+            .unwrap_or_else(String::new);
+        let mut lines: Vec<&str> = text.lines().map(str::trim_end).collect();
+        while lines.last().map(|l| l.is_empty()).unwrap_or(false) {
+            lines.pop();
+        }
+        lines.join("\n")
+    }
+
+    pub fn cursor_position(&self) -> Option<(usize, usize)> {
+        self.headless().cursor_position(self.tab_id())
+    }
+
+    /// Process pty output until `predicate` returns true for the
+    /// current snapshot, or the timeout expires.
+    pub fn wait_for<F>(&self, predicate: F) -> Fallible<String>
+    where
+        F: Fn(&str) -> bool,
+    {
+        let deadline = Instant::now() + DEFAULT_TIMEOUT;
+        loop {
+            let snapshot = self.snapshot();
+            if predicate(&snapshot) {
+                return Ok(snapshot);
+            }
+            if Instant::now() >= deadline {
+                bail!("timed out waiting for screen; current state:\n{}", snapshot);
+            }
+            self.headless().pump(POLL_INTERVAL)?;
+        }
+    }
+
+    /// Wait for the screen to contain `text`
+    pub fn wait_for_text(&self, text: &str) -> Fallible<String> {
+        self.wait_for(|screen| screen.contains(text))
+    }
+
+    /// Process pty output until the child has exited and its tab
+    /// has been removed from the mux.
+    pub fn wait_for_exit(&self) -> Fallible<String> {
+        let deadline = Instant::now() + DEFAULT_TIMEOUT;
+        while self.mux.get_tab(self.tab_id()).is_some() {
+            if Instant::now() >= deadline {
+                bail!("timed out waiting for tab {} to exit", self.tab_id());
+            }
+            self.headless().pump(POLL_INTERVAL)?;
+        }
+        Ok(self.snapshot())
+    }
+}
+
+/// A tab with a terminal model but no program, which records the
+/// focus changes and the data that would be sent to the program
+pub struct FakeTab {
+    tab_id: TabId,
+    terminal: RefCell<Terminal>,
+    written: RefCell<Vec<u8>>,
+    focus: RefCell<Vec<bool>>,
+}
+
+impl FakeTab {
+    pub fn new() -> Rc<dyn Tab> {
+        Rc::new(Self {
+            tab_id: alloc_tab_id(),
+            terminal: RefCell::new(Terminal::new(24, 80, 0, 0, 0, vec![])),
+            written: RefCell::new(vec![]),
+            focus: RefCell::new(vec![]),
+        })
+    }
+
+    /// Returns the focus changes that the tab was told about
+    pub fn focus_changes(tab: &Rc<dyn Tab>) -> Vec<bool> {
+        tab.downcast_ref::<FakeTab>()
+            .expect("tab to be a FakeTab")
+            .focus
+            .borrow()
+            .clone()
+    }
+}
+
+impl Tab for FakeTab {
+    fn tab_id(&self) -> TabId {
+        self.tab_id
+    }
+
+    fn renderer(&self) -> RefMut<dyn Renderable> {
+        RefMut::map(self.terminal.borrow_mut(), |t| &mut *t)
+    }
+
+    fn get_title(&self) -> String {
+        self.terminal.borrow_mut().get_title().to_string()
+    }
+
+    fn set_title(&self, _title: Option<String>) {}
+
+    fn send_paste(&self, text: &str) -> Fallible<()> {
+        self.written.borrow_mut().extend_from_slice(text.as_bytes());
+        Ok(())
+    }
+
+    fn reader(&self) -> Fallible<Box<dyn std::io::Read + Send>> {
+        bail!("a FakeTab has no program to read from")
+    }
+
+    fn writer(&self) -> RefMut<dyn std::io::Write> {
+        self.written.borrow_mut()
+    }
+
+    fn resize(&self, _size: PtySize) -> Fallible<()> {
+        Ok(())
+    }
+
+    fn key_down(&self, key: KeyCode, mods: KeyModifiers) -> Fallible<()> {
+        self.terminal
+            .borrow_mut()
+            .key_down(key, mods, &mut *self.written.borrow_mut())
+    }
+
+    fn mouse_event(&self, event: MouseEvent, host: &mut dyn TerminalHost) -> Fallible<()> {
+        self.terminal.borrow_mut().mouse_event(event, host)
+    }
+
+    fn focus_changed(&self, focused: bool) -> Fallible<()> {
+        self.focus.borrow_mut().push(focused);
+        Ok(())
+    }
+
+    fn advance_bytes(&self, buf: &[u8], host: &mut dyn TerminalHost) {
+        self.terminal.borrow_mut().advance_bytes(buf, host)
+    }
+
+    fn is_dead(&self) -> bool {
+        false
+    }
+
+    fn palette(&self) -> ColorPalette {
+        self.terminal.borrow().palette().clone()
+    }
+
+    fn set_palette(&self, palette: ColorPalette) {
+        self.terminal.borrow_mut().set_palette(palette);
+    }
+
+    fn domain_id(&self) -> DomainId {
+        0
+    }
+
+    fn copy_mode(&self, action: CopyModeAction) -> Fallible<Option<String>> {
+        Ok(self.terminal.borrow_mut().copy_mode_action(action))
+    }
+
+    fn in_copy_mode(&self) -> bool {
+        self.terminal.borrow().in_copy_mode()
+    }
+
+    fn hyperlink_near_cursor(&self) -> Fallible<Option<Arc<Hyperlink>>> {
+        Ok(self.terminal.borrow_mut().hyperlink_near_cursor())
+    }
+
+    fn config_was_reloaded(&self, _config: &Config) {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn echo_output() {
+        let harness = PtyHarness::spawn_shell_command("echo hello").unwrap();
+        harness.wait_for_text("hello").unwrap();
+        let screen = harness.wait_for_exit().unwrap();
+        assert_eq!(screen.lines().next(), Some("hello"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn scripted_input() {
+        let harness = PtyHarness::spawn(&["/bin/sh", "-c", "read x; echo got:$x"], 24, 80).unwrap();
+        harness.send_text("wez\r").unwrap();
+        let screen = harness.wait_for_text("got:wez").unwrap();
+        assert_eq!(screen, "wez\ngot:wez");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn key_down_is_encoded() {
+        let harness = PtyHarness::spawn(&["/bin/sh", "-c", "read x; echo got:$x"], 24, 80).unwrap();
+        for c in "abc".chars() {
+            harness
+                .key_down(KeyCode::Char(c), KeyModifiers::NONE)
+                .unwrap();
+        }
+        harness
+            .key_down(KeyCode::Enter, KeyModifiers::NONE)
+            .unwrap();
+        harness.wait_for_text("got:abc").unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn escape_sequences_are_applied() {
+        let harness =
+            PtyHarness::spawn_shell_command("printf 'one\\ntwo\\033[1;1Hxx'; sleep 1").unwrap();
+        let screen = harness.wait_for_text("xxe").unwrap();
+        assert_eq!(screen, "xxe\ntwo");
+        assert_eq!(harness.cursor_position(), Some((2, 0)));
+    }
+}
@@ -16,6 +16,9 @@ pub struct ColorPalette {
     pub cursor_bg: RgbColor,
     pub selection_fg: RgbColor,
     pub selection_bg: RgbColor,
+    /// Half-bright versions of the 8 basic ANSI colors.  When None,
+    /// dim text is rendered by blending toward the background.
+    pub dims: Option<[RgbColor; 8]>,
 }
 
 impl fmt::Debug for Palette256 {
@@ -37,6 +40,23 @@ impl ColorPalette {
             | ColorAttribute::TrueColorWithDefaultFallback(color) => color,
         }
     }
+    /// Computes the color of half-bright text.  `fg` and `bg` are the
+    /// colors that the text would otherwise be rendered with, and
+    /// `color` is the foreground attribute that produced `fg`.
+    pub fn resolve_dim_fg(&self, color: ColorAttribute, fg: RgbColor, bg: RgbColor) -> RgbColor {
+        match (color, self.dims.as_ref()) {
+            (ColorAttribute::PaletteIndex(idx), Some(dims)) if idx < 8 => dims[idx as usize],
+            _ => {
+                let blend = |fg: u8, bg: u8| ((2 * u16::from(fg) + u16::from(bg)) / 3) as u8;
+                RgbColor::new(
+                    blend(fg.red, bg.red),
+                    blend(fg.green, bg.green),
+                    blend(fg.blue, bg.blue),
+                )
+            }
+        }
+    }
+
     pub fn resolve_bg(&self, color: ColorAttribute) -> RgbColor {
         match color {
             ColorAttribute::Default => self.background,
@@ -193,6 +213,7 @@ impl Default for ColorPalette {
             cursor_bg,
             selection_fg,
             selection_bg,
+            dims: None,
         }
     }
 }
@@ -0,0 +1,51 @@
+//! Keyboard driven selection, in the style of the tmux copy mode.
+//! While copy mode is active the terminal displays a separate cursor
+//! that can be moved through the screen and scrollback, and a
+//! selection can be marked relative to it.
+use crate::selection::SelectionCoordinate;
+use serde_derive::*;
+
+/// Describes how the copy mode cursor should be moved
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum CopyModeMotion {
+    Left,
+    Right,
+    Up,
+    Down,
+    StartOfLine,
+    /// Moves to the last non-blank cell of the line
+    EndOfLine,
+    /// Moves to the start of the next word, continuing onto
+    /// subsequent lines as needed
+    WordForward,
+    /// Moves to the start of the current or previous word
+    WordBackward,
+    PageUp,
+    PageDown,
+    /// Moves to the start of the scrollback
+    Top,
+    /// Moves to the start of the last line of the screen
+    Bottom,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum CopyModeAction {
+    /// Enters copy mode with the copy cursor placed at the
+    /// terminal cursor
+    Enter,
+    /// Leaves copy mode, discarding any selection
+    Exit,
+    Move(CopyModeMotion),
+    /// Starts a selection at the copy cursor, or cancels the
+    /// selection if one is already in progress
+    ToggleSelection,
+    /// Leaves copy mode, yielding the selected text
+    Copy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct CopyModeState {
+    pub cursor: SelectionCoordinate,
+    /// The other end of the selection, if one has been started
+    pub mark: Option<SelectionCoordinate>,
+}
@@ -11,6 +11,7 @@ pub use crate::input::*;
 pub use termwiz::cell::{self, *};
 
 pub use termwiz::surface::line::*;
+pub use termwiz::surface::{SequenceNo, SEQ_ZERO};
 
 pub mod screen;
 pub use crate::screen::*;
@@ -18,6 +19,11 @@ pub use crate::screen::*;
 pub mod selection;
 use crate::selection::{SelectionCoordinate, SelectionRange};
 
+pub mod copymode;
+pub use crate::copymode::*;
+
+mod cp437;
+
 use termwiz::hyperlink::Hyperlink;
 
 pub mod terminal;
@@ -1,7 +1,58 @@
 use super::*;
-use log::debug;
+use failure::Fallible;
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
 
+/// The number of scrollback lines that are frozen together
+const COLD_CHUNK_LINES: usize = 1024;
+
+/// Scrollback lines that have been moved out of `Screen::lines` to
+/// save memory
+#[derive(Debug, Clone)]
+enum ColdChunk {
+    /// The lines, serialized and compressed with zstd
+    Compressed { data: Vec<u8>, len: usize },
+    /// Lines holding images are kept as they are, because serializing
+    /// them would copy the image into each of its cells
+    Plain(Vec<Line>),
+}
+
+impl ColdChunk {
+    fn compress(lines: &[Line]) -> Fallible<Self> {
+        let mut data = vec![];
+        let mut compress = zstd::Encoder::new(&mut data, zstd::DEFAULT_COMPRESSION_LEVEL)?;
+        lines.serialize(&mut varbincode::Serializer::new(&mut compress))?;
+        compress.finish()?;
+        Ok(ColdChunk::Compressed {
+            data,
+            len: lines.len(),
+        })
+    }
+
+    fn thaw(self) -> Fallible<Vec<Line>> {
+        match self {
+            ColdChunk::Compressed { data, .. } => {
+                let decompress = zstd::Decoder::new(data.as_slice())?;
+                let mut lines: Vec<Line> =
+                    Deserialize::deserialize(&mut varbincode::Deserializer::new(decompress))?;
+                for line in &mut lines {
+                    line.set_dirty();
+                }
+                Ok(lines)
+            }
+            ColdChunk::Plain(lines) => Ok(lines),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            ColdChunk::Compressed { len, .. } => *len,
+            ColdChunk::Plain(lines) => lines.len(),
+        }
+    }
+}
+
 /// Holds the model of a screen.  This can either be the primary screen
 /// which includes lines of scrollback text, or the alternate screen
 /// which holds no scrollback.  The intent is to have one instance of
@@ -18,6 +69,24 @@ pub struct Screen {
     /// would otherwise have exceeded the line capacity
     pub lines: VecDeque<Line>,
 
+    /// The oldest lines of the scrollback, which precede `lines`, in
+    /// chunks that are compressed to save memory.  They are thawed back
+    /// into `lines` when the viewport or a search reaches them.
+    cold: VecDeque<ColdChunk>,
+    /// The total length of the chunks in `cold`, which is kept rather
+    /// than summed as it is needed for each new line
+    cold_len: usize,
+    /// The number of the oldest lines of the first cold chunk that have
+    /// been discarded, as the chunk is only dropped once all of its
+    /// lines have been
+    cold_dropped: usize,
+    /// The number of lines that have been discarded from the top of the
+    /// scrollback since the screen was created
+    discarded: usize,
+    /// The number of scrollback lines kept in `lines` before older ones
+    /// are frozen into `cold`, or None to never freeze them
+    uncompressed_scrollback: Option<usize>,
+
     /// Maximum number of lines of scrollback
     pub scrollback_size: usize,
 
@@ -39,6 +108,11 @@ impl Screen {
 
         Screen {
             lines,
+            cold: VecDeque::new(),
+            cold_len: 0,
+            cold_dropped: 0,
+            discarded: 0,
+            uncompressed_scrollback: None,
             scrollback_size,
             physical_rows,
             physical_cols,
@@ -47,7 +121,7 @@ impl Screen {
 
     /// Resize the physical, viewable portion of the screen
     pub fn resize(&mut self, physical_rows: usize, physical_cols: usize) {
-        let capacity = physical_rows + self.scrollback_size;
+        let capacity = physical_rows + self.hot_scrollback_capacity();
         let current_capacity = self.lines.capacity();
         if capacity > current_capacity {
             self.lines.reserve(capacity - current_capacity);
@@ -63,6 +137,142 @@ impl Screen {
         self.physical_cols = physical_cols;
     }
 
+    /// Change the number of lines of scrollback that are retained,
+    /// discarding the oldest lines if there are now too many
+    pub fn set_scrollback_size(&mut self, scrollback_size: usize) {
+        self.scrollback_size = scrollback_size;
+        let max_lines = self.physical_rows + scrollback_size;
+        let total = self.cold_lines() + self.lines.len();
+        if total > max_lines {
+            let excess = total - max_lines;
+            self.discarded += excess;
+            let excess = excess - self.drop_cold(excess);
+            self.lines.drain(..excess);
+        }
+    }
+
+    /// Sets how many lines of scrollback are kept uncompressed, beyond
+    /// which the older lines are compressed by `freeze`, or None to
+    /// keep them all uncompressed
+    pub fn set_uncompressed_scrollback(&mut self, lines: Option<usize>) {
+        self.uncompressed_scrollback = lines;
+        match lines {
+            // Release the room reserved for the whole scrollback
+            Some(_) => self.lines.shrink_to_fit(),
+            None => self.thaw_all(),
+        }
+    }
+
+    /// The most scrollback lines that `lines` holds at once
+    fn hot_scrollback_capacity(&self) -> usize {
+        match self.uncompressed_scrollback {
+            Some(hot) => self.scrollback_size.min(hot + COLD_CHUNK_LINES),
+            None => self.scrollback_size,
+        }
+    }
+
+    /// Returns the number of scrollback lines in cold storage
+    pub fn cold_lines(&self) -> usize {
+        self.cold_len - self.cold_dropped
+    }
+
+    /// Returns the number of lines that have been discarded from the top
+    /// of the scrollback.  Adding it to the index of a line from the top
+    /// gives a number for the line that stays the same as older lines
+    /// are discarded.
+    pub fn discarded_rows(&self) -> usize {
+        self.discarded
+    }
+
+    /// Compresses the oldest lines of the scrollback, in chunks, until
+    /// no more than the configured number of scrollback lines remain
+    /// uncompressed.  The last `keep` lines are left alone regardless,
+    /// as they are in view.
+    pub fn freeze(&mut self, keep: usize) {
+        let hot = match self.uncompressed_scrollback {
+            Some(hot) => hot,
+            None => return,
+        };
+        let keep = keep.max(self.physical_rows + hot);
+        while self.lines.len() >= keep + COLD_CHUNK_LINES {
+            let lines: Vec<Line> = self.lines.drain(..COLD_CHUNK_LINES).collect();
+            let has_image = lines
+                .iter()
+                .any(|line| line.cells().iter().any(|cell| cell.attrs().image.is_some()));
+            if has_image {
+                self.cold_len += lines.len();
+                self.cold.push_back(ColdChunk::Plain(lines));
+                continue;
+            }
+            match ColdChunk::compress(&lines) {
+                Ok(chunk) => {
+                    self.cold_len += chunk.len();
+                    self.cold.push_back(chunk);
+                }
+                Err(err) => {
+                    error!("unable to compress the scrollback: {}", err);
+                    for line in lines.into_iter().rev() {
+                        self.lines.push_front(line);
+                    }
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Moves lines from cold storage back into `lines` until it holds
+    /// at least `wanted` lines, or there are no more
+    pub fn thaw(&mut self, wanted: usize) {
+        while self.lines.len() < wanted {
+            let chunk = match self.cold.pop_back() {
+                Some(chunk) => chunk,
+                None => return,
+            };
+            self.cold_len -= chunk.len();
+            let skip = if self.cold.is_empty() {
+                std::mem::replace(&mut self.cold_dropped, 0)
+            } else {
+                0
+            };
+            match chunk.thaw() {
+                Ok(lines) => {
+                    for line in lines.into_iter().skip(skip).rev() {
+                        self.lines.push_front(line);
+                    }
+                }
+                Err(err) => error!("unable to decompress the scrollback: {}", err),
+            }
+        }
+    }
+
+    /// Moves all of the lines in cold storage back into `lines`
+    pub fn thaw_all(&mut self) {
+        self.thaw(usize::max_value());
+    }
+
+    /// Discards up to `count` of the oldest lines in cold storage,
+    /// returning how many were discarded
+    fn drop_cold(&mut self, count: usize) -> usize {
+        let mut dropped = 0;
+        while dropped < count {
+            let first_len = match self.cold.front() {
+                Some(chunk) => chunk.len(),
+                None => break,
+            };
+            let remaining = first_len - self.cold_dropped;
+            if count - dropped >= remaining {
+                self.cold.pop_front();
+                self.cold_len -= first_len;
+                self.cold_dropped = 0;
+                dropped += remaining;
+            } else {
+                self.cold_dropped += count - dropped;
+                dropped = count;
+            }
+        }
+        dropped
+    }
+
     /// Get mutable reference to a line, relative to start of scrollback.
     #[inline]
     pub fn line_mut(&mut self, idx: PhysRowIndex) -> &mut Line {
@@ -204,11 +414,15 @@ impl Screen {
             num_rows
         } else {
             let max_allowed = self.physical_rows + self.scrollback_size;
-            if self.lines.len() + num_rows >= max_allowed {
-                (self.lines.len() + num_rows) - max_allowed
+            let total = self.cold_lines() + self.lines.len();
+            let excess = if total + num_rows >= max_allowed {
+                (total + num_rows) - max_allowed
             } else {
                 0
-            }
+            };
+            self.discarded += excess;
+            // The oldest lines are those in cold storage
+            excess - self.drop_cold(excess)
         };
 
         let remove_idx = if scroll_region.start == 0 {
@@ -3,6 +3,9 @@ use std::sync::Arc;
 use termwiz::escape::parser::Parser;
 use termwiz::hyperlink::Rule as HyperlinkRule;
 
+const ANSI_ART_MODE_SET: &[u8] = b"\x1b[?8437h";
+const ANSI_ART_MODE_RESET: &[u8] = b"\x1b[?8437l";
+
 /// Represents the host of the terminal.
 /// Provides a means for sending data to the connected pty,
 /// and for operating on the clipboard
@@ -40,6 +43,15 @@ pub trait TerminalHost {
 
     /// Reset font size
     fn reset_font_size(&mut self) {}
+
+    /// Switch to the named color scheme
+    fn set_color_scheme(&mut self, _name: &str) {}
+
+    /// The program rang the bell
+    fn bell(&mut self) {}
+
+    /// The program reported that its working directory changed
+    fn set_current_dir(&mut self, _dir: &str) {}
 }
 
 pub struct Terminal {
@@ -67,6 +79,8 @@ impl Terminal {
     pub fn new(
         physical_rows: usize,
         physical_cols: usize,
+        pixel_width: usize,
+        pixel_height: usize,
         scrollback_size: usize,
         hyperlink_rules: Vec<HyperlinkRule>,
     ) -> Terminal {
@@ -74,6 +88,8 @@ impl Terminal {
             state: TerminalState::new(
                 physical_rows,
                 physical_cols,
+                pixel_width,
+                pixel_height,
                 scrollback_size,
                 hyperlink_rules,
             ),
@@ -83,10 +99,40 @@ impl Terminal {
 
     /// Feed the terminal parser a slice of bytes of input.
     pub fn advance_bytes<B: AsRef<[u8]>>(&mut self, bytes: B, host: &mut TerminalHost) {
-        let bytes = bytes.as_ref();
+        let mut bytes = bytes.as_ref();
 
         let mut performer = Performer::new(&mut self.state, host);
-
-        self.parser.parse(bytes, |action| performer.perform(action));
+        let parser = &mut self.parser;
+
+        while !bytes.is_empty() {
+            // The ANSI art mode changes how the bytes that follow the
+            // sequence that switches it are decoded, so the input is
+            // split after that sequence.  The mode may also be switched
+            // by a sequence that sets several modes at once, in which
+            // case it applies from the next read.
+            let ansi_art = performer.ansi_art_mode();
+            let switch: &[u8] = if ansi_art {
+                ANSI_ART_MODE_RESET
+            } else {
+                ANSI_ART_MODE_SET
+            };
+            let split = bytes
+                .windows(switch.len())
+                .position(|window| window == switch)
+                .map(|idx| idx + switch.len())
+                .unwrap_or_else(|| bytes.len());
+            let (chunk, remainder) = bytes.split_at(split);
+
+            if ansi_art {
+                parser.parse(cp437::decode(chunk).as_bytes(), |action| {
+                    performer.perform(action)
+                });
+            } else {
+                parser.parse(chunk, |action| performer.perform(action));
+            }
+            bytes = remainder;
+        }
+        drop(performer);
+        self.state.freeze_scrollback();
     }
 }
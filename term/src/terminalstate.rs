@@ -2,23 +2,36 @@
 // and inclusive range
 #![cfg_attr(feature = "cargo-clippy", allow(clippy::range_plus_one))]
 use super::*;
-use crate::color::ColorPalette;
+use crate::color::{ColorAttribute, ColorPalette};
 use failure::bail;
 use image::{self, GenericImageView};
 use log::{debug, error};
 use ordered_float::NotNan;
 use std::fmt::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use termwiz::escape::csi::{
-    Cursor, DecPrivateMode, DecPrivateModeCode, Device, Edit, EraseInDisplay, EraseInLine, Mode,
-    Sgr, TerminalMode, TerminalModeCode, Window,
+    Cursor, CursorStyle, DecPrivateMode, DecPrivateModeCode, Device, Edit, EraseInDisplay,
+    EraseInLine, Mode, Sgr, TerminalMode, TerminalModeCode, Window, XtermKeyModifierResource,
+};
+use termwiz::escape::osc::{
+    ChangeColorPair, ColorOrQuery, FinalTermSemanticPrompt, ITermFileData, ITermProprietary,
 };
-use termwiz::escape::osc::{ChangeColorPair, ColorOrQuery, ITermFileData, ITermProprietary};
 use termwiz::escape::{Action, ControlCode, Esc, EscCode, OneBased, OperatingSystemCommand, CSI};
 use termwiz::hyperlink::Rule as HyperlinkRule;
 use termwiz::image::{ImageCell, ImageData, TextureCoordinate};
 use unicode_width::UnicodeWidthStr;
 
+/// Sequence numbers are allocated from a single counter that is shared
+/// by every terminal, so that a consumer that switches between terminals
+/// can still compare what it saw last with the lines of another one.
+static NEXT_SEQNO: AtomicUsize = AtomicUsize::new(SEQ_ZERO + 1);
+
+/// Allocates a sequence number that is larger than any allocated before
+pub fn alloc_seqno() -> SequenceNo {
+    NEXT_SEQNO.fetch_add(1, Ordering::SeqCst)
+}
+
 struct TabStop {
     tabs: Vec<bool>,
     tab_width: usize,
@@ -65,6 +78,7 @@ struct SavedCursor {
     position: CursorPosition,
     wrap_next: bool,
     insert: bool,
+    dec_origin_mode: bool,
 }
 
 struct ScreenOrAlt {
@@ -158,6 +172,9 @@ pub struct TerminalState {
 
     /// The scroll region
     scroll_region: Range<VisibleRowIndex>,
+    /// DECOM: when set, cursor positions are relative to the top of
+    /// the scroll region, which the cursor can't leave
+    dec_origin_mode: bool,
 
     /// When set, modifies the sequence of bytes sent for keys
     /// designated as cursor keys.  This includes various navigation
@@ -172,16 +189,41 @@ pub struct TerminalState {
     /// designated marker characters.
     bracketed_paste: bool,
 
-    sgr_mouse: bool,
-    button_event_mouse: bool,
+    /// The modifyOtherKeys level that the application selected with
+    /// XTMODKEYS.  0 is off, 1 reports the modifiers of keys whose
+    /// usual encoding loses them, and 2 reports them for all keys.
+    modify_other_keys: i64,
+    /// When set, keys with modifiers are reported in the CSI u form
+    /// whether or not the application asked for it
+    csi_u_key_encoding: bool,
+    /// Decode the output as CP437 and show blinking text with a bright
+    /// background instead, as ANSI art expects
+    ansi_art_mode: bool,
+    /// Render monochrome text and ignore everything but the basic
+    /// cursor and editing sequences, for programs that were told
+    /// that this is a dumb terminal
+    dumb_mode: bool,
+
+    /// Which mouse events are reported to the application
+    mouse_tracking: MouseTracking,
+    /// How the mouse events are encoded when they are reported
+    mouse_encoding: MouseEncoding,
+    /// When set, gaining and losing the focus is reported to the
+    /// application
+    focus_tracking: bool,
     current_mouse_button: MouseButton,
     mouse_position: CursorPosition,
     cursor_visible: bool,
+    /// The shape of the cursor, as set by DECSCUSR
+    cursor_style: CursorStyle,
     dec_line_drawing_mode: bool,
 
     /// Which hyperlink is considered to be highlighted, because the
     /// mouse_position is over a cell with a Hyperlink attribute.
     current_highlight: Option<Arc<Hyperlink>>,
+    /// The modifiers that must be held down when clicking on an
+    /// implicit hyperlink to open it
+    implicit_link_click_modifiers: KeyModifiers,
 
     /// Keeps track of double and triple clicks
     last_mouse_click: Option<LastMouseClick>,
@@ -202,26 +244,259 @@ pub struct TerminalState {
     /// Holds the not-normalized selection range.
     selection_range: Option<SelectionRange>,
 
+    /// Set while the keyboard driven copy mode is active
+    copy_mode: Option<CopyModeState>,
+
     tabs: TabStop,
 
     hyperlink_rules: Vec<HyperlinkRule>,
 
+    /// Characters that delimit a word for double-click selection
+    word_boundary: String,
+
     /// The terminal title string
     title: String,
+    /// The working directory that the program reported with OSC 7
+    current_dir: Option<String>,
     palette: ColorPalette,
+
+    /// The size of the visible screen in pixels, which is used to
+    /// work out how many cells an image occupies.  Zero if unknown.
+    pixel_width: usize,
+    pixel_height: usize,
 }
 
-fn is_double_click_word(s: &str) -> bool {
-    // TODO: add configuration for this
-    if s.len() > 1 {
-        true
-    } else if s.len() == 1 {
-        match s.chars().nth(0).unwrap() {
-            ' ' | '\t' | '\n' | '{' | '[' | '}' | ']' | '(' | ')' | '"' | '\'' => false,
-            _ => true,
+/// Which mouse events are reported to the application, as chosen with
+/// DEC private modes 1000, 1002 and 1003
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MouseTracking {
+    /// The mouse is ours, to select text and follow links with
+    Off,
+    /// Button presses and releases, including the wheel
+    Buttons,
+    /// As Buttons, and movement while a button is held down
+    Drag,
+    /// As Buttons, and all movement
+    AnyMotion,
+}
+
+/// How mouse events are reported, as chosen with DEC private modes
+/// 1005, 1006 and 1015
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MouseEncoding {
+    /// `CSI M` followed by the button and coordinates as bytes offset
+    /// by 32, which can't describe columns or rows past 223
+    X10,
+    /// As X10, but the values are encoded as UTF-8 characters
+    Utf8,
+    /// `CSI < button ; x ; y M`, or `m` for a release, which also
+    /// says which button was released
+    Sgr,
+    /// `CSI button ; x ; y M`, with the button offset by 32
+    Urxvt,
+}
+
+/// Encodes a mouse report.  `button` includes the bits for the held
+/// modifiers and for motion, while `x` and `y` are zero based.
+/// Returns None if the position can't be described in the encoding.
+fn encode_mouse_report(
+    encoding: MouseEncoding,
+    button: usize,
+    release: bool,
+    x: usize,
+    y: usize,
+) -> Option<Vec<u8>> {
+    // Only SGR says which button was released
+    let button = match (encoding, release) {
+        (MouseEncoding::Sgr, _) | (_, false) => button,
+        (_, true) => (button & !3) | 3,
+    };
+    let (x, y) = (x + 1, y + 1);
+    match encoding {
+        MouseEncoding::X10 => {
+            if x + 32 > 0xff || y + 32 > 0xff {
+                return None;
+            }
+            Some(vec![
+                0x1b,
+                b'[',
+                b'M',
+                (button + 32) as u8,
+                (x + 32) as u8,
+                (y + 32) as u8,
+            ])
+        }
+        MouseEncoding::Utf8 => {
+            // xterm limits the values to two byte sequences
+            if x + 32 >= 0x800 || y + 32 >= 0x800 {
+                return None;
+            }
+            let mut report = "\x1b[M".to_string();
+            for value in &[button, x, y] {
+                report.push(std::char::from_u32((value + 32) as u32)?);
+            }
+            Some(report.into_bytes())
         }
+        MouseEncoding::Sgr => Some(
+            format!(
+                "\x1b[<{};{};{}{}",
+                button,
+                x,
+                y,
+                if release { 'm' } else { 'M' }
+            )
+            .into_bytes(),
+        ),
+        MouseEncoding::Urxvt => Some(format!("\x1b[{};{};{}M", button + 32, x, y).into_bytes()),
+    }
+}
+
+/// Adds the bits for the held modifier keys to a button number
+/// reported by the mouse protocol
+fn mouse_report_button(button: usize, modifiers: KeyModifiers) -> usize {
+    let mut button = button;
+    if modifiers.contains(KeyModifiers::SHIFT) {
+        button |= 4;
+    }
+    if modifiers.contains(KeyModifiers::ALT) {
+        button |= 8;
+    }
+    if modifiers.contains(KeyModifiers::CTRL) {
+        button |= 16;
+    }
+    button
+}
+
+/// The maximum number of bytes of pasted text to write to the pty
+/// before flushing it
+pub const PASTE_CHUNK_SIZE: usize = 1024;
+
+/// Writes pasted text to the pty, enclosed in the bracketing if
+/// `bracketed` is true.  This is what `TerminalState::send_paste` does
+/// with the bracketed paste mode of the terminal; it is exposed so that
+/// large pastes can be written from another thread.
+pub fn write_paste(text: &str, bracketed: bool, writer: &mut std::io::Write) -> Result<(), Error> {
+    if bracketed {
+        // Don't allow the pasted text to end the bracketing early
+        let text = text.replace("\x1b[201~", "");
+        writer.write_all(b"\x1b[200~")?;
+        write_paste_chunks(&text, writer)?;
+        writer.write_all(b"\x1b[201~")?;
     } else {
-        false
+        write_paste_chunks(text, writer)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes text to the pty in chunks of at most PASTE_CHUNK_SIZE bytes,
+/// splitting only on character boundaries
+fn write_paste_chunks(text: &str, writer: &mut std::io::Write) -> Result<(), Error> {
+    let mut remain = text;
+    while !remain.is_empty() {
+        let mut len = remain.len().min(PASTE_CHUNK_SIZE);
+        while !remain.is_char_boundary(len) {
+            len -= 1;
+        }
+        let (chunk, rest) = remain.split_at(len);
+        writer.write_all(chunk.as_bytes())?;
+        writer.flush()?;
+        remain = rest;
+    }
+    Ok(())
+}
+
+/// Works out the size in pixels at which to display an image whose
+/// natural size is `image_width` x `image_height`, given the requested
+/// `width` and `height` (None meaning automatic).  Automatic sizes
+/// follow the aspect ratio of the image.  Whatever the requested size,
+/// the image is shrunk to fit within `max_width` x `max_height`.
+fn image_pixel_size(
+    image_width: usize,
+    image_height: usize,
+    width: Option<usize>,
+    height: Option<usize>,
+    preserve_aspect_ratio: bool,
+    max_width: usize,
+    max_height: usize,
+) -> (usize, usize) {
+    let image_width = image_width.max(1);
+    let image_height = image_height.max(1);
+    let (width, height) = match (width, height) {
+        (None, None) if image_width > max_width => {
+            (max_width, image_height * max_width / image_width)
+        }
+        (None, None) => (image_width, image_height),
+        (Some(w), None) => (w, image_height.saturating_mul(w) / image_width),
+        (None, Some(h)) => (image_width.saturating_mul(h) / image_height, h),
+        (Some(w), Some(h)) if preserve_aspect_ratio => {
+            // Fit the image within the requested box
+            let scale = (w as f32 / image_width as f32).min(h as f32 / image_height as f32);
+            (
+                (image_width as f32 * scale) as usize,
+                (image_height as f32 * scale) as usize,
+            )
+        }
+        (Some(w), Some(h)) => (w, h),
+    };
+    let (width, height) = (width.max(1), height.max(1));
+    if width <= max_width && height <= max_height {
+        return (width, height);
+    }
+    // The size comes from the escape sequence, so it may be arbitrarily
+    // large; keep the image to a size that can be displayed
+    let scale = (max_width as f32 / width as f32).min(max_height as f32 / height as f32);
+    (
+        ((width as f32 * scale) as usize).max(1),
+        ((height as f32 * scale) as usize).max(1),
+    )
+}
+
+/// The characters that terminate a word when double-click selecting
+pub const DEFAULT_WORD_BOUNDARY: &str = " \t\n{[}]()\"'";
+
+/// Extracts the path from the `file://host/path` URL of OSC 7,
+/// decoding its percent escapes.  Anything else is returned as is.
+fn path_from_file_url(url: &str) -> String {
+    let path = match url.find("file://") {
+        Some(0) => {
+            let rest = &url["file://".len()..];
+            match rest.find('/') {
+                Some(slash) => &rest[slash..],
+                None => "/",
+            }
+        }
+        _ => return url.to_owned(),
+    };
+
+    let mut bytes = vec![];
+    let mut iter = path.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hex: Vec<u8> = iter.clone().take(2).collect();
+            if hex.len() == 2 && hex.iter().all(u8::is_ascii_hexdigit) {
+                let hex = String::from_utf8_lossy(&hex);
+                bytes.push(u8::from_str_radix(&hex, 16).unwrap());
+                iter.nth(1);
+                continue;
+            }
+        }
+        bytes.push(b);
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Returns true if the grapheme `s` is part of a word for the purposes
+/// of double click selection.  ASCII characters are part of a word
+/// unless they appear in `word_boundary`, while other characters have
+/// to be letters or digits, so that punctuation such as `。` and emoji
+/// separate words.  Combining marks follow their base character.
+fn is_double_click_word(s: &str, word_boundary: &str) -> bool {
+    match s.chars().next() {
+        None => false,
+        Some(c) if word_boundary.contains(c) => false,
+        Some(c) if c.is_ascii() => true,
+        Some(c) => c.is_alphanumeric(),
     }
 }
 
@@ -229,6 +504,8 @@ impl TerminalState {
     pub fn new(
         physical_rows: usize,
         physical_cols: usize,
+        pixel_width: usize,
+        pixel_height: usize,
         scrollback_size: usize,
         hyperlink_rules: Vec<HyperlinkRule>,
     ) -> TerminalState {
@@ -239,26 +516,39 @@ impl TerminalState {
             pen: CellAttributes::default(),
             cursor: CursorPosition::default(),
             scroll_region: 0..physical_rows as VisibleRowIndex,
+            dec_origin_mode: false,
             wrap_next: false,
             insert: false,
             application_cursor_keys: false,
             application_keypad: false,
             bracketed_paste: false,
-            sgr_mouse: false,
-            button_event_mouse: false,
+            modify_other_keys: 0,
+            csi_u_key_encoding: false,
+            ansi_art_mode: false,
+            dumb_mode: false,
+            mouse_tracking: MouseTracking::Off,
+            mouse_encoding: MouseEncoding::X10,
+            focus_tracking: false,
             cursor_visible: true,
+            cursor_style: CursorStyle::Default,
             dec_line_drawing_mode: false,
             current_mouse_button: MouseButton::None,
             mouse_position: CursorPosition::default(),
             current_highlight: None,
+            implicit_link_click_modifiers: KeyModifiers::CTRL,
             last_mouse_click: None,
             viewport_offset: 0,
             selection_range: None,
             selection_start: None,
+            copy_mode: None,
             tabs: TabStop::new(physical_cols, 8),
             hyperlink_rules,
+            word_boundary: DEFAULT_WORD_BOUNDARY.to_string(),
             title: "wezterm".to_string(),
+            current_dir: None,
             palette: ColorPalette::default(),
+            pixel_width,
+            pixel_height,
         }
     }
 
@@ -266,10 +556,114 @@ impl TerminalState {
         &self.title
     }
 
+    /// Returns the working directory that the program reported, if any
+    pub fn get_current_dir(&self) -> Option<&str> {
+        self.current_dir.as_ref().map(String::as_str)
+    }
+
     pub fn palette(&self) -> &ColorPalette {
         &self.palette
     }
 
+    /// Replaces the palette, for example when the color scheme is
+    /// changed, and redraws the screen with the new colors
+    pub fn set_palette(&mut self, palette: ColorPalette) {
+        self.palette = palette;
+        self.make_all_lines_dirty();
+    }
+
+    /// Replaces the set of characters that delimit a word when
+    /// selecting by double-clicking
+    pub fn set_word_boundary(&mut self, word_boundary: &str) {
+        self.word_boundary = word_boundary.to_string();
+    }
+
+    /// Replaces the rules used to recognize implicit hyperlinks.
+    /// Links found by the previous rules are discarded and the text
+    /// is rescanned the next time that it is inspected.
+    pub fn set_hyperlink_rules(&mut self, rules: Vec<HyperlinkRule>) {
+        self.hyperlink_rules = rules;
+        let screen = &mut self.screen;
+        for line in screen
+            .screen
+            .lines
+            .iter_mut()
+            .chain(screen.alt_screen.lines.iter_mut())
+        {
+            line.invalidate_implicit_hyperlinks();
+        }
+        self.current_highlight = None;
+    }
+
+    /// Sets the modifiers that must be held down when clicking on an
+    /// implicit hyperlink to open it.  Explicit hyperlinks, which the
+    /// application asked for with OSC 8, open on a plain click.
+    pub fn set_implicit_link_click_modifiers(&mut self, mods: KeyModifiers) {
+        self.implicit_link_click_modifiers = mods;
+    }
+
+    /// Returns true if clicking on `link` while holding down `mods`
+    /// should open it
+    pub fn link_is_clickable(&self, link: &Hyperlink, mods: KeyModifiers) -> bool {
+        !link.is_implicit() || mods.contains(self.implicit_link_click_modifiers)
+    }
+
+    /// Enables reporting keys with modifiers in the CSI u form, which
+    /// lets applications tell apart chords like Ctrl+Shift+letter
+    pub fn set_csi_u_key_encoding(&mut self, enabled: bool) {
+        self.csi_u_key_encoding = enabled;
+    }
+
+    /// Enables the mode for displaying ANSI art, in which the output
+    /// is decoded as CP437 rather than UTF-8 and blinking text has a
+    /// bright background (iCE colors).  Programs can also switch it
+    /// with the private mode 8437.
+    pub fn set_ansi_art_mode(&mut self, enabled: bool) {
+        self.ansi_art_mode = enabled;
+    }
+
+    pub fn ansi_art_mode(&self) -> bool {
+        self.ansi_art_mode
+    }
+
+    /// Enables the degraded mode in which the text is monochrome and
+    /// only printing, the C0 controls and the cursor and editing
+    /// sequences have any effect.  The colors, attributes, private
+    /// modes, window operations and OSC sequences are discarded.
+    pub fn set_dumb_mode(&mut self, enabled: bool) {
+        self.dumb_mode = enabled;
+        if enabled {
+            self.pen = CellAttributes::default();
+        }
+    }
+
+    /// Changes the number of lines of scrollback that are retained.
+    /// Reducing it discards the oldest lines of the scrollback.
+    pub fn set_scrollback_size(&mut self, scrollback_size: usize) {
+        self.screen.screen.set_scrollback_size(scrollback_size);
+        // The viewport may now be beyond the start of the scrollback
+        let offset = self.viewport_offset;
+        self.set_scroll_viewport(offset);
+    }
+
+    /// Sets how many lines of scrollback are kept uncompressed; older
+    /// lines are compressed to save memory, or never if None
+    pub fn set_uncompressed_scrollback(&mut self, lines: Option<usize>) {
+        self.screen.screen.set_uncompressed_scrollback(lines);
+    }
+
+    /// Compresses the older lines of the scrollback of the primary
+    /// screen, leaving alone those in view.  Nothing is compressed
+    /// while there is a selection or copy mode is active, as they may
+    /// refer to lines out of view.
+    pub(crate) fn freeze_scrollback(&mut self) {
+        if self.copy_mode.is_some() || self.selection_range.is_some() {
+            return;
+        }
+        let keep = self.screen.screen.physical_rows + self.viewport_offset as usize;
+        self.screen.screen.freeze(keep);
+    }
+
     pub fn screen(&self) -> &Screen {
         &self.screen
     }
@@ -278,6 +672,11 @@ impl TerminalState {
         &mut self.screen
     }
 
+    /// Returns true if the alternate screen is the one being shown
+    pub fn is_alt_screen_active(&self) -> bool {
+        self.screen.is_alt_screen_active()
+    }
+
     pub fn get_selection_text(&self) -> String {
         let mut s = String::new();
 
@@ -376,19 +775,92 @@ impl TerminalState {
         x: usize,
         y: ScrollbackOrVisibleRowIndex,
     ) -> Option<Arc<Hyperlink>> {
-        let rules = &self.hyperlink_rules;
-
         let idx = self.screen.scrollback_or_visible_row(y);
-        match self.screen.lines.get_mut(idx) {
-            Some(ref mut line) => {
-                line.scan_and_create_hyperlinks(rules);
-                match line.cells().get(x) {
-                    Some(cell) => cell.attrs().hyperlink.as_ref().cloned(),
-                    None => None,
+        if idx >= self.screen.lines.len() {
+            return None;
+        }
+        self.scan_logical_line_for_hyperlinks(idx);
+        match self.screen.lines[idx].cells().get(x) {
+            Some(cell) => cell.attrs().hyperlink.as_ref().cloned(),
+            None => None,
+        }
+    }
+
+    /// Scans the logical line that includes the physical row `idx` for
+    /// implicit hyperlinks.  The logical line is the run of rows that
+    /// are joined by wrapping, so that a URL that was wrapped onto the
+    /// following rows is recognized as a single link.
+    fn scan_logical_line_for_hyperlinks(&mut self, idx: PhysRowIndex) {
+        let mut first = idx;
+        while first > 0 && self.is_line_wrapped(first - 1) {
+            first -= 1;
+        }
+        let mut last = idx;
+        while last + 1 < self.screen().lines.len() && self.is_line_wrapped(last) {
+            last += 1;
+        }
+
+        let lines = &mut self.screen.lines;
+        if (first..=last).all(|i| lines[i].has_scanned_implicit_hyperlinks()) {
+            return;
+        }
+
+        // Build the text of the logical line, remembering the row
+        // and column that each grapheme came from
+        let mut text = String::new();
+        let mut cells = vec![];
+        for i in first..=last {
+            let line = &mut lines[i];
+            line.invalidate_implicit_hyperlinks();
+            line.mark_scanned_implicit_hyperlinks();
+            for (x, cell) in line.visible_cells() {
+                cells.push((text.len(), i, x));
+                text.push_str(cell.str());
+            }
+        }
+
+        for m in HyperlinkRule::match_hyperlinks(&text, &self.hyperlink_rules) {
+            for &(byte_idx, i, x) in &cells {
+                if m.range.contains(&byte_idx) {
+                    lines[i].set_implicit_hyperlink(x, &m.link);
+                }
+            }
+        }
+    }
+
+    /// Returns the hyperlink closest to the cursor, searching backwards
+    /// from the cursor through the visible portion of the screen.
+    /// If there is no link at or before the cursor, the last link on
+    /// the screen is returned instead.
+    pub fn hyperlink_near_cursor(&mut self) -> Option<Arc<Hyperlink>> {
+        let cursor = self.cursor_pos();
+        let rows = self.screen().physical_rows;
+        let mut nearest = None;
+        let mut last = None;
+
+        for row in 0..rows {
+            let y = row as ScrollbackOrVisibleRowIndex
+                - self.viewport_offset as ScrollbackOrVisibleRowIndex;
+            let idx = self.screen.scrollback_or_visible_row(y);
+            if idx >= self.screen.lines.len() {
+                continue;
+            }
+            self.scan_logical_line_for_hyperlinks(idx);
+            let line = &self.screen.lines[idx];
+            if !line.has_hyperlink() {
+                continue;
+            }
+            for (x, cell) in line.cells().iter().enumerate() {
+                if let Some(link) = cell.attrs().hyperlink.as_ref() {
+                    if (row as VisibleRowIndex, x) <= (cursor.y, cursor.x) {
+                        nearest = Some(Arc::clone(link));
+                    }
+                    last = Some(Arc::clone(link));
                 }
             }
-            None => None,
         }
+
+        nearest.or(last)
     }
 
     /// Invalidate rows that have hyperlinks
@@ -428,7 +900,8 @@ impl TerminalState {
         host.set_clipboard(None)
     }
 
-    /// Double click to select a word on the current line
+    /// Double click to select a word on the current line, following
+    /// the word across wrapped lines in both directions
     fn mouse_double_click_left(
         &mut self,
         event: MouseEvent,
@@ -437,61 +910,75 @@ impl TerminalState {
         let y = event.y as ScrollbackOrVisibleRowIndex
             - self.viewport_offset as ScrollbackOrVisibleRowIndex;
         let idx = self.screen().scrollback_or_visible_row(y);
-        let selection_range = match self.screen().lines[idx]
-            .compute_double_click_range(event.x, is_double_click_word)
-        {
-            DoubleClickRange::Range(click_range) => SelectionRange {
-                start: SelectionCoordinate {
-                    x: click_range.start,
-                    y,
-                },
-                end: SelectionCoordinate {
-                    x: click_range.end - 1,
-                    y,
-                },
-            },
-            DoubleClickRange::RangeWithWrap(range_start) => {
-                let start_coord = SelectionCoordinate {
-                    x: range_start.start,
-                    y,
-                };
+        let word_boundary = &self.word_boundary;
+        let is_word = |s: &str| is_double_click_word(s, word_boundary);
+        let screen = self.screen();
 
-                let mut end_coord = SelectionCoordinate {
-                    x: range_start.end - 1,
-                    y,
-                };
+        let (click_range, wrapped) =
+            match screen.lines[idx].compute_double_click_range(event.x, &is_word) {
+                DoubleClickRange::Range(click_range) => (click_range, false),
+                DoubleClickRange::RangeWithWrap(click_range) => (click_range, true),
+            };
 
-                for y_cont in idx + 1..self.screen().lines.len() {
-                    match self.screen().lines[y_cont]
-                        .compute_double_click_range(0, is_double_click_word)
-                    {
-                        DoubleClickRange::Range(range_end) => {
-                            if range_end.end > range_end.start {
-                                end_coord = SelectionCoordinate {
-                                    x: range_end.end - 1,
-                                    y: y + (y_cont - idx) as i32,
-                                };
-                            }
-                            break;
-                        }
-                        DoubleClickRange::RangeWithWrap(range_end) => {
+        let mut start_coord = SelectionCoordinate {
+            x: click_range.start,
+            y,
+        };
+        let mut end_coord = SelectionCoordinate {
+            x: click_range.end.saturating_sub(1),
+            y,
+        };
+
+        if wrapped {
+            for y_cont in idx + 1..screen.lines.len() {
+                match screen.lines[y_cont].compute_double_click_range(0, &is_word) {
+                    DoubleClickRange::Range(range_end) => {
+                        if range_end.end > range_end.start {
                             end_coord = SelectionCoordinate {
                                 x: range_end.end - 1,
                                 y: y + (y_cont - idx) as i32,
                             };
                         }
+                        break;
+                    }
+                    DoubleClickRange::RangeWithWrap(range_end) => {
+                        end_coord = SelectionCoordinate {
+                            x: range_end.end - 1,
+                            y: y + (y_cont - idx) as i32,
+                        };
                     }
                 }
+            }
+        }
 
-                SelectionRange {
-                    start: start_coord,
-                    end: end_coord,
+        if click_range.start == 0 && click_range.end > click_range.start {
+            // The word begins at the left edge; if the prior line
+            // wrapped into this one then the word may start there.
+            for y_prev in (0..idx).rev() {
+                let line = &screen.lines[y_prev];
+                let last_col = match line.cells().len() {
+                    0 => break,
+                    n => n - 1,
+                };
+                match line.compute_double_click_range(last_col, &is_word) {
+                    DoubleClickRange::RangeWithWrap(range_start) => {
+                        start_coord = SelectionCoordinate {
+                            x: range_start.start,
+                            y: y - (idx - y_prev) as i32,
+                        };
+                        if range_start.start > 0 {
+                            break;
+                        }
+                    }
+                    DoubleClickRange::Range(_) => break,
                 }
             }
-        };
+        }
 
-        // TODO: if selection_range.start.x == 0, search backwards for wrapping
-        // lines too.
+        let selection_range = SelectionRange {
+            start: start_coord,
+            end: end_coord,
+        };
 
         self.selection_start = Some(selection_range.start);
         self.selection_range = Some(selection_range);
@@ -505,7 +992,18 @@ impl TerminalState {
         host.set_clipboard(Some(text))
     }
 
-    /// triple click to select the current line
+    /// Returns true if the line at the physical row index wrapped
+    /// into the line that follows it
+    fn is_line_wrapped(&self, idx: PhysRowIndex) -> bool {
+        self.screen().lines[idx]
+            .cells()
+            .last()
+            .map(|cell| cell.attrs().wrapped())
+            .unwrap_or(false)
+    }
+
+    /// triple click to select the current logical line, including
+    /// any lines that it wrapped onto
     fn mouse_triple_click_left(
         &mut self,
         event: MouseEvent,
@@ -513,12 +1011,26 @@ impl TerminalState {
     ) -> Result<(), Error> {
         let y = event.y as ScrollbackOrVisibleRowIndex
             - self.viewport_offset as ScrollbackOrVisibleRowIndex;
+        let idx = self.screen().scrollback_or_visible_row(y);
+
+        let mut first = idx;
+        while first > 0 && self.is_line_wrapped(first - 1) {
+            first -= 1;
+        }
+        let mut last = idx;
+        while last + 1 < self.screen().lines.len() && self.is_line_wrapped(last) {
+            last += 1;
+        }
+
         self.selection_start = Some(SelectionCoordinate { x: event.x, y });
         self.selection_range = Some(SelectionRange {
-            start: SelectionCoordinate { x: 0, y },
+            start: SelectionCoordinate {
+                x: 0,
+                y: y - (idx - first) as i32,
+            },
             end: SelectionCoordinate {
                 x: usize::max_value(),
-                y,
+                y: y + (last - idx) as i32,
             },
         });
         self.dirty_selection_lines();
@@ -578,7 +1090,9 @@ impl TerminalState {
             } else if let Some(link) = self.current_highlight() {
                 // If the button release wasn't a drag, consider
                 // whether it was a click on a hyperlink
-                host.click_link(&link);
+                if self.link_is_clickable(&link, event.modifiers) {
+                    host.click_link(&link);
+                }
             }
             Ok(())
         } else {
@@ -612,10 +1126,8 @@ impl TerminalState {
             (65, 1, KeyCode::DownArrow)
         };
 
-        if self.sgr_mouse {
-            writer.write_all(
-                format!("\x1b[<{};{};{}M", report_button, event.x + 1, event.y + 1).as_bytes(),
-            )?;
+        if self.mouse_tracking != MouseTracking::Off {
+            self.report_mouse(report_button, false, &event, writer)?;
         } else if self.screen.is_alt_screen_active() {
             // Send cursor keys instead (equivalent to xterm's alternateScroll mode)
             self.key_down(key, KeyModifiers::default(), writer)?;
@@ -637,10 +1149,8 @@ impl TerminalState {
             MouseButton::Right => Some(2),
             _ => None,
         } {
-            if self.sgr_mouse {
-                host.writer().write_all(
-                    format!("\x1b[<{};{};{}M", button, event.x + 1, event.y + 1).as_bytes(),
-                )?;
+            if self.mouse_tracking != MouseTracking::Off {
+                self.report_mouse(button, false, &event, host.writer())?;
             } else if event.button == MouseButton::Middle {
                 let clip = host.get_clipboard()?;
                 self.send_paste(&clip, host.writer())?
@@ -656,25 +1166,78 @@ impl TerminalState {
         writer: &mut std::io::Write,
     ) -> Result<(), Error> {
         if self.current_mouse_button != MouseButton::None {
+            // SGR mode reports which button was released
+            let button = match self.current_mouse_button {
+                MouseButton::Middle => 1,
+                MouseButton::Right => 2,
+                _ => 0,
+            };
             self.current_mouse_button = MouseButton::None;
-            if self.sgr_mouse {
-                write!(writer, "\x1b[<3;{};{}m", event.x + 1, event.y + 1)?;
+            if self.mouse_tracking != MouseTracking::Off {
+                self.report_mouse(button, true, &event, writer)?;
             }
         }
 
         Ok(())
     }
 
-    fn mouse_move(&mut self, event: MouseEvent, writer: &mut std::io::Write) -> Result<(), Error> {
-        if let Some(button) = match (self.current_mouse_button, self.button_event_mouse) {
-            (MouseButton::Left, true) => Some(32),
-            (MouseButton::Middle, true) => Some(33),
-            (MouseButton::Right, true) => Some(34),
-            (..) => None,
-        } {
-            if self.sgr_mouse {
-                write!(writer, "\x1b[<{};{};{}M", button, event.x + 1, event.y + 1)?;
+    /// Reports movement to a different cell, if the application asked
+    /// for movement with or without a button held down
+    fn mouse_move(
+        &mut self,
+        event: MouseEvent,
+        moved: bool,
+        writer: &mut std::io::Write,
+    ) -> Result<(), Error> {
+        if !moved {
+            return Ok(());
+        }
+        let held = match self.current_mouse_button {
+            MouseButton::Left => Some(0),
+            MouseButton::Middle => Some(1),
+            MouseButton::Right => Some(2),
+            _ => None,
+        };
+        let button = match (self.mouse_tracking, held) {
+            (MouseTracking::Drag, Some(button)) | (MouseTracking::AnyMotion, Some(button)) => {
+                button
             }
+            (MouseTracking::AnyMotion, None) => 3,
+            _ => return Ok(()),
+        };
+        // Movement is flagged by adding 32 to the button
+        self.report_mouse(button + 32, false, &event, writer)
+    }
+
+    /// Writes a report of a mouse event, encoded as the application
+    /// asked, adding the bits for the modifiers to `button`
+    fn report_mouse(
+        &self,
+        button: usize,
+        release: bool,
+        event: &MouseEvent,
+        writer: &mut std::io::Write,
+    ) -> Result<(), Error> {
+        let button = mouse_report_button(button, event.modifiers);
+        let y = event.y.max(0) as usize;
+        if let Some(report) = encode_mouse_report(self.mouse_encoding, button, release, event.x, y)
+        {
+            writer.write_all(&report)?;
+        }
+        Ok(())
+    }
+
+    /// Called when the window that shows the terminal gains or loses
+    /// the keyboard focus, which is reported to the application if
+    /// it asked for that with DEC private mode 1004
+    pub fn focus_changed(
+        &mut self,
+        focused: bool,
+        writer: &mut std::io::Write,
+    ) -> Result<(), Error> {
+        if self.focus_tracking {
+            writer.write_all(if focused { b"\x1b[I" } else { b"\x1b[O" })?;
+            writer.flush()?;
         }
         Ok(())
     }
@@ -700,13 +1263,16 @@ impl TerminalState {
             y: event.y as VisibleRowIndex,
         };
 
-        if new_position != self.mouse_position {
+        let moved = new_position != self.mouse_position;
+        if moved {
             self.mouse_position = new_position;
             self.recompute_highlight();
         }
 
-        // First pass to figure out if we're messing with the selection
-        let send_event = self.sgr_mouse && !event.modifiers.contains(KeyModifiers::SHIFT);
+        // First pass to figure out if we're messing with the selection.
+        // Holding shift lets the user select text regardless.
+        let send_event = self.mouse_tracking != MouseTracking::Off
+            && !event.modifiers.contains(KeyModifiers::SHIFT);
 
         // Perform click counting
         if event.kind == MouseEventKind::Press {
@@ -774,7 +1340,7 @@ impl TerminalState {
             MouseEvent {
                 kind: MouseEventKind::Move,
                 ..
-            } => self.mouse_move(event, host.writer()),
+            } => self.mouse_move(event, moved, host.writer()),
         }
     }
 
@@ -782,17 +1348,18 @@ impl TerminalState {
         self.bracketed_paste
     }
 
+    /// Returns true if the program asked to be sent mouse events
+    pub fn is_mouse_grabbed(&self) -> bool {
+        self.mouse_tracking != MouseTracking::Off
+    }
+
     /// Send text to the terminal that is the result of pasting.
     /// If bracketed paste mode is enabled, the paste is enclosed
     /// in the bracketing, otherwise it is fed to the pty as-is.
+    /// Large pastes are written in chunks, flushing as we go, rather
+    /// than in a single large write.
     pub fn send_paste(&mut self, text: &str, writer: &mut std::io::Write) -> Result<(), Error> {
-        if self.bracketed_paste {
-            let buf = format!("\x1b[200~{}\x1b[201~", text);
-            writer.write_all(buf.as_bytes())?;
-        } else {
-            writer.write_all(text.as_bytes())?;
-        }
-        Ok(())
+        write_paste(text, self.bracketed_paste, writer)
     }
 
     /// Processes a key_down event generated by the gui/render layer
@@ -816,11 +1383,13 @@ impl TerminalState {
         let shift = mods & SHIFT;
         let alt = mods & ALT;
 
-        let mut buf = String::new();
+        let mut buf = self.encode_modified_key(key, mods).unwrap_or_default();
 
         // TODO: also respect self.application_keypad
 
         let to_send = match (key, ctrl, alt, shift, self.application_cursor_keys) {
+            // The modifiers were encoded into buf above
+            _ if !buf.is_empty() => buf.as_str(),
             (Tab, ..) => "\t",
             (Enter, ..) => "\r",
             (Backspace, ..) => "\x08",
@@ -980,8 +1549,89 @@ impl TerminalState {
         Ok(())
     }
 
-    pub fn resize(&mut self, physical_rows: usize, physical_cols: usize) {
+    /// Encodes keys whose modifiers would otherwise be lost, so that
+    /// applications can tell apart chords like Ctrl+Shift+Arrow.
+    /// The cursor and editing keys always report their modifiers in
+    /// the xterm style.  Other keys report them when the application
+    /// enabled modifyOtherKeys or when CSI u encoding is configured.
+    /// Returns None for keys that are sent in the usual way.
+    fn encode_modified_key(&self, key: KeyCode, mods: KeyModifiers) -> Option<String> {
+        use crate::KeyCode::*;
+
+        let shift = mods.contains(KeyModifiers::SHIFT);
+        let alt = mods.contains(KeyModifiers::ALT);
+        let ctrl = mods.contains(KeyModifiers::CTRL);
+        let sup = mods.contains(KeyModifiers::SUPER);
+        if !(shift || alt || ctrl || sup) {
+            return None;
+        }
+        let modifier = 1
+            + if shift { 1 } else { 0 }
+            + if alt { 2 } else { 0 }
+            + if ctrl { 4 } else { 0 }
+            + if sup { 8 } else { 0 };
+
+        match key {
+            UpArrow => return Some(format!("\x1b[1;{}A", modifier)),
+            DownArrow => return Some(format!("\x1b[1;{}B", modifier)),
+            RightArrow => return Some(format!("\x1b[1;{}C", modifier)),
+            LeftArrow => return Some(format!("\x1b[1;{}D", modifier)),
+            Home => return Some(format!("\x1b[1;{}H", modifier)),
+            End => return Some(format!("\x1b[1;{}F", modifier)),
+            Insert => return Some(format!("\x1b[2;{}~", modifier)),
+            Delete => return Some(format!("\x1b[3;{}~", modifier)),
+            // Shift+PageUp and Shift+PageDown scroll the viewport
+            PageUp | PageDown if mods == KeyModifiers::SHIFT => return None,
+            PageUp => return Some(format!("\x1b[5;{}~", modifier)),
+            PageDown => return Some(format!("\x1b[6;{}~", modifier)),
+            _ => {}
+        }
+
+        let (code, is_char) = match key {
+            Char(c) => (c as u32, true),
+            Tab => (0x09, false),
+            Enter => (0x0d, false),
+            Backspace => (0x7f, false),
+            Escape => (0x1b, false),
+            _ => return None,
+        };
+        // Shift is already reflected in the character
+        if is_char && !(alt || ctrl || sup) {
+            return None;
+        }
+
+        if self.csi_u_key_encoding {
+            return Some(format!("\x1b[{};{}u", code, modifier));
+        }
+        let report = match self.modify_other_keys {
+            0 => false,
+            // Only report the keys whose usual encoding drops some
+            // of the modifiers; Ctrl and Alt with a letter are fine
+            1 => match key {
+                Char(c) => sup || (ctrl && (shift || !c.is_ascii_alphabetic())),
+                _ => true,
+            },
+            _ => true,
+        };
+        if report {
+            Some(format!("\x1b[27;{};{}~", modifier, code))
+        } else {
+            None
+        }
+    }
+
+    pub fn resize(
+        &mut self,
+        physical_rows: usize,
+        physical_cols: usize,
+        pixel_width: usize,
+        pixel_height: usize,
+    ) {
+        // The copy cursor may no longer be within the screen
+        self.copy_mode = None;
         self.screen.resize(physical_rows, physical_cols);
+        self.pixel_width = pixel_width;
+        self.pixel_height = pixel_height;
         self.scroll_region = 0..physical_rows as i64;
         self.tabs.resize(physical_cols);
         self.set_scroll_viewport(0);
@@ -989,27 +1639,41 @@ impl TerminalState {
         self.set_cursor_pos(&Position::Relative(0), &Position::Relative(0));
     }
 
-    /// Returns true if any of the visible lines are marked dirty
-    pub fn has_dirty_lines(&self) -> bool {
+    /// Records the changes made to the lines since the previous call
+    /// and returns the sequence number that they were recorded with.
+    /// A consumer that holds on to the returned value can later pass
+    /// it to `get_changed_lines` or `has_changes_since` to learn what
+    /// has changed in the meantime, without affecting what any other
+    /// consumer sees.
+    pub fn current_seqno(&mut self) -> SequenceNo {
+        let seqno = alloc_seqno();
+        let screen = self.screen_mut();
+        for line in &mut screen.lines {
+            line.update_last_change_seqno(seqno);
+        }
+        seqno
+    }
+
+    /// Returns true if any of the visible lines have changed after `seqno`
+    pub fn has_changes_since(&self, seqno: SequenceNo) -> bool {
         let screen = self.screen();
         let height = screen.physical_rows;
         let len = screen.lines.len() - self.viewport_offset as usize;
 
-        for line in screen.lines.iter().skip(len - height) {
-            if line.is_dirty() {
-                return true;
-            }
-        }
-
-        false
+        screen
+            .lines
+            .iter()
+            .skip(len - height)
+            .take(height)
+            .any(|line| line.changed_since(seqno))
     }
 
-    /// Returns the set of visible lines that are dirty.
+    /// Returns the set of visible lines that have changed after `seqno`.
     /// The return value is a Vec<(line_idx, line, selrange)>, where
     /// line_idx is relative to the top of the viewport.
     /// The selrange value is the column range representing the selected
     /// columns on this line.
-    pub fn get_dirty_lines(&self) -> Vec<(usize, &Line, Range<usize>)> {
+    pub fn get_changed_lines(&self, seqno: SequenceNo) -> Vec<(usize, &Line, Range<usize>)> {
         let mut res = Vec::new();
 
         let screen = self.screen();
@@ -1024,7 +1688,7 @@ impl TerminalState {
                 // are below the bottom of the viewport
                 break;
             }
-            if line.is_dirty() {
+            if line.changed_since(seqno) {
                 let selrange = match selection {
                     None => 0..0,
                     Some(sel) => {
@@ -1046,15 +1710,8 @@ impl TerminalState {
         self.viewport_offset
     }
 
-    /// Clear the dirty flag for all dirty lines
-    pub fn clean_dirty_lines(&mut self) {
-        let screen = self.screen_mut();
-        for line in &mut screen.lines {
-            line.clear_dirty();
-        }
-    }
-
-    /// When dealing with selection, mark a range of lines as dirty
+    /// Marks all of the lines as changed, for example because the
+    /// way in which they are presented has changed
     pub fn make_all_lines_dirty(&mut self) {
         let screen = self.screen_mut();
         for line in &mut screen.lines {
@@ -1063,8 +1720,15 @@ impl TerminalState {
     }
 
     /// Returns the 0-based cursor position relative to the top left of
-    /// the visible screen
+    /// the visible screen.  While in copy mode, this is the position
+    /// of the copy cursor.
     pub fn cursor_pos(&self) -> CursorPosition {
+        if let Some(copy) = self.copy_mode.as_ref() {
+            return CursorPosition {
+                x: copy.cursor.x,
+                y: copy.cursor.y as VisibleRowIndex + self.viewport_offset,
+            };
+        }
         // TODO: figure out how to expose cursor visibility; Option<CursorPosition>?
         CursorPosition {
             x: self.cursor.x,
@@ -1072,14 +1736,239 @@ impl TerminalState {
         }
     }
 
+    /// Returns the cursor shape that was requested with DECSCUSR.
+    /// CursorStyle::Default means that the application left the
+    /// choice to the user.
+    pub fn cursor_style(&self) -> CursorStyle {
+        self.cursor_style
+    }
+
+    pub fn in_copy_mode(&self) -> bool {
+        self.copy_mode.is_some()
+    }
+
+    /// Performs a copy mode action.  Returns the selected text when
+    /// the action completes a copy.
+    pub fn copy_mode_action(&mut self, action: CopyModeAction) -> Option<String> {
+        let mut state = match (action, self.copy_mode) {
+            (CopyModeAction::Enter, None) => {
+                // Let the copy cursor reach the whole scrollback
+                self.screen_mut().thaw_all();
+                CopyModeState {
+                    cursor: SelectionCoordinate {
+                        x: self.cursor.x,
+                        y: self.cursor.y as ScrollbackOrVisibleRowIndex,
+                    },
+                    mark: None,
+                }
+            }
+            (_, Some(state)) => state,
+            (_, None) => return None,
+        };
+
+        match action {
+            CopyModeAction::Enter => {}
+            CopyModeAction::Exit => {
+                self.exit_copy_mode();
+                return None;
+            }
+            CopyModeAction::Copy => {
+                let text = if state.mark.is_some() {
+                    Some(self.get_selection_text())
+                } else {
+                    None
+                };
+                self.exit_copy_mode();
+                return text;
+            }
+            CopyModeAction::ToggleSelection => {
+                state.mark = match state.mark {
+                    Some(_) => None,
+                    None => Some(state.cursor),
+                };
+            }
+            CopyModeAction::Move(motion) => {
+                state.cursor = self.copy_mode_motion(state.cursor, motion);
+            }
+        }
+
+        self.update_copy_mode(state);
+        None
+    }
+
+    fn exit_copy_mode(&mut self) {
+        self.copy_mode = None;
+        // This clears the selection and dirties the visible lines,
+        // which takes care of both the copy and terminal cursors
+        self.set_scroll_viewport(0);
+    }
+
+    /// The range of rows that the copy cursor may occupy
+    fn copy_mode_rows(&self) -> Range<ScrollbackOrVisibleRowIndex> {
+        let screen = self.screen();
+        let scrollback = (screen.lines.len() - screen.physical_rows) as ScrollbackOrVisibleRowIndex;
+        -scrollback..screen.physical_rows as ScrollbackOrVisibleRowIndex
+    }
+
+    fn copy_mode_line(&self, y: ScrollbackOrVisibleRowIndex) -> &Line {
+        let screen = self.screen();
+        &screen.lines[screen.scrollback_or_visible_row(y)]
+    }
+
+    fn copy_mode_motion(
+        &self,
+        cursor: SelectionCoordinate,
+        motion: CopyModeMotion,
+    ) -> SelectionCoordinate {
+        let rows = self.copy_mode_rows();
+        let page = self.screen().physical_rows as ScrollbackOrVisibleRowIndex;
+        let last_col = self.screen().physical_cols - 1;
+        let clamp_y = |y: ScrollbackOrVisibleRowIndex| y.max(rows.start).min(rows.end - 1);
+        let is_blank = |line: &Line, x: usize| {
+            line.cells()
+                .get(x)
+                .map(|cell| cell.str().trim().is_empty())
+                .unwrap_or(true)
+        };
+
+        let SelectionCoordinate { x, y } = cursor;
+        match motion {
+            CopyModeMotion::Left => SelectionCoordinate {
+                x: x.saturating_sub(1),
+                y,
+            },
+            CopyModeMotion::Right => SelectionCoordinate {
+                x: (x + 1).min(last_col),
+                y,
+            },
+            CopyModeMotion::Up => SelectionCoordinate {
+                x,
+                y: clamp_y(y - 1),
+            },
+            CopyModeMotion::Down => SelectionCoordinate {
+                x,
+                y: clamp_y(y + 1),
+            },
+            CopyModeMotion::PageUp => SelectionCoordinate {
+                x,
+                y: clamp_y(y - page),
+            },
+            CopyModeMotion::PageDown => SelectionCoordinate {
+                x,
+                y: clamp_y(y + page),
+            },
+            CopyModeMotion::StartOfLine => SelectionCoordinate { x: 0, y },
+            CopyModeMotion::EndOfLine => {
+                let line = self.copy_mode_line(y);
+                let x = (0..line.cells().len())
+                    .rev()
+                    .find(|&x| !is_blank(line, x))
+                    .unwrap_or(0);
+                SelectionCoordinate { x, y }
+            }
+            CopyModeMotion::Top => SelectionCoordinate {
+                x: 0,
+                y: rows.start,
+            },
+            CopyModeMotion::Bottom => SelectionCoordinate {
+                x: 0,
+                y: rows.end - 1,
+            },
+            CopyModeMotion::WordForward => {
+                let (mut x, mut y) = (x, y);
+                // Skip the remainder of the current word
+                let mut line = self.copy_mode_line(y);
+                while x <= last_col && !is_blank(line, x) {
+                    x += 1;
+                }
+                // and then the whitespace that follows it, which
+                // may span multiple lines
+                loop {
+                    while x <= last_col && is_blank(line, x) {
+                        x += 1;
+                    }
+                    if x <= last_col || y + 1 >= rows.end {
+                        break;
+                    }
+                    y += 1;
+                    x = 0;
+                    line = self.copy_mode_line(y);
+                }
+                SelectionCoordinate {
+                    x: x.min(last_col),
+                    y,
+                }
+            }
+            CopyModeMotion::WordBackward => {
+                let (mut x, mut y) = (x, y);
+                let mut line = self.copy_mode_line(y);
+                // Skip backwards over whitespace, which may span
+                // multiple lines
+                loop {
+                    while x > 0 && is_blank(line, x - 1) {
+                        x -= 1;
+                    }
+                    if x > 0 || y <= rows.start {
+                        break;
+                    }
+                    y -= 1;
+                    x = last_col + 1;
+                    line = self.copy_mode_line(y);
+                }
+                // and then to the start of the word
+                while x > 0 && !is_blank(line, x - 1) {
+                    x -= 1;
+                }
+                SelectionCoordinate {
+                    x: x.min(last_col),
+                    y,
+                }
+            }
+        }
+    }
+
+    /// Applies the new copy mode state, scrolling the viewport so
+    /// that the copy cursor is visible and updating the selection
+    fn update_copy_mode(&mut self, state: CopyModeState) {
+        let rows = self.screen().physical_rows as ScrollbackOrVisibleRowIndex;
+        let top = -(self.viewport_offset as ScrollbackOrVisibleRowIndex);
+        let y = state.cursor.y;
+        if y < top {
+            self.set_scroll_viewport(-y as VisibleRowIndex);
+        } else if y >= top + rows {
+            self.set_scroll_viewport((rows - 1 - y) as VisibleRowIndex);
+        }
+
+        if let Some(prior) = self.copy_mode.take() {
+            let screen = self.screen_mut();
+            let idx = screen.scrollback_or_visible_row(prior.cursor.y);
+            screen.line_mut(idx).set_dirty();
+        }
+        {
+            let screen = self.screen_mut();
+            let idx = screen.scrollback_or_visible_row(y);
+            screen.line_mut(idx).set_dirty();
+        }
+
+        self.dirty_selection_lines();
+        self.selection_start = state.mark;
+        self.selection_range = state.mark.map(|mark| SelectionRange {
+            start: mark,
+            end: state.cursor,
+        });
+        self.dirty_selection_lines();
+
+        self.copy_mode = Some(state);
+    }
+
     /// Returns the currently highlighted hyperlink
     pub fn current_highlight(&self) -> Option<Arc<Hyperlink>> {
         self.current_highlight.as_ref().cloned()
     }
 
     /// Sets the cursor position. x and y are 0-based and relative to the
-    /// top left of the visible screen.
-    /// TODO: DEC origin mode impacts the interpreation of these
+    /// top left of the visible screen; positions from the application
+    /// go through `row_from_origin` first, for DEC origin mode.
     fn set_cursor_pos(&mut self, x: &Position, y: &Position) {
         let x = match *x {
             Position::Relative(x) => (self.cursor.x as i64 + x).max(0),
@@ -1093,7 +1982,7 @@ impl TerminalState {
         let rows = self.screen().physical_rows;
         let cols = self.screen().physical_cols;
         let old_y = self.cursor.y;
-        let new_y = y.min(rows as i64 - 1);
+        let new_y = y.max(0).min(rows as i64 - 1);
 
         self.cursor.x = x.min(cols as i64 - 1) as usize;
         self.cursor.y = new_y;
@@ -1104,11 +1993,72 @@ impl TerminalState {
         screen.dirty_line(new_y);
     }
 
-    fn set_scroll_viewport(&mut self, position: VisibleRowIndex) {
+    /// Returns the row of the screen that the application means by
+    /// `row`, which in origin mode counts from the top margin and
+    /// can't be below the scroll region
+    fn row_from_origin(&self, row: i64) -> i64 {
+        if self.dec_origin_mode {
+            (row + self.scroll_region.start).min(self.scroll_region.end - 1)
+        } else {
+            row
+        }
+    }
+
+    /// Moves the cursor to the top left corner, which in origin mode
+    /// is at the top margin
+    fn home_cursor(&mut self) {
+        let y = self.row_from_origin(0);
+        self.set_cursor_pos(&Position::Absolute(0), &Position::Absolute(y));
+    }
+
+    /// Moves the cursor up by `n` rows.  It stops at the top margin,
+    /// unless it started above it.
+    fn cursor_up(&mut self, n: i64) {
+        let y = self.cursor.y;
+        let top = if y >= self.scroll_region.start {
+            self.scroll_region.start
+        } else {
+            0
+        };
+        let y = (y - n).max(top);
+        self.set_cursor_pos(&Position::Relative(0), &Position::Absolute(y));
+    }
+
+    /// Moves the cursor down by `n` rows.  It stops at the bottom
+    /// margin, unless it started below it.
+    fn cursor_down(&mut self, n: i64) {
+        let y = self.cursor.y;
+        let bottom = if y < self.scroll_region.end {
+            self.scroll_region.end - 1
+        } else {
+            self.screen().physical_rows as i64 - 1
+        };
+        let y = (y + n).min(bottom);
+        self.set_cursor_pos(&Position::Relative(0), &Position::Absolute(y));
+    }
+
+    /// Returns up to `rows` lines from the top of the viewport that is
+    /// shown when scrolled back by `offset` rows, without moving the
+    /// viewport.  Compressed lines are brought back as needed.
+    pub fn get_viewport_lines(&mut self, offset: usize, rows: usize) -> Vec<Line> {
+        let physical_rows = self.screen().physical_rows;
+        self.screen_mut().thaw(physical_rows + offset);
+        let screen = self.screen();
+        let scrollback_rows = screen.lines.len() - physical_rows;
+        let top = scrollback_rows - offset.min(scrollback_rows);
+        screen.lines.iter().skip(top).take(rows).cloned().collect()
+    }
+
+    /// Scroll the viewport so that it is `position` rows back from
+    /// the bottom of the scrollback.  Dirties the lines that are now
+    /// in view.
+    pub fn set_scroll_viewport(&mut self, position: VisibleRowIndex) {
         self.clear_selection();
         let position = position.max(0);
 
         let rows = self.screen().physical_rows;
+        // Bring back any compressed lines that are now in view
+        self.screen_mut().thaw(rows + position as usize);
         let avail_scrollback = self.screen().lines.len() - rows;
 
         let position = position.min(avail_scrollback as i64);
@@ -1131,6 +2081,51 @@ impl TerminalState {
         self.set_scroll_viewport(position);
     }
 
+    /// Scroll the viewport to put a shell prompt at the top of it,
+    /// moving back over `delta` prompts if it is negative, or forward
+    /// over them if it is positive.  Moving forward past the last
+    /// prompt returns to the bottom.  The shell must mark its prompts
+    /// with the FinalTerm semantic prompt sequence (OSC 133).
+    pub fn scroll_to_prompt(&mut self, delta: isize) {
+        let screen = self.screen();
+        let bottom_top = screen.lines.len() - screen.physical_rows;
+        let top = bottom_top - self.viewport_offset as usize;
+        let prompts: Vec<usize> = screen
+            .lines
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| line.is_prompt())
+            .map(|(idx, _)| idx)
+            .collect();
+
+        let target = if delta < 0 {
+            prompts
+                .iter()
+                .rev()
+                .filter(|&&idx| idx < top)
+                .nth((-delta - 1) as usize)
+        } else if delta > 0 {
+            prompts
+                .iter()
+                .filter(|&&idx| idx > top)
+                .nth(delta as usize - 1)
+        } else {
+            return;
+        };
+
+        let position = match target {
+            Some(&idx) => bottom_top.saturating_sub(idx),
+            None if delta > 0 => 0,
+            None if screen.cold_lines() > 0 => {
+                // Look through the compressed scrollback too
+                self.screen_mut().thaw_all();
+                return self.scroll_to_prompt(delta);
+            }
+            None => return,
+        };
+        self.set_scroll_viewport(position as VisibleRowIndex);
+    }
+
     fn scroll_up(&mut self, num_rows: usize) {
         self.clear_selection();
         let scroll_region = self.scroll_region.clone();
@@ -1214,6 +2209,20 @@ impl TerminalState {
         }
     }
 
+    /// Returns the size of a cell in pixels, guessing at a typical
+    /// size when the pixel dimensions of the screen are not known
+    fn cell_pixel_size(&self) -> (usize, usize) {
+        let screen = self.screen();
+        if self.pixel_width == 0 || self.pixel_height == 0 {
+            (8, 16)
+        } else {
+            (
+                (self.pixel_width / screen.physical_cols).max(1),
+                (self.pixel_height / screen.physical_rows).max(1),
+            )
+        }
+    }
+
     fn set_image(&mut self, image: ITermFileData) {
         if !image.inline {
             error!(
@@ -1224,7 +2233,8 @@ impl TerminalState {
             return;
         }
 
-        // Decode the image data
+        // Decode the image data to learn its dimensions.  The renderer
+        // decodes it again at the size that it is displayed.
         let decoded_image = match image::load_from_memory(&image.data) {
             Ok(im) => im,
             Err(e) => {
@@ -1238,124 +2248,80 @@ impl TerminalState {
             }
         };
 
-        // Figure out the dimensions.
-        // TODO: we need to understand pixels here, and we don't today,
-        // so "guess" using the values that I see in my setup.
-        let cell_pixel_width = 8;
-        let cell_pixel_height = 15;
-
-        let width = image
-            .width
-            .to_pixels(cell_pixel_width, self.screen().physical_cols);
-        let height = image
-            .height
-            .to_pixels(cell_pixel_height, self.screen().physical_rows);
-
-        // Compute any Automatic dimensions
-        let (width, height) = match (width, height) {
-            (None, None) => (
-                decoded_image.width() as usize,
-                decoded_image.height() as usize,
-            ),
-            (Some(w), None) => {
-                let scale = decoded_image.width() as f32 / w as f32;
-                let h = decoded_image.height() as f32 * scale;
-                (w, h as usize)
-            }
-            (None, Some(h)) => {
-                let scale = decoded_image.height() as f32 / h as f32;
-                let w = decoded_image.width() as f32 * scale;
-                (w as usize, h)
-            }
-            (Some(w), Some(h)) => (w, h),
-        };
-
-        let width_in_cells = width / cell_pixel_width;
-        let height_in_cells = height / cell_pixel_height;
-
-        let available_pixel_width = width_in_cells * cell_pixel_width;
-        let available_pixel_height = height_in_cells * cell_pixel_height;
-
-        // TODO: defer this to the actual renderer
-        /*
-        let resized_image = if image.preserve_aspect_ratio {
-            let resized = decoded_image.resize(
-                available_pixel_width as u32,
-                available_pixel_height as u32,
-                image::FilterType::Lanczos3,
-            );
-            // Pad with black bars to preserve aspect ratio
-            // Assumption: new_rgba8 returns black/transparent pixels by default.
-            let dest = DynamicImage::new_rgba8(available_pixel_width, available_pixel_height);
-            dest.copy_from(resized, 0, 0);
-            dest
-        } else {
-            decoded_image.resize_exact(
-                available_pixel_width as u32,
-                available_pixel_height as u32,
-                image::FilterType::Lanczos3,
-            )
-        };
-        */
-
-        let image_data = Arc::new(ImageData::with_raw_data(image.data));
-
-        let mut ypos = NotNan::new(0.0).unwrap();
+        let (cell_pixel_width, cell_pixel_height) = self.cell_pixel_size();
+        let physical_cols = self.screen().physical_cols;
+        let physical_rows = self.screen().physical_rows;
         let cursor_x = self.cursor.x;
-        let x_delta = 1.0 / available_pixel_width as f32;
-        let y_delta = 1.0 / available_pixel_height as f32;
+        let available_cols = physical_cols.saturating_sub(cursor_x).max(1);
+
+        let (width, height) = image_pixel_size(
+            decoded_image.width() as usize,
+            decoded_image.height() as usize,
+            image.width.to_pixels(cell_pixel_width, physical_cols),
+            image.height.to_pixels(cell_pixel_height, physical_rows),
+            image.preserve_aspect_ratio,
+            available_cols * cell_pixel_width,
+            physical_rows * cell_pixel_height,
+        );
+
+        let width_in_cells = ((width + cell_pixel_width - 1) / cell_pixel_width)
+            .max(1)
+            .min(available_cols);
+        let height_in_cells = ((height + cell_pixel_height - 1) / cell_pixel_height)
+            .max(1)
+            .min(physical_rows);
         debug!(
             "image is {}x{} cells, {}x{} pixels",
             width_in_cells, height_in_cells, width, height
         );
-        for _ in 0..height_in_cells {
-            let mut xpos = NotNan::new(0.0).unwrap();
+
+        let image_data = Arc::new(ImageData::with_raw_data(image.data));
+
+        // Each cell shows its own slice of the image, expressed as a
+        // fraction of the whole image
+        let x_delta = 1.0 / width_in_cells as f32;
+        let y_delta = 1.0 / height_in_cells as f32;
+        for y in 0..height_in_cells {
             let cursor_y = self.cursor.y;
-            debug!(
-                "setting cells for y={} x=[{}..{}]",
-                cursor_y,
-                cursor_x,
-                cursor_x + width_in_cells
-            );
+            let top = NotNan::new(y as f32 * y_delta).unwrap();
+            let bottom = NotNan::new((y + 1) as f32 * y_delta).unwrap();
             for x in 0..width_in_cells {
+                let left = NotNan::new(x as f32 * x_delta).unwrap();
+                let right = NotNan::new((x + 1) as f32 * x_delta).unwrap();
                 self.screen_mut().set_cell(
                     cursor_x + x,
-                    cursor_y, // + y as VisibleRowIndex,
+                    cursor_y,
                     &Cell::new(
                         ' ',
                         CellAttributes::default()
                             .set_image(Some(Box::new(ImageCell::new(
-                                TextureCoordinate::new(xpos, ypos),
-                                TextureCoordinate::new(
-                                    xpos + cell_pixel_width as f32,
-                                    ypos + cell_pixel_height as f32,
-                                ),
-                                image_data.clone(),
+                                TextureCoordinate::new(left, top),
+                                TextureCoordinate::new(right, bottom),
+                                Arc::clone(&image_data),
                             ))))
                             .clone(),
                     ),
                 );
-                xpos += x_delta;
             }
-            ypos += y_delta;
             self.new_line(false);
         }
-
-        // FIXME: check cursor positioning in iterm
-        /*
-        self.set_cursor_pos(
-            &Position::Relative(width_in_cells as i64),
-            &Position::Relative(-(height_in_cells as i64)),
-        );
-        */
     }
 
     fn perform_device(&mut self, dev: Device, host: &mut TerminalHost) {
         match dev {
             Device::DeviceAttributes(a) => error!("unhandled: {:?}", a),
             Device::SoftReset => {
+                // See https://vt100.net/docs/vt510-rm/DECSTR.html
                 self.pen = CellAttributes::default();
-                // TODO: see https://vt100.net/docs/vt510-rm/DECSTR.html
+                self.insert = false;
+                self.dec_origin_mode = false;
+                self.cursor_visible = true;
+                self.application_cursor_keys = false;
+                self.application_keypad = false;
+                self.dec_line_drawing_mode = false;
+                self.scroll_region = 0..self.screen().physical_rows as VisibleRowIndex;
+                // Restoring the cursor now homes it
+                *self.screen.saved_cursor() = None;
             }
             Device::RequestPrimaryDeviceAttributes => {
                 host.writer().write(DEVICE_IDENT).ok();
@@ -1385,6 +2351,13 @@ impl TerminalState {
                 self.insert = false;
             }
 
+            Mode::SetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::AnsiArt)) => {
+                self.ansi_art_mode = true;
+            }
+            Mode::ResetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::AnsiArt)) => {
+                self.ansi_art_mode = false;
+            }
+
             Mode::SetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::BracketedPaste)) => {
                 self.bracketed_paste = true;
             }
@@ -1392,8 +2365,20 @@ impl TerminalState {
                 self.bracketed_paste = false;
             }
 
+            Mode::SetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::OriginMode)) => {
+                self.dec_origin_mode = true;
+                self.home_cursor();
+            }
+            Mode::ResetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::OriginMode)) => {
+                self.dec_origin_mode = false;
+                self.home_cursor();
+            }
+
             Mode::SetDecPrivateMode(DecPrivateMode::Code(
                 DecPrivateModeCode::EnableAlternateScreen,
+            ))
+            | Mode::SetDecPrivateMode(DecPrivateMode::Code(
+                DecPrivateModeCode::OptEnableAlternateScreen,
             )) => {
                 if !self.screen.is_alt_screen_active() {
                     self.screen.activate_alt_screen();
@@ -1408,6 +2393,22 @@ impl TerminalState {
                     self.set_scroll_viewport(0);
                 }
             }
+            Mode::ResetDecPrivateMode(DecPrivateMode::Code(
+                DecPrivateModeCode::OptEnableAlternateScreen,
+            )) => {
+                if self.screen.is_alt_screen_active() {
+                    self.erase_in_display(EraseInDisplay::EraseDisplay);
+                    self.screen.activate_primary_screen();
+                    self.set_scroll_viewport(0);
+                }
+            }
+
+            Mode::SetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::SaveCursor)) => {
+                self.save_cursor();
+            }
+            Mode::ResetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::SaveCursor)) => {
+                self.restore_cursor();
+            }
 
             Mode::SetDecPrivateMode(DecPrivateMode::Code(
                 DecPrivateModeCode::ApplicationCursorKeys,
@@ -1427,8 +2428,24 @@ impl TerminalState {
                 self.cursor_visible = false;
             }
 
-            Mode::SetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::MouseTracking))
-            | Mode::ResetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::MouseTracking)) => {
+            Mode::SetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::MouseTracking)) => {
+                self.mouse_tracking = MouseTracking::Buttons;
+            }
+            Mode::SetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::ButtonEventMouse)) => {
+                self.mouse_tracking = MouseTracking::Drag;
+            }
+            Mode::SetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::AnyEventMouse)) => {
+                self.mouse_tracking = MouseTracking::AnyMotion;
+            }
+            // As in xterm, resetting any of the tracking modes stops
+            // the reports
+            Mode::ResetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::MouseTracking))
+            | Mode::ResetDecPrivateMode(DecPrivateMode::Code(
+                DecPrivateModeCode::ButtonEventMouse,
+            ))
+            | Mode::ResetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::AnyEventMouse)) => {
+                self.mouse_tracking = MouseTracking::Off;
+                self.current_mouse_button = MouseButton::None;
             }
 
             Mode::SetDecPrivateMode(DecPrivateMode::Code(
@@ -1438,33 +2455,35 @@ impl TerminalState {
                 DecPrivateModeCode::HighlightMouseTracking,
             )) => {}
 
-            Mode::SetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::ButtonEventMouse)) => {
-                self.button_event_mouse = true;
+            Mode::SetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::Utf8Mouse)) => {
+                self.mouse_encoding = MouseEncoding::Utf8;
             }
-            Mode::ResetDecPrivateMode(DecPrivateMode::Code(
-                DecPrivateModeCode::ButtonEventMouse,
-            )) => {
-                self.button_event_mouse = false;
+            Mode::SetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::SGRMouse)) => {
+                self.mouse_encoding = MouseEncoding::Sgr;
             }
-
-            Mode::SetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::AnyEventMouse))
-            | Mode::ResetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::AnyEventMouse)) => {
+            Mode::SetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::UrxvtMouse)) => {
+                self.mouse_encoding = MouseEncoding::Urxvt;
+            }
+            Mode::ResetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::Utf8Mouse))
+            | Mode::ResetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::SGRMouse))
+            | Mode::ResetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::UrxvtMouse)) => {
+                self.mouse_encoding = MouseEncoding::X10;
             }
 
-            Mode::SetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::SGRMouse)) => {
-                self.sgr_mouse = true;
+            Mode::SetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::FocusTracking)) => {
+                self.focus_tracking = true;
             }
-            Mode::ResetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::SGRMouse)) => {
-                self.sgr_mouse = false;
+            Mode::ResetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::FocusTracking)) => {
+                self.focus_tracking = false;
             }
 
             Mode::SetDecPrivateMode(DecPrivateMode::Code(
                 DecPrivateModeCode::ClearAndEnableAlternateScreen,
             )) => {
                 if !self.screen.is_alt_screen_active() {
+                    // As in xterm, the cursor stays where it was
                     self.save_cursor();
                     self.screen.activate_alt_screen();
-                    self.set_cursor_pos(&Position::Absolute(0), &Position::Absolute(0));
                     self.erase_in_display(EraseInDisplay::EraseDisplay);
                     self.set_scroll_viewport(0);
                 }
@@ -1498,6 +2517,16 @@ impl TerminalState {
             Mode::SetMode(m) | Mode::ResetMode(m) => {
                 error!("unhandled TerminalMode {:?}", m);
             }
+
+            Mode::XtermKeyMode {
+                resource: XtermKeyModifierResource::OtherKeys,
+                value,
+            } => {
+                self.modify_other_keys = value.unwrap_or(0);
+            }
+            Mode::XtermKeyMode { resource, value } => {
+                error!("unhandled XtermKeyMode {:?} {:?}", resource, value);
+            }
         }
     }
 
@@ -1696,15 +2725,15 @@ impl TerminalState {
     fn perform_csi_cursor(&mut self, cursor: Cursor, host: &mut TerminalHost) {
         match cursor {
             Cursor::SetTopAndBottomMargins { top, bottom } => {
-                let rows = self.screen().physical_rows;
-                let mut top = i64::from(top.as_zero_based()).min(rows as i64 - 1).max(0);
-                let mut bottom = i64::from(bottom.as_zero_based())
-                    .min(rows as i64 - 1)
-                    .max(0);
-                if top > bottom {
-                    std::mem::swap(&mut top, &mut bottom);
+                let rows = self.screen().physical_rows as i64;
+                let top = i64::from(top.as_zero_based()).min(rows - 1);
+                let bottom = i64::from(bottom.as_zero_based()).min(rows - 1);
+                // As in xterm, a region must span at least two lines,
+                // and the cursor goes home when it is set
+                if top < bottom {
+                    self.scroll_region = top..bottom + 1;
+                    self.home_cursor();
                 }
-                self.scroll_region = top..bottom + 1;
             }
             Cursor::ForwardTabulation(n) => {
                 for _ in 0..n {
@@ -1722,17 +2751,15 @@ impl TerminalState {
             Cursor::Right(n) => {
                 self.set_cursor_pos(&Position::Relative(i64::from(n)), &Position::Relative(0))
             }
-            Cursor::Up(n) => {
-                self.set_cursor_pos(&Position::Relative(0), &Position::Relative(-(i64::from(n))))
-            }
-            Cursor::Down(n) => {
-                self.set_cursor_pos(&Position::Relative(0), &Position::Relative(i64::from(n)))
-            }
-            Cursor::CharacterAndLinePosition { line, col } | Cursor::Position { line, col } => self
-                .set_cursor_pos(
+            Cursor::Up(n) => self.cursor_up(i64::from(n)),
+            Cursor::Down(n) => self.cursor_down(i64::from(n)),
+            Cursor::CharacterAndLinePosition { line, col } | Cursor::Position { line, col } => {
+                let y = self.row_from_origin(i64::from(line.as_zero_based()));
+                self.set_cursor_pos(
                     &Position::Absolute(i64::from(col.as_zero_based())),
-                    &Position::Absolute(i64::from(line.as_zero_based())),
-                ),
+                    &Position::Absolute(y),
+                )
+            }
             Cursor::CharacterAbsolute(col) | Cursor::CharacterPositionAbsolute(col) => self
                 .set_cursor_pos(
                     &Position::Absolute(i64::from(col.as_zero_based())),
@@ -1745,10 +2772,10 @@ impl TerminalState {
             Cursor::CharacterPositionForward(col) => {
                 self.set_cursor_pos(&Position::Relative(i64::from(col)), &Position::Relative(0))
             }
-            Cursor::LinePositionAbsolute(line) => self.set_cursor_pos(
-                &Position::Relative(0),
-                &Position::Absolute((i64::from(line)).saturating_sub(1)),
-            ),
+            Cursor::LinePositionAbsolute(line) => {
+                let y = self.row_from_origin(i64::from(line).saturating_sub(1));
+                self.set_cursor_pos(&Position::Relative(0), &Position::Absolute(y))
+            }
             Cursor::LinePositionBackward(line) => self.set_cursor_pos(
                 &Position::Relative(0),
                 &Position::Relative(-(i64::from(line))),
@@ -1757,26 +2784,36 @@ impl TerminalState {
                 self.set_cursor_pos(&Position::Relative(0), &Position::Relative(i64::from(line)))
             }
             Cursor::NextLine(n) => {
-                for _ in 0..n {
-                    self.new_line(true);
-                }
+                self.cursor_down(i64::from(n));
+                self.set_cursor_pos(&Position::Absolute(0), &Position::Relative(0));
             }
             Cursor::PrecedingLine(n) => {
-                self.set_cursor_pos(&Position::Absolute(0), &Position::Relative(-(i64::from(n))))
+                self.cursor_up(i64::from(n));
+                self.set_cursor_pos(&Position::Absolute(0), &Position::Relative(0));
             }
             Cursor::ActivePositionReport { .. } => {
                 // This is really a response from the terminal, and
                 // we don't need to process it as a terminal command
             }
             Cursor::RequestActivePositionReport => {
-                let line = OneBased::from_zero_based(self.cursor.y as u32);
+                let top = if self.dec_origin_mode {
+                    self.scroll_region.start
+                } else {
+                    0
+                };
+                let line = OneBased::from_zero_based((self.cursor.y - top).max(0) as u32);
                 let col = OneBased::from_zero_based(self.cursor.x as u32);
                 let report = CSI::Cursor(Cursor::ActivePositionReport { line, col });
                 write!(host.writer(), "{}", report).ok();
             }
             Cursor::SaveCursor => self.save_cursor(),
             Cursor::RestoreCursor => self.restore_cursor(),
-            Cursor::CursorStyle(style) => error!("unhandled: CursorStyle {:?}", style),
+            Cursor::CursorStyle(style) => {
+                self.cursor_style = style;
+                // Repaint the cursor in its new shape
+                let y = self.cursor.y;
+                self.screen_mut().dirty_line(y);
+            }
         }
     }
 
@@ -1785,6 +2822,7 @@ impl TerminalState {
             position: self.cursor,
             insert: self.insert,
             wrap_next: self.wrap_next,
+            dec_origin_mode: self.dec_origin_mode,
         };
         debug!(
             "saving cursor {:?} is_alt={}",
@@ -1798,6 +2836,7 @@ impl TerminalState {
             position: CursorPosition::default(),
             insert: false,
             wrap_next: false,
+            dec_origin_mode: false,
         });
         debug!(
             "restore cursor {:?} is_alt={}",
@@ -1809,6 +2848,7 @@ impl TerminalState {
         self.set_cursor_pos(&Position::Absolute(x as i64), &Position::Absolute(y));
         self.wrap_next = saved.wrap_next;
         self.insert = saved.insert;
+        self.dec_origin_mode = saved.dec_origin_mode;
     }
 
     fn perform_csi_sgr(&mut self, sgr: Sgr) {
@@ -1840,6 +2880,9 @@ impl TerminalState {
             Sgr::StrikeThrough(strike) => {
                 self.pen.set_strikethrough(strike);
             }
+            Sgr::Overline(overline) => {
+                self.pen.set_overline(overline);
+            }
             Sgr::Foreground(col) => {
                 self.pen.set_foreground(col);
             }
@@ -1925,6 +2968,21 @@ impl<'a> Performer<'a> {
             let width = self.screen().physical_cols;
 
             let mut pen = self.pen.clone();
+            if self.ansi_art_mode && pen.blink() != Blink::None {
+                // iCE colors: ANSI art uses the blink attribute to
+                // select the bright half of the palette for the
+                // background, which is otherwise unavailable
+                pen.set_blink(Blink::None);
+                let background = match pen.background {
+                    ColorAttribute::PaletteIndex(idx) if idx < 8 => {
+                        ColorAttribute::PaletteIndex(idx + 8)
+                    }
+                    // The art is drawn on black
+                    ColorAttribute::Default => ColorAttribute::PaletteIndex(8),
+                    background => background,
+                };
+                pen.set_background(background);
+            }
             // the max(1) here is to ensure that we advance to the next cell
             // position for zero-width graphemes.  We want to make sure that
             // they occupy a cell so that we can re-emit them when we output them.
@@ -1970,6 +3028,7 @@ impl<'a> Performer<'a> {
             Action::Print(c) => self.print(c),
             Action::Control(code) => self.control(code),
             Action::DeviceControl(ctrl) => error!("Unhandled {:?}", ctrl),
+            Action::OperatingSystemCommand(_) if self.dumb_mode => {}
             Action::OperatingSystemCommand(osc) => self.osc_dispatch(*osc),
             Action::Esc(esc) => self.esc_dispatch(esc),
             Action::CSI(csi) => self.csi_dispatch(csi),
@@ -1995,13 +3054,22 @@ impl<'a> Performer<'a> {
                 self.set_cursor_pos(&Position::Relative(-1), &Position::Relative(0));
             }
             ControlCode::HorizontalTab => self.c0_horizontal_tab(),
-            ControlCode::Bell => error!("Ding! (this is the bell)"),
+            ControlCode::Bell => self.host.bell(),
             _ => error!("unhandled ControlCode {:?}", control),
         }
     }
 
     fn csi_dispatch(&mut self, csi: CSI) {
         self.flush_print();
+        if self.dumb_mode {
+            match csi {
+                CSI::Cursor(_) | CSI::Edit(_) | CSI::Device(_) => {}
+                _ => {
+                    debug!("dumb mode discards {:?}", csi);
+                    return;
+                }
+            }
+        }
         match csi {
             CSI::Sgr(sgr) => self.state.perform_csi_sgr(sgr),
             CSI::Cursor(cursor) => self.state.perform_csi_cursor(cursor, self.host),
@@ -2056,6 +3124,11 @@ impl<'a> Performer<'a> {
                 self.host.set_title(&title);
             }
             OperatingSystemCommand::SetIconName(_) => {}
+            OperatingSystemCommand::SetCurrentWorkingDirectory(url) => {
+                let dir = path_from_file_url(&url);
+                self.host.set_current_dir(&dir);
+                self.current_dir = Some(dir);
+            }
             OperatingSystemCommand::SetHyperlink(link) => {
                 self.set_hyperlink(link);
             }
@@ -2071,7 +3144,16 @@ impl<'a> Performer<'a> {
             OperatingSystemCommand::ClearSelection(_) => {
                 self.host.set_clipboard(None).ok();
             }
-            OperatingSystemCommand::QuerySelection(_) => {}
+            OperatingSystemCommand::QuerySelection(selection) => match self.host.get_clipboard() {
+                Ok(clip) => {
+                    let response = OperatingSystemCommand::SetSelection(selection, clip);
+                    write!(self.host.writer(), "{}", response).ok();
+                }
+                // Queries are usually denied by osc52_clipboard_access,
+                // and programs may ask often, so keep this out of the
+                // error log
+                Err(err) => debug!("failed to query clipboard in response to OSC 52: {}", err),
+            },
             OperatingSystemCommand::SetSelection(_, selection_data) => {
                 match self.host.set_clipboard(Some(selection_data)) {
                     Ok(_) => (),
@@ -2080,8 +3162,24 @@ impl<'a> Performer<'a> {
             }
             OperatingSystemCommand::ITermProprietary(iterm) => match iterm {
                 ITermProprietary::File(image) => self.set_image(*image),
+                // We don't have profiles, but color schemes are the
+                // closest equivalent
+                ITermProprietary::SetProfile(name) => self.host.set_color_scheme(&name),
+                ITermProprietary::CurrentDir(dir) => {
+                    self.host.set_current_dir(&dir);
+                    self.current_dir = Some(dir);
+                }
                 _ => error!("unhandled iterm2: {:?}", iterm),
             },
+            OperatingSystemCommand::FinalTermSemanticPrompt(marker) => match marker {
+                FinalTermSemanticPrompt::PromptStart => {
+                    let row = self.screen().phys_row(self.cursor.y);
+                    self.screen_mut().line_mut(row).set_prompt();
+                }
+                // Only the start of each prompt is needed to move
+                // between them
+                _ => {}
+            },
             OperatingSystemCommand::SystemNotification(message) => {
                 error!("Application sends SystemNotification: {}", message);
             }
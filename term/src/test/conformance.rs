@@ -0,0 +1,118 @@
+//! Feeds recorded streams of output, modelled on vttest screens and
+//! captures of full screen programs, to the terminal and compares the
+//! screen that results with the expectation recorded alongside them.
+//!
+//! Each case is a pair of files in the `streams` directory: the raw
+//! bytes of the output in `<case>.stream`, and the expected outcome in
+//! `<case>.screen`, which looks like this:
+//!
+//! ```text
+//! # Comments start with a hash
+//! size 4x10
+//! cursor 1,0
+//! |a|
+//! |  b|
+//! ||
+//! ||
+//! ```
+//!
+//! `size` is the number of rows and columns of the terminal, `cursor`
+//! is the column and row that the cursor ends up at, and the text of
+//! each visible line of the screen follows between bars.  Trailing
+//! spaces are not significant.
+
+use super::*;
+
+/// The outcome that a stream is expected to have
+#[derive(Debug)]
+struct Expectation {
+    rows: usize,
+    cols: usize,
+    cursor: Option<(usize, i64)>,
+    lines: Vec<String>,
+}
+
+impl Expectation {
+    fn parse(text: &str) -> Self {
+        let mut expect = Expectation {
+            rows: 0,
+            cols: 0,
+            cursor: None,
+            lines: vec![],
+        };
+        for line in text.lines() {
+            if line.starts_with('#') || line.trim().is_empty() {
+                continue;
+            }
+            if line.starts_with('|') {
+                assert!(line.ends_with('|'), "unterminated line {:?}", line);
+                let text = &line[1..line.len() - 1];
+                expect.lines.push(text.trim_end().to_owned());
+            } else if line.starts_with("size ") {
+                let mut dims = line["size ".len()..].split('x');
+                expect.rows = dims.next().unwrap().parse().unwrap();
+                expect.cols = dims.next().unwrap().parse().unwrap();
+            } else if line.starts_with("cursor ") {
+                let mut pos = line["cursor ".len()..].split(',');
+                let x = pos.next().unwrap().parse().unwrap();
+                let y = pos.next().unwrap().parse().unwrap();
+                expect.cursor = Some((x, y));
+            } else {
+                panic!("unrecognized line {:?}", line);
+            }
+        }
+        assert_eq!(expect.lines.len(), expect.rows, "wrong number of lines");
+        expect
+    }
+}
+
+fn check(term: &TestTerm, expect: &Expectation, how: &str) {
+    print_visible_lines(term);
+    let lines: Vec<String> = term
+        .screen()
+        .visible_lines()
+        .iter()
+        .map(|line| line.as_str().trim_end().to_owned())
+        .collect();
+    assert_eq!(lines, expect.lines, "screen didn't match when fed {}", how);
+    if let Some((x, y)) = expect.cursor {
+        term.assert_cursor_pos(x, y, Some(how));
+    }
+}
+
+/// Feeds the stream to a terminal all at once, and to another one a
+/// byte at a time, so that sequences are also split between reads
+fn check_stream(stream: &[u8], expected: &str) {
+    let expect = Expectation::parse(expected);
+
+    let mut term = TestTerm::new(expect.rows, expect.cols, 10);
+    term.print(stream);
+    check(&term, &expect, "all at once");
+
+    let mut term = TestTerm::new(expect.rows, expect.cols, 10);
+    for byte in stream {
+        term.print(&[*byte]);
+    }
+    check(&term, &expect, "a byte at a time");
+}
+
+macro_rules! stream_case {
+    ($name:ident) => {
+        #[test]
+        fn $name() {
+            check_stream(
+                include_bytes!(concat!("streams/", stringify!($name), ".stream")),
+                include_str!(concat!("streams/", stringify!($name), ".screen")),
+            );
+        }
+    };
+}
+
+stream_case!(scroll_region_index);
+stream_case!(scroll_region_cursor_movement);
+stream_case!(scroll_region_invalid_and_soft_reset);
+stream_case!(origin_mode);
+stream_case!(alt_screen_vim);
+stream_case!(alt_screen_vim_exit);
+stream_case!(alt_screen_1047);
+stream_case!(save_cursor_1048);
@@ -0,0 +1,110 @@
+use super::*;
+use pretty_assertions::assert_eq;
+
+fn copy_mode(term: &mut TestTerm, actions: &[CopyModeAction]) -> Option<String> {
+    let mut result = None;
+    for action in actions {
+        result = term.copy_mode_action(*action);
+    }
+    result
+}
+
+/// Select a region with the keyboard and copy it
+#[test]
+fn copy_mode_selection() {
+    use CopyModeAction::*;
+    use CopyModeMotion::*;
+    let mut term = TestTerm::new(3, 12, 0);
+    term.print("hello world\r\nsecond line");
+
+    copy_mode(&mut term, &[Enter]);
+    assert!(term.in_copy_mode());
+    assert_eq!(term.cursor_pos(), CursorPosition { x: 11, y: 1 });
+
+    copy_mode(&mut term, &[Move(StartOfLine), Move(Up)]);
+    assert_eq!(term.cursor_pos(), CursorPosition { x: 0, y: 0 });
+
+    let text = copy_mode(&mut term, &[ToggleSelection, Move(EndOfLine), Copy]);
+    assert_eq!(text, Some("hello world".to_string()));
+    assert!(!term.in_copy_mode());
+    assert_eq!(term.cursor_pos(), CursorPosition { x: 11, y: 1 });
+}
+
+/// Leaving copy mode without copying discards the selection
+#[test]
+fn copy_mode_exit() {
+    use CopyModeAction::*;
+    use CopyModeMotion::*;
+    let mut term = TestTerm::new(3, 12, 0);
+    term.print("hello world");
+
+    assert_eq!(
+        copy_mode(&mut term, &[Enter, ToggleSelection, Move(Left), Exit]),
+        None
+    );
+    assert!(!term.in_copy_mode());
+    assert_eq!(term.get_selection_text(), "");
+
+    // Copying without a selection yields nothing
+    assert_eq!(copy_mode(&mut term, &[Enter, Copy]), None);
+}
+
+#[test]
+fn copy_mode_word_motion() {
+    use CopyModeAction::*;
+    use CopyModeMotion::*;
+    let mut term = TestTerm::new(3, 12, 0);
+    term.print("one two\r\nthree");
+
+    copy_mode(&mut term, &[Enter, Move(Top)]);
+    assert_eq!(term.cursor_pos(), CursorPosition { x: 0, y: 0 });
+
+    copy_mode(&mut term, &[Move(WordForward)]);
+    assert_eq!(term.cursor_pos(), CursorPosition { x: 4, y: 0 });
+
+    // Continues onto the next line
+    copy_mode(&mut term, &[Move(WordForward)]);
+    assert_eq!(term.cursor_pos(), CursorPosition { x: 0, y: 1 });
+
+    copy_mode(&mut term, &[Move(WordBackward)]);
+    assert_eq!(term.cursor_pos(), CursorPosition { x: 4, y: 0 });
+
+    copy_mode(&mut term, &[Move(WordBackward)]);
+    assert_eq!(term.cursor_pos(), CursorPosition { x: 0, y: 0 });
+}
+
+/// Moving the copy cursor above the top of the screen scrolls
+/// back through the scrollback
+#[test]
+fn copy_mode_scrollback() {
+    use CopyModeAction::*;
+    use CopyModeMotion::*;
+    let mut term = TestTerm::new(2, 5, 10);
+    term.print("a\r\nb\r\nc\r\nd");
+
+    copy_mode(&mut term, &[Enter, Move(StartOfLine), Move(Up), Move(Up)]);
+    assert_eq!(term.get_viewport_offset(), 1);
+    assert_eq!(term.cursor_pos(), CursorPosition { x: 0, y: 0 });
+    term.assert_viewport_contents(&["b    ", "c    "]);
+
+    copy_mode(&mut term, &[ToggleSelection, Move(Down), Move(Down)]);
+    assert_eq!(term.get_viewport_offset(), 0);
+    assert_eq!(term.cursor_pos(), CursorPosition { x: 0, y: 1 });
+
+    assert_eq!(copy_mode(&mut term, &[Copy]), Some("b\nc\nd".to_string()));
+}
+
+/// Resizing leaves copy mode, whose cursor may be outside of the
+/// smaller screen
+#[test]
+fn copy_mode_resize() {
+    use CopyModeAction::*;
+    use CopyModeMotion::*;
+    let mut term = TestTerm::new(3, 12, 0);
+    term.print("hello world");
+
+    copy_mode(&mut term, &[Enter, ToggleSelection, Move(StartOfLine)]);
+    term.resize(2, 5, 0, 0);
+    assert!(!term.in_copy_mode());
+    assert_eq!(copy_mode(&mut term, &[Copy]), None);
+}
@@ -176,3 +176,28 @@ fn test_ed() {
         Compare::TEXT | Compare::ATTRS,
     );
 }
+
+#[test]
+fn test_decscusr() {
+    use termwiz::escape::csi::CursorStyle;
+    let mut term = TestTerm::new(3, 4, 0);
+    assert_eq!(term.cursor_style(), CursorStyle::Default);
+    term.print("\x1b[5 q");
+    assert_eq!(term.cursor_style(), CursorStyle::BlinkingBar);
+    term.print("\x1b[4 q");
+    assert_eq!(term.cursor_style(), CursorStyle::SteadyUnderline);
+    term.print("\x1b[0 q");
+    assert_eq!(term.cursor_style(), CursorStyle::Default);
+}
+
+#[test]
+fn test_cpr_origin_mode() {
+    let mut term = TestTerm::new(6, 4, 0);
+    term.print("\x1b[3;5r\x1b[?6h\x1b[2;2H\x1b[6n");
+    term.assert_cursor_pos(1, 3, None);
+    // The report counts from the top margin, like the position did
+    assert_eq!(
+        String::from_utf8(term.host.written.clone()).unwrap(),
+        "\x1b[2;2R"
+    );
+}
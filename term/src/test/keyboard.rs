@@ -0,0 +1,91 @@
+use super::*;
+
+fn send_key(term: &mut TestTerm, key: KeyCode, mods: KeyModifiers) -> String {
+    let mut out = Vec::new();
+    term.key_down(key, mods, &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn modified_cursor_keys() {
+    let mut term = TestTerm::new(3, 4, 0);
+    assert_eq!(
+        send_key(&mut term, KeyCode::UpArrow, KeyModifiers::NONE),
+        "\x1b[A"
+    );
+    assert_eq!(
+        send_key(
+            &mut term,
+            KeyCode::LeftArrow,
+            KeyModifiers::CTRL | KeyModifiers::SHIFT
+        ),
+        "\x1b[1;6D"
+    );
+    assert_eq!(
+        send_key(&mut term, KeyCode::Delete, KeyModifiers::ALT),
+        "\x1b[3;3~"
+    );
+}
+
+#[test]
+fn modify_other_keys() {
+    let mut term = TestTerm::new(3, 4, 0);
+    let ctrl_shift = KeyModifiers::CTRL | KeyModifiers::SHIFT;
+
+    // Without modifyOtherKeys, Ctrl+Shift+C is the same as Ctrl+C
+    assert_eq!(send_key(&mut term, KeyCode::Char('C'), ctrl_shift), "\x03");
+
+    term.print("\x1b[>4;1m");
+    assert_eq!(
+        send_key(&mut term, KeyCode::Char('C'), ctrl_shift),
+        "\x1b[27;6;67~"
+    );
+    // Level 1 leaves the unambiguous chords alone
+    assert_eq!(
+        send_key(&mut term, KeyCode::Char('c'), KeyModifiers::CTRL),
+        "\x03"
+    );
+
+    term.print("\x1b[>4;2m");
+    assert_eq!(
+        send_key(&mut term, KeyCode::Char('c'), KeyModifiers::CTRL),
+        "\x1b[27;5;99~"
+    );
+    assert_eq!(
+        send_key(&mut term, KeyCode::Enter, KeyModifiers::SHIFT),
+        "\x1b[27;2;13~"
+    );
+    // Shift alone is already reflected in the character
+    assert_eq!(
+        send_key(&mut term, KeyCode::Char('C'), KeyModifiers::SHIFT),
+        "C"
+    );
+
+    term.print("\x1b[>4m");
+    assert_eq!(
+        send_key(&mut term, KeyCode::Char('c'), KeyModifiers::CTRL),
+        "\x03"
+    );
+}
+
+#[test]
+fn csi_u() {
+    let mut term = TestTerm::new(3, 4, 0);
+    term.set_csi_u_key_encoding(true);
+    assert_eq!(
+        send_key(
+            &mut term,
+            KeyCode::Char('C'),
+            KeyModifiers::CTRL | KeyModifiers::SHIFT
+        ),
+        "\x1b[67;6u"
+    );
+    assert_eq!(
+        send_key(&mut term, KeyCode::Tab, KeyModifiers::CTRL),
+        "\x1b[9;5u"
+    );
+    assert_eq!(
+        send_key(&mut term, KeyCode::Char('a'), KeyModifiers::NONE),
+        "a"
+    );
+}
@@ -5,17 +5,31 @@ use super::*;
 mod c0;
 use bitflags::bitflags;
 mod c1;
+mod conformance;
+mod copymode;
 mod csi;
+mod keyboard;
 mod selection;
+use crate::color::ColorAttribute;
 use pretty_assertions::assert_eq;
 use std::sync::Arc;
 use termwiz::escape::csi::{Edit, EraseInDisplay, EraseInLine};
+use termwiz::escape::osc::{ITermDimension, ITermFileData, ITermProprietary};
 use termwiz::escape::{OneBased, OperatingSystemCommand, CSI};
+use termwiz::image::TextureCoordinate;
 
 #[derive(Default, Debug)]
 struct TestHost {
     title: String,
     clip: Option<String>,
+    /// The data that the terminal sent back to the application
+    written: Vec<u8>,
+    /// The hyperlinks that were clicked
+    clicked: Vec<Arc<Hyperlink>>,
+    /// The number of times that the bell was rung
+    bells: usize,
+    /// The working directories that the terminal reported
+    dirs: Vec<String>,
 }
 
 impl TestHost {
@@ -25,11 +39,12 @@ impl TestHost {
 }
 
 impl std::io::Write for TestHost {
-    fn write(&mut self, _buf: &[u8]) -> Result<usize, std::io::Error> {
-        panic!("no writer support in TestHost");
+    fn write(&mut self, buf: &[u8]) -> Result<usize, std::io::Error> {
+        self.written.extend_from_slice(buf);
+        Ok(buf.len())
     }
     fn flush(&mut self) -> Result<(), std::io::Error> {
-        panic!("no writer support in TestHost");
+        Ok(())
     }
 }
 
@@ -54,22 +69,41 @@ impl TerminalHost for TestHost {
         self
     }
 
-    fn click_link(&mut self, _link: &Arc<Hyperlink>) {}
+    fn click_link(&mut self, link: &Arc<Hyperlink>) {
+        self.clicked.push(Arc::clone(link));
+    }
+
+    fn bell(&mut self) {
+        self.bells += 1;
+    }
+
+    fn set_current_dir(&mut self, dir: &str) {
+        self.dirs.push(dir.to_owned());
+    }
 }
 
 struct TestTerm {
     term: Terminal,
     host: TestHost,
+    /// The sequence number as of the last call to mark_seen
+    seqno: SequenceNo,
 }
 
 impl TestTerm {
     fn new(height: usize, width: usize, scrollback: usize) -> Self {
         Self {
-            term: Terminal::new(height, width, scrollback, Vec::new()),
+            term: Terminal::new(height, width, 0, 0, scrollback, Vec::new()),
             host: TestHost::new(),
+            seqno: SEQ_ZERO,
         }
     }
 
+    /// Treat the current state of the lines as having been seen,
+    /// so that only subsequent changes are reported as dirty
+    fn mark_seen(&mut self) {
+        self.seqno = self.term.current_seqno();
+    }
+
     fn print<B: AsRef<[u8]>>(&mut self, bytes: B) {
         self.term.advance_bytes(bytes, &mut self.host);
     }
@@ -156,6 +190,27 @@ impl TestTerm {
         }
     }
 
+    /// Moves the mouse to the specified coordinates and clicks the
+    /// left button there while holding down `modifiers`
+    fn click_with(&mut self, x: usize, y: i64, modifiers: KeyModifiers) {
+        // Break any outstanding click streak
+        self.click_n(0, 0, MouseButton::Right, 1);
+        for (kind, button) in &[
+            (MouseEventKind::Move, MouseButton::None),
+            (MouseEventKind::Press, MouseButton::Left),
+            (MouseEventKind::Release, MouseButton::Left),
+        ] {
+            self.mouse(MouseEvent {
+                kind: *kind,
+                x,
+                y,
+                button: *button,
+                modifiers,
+            })
+            .unwrap();
+        }
+    }
+
     /// Left mouse button drag from the start to the end coordinates
     fn drag_select(&mut self, start_x: usize, start_y: i64, end_x: usize, end_y: i64) {
         // Break any outstanding click streak that might falsely trigger due to
@@ -205,7 +260,11 @@ impl TestTerm {
     }
 
     fn assert_dirty_lines(&self, expected: &[usize], reason: Option<&str>) {
-        let dirty_indices: Vec<usize> = self.get_dirty_lines().iter().map(|&(i, ..)| i).collect();
+        let dirty_indices: Vec<usize> = self
+            .get_changed_lines(self.seqno)
+            .iter()
+            .map(|&(i, ..)| i)
+            .collect();
         assert_eq!(
             &dirty_indices, &expected,
             "actual dirty lines (left) didn't match expected dirty lines (right) reason={:?}",
@@ -403,6 +462,229 @@ fn basic_output() {
     );
 }
 
+/// Records the individual writes made to it
+#[derive(Default)]
+struct WriteRecorder {
+    writes: Vec<Vec<u8>>,
+}
+
+impl std::io::Write for WriteRecorder {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, std::io::Error> {
+        self.writes.push(buf.to_vec());
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        Ok(())
+    }
+}
+
+#[test]
+fn paste_chunking() {
+    let mut term = TestTerm::new(2, 10, 0);
+
+    let text = "a".repeat(2500);
+    let mut recorder = WriteRecorder::default();
+    term.send_paste(&text, &mut recorder).unwrap();
+    let sizes: Vec<usize> = recorder.writes.iter().map(|w| w.len()).collect();
+    assert_eq!(sizes, vec![1024, 1024, 452]);
+
+    // Multi-byte characters are not split across chunks
+    let text = format!("a{}", "\u{1F480}".repeat(300));
+    let mut recorder = WriteRecorder::default();
+    term.send_paste(&text, &mut recorder).unwrap();
+    assert_eq!(recorder.writes[0].len(), 1021);
+    assert_eq!(recorder.writes.concat(), text.as_bytes());
+}
+
+/// A TerminalHost that records what the terminal writes to it
+#[derive(Default)]
+struct RecordingHost {
+    output: WriteRecorder,
+}
+
+impl TerminalHost for RecordingHost {
+    fn set_title(&mut self, _title: &str) {}
+
+    fn set_clipboard(&mut self, _clip: Option<String>) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn get_clipboard(&mut self) -> Result<String, Error> {
+        Ok(String::new())
+    }
+
+    fn writer(&mut self) -> &mut std::io::Write {
+        &mut self.output
+    }
+
+    fn click_link(&mut self, _link: &Arc<Hyperlink>) {}
+}
+
+#[test]
+fn sgr_mouse_reporting() {
+    let mut term = TestTerm::new(2, 10, 0);
+    term.print("\x1b[?1002h\x1b[?1006h");
+
+    let mut host = RecordingHost::default();
+    let mut report = |kind, button, x, y, modifiers| {
+        term.mouse_event(
+            MouseEvent {
+                kind,
+                button,
+                x,
+                y,
+                modifiers,
+            },
+            &mut host,
+        )
+        .unwrap();
+    };
+    report(
+        MouseEventKind::Press,
+        MouseButton::Left,
+        1,
+        0,
+        KeyModifiers::CTRL,
+    );
+    report(
+        MouseEventKind::Move,
+        MouseButton::None,
+        2,
+        0,
+        KeyModifiers::CTRL,
+    );
+    report(
+        MouseEventKind::Release,
+        MouseButton::Left,
+        2,
+        0,
+        KeyModifiers::NONE,
+    );
+    report(
+        MouseEventKind::Press,
+        MouseButton::WheelUp,
+        2,
+        1,
+        KeyModifiers::ALT,
+    );
+
+    assert_eq!(
+        String::from_utf8(host.output.writes.concat()).unwrap(),
+        "\x1b[<16;2;1M\x1b[<48;3;1M\x1b[<0;3;1m\x1b[<72;3;2M"
+    );
+}
+
+/// Sends the mouse `events` to a terminal of the given width that was
+/// first sent `setup`, and returns what the terminal reported
+fn mouse_reports(
+    setup: &str,
+    cols: usize,
+    events: &[(MouseEventKind, MouseButton, usize, i64)],
+) -> Vec<u8> {
+    let mut term = TestTerm::new(2, cols, 0);
+    term.print(setup);
+    let mut host = RecordingHost::default();
+    for &(kind, button, x, y) in events {
+        term.mouse_event(
+            MouseEvent {
+                kind,
+                button,
+                x,
+                y,
+                modifiers: KeyModifiers::NONE,
+            },
+            &mut host,
+        )
+        .unwrap();
+    }
+    host.output.writes.concat()
+}
+
+#[test]
+fn mouse_reporting_modes() {
+    use self::MouseButton::Left;
+    use self::MouseEventKind::{Move, Press, Release};
+    const NO_BUTTON: MouseButton = MouseButton::None;
+
+    // Buttons only, in the X10 encoding, which says that a button was
+    // released but not which one
+    assert_eq!(
+        mouse_reports(
+            "\x1b[?1000h",
+            10,
+            &[
+                (Press, Left, 1, 0),
+                (Move, NO_BUTTON, 2, 0),
+                (Release, Left, 2, 0)
+            ]
+        ),
+        b"\x1b[M \"!\x1b[M##!".to_vec()
+    );
+    // The X10 encoding can't describe columns past 223
+    assert_eq!(
+        mouse_reports("\x1b[?1000h", 300, &[(Press, Left, 250, 0)]),
+        b"".to_vec()
+    );
+    // UTF-8 can, and drags are reported
+    assert_eq!(
+        mouse_reports(
+            "\x1b[?1002h\x1b[?1005h",
+            300,
+            &[(Press, Left, 250, 0), (Move, NO_BUTTON, 251, 1)]
+        ),
+        "\x1b[M \u{11b}!\x1b[M@\u{11c}\"".as_bytes().to_vec()
+    );
+    // Any movement in the urxvt encoding, but only into another cell
+    assert_eq!(
+        mouse_reports(
+            "\x1b[?1003h\x1b[?1015h",
+            10,
+            &[
+                (Move, NO_BUTTON, 4, 1),
+                (Move, NO_BUTTON, 4, 1),
+                (Press, Left, 4, 1)
+            ]
+        ),
+        b"\x1b[67;5;2M\x1b[32;5;2M".to_vec()
+    );
+    // Turning tracking off gives the mouse back to selection
+    assert_eq!(
+        mouse_reports(
+            "\x1b[?1002h\x1b[?1006h\x1b[?1002l",
+            10,
+            &[(Press, Left, 1, 0), (Release, Left, 1, 0)]
+        ),
+        b"".to_vec()
+    );
+}
+
+#[test]
+fn focus_reporting() {
+    let mut term = TestTerm::new(2, 10, 0);
+    let mut recorder = WriteRecorder::default();
+    term.focus_changed(true, &mut recorder).unwrap();
+    term.print("\x1b[?1004h");
+    term.focus_changed(false, &mut recorder).unwrap();
+    term.focus_changed(true, &mut recorder).unwrap();
+    term.print("\x1b[?1004l");
+    term.focus_changed(false, &mut recorder).unwrap();
+    assert_eq!(recorder.writes.concat(), b"\x1b[O\x1b[I".to_vec());
+}
+
+#[test]
+fn bracketed_paste() {
+    let mut term = TestTerm::new(2, 10, 0);
+    term.print("\x1b[?2004h");
+    assert!(term.bracketed_paste_enabled());
+
+    let mut recorder = WriteRecorder::default();
+    term.send_paste("one\ntwo\x1b[201~three", &mut recorder).unwrap();
+    assert_eq!(
+        recorder.writes.concat(),
+        b"\x1b[200~one\ntwothree\x1b[201~".to_vec()
+    );
+}
+
 /// Ensure that we dirty lines as the cursor is moved around, otherwise
 /// the renderer won't draw the cursor in the right place
 #[test]
@@ -415,16 +697,46 @@ fn cursor_movement_damage() {
     term.assert_dirty_lines(&[0, 1], None);
 
     term.cup(0, 1);
-    term.clean_dirty_lines();
+    term.mark_seen();
     term.print("\x08");
     term.assert_cursor_pos(0, 1, Some("BS doesn't change the line"));
     term.assert_dirty_lines(&[1], None);
-    term.clean_dirty_lines();
+    term.mark_seen();
 
     term.cup(0, 0);
     term.assert_dirty_lines(&[0, 1], Some("cursor movement dirties old and new lines"));
 }
 
+/// Each consumer of the changes tracks its own sequence number, so
+/// catching up one of them doesn't hide the changes from another
+#[test]
+fn independent_change_tracking() {
+    let mut term = TestTerm::new(3, 3, 0);
+    term.print("a");
+    let first = term.current_seqno();
+
+    term.cup(0, 2);
+    term.print("b");
+    let second = term.current_seqno();
+    assert!(second > first);
+
+    let changed = |term: &TestTerm, seqno| -> Vec<usize> {
+        term.get_changed_lines(seqno)
+            .iter()
+            .map(|&(i, ..)| i)
+            .collect()
+    };
+    assert_eq!(changed(&term, SEQ_ZERO), vec![0, 1, 2]);
+    assert_eq!(changed(&term, first), vec![0, 2]);
+    assert_eq!(changed(&term, second), Vec::<usize>::new());
+    assert!(!term.has_changes_since(second));
+
+    term.print("c");
+    assert!(term.has_changes_since(second));
+    assert_eq!(changed(&term, second), vec![2]);
+    assert_eq!(changed(&term, first), vec![0, 2]);
+}
+
 /// Replicates a bug I initially found via:
 /// $ vim
 /// :help
@@ -437,22 +749,26 @@ fn test_delete_lines() {
     assert_visible_contents(&term, &["111", "222", "333", "444", "555"]);
     term.assert_dirty_lines(&[0, 1, 2, 3, 4], None);
     term.cup(0, 1);
-    term.clean_dirty_lines();
+    term.mark_seen();
 
     term.assert_dirty_lines(&[], None);
     term.delete_lines(2);
     assert_visible_contents(&term, &["111", "444", "555", "   ", "   "]);
     term.assert_dirty_lines(&[1, 2, 3, 4], None);
-    term.clean_dirty_lines();
+    term.mark_seen();
 
     term.cup(0, 3);
     term.print("aaa\r\nbbb");
     term.cup(0, 1);
-    term.clean_dirty_lines();
+    term.mark_seen();
     assert_visible_contents(&term, &["111", "444", "555", "aaa", "bbb"]);
 
-    // test with a scroll region smaller than the screen
+    // test with a scroll region smaller than the screen; setting it
+    // moves the cursor home
     term.set_scroll_region(1, 3);
+    term.assert_cursor_pos(0, 0, None);
+    term.cup(0, 1);
+    term.mark_seen();
     print_all_lines(&term);
     term.delete_lines(2);
 
@@ -461,7 +777,8 @@ fn test_delete_lines() {
 
     // expand the scroll region to fill the screen
     term.set_scroll_region(0, 4);
-    term.clean_dirty_lines();
+    term.cup(0, 1);
+    term.mark_seen();
     term.delete_lines(1);
 
     assert_visible_contents(&term, &["111", "   ", "   ", "bbb", "   "]);
@@ -500,7 +817,10 @@ fn test_scroll_margins() {
         bottom: OneBased::new(2),
     });
     term.print(format!("{}", margins));
+    // Setting the margins moves the cursor home
+    term.assert_cursor_pos(0, 0, None);
 
+    term.cup(0, 2);
     term.print("z\n");
     assert_all_contents(&term, &["1", "2", "3", "4", "z"]);
 
@@ -589,3 +909,324 @@ fn test_hyperlinks() {
         Compare::TEXT | Compare::ATTRS,
     );
 }
+
+#[test]
+fn test_hyperlink_near_cursor() {
+    let mut term = TestTerm::new(3, 10, 0);
+    let one = Arc::new(Hyperlink::new("http://example.com/one"));
+    let two = Arc::new(Hyperlink::new("http://example.com/two"));
+
+    term.hyperlink(&one);
+    term.print("one");
+    term.hyperlink_off();
+    term.print(" ");
+    term.hyperlink(&two);
+    term.print("two");
+    term.hyperlink_off();
+    term.print("\r\n");
+
+    // The closest link preceding the cursor
+    assert_eq!(term.hyperlink_near_cursor(), Some(Arc::clone(&two)));
+
+    // A link under the cursor counts as preceding it
+    term.cup(1, 0);
+    assert_eq!(term.hyperlink_near_cursor(), Some(Arc::clone(&one)));
+
+    // Nothing precedes the cursor, so take the last link on screen
+    term.cup(0, 0);
+    term.erase_in_line(EraseInLine::EraseToStartOfLine);
+    assert_eq!(term.hyperlink_near_cursor(), Some(Arc::clone(&two)));
+}
+
+#[test]
+fn test_implicit_hyperlink_wrapped() {
+    let mut term = TestTerm::new(3, 10, 0);
+    term.print("go http://example.com/x now");
+    assert_eq!(term.hyperlink_near_cursor(), None);
+
+    let rule = termwiz::hyperlink::Rule::new(r"\b\w+://\S+", "$0").unwrap();
+    term.set_hyperlink_rules(vec![rule]);
+
+    // The url wraps across all three rows but is matched as one link
+    let link = term.hyperlink_near_cursor().unwrap();
+    assert_eq!(link.uri(), "http://example.com/x");
+    let link_at = |row: usize, col: usize| {
+        term.screen().lines[row].cells()[col]
+            .attrs()
+            .hyperlink
+            .clone()
+    };
+    assert_eq!(link_at(0, 2), None);
+    assert_eq!(link_at(0, 3), Some(Arc::clone(&link)));
+    assert_eq!(link_at(1, 0), Some(Arc::clone(&link)));
+    assert_eq!(link_at(2, 2), Some(Arc::clone(&link)));
+    assert_eq!(link_at(2, 3), None);
+
+    // Replacing the rules discards the links found by the old ones
+    term.set_hyperlink_rules(vec![]);
+    assert_eq!(term.hyperlink_near_cursor(), None);
+}
+
+#[test]
+fn test_implicit_hyperlink_needs_modifiers() {
+    let mut term = TestTerm::new(2, 20, 0);
+    let explicit = Arc::new(Hyperlink::new("http://example.com/explicit"));
+    term.hyperlink(&explicit);
+    term.print("explicit");
+    term.hyperlink_off();
+    term.print("\r\nhttp://implicit/");
+    let rule = termwiz::hyperlink::Rule::new(r"\b\w+://\S+", "$0").unwrap();
+    term.set_hyperlink_rules(vec![rule]);
+
+    // A plain click opens an explicit link, but not an implicit one
+    term.click_with(2, 0, KeyModifiers::NONE);
+    assert_eq!(term.host.clicked, vec![Arc::clone(&explicit)]);
+    term.click_with(2, 1, KeyModifiers::NONE);
+    assert_eq!(term.host.clicked.len(), 1);
+
+    term.click_with(2, 1, KeyModifiers::CTRL);
+    assert_eq!(term.host.clicked.len(), 2);
+    assert_eq!(term.host.clicked[1].uri(), "http://implicit/");
+
+    term.set_implicit_link_click_modifiers(KeyModifiers::NONE);
+    term.click_with(2, 1, KeyModifiers::NONE);
+    assert_eq!(term.host.clicked.len(), 3);
+}
+
+#[test]
+fn test_scroll_to_prompt() {
+    let mut term = TestTerm::new(2, 3, 10);
+    term.print("\x1b]133;A\x07$1\r\nx\r\ny\r\n");
+    term.print("\x1b]133;A\x07$2\r\nz\r\n");
+    term.print("\x1b]133;A\x07$3");
+    term.assert_viewport_contents(&["z  ", "$3 "]);
+
+    term.scroll_to_prompt(-1);
+    term.assert_viewport_contents(&["$2 ", "z  "]);
+    term.scroll_to_prompt(-1);
+    term.assert_viewport_contents(&["$1 ", "x  "]);
+
+    // There are no earlier prompts to move to
+    term.scroll_to_prompt(-1);
+    assert_eq!(term.get_viewport_offset(), 4);
+
+    term.scroll_to_prompt(1);
+    term.assert_viewport_contents(&["$2 ", "z  "]);
+
+    // Moving past the last prompt returns to the bottom
+    term.scroll_to_prompt(2);
+    assert_eq!(term.get_viewport_offset(), 0);
+}
+
+#[test]
+fn test_current_dir() {
+    let mut term = TestTerm::new(2, 2, 0);
+    assert_eq!(term.get_current_dir(), None);
+    term.print("\x1b]7;file://host/home/me/my%20dir\x07");
+    assert_eq!(term.get_current_dir(), Some("/home/me/my dir"));
+    term.print("\x1b]1337;CurrentDir=/tmp\x07");
+    assert_eq!(term.get_current_dir(), Some("/tmp"));
+    assert_eq!(term.host.dirs, vec!["/home/me/my dir", "/tmp"]);
+}
+
+#[test]
+fn test_bell() {
+    let mut term = TestTerm::new(2, 2, 0);
+    term.print("a\x07b\x07");
+    assert_eq!(term.host.bells, 2);
+    // The bell that terminates an OSC doesn't ring
+    term.print("\x1b]2;title\x07");
+    assert_eq!(term.host.bells, 2);
+}
+
+#[test]
+fn test_set_scrollback_size() {
+    let mut term = TestTerm::new(2, 2, 4);
+    for i in 0..6 {
+        term.print(format!("{}\r\n", i));
+    }
+    assert_eq!(term.screen().lines.len(), 6);
+    term.scroll_viewport(-4);
+    term.assert_viewport_contents(&["1 ", "2 "]);
+
+    // The oldest lines are discarded and the viewport is kept
+    // within the remaining scrollback
+    term.set_scrollback_size(1);
+    assert_eq!(term.screen().lines.len(), 3);
+    assert_eq!(term.get_viewport_offset(), 1);
+    term.assert_viewport_contents(&["4 ", "5 "]);
+
+    term.set_scrollback_size(10);
+    assert_eq!(term.screen().lines.len(), 3);
+}
+
+#[test]
+fn test_compressed_scrollback() {
+    let mut term = TestTerm::new(2, 4, 5000);
+    term.set_uncompressed_scrollback(Some(100));
+    for i in 0..3000 {
+        term.print(format!("{}\r\n", i));
+    }
+    let cold = term.screen().cold_lines();
+    assert!(cold > 0);
+    assert_eq!(cold + term.screen().lines.len(), 3001);
+
+    // Scrolling back to the compressed lines brings them back
+    term.scroll_viewport(-3000);
+    assert_eq!(term.screen().cold_lines(), 0);
+    assert_eq!(term.screen().lines.len(), 3001);
+    term.assert_viewport_contents(&["0   ", "1   "]);
+
+    // Once back at the bottom, the output that follows compresses
+    // them again, and the oldest are dropped from the compressed
+    // lines when the scrollback shrinks
+    term.scroll_viewport(3000);
+    term.print("3000\r\n");
+    assert!(term.screen().cold_lines() > 0);
+    term.set_scrollback_size(2000);
+    assert_eq!(term.screen().cold_lines() + term.screen().lines.len(), 2002);
+    term.scroll_viewport(-3000);
+    term.assert_viewport_contents(&["1000", "1001"]);
+}
+
+/// Reading lines out of view brings back those that were compressed
+#[test]
+fn test_viewport_lines_from_compressed_scrollback() {
+    let mut term = TestTerm::new(2, 4, 5000);
+    term.set_uncompressed_scrollback(Some(100));
+    for i in 0..3000 {
+        term.print(format!("{}\r\n", i));
+    }
+    let total = term.screen().cold_lines() + term.screen().lines.len();
+    assert!(term.screen().cold_lines() > 0);
+
+    let lines: Vec<String> = term
+        .get_viewport_lines(2999, 2)
+        .iter()
+        .map(|line| line.as_str().trim_end().to_owned())
+        .collect();
+    assert_eq!(lines, vec!["0", "1"]);
+    assert_eq!(term.screen().cold_lines(), 0);
+    assert_eq!(term.screen().lines.len(), total);
+    // The viewport stays put
+    assert_eq!(term.get_viewport_offset(), 0);
+}
+
+#[test]
+fn test_discarded_rows() {
+    let mut term = TestTerm::new(2, 4, 3);
+    term.print("0\r\n1\r\n2\r\n3");
+    assert_eq!(term.screen().discarded_rows(), 0);
+    term.print("\r\n4\r\n5");
+    assert_eq!(term.screen().discarded_rows(), 1);
+    // The line numbered by the count stays the top one
+    let lines = term.get_viewport_lines(3, 1);
+    assert_eq!(lines[0].as_str().trim_end(), "1");
+
+    term.set_scrollback_size(1);
+    assert_eq!(term.screen().discarded_rows(), 3);
+}
+
+#[test]
+fn test_osc52_clipboard() {
+    let mut term = TestTerm::new(2, 10, 0);
+    term.print("\x1b]52;c;aGVsbG8=\x1b\\");
+    assert_eq!(term.get_clipboard(), Some(&"hello".to_string()));
+
+    term.print("\x1b]52;c;?\x1b\\");
+    assert_eq!(
+        String::from_utf8(term.host.written.clone()).unwrap(),
+        "\x1b]52;c;aGVsbG8=\x07"
+    );
+
+    term.print("\x1b]52;c\x1b\\");
+    assert_eq!(term.get_clipboard(), None);
+}
+
+/// Formats an iTerm2 inline image sequence for a blank PNG image
+fn iterm_image(width: u32, height: u32, cell_width: ITermDimension) -> String {
+    let mut png = vec![];
+    image::DynamicImage::new_rgb8(width, height)
+        .write_to(&mut png, image::ImageOutputFormat::PNG)
+        .unwrap();
+    let osc =
+        OperatingSystemCommand::ITermProprietary(ITermProprietary::File(Box::new(ITermFileData {
+            name: None,
+            size: Some(png.len()),
+            width: cell_width,
+            height: ITermDimension::Automatic,
+            preserve_aspect_ratio: true,
+            inline: true,
+            data: png,
+        })));
+    format!("{}", osc)
+}
+
+#[test]
+fn test_iterm_inline_image() {
+    let mut term = TestTerm::new(4, 10, 0);
+
+    // Without pixel dimensions a cell is assumed to be 8x16 pixels,
+    // so a 20x20 image spans 3x2 cells
+    term.print(iterm_image(20, 20, ITermDimension::Automatic));
+    term.assert_cursor_pos(0, 2, Some("cursor moves below the image"));
+
+    let image_at = |term: &TestTerm, row: usize, col: usize| {
+        term.screen().lines[row].cells()[col].attrs().image.clone()
+    };
+    for row in 0..2 {
+        for col in 0..3 {
+            assert!(image_at(&term, row, col).is_some(), "{},{}", row, col);
+        }
+        assert!(image_at(&term, row, 3).is_none());
+    }
+    let cell = image_at(&term, 1, 2).unwrap();
+    assert_eq!(cell.top_left(), TextureCoordinate::new_f32(2.0 / 3.0, 0.5));
+    assert_eq!(cell.bottom_right(), TextureCoordinate::new_f32(1.0, 1.0));
+    assert_eq!(
+        cell.image_data().id(),
+        image_at(&term, 0, 0).unwrap().image_data().id()
+    );
+
+    // A requested width in cells scales the height to match
+    term.print(iterm_image(20, 20, ITermDimension::Cells(2)));
+    term.assert_cursor_pos(0, 3, None);
+    assert!(image_at(&term, 2, 1).is_some());
+    assert!(image_at(&term, 2, 2).is_none());
+
+    // An image requested larger than the screen is shrunk to fit it,
+    // here to 64x64 pixels, which is 8x4 cells
+    term.print(iterm_image(20, 20, ITermDimension::Cells(1000)));
+    term.assert_cursor_pos(0, 3, None);
+    assert!(image_at(&term, 0, 7).is_some());
+    assert!(image_at(&term, 0, 8).is_none());
+}
+
+#[test]
+fn test_ansi_art_mode() {
+    let mut term = TestTerm::new(2, 10, 0);
+    term.print(b"\xe2\x96\x88\x1b[?8437h\xdb\xb0\x1b[5;41mx\x1b[?8437l\xe2\x96\x88");
+    assert_visible_contents(&term, &["██░x█", ""]);
+
+    let cell = &term.screen().lines[0].cells()[3];
+    assert_eq!(cell.attrs().blink(), Blink::None);
+    assert_eq!(cell.attrs().background, ColorAttribute::PaletteIndex(9));
+
+    // Outside of the mode blinking text keeps its background
+    term.print("y");
+    let cell = &term.screen().lines[0].cells()[5];
+    assert_eq!(cell.attrs().blink(), Blink::Slow);
+    assert_eq!(cell.attrs().background, ColorAttribute::PaletteIndex(1));
+}
+
+#[test]
+fn test_dumb_mode() {
+    let mut term = TestTerm::new(2, 10, 0);
+    term.set_dumb_mode(true);
+    term.print("\x1b[31;1mred\x1b]2;title\x07\x1b[?1049h\x1b[2Gx");
+    assert_visible_contents(&term, &["rxd", ""]);
+    assert_eq!(term.host.title, "");
+
+    let cell = &term.screen().lines[0].cells()[0];
+    assert_eq!(cell.attrs(), &CellAttributes::default());
+}
@@ -61,7 +61,21 @@ fn triple_click_selection() {
     assert_visible_contents(&term, &["hello worl", "d         ", "          "]);
     term.click_n(1, 0, MouseButton::Left, 3);
 
-    assert_eq!(term.get_clipboard().unwrap(), "hello worl");
+    assert_eq!(term.get_clipboard().unwrap(), "hello world");
+}
+
+/// Test triple click on a continuation line selects the whole logical line
+#[test]
+fn triple_click_wrapped_selection() {
+    let mut term = TestTerm::new(4, 10, 0);
+    term.print("hello world\r\nnext");
+    assert_visible_contents(
+        &term,
+        &["hello worl", "d         ", "next      ", "          "],
+    );
+    term.click_n(0, 1, MouseButton::Left, 3);
+
+    assert_eq!(term.get_clipboard().unwrap(), "hello world");
 }
 
 /// Test double click on wrapped line selects across the line boundary
@@ -73,6 +87,45 @@ fn double_click_wrapped_selection() {
     term.click_n(7, 0, MouseButton::Left, 2);
 
     assert_eq!(term.get_clipboard().unwrap(), "world");
+
+    // Clear the click streak
+    term.click_n(0, 2, MouseButton::Right, 1);
+
+    // Clicking the continuation finds the start of the word on the prior line
+    term.click_n(0, 1, MouseButton::Left, 2);
+    assert_eq!(term.get_clipboard().unwrap(), "world");
+}
+
+/// Test that the word boundary characters can be changed
+#[test]
+fn double_click_word_boundary() {
+    let mut term = TestTerm::new(3, 20, 0);
+    term.print("foo/bar.baz qux");
+
+    term.click_n(5, 0, MouseButton::Left, 2);
+    assert_eq!(term.get_clipboard().unwrap(), "foo/bar.baz");
+
+    term.click_n(0, 1, MouseButton::Right, 1);
+
+    term.set_word_boundary(" /.");
+    term.click_n(5, 0, MouseButton::Left, 2);
+    assert_eq!(term.get_clipboard().unwrap(), "bar");
+}
+
+/// Test that non-ASCII letters are part of words while non-ASCII
+/// punctuation separates them
+#[test]
+fn double_click_non_ascii() {
+    let mut term = TestTerm::new(3, 20, 0);
+    term.print("caf\u{e9}\u{2014}au\u{ab}lait\u{bb}");
+
+    term.click_n(1, 0, MouseButton::Left, 2);
+    assert_eq!(term.get_clipboard().unwrap(), "caf\u{e9}");
+
+    term.click_n(0, 1, MouseButton::Right, 1);
+
+    term.click_n(8, 0, MouseButton::Left, 2);
+    assert_eq!(term.get_clipboard().unwrap(), "lait");
 }
 
 /// Make sure that we adjust for the viewport offset when scrolling
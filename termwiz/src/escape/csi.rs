@@ -413,6 +413,21 @@ pub enum Mode {
     RestoreDecPrivateMode(DecPrivateMode),
     SetMode(TerminalMode),
     ResetMode(TerminalMode),
+    /// XTMODKEYS: sets how keys combined with modifiers are reported.
+    /// A value of None resets the resource to its initial value.
+    XtermKeyMode {
+        resource: XtermKeyModifierResource,
+        value: Option<i64>,
+    },
+}
+
+/// The resources whose modifier reporting can be changed by XTMODKEYS
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive, ToPrimitive)]
+pub enum XtermKeyModifierResource {
+    Keyboard = 0,
+    CursorKeys = 1,
+    FunctionKeys = 2,
+    OtherKeys = 4,
 }
 
 impl Display for Mode {
@@ -442,6 +457,13 @@ impl Display for Mode {
             Mode::RestoreDecPrivateMode(mode) => emit!("r", mode),
             Mode::SetMode(mode) => emit_mode!("h", mode),
             Mode::ResetMode(mode) => emit_mode!("l", mode),
+            Mode::XtermKeyMode { resource, value } => {
+                write!(f, ">{}", *resource as u8)?;
+                if let Some(value) = value {
+                    write!(f, ";{}", value)?;
+                }
+                write!(f, "m")
+            }
         }
     }
 }
@@ -455,6 +477,9 @@ pub enum DecPrivateMode {
 #[derive(Debug, Clone, PartialEq, Eq, FromPrimitive, ToPrimitive)]
 pub enum DecPrivateModeCode {
     ApplicationCursorKeys = 1,
+    /// DECOM: cursor positions are relative to the top margin and
+    /// the cursor can't leave the scroll region
+    OriginMode = 6,
     StartBlinkingCursor = 12,
     ShowCursor = 25,
     /// Enable mouse button press/release reporting
@@ -466,13 +491,34 @@ pub enum DecPrivateModeCode {
     ButtonEventMouse = 1002,
     /// Enable mouse motion, button press/release and drag reporting
     AnyEventMouse = 1003,
+    /// Report gaining and losing the keyboard focus as `CSI I` and
+    /// `CSI O` respectively
+    FocusTracking = 1004,
+    /// Encode the coordinates of mouse reports as UTF-8 so that they
+    /// can exceed 223.  Like SGRMouse, this doesn't enable mouse
+    /// reporting itself.
+    Utf8Mouse = 1005,
     /// Use extended coordinate system in mouse reporting.  Does not
     /// enable mouse reporting itself, it just controls how reports
     /// will be encoded.
     SGRMouse = 1006,
+    /// Encode mouse reports as decimal parameters in the manner of
+    /// urxvt.  Like SGRMouse, this doesn't enable mouse reporting
+    /// itself.
+    UrxvtMouse = 1015,
     ClearAndEnableAlternateScreen = 1049,
     EnableAlternateScreen = 47,
+    /// Like EnableAlternateScreen, but the alternate screen is cleared
+    /// when switching back to the primary screen
+    OptEnableAlternateScreen = 1047,
+    /// Save the cursor as DECSC does when set, and restore it as DECRC
+    /// does when reset
+    SaveCursor = 1048,
     BracketedPaste = 2004,
+    /// A wezterm extension for displaying ANSI art: decode the
+    /// output as CP437 and show blinking text with a bright
+    /// background (iCE colors)
+    AnsiArt = 8437,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -970,6 +1016,7 @@ pub enum Sgr {
     Inverse(bool),
     Invisible(bool),
     StrikeThrough(bool),
+    Overline(bool),
     Font(Font),
     Foreground(ColorSpec),
     Background(ColorSpec),
@@ -1014,6 +1061,8 @@ impl Display for Sgr {
             Sgr::Invisible(false) => code!(InvisibleOff),
             Sgr::StrikeThrough(true) => code!(StrikeThroughOn),
             Sgr::StrikeThrough(false) => code!(StrikeThroughOff),
+            Sgr::Overline(true) => code!(OverlineOn),
+            Sgr::Overline(false) => code!(OverlineOff),
             Sgr::Font(Font::Default) => code!(DefaultFont),
             Sgr::Font(Font::Alternate(1)) => code!(AltFont1),
             Sgr::Font(Font::Alternate(2)) => code!(AltFont2),
@@ -1282,6 +1331,7 @@ impl<'a> CSIParser<'a> {
                 .map(|mode| CSI::Mode(Mode::SaveDecPrivateMode(mode))),
 
             ('m', &[b'<']) | ('M', &[b'<']) => self.mouse_sgr1006(params).map(CSI::Mouse),
+            ('m', &[b'>']) => self.xterm_key_modifier(params).map(CSI::Mode),
 
             ('c', &[]) => self
                 .req_primary_device_attributes(params)
@@ -1309,6 +1359,21 @@ impl<'a> CSIParser<'a> {
         result
     }
 
+    fn xterm_key_modifier(&mut self, params: &'a [i64]) -> Result<Mode, ()> {
+        if params.is_empty() || params.len() > 2 {
+            return Err(());
+        }
+        let resource = num::FromPrimitive::from_i64(params[0]).ok_or(())?;
+        Ok(self.advance_by(
+            params.len(),
+            params,
+            Mode::XtermKeyMode {
+                resource,
+                value: params.get(1).cloned(),
+            },
+        ))
+    }
+
     fn cursor_style(&mut self, params: &'a [i64]) -> Result<CSI, ()> {
         if params.len() != 1 {
             Err(())
@@ -1338,13 +1403,28 @@ impl<'a> CSIParser<'a> {
                 top: OneBased::new(1),
                 bottom: OneBased::new(u32::max_value()),
             }))
+        } else if params.len() == 1 {
+            Ok(self.advance_by(
+                1,
+                params,
+                CSI::Cursor(Cursor::SetTopAndBottomMargins {
+                    top: OneBased::from_esc_param(params[0])?,
+                    bottom: OneBased::new(u32::max_value()),
+                }),
+            ))
         } else if params.len() == 2 {
+            // A bottom of 0 is the default, which is the last line
+            let bottom = if params[1] == 0 {
+                OneBased::new(u32::max_value())
+            } else {
+                OneBased::from_esc_param(params[1])?
+            };
             Ok(self.advance_by(
                 2,
                 params,
                 CSI::Cursor(Cursor::SetTopAndBottomMargins {
                     top: OneBased::from_esc_param(params[0])?,
-                    bottom: OneBased::from_esc_param(params[1])?,
+                    bottom,
                 }),
             ))
         } else {
@@ -1657,6 +1737,8 @@ impl<'a> CSIParser<'a> {
                     SgrCode::InvisibleOff => one!(Sgr::Invisible(false)),
                     SgrCode::StrikeThroughOn => one!(Sgr::StrikeThrough(true)),
                     SgrCode::StrikeThroughOff => one!(Sgr::StrikeThrough(false)),
+                    SgrCode::OverlineOn => one!(Sgr::Overline(true)),
+                    SgrCode::OverlineOff => one!(Sgr::Overline(false)),
                     SgrCode::DefaultFont => one!(Sgr::Font(Font::Default)),
                     SgrCode::AltFont1 => one!(Sgr::Font(Font::Alternate(1))),
                     SgrCode::AltFont2 => one!(Sgr::Font(Font::Alternate(2))),
@@ -1724,6 +1806,8 @@ pub enum SgrCode {
     BackgroundCyan = 46,
     BackgroundWhite = 47,
     BackgroundDefault = 49,
+    OverlineOn = 53,
+    OverlineOff = 55,
 
     ForegroundBrightBlack = 90,
     ForegroundBrightRed = 91,
@@ -1796,6 +1880,35 @@ mod test {
         String::from_utf8(res).unwrap()
     }
 
+    #[test]
+    fn decstbm() {
+        assert_eq!(
+            parse('r', &[2, 5], "\x1b[2;5r"),
+            vec![CSI::Cursor(Cursor::SetTopAndBottomMargins {
+                top: OneBased::new(2),
+                bottom: OneBased::new(5),
+            })]
+        );
+        // The bottom margin defaults to the last line
+        assert_eq!(
+            parse('r', &[2], "\x1b[2;4294967295r"),
+            parse('r', &[2, 0], "\x1b[2;4294967295r")
+        );
+        assert_eq!(parse('r', &[0, 0], "\x1b[r"), parse('r', &[], "\x1b[r"));
+    }
+
+    #[test]
+    fn overline() {
+        assert_eq!(
+            parse('m', &[53], "\x1b[53m"),
+            vec![CSI::Sgr(Sgr::Overline(true))]
+        );
+        assert_eq!(
+            parse('m', &[55], "\x1b[55m"),
+            vec![CSI::Sgr(Sgr::Overline(false))]
+        );
+    }
+
     #[test]
     fn basic() {
         assert_eq!(parse('m', &[], "\x1b[0m"), vec![CSI::Sgr(Sgr::Reset)]);
@@ -1965,6 +2078,24 @@ mod test {
         );
     }
 
+    #[test]
+    fn xterm_key_modifiers() {
+        assert_eq!(
+            parse_int('m', &[4, 2], b'>', "\x1b[>4;2m"),
+            vec![CSI::Mode(Mode::XtermKeyMode {
+                resource: XtermKeyModifierResource::OtherKeys,
+                value: Some(2),
+            })]
+        );
+        assert_eq!(
+            parse_int('m', &[4], b'>', "\x1b[>4m"),
+            vec![CSI::Mode(Mode::XtermKeyMode {
+                resource: XtermKeyModifierResource::OtherKeys,
+                value: None,
+            })]
+        );
+    }
+
     #[test]
     fn mouse() {
         assert_eq!(
@@ -30,6 +30,8 @@ pub enum OperatingSystemCommand {
     SetIconNameAndWindowTitle(String),
     SetWindowTitle(String),
     SetIconName(String),
+    /// The working directory of the program, as a `file://host/path` URL
+    SetCurrentWorkingDirectory(String),
     SetHyperlink(Option<Hyperlink>),
     ClearSelection(Selection),
     QuerySelection(Selection),
@@ -38,6 +40,7 @@ pub enum OperatingSystemCommand {
     ITermProprietary(ITermProprietary),
     ChangeColorNumber(Vec<ChangeColorPair>),
     ChangeDynamicColors(DynamicColorNumber, Vec<ColorOrQuery>),
+    FinalTermSemanticPrompt(FinalTermSemanticPrompt),
 
     Unspecified(Vec<Vec<u8>>),
 }
@@ -57,6 +60,53 @@ pub enum DynamicColorNumber {
     HighlightForegroundColor = 19,
 }
 
+/// The shell integration markers from FinalTerm, which are also used
+/// by iTerm2, that delimit the prompt, the command and its output.
+/// See https://iterm2.com/documentation-escape-codes.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FinalTermSemanticPrompt {
+    /// `A`: the prompt is about to be shown
+    PromptStart,
+    /// `B`: the prompt has been shown and the command is being typed
+    CommandStart,
+    /// `C`: the command was entered and its output follows
+    CommandExecuted,
+    /// `D`: the command finished, with its exit status if known
+    CommandFinished(Option<i64>),
+}
+
+impl FinalTermSemanticPrompt {
+    fn parse(osc: &[&[u8]]) -> Fallible<Self> {
+        ensure!(osc.len() > 1, "not enough args");
+        match osc[1] {
+            b"A" => Ok(FinalTermSemanticPrompt::PromptStart),
+            b"B" => Ok(FinalTermSemanticPrompt::CommandStart),
+            b"C" => Ok(FinalTermSemanticPrompt::CommandExecuted),
+            b"D" => {
+                let status = match osc.get(2) {
+                    Some(status) => Some(str::from_utf8(status)?.parse()?),
+                    None => None,
+                };
+                Ok(FinalTermSemanticPrompt::CommandFinished(status))
+            }
+            _ => bail!("unknown FinalTermSemanticPrompt {:?}", osc),
+        }
+    }
+}
+
+impl Display for FinalTermSemanticPrompt {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        write!(f, "133;")?;
+        match self {
+            FinalTermSemanticPrompt::PromptStart => write!(f, "A"),
+            FinalTermSemanticPrompt::CommandStart => write!(f, "B"),
+            FinalTermSemanticPrompt::CommandExecuted => write!(f, "C"),
+            FinalTermSemanticPrompt::CommandFinished(None) => write!(f, "D"),
+            FinalTermSemanticPrompt::CommandFinished(Some(status)) => write!(f, "D;{}", status),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ChangeColorPair {
     pub palette_index: u8,
@@ -235,6 +285,7 @@ impl OperatingSystemCommand {
             SetIconNameAndWindowTitle => single_string!(SetIconNameAndWindowTitle),
             SetWindowTitle => single_string!(SetWindowTitle),
             SetIconName => single_string!(SetIconName),
+            SetCurrentWorkingDirectory => single_string!(SetCurrentWorkingDirectory),
             SetHyperlink => Ok(OperatingSystemCommand::SetHyperlink(Hyperlink::parse(osc)?)),
             ManipulateSelectionData => Self::parse_selection(osc),
             SystemNotification => single_string!(SystemNotification),
@@ -242,6 +293,8 @@ impl OperatingSystemCommand {
                 self::ITermProprietary::parse(osc).map(OperatingSystemCommand::ITermProprietary)
             }
             ChangeColorNumber => Self::parse_change_color_number(osc),
+            FinalTermSemanticPrompt => self::FinalTermSemanticPrompt::parse(osc)
+                .map(OperatingSystemCommand::FinalTermSemanticPrompt),
 
             SetTextForegroundColor
             | SetTextBackgroundColor
@@ -289,6 +342,8 @@ pub enum OperatingSystemCommandCode {
     SetFont = 50,
     EmacsShell = 51,
     ManipulateSelectionData = 52,
+    /// The shell integration markers from FinalTerm
+    FinalTermSemanticPrompt = 133,
     RxvtProprietary = 777,
     ITermProprietary = 1337,
 }
@@ -308,6 +363,7 @@ impl Display for OperatingSystemCommand {
             SetIconNameAndWindowTitle(title) => single_string!(SetIconNameAndWindowTitle, title),
             SetWindowTitle(title) => single_string!(SetWindowTitle, title),
             SetIconName(title) => single_string!(SetIconName, title),
+            SetCurrentWorkingDirectory(url) => single_string!(SetCurrentWorkingDirectory, url),
             SetHyperlink(Some(link)) => link.fmt(f)?,
             SetHyperlink(None) => write!(f, "8;;")?,
             Unspecified(v) => {
@@ -323,6 +379,7 @@ impl Display for OperatingSystemCommand {
             SetSelection(s, val) => write!(f, "52;{};{}", s, base64::encode(val))?,
             SystemNotification(s) => write!(f, "9;{}", s)?,
             ITermProprietary(i) => i.fmt(f)?,
+            FinalTermSemanticPrompt(i) => i.fmt(f)?,
             ChangeColorNumber(specs) => {
                 write!(f, "4;")?;
                 for pair in specs {
@@ -567,7 +624,7 @@ impl ITermDimension {
     pub fn to_pixels(&self, cell_size: usize, num_cells: usize) -> Option<usize> {
         match self {
             ITermDimension::Automatic => None,
-            ITermDimension::Cells(n) => Some((*n).max(0) as usize * cell_size),
+            ITermDimension::Cells(n) => Some(((*n).max(0) as usize).saturating_mul(cell_size)),
             ITermDimension::Pixels(n) => Some((*n).max(0) as usize),
             ITermDimension::Percent(n) => Some(
                 (((*n).max(0).min(100) as f32 / 100.0) * num_cells as f32 * cell_size as f32)
@@ -784,6 +841,32 @@ mod test {
         );
     }
 
+    #[test]
+    fn current_dir() {
+        assert_eq!(
+            parse(&["7", "file://host/tmp"], "\x1b]7;file://host/tmp\x07"),
+            OperatingSystemCommand::SetCurrentWorkingDirectory("file://host/tmp".into())
+        );
+    }
+
+    #[test]
+    fn semantic_prompt() {
+        assert_eq!(
+            parse(&["133", "A"], "\x1b]133;A\x07"),
+            OperatingSystemCommand::FinalTermSemanticPrompt(FinalTermSemanticPrompt::PromptStart)
+        );
+        assert_eq!(
+            parse(&["133", "D", "1"], "\x1b]133;D;1\x07"),
+            OperatingSystemCommand::FinalTermSemanticPrompt(
+                FinalTermSemanticPrompt::CommandFinished(Some(1))
+            )
+        );
+        assert_eq!(
+            parse(&["133", "Z"], "\x1b]133;Z\x07"),
+            OperatingSystemCommand::Unspecified(vec![b"133".to_vec(), b"Z".to_vec()])
+        );
+    }
+
     #[test]
     fn iterm() {
         assert_eq!(
@@ -6,7 +6,7 @@
 //! as we recognize linkable input text during print() processing.
 use failure::{ensure, err_msg, Error};
 use regex::{Captures, Regex};
-use serde::{self, Deserialize, Deserializer};
+use serde::{self, Deserialize, Deserializer, Serialize, Serializer};
 use serde_derive::*;
 use std::collections::HashMap;
 use std::fmt::{Display, Error as FmtError, Formatter};
@@ -122,12 +122,15 @@ impl Display for Hyperlink {
 /// URL to view the details for that issue.
 /// The Rule struct is configuration that is passed to the terminal
 /// and is evaluated when processing mouse hover events.
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Rule {
     /// The compiled regex for the rule.  This is used to match
     /// against a line of text from the screen (typically the line
     /// over which the mouse is hovering).
-    #[serde(deserialize_with = "deserialize_regex")]
+    #[serde(
+        deserialize_with = "deserialize_regex",
+        serialize_with = "serialize_regex"
+    )]
     regex: Regex,
     /// The format string that defines how to transform the matched
     /// text into a URL.  For example, a format string of `$0` expands
@@ -150,6 +153,13 @@ where
     Regex::new(&s).map_err(|e| serde::de::Error::custom(format!("{:?}", e)))
 }
 
+fn serialize_regex<S>(regex: &Regex, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    regex.as_str().serialize(serializer)
+}
+
 /// Holds a resolved rule match.
 #[derive(Debug, PartialEq)]
 pub struct RuleMatch {
@@ -264,4 +274,29 @@ mod test {
             ]
         );
     }
+
+    #[test]
+    fn parse_file_line() {
+        let rules =
+            vec![Rule::new(r"((?:/|\b)(?:[\w.-]+/)+[\w.-]+\.\w+):(\d+)\b", "file:$1#$2").unwrap()];
+
+        assert_eq!(
+            Rule::match_hyperlinks("  --> src/main.rs:42:5", &rules),
+            vec![RuleMatch {
+                range: 6..20,
+                link: Arc::new(Hyperlink::new_implicit("file:src/main.rs#42")),
+            }]
+        );
+
+        assert_eq!(
+            Rule::match_hyperlinks("/tmp/foo.c:7: error", &rules),
+            vec![RuleMatch {
+                range: 0..12,
+                link: Arc::new(Hyperlink::new_implicit("file:/tmp/foo.c#7")),
+            }]
+        );
+
+        // A bare file name is too easily confused with host:port
+        assert_eq!(Rule::match_hyperlinks("example.com:8080", &rules), vec![]);
+    }
 }
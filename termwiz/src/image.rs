@@ -87,6 +87,18 @@ impl ImageCell {
             data,
         }
     }
+
+    pub fn top_left(&self) -> TextureCoordinate {
+        self.top_left.clone()
+    }
+
+    pub fn bottom_right(&self) -> TextureCoordinate {
+        self.bottom_right.clone()
+    }
+
+    pub fn image_data(&self) -> &Arc<ImageData> {
+        &self.data
+    }
 }
 
 static IMAGE_ID: ::std::sync::atomic::AtomicUsize = ::std::sync::atomic::AtomicUsize::new(0);
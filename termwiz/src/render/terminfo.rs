@@ -123,6 +123,10 @@ impl TerminfoRenderer {
                 if attr.strikethrough() {
                     attr_on!(Sgr::StrikeThrough(true));
                 }
+
+                if attr.overline() {
+                    attr_on!(Sgr::Overline(true));
+                }
             }
 
             let has_true_color = self.caps.color_level() == ColorLevel::TrueColor;
@@ -383,6 +387,9 @@ impl TerminfoRenderer {
                 Change::Attribute(AttributeChange::StrikeThrough(value)) => {
                     record!(set_strikethrough, value);
                 }
+                Change::Attribute(AttributeChange::Overline(value)) => {
+                    record!(set_overline, value);
+                }
                 Change::Attribute(AttributeChange::Blink(value)) => {
                     record!(set_blink, value);
                 }
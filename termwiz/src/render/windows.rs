@@ -239,6 +239,9 @@ impl WindowsConsoleRenderer {
                 Change::Attribute(AttributeChange::StrikeThrough(value)) => {
                     self.current_attr.set_strikethrough(*value);
                 }
+                Change::Attribute(AttributeChange::Overline(value)) => {
+                    self.current_attr.set_overline(*value);
+                }
                 Change::Attribute(AttributeChange::Blink(value)) => {
                     self.current_attr.set_blink(*value);
                 }
@@ -1,7 +1,7 @@
 use crate::cell::{Cell, CellAttributes};
 use crate::cellcluster::CellCluster;
-use crate::hyperlink::Rule;
-use crate::surface::Change;
+use crate::hyperlink::{Hyperlink, Rule};
+use crate::surface::{Change, SequenceNo, SEQ_ZERO};
 use bitflags::bitflags;
 use serde_derive::*;
 use std::ops::Range;
@@ -21,6 +21,9 @@ bitflags! {
         const SCANNED_IMPLICIT_HYPERLINKS = 1<<2;
         /// true if we found implicit hyperlinks in the last scan
         const HAS_IMPLICIT_HYPERLINKS = 1<<3;
+        /// The line is the start of a shell prompt, as marked by the
+        /// FinalTerm semantic prompt sequence
+        const PROMPT = 1<<4;
     }
 }
 
@@ -28,6 +31,10 @@ bitflags! {
 pub struct Line {
     bits: LineBits,
     cells: Vec<Cell>,
+    /// The sequence number most recently recorded for a change to
+    /// this line; see `update_last_change_seqno`
+    #[serde(skip)]
+    seqno: SequenceNo,
 }
 
 pub enum DoubleClickRange {
@@ -40,7 +47,11 @@ impl Line {
         let mut cells = Vec::with_capacity(width);
         cells.resize(width, Cell::default());
         let bits = LineBits::DIRTY;
-        Self { bits, cells }
+        Self {
+            bits,
+            cells,
+            seqno: SEQ_ZERO,
+        }
     }
 
     pub fn from_text(s: &str, attrs: &CellAttributes) -> Line {
@@ -58,6 +69,22 @@ impl Line {
         Line {
             cells,
             bits: LineBits::DIRTY,
+            seqno: SEQ_ZERO,
+        }
+    }
+
+    /// Construct a line from a sequence of cells that has already been
+    /// laid out; the cells that are overlapped by a double-width cell
+    /// are expected to be present in the sequence.
+    pub fn from_cells(cells: Vec<Cell>) -> Line {
+        let mut bits = LineBits::DIRTY;
+        if cells.iter().any(|cell| cell.attrs().hyperlink.is_some()) {
+            bits |= LineBits::HAS_HYPERLINK;
+        }
+        Line {
+            cells,
+            bits,
+            seqno: SEQ_ZERO,
         }
     }
 
@@ -102,6 +129,41 @@ impl Line {
         self.bits &= !LineBits::DIRTY;
     }
 
+    /// Marks the line as the one on which a shell prompt starts
+    pub fn set_prompt(&mut self) {
+        self.bits |= LineBits::PROMPT;
+    }
+
+    /// Returns true if a shell prompt starts on this line
+    pub fn is_prompt(&self) -> bool {
+        (self.bits & LineBits::PROMPT) == LineBits::PROMPT
+    }
+
+    /// Records the changes made since the last call, as indicated by
+    /// the dirty bit, as having happened at `seqno`, and clears the
+    /// dirty bit.  `seqno` must be larger than any value previously
+    /// passed for this line.
+    pub fn update_last_change_seqno(&mut self, seqno: SequenceNo) {
+        if self.is_dirty() {
+            self.seqno = seqno;
+            self.clear_dirty();
+        }
+    }
+
+    /// Returns the sequence number recorded for the most recent change
+    /// to the line by `update_last_change_seqno`
+    #[inline]
+    pub fn current_seqno(&self) -> SequenceNo {
+        self.seqno
+    }
+
+    /// Returns true if the line has changed after `seqno`, including
+    /// changes that have not yet been assigned a sequence number.
+    #[inline]
+    pub fn changed_since(&self, seqno: SequenceNo) -> bool {
+        self.is_dirty() || self.seqno > seqno
+    }
+
     /// If we have any cells with an implicit hyperlink, remove the hyperlink
     /// from the cell attributes but leave the remainder of the attributes alone.
     pub fn invalidate_implicit_hyperlinks(&mut self) {
@@ -166,19 +228,44 @@ impl Line {
                     continue;
                 }
                 if m.range.contains(&byte_idx) {
-                    let attrs = self.cells[cell_idx]
-                        .attrs()
-                        .clone()
-                        .set_hyperlink(Some(Arc::clone(&m.link)))
-                        .clone();
-                    let cell = Cell::new_grapheme(self.cells[cell_idx].str(), attrs);
-                    self.cells[cell_idx] = cell;
-                    self.bits |= LineBits::HAS_IMPLICIT_HYPERLINKS;
+                    self.set_implicit_hyperlink(cell_idx, &m.link);
                 }
             }
         }
     }
 
+    /// Returns true if the line has been scanned for implicit hyperlinks
+    /// and has not been modified since.
+    #[inline]
+    pub fn has_scanned_implicit_hyperlinks(&self) -> bool {
+        (self.bits & LineBits::SCANNED_IMPLICIT_HYPERLINKS) == LineBits::SCANNED_IMPLICIT_HYPERLINKS
+    }
+
+    /// Record that the line has been scanned for implicit hyperlinks.
+    /// This is intended for callers that scan a run of wrapped lines as
+    /// a unit rather than using `scan_and_create_hyperlinks`; they are
+    /// expected to have called `invalidate_implicit_hyperlinks` first.
+    #[inline]
+    pub fn mark_scanned_implicit_hyperlinks(&mut self) {
+        self.bits |= LineBits::SCANNED_IMPLICIT_HYPERLINKS;
+    }
+
+    /// Associate an implicit hyperlink with the cell at `cell_idx`.
+    /// Cells that already have a hyperlink are left alone.
+    pub fn set_implicit_hyperlink(&mut self, cell_idx: usize, link: &Arc<Hyperlink>) {
+        let cell = match self.cells.get(cell_idx) {
+            Some(cell) if cell.attrs().hyperlink.is_none() => cell,
+            _ => return,
+        };
+        let attrs = cell
+            .attrs()
+            .clone()
+            .set_hyperlink(Some(Arc::clone(link)))
+            .clone();
+        self.cells[cell_idx] = Cell::new_grapheme(cell.str(), attrs);
+        self.bits |= LineBits::HAS_IMPLICIT_HYPERLINKS;
+    }
+
     /// Returns true if the line contains a hyperlink
     #[inline]
     pub fn has_hyperlink(&self) -> bool {
@@ -195,10 +282,10 @@ impl Line {
         s
     }
 
-    pub fn compute_double_click_range(
+    pub fn compute_double_click_range<F: Fn(&str) -> bool>(
         &self,
         click_col: usize,
-        is_word: fn(s: &str) -> bool,
+        is_word: F,
     ) -> DoubleClickRange {
         let mut lower = click_col;
         let mut upper = click_col;
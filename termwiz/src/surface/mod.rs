@@ -51,6 +51,9 @@ impl Default for CursorShape {
 /// The sequence is only meaningful within a given `Surface` instance.
 pub type SequenceNo = usize;
 
+/// A SequenceNo that precedes every change
+pub const SEQ_ZERO: SequenceNo = 0;
+
 /// The `Surface` type represents the contents of a terminal screen.
 /// It is not directly connected to a terminal device.
 /// It consists of a buffer and a log of changes.  You can accumulate
@@ -438,6 +441,9 @@ impl Surface {
             StrikeThrough(value) => {
                 self.attributes.set_strikethrough(*value);
             }
+            Overline(value) => {
+                self.attributes.set_overline(*value);
+            }
             Invisible(value) => {
                 self.attributes.set_invisible(*value);
             }